@@ -0,0 +1,347 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use newsfeed_core::events::Recorded;
+use newsfeed_core::ids::{PostId, UserId};
+use newsfeed_core::interests::{Engagement, Interests};
+use newsfeed_core::ranking::{self, RankingWeights};
+use newsfeed_core::{FeedEvent, Post};
+
+// Offline evaluation of ranking weights. Replays an exported event log
+// (GET /v1/admin/events) and, at each like, rebuilds the liker's feed as it
+// stood just before: the newest posts of the accounts they followed then,
+// minus what they had already liked. Each variant ranks that feed with the
+// liker's interests as of that moment, and is scored on where the liked post
+// landed and what its top of feed looked like.
+
+const TOP_K: usize = 10;
+// About what a home feed holds
+const CANDIDATES: usize = 200;
+const HOUR_MILLIS: u64 = 60 * 60 * 1000;
+// Upper bounds, in hours, of the age buckets in the recency distribution;
+// anything older goes in the last bucket
+const AGE_BUCKETS: [(&str, u64); 4] = [("1h", 1), ("6h", 6), ("24h", 24), ("7d", 168)];
+
+// A named set of weights to compare
+#[derive(Debug, Clone)]
+pub struct Variant {
+    pub name: String,
+    pub weights: RankingWeights,
+}
+
+impl Variant {
+    // "production", "chronological", or "name:topics=1.0,authors=0.5" with
+    // unnamed weights at their production values
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (name, overrides) = match spec {
+            "production" => return Ok(Self::named("production", RankingWeights::default())),
+            "chronological" => {
+                return Ok(Self::named("chronological", RankingWeights { topics: 0.0, authors: 0.0 }));
+            }
+            _ => spec
+                .split_once(':')
+                .ok_or_else(|| format!("expected production, chronological, or name:weight=value,..., got {}", spec))?,
+        };
+        let mut weights = RankingWeights::default();
+        for item in overrides.split(',').filter(|item| !item.is_empty()) {
+            let (key, value) = item
+                .split_once('=')
+                .ok_or_else(|| format!("{}: expected weight=value, got {}", name, item))?;
+            let value: f64 = value
+                .parse()
+                .ok()
+                .filter(|value: &f64| value.is_finite())
+                .ok_or_else(|| format!("{}: {} isn't a number", name, value))?;
+            match key {
+                "topics" => weights.topics = value,
+                "authors" => weights.authors = value,
+                _ => return Err(format!("{}: unknown weight {}; expected topics or authors", name, key)),
+            }
+        }
+        Ok(Self::named(name, weights))
+    }
+
+    fn named(name: &str, weights: RankingWeights) -> Self {
+        Self {
+            name: name.to_string(),
+            weights,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub events: usize,
+    pub evaluated: usize, // likes on a post that was in the liker's feed
+    pub skipped: usize,   // likes on posts from elsewhere: trending, search, profiles
+    pub variants: Vec<VariantReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VariantReport {
+    pub name: String,
+    pub weights: RankingWeights,
+    pub engaged_at_10: f64, // share of evaluated likes whose post ranked in the top 10
+    pub mean_reciprocal_rank: f64,
+    pub author_diversity: f64, // distinct authors over posts in the top 10, averaged
+    pub median_age_hours: f64, // of posts in the top 10
+    pub age_distribution: Vec<(String, f64)>, // share of top 10 posts per age bucket
+}
+
+#[derive(Default)]
+struct Totals {
+    hits: usize,
+    reciprocal_ranks: f64,
+    diversity: f64,
+    ages: Vec<u64>, // millis
+}
+
+// The state the log builds, as far as ranking needs it
+#[derive(Default)]
+struct Replay {
+    posts: HashMap<PostId, Post>,
+    by_author: HashMap<UserId, Vec<PostId>>, // top-level posts, including co-authored ones
+    following: HashMap<UserId, HashSet<UserId>>,
+    liked: HashMap<UserId, HashSet<PostId>>,
+    interests: HashMap<UserId, Interests>,
+}
+
+impl Replay {
+    // Newest first, as the feed serves them before ranking
+    fn feed(&self, user_id: &UserId, at: u64) -> Vec<&Post> {
+        let liked = self.liked.get(user_id);
+        let mut feed: Vec<&Post> = self
+            .following
+            .get(user_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|author_id| self.by_author.get(author_id))
+            .flatten()
+            .filter_map(|post_id| self.posts.get(post_id))
+            .filter(|post| post.timestamp <= at && !liked.is_some_and(|liked| liked.contains(&post.id)))
+            .collect();
+        feed.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then_with(|| b.id.cmp(&a.id)));
+        // Followed co-authors list the same post
+        feed.dedup_by(|a, b| a.id == b.id);
+        feed.truncate(CANDIDATES);
+        feed
+    }
+
+    fn record(&mut self, user_id: &UserId, post_id: &PostId, engagement: Engagement, at: u64) {
+        if let Some(post) = self.posts.get(post_id) {
+            self.interests
+                .entry(user_id.clone())
+                .or_default()
+                .record_post(user_id, post, engagement, at);
+        }
+    }
+}
+
+pub fn load(path: &str) -> Result<Vec<Recorded<FeedEvent>>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+        .map(|(index, line)| {
+            let line = line.map_err(|e| format!("{}: {}", path, e))?;
+            serde_json::from_str(&line).map_err(|e| format!("{} line {}: {}", path, index + 1, e))
+        })
+        .collect()
+}
+
+pub fn evaluate(events: &[Recorded<FeedEvent>], variants: &[Variant]) -> Report {
+    let mut replay = Replay::default();
+    let mut totals: Vec<Totals> = variants.iter().map(|_| Totals::default()).collect();
+    let (mut evaluated, mut skipped) = (0, 0);
+    let no_signals = [];
+
+    for recorded in events {
+        let at = recorded.at;
+        match &recorded.event {
+            FeedEvent::PostCreated(post) | FeedEvent::PostRestored(post) => {
+                match &post.in_reply_to {
+                    Some(parent_id) => replay.record(&post.user_id, parent_id, Engagement::Reply, at),
+                    None => {
+                        let accepted = post.co_authors.iter().filter(|co_author| co_author.accepted);
+                        for author_id in std::iter::once(&post.user_id).chain(accepted.map(|co_author| &co_author.user_id)) {
+                            replay.by_author.entry(author_id.clone()).or_default().push(post.id.clone());
+                        }
+                    }
+                }
+                replay.posts.insert(post.id.clone(), (**post).clone());
+            }
+            FeedEvent::PostEdited(post) => {
+                if let Some(existing) = replay.posts.get_mut(&post.id) {
+                    *existing = (**post).clone();
+                }
+            }
+            FeedEvent::PostLiked { user_id, post_id } => {
+                let Some(post) = replay.posts.get(post_id) else {
+                    continue;
+                };
+                if &post.user_id == user_id || replay.liked.get(user_id).is_some_and(|liked| liked.contains(post_id)) {
+                    continue;
+                }
+                let feed = replay.feed(user_id, at);
+                if feed.iter().any(|candidate| &candidate.id == post_id) {
+                    evaluated += 1;
+                    let interests = replay.interests.get(user_id).cloned().unwrap_or_default();
+                    for (variant, totals) in variants.iter().zip(&mut totals) {
+                        // Signals aren't in the log, so only interests move posts
+                        let mut ranked: Vec<(f64, &Post)> = feed
+                            .iter()
+                            .map(|post| {
+                                let score = ranking::penalty(&variant.weights, &no_signals, &interests, 1.0, post, at);
+                                (score, *post)
+                            })
+                            .collect();
+                        ranked.sort_by(|a, b| a.0.total_cmp(&b.0));
+                        let rank = ranked.iter().position(|(_, post)| &post.id == post_id).unwrap_or(ranked.len());
+                        if rank < TOP_K {
+                            totals.hits += 1;
+                        }
+                        totals.reciprocal_ranks += 1.0 / (rank + 1) as f64;
+                        let top: Vec<&Post> = ranked.iter().take(TOP_K).map(|(_, post)| *post).collect();
+                        let authors: HashSet<&UserId> = top.iter().map(|post| &post.user_id).collect();
+                        totals.diversity += authors.len() as f64 / top.len() as f64;
+                        totals.ages.extend(top.iter().map(|post| at.saturating_sub(post.timestamp)));
+                    }
+                } else {
+                    skipped += 1;
+                }
+                replay.liked.entry(user_id.clone()).or_default().insert(post_id.clone());
+                replay.record(user_id, post_id, Engagement::Like, at);
+            }
+            FeedEvent::CoAuthorAnswered { post_id, user_id, accepted: true } => {
+                if replay.posts.get(post_id).is_some_and(|post| post.in_reply_to.is_none()) {
+                    replay.by_author.entry(user_id.clone()).or_default().push(post_id.clone());
+                }
+            }
+            FeedEvent::PostUnliked { user_id, post_id } => {
+                if let Some(liked) = replay.liked.get_mut(user_id) {
+                    liked.remove(post_id);
+                }
+            }
+            FeedEvent::PostDeleted { post_id } => {
+                replay.posts.remove(post_id);
+            }
+            FeedEvent::Followed { follower_id, followed_id }
+            | FeedEvent::FollowRestored { follower_id, followed_id, .. } => {
+                replay.following.entry(follower_id.clone()).or_default().insert(followed_id.clone());
+            }
+            FeedEvent::Unfollowed { follower_id, followed_id } => {
+                if let Some(following) = replay.following.get_mut(follower_id) {
+                    following.remove(followed_id);
+                }
+            }
+            FeedEvent::ThreadPublished { .. }
+            | FeedEvent::NotifySet { .. }
+            | FeedEvent::DailyLimitSet { .. }
+            | FeedEvent::CoAuthorAnswered { .. }
+            | FeedEvent::RsvpSet { .. } => {}
+        }
+    }
+
+    let variants = variants
+        .iter()
+        .zip(totals)
+        .map(|(variant, mut totals)| summarize(variant, &mut totals, evaluated))
+        .collect();
+    Report {
+        events: events.len(),
+        evaluated,
+        skipped,
+        variants,
+    }
+}
+
+fn summarize(variant: &Variant, totals: &mut Totals, evaluated: usize) -> VariantReport {
+    let per_like = |sum: f64| if evaluated == 0 { 0.0 } else { sum / evaluated as f64 };
+    totals.ages.sort_unstable();
+    let median_age_hours = totals
+        .ages
+        .get(totals.ages.len() / 2)
+        .map_or(0.0, |age| *age as f64 / HOUR_MILLIS as f64);
+    let shown = totals.ages.len().max(1) as f64;
+    let mut lower = 0;
+    let mut age_distribution: Vec<(String, f64)> = AGE_BUCKETS
+        .iter()
+        .map(|(label, hours)| {
+            let upper = hours * HOUR_MILLIS;
+            let count = totals.ages.iter().filter(|age| **age >= lower && **age < upper).count();
+            lower = upper;
+            (format!("<{}", label), count as f64 / shown)
+        })
+        .collect();
+    let older = totals.ages.iter().filter(|age| **age >= lower).count();
+    age_distribution.push(("older".to_string(), older as f64 / shown));
+
+    VariantReport {
+        name: variant.name.clone(),
+        weights: variant.weights,
+        engaged_at_10: per_like(totals.hits as f64),
+        mean_reciprocal_rank: per_like(totals.reciprocal_ranks),
+        author_diversity: per_like(totals.diversity),
+        median_age_hours,
+        age_distribution,
+    }
+}
+
+const USAGE: &str = "usage: news-feed-rs eval <events.jsonl> [--variant <spec>]... [--json]
+  <spec> is production, chronological, or name:topics=<w>,authors=<w>
+  with no --variant, compares chronological and production";
+
+// `news-feed-rs eval ...`: prints a table, or the report as JSON
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut variants = Vec::new();
+    let mut json = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--variant" => {
+                let spec = args.next().ok_or(USAGE)?;
+                variants.push(Variant::parse(spec)?);
+            }
+            "--json" => json = true,
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg.as_str()),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let path = path.ok_or(USAGE)?;
+    if variants.is_empty() {
+        variants = vec![Variant::parse("chronological")?, Variant::parse("production")?];
+    }
+
+    let report = evaluate(&load(path)?, &variants);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report).expect("report serializes"));
+        return Ok(());
+    }
+    println!(
+        "Replayed {} events: {} likes evaluated, {} skipped (the post wasn't in the liker's feed)",
+        report.events, report.evaluated, report.skipped
+    );
+    print!(
+        "{:<16} {:>8} {:>7} {:>10} {:>12}",
+        "variant", "engaged@10", "mrr", "diversity", "median_age_h"
+    );
+    for (label, _) in &report.variants.first().map(|first| first.age_distribution.clone()).unwrap_or_default() {
+        print!(" {:>6}", label);
+    }
+    println!();
+    for variant in &report.variants {
+        print!(
+            "{:<16} {:>10.3} {:>7.3} {:>10.3} {:>12.1}",
+            variant.name, variant.engaged_at_10, variant.mean_reciprocal_rank, variant.author_diversity, variant.median_age_hours
+        );
+        for (_, share) in &variant.age_distribution {
+            print!(" {:>6.2}", share);
+        }
+        println!();
+    }
+    Ok(())
+}
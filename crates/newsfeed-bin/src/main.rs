@@ -0,0 +1,375 @@
+// The news-feed-rs server: loads the config, opens storage, builds each
+// tenant's state, and serves it
+#![recursion_limit = "256"]
+
+mod eval;
+mod schedules;
+mod state;
+
+use std::sync::Arc;
+
+use newsfeed_core::bootstrap::{Bootstrap, StartupError};
+use newsfeed_core::config::{Config, Settings, Source, Tunables};
+use newsfeed_core::ids::UserId;
+use newsfeed_core::oauth::OAuthProvider;
+use newsfeed_core::{info, residency, warn};
+
+use newsfeed_store_memory::{broker, storage};
+
+use newsfeed_http::access_log::{AccessLogger, RequestMetrics, with_access_log};
+use newsfeed_http::cluster::FeedDeliveryServer;
+use newsfeed_http::handlers::reload_settings;
+use newsfeed_http::limits::{Exposure, RequestLimits};
+use newsfeed_http::routes::build_routes;
+use newsfeed_http::sandbox::with_sandbox;
+use newsfeed_http::{AppState, cluster, limits};
+
+use crate::state::{build_state, init_sample_data, init_sandbox_data, restore_from_storage, schedule_sandbox_reset};
+
+// A storage root that can't be written to won't fix itself; anything else
+// (a mount still coming up, say) is worth another try
+fn storage_error(e: std::io::Error) -> StartupError {
+    match e.kind() {
+        std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::ReadOnlyFilesystem => StartupError::Fatal(e.to_string()),
+        _ => StartupError::Transient(e.to_string()),
+    }
+}
+
+// Matching a request polls down the route tree, which in debug builds can
+// take more than tokio's default 2 MiB worker stack
+const WORKER_STACK_BYTES: usize = 8 << 20;
+
+fn main() {
+    // `news-feed-rs eval ...` replays an exported event log offline; see eval.rs
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).is_some_and(|arg| arg == "eval") {
+        if let Err(e) = eval::run(&args[2..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .thread_stack_size(WORKER_STACK_BYTES)
+        .build()
+        .expect("tokio runtime starts")
+        .block_on(run());
+}
+
+async fn run() {
+    // Subsystems come up in dependency order; see newsfeed-core's bootstrap.rs
+    let startup = Arc::new(Bootstrap::default());
+    let (config, settings) = startup
+        .step("config", || {
+            let source = Source::load().map_err(StartupError::Fatal)?;
+            let config = Config::from_source(&source);
+            let tunables = Tunables::from_source(&source);
+            source.check().map_err(StartupError::Fatal)?;
+            config.validate().map_err(StartupError::Fatal)?;
+            newsfeed_store_memory::validate(&config).map_err(StartupError::Fatal)?;
+            newsfeed_http::validate(&config).map_err(StartupError::Fatal)?;
+            let settings = Settings::new(&config, tunables).map_err(StartupError::Fatal)?;
+            Ok((Arc::new(config), Arc::new(settings)))
+        })
+        .await;
+    let sandbox_config = Arc::new(config.for_sandbox());
+
+    let storage = startup
+        .step("storage", || {
+            residency::check_roots(&config).map_err(storage_error)?;
+            residency::check_roots(&sandbox_config).map_err(storage_error)?;
+            // Another process holding the database may be on its way out
+            storage::open(&config).map_err(StartupError::Transient)
+        })
+        .await;
+
+    // Sandbox apps get their own tenant, seeded with fake data and wiped
+    // every NEWS_FEED_SANDBOX_RESET_SECS
+    let oauth = Arc::new(OAuthProvider::default());
+    let requests = Arc::new(RequestMetrics::default());
+    let (state, sandbox) = startup
+        .step("caches", || {
+            let state = build_state(
+                config.clone(),
+                settings.clone(),
+                oauth.clone(),
+                requests.clone(),
+                startup.clone(),
+                storage.clone(),
+                false,
+            );
+            // The sample accounts are only made on first start
+            let (users, follows, posts) = restore_from_storage(&state).map_err(StartupError::Fatal)?;
+            if let Some(storage) = &storage {
+                info!(
+                    "Restored {} users, {} follows, and {} posts from {} storage",
+                    users,
+                    follows,
+                    posts,
+                    storage.name()
+                );
+            }
+            if users == 0 {
+                init_sample_data(&state);
+            }
+            let sandbox = build_state(
+                sandbox_config.clone(),
+                settings.clone(),
+                oauth.clone(),
+                requests.clone(),
+                startup.clone(),
+                None,
+                true,
+            );
+            init_sandbox_data(&sandbox);
+            schedule_sandbox_reset(sandbox.clone(), &config);
+            Ok((state, sandbox))
+        })
+        .await;
+
+    startup
+        .step_async("queues", || async {
+            // Connected first: a failed attempt must not restore the jobs twice
+            let broker = broker::connect(&config).await.map_err(StartupError::Transient)?;
+            let restored = state.jobs.restore().map_err(|e| StartupError::Transient(e.to_string()))?;
+            if restored > 0 {
+                info!("Restored {} queued jobs", restored);
+            }
+            if let Some(broker) = broker {
+                info!(
+                    "Fanout jobs go through the shared {} queue {} as group {}",
+                    broker.name(),
+                    config.queue_stream,
+                    config.queue_group
+                );
+                state.jobs.attach(broker);
+            }
+            Ok(())
+        })
+        .await;
+    state.jobs.spawn();
+    sandbox.jobs.spawn();
+    spawn_reload_on_hangup(state.clone());
+    let rpc_server = FeedDeliveryServer::new(state.fanout_worker.clone(), &state.feed_nodes);
+
+    let account_tokens = state.account_tokens.clone();
+    let access_logger = Arc::new(AccessLogger::new(
+        &config,
+        settings.clone(),
+        account_tokens.clone(),
+        requests.clone(),
+    ));
+    let catalogs = state.catalogs.clone();
+    let routes = with_sandbox(build_routes(state), build_routes(sandbox), account_tokens, oauth);
+    let routes = with_access_log(routes, access_logger.clone());
+
+    let (listeners, rpc_listener) = startup
+        .step("http", || {
+            let listeners = limits::listeners(&config).map_err(StartupError::Fatal)?;
+            let listeners = listeners
+                .iter()
+                .map(|(addr, exposure)| {
+                    limits::bind(addr, *exposure).map_err(|e| StartupError::Transient(format!("{}: {}", addr, e)))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let rpc_listener = config
+                .rpc_listen
+                .map(|addr| cluster::bind(addr).map_err(|e| StartupError::Transient(format!("{}: {}", addr, e))))
+                .transpose()?;
+            Ok((listeners, rpc_listener))
+        })
+        .await;
+    if let Some(incoming) = rpc_listener {
+        tokio::spawn(rpc_server.serve(incoming));
+    }
+    startup.ready();
+
+    for listener in &listeners {
+        match listener.exposure {
+            Exposure::All => println!("News Feed server listening on {}", listener.addr),
+            Exposure::Public => println!("News Feed server listening on {} (admin API moved)", listener.addr),
+            Exposure::Internal => println!("Admin API and metrics listening on {}", listener.addr),
+        }
+    }
+    if let Some(addr) = config.rpc_listen {
+        println!("Feed delivery RPC for node {} listening on {}", config.node_id, addr);
+    }
+    if let Some(dir) = &config.engagement_log {
+        println!("Engagement log writing to {}", dir.display());
+    }
+    if config.pull_fanout_followers > 0 {
+        println!(
+            "Posts by accounts with {}+ followers are pulled into feeds at read time",
+            config.pull_fanout_followers
+        );
+    }
+    println!("Fanout queue holds up to {} jobs; posting is turned away while it's full", config.fanout_queue_limit);
+    println!(
+        "Server text in {}; {} unless a request or user picks another",
+        catalogs.locales().join(", "),
+        config.default_locale
+    );
+    if config.demo_tokens {
+        println!("Demo tokens are on: user_<id> tokens act as any account without a signature");
+    }
+    println!("API Endpoints (authenticated ones take Authorization: Bearer <token>, or ?auth_token=<token>):");
+    println!("POST /v1/me/feed - Create post (publish_at to schedule it)");
+    println!("POST /v1/me/threads - Publish a thread");
+    println!("POST /v1/articles - Publish a long-form markdown article");
+    println!("GET /v1/articles/{{id}} - Read an article, as markdown and HTML");
+    println!("GET /v1/me/feed - Get news feed (limit and cursor=<next_cursor> to page, resume=true to continue from saved position, mode=latest for delivery order, ranking=personalized|chronological|engagement)");
+    println!("GET /v2/me/feed - Get news feed in the v2 data/meta envelope");
+    println!("GET /v1/me/feed/poll?since=<cursor>&wait=25 - Long-poll for new feed items");
+    println!("GET /v1/me/feed/stream - WebSocket of new feed items as they're delivered");
+    println!("POST /v1/me/feed/hide - Hide posts from your feed");
+    println!("POST /v1/me/feed/feedback - Send not interested/mute/fast-scroll feedback");
+    println!("POST /v1/me/feed/telemetry - Report time on screen and clicks for feed items");
+    println!("POST /v1/sponsored/{{campaign_id}}/click - Record a sponsored post click");
+    println!("POST /v1/batch - Run several requests in one round trip");
+    println!("GET/POST /v1/admin/campaigns - List or create sponsored campaigns (admin)");
+    println!("GET /v1/admin/debug/{{runtime,caches,profile,memory}} - Runtime, cache, memory, and CPU profiling (admin)");
+    println!("GET /v1/admin/debug/interests/{{user_id}} - A user's topic and author interests (admin)");
+    println!("GET /metrics - Prometheus metrics");
+    println!("GET/PUT /v1/me/feed/position - Read or save last-read feed position");
+    println!("GET /v1/posts/{{id}} - One post, or why you can't see it");
+    println!("PATCH/DELETE /v1/posts/{{id}} - Edit or delete your post");
+    println!("POST /v1/posts/{{id}}/replies - Reply to a post");
+    println!("GET /v1/posts/{{id}}/replies - A post's direct replies, oldest first");
+    println!("GET /v1/posts/{{id}}/conversation - View a conversation");
+    println!("GET /v1/posts/{{id}}/related - Related posts (\"more like this\")");
+    println!("POST /v1/users/follow - Follow user");
+    println!("POST /v1/users/unfollow - Unfollow user");
+    println!("PUT /v1/users/{{id}}/block - Block a user (DELETE to unblock)");
+    println!("PUT /v1/users/{{id}}/notify - Turn post notifications for a followed account on or off");
+    println!("GET /v1/me/notifications - List notifications, with the unread count");
+    println!("POST /v1/me/notifications/read - Mark notifications read");
+    println!("GET /v1/me/co_author_requests - Posts you were invited to co-author");
+    println!("POST /v1/posts/{{id}}/co_author - Accept or decline co-authoring a post");
+    println!("PUT /v1/posts/{{id}}/rsvp - RSVP going or interested to an event post");
+    println!("DELETE /v1/posts/{{id}}/rsvp - Withdraw an RSVP");
+    println!("GET /v1/me/stats - Daily activity and weekly summaries");
+    println!("GET /v1/me/profile/views - Who viewed your profile, per day");
+    println!("PUT /v1/users/{{id}}/daily-limit - Limit a followed account's posts per day in your feed");
+    println!("GET /v1/users/{{id}}/posts - Profile timeline of an account's posts");
+    println!("GET /v1/search/posts?q=hello%20from:alice - Search posts, ranked or newest first (sort=latest)");
+    println!("GET/POST /v1/me/saved_searches, DELETE /v1/me/saved_searches/{{id}} - Saved searches, checked for new posts in the background");
+    println!("GET /v1/typeahead?q=al - Complete usernames and hashtags, followed accounts first");
+    println!("GET /v1/tags/{{tag}}/posts - Posts using a hashtag, newest first");
+    println!("GET /v1/tags/trending - Hashtags used most in the last day");
+    println!("POST /v1/posts/like - Like post");
+    println!("POST /v1/posts/unlike - Unlike post");
+    println!("POST /v1/posts/views - Report viewed posts");
+    println!("GET /v1/me/analytics - Likes, replies, unique viewers, and impressions by channel of your posts");
+    println!("GET/POST /v1/me/accounts - List or link accounts for switching");
+    println!("DELETE /v1/me/accounts/{{id}} - Unlink an account");
+    println!("GET /v1/users/{{id}} - View a profile");
+    println!("GET /v1/users/by-username/{{username}} - Look up a profile by username");
+    println!("GET /v1/public/users/{{id}}, /v1/public/users/{{id}}/posts - Public profile and posts, no token");
+    println!("GET /v1/public/posts/{{id}}, /v1/public/tags/{{tag}} - Public post or hashtag timeline, no token");
+    println!("POST /v1/accounts - Sign up with a password; the account stays pending until its email is verified");
+    println!("POST /v1/accounts/verify-email - Verify an email address with the emailed token");
+    println!("POST /v1/auth/register - Same as POST /v1/accounts; returns a signed JWT");
+    println!("POST /v1/auth/login - Log in with a username and password; returns a signed JWT");
+    println!("PUT /v1/me/password - Set or change your password");
+    println!("GET /v1/me/account - Account state and email");
+    println!("GET /v1/me/context - Request context: roles, tenant, request ID, deadline, feature flags");
+    println!("PUT /v1/me/email, POST /v1/me/email/verification - Change email or resend its verification");
+    println!("POST /v1/me/deactivate - Deactivate your account");
+    println!("PUT /v1/admin/users/{{id}}/state - Suspend, reinstate, or deactivate an account (admin)");
+    println!("GET /v1/me/2fa, POST /v1/me/2fa/setup - Two-factor status, or start enrollment (QR code)");
+    println!("POST /v1/me/2fa/enable, DELETE /v1/me/2fa - Confirm enrollment with a code, or turn 2FA off");
+    println!("POST /v1/login - Start a session; takes an authenticator code when 2FA is on");
+    println!("GET /v1/me/security/logins - Recent logins, with new devices and locations flagged");
+    println!("GET /v1/me/privacy/access_log - Admin accesses to your data");
+    println!("POST/GET /v1/oauth/clients - Register or list your OAuth apps");
+    println!("GET /oauth/authorize?client_id=... - OAuth consent page; POST /oauth/token exchanges the code");
+    println!("POST /oauth/token with grant_type=client_credentials - Test token for a sandbox app, served by the sandbox tenant");
+    println!("GET /v1/me/oauth/grants, DELETE /v1/me/oauth/grants/{{client_id}} - Apps you've authorized, or revoke one");
+    println!("GET /v1/federation/key - This server's public key for signed server-to-server calls");
+    println!("POST /v1/federation/inbox - Signed deliveries from federation peers (RFC 9421)");
+    println!("GET/PUT /v1/admin/2fa-policy - Require two-factor for admins (admin)");
+    println!("PUT /v1/me/username - Change username");
+    println!("PATCH /v1/me/profile - Edit profile fields, including timezone");
+    println!("PUT /v1/me/avatar, /v1/me/banner - Upload profile images");
+    println!("PUT /v1/admin/users/{{id}}/verified - Grant or revoke verification (admin)");
+    println!("GET /v1/admin/moderation - Accounts flagged for follow abuse, with evidence (admin)");
+    println!("POST /v1/admin/moderation/{{id}}/resolve - Close a moderation case (admin)");
+    println!("GET /v1/admin/legal-holds, PUT/DELETE /v1/admin/legal-holds/{{posts|users}}/{{id}} - Manage legal holds (admin)");
+    println!("PUT/DELETE /v1/admin/takedowns/{{post_id}} - Withhold a post, optionally per jurisdiction (admin)");
+    println!("GET /v1/admin/audit - Audit log of legal, residency, and data access actions (admin)");
+    println!("GET /v1/admin/config, POST /v1/admin/config/reload - Current tunable settings, or reload them from the config file (admin)");
+    println!("GET /v1/admin/posts/{{id}}/delivery - Fanout progress and delivery latency for a post (admin)");
+    println!("POST /v1/admin/posts/{{id}}/reveal - Who wrote an anonymous post, with a reason (admin, audited)");
+    println!("GET /v1/admin/startup - Startup report: each init step's attempts, time, and last error (admin)");
+    println!("GET /v1/admin/jobs, POST /v1/admin/jobs/{{id}}/retry - Running, queued, failed, and scheduled background jobs, or retry a failed one (admin)");
+    println!("GET /v1/admin/search - Search index size, schema version, and how far it trails the event log (admin)");
+    println!("GET /v1/admin/projections, POST /v1/admin/projections/{{name}}/rebuild - Event log projections, or rebuild one from history (admin)");
+    println!("GET /v1/admin/events?after=0 - Export the event log as JSON lines, for the eval harness (admin)");
+    println!("GET /v1/admin/regions, PUT /v1/admin/users/{{id}}/region - Storage regions, or move an account's data (admin)");
+    println!("GET/PUT /v1/me/preferences - Read or update preferences");
+    println!("GET /v1/emojis - List custom emoji");
+    println!("PUT/DELETE /v1/admin/emojis/{{shortcode}} - Manage custom emoji (admin)");
+    println!();
+    println!("Example usage:");
+    if config.demo_tokens {
+        // user_<id> acts as that account; the sample accounts are user1 to user3
+        println!("# Create post");
+        println!(r#"curl -X POST "http://localhost:3030/v1/me/feed?auth_token=user_user1" -H "Content-Type: application/json" -d '{{"content":"Hello from Rust!"}}'"#);
+        println!();
+        println!("# Get feed");
+        println!(r#"curl "http://localhost:3030/v1/me/feed?auth_token=user_user2""#);
+        println!();
+        println!("# Follow user");
+        println!(r#"curl -X POST "http://localhost:3030/v1/users/follow?auth_token=user_user2" -H "Content-Type: application/json" -d '{{"target_user_id":"user1"}}'"#);
+        println!();
+        println!("# Like post");
+        println!(r#"curl -X POST "http://localhost:3030/v1/posts/like?auth_token=user_user2" -H "Content-Type: application/json" -d '{{"post_id":"<id from the feed>"}}'"#);
+    } else {
+        println!("# Sign up; the response's token goes in the Authorization header");
+        println!(r#"curl -X POST "http://localhost:3030/v1/auth/register" -H "Content-Type: application/json" -d '{{"username":"dana","email":"dana@example.com","password":"<password>"}}'"#);
+        println!();
+        println!("# Verify the email with the code from the email written to stdout");
+        println!(r#"curl -X POST "http://localhost:3030/v1/accounts/verify-email" -H "Content-Type: application/json" -d '{{"token":"<code from the email>"}}'"#);
+        println!();
+        println!("# Log in again later");
+        println!(r#"curl -X POST "http://localhost:3030/v1/auth/login" -H "Content-Type: application/json" -d '{{"username":"dana","password":"<password>"}}'"#);
+        println!();
+        println!("# Create post");
+        println!(r#"curl -X POST "http://localhost:3030/v1/me/feed" -H "Authorization: Bearer <token>" -H "Content-Type: application/json" -d '{{"content":"Hello from Rust!"}}'"#);
+        println!();
+        println!("# Get feed");
+        println!(r#"curl "http://localhost:3030/v1/me/feed" -H "Authorization: Bearer <token>""#);
+    }
+
+    let request_limits = Arc::new(RequestLimits::new(settings));
+    futures::future::join_all(listeners.into_iter().map(|listener| {
+        limits::serve(
+            listener,
+            routes.clone(),
+            request_limits.clone(),
+            access_logger.clone(),
+            catalogs.clone(),
+        )
+    }))
+    .await;
+}
+
+// Reloads on SIGHUP too; those reloads are audited as admin "system"
+fn spawn_reload_on_hangup(state: AppState) {
+    tokio::spawn(async move {
+        let mut hangups = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(e) => {
+                warn!("Can't listen for SIGHUP, config reloads need the admin endpoint: {}", e);
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            match reload_settings(&state, &UserId::new("system")) {
+                Ok(changes) => info!("Config reloaded on SIGHUP, {} settings changed", changes.len()),
+                Err(e) => warn!("Config reload failed, keeping the current settings: {}", e),
+            }
+        }
+    });
+}
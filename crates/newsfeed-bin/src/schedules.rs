@@ -0,0 +1,421 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+use newsfeed_core::account_state::AccountState;
+use newsfeed_core::accounts::Scope;
+use newsfeed_core::cache::Cache;
+use newsfeed_core::config::Config;
+use newsfeed_core::email::EmailService;
+use newsfeed_core::events::EventStore;
+use newsfeed_core::i18n::Catalogs;
+use newsfeed_core::ids::{PostId, UserId};
+use newsfeed_core::jobs::{Cron, Job, JobPolicy, JobQueue, Schedule};
+use newsfeed_core::mixer::DAY_MILLIS;
+use newsfeed_core::moderation::{FollowAnalyzer, ModerationQueue};
+use newsfeed_core::notifications::{Notification, NotificationKind, PushGateway, notification_text};
+use newsfeed_core::posts::{EventReminder, PostService, ScheduledPost, schedule_event_reminder};
+use newsfeed_core::residency::StorageRouter;
+use newsfeed_core::retention::RetentionPolicy;
+use newsfeed_core::saved_searches::SavedSearchMatch;
+use newsfeed_core::search::SearchIndex;
+use newsfeed_core::timezones::ClockTime;
+use newsfeed_core::{FanoutMessage, FeedEvent, info, now_millis, search, warn};
+
+use newsfeed_store_memory::cache::CacheLayer;
+
+use newsfeed_http::handlers::submit_video;
+use newsfeed_http::video::VideoPipeline;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompactCounters;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExpireDeliveryMarkers;
+
+impl Job for ExpireDeliveryMarkers {
+    const KIND: &'static str = "expire_delivery_markers";
+}
+
+impl Job for CompactCounters {
+    const KIND: &'static str = "compact_counters";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EvictIdleEntries;
+
+impl Job for EvictIdleEntries {
+    const KIND: &'static str = "evict_idle_entries";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckSavedSearches;
+
+impl Job for CheckSavedSearches {
+    const KIND: &'static str = "check_saved_searches";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SendDigests;
+
+impl Job for SendDigests {
+    const KIND: &'static str = "send_digests";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RetentionSweep;
+
+impl Job for RetentionSweep {
+    const KIND: &'static str = "retention_sweep";
+}
+
+// Periodically folds counters into posts so the counters map only holds
+// posts that are still getting engagement
+pub fn schedule_counter_compaction(jobs: &JobQueue, cache: Arc<CacheLayer>, config: &Config) {
+    let cold_millis = config.counter_cold_secs.saturating_mul(1000);
+    jobs.register(JobPolicy::default(), move |_: CompactCounters| {
+        let cache = cache.clone();
+        async move {
+            let cold_before = now_millis().saturating_sub(cold_millis);
+            let (folded, pruned) = tokio::task::spawn_blocking(move || cache.compact_counters(cold_before))
+                .await
+                .map_err(|e| e.to_string())?;
+            if pruned > 0 {
+                info!("Compacted counters: {} folded into posts, {} cold entries pruned", folded, pruned);
+            }
+            Ok(())
+        }
+    });
+    let interval = Duration::from_secs(config.counter_compaction_secs.max(1));
+    jobs.schedule(Schedule::Every(interval), &CompactCounters);
+}
+
+// Periodically drops cache entries that haven't been used within their
+// map's TTL (see crates/newsfeed-store-memory/src/eviction.rs)
+pub fn schedule_eviction(jobs: &JobQueue, cache: Arc<CacheLayer>, config: &Config) {
+    jobs.register(JobPolicy::default(), move |_: EvictIdleEntries| {
+        let cache = cache.clone();
+        async move {
+            let evicted = tokio::task::spawn_blocking(move || cache.evict_idle(now_millis()))
+                .await
+                .map_err(|e| e.to_string())?;
+            if evicted > 0 {
+                info!("Evicted {} idle cache entries", evicted);
+            }
+            Ok(())
+        }
+    });
+    jobs.schedule(Schedule::Every(Duration::from_secs(config.eviction_secs)), &EvictIdleEntries);
+}
+
+// Drops delivery markers once no redelivery of their fanout is expected.
+// A fanout retried after that still skips feeds that have the post.
+pub fn schedule_marker_expiry(jobs: &JobQueue, cache: Arc<CacheLayer>, config: &Config) {
+    let ttl_millis = config.delivery_marker_secs.saturating_mul(1000);
+    jobs.register(JobPolicy::default(), move |_: ExpireDeliveryMarkers| {
+        let cache = cache.clone();
+        async move {
+            let expired = cache.expire_delivery_markers(now_millis().saturating_sub(ttl_millis));
+            if expired > 0 {
+                info!("Expired {} delivery markers", expired);
+            }
+            Ok(())
+        }
+    });
+    let interval = Duration::from_secs((config.delivery_marker_secs / 4).clamp(1, 600));
+    jobs.schedule(Schedule::Every(interval), &ExpireDeliveryMarkers);
+}
+
+// Runs every saved search against posts published since its last check,
+// and notifies the owner of any new matches. A check waits for the search
+// index to catch up, so a post published just before it isn't skipped.
+pub fn schedule_saved_search_checks(jobs: &JobQueue, cache: Arc<CacheLayer>, search: Arc<SearchIndex>, config: &Config) {
+    jobs.register(JobPolicy::default(), move |_: CheckSavedSearches| {
+        let (cache, search) = (cache.clone(), search.clone());
+        async move {
+            let notified = tokio::task::spawn_blocking(move || check_saved_searches(&cache, &search))
+                .await
+                .map_err(|e| e.to_string())?;
+            if notified > 0 {
+                info!("Saved searches found new posts for {} searches", notified);
+            }
+            Ok(())
+        }
+    });
+    let interval = Duration::from_secs(config.saved_search_interval_secs.max(1));
+    jobs.schedule(Schedule::Every(interval), &CheckSavedSearches);
+}
+
+// Publishes scheduled posts as they come due. An author who can no longer
+// post by then loses the post. While the fanout queue is full the job fails
+// and is retried, so the post goes out late rather than without a fanout.
+pub fn register_scheduled_posts(
+    jobs: &Arc<JobQueue>,
+    cache: Arc<CacheLayer>,
+    post_service: Arc<PostService>,
+    video_pipeline: Arc<VideoPipeline>,
+    storage: Arc<StorageRouter>,
+    reminder_secs: u64,
+) {
+    let queue = Arc::downgrade(jobs);
+    let policy = JobPolicy {
+        max_attempts: 10,
+        backoff: Duration::from_secs(5),
+        ..JobPolicy::default()
+    };
+    jobs.register(policy, move |scheduled: ScheduledPost| {
+        let (queue, cache, post_service) = (queue.clone(), cache.clone(), post_service.clone());
+        let (video_pipeline, storage) = (video_pipeline.clone(), storage.clone());
+        async move {
+            let user_id = scheduled.user_id;
+            if cache.get_user(&user_id).is_none() || !cache.account_state(&user_id).allows(Scope::Post) {
+                info!("Dropped a scheduled post by {}: the account can't post", user_id);
+                return Ok(());
+            }
+            if queue.upgrade().is_some_and(|jobs| jobs.is_full::<FanoutMessage>()) {
+                return Err("Fanout queue is full".to_string());
+            }
+            let post = post_service.create_post(&user_id, scheduled.draft).await;
+            submit_video(&video_pipeline, &storage.resolve(cache.account_region(&user_id).as_ref()), &post);
+            if let Some(jobs) = queue.upgrade() {
+                schedule_event_reminder(&jobs, &post, reminder_secs);
+            }
+            Ok(())
+        }
+    });
+}
+
+// Attendees who can still see the event get a notification, pushed unless
+// it's their quiet hours
+pub fn register_event_reminders(jobs: &JobQueue, cache: Arc<CacheLayer>, push: Arc<PushGateway>, catalogs: Arc<Catalogs>) {
+    jobs.register(JobPolicy::default(), move |reminder: EventReminder| {
+        let (cache, push, catalogs) = (cache.clone(), push.clone(), catalogs.clone());
+        async move {
+            let Some(post) = cache.get_post(&reminder.post_id).filter(|post| post.event.is_some()) else {
+                return Ok(());
+            };
+            let notification = Notification::event_reminder(&post.user_id, &post.id);
+            let mut reminded = 0;
+            for user_id in cache.event_attendees(&post.id) {
+                if cache.post_for(&cache.viewer(&user_id), &post.id).is_err() {
+                    continue;
+                }
+                cache.add_notification_once(&user_id, notification.clone());
+                if cache.in_quiet_hours(&user_id, now_millis()) {
+                    push.hold();
+                } else {
+                    let locale = cache.user_locale(&user_id, &catalogs);
+                    let text = notification_text(cache.as_ref(), &catalogs, &locale, &notification);
+                    push.send(&user_id, &notification, &text);
+                }
+                reminded += 1;
+            }
+            info!("Reminded {} attendees of event {}", reminded, post.id);
+            Ok(())
+        }
+    });
+}
+
+// Emails each user who asked for a digest the notifications since their last
+// one, once a day at NEWS_FEED_DIGEST_HOUR in their timezone. Checked every
+// 15 minutes, so zones with half- and quarter-hour offsets get theirs too.
+pub fn schedule_digests(
+    jobs: &JobQueue,
+    cache: Arc<CacheLayer>,
+    email: Arc<EmailService>,
+    catalogs: Arc<Catalogs>,
+    config: &Config,
+) {
+    let hour = config.digest_hour;
+    jobs.register(JobPolicy::default(), move |_: SendDigests| {
+        let (cache, email, catalogs) = (cache.clone(), email.clone(), catalogs.clone());
+        async move {
+            let sent = tokio::task::spawn_blocking(move || send_digests(&cache, &email, &catalogs, hour, now_millis()))
+                .await
+                .map_err(|e| e.to_string())?;
+            if sent > 0 {
+                info!("Sent {} email digests", sent);
+            }
+            Ok(())
+        }
+    });
+    jobs.schedule(Schedule::Every(Duration::from_secs(15 * 60)), &SendDigests);
+}
+
+// Returns how many digests were sent. A user is marked done for the day even
+// with nothing to report, so they're only looked at again tomorrow.
+pub fn send_digests(cache: &CacheLayer, email: &EmailService, catalogs: &Catalogs, hour: u16, now: u64) -> usize {
+    let subscribers: Vec<UserId> = cache
+        .preferences
+        .iter()
+        .filter(|entry| entry.email_digest)
+        .map(|entry| entry.key().clone())
+        .collect();
+    let mut sent = 0;
+    for user_id in subscribers {
+        let zone = cache.user_zone(&user_id);
+        let local = zone.local((now / 1000) as i64);
+        if ClockTime::of(local).hour() != hour {
+            continue;
+        }
+        let last = cache.digests.get(&user_id).map(|last| *last);
+        let today = local.div_euclid(86_400);
+        if last.is_some_and(|last| zone.local((last / 1000) as i64).div_euclid(86_400) == today) {
+            continue;
+        }
+        cache.digests.insert(user_id.clone(), now);
+
+        let since = last.unwrap_or(0).max(now.saturating_sub(DAY_MILLIS));
+        let mut counts: Vec<(NotificationKind, usize)> = Vec::new();
+        for notification in cache.get_notifications(&user_id) {
+            if notification.created_at <= since {
+                break; // newest first
+            }
+            match counts.iter_mut().find(|(kind, _)| *kind == notification.kind) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((notification.kind, 1)),
+            }
+        }
+        let Some(address) = cache.account_record(&user_id).email else {
+            continue;
+        };
+        if counts.is_empty() {
+            continue;
+        }
+        let locale = cache.user_locale(&user_id, catalogs);
+        let lines: Vec<String> = counts
+            .iter()
+            .map(|(kind, count)| catalogs.format(&locale, kind.digest_line(), &[("count", &count.to_string())]))
+            .collect();
+        email.send(&address, catalogs.text(&locale, "Your daily digest"), &lines.join("\n"));
+        sent += 1;
+    }
+    sent
+}
+
+// Returns how many searches had new matches
+pub fn check_saved_searches(cache: &CacheLayer, search: &SearchIndex) -> usize {
+    let status = search.status();
+    if status.lag > 0 || status.reindexing {
+        warn!("Search index is {} events behind; saved searches wait for the next check", status.lag);
+        return 0;
+    }
+    // Posts are stamped just before they are logged, so leave a moment for
+    // one being published right now
+    let through = now_millis().saturating_sub(1000);
+    let mut notified = 0;
+    for user_id in cache.saved_search_owners() {
+        if cache.account_state(&user_id) != AccountState::Active {
+            continue;
+        }
+        for saved in cache.get_saved_searches(&user_id) {
+            let Ok(mut query) = search::Query::parse(&saved.query) else {
+                continue;
+            };
+            let since = saved.checked_through + 1;
+            query.since = Some(query.since.map_or(since, |start| start.max(since)));
+            let author = match &query.from {
+                Some(username) => match cache.find_user_id_by_username(username) {
+                    Some(author_id) => Some(author_id),
+                    None => {
+                        cache.mark_saved_search_checked(&user_id, &saved.id, through, false);
+                        continue;
+                    }
+                },
+                None => None,
+            };
+            let mut matches: Vec<(u64, PostId)> = search
+                .search(&query, author.as_ref())
+                .hits
+                .into_iter()
+                .filter(|hit| hit.timestamp <= through)
+                .filter_map(|hit| cache.get_post(&hit.post_id))
+                .filter(|post| post.user_id != user_id && !cache.is_user_held(&post.user_id))
+                .filter(|post| cache.tombstone_for(&user_id, &post.id).is_none())
+                .map(|post| (post.timestamp, post.id))
+                .collect();
+            matches.sort();
+            if let Some((_, newest_post_id)) = matches.last() {
+                let found = SavedSearchMatch {
+                    search_id: saved.id.clone(),
+                    query: saved.query.clone(),
+                    new_matches: matches.len(),
+                };
+                cache.add_notification(&user_id, Notification::saved_search(&user_id, newest_post_id, found));
+                notified += 1;
+            }
+            cache.mark_saved_search_checked(&user_id, &saved.id, through, !matches.is_empty());
+        }
+    }
+    notified
+}
+
+// Periodically applies the retention policy; does nothing when no retention
+// is configured. Deletions go through the event log like any other write.
+pub fn schedule_retention_sweep(
+    jobs: &JobQueue,
+    cache: Arc<CacheLayer>,
+    events: Arc<EventStore<FeedEvent>>,
+    config: &Config,
+) {
+    let policy = Arc::new(RetentionPolicy::new(config));
+    if !policy.is_enabled() {
+        return;
+    }
+    jobs.register(JobPolicy::default(), move |_: RetentionSweep| {
+        let cache = cache.clone();
+        let events = events.clone();
+        let policy = policy.clone();
+        async move {
+            let dry_run = policy.dry_run;
+            let expired = tokio::task::spawn_blocking(move || {
+                let expired = cache.expired_posts(&policy, now_millis());
+                if !policy.dry_run {
+                    for post_id in &expired {
+                        events.publish(FeedEvent::PostDeleted { post_id: post_id.clone() });
+                    }
+                }
+                expired.len()
+            })
+            .await
+            .map_err(|e| e.to_string())?;
+            match (expired, dry_run) {
+                (0, _) => {}
+                (expired, true) => info!("Retention dry run: {} posts would be deleted", expired),
+                (deleted, false) => info!("Retention: deleted {} expired posts", deleted),
+            }
+            Ok(())
+        }
+    });
+    let schedule = match config.retention_cron.as_deref().map(Cron::parse) {
+        Some(Ok(cron)) => Schedule::Cron(cron),
+        Some(Err(e)) => {
+            warn!("Invalid NEWS_FEED_RETENTION_CRON ({}); using the interval", e);
+            Schedule::Every(Duration::from_secs(config.retention_interval_secs.max(1)))
+        }
+        None => Schedule::Every(Duration::from_secs(config.retention_interval_secs.max(1))),
+    };
+    jobs.schedule(schedule, &RetentionSweep);
+}
+
+// Feeds graph events to the follow analyzer, which flags suspicious accounts
+// into the moderation queue. The sweep bounds memory for idle accounts.
+pub fn spawn_follow_analyzer(cache: Arc<CacheLayer>, queue: Arc<ModerationQueue>, config: &Config) {
+    let mut analyzer = FollowAnalyzer::new(queue, config);
+    let mut events = cache.graph().subscribe();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => analyzer.handle(event),
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!("Follow analyzer fell behind and missed {} graph events", missed)
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                },
+                _ = ticker.tick() => analyzer.sweep(now_millis()),
+            }
+        }
+    });
+}
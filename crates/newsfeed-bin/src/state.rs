@@ -0,0 +1,433 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+use newsfeed_core::accounts::AccountTokens;
+use newsfeed_core::ads::AdService;
+use newsfeed_core::audit::AuditLog;
+use newsfeed_core::bootstrap::Bootstrap;
+use newsfeed_core::cluster::FeedPartitions;
+use newsfeed_core::config::{Config, Settings};
+use newsfeed_core::conversations::ConversationService;
+use newsfeed_core::email::EmailService;
+use newsfeed_core::embeddings::Vectors;
+use newsfeed_core::engagement_log::EngagementLog;
+use newsfeed_core::events::EventStore;
+use newsfeed_core::fanout::{FanoutService, FanoutWorker};
+use newsfeed_core::i18n::Catalogs;
+use newsfeed_core::ids::{PostId, UserId};
+use newsfeed_core::images::ImagePipeline;
+use newsfeed_core::jobs::{Job, JobPolicy, JobQueue, Schedule};
+use newsfeed_core::media::MediaSigner;
+use newsfeed_core::mixer::FeedMixer;
+use newsfeed_core::moderation::ModerationQueue;
+use newsfeed_core::news_feed::NewsFeedService;
+use newsfeed_core::notifications::PushGateway;
+use newsfeed_core::oauth::{self, OAuthProvider};
+use newsfeed_core::passwords::Passwords;
+use newsfeed_core::pipeline::{Ranker, RankingStrategy};
+use newsfeed_core::posts::PostService;
+use newsfeed_core::projections::{
+    FeedProjection, GraphProjection, NotificationProjection, PostProjection, SearchProjection, VectorProjection,
+};
+use newsfeed_core::ranking::{EngagementRanker, RankingService, VectorRanker};
+use newsfeed_core::residency::StorageRouter;
+use newsfeed_core::search::SearchIndex;
+use newsfeed_core::storage::Storage;
+use newsfeed_core::two_factor::TwoFactor;
+use newsfeed_core::users::UserService;
+use newsfeed_core::{FanoutMessage, FeedEvent, FollowBackfill, Post, ReplyPolicy, User, info, now_millis, warn};
+
+use newsfeed_store_memory::cache::CacheLayer;
+use newsfeed_store_memory::eviction::CacheEviction;
+use newsfeed_store_memory::memory::MemoryMonitor;
+
+use newsfeed_http::access_log::RequestMetrics;
+use newsfeed_http::batch::BatchDispatcher;
+use newsfeed_http::cluster::FeedDeliveryClient;
+use newsfeed_http::feed_stream::FeedStreamService;
+use newsfeed_http::http_signature::{KeyRing, Signer};
+use newsfeed_http::profiling::{Profiler, TaskMonitors};
+use newsfeed_http::rate_limit::RateLimiter;
+use newsfeed_http::video::VideoPipeline;
+use newsfeed_http::{AppState, embeddings};
+
+use crate::schedules::{
+    register_event_reminders, register_scheduled_posts, schedule_counter_compaction, schedule_digests,
+    schedule_eviction, schedule_marker_expiry, schedule_retention_sweep, schedule_saved_search_checks,
+    spawn_follow_analyzer,
+};
+
+pub fn init_sample_data(state: &AppState) {
+    let cache = &state.cache;
+    // Create sample users
+    cache.set_user(User {
+        id: UserId::new("user1"),
+        username: "alice".to_string(),
+        profile_picture: "https://example.com/alice.jpg".to_string(),
+        profile_picture_variants: Vec::new(),
+        banner_url: None,
+        banner_variants: Vec::new(),
+        location: None,
+        website: None,
+        joined_at: now_millis(),
+        verified: false,
+        username_changed_at: None,
+        timezone: None,
+    });
+    cache.set_user(User {
+        id: UserId::new("user2"),
+        username: "bob".to_string(),
+        profile_picture: "https://example.com/bob.jpg".to_string(),
+        profile_picture_variants: Vec::new(),
+        banner_url: None,
+        banner_variants: Vec::new(),
+        location: None,
+        website: None,
+        joined_at: now_millis(),
+        verified: false,
+        username_changed_at: None,
+        timezone: None,
+    });
+    cache.set_user(User {
+        id: UserId::new("user3"),
+        username: "charlie".to_string(),
+        profile_picture: "https://example.com/charlie.jpg".to_string(),
+        profile_picture_variants: Vec::new(),
+        banner_url: None,
+        banner_variants: Vec::new(),
+        location: None,
+        website: None,
+        joined_at: now_millis(),
+        verified: false,
+        username_changed_at: None,
+        timezone: None,
+    });
+
+    // Create some follow relationships
+    let (alice, bob, charlie) = (UserId::new("user1"), UserId::new("user2"), UserId::new("user3"));
+    for (follower_id, followed_id) in [
+        (bob.clone(), alice.clone()), // Bob follows Alice
+        (charlie.clone(), alice),     // Charlie follows Alice
+        (charlie, bob),               // Charlie follows Bob
+    ] {
+        state.events.publish(FeedEvent::Followed { follower_id, followed_id });
+    }
+}
+
+// Loads what storage kept from the last run. Users go straight into the
+// cache; posts and follows are appended to the event log, so every
+// projection, search included, is built from them and a rebuild replays
+// them like any other history. Returns how many users, follows, and posts
+// were restored.
+pub fn restore_from_storage(state: &AppState) -> Result<(usize, usize, usize), String> {
+    let Some(storage) = &state.cache.storage else {
+        return Ok((0, 0, 0));
+    };
+    let users = storage.users()?;
+    let follows = storage.follows()?;
+    let mut posts = storage.posts()?;
+    let counts = (users.len(), follows.len(), posts.len());
+    for user in users {
+        state.cache.cache_user(user);
+    }
+    for (follower_id, followed_id, edge) in follows {
+        state.events.publish(FeedEvent::FollowRestored { follower_id, followed_id, edge });
+    }
+    // Oldest first, so replies come after their parents and authors' post
+    // lists are in order
+    posts.sort_by(|a, b| a.timestamp.cmp(&b.timestamp).then_with(|| a.id.cmp(&b.id)));
+    for post in posts {
+        state.events.publish(FeedEvent::PostRestored(Box::new(post)));
+    }
+    Ok(counts)
+}
+
+// Builds one tenant's services and starts its background tasks, except the
+// job dispatcher, which startup starts once the caches are seeded. Production
+// and the sandbox each get their own; they share the OAuth provider so an
+// app's tokens can be told apart in both.
+pub fn build_state(
+    config: Arc<Config>,
+    settings: Arc<Settings>,
+    oauth: Arc<OAuthProvider>,
+    requests: Arc<RequestMetrics>,
+    startup: Arc<Bootstrap>,
+    storage: Option<Arc<dyn Storage>>,
+    sandbox: bool,
+) -> AppState {
+    // Initialize services
+    let passwords = Arc::new(Passwords::new(storage.clone()));
+    let two_factor = Arc::new(TwoFactor::new(config.require_admin_two_factor, storage.clone()));
+    let cache = Arc::new(CacheLayer::new(storage.clone(), CacheEviction::new(&config, settings.clone())));
+    if let Some(storage) = &storage {
+        let subscriber = cache.clone();
+        match storage.subscribe(Box::new(move |change| subscriber.apply_change(change))) {
+            Ok(true) => info!("storage: following changes from other instances"),
+            Ok(false) => {}
+            Err(e) => warn!("storage: failed to follow changes from other instances: {}", e),
+        }
+    }
+    let task_monitors = TaskMonitors::default();
+    let push_gateway = Arc::new(PushGateway::default());
+    let jobs = Arc::new(JobQueue::new(config.jobs_file.clone()));
+    let feed_nodes = Arc::new(FeedDeliveryClient::new(&config));
+    let catalogs = Arc::new(Catalogs::load(&config).expect("NEWS_FEED_LOCALES_DIR is checked at startup"));
+    let fanout_worker = Arc::new(FanoutWorker::new(
+        cache.clone(),
+        push_gateway.clone(),
+        catalogs.clone(),
+        Arc::new(FeedPartitions::new(&config)),
+        feed_nodes.clone(),
+    ));
+    let fanout_monitor = task_monitors.fanout.clone();
+    let worker = fanout_worker.clone();
+    jobs.register(
+        JobPolicy {
+            concurrency: 5,
+            shared: true,
+            max_queued: Some(config.fanout_queue_limit),
+            ..JobPolicy::default()
+        },
+        move |message: FanoutMessage| {
+            let worker = worker.clone();
+            fanout_monitor.instrument(async move { worker.process(message).await })
+        },
+    );
+    let worker = fanout_worker.clone();
+    jobs.register(JobPolicy::default(), move |job: FollowBackfill| {
+        let worker = worker.clone();
+        async move {
+            worker.backfill(job).await;
+            Ok(())
+        }
+    });
+    let fanout_service = Arc::new(FanoutService::new(
+        cache.clone(),
+        jobs.clone(),
+        config.pull_fanout_followers,
+        config.follow_backfill_posts,
+    ));
+    // Registered in dependency order: fanout reads the graph
+    let engagement_log = Arc::new(EngagementLog::new(&config, settings.clone()));
+    let events = Arc::new(EventStore::default());
+    events.register(Arc::new(PostProjection {
+        cache: cache.clone(),
+        engagement_log: engagement_log.clone(),
+    }));
+    events.register(Arc::new(GraphProjection { cache: cache.clone() }));
+    events.register(Arc::new(NotificationProjection { cache: cache.clone() }));
+    events.register(Arc::new(FeedProjection {
+        cache: cache.clone(),
+        fanout_service: fanout_service.clone(),
+    }));
+    let search = SearchIndex::spawn();
+    events.register(Arc::new(SearchProjection { index: search.clone() }));
+    let vectors = embeddings::connect(&config)
+        .expect("NEWS_FEED_EMBEDDINGS is checked at startup")
+        .map(Vectors::spawn);
+    if let Some(vectors) = &vectors {
+        events.register(Arc::new(VectorProjection { vectors: vectors.clone() }));
+    }
+    let post_service = Arc::new(PostService::new(cache.clone(), events.clone()));
+    let media_signer = Arc::new(MediaSigner::new(&config, settings.clone()));
+    // Personalized runs vector similarity first, so viewer feedback still has
+    // the last word. Chronological has no rankers.
+    const PERSONALIZED: &[RankingStrategy] = &[RankingStrategy::Personalized];
+    let mut rankers: Vec<(Arc<dyn Ranker>, &'static [RankingStrategy])> = Vec::new();
+    if let Some(vectors) = &vectors {
+        rankers.push((Arc::new(VectorRanker::new(cache.clone(), vectors.clone())), PERSONALIZED));
+    }
+    rankers.push((Arc::new(RankingService::new(cache.clone(), settings.clone())), PERSONALIZED));
+    rankers.push((
+        Arc::new(EngagementRanker::new(cache.clone(), settings.clone())),
+        &[RankingStrategy::Engagement],
+    ));
+    let ad_service = Arc::new(AdService::new(cache.clone(), &config, settings.clone()));
+    let news_feed_service = Arc::new(NewsFeedService::new(
+        cache.clone(),
+        media_signer.clone(),
+        rankers,
+        Arc::new(FeedMixer::new(cache.clone(), settings.clone())),
+        ad_service,
+        engagement_log.clone(),
+        settings.clone(),
+    ));
+    let conversation_service = Arc::new(ConversationService::new(
+        cache.clone(),
+        news_feed_service.clone(),
+    ));
+    let email_service = Arc::new(EmailService::default());
+    let user_service = Arc::new(UserService::new(
+        cache.clone(),
+        config.clone(),
+        email_service.clone(),
+        push_gateway.clone(),
+        catalogs.clone(),
+    ));
+    let storage = Arc::new(StorageRouter::new(&config));
+    let image_pipeline = Arc::new(ImagePipeline::new(storage.clone()));
+    let account_tokens = Arc::new(AccountTokens::new(&config));
+    let video_pipeline = Arc::new(VideoPipeline::new(
+        cache.clone(),
+        storage.clone(),
+        &config,
+        task_monitors.transcode.clone(),
+    ));
+
+    schedule_counter_compaction(&jobs, cache.clone(), &config);
+    schedule_eviction(&jobs, cache.clone(), &config);
+    schedule_marker_expiry(&jobs, cache.clone(), &config);
+    schedule_retention_sweep(&jobs, cache.clone(), events.clone(), &config);
+    schedule_saved_search_checks(&jobs, cache.clone(), search.clone(), &config);
+    schedule_digests(&jobs, cache.clone(), email_service.clone(), catalogs.clone(), &config);
+    register_scheduled_posts(
+        &jobs,
+        cache.clone(),
+        post_service.clone(),
+        video_pipeline.clone(),
+        storage.clone(),
+        config.event_reminder_secs,
+    );
+    register_event_reminders(&jobs, cache.clone(), push_gateway.clone(), catalogs.clone());
+
+    let moderation = Arc::new(ModerationQueue::default());
+    spawn_follow_analyzer(cache.clone(), moderation.clone(), &config);
+
+    let memory_monitor = Arc::new(MemoryMonitor::new(cache.clone(), &config));
+    memory_monitor
+        .clone()
+        .spawn_budget_checks(Duration::from_secs(config.memory_check_secs.max(1)));
+
+    AppState {
+        cache: cache.clone(),
+        post_service,
+        feed_stream: Arc::new(FeedStreamService::new(
+            cache.clone(),
+            news_feed_service.clone(),
+            config.feed_stream_max_per_user,
+        )),
+        news_feed_service,
+        conversation_service,
+        user_service,
+        image_pipeline,
+        account_tokens: account_tokens.clone(),
+        video_pipeline,
+        media_signer,
+        profiler: Arc::new(Profiler::new(task_monitors)),
+        memory_monitor: memory_monitor.clone(),
+        push_gateway: push_gateway.clone(),
+        moderation,
+        audit_log: Arc::new(AuditLog::default()),
+        email_service,
+        catalogs: catalogs.clone(),
+        two_factor,
+        passwords,
+        oauth,
+        signer: Arc::new(Signer::ed25519(&config.federation_key_id, &config.federation_signing_key)),
+        peers: Arc::new(KeyRing::new(&config.federation_peers, config.signature_max_age_secs)),
+        storage,
+        batch: Arc::new(BatchDispatcher::new(catalogs.clone())),
+        events,
+        engagement_log,
+        telemetry_limiter: Arc::new(RateLimiter::new(settings.clone(), |tunables| {
+            (tunables.telemetry_per_minute, tunables.telemetry_burst)
+        })),
+        public_limiter: Arc::new(RateLimiter::new(settings.clone(), |tunables| {
+            (tunables.public_per_minute, tunables.public_burst)
+        })),
+        search,
+        vectors,
+        jobs,
+        fanout_worker,
+        feed_nodes,
+        startup,
+        requests,
+        config: config.clone(),
+        settings,
+        sandbox,
+    }
+}
+
+// The sandbox's fake data: the sample accounts with a few posts already in
+// their feeds, plus the test account sandbox apps act as, following them all
+pub fn init_sandbox_data(state: &AppState) {
+    init_sample_data(state);
+    let test_user = oauth::test_user();
+    state.cache.set_user(User {
+        id: test_user.clone(),
+        username: "sandbox".to_string(),
+        profile_picture: "https://example.com/sandbox.jpg".to_string(),
+        profile_picture_variants: Vec::new(),
+        banner_url: None,
+        banner_variants: Vec::new(),
+        location: None,
+        website: None,
+        joined_at: now_millis(),
+        verified: false,
+        username_changed_at: None,
+        timezone: None,
+    });
+
+    let posts = [
+        ("user1", "Welcome to the sandbox! Nothing here reaches production."),
+        ("user2", "Try liking this post or replying to it."),
+        ("user3", "Everything you write here is wiped on the next reset."),
+    ];
+    for (offset, (author, content)) in posts.into_iter().enumerate() {
+        let author = UserId::new(author);
+        state.events.publish(FeedEvent::Followed {
+            follower_id: test_user.clone(),
+            followed_id: author.clone(),
+        });
+        let post = Post {
+            id: PostId::new(format!("post_sandbox_{}", offset + 1)),
+            user_id: author.clone(),
+            content: content.to_string(),
+            image_url: None,
+            video_url: None,
+            alt_text: None,
+            in_reply_to: None,
+            mentions: Vec::new(),
+            hashtags: Vec::new(),
+            reply_policy: ReplyPolicy::Everyone,
+            emojis: Vec::new(),
+            timestamp: now_millis() + offset as u64,
+            like_count: 0,
+            reply_count: 0,
+            edited_at: None,
+            anonymous: false,
+            license: None,
+            co_authors: Vec::new(),
+            event: None,
+            article: None,
+        };
+        state.events.publish(FeedEvent::PostCreated(Box::new(post)));
+    }
+}
+
+// Wipes the sandbox tenant, history included, and reseeds it with fresh
+// fake data
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResetSandbox;
+
+impl Job for ResetSandbox {
+    const KIND: &'static str = "reset_sandbox";
+}
+
+pub fn schedule_sandbox_reset(state: AppState, config: &Config) {
+    let jobs = state.jobs.clone();
+    jobs.register(JobPolicy::default(), move |_: ResetSandbox| {
+        let state = state.clone();
+        async move {
+            state.events.clear();
+            state.search.clear();
+            state.cache.clear();
+            init_sandbox_data(&state);
+            info!("Sandbox tenant reset");
+            Ok(())
+        }
+    });
+    let interval = Duration::from_secs(config.sandbox_reset_secs.max(1));
+    jobs.schedule(Schedule::Every(interval), &ResetSandbox);
+}
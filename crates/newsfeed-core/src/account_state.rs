@@ -0,0 +1,109 @@
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::ids::UserId;
+use crate::region::Region;
+
+// What a linked account may be used for when switched to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    Read,
+    Post,
+    Engage, // likes and follows
+    Manage, // profile, settings, and admin actions
+}
+
+// Accounts without a record (everyone who predates signup) are active
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountState {
+    PendingVerification,
+    #[default]
+    Active,
+    Suspended,
+    Deactivated,
+}
+
+impl AccountState {
+    // pending_verification → active → suspended → deactivated. Verifying the
+    // email activates a pending account; admins can reinstate suspended or
+    // deactivated accounts, and any account can be deactivated.
+    pub fn can_become(self, next: AccountState) -> bool {
+        use AccountState::*;
+        self != next
+            && matches!(
+                (self, next),
+                (PendingVerification, Active)
+                    | (Active, Suspended)
+                    | (Suspended | Deactivated, Active)
+                    | (_, Deactivated)
+            )
+    }
+
+    // Unverified accounts may read and manage their profile but not post or
+    // engage; suspended ones may only read; deactivated ones may do nothing
+    pub fn allows(self, scope: Scope) -> bool {
+        match self {
+            AccountState::Active => true,
+            AccountState::PendingVerification => matches!(scope, Scope::Read | Scope::Manage),
+            AccountState::Suspended => scope == Scope::Read,
+            AccountState::Deactivated => false,
+        }
+    }
+}
+
+// Also what storage keeps, so it reads back what it wrote
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccountRecord {
+    pub state: AccountState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub state_changed_at: u64,
+    // Why an admin last changed the state
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    // Where the account's data is stored; none means the node's own region
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<Region>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PendingEmail {
+    pub user_id: UserId,
+    pub email: String,
+    expires_at: u64,
+}
+
+// Single-use email verification tokens. Issuing a new token for an account
+// invalidates its earlier ones.
+#[derive(Debug, Default)]
+pub struct EmailVerifications {
+    tokens: DashMap<String, PendingEmail>,
+}
+
+impl EmailVerifications {
+    pub fn issue(&self, user_id: &UserId, email: &str, expires_at: u64, now: u64) -> String {
+        self.tokens
+            .retain(|_, pending| pending.user_id != *user_id && pending.expires_at > now);
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        self.tokens.insert(
+            token.clone(),
+            PendingEmail {
+                user_id: user_id.clone(),
+                email: email.to_string(),
+                expires_at,
+            },
+        );
+        token
+    }
+
+    pub fn redeem(&self, token: &str, now: u64) -> Option<PendingEmail> {
+        self.tokens
+            .remove(token)
+            .map(|(_, pending)| pending)
+            .filter(|pending| pending.expires_at > now)
+    }
+}
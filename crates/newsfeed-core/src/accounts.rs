@@ -0,0 +1,217 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::config::Config;
+use crate::ids::UserId;
+use crate::now_millis;
+pub use crate::account_state::Scope;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkedAccount {
+    pub user_id: UserId,
+    pub scopes: Vec<Scope>,
+    // Whether the token it was linked with had passed two-factor login
+    #[serde(default)]
+    pub two_factor: bool,
+}
+
+// Set on tokens issued to third-party apps through OAuth: the app may act as
+// the primary account only, and only within the scopes the user approved
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delegation {
+    pub client_id: String,
+    pub scopes: Vec<Scope>,
+    pub issued_at: u64,
+    pub expires_at: u64,
+}
+
+// Claims carried by a token. The primary account has every scope, unless
+// the token was delegated to an app.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountSet {
+    pub primary: UserId,
+    pub linked: Vec<LinkedAccount>,
+    // Set when the primary account logged in with a second factor
+    #[serde(default)]
+    pub two_factor: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delegation: Option<Delegation>,
+    // When the login behind this token happened, in seconds. Renewed tokens
+    // keep it, so a session can't be stretched past NEWS_FEED_SESSION_SECS.
+    #[serde(default)]
+    pub auth_time: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AccountError {
+    InvalidToken, // also an expired one
+    SessionExpired, // renewed past NEWS_FEED_SESSION_SECS; log in again
+    NotLinked,
+    ScopeDenied,
+}
+
+// The only header tokens are issued with; anything else, "alg": "none"
+// included, is refused
+const JWT_HEADER: &str = r#"{"alg":"HS256","typ":"JWT"}"#;
+
+#[derive(Debug, Deserialize)]
+struct JwtHeader {
+    alg: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    sub: UserId, // the primary account, for tools that read JWTs
+    iat: u64,    // seconds
+    exp: u64,
+    #[serde(flatten)]
+    accounts: AccountSet,
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Issues and verifies JWTs signed with HS256
+pub struct AccountTokens {
+    key: Vec<u8>,
+    ttl_secs: u64,
+    session_secs: u64,
+    demo_tokens: bool,
+}
+
+impl AccountTokens {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            key: config.token_signing_key.as_bytes().to_vec(),
+            ttl_secs: config.token_ttl_secs,
+            session_secs: config.session_ttl_secs,
+            demo_tokens: config.demo_tokens,
+        }
+    }
+
+    // Tokens delegated to an app last as long as the grant; the rest for
+    // NEWS_FEED_TOKEN_SECS, but never past NEWS_FEED_SESSION_SECS after the
+    // login they came from. A session already past that needs the password
+    // again rather than a token that's expired when issued.
+    pub fn issue(&self, accounts: &AccountSet) -> Result<String, AccountError> {
+        let now = now_millis() / 1000;
+        let exp = match &accounts.delegation {
+            Some(delegation) => delegation.expires_at / 1000,
+            None => (now + self.ttl_secs).min(accounts.auth_time + self.session_secs),
+        };
+        if exp <= now {
+            return Err(AccountError::SessionExpired);
+        }
+        let claims = Claims {
+            sub: accounts.primary.clone(),
+            iat: now,
+            exp,
+            accounts: accounts.clone(),
+        };
+        let header = URL_SAFE_NO_PAD.encode(JWT_HEADER);
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).expect("claims serialize"));
+        let signed = format!("{}.{}", header, payload);
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(&signed).finalize().into_bytes());
+        Ok(format!("{}.{}", signed, signature))
+    }
+
+    // Accepts a JWT, bare or in the "Bearer <token>" form OAuth clients send.
+    // Unsigned `user_<id>` tokens only work with NEWS_FEED_DEMO_TOKENS on.
+    pub fn decode(&self, token: &str) -> Result<AccountSet, AccountError> {
+        let token = token.strip_prefix("Bearer ").unwrap_or(token);
+        if self.demo_tokens
+            && let Some(user_id) = token.strip_prefix("user_")
+        {
+            return Ok(AccountSet {
+                primary: UserId::new(user_id),
+                linked: Vec::new(),
+                two_factor: false,
+                delegation: None,
+                auth_time: now_millis() / 1000,
+            });
+        }
+
+        let (signed, signature) = token.rsplit_once('.').ok_or(AccountError::InvalidToken)?;
+        let (header, payload) = signed.split_once('.').ok_or(AccountError::InvalidToken)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| AccountError::InvalidToken)?;
+        self.mac(signed)
+            .verify_slice(&signature)
+            .map_err(|_| AccountError::InvalidToken)?;
+
+        let header: JwtHeader = decode_part(header)?;
+        if header.alg != "HS256" {
+            return Err(AccountError::InvalidToken);
+        }
+        let mut claims: Claims = decode_part(payload)?;
+        if claims.exp <= now_millis() / 1000 {
+            return Err(AccountError::InvalidToken);
+        }
+        // Tokens from before auth_time was carried count from when issued
+        if claims.accounts.auth_time == 0 {
+            claims.accounts.auth_time = claims.iat;
+        }
+        Ok(claims.accounts)
+    }
+
+    fn mac(&self, payload: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(payload.as_bytes());
+        mac
+    }
+}
+
+fn decode_part<T: serde::de::DeserializeOwned>(part: &str) -> Result<T, AccountError> {
+    let bytes = URL_SAFE_NO_PAD.decode(part).map_err(|_| AccountError::InvalidToken)?;
+    serde_json::from_slice(&bytes).map_err(|_| AccountError::InvalidToken)
+}
+
+impl AccountSet {
+    // Records a successful second factor for one of the token's accounts
+    pub fn mark_two_factor(&mut self, user_id: &UserId) {
+        if self.primary == *user_id {
+            self.two_factor = true;
+        } else if let Some(account) = self.linked.iter_mut().find(|account| account.user_id == *user_id) {
+            account.two_factor = true;
+        }
+    }
+
+    pub fn passed_two_factor(&self, user_id: &UserId) -> bool {
+        if self.primary == *user_id {
+            return self.two_factor;
+        }
+        self.linked
+            .iter()
+            .any(|account| account.user_id == *user_id && account.two_factor)
+    }
+
+    // Picks the account a request acts as and checks it may do so
+    pub fn select(&self, active: Option<&str>, required: Scope) -> Result<UserId, AccountError> {
+        if let Some(delegation) = &self.delegation {
+            return match active {
+                Some(user_id) if self.primary != *user_id => Err(AccountError::NotLinked),
+                _ if delegation.scopes.contains(&required) => Ok(self.primary.clone()),
+                _ => Err(AccountError::ScopeDenied),
+            };
+        }
+        match active {
+            None => Ok(self.primary.clone()),
+            Some(user_id) if self.primary == *user_id => Ok(self.primary.clone()),
+            Some(user_id) => {
+                let account = self
+                    .linked
+                    .iter()
+                    .find(|account| account.user_id == *user_id)
+                    .ok_or(AccountError::NotLinked)?;
+                if account.scopes.contains(&required) {
+                    Ok(account.user_id.clone())
+                } else {
+                    Err(AccountError::ScopeDenied)
+                }
+            }
+        }
+    }
+}
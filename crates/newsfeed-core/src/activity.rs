@@ -0,0 +1,150 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+const MINUTE_MILLIS: u64 = 60 * 1000;
+// A year plus the partial week it starts in
+const RETAINED_DAYS: u64 = 366 + 7;
+// Feed reads further apart than this start a new reading session
+const SESSION_GAP_MILLIS: u64 = 5 * MINUTE_MILLIS;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activity {
+    Post,
+    Like,
+    FeedRead,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct DayActivity {
+    posts: u32,
+    likes: u32,
+    read_millis: u64,
+}
+
+// One user's activity per UTC day, kept for about a year
+#[derive(Debug, Default, Serialize)]
+pub struct ActivityLog {
+    days: BTreeMap<u64, DayActivity>,
+    last_read: Option<u64>,
+}
+
+impl ActivityLog {
+    // There is no client-side reading timer, so reading time is inferred from
+    // feed requests: a request within the session gap of the previous one
+    // counts the time in between, and the first request of a session counts
+    // one minute.
+    pub fn record(&mut self, activity: Activity, now: u64) {
+        let today = now / DAY_MILLIS;
+        let day = self.days.entry(today).or_default();
+        match activity {
+            Activity::Post => day.posts += 1,
+            Activity::Like => day.likes += 1,
+            Activity::FeedRead => {
+                day.read_millis += match self.last_read {
+                    Some(last) if now.saturating_sub(last) <= SESSION_GAP_MILLIS => now - last,
+                    _ => MINUTE_MILLIS,
+                };
+                self.last_read = Some(now);
+            }
+        }
+
+        if let Some((&oldest, _)) = self.days.first_key_value()
+            && oldest + RETAINED_DAYS <= today
+        {
+            self.days = self.days.split_off(&(today + 1 - RETAINED_DAYS));
+        }
+    }
+
+    // Weeks run Monday to Sunday, oldest first, ending with the current week
+    pub fn stats(&self, now: u64, weeks: u64) -> ActivityStats {
+        let today = now / DAY_MILLIS;
+        let first_day = week_start(today).saturating_sub((weeks - 1) * 7);
+
+        let mut stats = ActivityStats::default();
+        for week in 0..weeks {
+            let start = first_day + week * 7;
+            let mut summary = WeekSummary {
+                week_start: civil_date(start),
+                ..WeekSummary::default()
+            };
+            for (&day, activity) in self.days.range(start..start + 7) {
+                summary.totals.add(activity);
+                stats.days.push(DaySummary {
+                    date: civil_date(day),
+                    posts: activity.posts,
+                    likes: activity.likes,
+                    read_minutes: activity.read_millis / MINUTE_MILLIS,
+                });
+            }
+            stats.totals.merge(&summary.totals);
+            stats.weeks.push(summary);
+        }
+        stats
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ActivityStats {
+    days: Vec<DaySummary>, // days with any activity, for the heatmap
+    weeks: Vec<WeekSummary>,
+    totals: Totals,
+}
+
+#[derive(Debug, Serialize)]
+struct DaySummary {
+    date: String,
+    posts: u32,
+    likes: u32,
+    read_minutes: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct WeekSummary {
+    week_start: String,
+    #[serde(flatten)]
+    totals: Totals,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Totals {
+    posts: u32,
+    likes: u32,
+    read_minutes: u64,
+    active_days: u32,
+}
+
+impl Totals {
+    fn add(&mut self, day: &DayActivity) {
+        self.posts += day.posts;
+        self.likes += day.likes;
+        self.read_minutes += day.read_millis / MINUTE_MILLIS;
+        self.active_days += 1;
+    }
+
+    fn merge(&mut self, other: &Totals) {
+        self.posts += other.posts;
+        self.likes += other.likes;
+        self.read_minutes += other.read_minutes;
+        self.active_days += other.active_days;
+    }
+}
+
+// Day 0 (1970-01-01) was a Thursday
+fn week_start(day: u64) -> u64 {
+    day.saturating_sub((day + 3) % 7)
+}
+
+// "YYYY-MM-DD" for a day number, using Howard Hinnant's civil_from_days
+pub fn civil_date(day: u64) -> String {
+    let z = day + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = era * 400 + yoe + u64::from(m <= 2);
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
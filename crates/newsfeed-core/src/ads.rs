@@ -0,0 +1,173 @@
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::cache::Cache;
+use crate::config::{Config, Settings};
+use crate::ids::{PostId, TagId, UserId};
+use crate::mixer::Injection;
+use crate::pipeline::{Candidate, FeedRequest, Mixer};
+use crate::{Post, now_millis};
+
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+// Who a sponsored post may be shown to; unset fields match everyone
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Targeting {
+    #[serde(default)]
+    pub follower_of: Option<UserId>, // viewer follows this user
+    #[serde(default)]
+    pub topic: Option<TagId>, // a post on the page carries this #hashtag
+    #[serde(default)]
+    pub language: Option<String>, // viewer's preferred language
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Campaign {
+    pub id: String,
+    pub post_id: PostId,
+    pub targeting: Targeting,
+    pub budget: u32, // impressions
+    pub starts_at: u64,
+    pub ends_at: u64,
+    pub impressions: u32,
+    pub clicks: u32,
+}
+
+impl Campaign {
+    // Even pacing: by any moment, spend at most the elapsed share of the budget
+    fn paced_allowance(&self, now: u64) -> u32 {
+        let duration = self.ends_at.saturating_sub(self.starts_at).max(1);
+        let elapsed = now.saturating_sub(self.starts_at).min(duration);
+        let allowance = self.budget as u64 * elapsed / duration + 1;
+        allowance.min(self.budget as u64) as u32
+    }
+
+    pub fn is_deliverable(&self, now: u64) -> bool {
+        now >= self.starts_at
+            && now < self.ends_at
+            && self.impressions < self.paced_allowance(now)
+    }
+}
+
+// Serves sponsored posts into fixed feed slots
+pub struct AdService {
+    cache: Arc<dyn Cache>,
+    slots: Vec<usize>,
+    settings: Arc<Settings>, // for the daily cap, shared with trending posts
+}
+
+impl AdService {
+    pub fn new(cache: Arc<dyn Cache>, config: &Config, settings: Arc<Settings>) -> Self {
+        let mut slots: Vec<usize> = config
+            .sponsored_slots
+            .iter()
+            .copied()
+            .filter(|slot| *slot > 0)
+            .collect();
+        slots.sort_unstable();
+        slots.dedup();
+        Self {
+            cache,
+            slots,
+            settings,
+        }
+    }
+
+    // Campaigns for up to `count` slots, each counted as one impression.
+    // Campaigns furthest behind their pacing go first.
+    pub fn pick(&self, viewer_id: &UserId, page_topics: &[TagId], count: usize) -> Vec<(Campaign, Post)> {
+        if count == 0 {
+            return Vec::new();
+        }
+
+        let now = now_millis();
+        let day = now / DAY_MILLIS;
+        let language = self.cache.get_preferences(viewer_id).language;
+        let daily_cap = self.settings.current().injected_daily_cap;
+        let mut candidates: Vec<Campaign> = self
+            .cache
+            .list_campaigns()
+            .into_iter()
+            .filter(|campaign| campaign.is_deliverable(now))
+            .filter(|campaign| self.matches(&campaign.targeting, viewer_id, page_topics, language.as_deref()))
+            .filter(|campaign| {
+                self.cache.impression_count(viewer_id, &campaign.post_id, day) < daily_cap
+                    && !self.cache.is_hidden(viewer_id, &campaign.post_id)
+            })
+            .collect();
+        candidates.sort_by_key(|campaign| campaign.impressions as u64 * 1000 / campaign.budget.max(1) as u64);
+
+        let mut picked = Vec::new();
+        for campaign in candidates {
+            if picked.len() == count {
+                break;
+            }
+            let Some(post) = self.cache.get_post(&campaign.post_id) else {
+                continue;
+            };
+            if &post.user_id == viewer_id {
+                continue;
+            }
+            // Another request may have used up the allowance in the meantime
+            if let Some(campaign) = self.cache.record_campaign_impression(&campaign.id, now) {
+                self.cache.record_impression(viewer_id, &campaign.post_id, day);
+                picked.push((campaign, post));
+            }
+        }
+        picked
+    }
+
+    fn matches(
+        &self,
+        targeting: &Targeting,
+        viewer_id: &UserId,
+        page_topics: &[TagId],
+        language: Option<&str>,
+    ) -> bool {
+        let follows = targeting
+            .follower_of
+            .as_ref()
+            .is_none_or(|user_id| self.cache.is_following(viewer_id, user_id));
+        let topic = targeting
+            .topic
+            .as_ref()
+            .is_none_or(|topic| page_topics.contains(topic));
+        let language_matches = targeting
+            .language
+            .as_deref()
+            .is_none_or(|wanted| language.is_some_and(|language| language.eq_ignore_ascii_case(wanted)));
+        follows && topic && language_matches
+    }
+}
+
+// Sponsored posts go at fixed 1-based positions that the page is long enough
+// to reach
+impl Mixer for AdService {
+    fn mix<'a>(&'a self, request: &'a FeedRequest<'_>, mut page: Vec<Candidate>) -> BoxFuture<'a, Vec<Candidate>> {
+        let slots: Vec<usize> = self
+            .slots
+            .iter()
+            .copied()
+            .filter(|slot| *slot <= page.len() + 1)
+            .collect();
+        let page_topics: Vec<TagId> = page
+            .iter()
+            .flat_map(|candidate| crate::content::extract_hashtags(&candidate.post.content))
+            .collect();
+
+        let picked = self.pick(&request.ctx.user_id, &page_topics, slots.len());
+        for (slot, (campaign, post)) in slots.into_iter().zip(picked) {
+            let index = (slot - 1).min(page.len());
+            page.insert(
+                index,
+                Candidate {
+                    post,
+                    injected: Some(Injection::Sponsored),
+                    campaign_id: Some(campaign.id),
+                },
+            );
+        }
+        Box::pin(async move { page })
+    }
+}
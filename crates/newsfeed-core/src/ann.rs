@@ -0,0 +1,145 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+// Hyperplanes per table; each splits the space in two, so a table has
+// 2^BITS buckets
+const BITS: usize = 12;
+const TABLES: usize = 8;
+
+// Below this many vectors a full scan is cheap enough to be exact
+const EXACT_BELOW: usize = 2_000;
+
+// Fixed so signatures are the same on every node and after every restart
+const SEED: u64 = 0x6e65_7773_6665_6564;
+
+// Scales a vector to unit length, so a dot product is its cosine; false for
+// the zero vector
+pub fn normalize(vector: &mut [f32]) -> bool {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm == 0.0 || !norm.is_finite() {
+        return false;
+    }
+    vector.iter_mut().for_each(|x| *x /= norm);
+    true
+}
+
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+// Approximate nearest neighbours by cosine similarity, using random
+// hyperplane LSH: each table hashes a vector to the side of each of its
+// hyperplanes it falls on, so close vectors tend to share buckets. A query
+// looks in its own bucket and the ones a single bit away in every table,
+// then ranks what it found exactly.
+pub struct AnnIndex<K> {
+    dimensions: usize,
+    planes: Vec<Vec<f32>>, // TABLES × BITS
+    tables: Vec<HashMap<u16, HashSet<K>>>,
+    vectors: HashMap<K, (Vec<f32>, [u16; TABLES])>,
+}
+
+impl<K: Clone + Eq + Hash> AnnIndex<K> {
+    pub fn new(dimensions: usize) -> Self {
+        let mut rng = StdRng::seed_from_u64(SEED);
+        let planes = (0..TABLES * BITS)
+            .map(|_| (0..dimensions).map(|_| rng.gen_range(-1.0..1.0)).collect())
+            .collect();
+        Self {
+            dimensions,
+            planes,
+            tables: vec![HashMap::new(); TABLES],
+            vectors: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.vectors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vectors.is_empty()
+    }
+
+    pub fn get(&self, key: &K) -> Option<&[f32]> {
+        self.vectors.get(key).map(|(vector, _)| vector.as_slice())
+    }
+
+    fn signature(&self, vector: &[f32], table: usize) -> u16 {
+        self.planes[table * BITS..(table + 1) * BITS]
+            .iter()
+            .enumerate()
+            .fold(0, |signature, (bit, plane)| {
+                if dot(plane, vector) >= 0.0 { signature | 1 << bit } else { signature }
+            })
+    }
+
+    // Replaces the key's vector. Vectors of the wrong length or with no
+    // direction are refused.
+    pub fn insert(&mut self, key: K, mut vector: Vec<f32>) -> bool {
+        if vector.len() != self.dimensions || !normalize(&mut vector) {
+            return false;
+        }
+        self.remove(&key);
+        let mut signatures = [0; TABLES];
+        for (table, signature) in signatures.iter_mut().enumerate() {
+            *signature = self.signature(&vector, table);
+            self.tables[table].entry(*signature).or_default().insert(key.clone());
+        }
+        self.vectors.insert(key, (vector, signatures));
+        true
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        let Some((_, signatures)) = self.vectors.remove(key) else {
+            return;
+        };
+        for (table, signature) in signatures.iter().enumerate() {
+            if let Some(bucket) = self.tables[table].get_mut(signature) {
+                bucket.remove(key);
+                if bucket.is_empty() {
+                    self.tables[table].remove(signature);
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.tables.iter_mut().for_each(HashMap::clear);
+        self.vectors.clear();
+    }
+
+    // Up to `max` keys most similar to `query`, with their cosine
+    // similarity, skipping those `skip` returns true for
+    pub fn nearest(&self, query: &[f32], max: usize, skip: impl Fn(&K) -> bool) -> Vec<(K, f32)> {
+        let mut query = query.to_vec();
+        if query.len() != self.dimensions || !normalize(&mut query) {
+            return Vec::new();
+        }
+        let candidates: Vec<&K> = if self.vectors.len() < EXACT_BELOW {
+            self.vectors.keys().collect()
+        } else {
+            let mut found = HashSet::new();
+            for table in 0..TABLES {
+                let signature = self.signature(&query, table);
+                let probes = std::iter::once(signature).chain((0..BITS).map(|bit| signature ^ 1 << bit));
+                for probe in probes {
+                    if let Some(bucket) = self.tables[table].get(&probe) {
+                        found.extend(bucket);
+                    }
+                }
+            }
+            found.into_iter().collect()
+        };
+        let mut scored: Vec<(K, f32)> = candidates
+            .into_iter()
+            .filter(|key| !skip(key))
+            .filter_map(|key| Some((key.clone(), dot(&query, &self.vectors.get(key)?.0))))
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scored.truncate(max);
+        scored
+    }
+}
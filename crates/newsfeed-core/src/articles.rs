@@ -0,0 +1,87 @@
+use pulldown_cmark::{CowStr, Event, Options, Parser, Tag, TagEnd, html};
+use serde::{Deserialize, Serialize};
+
+pub const MAX_ARTICLE_TITLE_CHARS: usize = 150;
+pub const MAX_ARTICLE_CHARS: usize = 100_000;
+// Markdown bodies are far larger than posts, so their route takes more
+pub const MAX_ARTICLE_JSON_BYTES: u64 = 512 * 1024;
+const WORDS_PER_MINUTE: usize = 230;
+
+// What an article's post carries into feeds; the body is kept apart (see
+// CacheLayer::article_body) and only read on the article's own page
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArticleCard {
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cover_image_url: Option<String>,
+    pub reading_minutes: u32,
+}
+
+// At least a minute, however short
+pub fn reading_minutes(markdown: &str) -> u32 {
+    let words = plain_text(Parser::new(markdown)).split_whitespace().count();
+    words.div_ceil(WORDS_PER_MINUTE).max(1) as u32
+}
+
+// The first paragraph as plain text, cut to `max_chars` with an ellipsis;
+// used as the post's text when the author gives no summary
+pub fn teaser(markdown: &str, max_chars: usize) -> String {
+    let paragraph = Parser::new(markdown)
+        .skip_while(|event| !matches!(event, Event::Start(Tag::Paragraph)))
+        .take_while(|event| !matches!(event, Event::End(TagEnd::Paragraph)));
+    let text = plain_text(paragraph);
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() <= max_chars {
+        return text;
+    }
+    let mut cut: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    cut.truncate(cut.trim_end().len());
+    cut.push('…');
+    cut
+}
+
+// HTML for the article page. Raw HTML in the markdown is shown as text, and
+// links and images only keep web, mail, and relative addresses.
+pub fn render(markdown: &str) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+    let events = Parser::new_ext(markdown, options).map(|event| match event {
+        Event::Html(raw) | Event::InlineHtml(raw) => Event::Text(raw),
+        Event::Start(Tag::Link { link_type, dest_url, title, id }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image { link_type, dest_url, title, id }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        event => event,
+    });
+    let mut out = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut out, events);
+    out
+}
+
+fn safe_url(url: CowStr<'_>) -> CowStr<'_> {
+    let lower = url.trim_start().to_ascii_lowercase();
+    let scheme = lower.split_once(':').map(|(scheme, _)| scheme).filter(|scheme| !scheme.contains('/'));
+    match scheme {
+        None | Some("http" | "https" | "mailto") => url,
+        Some(_) => CowStr::Borrowed(""),
+    }
+}
+
+fn plain_text<'a>(events: impl Iterator<Item = Event<'a>>) -> String {
+    let mut text = String::new();
+    for event in events {
+        match event {
+            Event::Text(part) | Event::Code(part) => text.push_str(&part),
+            Event::SoftBreak | Event::HardBreak | Event::End(_) => text.push(' '),
+            _ => {}
+        }
+    }
+    text
+}
@@ -0,0 +1,129 @@
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::ids::UserId;
+use crate::now_millis;
+
+// Entries kept in memory for GET /v1/admin/audit; every entry is also
+// written to stdout, which is the durable copy
+const MAX_ENTRIES: usize = 10_000;
+// Entries kept per user for GET /v1/me/privacy/access_log
+const MAX_ACCESSES_PER_USER: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    PlaceHold,
+    ReleaseHold,
+    Takedown,
+    LiftTakedown,
+    SetRegion,
+    ReloadConfig,
+    ViewInterests,
+    ViewDelivery,
+    RevealAuthor,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    pub at: u64,
+    pub admin_id: UserId,
+    pub action: AuditAction,
+    pub target: String, // e.g. "post:post_<uuid>", "user:user1", or "config"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub case_ref: Option<String>,
+    pub details: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<UserId>, // whose data was accessed, for their access log
+}
+
+// Append-only record of legal, residency, and configuration actions taken by
+// admins, and of admins reading users' data
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    entries: Mutex<VecDeque<AuditEntry>>,
+    accesses: Mutex<HashMap<UserId, VecDeque<AuditEntry>>>,
+}
+
+impl AuditLog {
+    pub fn record(
+        &self,
+        admin_id: &UserId,
+        action: AuditAction,
+        target: String,
+        case_ref: Option<String>,
+        details: serde_json::Value,
+    ) {
+        self.push(AuditEntry {
+            at: now_millis(),
+            admin_id: admin_id.clone(),
+            action,
+            target,
+            case_ref,
+            details,
+            subject: None,
+        });
+    }
+
+    // An action on one user's data, which that user can also see
+    pub fn record_access(
+        &self,
+        admin_id: &UserId,
+        action: AuditAction,
+        subject: &UserId,
+        target: String,
+        details: serde_json::Value,
+    ) {
+        let entry = AuditEntry {
+            at: now_millis(),
+            admin_id: admin_id.clone(),
+            action,
+            target,
+            case_ref: None,
+            details,
+            subject: Some(subject.clone()),
+        };
+        let mut accesses = self.accesses.lock().expect("audit accesses poisoned");
+        let user_accesses = accesses.entry(subject.clone()).or_default();
+        user_accesses.push_back(entry.clone());
+        if user_accesses.len() > MAX_ACCESSES_PER_USER {
+            user_accesses.pop_front();
+        }
+        drop(accesses);
+        self.push(entry);
+    }
+
+    fn push(&self, entry: AuditEntry) {
+        if let Ok(line) = serde_json::to_string(&entry) {
+            println!("AUDIT {}", line);
+        }
+        let mut entries = self.entries.lock().expect("audit log poisoned");
+        entries.push_back(entry);
+        if entries.len() > MAX_ENTRIES {
+            entries.pop_front();
+        }
+    }
+
+    // Newest first
+    pub fn entries(&self, limit: usize) -> Vec<AuditEntry> {
+        self.entries
+            .lock()
+            .expect("audit log poisoned")
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    // Accesses to one user's data, newest first
+    pub fn accesses(&self, user_id: &UserId, limit: usize) -> Vec<AuditEntry> {
+        self.accesses
+            .lock()
+            .expect("audit accesses poisoned")
+            .get(user_id)
+            .map(|accesses| accesses.iter().rev().take(limit).cloned().collect())
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,93 @@
+use serde::Serialize;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use crate::ids::PostId;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    // Sized for `items` entries at the given false-positive rate
+    pub fn with_capacity(items: usize, false_positive_rate: f64) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-(items.max(1) as f64) * false_positive_rate.ln() / (ln2 * ln2)).ceil() as usize;
+        let hashes = ((bits as f64 / items.max(1) as f64) * ln2).round().max(1.0) as u32;
+        Self {
+            bits: vec![0; bits.div_ceil(64)],
+            hashes,
+        }
+    }
+
+    pub fn insert(&mut self, item: &str) {
+        for index in self.indexes(item) {
+            self.bits[index / 64] |= 1 << (index % 64);
+        }
+    }
+
+    pub fn might_contain(&self, item: &str) -> bool {
+        self.indexes(item)
+            .all(|index| self.bits[index / 64] & (1 << (index % 64)) != 0)
+    }
+
+    // Double hashing: index_i = h1 + i * h2
+    fn indexes(&self, item: &str) -> impl Iterator<Item = usize> + use<> {
+        let len = (self.bits.len() * 64) as u64;
+        let h1 = seeded_hash(item, 0);
+        let h2 = seeded_hash(item, 1) | 1;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+}
+
+fn seeded_hash(item: &str, seed: u64) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    seed.hash(&mut hasher);
+    item.hash(&mut hasher);
+    hasher.finish()
+}
+
+const GENERATION_SIZE: usize = 1000;
+const FALSE_POSITIVE_RATE: f64 = 0.01;
+
+// Post IDs recently delivered to one feed. Bloom filters can't forget, so two
+// generations rotate: once the current one holds a feed's worth of IDs it
+// becomes the previous one and the oldest is dropped. A hit means "maybe
+// delivered", a miss means "definitely not among the last 1000 or more".
+#[derive(Debug, Clone, Serialize)]
+pub struct DeliveredFilter {
+    current: BloomFilter,
+    previous: Option<BloomFilter>,
+    inserted: usize,
+}
+
+impl Default for DeliveredFilter {
+    fn default() -> Self {
+        Self {
+            current: BloomFilter::with_capacity(GENERATION_SIZE, FALSE_POSITIVE_RATE),
+            previous: None,
+            inserted: 0,
+        }
+    }
+}
+
+impl DeliveredFilter {
+    pub fn insert(&mut self, post_id: &PostId) {
+        if self.inserted >= GENERATION_SIZE {
+            let fresh = BloomFilter::with_capacity(GENERATION_SIZE, FALSE_POSITIVE_RATE);
+            self.previous = Some(std::mem::replace(&mut self.current, fresh));
+            self.inserted = 0;
+        }
+        self.current.insert(post_id.as_str());
+        self.inserted += 1;
+    }
+
+    pub fn might_contain(&self, post_id: &PostId) -> bool {
+        self.current.might_contain(post_id.as_str())
+            || self
+                .previous
+                .as_ref()
+                .is_some_and(|previous| previous.might_contain(post_id.as_str()))
+    }
+}
@@ -0,0 +1,135 @@
+use serde::Serialize;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::{error, info, now_millis, warn};
+
+// Attempts per step before startup gives up, and the wait before the first
+// retry; the wait doubles after each attempt
+const MAX_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+pub enum StartupError {
+    // Worth retrying: a mount that isn't ready yet, a port still held by the
+    // previous process
+    Transient(String),
+    // Retrying won't help, e.g. invalid configuration
+    Fatal(String),
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Ok,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StepReport {
+    name: &'static str,
+    status: StepStatus,
+    attempts: u32,
+    duration_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>, // the last failure, even if a retry then succeeded
+}
+
+#[derive(Debug, Serialize)]
+pub struct InitReport {
+    started_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ready_after_ms: Option<f64>,
+    steps: Vec<StepReport>,
+}
+
+// Brings the service up one subsystem at a time, in dependency order:
+// config, storage, caches, queues, then HTTP. Each step is retried with
+// backoff while its failures are transient. A step that can't succeed stops
+// startup with the report so far, rather than leaving a half-started server.
+pub struct Bootstrap {
+    started: Instant,
+    started_at: u64,
+    steps: Mutex<Vec<StepReport>>,
+    ready_after_ms: OnceLock<f64>,
+}
+
+impl Default for Bootstrap {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            started_at: now_millis(),
+            steps: Mutex::new(Vec::new()),
+            ready_after_ms: OnceLock::new(),
+        }
+    }
+}
+
+impl Bootstrap {
+    pub async fn step<T>(&self, name: &'static str, mut attempt: impl FnMut() -> Result<T, StartupError>) -> T {
+        self.step_async(name, || std::future::ready(attempt())).await
+    }
+
+    // A step whose attempts have to wait on the network, e.g. connecting to
+    // the shared job queue
+    pub async fn step_async<T, F>(&self, name: &'static str, mut attempt: impl FnMut() -> F) -> T
+    where
+        F: Future<Output = Result<T, StartupError>>,
+    {
+        let started = Instant::now();
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempts = 0;
+        let mut last_error = None;
+        loop {
+            attempts += 1;
+            let (error, retry) = match attempt().await {
+                Ok(value) => {
+                    self.record(name, StepStatus::Ok, attempts, started, last_error);
+                    info!("Startup: {} ready", name);
+                    return value;
+                }
+                Err(StartupError::Transient(error)) => (error, attempts < MAX_ATTEMPTS),
+                Err(StartupError::Fatal(error)) => (error, false),
+            };
+            if !retry {
+                self.record(name, StepStatus::Failed, attempts, started, Some(error));
+                self.abort();
+            }
+            warn!("Startup: {} failed (attempt {}), retrying in {:?}: {}", name, attempts, backoff, error);
+            last_error = Some(error);
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+
+    // Every step is done and the server is accepting connections
+    pub fn ready(&self) {
+        let elapsed = self.started.elapsed().as_secs_f64() * 1000.0;
+        let _ = self.ready_after_ms.set(elapsed);
+        info!("Startup: ready after {:.0} ms", elapsed);
+    }
+
+    pub fn report(&self) -> InitReport {
+        InitReport {
+            started_at: self.started_at,
+            ready_after_ms: self.ready_after_ms.get().copied(),
+            steps: self.steps.lock().expect("startup report poisoned").clone(),
+        }
+    }
+
+    fn record(&self, name: &'static str, status: StepStatus, attempts: u32, started: Instant, error: Option<String>) {
+        self.steps.lock().expect("startup report poisoned").push(StepReport {
+            name,
+            status,
+            attempts,
+            duration_ms: started.elapsed().as_secs_f64() * 1000.0,
+            error,
+        });
+    }
+
+    fn abort(&self) -> ! {
+        let report = serde_json::to_string_pretty(&self.report()).expect("startup report serializes");
+        error!("Startup failed:\n{}", report);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,30 @@
+use futures::future::BoxFuture;
+use std::time::Duration;
+
+// A job taken from the shared queue. It stays pending until acknowledged;
+// one that isn't is delivered again, to this instance or another, once the
+// redelivery timeout passes.
+pub struct Delivery {
+    pub id: String,
+    pub kind: String,
+    pub payload: serde_json::Value,
+    pub attempts: u32, // deliveries so far, this one included
+}
+
+// A queue shared by every instance, for job kinds whose policy is `shared`.
+// Instances read it as one consumer group, so each job goes to one of them.
+pub trait Broker: Send + Sync + 'static {
+    fn name(&self) -> &'static str;
+
+    fn publish<'a>(&'a self, kind: &'a str, payload: &'a serde_json::Value) -> BoxFuture<'a, Result<(), String>>;
+
+    // Waits up to `wait` for at most `max` jobs. Jobs another consumer took
+    // and never acknowledged come first, once they're past the timeout.
+    fn fetch(&self, max: usize, wait: Duration) -> BoxFuture<'_, Result<Vec<Delivery>, String>>;
+
+    fn ack(&self, delivery: Delivery) -> BoxFuture<'_, Result<(), String>>;
+
+    // Hands a job back to run again after `delay`. Backends that can't delay
+    // a single message leave it pending until the redelivery timeout.
+    fn nack(&self, delivery: Delivery, delay: Duration) -> BoxFuture<'_, Result<(), String>>;
+}
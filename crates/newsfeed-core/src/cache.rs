@@ -0,0 +1,273 @@
+
+
+use crate::account_state::AccountRecord;
+use crate::activity::Activity;
+use crate::ads::Campaign;
+use crate::context::ViewerContext;
+use crate::emoji::CustomEmoji;
+use crate::feed_locks::FeedLocks;
+use crate::graph::{FollowEdge, SocialGraph};
+use crate::i18n::Catalogs;
+use crate::ids::{PostId, TagId, UserId};
+use crate::interests::{Engagement, Interests};
+use crate::legal::Tombstone;
+use crate::login_history::{LoginContext, LoginRecord};
+use crate::markers::Step;
+use crate::media::VideoStatus;
+use crate::notifications::Notification;
+use crate::ranking::NegativeSignal;
+use crate::reach::Channel;
+use crate::receipts::Outcome;
+use crate::rsvp::{RsvpStatus, RsvpSummary};
+use crate::typeahead::Typeahead;
+use crate::{Counters, HydratedPost, NewsFeedItem, Post, PostUnavailable, User, UserPreferences, UsernameError};
+
+// What the services need from the cache in front of storage. The DashMap
+// implementation is in newsfeed-store-memory.
+pub trait Cache: Send + Sync {
+    fn graph(&self) -> &SocialGraph;
+
+    fn typeahead(&self) -> &Typeahead;
+
+    fn feed_locks(&self) -> &FeedLocks;
+
+    // The feed as the user reads it: what fanout pushed, with recent posts
+    // from followed accounts whose posts aren't pushed merged in by time.
+    // Pushed items keep their delivery order.
+    fn home_feed(&self, user_id: &UserId) -> Vec<NewsFeedItem>;
+
+    // Marks an author whose posts are read from their timeline rather than
+    // pushed. Kept even if they drop below the threshold, so the posts that
+    // weren't pushed stay in their followers' feeds.
+    fn mark_pull_author(&self, user_id: &UserId);
+
+    // Returns false if the post was hidden or is already in the feed
+    fn add_to_news_feed(&self, user_id: &UserId, item: NewsFeedItem) -> bool;
+
+    // Merges older items into a feed by timestamp, skipping hidden posts and
+    // ones already there. Returns how many were added.
+    fn backfill_news_feed(&self, user_id: &UserId, items: Vec<NewsFeedItem>) -> usize;
+
+    fn step_done(&self, post_id: &PostId, user_id: &UserId, step: Step) -> bool;
+
+    // Records that the post's fanout did `step` for this follower; false if
+    // an earlier delivery already had
+    fn mark_step(&self, post_id: &PostId, user_id: &UserId, step: Step) -> bool;
+
+    // Starts tracking a post's fanout; a retried fanout keeps the counts so far
+    fn start_receipt(&self, post_id: &PostId, author_id: &UserId, published_at: u64, followers: u32);
+
+    // Returns false if the post's fanout isn't tracked here, e.g. a batch
+    // from another node
+    fn record_receipt(&self, post_id: &PostId, outcome: Outcome, count: u32, at: u64) -> bool;
+
+    // A cached page for `key`, if it was stored at or after `fresh_after`
+    fn get_feed_page(&self, user_id: &UserId, key: &str, fresh_after: u64) -> Option<Vec<HydratedPost>>;
+
+    fn put_feed_page(&self, user_id: &UserId, key: String, posts: Vec<HydratedPost>, fresh_after: u64);
+
+    fn is_hidden(&self, user_id: &UserId, post_id: &PostId) -> bool;
+
+    fn has_blocked(&self, blocker_id: &UserId, user_id: &UserId) -> bool;
+
+    // The same for one post. An anonymous post the viewer can't have is just
+    // missing, since the reason would say something about its author.
+    fn post_restriction(&self, viewer: &ViewerContext, post: &Post) -> Option<PostUnavailable>;
+
+    fn hidden_from(&self, viewer: &ViewerContext, author_id: &UserId) -> bool;
+
+    // A post by ID, or why the viewer can't have it. Held posts are missing.
+    // A deleted post the viewer couldn't have seen is missing too, so its ID
+    // gives nothing away.
+    fn post_for(&self, viewer: &ViewerContext, post_id: &PostId) -> Result<Post, PostUnavailable>;
+
+    fn record_interest(&self, user_id: &UserId, post: &Post, engagement: Engagement, at: u64);
+
+    fn get_interests(&self, user_id: &UserId) -> Interests;
+
+    fn get_negative_signals(&self, user_id: &UserId) -> Vec<NegativeSignal>;
+
+    fn impression_count(&self, user_id: &UserId, post_id: &PostId, day: u64) -> u16;
+
+    fn record_impression(&self, user_id: &UserId, post_id: &PostId, day: u64);
+
+    fn delivered_from(&self, user_id: &UserId, author_id: &UserId, day: u64) -> u16;
+
+    fn record_delivery(&self, user_id: &UserId, author_id: &UserId, day: u64);
+
+    fn list_campaigns(&self) -> Vec<Campaign>;
+
+    // Counts an impression if the campaign can still deliver one
+    fn record_campaign_impression(&self, campaign_id: &str, now: u64) -> Option<Campaign>;
+
+    // What the posts projection builds, dropped before it is rebuilt
+    fn clear_posts(&self);
+
+    // What the feeds projection builds
+    fn clear_feeds(&self);
+
+    fn recent_posts(&self, since: u64) -> Vec<Post>;
+
+    // Post Cache
+    // Posts under legal hold are hidden from every read path
+    fn get_post(&self, post_id: &PostId) -> Option<Post>;
+
+    fn set_post(&self, post: Post);
+
+    // A post read back from storage: indexed like a new one, but its reply
+    // count already includes its replies and there's nothing to save
+    fn restore_post(&self, post: Post);
+
+    // Custom Emoji
+    fn get_emoji(&self, shortcode: &str) -> Option<CustomEmoji>;
+
+    // Threads
+    fn get_thread(&self, head_post_id: &PostId) -> Option<Vec<PostId>>;
+
+    fn set_thread(&self, head_post_id: &PostId, post_ids: Vec<PostId>);
+
+    fn add_user_post(&self, user_id: &UserId, post_id: &PostId);
+
+    // Co-Authors
+    fn request_co_authors(&self, post: &Post);
+
+    // Posts the user was invited to co-author and hasn't answered, newest first
+    fn pending_co_authorships(&self, user_id: &UserId) -> Vec<PostId>;
+
+    // Settles an invitation, returning the updated post. An accepted post
+    // joins the co-author's timeline where its timestamp puts it; a declined
+    // co-author is dropped from the post.
+    fn answer_co_author(&self, post_id: &PostId, user_id: &UserId, accepted: bool) -> Option<Post>;
+
+    // Hashtags
+    // Adds the post under each of its hashtags it wasn't already under
+    fn index_hashtags(&self, post: &Post, indexed: &[TagId]);
+
+    // An edit moves the post to the hashtags its new text uses
+    fn reindex_hashtags(&self, previous: &Post, post: &Post);
+
+    fn hashtag_post_ids(&self, tag: &TagId) -> Vec<PostId>;
+
+    // Articles
+    fn set_article(&self, post_id: &PostId, markdown: String);
+
+    // RSVPs
+    fn set_rsvp(&self, post_id: &PostId, user_id: &UserId, status: Option<RsvpStatus>);
+
+    fn rsvp_summary(&self, post_id: &PostId, viewer_id: Option<&UserId>) -> RsvpSummary;
+
+    fn get_user_post_ids(&self, user_id: &UserId) -> Vec<PostId>;
+
+    // User Cache
+    fn get_user(&self, user_id: &UserId) -> Option<User>;
+
+    // Claims the username and stores a brand new user
+    fn create_user(&self, user: User) -> Result<(), UsernameError>;
+
+    // Account state. Records are written through and read back on a miss;
+    // an account with no record anywhere predates signup and is active.
+    fn account_record(&self, user_id: &UserId) -> AccountRecord;
+
+    fn set_account_record(&self, user_id: &UserId, record: AccountRecord);
+
+    fn find_user_id_by_username(&self, username: &str) -> Option<UserId>;
+
+    // Claims the new username atomically and leaves a redirect behind
+    fn rename_user(
+        &self,
+        user_id: &UserId,
+        new_username: &str,
+        redirect_until: u64,
+    ) -> Result<User, UsernameError>;
+
+    // User Preferences
+    fn get_preferences(&self, user_id: &UserId) -> UserPreferences;
+
+    fn in_quiet_hours(&self, user_id: &UserId, now: u64) -> bool;
+
+    // Language for text sent outside a request, like emails and pushes
+    fn user_locale(&self, user_id: &UserId, catalogs: &Catalogs) -> String;
+
+    // Social Graph
+    fn is_following(&self, follower_id: &UserId, user_id: &UserId) -> bool;
+
+    // Followers to fan out to. Other instances sharing storage may have
+    // added some, so a shared backend answers when it can.
+    fn followers(&self, user_id: &UserId) -> Vec<(UserId, FollowEdge)>;
+
+    fn store_follow(&self, follower_id: &UserId, followed_id: &UserId);
+
+    // Login history
+    fn record_login(&self, user_id: &UserId, context: LoginContext, two_factor: bool) -> LoginRecord;
+
+    // Notifications
+    fn add_notification(&self, user_id: &UserId, notification: Notification);
+
+    // Leaves the inbox alone if it still has the same notification, so
+    // liking a post again, or following again, notifies once
+    fn add_notification_once(&self, user_id: &UserId, notification: Notification);
+
+    // The tombstone to show instead of the post, if a takedown covers the viewer
+    fn tombstone_for(&self, viewer_id: &UserId, post_id: &PostId) -> Option<Tombstone>;
+
+    // For a viewer known only by the country the edge reported, if that
+    fn tombstone_in(&self, country: Option<&str>, post_id: &PostId) -> Option<Tombstone>;
+
+    fn viewer(&self, user_id: &UserId) -> ViewerContext;
+
+    // Activity
+    fn record_activity(&self, user_id: &UserId, activity: Activity);
+
+    fn can_reply(&self, user_id: &UserId, post: &Post) -> bool;
+
+    // Actions
+    // False if the user already liked the post, which changes nothing
+    fn like_post(&self, user_id: &UserId, post_id: &PostId) -> bool;
+
+    // False if the user hadn't liked the post
+    fn unlike_post(&self, user_id: &UserId, post_id: &PostId) -> bool;
+
+    fn add_reply(&self, post_id: &PostId, reply_id: &PostId);
+
+    fn record_reach(&self, post_id: &PostId, channel: Channel);
+
+    // Adds a live like or reply change to the stored post. Replays skip it:
+    // storage already counted them, and other instances may have since.
+    fn persist_counts(&self, post_id: &PostId, likes: i64, replies: i64);
+
+    // A live like or unlike; true if it changed anything. A backend that
+    // keeps likers answers first, since the user may have liked the post
+    // through another instance, and only then does the local count move.
+    // Otherwise the cache decides and storage gets the change.
+    fn apply_like(&self, user_id: &UserId, post_id: &PostId, liked: bool) -> bool;
+
+    // Removes the post and what is indexed by it. Feed items that still point
+    // at it are skipped when hydrating, like any other missing post. Its ID
+    // and author are kept so lookups can tell it was deleted.
+    fn delete_post(&self, post_id: &PostId) -> Option<Post>;
+
+    // Swaps in an edited post, returning the one it replaced. Counters are
+    // kept apart from the post, so they carry over.
+    fn replace_post(&self, post: Post) -> Option<Post>;
+
+    fn get_replies(&self, post_id: &PostId) -> Vec<PostId>;
+
+    // The newest `count` direct replies, newest first, without copying the rest
+    fn latest_replies(&self, post_id: &PostId, count: usize) -> Vec<PostId>;
+
+    fn has_liked(&self, user_id: &UserId, post_id: &PostId) -> bool;
+
+    // Up to `max` users who liked the post, in no particular order
+    fn post_likers(&self, post_id: &PostId, max: usize) -> Vec<UserId>;
+
+    // Up to `max` posts the user liked, in no particular order. A user whose
+    // actions were evicted has none until they like something again.
+    fn liked_posts(&self, user_id: &UserId, max: usize) -> Vec<PostId>;
+
+    fn get_counters(&self, post_id: &PostId) -> Counters;
+
+    // Video processing state
+    fn get_video(&self, post_id: &PostId) -> Option<VideoStatus>;
+
+    fn set_video(&self, post_id: &PostId, status: VideoStatus);
+}
@@ -0,0 +1,114 @@
+use futures::future::BoxFuture;
+use sha2::{Digest, Sha256};
+
+use crate::config::Config;
+use crate::ids::UserId;
+
+// The wire format is protobuf, as described in proto/feed_delivery.proto.
+// The messages are written out here rather than generated, so building
+// doesn't need protoc; the gRPC client and server are in newsfeed-http.
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DeliverFeedItemsRequest {
+    #[prost(string, tag = "1")]
+    pub batch_id: String,
+    #[prost(string, tag = "2")]
+    pub origin_node: String,
+    #[prost(string, tag = "3")]
+    pub author_id: String,
+    #[prost(string, tag = "4")]
+    pub post_id: String,
+    #[prost(uint64, tag = "5")]
+    pub published_at: u64,
+    // The post as JSON, so the owning node can show it without a shared store
+    #[prost(bytes = "vec", tag = "6")]
+    pub post_json: Vec<u8>,
+    #[prost(message, repeated, tag = "7")]
+    pub targets: Vec<FeedTarget>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FeedTarget {
+    #[prost(string, tag = "1")]
+    pub user_id: String,
+    #[prost(bool, tag = "2")]
+    pub deliver: bool, // add the post to this user's feed
+    #[prost(bool, tag = "3")]
+    pub notify: bool, // bell notification
+    #[prost(uint32, optional, tag = "4")]
+    pub daily_limit: Option<u32>,
+}
+
+// The acknowledgement. A batch is acknowledged once every target it owns has
+// been handled; misrouted targets belong to another node by the receiver's
+// partition map, and repeated ones were handled by an earlier copy of the
+// batch and aren't in the other counts.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DeliverFeedItemsResponse {
+    #[prost(string, tag = "1")]
+    pub batch_id: String,
+    #[prost(uint32, tag = "2")]
+    pub delivered: u32,
+    #[prost(uint32, tag = "3")]
+    pub skipped: u32,
+    #[prost(uint32, tag = "4")]
+    pub throttled: u32,
+    #[prost(string, repeated, tag = "5")]
+    pub misrouted: Vec<String>,
+    #[prost(uint32, tag = "6")]
+    pub repeated: u32,
+}
+
+// Which node owns each user's feed. Feeds are spread by rendezvous hashing,
+// so adding or removing a node only moves the feeds that node gains or loses.
+// With no nodes configured every feed is local.
+pub struct FeedPartitions {
+    local: String,
+    nodes: Vec<String>,
+}
+
+impl FeedPartitions {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            local: config.node_id.clone(),
+            nodes: config.feed_nodes.iter().map(|(node, _)| node.clone()).collect(),
+        }
+    }
+
+    pub fn owner(&self, user_id: &UserId) -> &str {
+        self.nodes
+            .iter()
+            .max_by_key(|node| {
+                let digest = Sha256::new()
+                    .chain_update(node.as_bytes())
+                    .chain_update(b":")
+                    .chain_update(user_id.as_str().as_bytes())
+                    .finalize();
+                u64::from_be_bytes(digest[..8].try_into().expect("digest has 8 bytes"))
+            })
+            .map(String::as_str)
+            .unwrap_or(&self.local)
+    }
+
+    pub fn is_local(&self, user_id: &UserId) -> bool {
+        self.owner(user_id) == self.local
+    }
+
+    pub fn local(&self) -> &str {
+        &self.local
+    }
+}
+
+// Writes a batch into the feeds this node owns
+pub trait LocalDelivery: Send + Sync + 'static {
+    fn deliver_batch(&self, request: DeliverFeedItemsRequest) -> BoxFuture<'_, Result<DeliverFeedItemsResponse, String>>;
+}
+
+// Sends a batch to the node that owns its feeds
+pub trait RemoteDelivery: Send + Sync + 'static {
+    fn deliver<'a>(
+        &'a self,
+        node: &'a str,
+        request: DeliverFeedItemsRequest,
+    ) -> BoxFuture<'a, Result<DeliverFeedItemsResponse, String>>;
+}
@@ -0,0 +1,706 @@
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use crate::engagement_log;
+use crate::i18n;
+use crate::ids::UserId;
+use crate::jobs::Cron;
+use crate::logging::{self, Level};
+
+// Creative Commons licenses plus all rights reserved
+const DEFAULT_LICENSES: [&str; 8] = [
+    "all-rights-reserved",
+    "cc0",
+    "cc-by",
+    "cc-by-sa",
+    "cc-by-nd",
+    "cc-by-nc",
+    "cc-by-nc-sa",
+    "cc-by-nc-nd",
+];
+
+// Runtime configuration, read from NEWS_FEED_* settings with defaults
+// suitable for running locally. Read once at startup; the settings that can
+// change while the server runs are in Tunables.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub listen: Vec<String>,
+    pub admin_listen: Option<String>,
+    pub node_id: String,
+    pub feed_nodes: Vec<(String, String)>,
+    pub rpc_listen: Option<SocketAddr>,
+    pub rpc_secret: Option<String>,
+    pub rpc_connections: usize,
+    pub media_dir: PathBuf,
+    pub ffmpeg_path: String,
+    pub transcode_workers: usize,
+    pub video_source_max_bytes: u64,
+    pub media_signing_key: String,
+    pub token_signing_key: String,
+    pub token_ttl_secs: u64,
+    pub session_ttl_secs: u64,
+    pub demo_tokens: bool,
+    pub require_image_alt_text: bool,
+    pub max_post_length: usize,
+    pub url_weight: usize,
+    pub licenses: Vec<String>, // lowercased
+    pub admin_user_ids: Vec<UserId>,
+    pub require_admin_two_factor: bool,
+    pub oauth_token_ttl_secs: u64,
+    pub username_change_cooldown_secs: u64,
+    pub username_redirect_grace_secs: u64,
+    pub email_verification_ttl_secs: u64,
+    pub sponsored_slots: Vec<usize>,
+    pub access_log: String,
+    pub access_log_max_bytes: u64,
+    pub engagement_log: Option<PathBuf>,
+    pub engagement_log_max_bytes: u64,
+    pub engagement_log_roll_secs: u64,
+    pub trusted_proxies: usize,
+    pub reveal_post_restrictions: bool,
+    pub feed_stream_max_per_user: usize,
+    pub cache_budgets: Vec<(String, u64)>,
+    pub memory_check_secs: u64,
+    pub max_json_body_bytes: u64,
+    pub api_v1_sunset: Option<u64>,
+    pub counter_compaction_secs: u64,
+    pub counter_cold_secs: u64,
+    pub hot_cache_max: usize,
+    pub eviction_secs: u64,
+    pub delivery_marker_secs: u64,
+    pub pull_fanout_followers: usize,
+    pub fanout_queue_limit: usize,
+    pub follow_backfill_posts: usize,
+    pub saved_search_interval_secs: u64,
+    pub digest_hour: u16,
+    pub event_reminder_secs: u64,
+    pub default_locale: String,
+    pub locales_dir: Option<PathBuf>,
+    pub follow_burst_limit: usize,
+    pub follow_burst_secs: u64,
+    pub follow_churn_limit: usize,
+    pub follow_churn_secs: u64,
+    pub retention_post_days: u64,
+    pub retention_overrides: Vec<(UserId, u64)>,
+    pub retention_dry_run: bool,
+    pub retention_interval_secs: u64,
+    pub retention_cron: Option<String>,
+    pub federation_key_id: String,
+    pub federation_signing_key: String,
+    pub federation_peers: Vec<(String, String)>,
+    pub signature_max_age_secs: u64,
+    pub region: String,
+    pub region_backends: Vec<(String, PathBuf)>,
+    pub cross_region_reads: bool,
+    pub sandbox_reset_secs: u64,
+    pub features: Vec<String>,
+    pub anonymous_posting: Vec<String>, // tenants whose users may post anonymously
+    pub jobs_file: Option<PathBuf>,
+    pub storage: Option<String>,
+    pub queue_url: Option<String>,
+    pub queue_stream: String,
+    pub queue_group: String,
+    pub queue_redeliver_secs: u64,
+    pub embeddings: Option<String>,
+    pub embedding_dimensions: usize,
+}
+
+impl Config {
+    pub fn from_source(source: &Source) -> Self {
+        Self {
+            // e.g. "0.0.0.0:3030,unix:/run/news-feed.sock"
+            listen: match source.var("NEWS_FEED_LISTEN") {
+                Ok(_) => source.list("NEWS_FEED_LISTEN"),
+                Err(_) => vec!["127.0.0.1:3030".to_string()],
+            },
+            // Moves the admin API and metrics to their own listener, e.g. "127.0.0.1:9090"
+            admin_listen: source
+                .var("NEWS_FEED_ADMIN_LISTEN")
+                .ok()
+                .filter(|value| !value.trim().is_empty()),
+            // This node's name in NEWS_FEED_FEED_NODES
+            node_id: source.var("NEWS_FEED_NODE_ID").unwrap_or_else(|_| "local".to_string()),
+            // Nodes that feeds are partitioned across, this one included, e.g.
+            // "a=http://10.0.0.1:50051,b=http://10.0.0.2:50051"; empty keeps every feed local
+            feed_nodes: source
+                .list("NEWS_FEED_FEED_NODES")
+                .iter()
+                .filter_map(|item| {
+                    let (node, url) = item.split_once('=')?;
+                    Some((node.trim().to_string(), url.trim().to_string()))
+                })
+                .collect(),
+            rpc_listen: source.optional("NEWS_FEED_RPC_LISTEN"),
+            rpc_secret: source
+                .var("NEWS_FEED_RPC_SECRET")
+                .ok()
+                .filter(|value| !value.is_empty()),
+            rpc_connections: source.parse("NEWS_FEED_RPC_CONNECTIONS", 2),
+            media_dir: source.var("NEWS_FEED_MEDIA_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| PathBuf::from("media")),
+            ffmpeg_path: source.var("NEWS_FEED_FFMPEG").unwrap_or_else(|_| "ffmpeg".to_string()),
+            transcode_workers: source.parse("NEWS_FEED_TRANSCODE_WORKERS", 2),
+            video_source_max_bytes: source.bytes("NEWS_FEED_VIDEO_MAX_BYTES", 1 << 30),
+            // Without a configured key, media URLs stop validating after a restart
+            media_signing_key: source.var("NEWS_FEED_MEDIA_KEY")
+                .unwrap_or_else(|_| uuid::Uuid::new_v4().to_string()),
+            token_signing_key: source.var("NEWS_FEED_TOKEN_KEY")
+                .unwrap_or_else(|_| uuid::Uuid::new_v4().to_string()),
+            token_ttl_secs: source.parse("NEWS_FEED_TOKEN_SECS", 7 * 86400),
+            // How long POST /v1/login can keep renewing a login's tokens
+            session_ttl_secs: source.parse("NEWS_FEED_SESSION_SECS", 30 * 86400),
+            // Unsigned `user_<id>` tokens, for local demos only
+            demo_tokens: source.parse("NEWS_FEED_DEMO_TOKENS", false),
+            require_image_alt_text: source.parse("NEWS_FEED_REQUIRE_ALT_TEXT", false),
+            max_post_length: source.parse("NEWS_FEED_MAX_POST_LENGTH", 500),
+            url_weight: source.parse("NEWS_FEED_URL_WEIGHT", 23),
+            // Licenses a post may carry; empty turns licenses off
+            licenses: match source.var("NEWS_FEED_LICENSES") {
+                Ok(_) => source.list("NEWS_FEED_LICENSES")
+                    .iter()
+                    .map(|license| license.to_lowercase())
+                    .collect(),
+                Err(_) => DEFAULT_LICENSES.iter().map(|license| license.to_string()).collect(),
+            },
+            admin_user_ids: source.list("NEWS_FEED_ADMINS").into_iter().map(UserId::new).collect(),
+            require_admin_two_factor: source.parse("NEWS_FEED_REQUIRE_ADMIN_2FA", false),
+            oauth_token_ttl_secs: source.parse("NEWS_FEED_OAUTH_TOKEN_SECS", 30 * 86400),
+            username_change_cooldown_secs: source.parse("NEWS_FEED_USERNAME_COOLDOWN_SECS", 7 * 86400),
+            username_redirect_grace_secs: source.parse("NEWS_FEED_USERNAME_REDIRECT_SECS", 30 * 86400),
+            email_verification_ttl_secs: source.parse("NEWS_FEED_EMAIL_VERIFICATION_SECS", 86400),
+            sponsored_slots: match source.var("NEWS_FEED_SPONSORED_SLOTS") {
+                Ok(_) => source.list("NEWS_FEED_SPONSORED_SLOTS")
+                    .iter()
+                    .filter_map(|slot| {
+                        let parsed = slot.parse().ok();
+                        if parsed.is_none() {
+                            source.reject("NEWS_FEED_SPONSORED_SLOTS", slot);
+                        }
+                        parsed
+                    })
+                    .collect(),
+                Err(_) => vec![3, 15],
+            },
+            // "stdout", "off", or a file path
+            access_log: source.var("NEWS_FEED_ACCESS_LOG").unwrap_or_else(|_| "stdout".to_string()),
+            access_log_max_bytes: source.parse("NEWS_FEED_ACCESS_LOG_MAX_BYTES", 10 * 1024 * 1024),
+            // A directory; unset leaves the engagement log off
+            engagement_log: source.var("NEWS_FEED_ENGAGEMENT_LOG")
+                .ok()
+                .filter(|value| !value.trim().is_empty())
+                .map(PathBuf::from),
+            engagement_log_max_bytes: source.parse("NEWS_FEED_ENGAGEMENT_LOG_MAX_BYTES", 64 * 1024 * 1024),
+            engagement_log_roll_secs: source.parse("NEWS_FEED_ENGAGEMENT_LOG_ROLL_SECS", 3600),
+            trusted_proxies: source.parse("NEWS_FEED_TRUSTED_PROXIES", 0),
+            // Off, posts hidden by a block or from logged-out visitors are
+            // plain 404s, like posts that never existed
+            reveal_post_restrictions: source.parse("NEWS_FEED_REVEAL_POST_RESTRICTIONS", false),
+            feed_stream_max_per_user: source.parse("NEWS_FEED_STREAMS_PER_USER", 5),
+            // e.g. "posts=64M,news_feeds=256M"
+            cache_budgets: source.list("NEWS_FEED_CACHE_BUDGETS")
+                .iter()
+                .filter_map(|item| {
+                    let (cache, budget) = item.split_once('=')?;
+                    let parsed = parse_bytes(budget.trim());
+                    if parsed.is_none() {
+                        source.reject("NEWS_FEED_CACHE_BUDGETS", item);
+                    }
+                    Some((cache.trim().to_string(), parsed?))
+                })
+                .collect(),
+            memory_check_secs: source.parse("NEWS_FEED_MEMORY_CHECK_SECS", 60),
+            max_json_body_bytes: source.bytes("NEWS_FEED_MAX_JSON_BODY", 64 * 1024),
+            // Unix seconds after which deprecated v1 routes may be removed
+            api_v1_sunset: source.optional("NEWS_FEED_V1_SUNSET"),
+            counter_compaction_secs: source.parse("NEWS_FEED_COUNTER_COMPACTION_SECS", 300),
+            counter_cold_secs: source.parse("NEWS_FEED_COUNTER_COLD_SECS", 86400),
+            hot_cache_max: source.parse("NEWS_FEED_HOT_CACHE_MAX", 10_000),
+            eviction_secs: source.parse("NEWS_FEED_EVICTION_SECS", 60),
+            // How long a fanout remembers which followers it reached; should
+            // outlast job retries and queue redelivery
+            delivery_marker_secs: source.parse("NEWS_FEED_DELIVERY_MARKER_SECS", 86400),
+            // Authors with this many followers are read into feeds instead of
+            // fanned out; 0 always fans out
+            pull_fanout_followers: source.parse("NEWS_FEED_PULL_FANOUT_FOLLOWERS", 10_000),
+            // Fanout jobs waiting on this node before new posts are turned away
+            fanout_queue_limit: source.parse("NEWS_FEED_FANOUT_QUEUE_LIMIT", 10_000),
+            // Recent posts copied into a feed when its owner follows someone; 0 none
+            follow_backfill_posts: source.parse("NEWS_FEED_FOLLOW_BACKFILL_POSTS", 20),
+            saved_search_interval_secs: source.parse("NEWS_FEED_SAVED_SEARCH_INTERVAL_SECS", 300),
+            digest_hour: source.parse("NEWS_FEED_DIGEST_HOUR", 8),
+            // How long before an event its attendees are reminded; 0 never
+            event_reminder_secs: source.parse("NEWS_FEED_EVENT_REMINDER_SECS", 3600),
+            // Language of server text when neither the request nor the user names one
+            default_locale: source.parse("NEWS_FEED_DEFAULT_LOCALE", "en".to_string()),
+            // Extra or overriding catalogs, one <tag>.json per language
+            locales_dir: source.var("NEWS_FEED_LOCALES_DIR")
+                .ok()
+                .filter(|value| !value.trim().is_empty())
+                .map(PathBuf::from),
+            follow_burst_limit: source.parse("NEWS_FEED_FOLLOW_BURST_LIMIT", 50),
+            follow_burst_secs: source.parse("NEWS_FEED_FOLLOW_BURST_SECS", 600),
+            follow_churn_limit: source.parse("NEWS_FEED_FOLLOW_CHURN_LIMIT", 10),
+            follow_churn_secs: source.parse("NEWS_FEED_FOLLOW_CHURN_SECS", 86400),
+            // 0 keeps posts forever
+            retention_post_days: source.parse("NEWS_FEED_RETENTION_POST_DAYS", 0),
+            // e.g. "user1=0,user7=30": per-account days, 0 for forever
+            retention_overrides: source.pairs("NEWS_FEED_RETENTION_OVERRIDES")
+                .into_iter()
+                .map(|(user_id, days)| (UserId::new(user_id), days))
+                .collect(),
+            retention_dry_run: source.parse("NEWS_FEED_RETENTION_DRY_RUN", false),
+            retention_interval_secs: source.parse("NEWS_FEED_RETENTION_INTERVAL_SECS", 3600),
+            // e.g. "0 3 * * *" for 03:00 UTC daily; replaces the interval when set
+            retention_cron: source.var("NEWS_FEED_RETENTION_CRON")
+                .ok()
+                .filter(|value| !value.trim().is_empty()),
+            federation_key_id: source.var("NEWS_FEED_FEDERATION_KEY_ID")
+                .unwrap_or_else(|_| "news-feed".to_string()),
+            // Without a configured key, peers have to be given the new public key after a restart
+            federation_signing_key: source.var("NEWS_FEED_FEDERATION_KEY")
+                .unwrap_or_else(|_| uuid::Uuid::new_v4().to_string()),
+            // e.g. "peer-a=ed25519:<hex public key>,hooks=hmac-sha256:<secret>"
+            federation_peers: source.list("NEWS_FEED_FEDERATION_PEERS")
+                .iter()
+                .filter_map(|item| {
+                    let (key_id, key) = item.split_once('=')?;
+                    Some((key_id.trim().to_string(), key.trim().to_string()))
+                })
+                .collect(),
+            signature_max_age_secs: source.parse("NEWS_FEED_SIGNATURE_MAX_AGE_SECS", 300),
+            // The region this node serves, and the one accounts without a region belong to
+            region: source.var("NEWS_FEED_REGION").unwrap_or_else(|_| "local".to_string()),
+            // e.g. "eu=/mnt/eu-media,us=/mnt/us-media"
+            region_backends: source.list("NEWS_FEED_REGION_BACKENDS")
+                .iter()
+                .filter_map(|item| {
+                    let (region, root) = item.split_once('=')?;
+                    Some((region.trim().to_string(), PathBuf::from(root.trim())))
+                })
+                .collect(),
+            cross_region_reads: source.parse("NEWS_FEED_CROSS_REGION_READS", false),
+            // How often the sandbox tenant is wiped and reseeded
+            sandbox_reset_secs: source.parse("NEWS_FEED_SANDBOX_RESET_SECS", 3600),
+            // Feature flags switched on, e.g. "new_composer,video_replies"
+            features: source.list("NEWS_FEED_FEATURES"),
+            anonymous_posting: source.list("NEWS_FEED_ANONYMOUS_POSTING"),
+            // Where queued background jobs are kept across restarts; unset keeps them in memory
+            jobs_file: source.var("NEWS_FEED_JOBS_FILE")
+                .ok()
+                .filter(|value| !value.trim().is_empty())
+                .map(PathBuf::from),
+            // Where posts, users, feeds, and follows are kept across restarts,
+            // e.g. "sled:/var/lib/news-feed" or "redis://10.0.0.5:6379/1";
+            // unset keeps them in memory
+            storage: source.var("NEWS_FEED_STORAGE")
+                .ok()
+                .filter(|value| !value.trim().is_empty()),
+            // A shared queue for fanout jobs, e.g. "redis://10.0.0.5:6379" or
+            // "nats://10.0.0.5:4222"; unset runs every job on the instance that queued it
+            queue_url: source.var("NEWS_FEED_QUEUE_URL")
+                .ok()
+                .filter(|value| !value.trim().is_empty()),
+            queue_stream: source.var("NEWS_FEED_QUEUE_STREAM").unwrap_or_else(|_| "news-feed-jobs".to_string()),
+            queue_group: source.var("NEWS_FEED_QUEUE_GROUP").unwrap_or_else(|_| "news-feed".to_string()),
+            // How long a taken job can go unacknowledged before it's delivered again
+            queue_redeliver_secs: source.parse("NEWS_FEED_QUEUE_REDELIVER_SECS", 30),
+            // "local" for the built-in model, or an embedding service, e.g.
+            // "http://10.0.0.7:8080/embed"; unset turns vector similarity off
+            embeddings: source.var("NEWS_FEED_EMBEDDINGS")
+                .ok()
+                .filter(|value| !value.trim().is_empty()),
+            embedding_dimensions: source.parse("NEWS_FEED_EMBEDDING_DIMENSIONS", 256),
+        }
+    }
+
+    // Settings that parse but can't work; startup stops on these
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(expression) = &self.retention_cron {
+            Cron::parse(expression).map_err(|e| format!("NEWS_FEED_RETENTION_CRON: {}", e))?;
+        }
+        if self.region.trim().is_empty() {
+            return Err("NEWS_FEED_REGION is empty".to_string());
+        }
+        if self.token_ttl_secs == 0 || self.session_ttl_secs == 0 {
+            return Err("NEWS_FEED_TOKEN_SECS and NEWS_FEED_SESSION_SECS must be above 0".to_string());
+        }
+        if self.engagement_log_max_bytes == 0 || self.engagement_log_roll_secs == 0 {
+            return Err("NEWS_FEED_ENGAGEMENT_LOG_MAX_BYTES and NEWS_FEED_ENGAGEMENT_LOG_ROLL_SECS must be above 0".to_string());
+        }
+        if self.fanout_queue_limit == 0 {
+            return Err("NEWS_FEED_FANOUT_QUEUE_LIMIT must be above 0".to_string());
+        }
+        if self.digest_hour > 23 {
+            return Err("NEWS_FEED_DIGEST_HOUR must be within 0-23".to_string());
+        }
+        i18n::Catalogs::load(self)?;
+        if self.feed_stream_max_per_user == 0 {
+            return Err("NEWS_FEED_STREAMS_PER_USER must be above 0".to_string());
+        }
+        if let Some(tenant) = self
+            .anonymous_posting
+            .iter()
+            .find(|tenant| !["production", "sandbox"].contains(&tenant.as_str()))
+        {
+            return Err(format!("NEWS_FEED_ANONYMOUS_POSTING: unknown tenant {}, expected production or sandbox", tenant));
+        }
+        if self.eviction_secs == 0 {
+            return Err("NEWS_FEED_EVICTION_SECS must be above 0".to_string());
+        }
+        if self.embeddings.is_some() && self.embedding_dimensions == 0 {
+            return Err("NEWS_FEED_EMBEDDING_DIMENSIONS must be above 0".to_string());
+        }
+        if !self.feed_nodes.is_empty() {
+            if !self.feed_nodes.iter().any(|(node, _)| *node == self.node_id) {
+                return Err(format!("NEWS_FEED_FEED_NODES doesn't list this node, {}", self.node_id));
+            }
+            if self.rpc_listen.is_none() {
+                return Err("NEWS_FEED_RPC_LISTEN is needed when feeds are partitioned".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    // The sandbox tenant keeps its files apart from production's and never
+    // writes to another region's backend
+    pub fn for_sandbox(&self) -> Self {
+        Self {
+            media_dir: env::temp_dir().join("news-feed-sandbox"),
+            region_backends: Vec::new(),
+            admin_user_ids: Vec::new(),
+            jobs_file: None,
+            storage: None,
+            feed_nodes: Vec::new(),
+            rpc_listen: None,
+            queue_url: None,
+            engagement_log: None,
+            ..self.clone()
+        }
+    }
+}
+
+// Settings that can change while the server runs: request and rate limits,
+// ranking and mixing weights, cache TTLs, access and engagement log
+// sampling, and the log level
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Tunables {
+    pub request_timeout_ms: u64,
+    pub route_timeouts_ms: Vec<(String, u64)>,
+    pub batch_max_requests: usize,
+    pub telemetry_per_minute: u32,
+    pub telemetry_burst: u32,
+    pub public_per_minute: u32,
+    pub public_burst: u32,
+    pub signal_half_life_secs: u64,
+    pub ranking_half_life_secs: u64,
+    pub injected_daily_cap: u16,
+    pub injection_interval: usize,
+    pub page_cache_ttl_ms: u64,
+    pub search_half_life_secs: u64,
+    pub search_engagement_weight: f64,
+    pub related_cache_ttl_secs: u64,
+    pub media_url_ttl_secs: u64,
+    pub post_ttl_secs: u64,
+    pub user_ttl_secs: u64,
+    pub actions_ttl_secs: u64,
+    pub hot_cache_ttl_secs: u64,
+    pub access_log_sampling: Vec<(String, f64)>,
+    pub engagement_user_rate: f64,
+    pub engagement_sampling: Vec<(String, f64)>,
+    pub log_level: Level,
+}
+
+impl Tunables {
+    pub fn from_source(source: &Source) -> Self {
+        Self {
+            media_url_ttl_secs: source.parse("NEWS_FEED_MEDIA_URL_TTL_SECS", 300),
+            signal_half_life_secs: source.parse("NEWS_FEED_SIGNAL_HALF_LIFE_SECS", 7 * 86400),
+            ranking_half_life_secs: source.parse("NEWS_FEED_RANKING_HALF_LIFE_SECS", 6 * 3600),
+            injected_daily_cap: source.parse("NEWS_FEED_INJECTED_DAILY_CAP", 2),
+            injection_interval: source.parse("NEWS_FEED_INJECTION_INTERVAL", 5),
+            search_half_life_secs: source.parse("NEWS_FEED_SEARCH_HALF_LIFE_SECS", 86400),
+            search_engagement_weight: source.parse("NEWS_FEED_SEARCH_ENGAGEMENT_WEIGHT", 0.3),
+            related_cache_ttl_secs: source.parse("NEWS_FEED_RELATED_CACHE_TTL_SECS", 600),
+            // Idle time before a cache entry is evicted; 0 keeps it
+            post_ttl_secs: source.parse("NEWS_FEED_POST_TTL_SECS", 0),
+            user_ttl_secs: source.parse("NEWS_FEED_USER_TTL_SECS", 0),
+            actions_ttl_secs: source.parse("NEWS_FEED_ACTIONS_TTL_SECS", 0),
+            hot_cache_ttl_secs: source.parse("NEWS_FEED_HOT_CACHE_TTL_SECS", 0),
+            // e.g. "GET /v1/me/feed=0.1,GET /media/{id}/*=0.01"
+            access_log_sampling: source.pairs("NEWS_FEED_ACCESS_LOG_SAMPLE"),
+            engagement_user_rate: source.parse("NEWS_FEED_ENGAGEMENT_USER_RATE", 1.0),
+            // e.g. "impression=0.1"
+            engagement_sampling: source.pairs("NEWS_FEED_ENGAGEMENT_SAMPLE"),
+            request_timeout_ms: source.parse("NEWS_FEED_REQUEST_TIMEOUT_MS", 10_000),
+            // e.g. "GET /v1/me/feed=2000"; CPU profiles and long polls hold
+            // requests open on purpose
+            route_timeouts_ms: match source.var("NEWS_FEED_ROUTE_TIMEOUTS_MS") {
+                Ok(_) => source.pairs("NEWS_FEED_ROUTE_TIMEOUTS_MS"),
+                Err(_) => vec![
+                    ("GET /v1/admin/debug/profile".to_string(), 65_000),
+                    ("GET /v1/me/feed/poll".to_string(), 35_000),
+                ],
+            },
+            batch_max_requests: source.parse("NEWS_FEED_BATCH_MAX_REQUESTS", 20),
+            telemetry_per_minute: source.parse("NEWS_FEED_TELEMETRY_PER_MINUTE", 12),
+            telemetry_burst: source.parse("NEWS_FEED_TELEMETRY_BURST", 5),
+            // Logged-out requests per client IP
+            public_per_minute: source.parse("NEWS_FEED_PUBLIC_PER_MINUTE", 30),
+            public_burst: source.parse("NEWS_FEED_PUBLIC_BURST", 10),
+            page_cache_ttl_ms: source.parse("NEWS_FEED_PAGE_CACHE_TTL_MS", 5000),
+            log_level: source.parse("NEWS_FEED_LOG_LEVEL", Level::Info),
+        }
+    }
+
+    // `storage` says whether NEWS_FEED_STORAGE is set
+    pub fn validate(&self, storage: bool) -> Result<(), String> {
+        if self.request_timeout_ms == 0 || self.route_timeouts_ms.iter().any(|(_, timeout)| *timeout == 0) {
+            return Err("request timeouts must be above 0".to_string());
+        }
+        if self.batch_max_requests == 0 {
+            return Err("NEWS_FEED_BATCH_MAX_REQUESTS must be above 0".to_string());
+        }
+        if self.telemetry_per_minute == 0 || self.telemetry_burst == 0 {
+            return Err("NEWS_FEED_TELEMETRY_PER_MINUTE and NEWS_FEED_TELEMETRY_BURST must be above 0".to_string());
+        }
+        if self.public_per_minute == 0 || self.public_burst == 0 {
+            return Err("NEWS_FEED_PUBLIC_PER_MINUTE and NEWS_FEED_PUBLIC_BURST must be above 0".to_string());
+        }
+        if (self.post_ttl_secs > 0 || self.user_ttl_secs > 0) && !storage {
+            return Err("NEWS_FEED_POST_TTL_SECS and NEWS_FEED_USER_TTL_SECS need NEWS_FEED_STORAGE to read evicted entries back".to_string());
+        }
+        if self.signal_half_life_secs == 0 || self.injection_interval == 0 {
+            return Err("NEWS_FEED_SIGNAL_HALF_LIFE_SECS and NEWS_FEED_INJECTION_INTERVAL must be above 0".to_string());
+        }
+        if self.search_half_life_secs == 0 {
+            return Err("NEWS_FEED_SEARCH_HALF_LIFE_SECS must be above 0".to_string());
+        }
+        if self.ranking_half_life_secs == 0 {
+            return Err("NEWS_FEED_RANKING_HALF_LIFE_SECS must be above 0".to_string());
+        }
+        if !(self.search_engagement_weight >= 0.0 && self.search_engagement_weight.is_finite()) {
+            return Err("NEWS_FEED_SEARCH_ENGAGEMENT_WEIGHT must be 0 or more".to_string());
+        }
+        if let Some((route, rate)) = self
+            .access_log_sampling
+            .iter()
+            .find(|(_, rate)| !(0.0..=1.0).contains(rate))
+        {
+            return Err(format!("NEWS_FEED_ACCESS_LOG_SAMPLE: rate {} for {} is outside 0-1", rate, route));
+        }
+        if !(0.0..=1.0).contains(&self.engagement_user_rate) {
+            return Err("NEWS_FEED_ENGAGEMENT_USER_RATE must be within 0-1".to_string());
+        }
+        for (kind, rate) in &self.engagement_sampling {
+            if !engagement_log::KINDS.contains(&kind.as_str()) {
+                return Err(format!(
+                    "NEWS_FEED_ENGAGEMENT_SAMPLE: unknown kind {}; expected one of {}",
+                    kind,
+                    engagement_log::KINDS.join(", ")
+                ));
+            }
+            if !(0.0..=1.0).contains(rate) {
+                return Err(format!("NEWS_FEED_ENGAGEMENT_SAMPLE: rate {} for {} is outside 0-1", rate, kind));
+            }
+        }
+        Ok(())
+    }
+
+    // Settings whose values differ from `other`'s, by field name
+    fn diff(&self, other: &Tunables) -> Vec<SettingChange> {
+        let (serde_json::Value::Object(before), serde_json::Value::Object(after)) =
+            (serde_json::json!(self), serde_json::json!(other))
+        else {
+            return Vec::new();
+        };
+        before
+            .into_iter()
+            .filter_map(|(setting, from)| {
+                let to = after.get(&setting)?.clone();
+                (from != to).then_some(SettingChange { setting, from, to })
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingChange {
+    pub setting: String,
+    pub from: serde_json::Value,
+    pub to: serde_json::Value,
+}
+
+// The tunables in effect. A reload swaps in a whole new snapshot, so a reader
+// sees either all of the old values or all of the new ones.
+#[derive(Debug)]
+pub struct Settings {
+    current: RwLock<Arc<Tunables>>,
+    storage: bool, // whether NEWS_FEED_STORAGE is set, for validating reloads
+}
+
+impl Settings {
+    pub fn new(config: &Config, tunables: Tunables) -> Result<Self, String> {
+        let storage = config.storage.is_some();
+        tunables.validate(storage)?;
+        logging::set_level(tunables.log_level);
+        Ok(Self {
+            current: RwLock::new(Arc::new(tunables)),
+            storage,
+        })
+    }
+
+    pub fn current(&self) -> Arc<Tunables> {
+        self.current.read().expect("settings poisoned").clone()
+    }
+
+    // Reads the tunables again and swaps them in if they're valid, returning
+    // what changed. Nothing changes on an error, including a value that
+    // doesn't parse.
+    pub fn reload(&self) -> Result<Vec<SettingChange>, String> {
+        let source = Source::load()?;
+        let next = Tunables::from_source(&source);
+        source.check()?;
+        next.validate(self.storage)?;
+        let mut current = self.current.write().expect("settings poisoned");
+        let changes = current.diff(&next);
+        if !changes.is_empty() {
+            logging::set_level(next.log_level);
+            *current = Arc::new(next);
+        }
+        Ok(changes)
+    }
+}
+
+// Where settings are read from: the file named by NEWS_FEED_CONFIG_FILE,
+// then the environment. The file holds NEWS_FEED_*=value lines, with # for
+// comments; a setting in the file wins over the same one in the environment.
+// Values that don't parse are collected, and `check` reports them.
+pub struct Source {
+    file: HashMap<String, String>,
+    invalid: RefCell<Vec<String>>,
+}
+
+impl Source {
+    pub fn load() -> Result<Self, String> {
+        let mut file = HashMap::new();
+        let Some(path) = env::var_os("NEWS_FEED_CONFIG_FILE").filter(|path| !path.is_empty()) else {
+            return Ok(Self::from_file(file));
+        };
+        let path = PathBuf::from(path);
+        let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=').filter(|(key, _)| key.trim().starts_with("NEWS_FEED_")) else {
+                return Err(format!("{} line {}: expected NEWS_FEED_*=value", path.display(), index + 1));
+            };
+            file.insert(key.trim().to_string(), value.trim().to_string());
+        }
+        Ok(Self::from_file(file))
+    }
+
+    fn from_file(file: HashMap<String, String>) -> Self {
+        Self {
+            file,
+            invalid: RefCell::new(Vec::new()),
+        }
+    }
+
+    // Fails with every setting read so far whose value didn't parse
+    pub fn check(&self) -> Result<(), String> {
+        let invalid = self.invalid.borrow();
+        if invalid.is_empty() {
+            return Ok(());
+        }
+        Err(format!("invalid values: {}", invalid.join(", ")))
+    }
+
+    fn reject(&self, key: &str, value: &str) {
+        self.invalid.borrow_mut().push(format!("{}={}", key, value));
+    }
+
+    fn var(&self, key: &str) -> Result<String, env::VarError> {
+        match self.file.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => env::var(key),
+        }
+    }
+
+    fn parse<T: std::str::FromStr>(&self, key: &str, default: T) -> T {
+        let Ok(value) = self.var(key) else {
+            return default;
+        };
+        value.trim().parse().unwrap_or_else(|_| {
+            self.reject(key, &value);
+            default
+        })
+    }
+
+    // A setting with no default; unset is None
+    fn optional<T: std::str::FromStr>(&self, key: &str) -> Option<T> {
+        let value = self.var(key).ok()?;
+        let parsed = value.trim().parse().ok();
+        if parsed.is_none() {
+            self.reject(key, &value);
+        }
+        parsed
+    }
+
+    // Byte counts, with an optional K/M/G suffix
+    fn bytes(&self, key: &str, default: u64) -> u64 {
+        let Ok(value) = self.var(key) else {
+            return default;
+        };
+        parse_bytes(value.trim()).unwrap_or_else(|| {
+            self.reject(key, &value);
+            default
+        })
+    }
+
+    // A list of name=value entries, split at the last `=`
+    fn pairs<T: std::str::FromStr>(&self, key: &str) -> Vec<(String, T)> {
+        self.list(key)
+            .iter()
+            .filter_map(|item| {
+                let parsed = item
+                    .rsplit_once('=')
+                    .and_then(|(name, value)| Some((name.trim().to_string(), value.trim().parse().ok()?)));
+                if parsed.is_none() {
+                    self.reject(key, item);
+                }
+                parsed
+            })
+            .collect()
+    }
+
+    fn list(&self, key: &str) -> Vec<String> {
+        self.var(key)
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(|item| item.trim().to_string())
+                    .filter(|item| !item.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+// Byte counts with an optional binary K/M/G suffix
+fn parse_bytes(value: &str) -> Option<u64> {
+    let (digits, multiplier) = match value.char_indices().last()? {
+        (index, 'k' | 'K') => (&value[..index], 1 << 10),
+        (index, 'm' | 'M') => (&value[..index], 1 << 20),
+        (index, 'g' | 'G') => (&value[..index], 1 << 30),
+        _ => (value, 1),
+    };
+    digits.trim().parse::<u64>().ok()?.checked_mul(multiplier)
+}
@@ -0,0 +1,94 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::ids::TagId;
+
+// Weighted post length: user-perceived characters (grapheme clusters), with
+// every URL costing a flat `url_weight` regardless of how long it is.
+pub fn weighted_length(content: &str, url_weight: usize) -> usize {
+    let mut length = 0;
+    let mut rest = content;
+
+    while let Some(start) = find_url(rest) {
+        length += rest[..start].graphemes(true).count();
+        let url_len = rest[start..]
+            .find(char::is_whitespace)
+            .unwrap_or(rest.len() - start);
+        length += url_weight;
+        rest = &rest[start + url_len..];
+    }
+
+    length + rest.graphemes(true).count()
+}
+
+pub fn grapheme_length(text: &str) -> usize {
+    text.graphemes(true).count()
+}
+
+fn find_url(text: &str) -> Option<usize> {
+    ["http://", "https://"]
+        .iter()
+        .filter_map(|scheme| {
+            text.match_indices(scheme)
+                .map(|(index, _)| index)
+                .find(|&index| index == 0 || text[..index].ends_with(char::is_whitespace))
+        })
+        .min()
+}
+
+// Usernames referenced as @username, in order of first appearance
+pub fn extract_mentions(content: &str) -> Vec<String> {
+    let mut mentions: Vec<String> = Vec::new();
+    for word in content.split_whitespace() {
+        let Some(rest) = word.strip_prefix('@') else {
+            continue;
+        };
+        let username: String = rest
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .collect();
+        if !username.is_empty() && !mentions.contains(&username) {
+            mentions.push(username);
+        }
+    }
+    mentions
+}
+
+// Lowercased topics written as #hashtag, in order of first appearance
+pub fn extract_hashtags(content: &str) -> Vec<TagId> {
+    let mut hashtags: Vec<TagId> = Vec::new();
+    for word in content.split_whitespace() {
+        let Some(rest) = word.strip_prefix('#') else {
+            continue;
+        };
+        let hashtag: String = rest
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || *c == '_')
+            .flat_map(char::to_lowercase)
+            .collect();
+        let hashtag = TagId::new(hashtag);
+        if !hashtag.as_str().is_empty() && !hashtags.contains(&hashtag) {
+            hashtags.push(hashtag);
+        }
+    }
+    hashtags
+}
+
+// Candidate custom emoji shortcodes written as :shortcode:
+pub fn extract_shortcodes(content: &str) -> Vec<String> {
+    let mut shortcodes: Vec<String> = Vec::new();
+    let mut rest = content;
+    while let Some(start) = rest.find(':') {
+        let after = &rest[start + 1..];
+        match after.find(':') {
+            Some(end) if crate::emoji::is_valid_shortcode(&after[..end]) => {
+                let shortcode = after[..end].to_string();
+                if !shortcodes.contains(&shortcode) {
+                    shortcodes.push(shortcode);
+                }
+                rest = &after[end + 1..];
+            }
+            _ => rest = after,
+        }
+    }
+    shortcodes
+}
@@ -0,0 +1,172 @@
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::ids::UserId;
+
+// Which tenant's state a request runs against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tenant {
+    Production,
+    Sandbox,
+}
+
+impl Tenant {
+    // As settings name it
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Production => "production",
+            Self::Sandbox => "sandbox",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Admin,
+}
+
+// When a request's time limit runs out. The server stores it in the request's
+// extensions before routing; batch sub-requests inherit the batch's.
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline(pub Instant);
+
+// Cancelled when the request ends for any reason: its response is sent, it
+// runs out of time, or the client disconnects. Stored next to the deadline;
+// batch sub-requests get a child of the batch's token.
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation(pub CancellationToken);
+
+// The address of the connection a request came in on; none over a Unix
+// socket. Stored next to the deadline; batch sub-requests inherit the
+// batch's.
+#[derive(Debug, Clone, Copy)]
+pub struct PeerAddr(pub Option<IpAddr>);
+
+// The caller's IP. Each trusted proxy in front of the server appends the
+// address it received the request from to x-forwarded-for, so only the
+// last `trusted_proxies` entries can be believed, and the one furthest left
+// of those is the client. Anything before it was sent by the client and may
+// be made up. With no trusted proxies, or no header, it's the peer address.
+pub fn client_ip(forwarded: Option<&str>, peer: PeerAddr, trusted_proxies: usize) -> Option<IpAddr> {
+    if trusted_proxies == 0 {
+        return peer.0;
+    }
+    let hops: Vec<&str> = forwarded.into_iter().flat_map(|forwarded| forwarded.split(',')).collect();
+    match hops.get(hops.len().saturating_sub(trusted_proxies)) {
+        Some(hop) => hop.trim().parse().ok(),
+        None => peer.0,
+    }
+}
+
+// The request was cancelled or ran out of time before the work finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+// The enabled feature flags, as of when a request started. A request keeps
+// the set it started with even if the flags change while it runs.
+#[derive(Debug, Clone, Default)]
+pub struct FeatureFlags(Arc<BTreeSet<String>>);
+
+impl FeatureFlags {
+    pub fn new(names: &[String]) -> Self {
+        Self(Arc::new(names.iter().map(|name| name.trim().to_string()).collect()))
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.0.iter().cloned().collect()
+    }
+}
+
+// Who is calling and under what limits. Built once by the auth filter and
+// passed to handlers and the services they call.
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    pub user_id: UserId,
+    pub roles: Vec<Role>,
+    pub tenant: Tenant,
+    pub request_id: String,
+    pub deadline: Instant,
+    pub cancel: CancellationToken,
+    pub flags: FeatureFlags,
+}
+
+impl RequestContext {
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    // For checks between steps of synchronous work, which select! can't
+    // interrupt
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.cancel.is_cancelled() || Instant::now() >= self.deadline {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    // Runs `work` unless the request is cancelled or out of time first, in
+    // which case `work` is dropped at its next await point
+    pub async fn run<F: Future>(&self, work: F) -> Result<F::Output, Cancelled> {
+        self.check()?;
+        tokio::select! {
+            output = work => Ok(output),
+            _ = self.cancel.cancelled() => Err(Cancelled),
+            _ = tokio::time::sleep_until(self.deadline.into()) => Err(Cancelled),
+        }
+    }
+}
+
+// Whom a response is shaped for. Hydration and profiles vary by it: an
+// anonymous viewer gets nothing viewer-specific, and a viewer an author has
+// blocked doesn't get the author's content at all.
+#[derive(Debug, Clone)]
+pub enum ViewerContext {
+    Anonymous { country: Option<String> },
+    User { user_id: UserId, country: Option<String> },
+}
+
+impl ViewerContext {
+    pub fn user_id(&self) -> Option<&UserId> {
+        match self {
+            Self::Anonymous { .. } => None,
+            Self::User { user_id, .. } => Some(user_id),
+        }
+    }
+
+    // For takedowns
+    pub fn country(&self) -> Option<&str> {
+        match self {
+            Self::Anonymous { country } | Self::User { country, .. } => country.as_deref(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_ip_skips_hops_the_client_could_have_sent() {
+        let peer = PeerAddr(Some("10.0.0.9".parse().unwrap()));
+        let forwarded = Some("6.6.6.6, 203.0.113.7, 10.0.0.5");
+        // No trusted proxies: the header is ignored
+        assert_eq!(client_ip(forwarded, peer, 0), peer.0);
+        assert_eq!(client_ip(forwarded, peer, 1), Some("10.0.0.5".parse().unwrap()));
+        assert_eq!(client_ip(forwarded, peer, 2), Some("203.0.113.7".parse().unwrap()));
+        // Fewer hops than proxies: every entry was added by a proxy
+        assert_eq!(client_ip(Some("203.0.113.7"), peer, 2), Some("203.0.113.7".parse().unwrap()));
+        assert_eq!(client_ip(None, peer, 1), peer.0);
+    }
+}
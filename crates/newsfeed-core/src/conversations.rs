@@ -0,0 +1,165 @@
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::cache::Cache;
+use crate::context::ViewerContext;
+use crate::ids::{PostId, UserId};
+use crate::news_feed::NewsFeedService;
+use crate::{HydratedPost, Post, PostUnavailable};
+
+#[derive(Debug, Serialize)]
+pub struct ReplyNode {
+    #[serde(flatten)]
+    pub post: HydratedPost,
+    pub replies: Vec<ReplyNode>,
+    pub more_replies: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Timeline {
+    pub posts: Vec<HydratedPost>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Conversation {
+    pub ancestors: Vec<HydratedPost>,
+    pub post: HydratedPost,
+    pub replies: Vec<ReplyNode>,
+    pub next_cursor: Option<String>,
+}
+
+pub const MAX_ANCESTORS: usize = 100;
+pub const NESTED_REPLY_DEPTH: usize = 2;
+pub const NESTED_REPLY_LIMIT: usize = 3;
+
+pub struct ConversationService {
+    pub cache: Arc<dyn Cache>,
+    pub news_feed_service: Arc<NewsFeedService>,
+}
+
+impl ConversationService {
+    pub fn new(cache: Arc<dyn Cache>, news_feed_service: Arc<NewsFeedService>) -> Self {
+        Self { cache, news_feed_service }
+    }
+
+    pub async fn get_conversation(
+        &self,
+        viewer_id: &UserId,
+        post_id: &PostId,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Conversation, PostUnavailable> {
+        let viewer = self.cache.viewer(viewer_id);
+        let post = self.cache.post_for(&viewer, post_id)?;
+        let hydrated = self.news_feed_service.shape(&viewer, post.clone())?;
+
+        let mut ancestors = Vec::new();
+        let mut parent_id = post.in_reply_to.clone();
+        while let Some(id) = parent_id {
+            if ancestors.len() >= MAX_ANCESTORS {
+                break;
+            }
+            match self.cache.get_post(&id) {
+                Some(parent) => {
+                    parent_id = parent.in_reply_to.clone();
+                    ancestors.push(parent);
+                }
+                None => break,
+            }
+        }
+        ancestors.reverse();
+
+        // Replies liked by whoever started the conversation rank first
+        let root_author = ancestors.first().unwrap_or(&post).user_id.clone();
+        let ranked = self.ranked_replies(&viewer, &post.id, &root_author);
+        let next_cursor = (ranked.len() > offset + limit).then(|| (offset + limit).to_string());
+        let replies = ranked
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .filter_map(|reply| self.reply_node(&viewer, reply, &root_author, NESTED_REPLY_DEPTH))
+            .collect();
+
+        // Ancestors the viewer can't see are skipped; the chain reads on
+        Ok(Conversation {
+            ancestors: ancestors
+                .into_iter()
+                .filter_map(|ancestor| self.news_feed_service.shape(&viewer, ancestor).ok())
+                .collect(),
+            post: hydrated,
+            replies,
+            next_cursor,
+        })
+    }
+
+    pub fn reply_node(&self, viewer: &ViewerContext, reply: Post, root_author: &UserId, depth: usize) -> Option<ReplyNode> {
+        let (replies, more_replies) = if depth == 0 {
+            (Vec::new(), self.cache.get_replies(&reply.id).len())
+        } else {
+            let nested = self.ranked_replies(viewer, &reply.id, root_author);
+            let more_replies = nested.len().saturating_sub(NESTED_REPLY_LIMIT);
+            let replies = nested
+                .into_iter()
+                .take(NESTED_REPLY_LIMIT)
+                .filter_map(|nested| self.reply_node(viewer, nested, root_author, depth - 1))
+                .collect();
+            (replies, more_replies)
+        };
+
+        Some(ReplyNode {
+            post: self.news_feed_service.shape(viewer, reply).ok()?,
+            replies,
+            more_replies,
+        })
+    }
+
+    // A post's direct replies, oldest first, without the ranking or nesting
+    // of the conversation view
+    pub fn replies(
+        &self,
+        viewer_id: &UserId,
+        post_id: &PostId,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Timeline, PostUnavailable> {
+        let viewer = self.cache.viewer(viewer_id);
+        self.cache.post_for(&viewer, post_id)?;
+        let reply_ids = self.cache.get_replies(post_id);
+        let next_cursor = (reply_ids.len() > offset + limit).then(|| (offset + limit).to_string());
+        Ok(Timeline {
+            posts: reply_ids
+                .iter()
+                .skip(offset)
+                .take(limit)
+                .filter_map(|reply_id| self.cache.get_post(reply_id))
+                .filter_map(|reply| self.news_feed_service.shape(&viewer, reply).ok())
+                .collect(),
+            next_cursor,
+        })
+    }
+
+    // Author-liked replies first, then by engagement, then oldest first.
+    // Replies the viewer can't see are left out before paging.
+    pub fn ranked_replies(&self, viewer: &ViewerContext, post_id: &PostId, root_author: &UserId) -> Vec<Post> {
+        let mut replies: Vec<(bool, u32, Post)> = self
+            .cache
+            .get_replies(post_id)
+            .iter()
+            .filter_map(|reply_id| self.cache.get_post(reply_id))
+            .filter(|reply| !self.cache.hidden_from(viewer, &reply.user_id))
+            .map(|reply| {
+                let counters = self.cache.get_counters(&reply.id);
+                let author_liked = self.cache.has_liked(root_author, &reply.id);
+                (author_liked, counters.likes + 2 * counters.replies, reply)
+            })
+            .collect();
+
+        replies.sort_by(|a, b| {
+            b.0.cmp(&a.0)
+                .then(b.1.cmp(&a.1))
+                .then(a.2.timestamp.cmp(&b.2.timestamp))
+        });
+        replies.into_iter().map(|(_, _, reply)| reply).collect()
+    }
+}
@@ -0,0 +1,19 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Sends transactional email. No mail provider is wired up yet, so each
+// message is logged instead.
+#[derive(Debug, Default)]
+pub struct EmailService {
+    sent: AtomicU64,
+}
+
+impl EmailService {
+    pub fn send(&self, to: &str, subject: &str, body: &str) {
+        println!("Email to {}: {}\n{}", to, subject, body);
+        self.sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,233 @@
+use futures::future::BoxFuture;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc;
+
+use crate::ann::{self, AnnIndex};
+use crate::cache::Cache;
+use crate::ids::{PostId, UserId};
+use crate::{search, warn};
+
+// Posts sent to the backend in one call
+const BATCH_SIZE: usize = 32;
+
+// Engagement a user's vector is built from: their newest posts, and posts
+// they liked
+const USER_OWN_POSTS: usize = 20;
+const USER_LIKED_POSTS: usize = 100;
+
+// Turns posts and users into vectors whose cosine similarity says how alike
+// they are. Backends are a local model or an external service.
+pub trait Embeddings: Send + Sync + 'static {
+    fn name(&self) -> &'static str;
+
+    fn dimensions(&self) -> usize;
+
+    // One vector per text, in order
+    fn embed_posts<'a>(&'a self, texts: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>, String>>;
+
+    // A user's vector, from the vectors of posts they wrote or liked. The
+    // default is their mean; None when there are none.
+    fn embed_user(&self, engaged: &[&[f32]]) -> Option<Vec<f32>> {
+        let mut mean = vec![0.0; self.dimensions()];
+        for vector in engaged {
+            mean.iter_mut().zip(*vector).for_each(|(sum, x)| *sum += x);
+        }
+        ann::normalize(&mut mean).then_some(mean)
+    }
+}
+
+// Feature hashing: each distinct word adds ±1 to the dimension its hash
+// picks. No model to download, but it only knows which words posts share,
+// not what they mean; a baseline until a real model is plugged in.
+pub struct LocalEmbeddings {
+    dimensions: usize,
+}
+
+impl LocalEmbeddings {
+    pub fn new(dimensions: usize) -> Self {
+        Self { dimensions }
+    }
+}
+
+// FNV-1a, so a word lands in the same dimension on every node
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+impl Embeddings for LocalEmbeddings {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    fn embed_posts<'a>(&'a self, texts: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>, String>> {
+        let vectors = texts
+            .iter()
+            .map(|text| {
+                let mut vector = vec![0.0; self.dimensions];
+                for term in search::terms(text) {
+                    let hash = fnv1a(term.as_bytes());
+                    vector[(hash % self.dimensions as u64) as usize] += if hash >> 63 == 0 { 1.0 } else { -1.0 };
+                }
+                vector
+            })
+            .collect();
+        Box::pin(async move { Ok(vectors) })
+    }
+}
+
+enum Op {
+    Upsert { post_id: PostId, text: String },
+    Remove(PostId),
+    Clear,
+}
+
+// Post vectors in an ANN index, kept up to date from the event log. Posts
+// are embedded in batches by one background task, so writes never wait on
+// the backend; a post is missing from the index until its batch is done,
+// and for good if the backend failed on it.
+pub struct Vectors {
+    embeddings: Arc<dyn Embeddings>,
+    index: RwLock<AnnIndex<PostId>>,
+    ops: mpsc::UnboundedSender<Op>,
+    pending: AtomicU64,  // queued posts not yet embedded
+    failures: AtomicU64, // posts the backend failed on
+}
+
+impl Vectors {
+    // Starts the embedding task
+    pub fn spawn(embeddings: Arc<dyn Embeddings>) -> Arc<Self> {
+        let (ops, receiver) = mpsc::unbounded_channel();
+        let vectors = Arc::new(Self {
+            index: RwLock::new(AnnIndex::new(embeddings.dimensions())),
+            embeddings,
+            ops,
+            pending: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+        });
+        tokio::spawn(vectors.clone().run(receiver));
+        vectors
+    }
+
+    pub fn submit(&self, post_id: PostId, text: String) {
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        let _ = self.ops.send(Op::Upsert { post_id, text });
+    }
+
+    pub fn remove(&self, post_id: PostId) {
+        let _ = self.ops.send(Op::Remove(post_id));
+    }
+
+    // Drops every vector; the posts are sent again as the log replays
+    pub fn clear(&self) {
+        let _ = self.ops.send(Op::Clear);
+    }
+
+    async fn run(self: Arc<Self>, mut ops: mpsc::UnboundedReceiver<Op>) {
+        while let Some(op) = ops.recv().await {
+            // Upserts queued back to back share a backend call; anything
+            // else waits for the batch before it, so order holds
+            let mut batch = Vec::new();
+            let mut next = Some(op);
+            while let Some(op) = next.take() {
+                match op {
+                    Op::Upsert { post_id, text } => {
+                        batch.push((post_id, text));
+                        if batch.len() < BATCH_SIZE {
+                            next = ops.try_recv().ok();
+                        }
+                    }
+                    Op::Remove(post_id) => {
+                        self.embed(std::mem::take(&mut batch)).await;
+                        self.index.write().expect("vector index poisoned").remove(&post_id);
+                        next = ops.try_recv().ok();
+                    }
+                    Op::Clear => {
+                        self.embed(std::mem::take(&mut batch)).await;
+                        self.index.write().expect("vector index poisoned").clear();
+                        next = ops.try_recv().ok();
+                    }
+                }
+            }
+            self.embed(batch).await;
+        }
+    }
+
+    async fn embed(&self, batch: Vec<(PostId, String)>) {
+        if batch.is_empty() {
+            return;
+        }
+        let count = batch.len() as u64;
+        let (post_ids, texts): (Vec<PostId>, Vec<String>) = batch.into_iter().unzip();
+        match self.embeddings.embed_posts(&texts).await {
+            Ok(vectors) => {
+                let mut index = self.index.write().expect("vector index poisoned");
+                for (post_id, vector) in post_ids.into_iter().zip(vectors) {
+                    // A post with no words has no direction, and no vector
+                    index.insert(post_id, vector);
+                }
+            }
+            Err(e) => {
+                warn!("embeddings: {} posts left without vectors: {}", count, e);
+                self.failures.fetch_add(count, Ordering::Relaxed);
+            }
+        }
+        self.pending.fetch_sub(count, Ordering::Relaxed);
+    }
+
+    // Up to `max` posts nearest to the post, with their cosine similarity
+    pub fn similar_posts(&self, post_id: &PostId, max: usize) -> Vec<(PostId, f32)> {
+        let index = self.index.read().expect("vector index poisoned");
+        match index.get(post_id) {
+            Some(vector) => index.nearest(vector, max, |other| other == post_id),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn post_vector(&self, post_id: &PostId) -> Option<Vec<f32>> {
+        self.index.read().expect("vector index poisoned").get(post_id).map(<[f32]>::to_vec)
+    }
+
+    // The user's vector, from their recent posts and likes
+    pub fn user_vector(&self, cache: &dyn Cache, user_id: &UserId) -> Option<Vec<f32>> {
+        let own = cache.get_user_post_ids(user_id);
+        let engaged: Vec<PostId> = own
+            .into_iter()
+            .rev()
+            .take(USER_OWN_POSTS)
+            .chain(cache.liked_posts(user_id, USER_LIKED_POSTS))
+            .collect();
+        let index = self.index.read().expect("vector index poisoned");
+        let vectors: Vec<&[f32]> = engaged.iter().filter_map(|post_id| index.get(post_id)).collect();
+        self.embeddings.embed_user(&vectors)
+    }
+
+    // Cosine similarity of a user's or post's vector to a post's, if it has one
+    pub fn similarity(&self, vector: &[f32], post_id: &PostId) -> Option<f32> {
+        let index = self.index.read().expect("vector index poisoned");
+        index.get(post_id).map(|other| ann::dot(vector, other))
+    }
+
+    // Prometheus text exposition of the index size and the backend's progress
+    pub fn metrics(&self) -> String {
+        let vectors = self.index.read().expect("vector index poisoned").len();
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP news_feed_embedding_vectors Post vectors in the ANN index.");
+        let _ = writeln!(out, "# TYPE news_feed_embedding_vectors gauge");
+        let _ = writeln!(out, "news_feed_embedding_vectors{{backend=\"{}\"}} {}", self.embeddings.name(), vectors);
+        let _ = writeln!(out, "# HELP news_feed_embedding_pending Posts queued for embedding.");
+        let _ = writeln!(out, "# TYPE news_feed_embedding_pending gauge");
+        let _ = writeln!(out, "news_feed_embedding_pending {}", self.pending.load(Ordering::Relaxed));
+        let _ = writeln!(out, "# HELP news_feed_embedding_failures_total Posts left without a vector because the backend failed.");
+        let _ = writeln!(out, "# TYPE news_feed_embedding_failures_total counter");
+        let _ = writeln!(out, "news_feed_embedding_failures_total {}", self.failures.load(Ordering::Relaxed));
+        out
+    }
+}
@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+
+// Deployment-wide custom emoji, referenced in content as :shortcode:
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomEmoji {
+    pub shortcode: String,
+    pub url: String,
+}
+
+pub const MAX_EMOJI_BYTES: u64 = 256 * 1024;
+
+pub fn is_valid_shortcode(shortcode: &str) -> bool {
+    (2..=32).contains(&shortcode.len())
+        && shortcode
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+pub fn image_extension(content_type: &str) -> Option<&'static str> {
+    match content_type {
+        "image/png" => Some("png"),
+        "image/gif" => Some("gif"),
+        "image/webp" => Some("webp"),
+        _ => None,
+    }
+}
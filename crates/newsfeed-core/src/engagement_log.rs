@@ -0,0 +1,215 @@
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+use crate::config::{Config, Settings};
+use crate::ids::{PostId, UserId};
+use crate::reach::Channel;
+use crate::{Post, now_millis, warn};
+
+// The file being written; it's renamed to engagement-<opened millis>.ndjson
+// when it rolls, so every other file in the directory is complete
+const CURRENT_FILE: &str = "current.ndjson";
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+pub const KINDS: &[&str] = &["impression", "like", "open", "click", "dwell"];
+
+// What a user did with a post
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Interaction {
+    Impression { channel: Channel }, // served on a page
+    Like,
+    Open,                 // opened its conversation
+    Click,                // clicked in the feed, as the client reports it
+    Dwell { millis: u64 }, // time on screen, as the client reports it
+}
+
+impl Interaction {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Impression { .. } => "impression",
+            Self::Like => "like",
+            Self::Open => "open",
+            Self::Click => "click",
+            Self::Dwell { .. } => "dwell",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Entry<'a> {
+    at: u64,
+    user_id: &'a UserId,
+    post_id: &'a PostId,
+    author_id: &'a UserId,
+    #[serde(flatten)]
+    interaction: Interaction,
+    // The chance this entry had of being kept, to weight it back up by
+    sample_rate: f64,
+}
+
+// Engagement events as JSON lines in a directory of roll-over files, the
+// dataset for offline evaluation and affinity models. Lines are written from
+// their own thread so requests never wait on disk.
+pub struct EngagementLog {
+    sender: Option<Sender<String>>, // unset when the log is off
+    settings: Arc<Settings>,        // for the sample rates
+}
+
+impl EngagementLog {
+    pub fn new(config: &Config, settings: Arc<Settings>) -> Self {
+        let sender = config.engagement_log.clone().map(|dir| {
+            spawn_writer(
+                dir,
+                config.engagement_log_max_bytes,
+                Duration::from_secs(config.engagement_log_roll_secs),
+            )
+        });
+        Self { sender, settings }
+    }
+
+    // Users are sampled as a whole, so a kept user's sessions are complete;
+    // then each kind has its own rate. Authors' own interactions aren't kept.
+    pub fn record(&self, user_id: &UserId, post: &Post, interaction: Interaction) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        if &post.user_id == user_id {
+            return;
+        }
+        let tunables = self.settings.current();
+        let user_rate = tunables.engagement_user_rate;
+        if user_rate < 1.0 && sample_point(user_id.as_str()) >= user_rate {
+            return;
+        }
+        let kind_rate = tunables
+            .engagement_sampling
+            .iter()
+            .find(|(kind, _)| kind == interaction.kind())
+            .map(|(_, rate)| *rate)
+            .unwrap_or(1.0);
+        if kind_rate < 1.0 && rand::random::<f64>() >= kind_rate {
+            return;
+        }
+
+        let entry = Entry {
+            at: now_millis(),
+            user_id,
+            post_id: &post.id,
+            author_id: &post.user_id,
+            interaction,
+            sample_rate: user_rate * kind_rate,
+        };
+        let line = serde_json::to_string(&entry).expect("engagement entry serializes");
+        let _ = sender.send(line);
+    }
+}
+
+fn spawn_writer(dir: PathBuf, max_bytes: u64, max_age: Duration) -> Sender<String> {
+    let (sender, receiver) = mpsc::channel::<String>();
+    std::thread::spawn(move || {
+        // A file left by the last run is complete as far as it goes
+        seal(&dir, now_millis());
+        let mut file: Option<(BufWriter<File>, u64)> = None; // with the millis it opened at
+        let mut opened = Instant::now();
+        let mut written = 0;
+        let mut flushed = Instant::now();
+
+        loop {
+            match receiver.recv_timeout(FLUSH_INTERVAL) {
+                Ok(line) => {
+                    if let Some((_, opened_at)) = &file
+                        && (written + line.len() as u64 + 1 > max_bytes || opened.elapsed() >= max_age)
+                    {
+                        let opened_at = *opened_at;
+                        close(file.take());
+                        seal(&dir, opened_at);
+                    }
+                    if file.is_none() {
+                        file = open_log(&dir).map(|writer| (writer, now_millis()));
+                        opened = Instant::now();
+                        written = 0;
+                    }
+                    if let Some((writer, _)) = file.as_mut() {
+                        match writeln!(writer, "{}", line) {
+                            Ok(()) => written += line.len() as u64 + 1,
+                            Err(e) => warn!("Failed to write engagement log in {}: {}", dir.display(), e),
+                        }
+                    }
+                    if flushed.elapsed() >= FLUSH_INTERVAL {
+                        flush(&mut file);
+                        flushed = Instant::now();
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    flush(&mut file);
+                    flushed = Instant::now();
+                    // Roll an idle file on time too, so it can be picked up
+                    if let Some((_, opened_at)) = &file
+                        && opened.elapsed() >= max_age
+                    {
+                        let opened_at = *opened_at;
+                        close(file.take());
+                        seal(&dir, opened_at);
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    close(file.take());
+                    break;
+                }
+            }
+        }
+    });
+    sender
+}
+
+fn open_log(dir: &Path) -> Option<BufWriter<File>> {
+    let _ = std::fs::create_dir_all(dir);
+    let path = dir.join(CURRENT_FILE);
+    match OpenOptions::new().create(true).append(true).open(&path) {
+        Ok(file) => Some(BufWriter::new(file)),
+        Err(e) => {
+            warn!("Failed to open engagement log {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn flush(file: &mut Option<(BufWriter<File>, u64)>) {
+    if let Some((writer, _)) = file.as_mut()
+        && let Err(e) = writer.flush()
+    {
+        warn!("Failed to flush engagement log: {}", e);
+    }
+}
+
+fn close(mut file: Option<(BufWriter<File>, u64)>) {
+    flush(&mut file);
+}
+
+// current.ndjson -> engagement-<millis>.ndjson
+fn seal(dir: &Path, opened_at: u64) {
+    let current = dir.join(CURRENT_FILE);
+    if current.exists() {
+        let sealed = dir.join(format!("engagement-{}.ndjson", opened_at));
+        if let Err(e) = std::fs::rename(&current, &sealed) {
+            warn!("Failed to roll engagement log {}: {}", current.display(), e);
+        }
+    }
+}
+
+// Deterministic per key, so a user is always in or out of the sample; also
+// samples access log entries by request ID
+pub fn sample_point(key: &str) -> f64 {
+    let hash = key
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+    (hash % 10_000) as f64 / 10_000.0
+}
@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::now_millis;
+
+// One entry in the event log. Sequence numbers start at 1 and have no gaps.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recorded<E> {
+    pub seq: u64,
+    pub at: u64,
+    pub event: E,
+}
+
+// State derived from the event log. A projection must be rebuildable: after
+// reset(), replaying every event again has to produce the same state.
+pub trait Projection<E>: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // `replay` is set while rebuilding from history; side effects that
+    // already happened once (notifications, pushes) must be skipped
+    fn apply(&self, event: &Recorded<E>, replay: bool);
+
+    fn reset(&self);
+}
+
+// The ordered log every write goes through, plus the projections built from
+// it. Appending and applying happen under one lock, so every projection sees
+// events in sequence order and a rebuild never interleaves with new writes.
+pub struct EventStore<E> {
+    log: Mutex<Vec<Recorded<E>>>,
+    projections: RwLock<Vec<Arc<dyn Projection<E>>>>,
+}
+
+impl<E> Default for EventStore<E> {
+    fn default() -> Self {
+        Self {
+            log: Mutex::new(Vec::new()),
+            projections: RwLock::new(Vec::new()),
+        }
+    }
+}
+
+impl<E> EventStore<E> {
+    // Appends the event and applies it to every projection
+    pub fn publish(&self, event: E) -> u64 {
+        let mut log = self.log.lock().expect("event log poisoned");
+        let recorded = Recorded {
+            seq: log.len() as u64 + 1,
+            at: now_millis(),
+            event,
+        };
+        for projection in self.projections.read().expect("projections poisoned").iter() {
+            projection.apply(&recorded, false);
+        }
+        let seq = recorded.seq;
+        log.push(recorded);
+        seq
+    }
+
+    // Adds a projection, first replaying the history it missed
+    pub fn register(&self, projection: Arc<dyn Projection<E>>) {
+        let log = self.log.lock().expect("event log poisoned");
+        for recorded in log.iter() {
+            projection.apply(recorded, true);
+        }
+        self.projections.write().expect("projections poisoned").push(projection);
+    }
+
+    // Resets the named projection and replays the whole log into it. Returns
+    // the number of events replayed, or None for an unknown projection.
+    pub fn rebuild(&self, name: &str) -> Option<usize> {
+        let log = self.log.lock().expect("event log poisoned");
+        let projections = self.projections.read().expect("projections poisoned");
+        let projection = projections.iter().find(|projection| projection.name() == name)?;
+        projection.reset();
+        for recorded in log.iter() {
+            projection.apply(recorded, true);
+        }
+        Some(log.len())
+    }
+
+    // Drops the history; the caller wipes the state that was built from it
+    pub fn clear(&self) {
+        self.log.lock().expect("event log poisoned").clear();
+    }
+
+    pub fn projection_names(&self) -> Vec<&'static str> {
+        self.projections
+            .read()
+            .expect("projections poisoned")
+            .iter()
+            .map(|projection| projection.name())
+            .collect()
+    }
+
+    // Every event after `after`, in order, for export
+    pub fn since(&self, after: u64) -> Vec<Recorded<E>>
+    where
+        E: Clone,
+    {
+        let log = self.log.lock().expect("event log poisoned");
+        log.iter().skip(after as usize).cloned().collect()
+    }
+
+    pub fn event_count(&self) -> usize {
+        self.log.lock().expect("event log poisoned").len()
+    }
+}
@@ -0,0 +1,451 @@
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::cache::Cache;
+use crate::cluster::{
+    DeliverFeedItemsRequest, DeliverFeedItemsResponse, FeedPartitions, FeedTarget, LocalDelivery, RemoteDelivery,
+};
+use crate::i18n::Catalogs;
+use crate::ids::{PostId, UserId};
+use crate::jobs::JobQueue;
+use crate::markers::Step;
+use crate::mixer::DAY_MILLIS;
+use crate::notifications::{Notification, PushGateway, notification_text};
+use crate::receipts::{LatencyHistogram, Outcome};
+use crate::{FanoutMessage, FollowBackfill, NewsFeedItem, Post, debug, now_millis, warn};
+
+// Runs fanout jobs
+pub struct FanoutWorker {
+    pub cache: Arc<dyn Cache>,
+    pub push: Arc<PushGateway>,
+    pub catalogs: Arc<Catalogs>,
+    pub partitions: Arc<FeedPartitions>,
+    pub peers: Arc<dyn RemoteDelivery>,
+    pub repeated: [AtomicU64; 2], // steps skipped as already done, by Step
+    pub outcomes: [AtomicU64; 3], // followers handled, by Outcome
+    pub latency: Mutex<LatencyHistogram>, // publish to feed insert, every post
+}
+
+// What happened to one delivery's followers. Repeats were handled by an
+// earlier run for the same post and aren't in the other counts.
+#[derive(Debug, Default)]
+pub struct DeliveryCounts {
+    pub delivered: u32,
+    pub skipped: u32,
+    pub throttled: u32,
+    pub repeated: u32,
+}
+
+impl FanoutWorker {
+    pub fn new(
+        cache: Arc<dyn Cache>,
+        push: Arc<PushGateway>,
+        catalogs: Arc<Catalogs>,
+        partitions: Arc<FeedPartitions>,
+        peers: Arc<dyn RemoteDelivery>,
+    ) -> Self {
+        Self {
+            cache,
+            push,
+            catalogs,
+            partitions,
+            peers,
+            repeated: Default::default(),
+            outcomes: Default::default(),
+            latency: Mutex::new(LatencyHistogram::default()),
+        }
+    }
+
+    // Counts followers handled for a post whose fanout runs on this node, in
+    // its receipt and the node-wide metrics
+    pub fn record(&self, post_id: &PostId, published_at: u64, outcome: Outcome, count: u32) {
+        let now = now_millis();
+        if count == 0 || !self.cache.record_receipt(post_id, outcome, count, now) {
+            return;
+        }
+        self.outcomes[outcome as usize].fetch_add(count as u64, Ordering::Relaxed);
+        if let Outcome::Delivered = outcome {
+            self.latency
+                .lock()
+                .expect("latency histogram poisoned")
+                .record(now.saturating_sub(published_at), count as u64);
+        }
+    }
+
+    pub fn repeated(&self, step: Step) -> &AtomicU64 {
+        &self.repeated[step as usize - 1]
+    }
+
+    pub async fn process(&self, message: FanoutMessage) -> Result<(), String> {
+        debug!("Processing fanout for post {} by {}", message.post_id, message.user_id);
+        if let Some(post) = message.post.clone()
+            && self.cache.get_post(&message.post_id).is_none()
+        {
+            self.cache.set_post(post);
+        }
+
+        // Group followers by the node that owns their feed
+        let mut targets: HashMap<&str, Vec<FeedTarget>> = HashMap::new();
+        let extra_notify = message.notify_ids.iter().filter(|id| !message.friend_ids.contains(id));
+        for user_id in message.friend_ids.iter().chain(extra_notify) {
+            targets
+                .entry(self.partitions.owner(user_id))
+                .or_default()
+                .push(FeedTarget {
+                    user_id: user_id.to_string(),
+                    deliver: message.friend_ids.contains(user_id),
+                    notify: message.notify_ids.contains(user_id),
+                    daily_limit: message.daily_limits.get(user_id).map(|&limit| limit as u32),
+                });
+        }
+
+        self.cache.start_receipt(
+            &message.post_id,
+            &message.user_id,
+            message.at,
+            message.friend_ids.len() as u32,
+        );
+        let item = NewsFeedItem {
+            post_id: message.post_id.clone(),
+            timestamp: message.at,
+        };
+        if let Some(local) = targets.remove(self.partitions.local()) {
+            self.deliver_local(&message.user_id, &item, &local).await;
+        }
+
+        // Other nodes' feeds get one batch each, sent together. A failed batch
+        // fails the job, and the retry skips feeds that already have the post.
+        let post_json = match self.cache.get_post(&message.post_id) {
+            Some(post) if !targets.is_empty() => serde_json::to_vec(&post).map_err(|e| e.to_string())?,
+            _ => Vec::new(),
+        };
+        let batches = targets.into_iter().map(|(node, targets)| {
+            let request = DeliverFeedItemsRequest {
+                batch_id: format!("{}@{}", message.post_id, node),
+                origin_node: self.partitions.local().to_string(),
+                author_id: message.user_id.to_string(),
+                post_id: message.post_id.to_string(),
+                published_at: message.at,
+                post_json: post_json.clone(),
+                targets,
+            };
+            let (post_id, published_at) = (&message.post_id, message.at);
+            async move {
+                let ack = self.peers.deliver(node, request).await?;
+                self.record(post_id, published_at, Outcome::Delivered, ack.delivered);
+                self.record(post_id, published_at, Outcome::Skipped, ack.skipped);
+                self.record(post_id, published_at, Outcome::Throttled, ack.throttled);
+                if !ack.misrouted.is_empty() {
+                    warn!(
+                        "Feed node {} doesn't own {} feeds in batch {}; partition maps disagree",
+                        node,
+                        ack.misrouted.len(),
+                        ack.batch_id
+                    );
+                }
+                Ok::<_, String>(())
+            }
+        });
+        let failures: Vec<String> = futures::future::join_all(batches)
+            .await
+            .into_iter()
+            .filter_map(Result::err)
+            .collect();
+
+        // Simulate processing time
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        if failures.is_empty() { Ok(()) } else { Err(failures.join("; ")) }
+    }
+
+    // Bell notifications and feed writes for feeds on this node. Returns how
+    // many feeds got the post, already had it, were over a daily limit, and
+    // were handled by an earlier run.
+    pub async fn deliver_local(&self, author_id: &UserId, item: &NewsFeedItem, targets: &[FeedTarget]) -> DeliveryCounts {
+        // Bell notifications go out before the (slower) feed writes. A
+        // delivery marker makes each happen once, however often the fanout
+        // for this post is run.
+        let notification = Notification::new_post(author_id, &item.post_id);
+        for target in targets.iter().filter(|target| target.notify) {
+            let follower_id = UserId::new(&target.user_id);
+            if !self.cache.mark_step(&item.post_id, &follower_id, Step::Notified) {
+                self.repeated(Step::Notified).fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            self.cache.add_notification(&follower_id, notification.clone());
+            if self.cache.in_quiet_hours(&follower_id, now_millis()) {
+                self.push.hold();
+            } else {
+                let locale = self.cache.user_locale(&follower_id, &self.catalogs);
+                let text = notification_text(self.cache.as_ref(), &self.catalogs, &locale, &notification);
+                self.push.send(&follower_id, &notification, &text);
+            }
+        }
+
+        // Add to each friend's news feed; retried fanouts skip feeds that have it,
+        // and feeds that already got their daily share of this author's posts
+        let day = item.timestamp / DAY_MILLIS;
+        let mut counts = DeliveryCounts::default();
+        for target in targets.iter().filter(|target| target.deliver) {
+            let friend_id = UserId::new(&target.user_id);
+            let _feed_lock = self.cache.feed_locks().lock(&friend_id).await;
+            // Checked before the daily limit, so a repeat isn't counted as throttled
+            if self.cache.step_done(&item.post_id, &friend_id, Step::Delivered) {
+                self.repeated(Step::Delivered).fetch_add(1, Ordering::Relaxed);
+                counts.repeated += 1;
+                continue;
+            }
+            let outcome = if let Some(limit) = target.daily_limit
+                && self.cache.delivered_from(&friend_id, author_id, day) as u32 >= limit
+            {
+                Outcome::Throttled
+            } else if !self.cache.add_to_news_feed(&friend_id, item.clone()) {
+                Outcome::Skipped
+            } else {
+                if target.daily_limit.is_some() {
+                    self.cache.record_delivery(&friend_id, author_id, day);
+                }
+                Outcome::Delivered
+            };
+            match outcome {
+                Outcome::Delivered => counts.delivered += 1,
+                Outcome::Skipped => counts.skipped += 1,
+                Outcome::Throttled => counts.throttled += 1,
+            }
+            // Receipts count each follower as it's handled, so progress shows mid-fanout
+            self.record(&item.post_id, item.timestamp, outcome, 1);
+        }
+        if counts.skipped > 0 {
+            debug!(
+                "Fanout skipped {} feeds that already had or hide post {}",
+                counts.skipped, item.post_id
+            );
+        }
+        if counts.throttled > 0 {
+            debug!(
+                "Fanout kept post {} out of {} feeds over their daily limit for {}",
+                item.post_id, counts.throttled, author_id
+            );
+        }
+        counts
+    }
+
+    // Copies the followed account's newest top-level posts into the
+    // follower's feed, if this node owns it and the follow still stands. A
+    // daily limit keeps the first posts of each UTC day, as fanout would.
+    pub async fn backfill(&self, job: FollowBackfill) {
+        if !self.partitions.is_local(&job.follower_id) {
+            return;
+        }
+        let _feed_lock = self.cache.feed_locks().lock(&job.follower_id).await;
+        let Some(edge) = self.cache.graph().edge(&job.follower_id, &job.followed_id) else {
+            return;
+        };
+        let mut posts: Vec<Post> = self
+            .cache
+            .get_user_post_ids(&job.followed_id)
+            .iter()
+            .rev()
+            .filter_map(|post_id| self.cache.get_post(post_id))
+            .filter(|post| post.in_reply_to.is_none() && !post.anonymous)
+            .take(job.posts)
+            .collect();
+        if let Some(limit) = edge.daily_limit {
+            posts.reverse();
+            let mut per_day: HashMap<u64, u16> = HashMap::new();
+            posts.retain(|post| {
+                let count = per_day.entry(post.timestamp / DAY_MILLIS).or_default();
+                *count += 1;
+                *count <= limit
+            });
+        }
+        let items = posts
+            .into_iter()
+            .map(|post| NewsFeedItem {
+                post_id: post.id,
+                timestamp: post.timestamp,
+            })
+            .collect();
+        let added = self.cache.backfill_news_feed(&job.follower_id, items);
+        if added > 0 {
+            debug!(
+                "Backfilled {} posts by {} into the feed of {}",
+                added, job.followed_id, job.follower_id
+            );
+        }
+    }
+
+    // Prometheus text exposition of repeated fanout steps
+    pub fn metrics(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP news_feed_fanout_repeated_steps_total Fanout steps skipped because an earlier run for the same post already did them.");
+        let _ = writeln!(out, "# TYPE news_feed_fanout_repeated_steps_total counter");
+        for step in Step::ALL {
+            let _ = writeln!(
+                out,
+                "news_feed_fanout_repeated_steps_total{{step=\"{}\"}} {}",
+                step.as_str(),
+                self.repeated(step).load(Ordering::Relaxed)
+            );
+        }
+        let _ = writeln!(out, "# HELP news_feed_fanout_followers_total Followers handled by fanouts run on this node, by outcome.");
+        let _ = writeln!(out, "# TYPE news_feed_fanout_followers_total counter");
+        for (outcome, name) in [
+            (Outcome::Delivered, "delivered"),
+            (Outcome::Skipped, "skipped"),
+            (Outcome::Throttled, "throttled"),
+        ] {
+            let _ = writeln!(
+                out,
+                "news_feed_fanout_followers_total{{outcome=\"{}\"}} {}",
+                name,
+                self.outcomes[outcome as usize].load(Ordering::Relaxed)
+            );
+        }
+        let _ = writeln!(out, "# HELP news_feed_fanout_delivery_latency_seconds Time from publishing a post to its insert into a follower's feed.");
+        let _ = writeln!(out, "# TYPE news_feed_fanout_delivery_latency_seconds histogram");
+        self.latency
+            .lock()
+            .expect("latency histogram poisoned")
+            .render(&mut out, "news_feed_fanout_delivery_latency_seconds", "");
+        out
+    }
+}
+
+// Batches from other nodes, for feeds this node owns
+impl LocalDelivery for FanoutWorker {
+    fn deliver_batch(&self, request: DeliverFeedItemsRequest) -> BoxFuture<'_, Result<DeliverFeedItemsResponse, String>> {
+        Box::pin(async move {
+            let post_id = PostId::new(&request.post_id);
+            if self.cache.get_post(&post_id).is_none() && !request.post_json.is_empty() {
+                let post: Post = serde_json::from_slice(&request.post_json).map_err(|e| format!("post_json: {}", e))?;
+                self.cache.set_post(post);
+            }
+            let (owned, misrouted): (Vec<FeedTarget>, Vec<FeedTarget>) = request
+                .targets
+                .into_iter()
+                .partition(|target| self.partitions.is_local(&UserId::new(&target.user_id)));
+            let item = NewsFeedItem {
+                post_id,
+                timestamp: request.published_at,
+            };
+            let author_id = UserId::new(&request.author_id);
+            let counts = self.deliver_local(&author_id, &item, &owned).await;
+            debug!(
+                "Delivered batch {} from {}: {} feeds, {} skipped, {} throttled, {} repeated",
+                request.batch_id,
+                request.origin_node,
+                counts.delivered,
+                counts.skipped,
+                counts.throttled,
+                counts.repeated
+            );
+            Ok(DeliverFeedItemsResponse {
+                batch_id: request.batch_id,
+                delivered: counts.delivered,
+                skipped: counts.skipped,
+                throttled: counts.throttled,
+                repeated: counts.repeated,
+                misrouted: misrouted.into_iter().map(|target| target.user_id).collect(),
+            })
+        })
+    }
+}
+
+pub struct FanoutService {
+    pub cache: Arc<dyn Cache>,
+    pub jobs: Arc<JobQueue>,
+    pub pull_followers: usize, // followers at which posts are pulled instead; 0 never
+    pub backfill_posts: usize, // recent posts a new follower gets; 0 none
+}
+
+impl FanoutService {
+    pub fn new(cache: Arc<dyn Cache>, jobs: Arc<JobQueue>, pull_followers: usize, backfill_posts: usize) -> Self {
+        Self {
+            cache,
+            jobs,
+            pull_followers,
+            backfill_posts,
+        }
+    }
+
+    pub fn is_pull_author(&self, follower_count: usize) -> bool {
+        self.pull_followers > 0 && follower_count >= self.pull_followers
+    }
+
+    // Restored posts aren't fanned out, so authors read at request time
+    // are found again from their followings after a restart
+    pub fn restore_pull(&self, user_id: &UserId) {
+        if self.is_pull_author(self.cache.graph().follower_count(user_id)) {
+            self.cache.mark_pull_author(user_id);
+        }
+    }
+
+    // A new follower gets the account's recent posts in the background
+    pub fn backfill(&self, follower_id: &UserId, followed_id: &UserId) {
+        if self.backfill_posts == 0 {
+            return;
+        }
+        self.jobs.enqueue(&FollowBackfill {
+            follower_id: follower_id.clone(),
+            followed_id: followed_id.clone(),
+            posts: self.backfill_posts,
+        });
+    }
+
+    // `notify` is off when replaying history: those bells already rang
+    pub fn fanout_post(&self, post_id: &PostId, user_id: &UserId, at: u64, notify: bool) {
+        debug!("Starting fanout for post {}", post_id);
+
+        let followers = self.cache.followers(user_id);
+
+        if followers.is_empty() {
+            debug!("No followers found for user {}", user_id);
+            return;
+        }
+
+        let notify_ids: Vec<UserId> = followers
+            .iter()
+            .filter(|(_, edge)| notify && edge.notify)
+            .map(|(follower_id, _)| follower_id.clone())
+            .collect();
+
+        // Too many followers to write to: their feeds merge the post in when
+        // read, and only the bells are delivered
+        if self.is_pull_author(followers.len()) {
+            debug!("Pulling post {} into {} feeds at read time", post_id, followers.len());
+            self.cache.mark_pull_author(user_id);
+            if notify_ids.is_empty() {
+                return;
+            }
+            self.jobs.enqueue(&FanoutMessage {
+                post_id: post_id.clone(),
+                user_id: user_id.clone(),
+                at,
+                friend_ids: Vec::new(),
+                notify_ids,
+                daily_limits: HashMap::new(),
+                post: self.cache.get_post(post_id),
+            });
+            return;
+        }
+
+        let daily_limits = followers
+            .iter()
+            .filter_map(|(follower_id, edge)| Some((follower_id.clone(), edge.daily_limit?)))
+            .collect();
+        let message = FanoutMessage {
+            post_id: post_id.clone(),
+            user_id: user_id.clone(),
+            at,
+            friend_ids: followers.into_iter().map(|(follower_id, _)| follower_id).collect(),
+            notify_ids,
+            daily_limits,
+            post: self.cache.get_post(post_id),
+        };
+
+        self.jobs.enqueue(&message);
+    }
+}
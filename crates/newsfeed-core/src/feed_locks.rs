@@ -0,0 +1,44 @@
+use dashmap::DashMap;
+use std::sync::Arc;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+use crate::ids::UserId;
+
+// One async mutex per user whose feed is being changed. Writers to the same
+// feed queue up behind each other; writers to different feeds never wait.
+#[derive(Debug, Default)]
+pub struct FeedLocks {
+    locks: DashMap<UserId, Arc<Mutex<()>>>,
+}
+
+impl FeedLocks {
+    pub async fn lock(&self, user_id: &UserId) -> FeedGuard<'_> {
+        let lock = self.locks.entry(user_id.clone()).or_default().clone();
+        // Built before waiting so a caller cancelled mid-wait still cleans up
+        let mut feed_guard = FeedGuard {
+            locks: self,
+            user_id: user_id.clone(),
+            guard: None,
+        };
+        feed_guard.guard = Some(lock.lock_owned().await);
+        feed_guard
+    }
+}
+
+// Holds a user's feed lock; the entry is dropped with the last holder or waiter
+pub struct FeedGuard<'a> {
+    locks: &'a FeedLocks,
+    user_id: UserId,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl Drop for FeedGuard<'_> {
+    fn drop(&mut self) {
+        drop(self.guard.take());
+        // Waiters hold a clone of the Arc, so only the map's reference left
+        // means nobody else wants this feed
+        self.locks
+            .locks
+            .remove_if(&self.user_id, |_, lock| Arc::strong_count(lock) == 1);
+    }
+}
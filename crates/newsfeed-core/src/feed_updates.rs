@@ -0,0 +1,41 @@
+use dashmap::DashMap;
+use tokio::sync::broadcast;
+
+use crate::ids::{PostId, UserId};
+
+const CHANNEL_CAPACITY: usize = 16;
+
+// Per-user broadcast channels announcing post IDs as they land in a feed.
+// A channel exists only while someone is waiting on it.
+#[derive(Debug, Default)]
+pub struct FeedUpdates {
+    channels: DashMap<UserId, broadcast::Sender<PostId>>,
+}
+
+impl FeedUpdates {
+    pub fn subscribe(&self, user_id: &UserId) -> broadcast::Receiver<PostId> {
+        self.channels
+            .entry(user_id.clone())
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+
+    pub fn publish(&self, user_id: &UserId, post_id: &PostId) {
+        let delivered = match self.channels.get(user_id) {
+            Some(sender) => sender.send(post_id.clone()).is_ok(),
+            None => return,
+        };
+        // A waiter went away without releasing (timed out or disconnected)
+        if !delivered {
+            self.channels
+                .remove_if(user_id, |_, sender| sender.receiver_count() == 0);
+        }
+    }
+
+    // Drops the user's channel once its last receiver is gone
+    pub fn release(&self, user_id: &UserId, receiver: broadcast::Receiver<PostId>) {
+        drop(receiver);
+        self.channels
+            .remove_if(user_id, |_, sender| sender.receiver_count() == 0);
+    }
+}
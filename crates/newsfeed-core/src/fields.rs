@@ -0,0 +1,90 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+const MAX_FIELDS: usize = 50;
+const MAX_DEPTH: usize = 4;
+
+// A `fields=` selection such as "post.id,post.content,author.username",
+// stored as a tree of keys. A key with no children keeps its whole value.
+#[derive(Debug, Default)]
+pub struct FieldSelection {
+    children: BTreeMap<String, FieldSelection>,
+}
+
+impl FieldSelection {
+    // `root` names the object the selection applies to, so "post.id" and
+    // "id" both select a feed item's id. Responses flatten that object into
+    // the top level, which is why the prefix is simply dropped.
+    pub fn parse(spec: &str, root: &str) -> Result<Self, String> {
+        let mut selection = FieldSelection::default();
+        let paths: Vec<&str> = spec
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .collect();
+        if paths.is_empty() {
+            return Err("fields must name at least one field".to_string());
+        }
+        if paths.len() > MAX_FIELDS {
+            return Err(format!("fields may name at most {} fields", MAX_FIELDS));
+        }
+
+        for path in paths {
+            let mut keys: Vec<&str> = path.split('.').collect();
+            if keys.len() > 1 && keys[0] == root {
+                keys.remove(0);
+            }
+            if keys.len() > MAX_DEPTH || keys.iter().any(|key| key.is_empty()) {
+                return Err(format!("Invalid field '{}'", path));
+            }
+            let mut node = &mut selection;
+            for key in keys {
+                node = node.children.entry(key.to_string()).or_default();
+            }
+        }
+        Ok(selection)
+    }
+
+    // Prunes a serialized value in place; arrays apply the selection to each element
+    pub fn apply(&self, value: &mut Value) {
+        if self.children.is_empty() {
+            return;
+        }
+        match value {
+            Value::Object(map) => {
+                let mut kept = Map::new();
+                for (key, child) in &self.children {
+                    if let Some(mut field) = map.remove(key) {
+                        child.apply(&mut field);
+                        kept.insert(key.clone(), field);
+                    }
+                }
+                *map = kept;
+            }
+            Value::Array(items) => {
+                for item in items {
+                    self.apply(item);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Serializes `value`, pruning the member named `key` (or the whole value when
+// `key` is None) to the selection. Without a selection the value is untouched.
+pub fn project<T: Serialize>(value: &T, key: Option<&str>, selection: Option<&FieldSelection>) -> Value {
+    let mut serialized = serde_json::to_value(value).unwrap_or(Value::Null);
+    if let Some(selection) = selection {
+        match key {
+            Some(key) => {
+                if let Some(member) = serialized.get_mut(key) {
+                    selection.apply(member);
+                }
+            }
+            None => selection.apply(&mut serialized),
+        }
+    }
+    serialized
+}
@@ -0,0 +1,258 @@
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::sync::broadcast;
+
+use crate::ids::UserId;
+
+// One follow relationship, as seen from either end
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FollowEdge {
+    pub followed_at: u64,
+    pub notify: bool,       // "notify me of every post" bell
+    pub close_friend: bool, // set by the followed account
+    // Most of the followed account's posts fanned out to the follower per UTC
+    // day; the rest stay on the author's profile timeline
+    pub daily_limit: Option<u16>,
+}
+
+impl FollowEdge {
+    fn new(followed_at: u64) -> Self {
+        Self {
+            followed_at,
+            notify: false,
+            close_friend: false,
+            daily_limit: None,
+        }
+    }
+}
+
+type Adjacency = DashMap<UserId, HashMap<UserId, FollowEdge>>;
+
+const EVENT_CAPACITY: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GraphEventKind {
+    Follow,
+    Unfollow,
+}
+
+// A change to the graph, published to anyone watching follow patterns
+#[derive(Debug, Clone)]
+pub struct GraphEvent {
+    pub kind: GraphEventKind,
+    pub follower_id: UserId,
+    pub followed_id: UserId,
+    pub at: u64,
+}
+
+// Follow edges indexed both ways, so "who follows X" and "whom does X follow"
+// are single lookups that come with the edge metadata. Every edge is stored
+// once per direction and both copies are written together.
+#[derive(Debug)]
+pub struct SocialGraph {
+    followers: Adjacency, // followed -> follower -> edge
+    following: Adjacency, // follower -> followed -> edge
+    events: broadcast::Sender<GraphEvent>,
+}
+
+impl Default for SocialGraph {
+    fn default() -> Self {
+        Self {
+            followers: Adjacency::default(),
+            following: Adjacency::default(),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+}
+
+impl SocialGraph {
+    // Returns false if the edge already existed; its metadata is kept
+    pub fn follow(&self, follower_id: &UserId, followed_id: &UserId, at: u64) -> bool {
+        let added = self.insert_edge(follower_id, followed_id, at);
+        if added {
+            self.publish(GraphEventKind::Follow, follower_id, followed_id, at);
+        }
+        added
+    }
+
+    // Returns false if there was no edge to remove
+    pub fn unfollow(&self, follower_id: &UserId, followed_id: &UserId, at: u64) -> bool {
+        let removed = self.remove_edge(follower_id, followed_id);
+        if removed {
+            self.publish(GraphEventKind::Unfollow, follower_id, followed_id, at);
+        }
+        removed
+    }
+
+    // Rebuilding the graph from history changes edges without announcing
+    // them, so watchers don't see old follows as new ones
+    pub fn restore_follow(&self, follower_id: &UserId, followed_id: &UserId, at: u64) {
+        self.insert_edge(follower_id, followed_id, at);
+    }
+
+    pub fn restore_unfollow(&self, follower_id: &UserId, followed_id: &UserId) {
+        self.remove_edge(follower_id, followed_id);
+    }
+
+    // Puts back an edge read from storage, settings included
+    pub fn restore_edge(&self, follower_id: &UserId, followed_id: &UserId, edge: FollowEdge) {
+        self.following
+            .entry(follower_id.clone())
+            .or_default()
+            .insert(followed_id.clone(), edge.clone());
+        self.followers
+            .entry(followed_id.clone())
+            .or_default()
+            .insert(follower_id.clone(), edge);
+    }
+
+    fn insert_edge(&self, follower_id: &UserId, followed_id: &UserId, at: u64) -> bool {
+        let mut following = self.following.entry(follower_id.clone()).or_default();
+        if following.contains_key(followed_id) {
+            return false;
+        }
+        let edge = FollowEdge::new(at);
+        following.insert(followed_id.clone(), edge.clone());
+        self.followers
+            .entry(followed_id.clone())
+            .or_default()
+            .insert(follower_id.clone(), edge);
+        true
+    }
+
+    fn remove_edge(&self, follower_id: &UserId, followed_id: &UserId) -> bool {
+        let Some(mut following) = self.following.get_mut(follower_id) else {
+            return false;
+        };
+        if following.remove(followed_id).is_none() {
+            return false;
+        }
+        if let Some(mut followers) = self.followers.get_mut(followed_id) {
+            followers.remove(follower_id);
+        }
+        true
+    }
+
+    // Events are dropped while nobody subscribes; a slow subscriber sees a lag
+    pub fn subscribe(&self) -> broadcast::Receiver<GraphEvent> {
+        self.events.subscribe()
+    }
+
+    fn publish(&self, kind: GraphEventKind, follower_id: &UserId, followed_id: &UserId, at: u64) {
+        let _ = self.events.send(GraphEvent {
+            kind,
+            follower_id: follower_id.clone(),
+            followed_id: followed_id.clone(),
+            at,
+        });
+    }
+
+    pub fn is_following(&self, follower_id: &UserId, followed_id: &UserId) -> bool {
+        self.following
+            .get(follower_id)
+            .is_some_and(|following| following.contains_key(followed_id))
+    }
+
+    pub fn edge(&self, follower_id: &UserId, followed_id: &UserId) -> Option<FollowEdge> {
+        self.following
+            .get(follower_id)
+            .and_then(|following| following.get(followed_id).cloned())
+    }
+
+    // Turns the follower's bell for an account on or off; false if not following
+    pub fn set_notify(&self, follower_id: &UserId, followed_id: &UserId, enabled: bool) -> bool {
+        self.update_edge(follower_id, followed_id, |edge| edge.notify = enabled)
+    }
+
+    // Caps (or with None uncaps) the account's posts in the follower's feed;
+    // false if not following
+    pub fn set_daily_limit(&self, follower_id: &UserId, followed_id: &UserId, limit: Option<u16>) -> bool {
+        self.update_edge(follower_id, followed_id, |edge| edge.daily_limit = limit)
+    }
+
+    pub fn followers(&self, user_id: &UserId) -> Vec<(UserId, FollowEdge)> {
+        Self::edges(&self.followers, user_id)
+    }
+
+    pub fn following(&self, user_id: &UserId) -> Vec<(UserId, FollowEdge)> {
+        Self::edges(&self.following, user_id)
+    }
+
+    pub fn follower_count(&self, user_id: &UserId) -> usize {
+        self.followers.get(user_id).map(|edges| edges.len()).unwrap_or(0)
+    }
+
+    pub fn following_count(&self, user_id: &UserId) -> usize {
+        self.following.get(user_id).map(|edges| edges.len()).unwrap_or(0)
+    }
+
+    // Drops every edge without publishing unfollow events
+    pub fn clear(&self) {
+        self.followers.clear();
+        self.following.clear();
+    }
+
+    // Named maps for the memory and shard reports
+    pub fn maps(&self) -> [(&'static str, &Adjacency); 2] {
+        [("followers", &self.followers), ("following", &self.following)]
+    }
+
+    // Applies the change to both copies of an existing edge
+    fn update_edge(&self, follower_id: &UserId, followed_id: &UserId, change: impl Fn(&mut FollowEdge)) -> bool {
+        let Some(mut following) = self.following.get_mut(follower_id) else {
+            return false;
+        };
+        let Some(edge) = following.get_mut(followed_id) else {
+            return false;
+        };
+        change(edge);
+        if let Some(mut followers) = self.followers.get_mut(followed_id)
+            && let Some(edge) = followers.get_mut(follower_id)
+        {
+            change(edge);
+        }
+        true
+    }
+
+    fn edges(map: &Adjacency, user_id: &UserId) -> Vec<(UserId, FollowEdge)> {
+        map.get(user_id)
+            .map(|edges| {
+                edges
+                    .iter()
+                    .map(|(other_id, edge)| (other_id.clone(), edge.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Watchers see live follows and unfollows, but not edges put back from
+    // history or storage
+    #[test]
+    fn only_live_changes_are_published() {
+        let graph = SocialGraph::default();
+        let mut events = graph.subscribe();
+        let (ana, bo, cy) = (UserId::new("ana"), UserId::new("bo"), UserId::new("cy"));
+
+        assert!(graph.follow(&ana, &bo, 1));
+        assert!(!graph.follow(&ana, &bo, 2));
+        graph.restore_follow(&cy, &bo, 3);
+        assert!(graph.unfollow(&ana, &bo, 4));
+
+        let followed = events.try_recv().expect("follow published");
+        assert_eq!((followed.kind, followed.at), (GraphEventKind::Follow, 1));
+        let unfollowed = events.try_recv().expect("unfollow published");
+        assert_eq!((unfollowed.kind, unfollowed.at), (GraphEventKind::Unfollow, 4));
+        assert!(events.try_recv().is_err());
+
+        assert!(graph.is_following(&cy, &bo));
+        assert_eq!(graph.follower_count(&bo), 1);
+        assert_eq!(graph.following_count(&ana), 0);
+    }
+}
@@ -0,0 +1,137 @@
+// Server-generated text in the reader's language. Messages are written in
+// English in the code, and a catalog maps each one, placeholders and all, to
+// its translation; a message a catalog doesn't have is sent in English.
+use std::collections::HashMap;
+
+use crate::config::Config;
+
+// The language messages are written in
+const SOURCE_LOCALE: &str = "en";
+
+// Catalogs shipped with the server; NEWS_FEED_LOCALES_DIR adds to them
+const BUILT_IN: &[(&str, &str)] = &[
+    ("de", include_str!("../../../locales/de.json")),
+    ("es", include_str!("../../../locales/es.json")),
+    ("fr", include_str!("../../../locales/fr.json")),
+];
+
+#[derive(Debug)]
+pub struct Catalogs {
+    catalogs: HashMap<String, HashMap<String, String>>, // lowercased tag -> English -> translation
+    default_locale: String,
+}
+
+impl Catalogs {
+    // The built-in catalogs, then each `<tag>.json` in the locales directory.
+    // A file for a built-in tag overrides its entries.
+    pub fn load(config: &Config) -> Result<Self, String> {
+        let mut catalogs = HashMap::new();
+        for (tag, json) in BUILT_IN {
+            let messages = serde_json::from_str(json).map_err(|e| format!("built-in catalog {}: {}", tag, e))?;
+            catalogs.insert(tag.to_string(), messages);
+        }
+        if let Some(dir) = &config.locales_dir {
+            let read_dir = |e: std::io::Error| format!("NEWS_FEED_LOCALES_DIR: {}: {}", dir.display(), e);
+            for entry in std::fs::read_dir(dir).map_err(read_dir)? {
+                let path = entry.map_err(read_dir)?.path();
+                if path.extension().is_none_or(|extension| extension != "json") {
+                    continue;
+                }
+                let Some(tag) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                    continue;
+                };
+                let messages: HashMap<String, String> = std::fs::read_to_string(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()))
+                    .map_err(|e| format!("NEWS_FEED_LOCALES_DIR: {}: {}", path.display(), e))?;
+                catalogs
+                    .entry(tag.to_ascii_lowercase())
+                    .or_insert_with(HashMap::new)
+                    .extend(messages);
+            }
+        }
+        let default_locale = config.default_locale.trim().to_ascii_lowercase();
+        if default_locale != SOURCE_LOCALE && !catalogs.contains_key(&default_locale) {
+            return Err(format!("NEWS_FEED_DEFAULT_LOCALE: no catalog for {}", default_locale));
+        }
+        Ok(Self {
+            catalogs,
+            default_locale,
+        })
+    }
+
+    // Whether there's a catalog for the tag; English has none
+    pub fn has_catalog(&self, locale: &str) -> bool {
+        self.catalogs.contains_key(locale)
+    }
+
+    // Tags text can be sent in, English included
+    pub fn locales(&self) -> Vec<&str> {
+        let mut locales: Vec<&str> = self.catalogs.keys().map(String::as_str).collect();
+        if !self.catalogs.contains_key(SOURCE_LOCALE) {
+            locales.push(SOURCE_LOCALE);
+        }
+        locales.sort_unstable();
+        locales
+    }
+
+    // The tag's own catalog, or its language's: "fr-CH" falls back to "fr"
+    fn supported(&self, tag: &str) -> Option<String> {
+        let tag = tag.trim().to_ascii_lowercase();
+        let language = tag.split(['-', '_']).next().unwrap_or_default().to_string();
+        [tag, language]
+            .into_iter()
+            .find(|tag| tag == SOURCE_LOCALE || self.catalogs.contains_key(tag))
+    }
+
+    // The most preferred supported language in an Accept-Language header.
+    // "*" means the default.
+    fn negotiate(&self, header: &str) -> Option<String> {
+        let mut ranges: Vec<(&str, f32)> = header
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.trim().parse().ok())?;
+                (!tag.is_empty() && quality > 0.0).then_some((tag, quality))
+            })
+            .collect();
+        // Stable, so equal weights keep the header's order
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranges.into_iter().find_map(|(tag, _)| match tag {
+            "*" => Some(self.default_locale.clone()),
+            tag => self.supported(tag),
+        })
+    }
+
+    // What a request is answered in: its Accept-Language, then the user's
+    // language preference, then the deployment's default
+    pub fn pick(&self, accept_language: Option<&str>, preferred: Option<&str>) -> String {
+        accept_language
+            .and_then(|header| self.negotiate(header))
+            .or_else(|| preferred.and_then(|tag| self.supported(tag)))
+            .unwrap_or_else(|| self.default_locale.clone())
+    }
+
+    // The message in `locale`, or as written when there's no translation
+    pub fn text<'a>(&'a self, locale: &str, message: &'a str) -> &'a str {
+        self.translation(locale, message).unwrap_or(message)
+    }
+
+    pub fn translation(&self, locale: &str, message: &str) -> Option<&str> {
+        self.catalogs
+            .get(locale)
+            .and_then(|catalog| catalog.get(message))
+            .map(String::as_str)
+    }
+
+    // Translates, then fills in each `{name}`
+    pub fn format(&self, locale: &str, message: &str, args: &[(&str, &str)]) -> String {
+        args.iter()
+            .fold(self.text(locale, message).to_string(), |text, (name, value)| {
+                text.replace(&format!("{{{}}}", name), value)
+            })
+    }
+}
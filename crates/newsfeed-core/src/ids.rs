@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+
+// Distinct string-backed ID types, so a post ID can't be passed where a user
+// ID is expected. They serialize as plain strings, and `Borrow<str>` lets
+// maps keyed by them be queried with a `&str`.
+macro_rules! string_id {
+    ($($name:ident),* $(,)?) => {$(
+        #[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+        #[serde(transparent)]
+        pub struct $name(String);
+
+        impl $name {
+            pub fn new(id: impl Into<String>) -> Self {
+                Self(id.into())
+            }
+
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl Borrow<str> for $name {
+            fn borrow(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                self.0 == other
+            }
+        }
+
+        // Path parameters parse straight into IDs
+        impl FromStr for $name {
+            type Err = Infallible;
+
+            fn from_str(id: &str) -> Result<Self, Self::Err> {
+                Ok(Self(id.to_string()))
+            }
+        }
+    )*};
+}
+
+string_id! {
+    // e.g. "user1"
+    UserId,
+    // e.g. "post_<uuid>"
+    PostId,
+    // A lowercased hashtag without the '#', e.g. "rust"
+    TagId,
+}
+
+impl TagId {
+    // Normalizes user input such as "#Rust" to the form hashtags are extracted in
+    pub fn from_hashtag(hashtag: &str) -> Self {
+        Self(hashtag.trim_start_matches('#').to_lowercase())
+    }
+}
@@ -0,0 +1,135 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use std::sync::Arc;
+
+use crate::{ImageVariant, warn};
+use crate::ids::UserId;
+use crate::residency::{Region, StorageRouter};
+
+pub const MAX_UPLOAD_BYTES: u64 = 5 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ProfileImageKind {
+    Avatar,
+    Banner,
+}
+
+impl ProfileImageKind {
+    fn name(self) -> &'static str {
+        match self {
+            ProfileImageKind::Avatar => "avatar",
+            ProfileImageKind::Banner => "banner",
+        }
+    }
+
+    // Largest first; the first variant is the canonical URL
+    fn sizes(self) -> &'static [(u32, u32)] {
+        match self {
+            ProfileImageKind::Avatar => &[(400, 400), (128, 128), (48, 48)],
+            ProfileImageKind::Banner => &[(1500, 500), (600, 200)],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    Unsupported,
+    Storage(String),
+}
+
+// Crops uploads to the target aspect ratio and renders fixed-size JPEG
+// variants, stored in the backend of the account's region
+pub struct ImagePipeline {
+    storage: Arc<StorageRouter>,
+}
+
+impl ImagePipeline {
+    pub fn new(storage: Arc<StorageRouter>) -> Self {
+        Self { storage }
+    }
+
+    pub async fn process_profile_image(
+        &self,
+        user_id: &UserId,
+        region: &Region,
+        kind: ProfileImageKind,
+        upload: Vec<u8>,
+    ) -> Result<Vec<ImageVariant>, ImageError> {
+        let root = self
+            .storage
+            .write_root(region)
+            .map_err(|_| ImageError::Storage(format!("no storage backend for region {}", region)))?;
+        let user_dir = root.join("profiles").join(user_id.as_str());
+        let prefix = format!("{}_{}", kind.name(), uuid::Uuid::new_v4().simple());
+        let url_base = format!("/profiles/{}", user_id);
+
+        // Decoding and resampling are CPU bound
+        tokio::task::spawn_blocking(move || {
+            let format = image::guess_format(&upload).map_err(|_| ImageError::Unsupported)?;
+            if !matches!(
+                format,
+                ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP | ImageFormat::Gif
+            ) {
+                return Err(ImageError::Unsupported);
+            }
+            let source = image::load_from_memory_with_format(&upload, format)
+                .map_err(|_| ImageError::Unsupported)?;
+
+            std::fs::create_dir_all(&user_dir)
+                .map_err(|e| ImageError::Storage(format!("{}: {}", user_dir.display(), e)))?;
+
+            let (aspect_w, aspect_h) = kind.sizes()[0];
+            let cropped = center_crop(&source, aspect_w, aspect_h);
+
+            kind.sizes()
+                .iter()
+                .map(|&(width, height)| {
+                    let file_name = format!("{}_{}x{}.jpg", prefix, width, height);
+                    let path = user_dir.join(&file_name);
+                    cropped
+                        .resize_exact(width, height, FilterType::Lanczos3)
+                        .to_rgb8()
+                        .save_with_format(&path, ImageFormat::Jpeg)
+                        .map_err(|e| ImageError::Storage(format!("{}: {}", path.display(), e)))?;
+                    Ok(ImageVariant {
+                        width,
+                        height,
+                        url: format!("{}/{}", url_base, file_name),
+                    })
+                })
+                .collect()
+        })
+        .await
+        .map_err(|e| ImageError::Storage(e.to_string()))?
+    }
+
+    // Best effort: stale variants are only wasted disk space
+    pub async fn remove_variants(&self, region: &Region, variants: &[ImageVariant]) {
+        let Ok(root) = self.storage.write_root(region) else {
+            return;
+        };
+        for variant in variants {
+            if let Some(relative) = variant.url.strip_prefix("/profiles/") {
+                let path = root.join("profiles").join(relative);
+                if let Err(e) = tokio::fs::remove_file(&path).await {
+                    warn!("Failed to remove {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+}
+
+fn center_crop(source: &DynamicImage, aspect_w: u32, aspect_h: u32) -> DynamicImage {
+    let (width, height) = (source.width() as u64, source.height() as u64);
+    let (aspect_w, aspect_h) = (aspect_w as u64, aspect_h as u64);
+
+    let (crop_w, crop_h) = if width * aspect_h > height * aspect_w {
+        (height * aspect_w / aspect_h, height)
+    } else {
+        (width, width * aspect_h / aspect_w)
+    };
+    let x = (width - crop_w) / 2;
+    let y = (height - crop_h) / 2;
+
+    source.crop_imm(x as u32, y as u32, crop_w.max(1) as u32, crop_h.max(1) as u32)
+}
@@ -0,0 +1,166 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::Post;
+use crate::content::extract_hashtags;
+use crate::ids::{TagId, UserId};
+
+// An engagement counts for half as much after this long
+const HALF_LIFE_MILLIS: f64 = 14.0 * 24.0 * 60.0 * 60.0 * 1000.0;
+
+// Topics and authors kept per user; past this the weakest is dropped
+const MAX_TOPICS: usize = 100;
+const MAX_AUTHORS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Engagement {
+    Like,
+    Reply,
+    ProfileVisit,
+    Dwell, // stopped on the post in the feed, per client telemetry
+    Click, // opened the post from the feed, per client telemetry
+}
+
+impl Engagement {
+    // What one engagement adds to each of the post's hashtags and to its
+    // author. A profile visit or a long look is a weak hint next to a like.
+    fn weight(self) -> f64 {
+        match self {
+            Engagement::Like => 1.0,
+            Engagement::Reply => 2.0,
+            Engagement::ProfileVisit => 0.25,
+            Engagement::Dwell => 0.25,
+            Engagement::Click => 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Interest {
+    weight: f64, // as of updated_at
+    updated_at: u64,
+}
+
+impl Interest {
+    fn at(&self, now: u64) -> f64 {
+        let age = now.saturating_sub(self.updated_at) as f64;
+        self.weight * 0.5f64.powf(age / HALF_LIFE_MILLIS)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TopicWeight {
+    pub topic: TagId,
+    pub weight: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthorWeight {
+    pub user_id: UserId,
+    pub weight: f64,
+}
+
+// A user's interest in each hashtag and each author, from the posts they
+// liked and replied to and the profiles they visited. Weights are stored as
+// of their last change and decayed when read, so interests the user stopped
+// engaging with fade without any upkeep.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Interests {
+    topics: HashMap<TagId, Interest>,
+    authors: HashMap<UserId, Interest>,
+}
+
+// Times are the engagements' own, so replaying history rebuilds the same
+// weights
+fn add<K: Clone + Eq + Hash>(map: &mut HashMap<K, Interest>, key: &K, engagement: Engagement, at: u64, max: usize) {
+    let interest = map.entry(key.clone()).or_insert(Interest {
+        weight: 0.0,
+        updated_at: at,
+    });
+    let at = at.max(interest.updated_at);
+    interest.weight = interest.at(at) + engagement.weight();
+    interest.updated_at = at;
+    while map.len() > max {
+        let Some(weakest) = map
+            .iter()
+            .min_by(|a, b| a.1.at(at).total_cmp(&b.1.at(at)))
+            .map(|(key, _)| key.clone())
+        else {
+            break;
+        };
+        map.remove(&weakest);
+    }
+}
+
+// Every key's weight at `now`, strongest first
+fn ranked<K: Clone + Ord>(map: &HashMap<K, Interest>, now: u64) -> Vec<(K, f64)> {
+    let mut weights: Vec<(K, f64)> = map.iter().map(|(key, interest)| (key.clone(), interest.at(now))).collect();
+    weights.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    weights
+}
+
+// The strongest of these keys' weights over the strongest overall, from 0 to 1
+fn affinity<'a, K: Eq + Hash + 'a>(map: &HashMap<K, Interest>, keys: impl IntoIterator<Item = &'a K>, now: u64) -> f64 {
+    let strongest = map.values().map(|interest| interest.at(now)).fold(0.0, f64::max);
+    if strongest <= 0.0 {
+        return 0.0;
+    }
+    let matched = keys
+        .into_iter()
+        .filter_map(|key| map.get(key))
+        .map(|interest| interest.at(now))
+        .fold(0.0, f64::max);
+    matched / strongest
+}
+
+impl Interests {
+    pub fn record_topics(&mut self, topics: &[TagId], engagement: Engagement, at: u64) {
+        for topic in topics {
+            add(&mut self.topics, topic, engagement, at, MAX_TOPICS);
+        }
+    }
+
+    pub fn record_author(&mut self, author_id: &UserId, engagement: Engagement, at: u64) {
+        add(&mut self.authors, author_id, engagement, at, MAX_AUTHORS);
+    }
+
+    // Engaging with a post counts for its hashtags and its author. Engaging
+    // with one's own posts says nothing new about one's interests.
+    pub fn record_post(&mut self, user_id: &UserId, post: &Post, engagement: Engagement, at: u64) {
+        if &post.user_id == user_id {
+            return;
+        }
+        self.record_topics(&extract_hashtags(&post.content), engagement, at);
+        self.record_author(&post.user_id, engagement, at);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.topics.is_empty() && self.authors.is_empty()
+    }
+
+    pub fn topic_weights(&self, now: u64) -> Vec<TopicWeight> {
+        ranked(&self.topics, now)
+            .into_iter()
+            .map(|(topic, weight)| TopicWeight { topic, weight })
+            .collect()
+    }
+
+    pub fn author_weights(&self, now: u64) -> Vec<AuthorWeight> {
+        ranked(&self.authors, now)
+            .into_iter()
+            .map(|(user_id, weight)| AuthorWeight { user_id, weight })
+            .collect()
+    }
+
+    // How well a post with these hashtags matches, from 0 to 1: its
+    // strongest topic's weight over the user's strongest overall
+    pub fn topic_affinity(&self, topics: &[TagId], now: u64) -> f64 {
+        affinity(&self.topics, topics, now)
+    }
+
+    // The same for a post's author, against the user's strongest author
+    pub fn author_affinity(&self, author_id: &UserId, now: u64) -> f64 {
+        affinity(&self.authors, [author_id], now)
+    }
+}
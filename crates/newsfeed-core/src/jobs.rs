@@ -0,0 +1,848 @@
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+
+use crate::broker::{Broker, Delivery};
+use crate::{error, now_millis, warn};
+use crate::receipts::LatencyHistogram;
+use crate::timezones::{self, civil_from_days};
+
+// Failed jobs kept for inspection and retry
+const MAX_FAILED: usize = 200;
+
+// How long a read from the shared queue waits for jobs before polling again
+const FETCH_WAIT: Duration = Duration::from_secs(5);
+
+// A kind of background work. Payloads are stored as JSON, so queued jobs can
+// be written to disk and picked up again after a restart.
+pub trait Job: Serialize + DeserializeOwned + Send + 'static {
+    const KIND: &'static str;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct JobPolicy {
+    pub concurrency: usize, // jobs of this kind running at once
+    pub max_attempts: u32,
+    pub backoff: Duration, // before the first retry; doubles after each
+    pub shared: bool,      // run from the shared queue, when there is one
+    // Jobs of this kind waiting here at most; past it, new ones go straight
+    // to failed. Retries of admitted jobs don't count against it.
+    pub max_queued: Option<usize>,
+}
+
+impl Default for JobPolicy {
+    fn default() -> Self {
+        Self {
+            concurrency: 1,
+            max_attempts: 3,
+            backoff: Duration::from_secs(1),
+            shared: false,
+            max_queued: None,
+        }
+    }
+}
+
+impl JobPolicy {
+    // The wait before retrying a job that has failed `attempts` times
+    fn backoff_after(&self, attempts: u32) -> Duration {
+        self.backoff.saturating_mul(1 << attempts.saturating_sub(1).min(16))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub id: u64,
+    pub kind: String,
+    pub payload: serde_json::Value,
+    pub run_at: u64,
+    pub attempts: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+// When a recurring job runs: at a fixed interval, or on a cron expression
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    Every(Duration),
+    Cron(Cron),
+}
+
+impl Schedule {
+    fn next_after(&self, millis: u64) -> u64 {
+        match self {
+            Self::Every(interval) => millis + (interval.as_millis() as u64).max(1),
+            // An expression that never matches (e.g. Feb 30) just never runs
+            Self::Cron(cron) => cron.next_after(millis).unwrap_or(u64::MAX),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::Every(interval) => format!("every {}s", interval.as_secs()),
+            Self::Cron(cron) => cron.expression.clone(),
+        }
+    }
+}
+
+// Standard five-field cron, evaluated in UTC: minute, hour, day of month,
+// month, day of week (0 or 7 is Sunday). Fields take `*`, numbers, ranges
+// (`1-5`), steps (`*/15`, `0-30/10`), and comma-separated lists.
+#[derive(Debug, Clone)]
+pub struct Cron {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!("expected 5 fields, got {}", fields.len()));
+        };
+        let mut weekdays = parse_field(weekday, 0, 7)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            expression: fields.join(" "),
+            minutes: parse_field(minute, 0, 59)?,
+            hours: parse_field(hour, 0, 23)?,
+            days: parse_field(day, 1, 31)?,
+            months: parse_field(month, 1, 12)?,
+            weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+
+    // The first matching minute after `millis`, looking at most a year ahead
+    fn next_after(&self, millis: u64) -> Option<u64> {
+        let first = millis / 60_000 + 1;
+        (first..first + 366 * 24 * 60)
+            .find(|&minute| self.matches(minute))
+            .map(|minute| minute * 60_000)
+    }
+
+    fn matches(&self, minute: u64) -> bool {
+        let days = minute / (24 * 60);
+        let (_, month, day) = civil_from_days(days as i64);
+        let weekday = timezones::weekday(days as i64);
+        let day_matches = self.days & (1 << day) != 0;
+        let weekday_matches = self.weekdays & (1 << weekday) != 0;
+        // Like cron: when both day fields are restricted, either may match
+        let date_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => day_matches || weekday_matches,
+            _ => day_matches && weekday_matches,
+        };
+        self.minutes & (1 << (minute % 60)) != 0
+            && self.hours & (1 << (minute / 60 % 24)) != 0
+            && self.months & (1 << month) != 0
+            && date_matches
+    }
+}
+
+fn parse_field(field: &str, min: u64, max: u64) -> Result<u64, String> {
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u64>().map_err(|_| format!("bad step in {}", part))?),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (parse_value(start, part)?, parse_value(end, part)?)
+        } else {
+            let value = parse_value(range, part)?;
+            // `5/10` means from 5 to the end, every 10
+            (value, if part.contains('/') { max } else { value })
+        };
+        if step == 0 || start < min || end > max || start > end {
+            return Err(format!("{} is out of range {}-{}", part, min, max));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+fn parse_value(value: &str, part: &str) -> Result<u64, String> {
+    value.parse().map_err(|_| format!("bad value in {}", part))
+}
+
+type Handler = Arc<dyn Fn(serde_json::Value) -> BoxFuture<'static, Result<(), String>> + Send + Sync>;
+
+struct Kind {
+    handler: Handler,
+    policy: JobPolicy,
+    permits: Arc<Semaphore>,
+    completed: AtomicU64,
+    failed: AtomicU64,
+    rejected: AtomicU64, // turned away by a full queue
+    durations: Mutex<LatencyHistogram>, // time each attempt took, failed ones too
+}
+
+impl Kind {
+    // Runs one attempt, timing it
+    async fn attempt(&self, future: BoxFuture<'static, Result<(), String>>) -> Result<(), String> {
+        let started = Instant::now();
+        let outcome = settle(future).await;
+        self.durations
+            .lock()
+            .expect("job durations poisoned")
+            .record(started.elapsed().as_millis() as u64, 1);
+        outcome
+    }
+}
+
+struct Recurring {
+    kind: &'static str,
+    payload: serde_json::Value,
+    schedule: Schedule,
+    next_run: u64,
+}
+
+#[derive(Default)]
+struct Jobs {
+    queued: BTreeMap<(u64, u64), JobRecord>, // (run_at, id)
+    queued_per_kind: HashMap<String, usize>,
+    running: HashMap<u64, JobRecord>,
+    failed: VecDeque<JobRecord>, // newest first
+    recurring: Vec<Recurring>,
+}
+
+impl Jobs {
+    fn push(&mut self, record: JobRecord) {
+        *self.queued_per_kind.entry(record.kind.clone()).or_default() += 1;
+        self.queued.insert((record.run_at, record.id), record);
+    }
+
+    fn take(&mut self, key: &(u64, u64)) -> Option<JobRecord> {
+        let record = self.queued.remove(key)?;
+        if let Some(count) = self.queued_per_kind.get_mut(&record.kind) {
+            *count = count.saturating_sub(1);
+        }
+        Some(record)
+    }
+
+    fn queued_count(&self, kind: &str) -> usize {
+        self.queued_per_kind.get(kind).copied().unwrap_or(0)
+    }
+
+    fn fail(&mut self, record: JobRecord) {
+        self.failed.push_front(record);
+        self.failed.truncate(MAX_FAILED);
+    }
+}
+
+// What's written to the jobs file. Jobs that were running when the process
+// stopped are saved as queued and run again: delivery is at least once.
+#[derive(Default, Serialize, Deserialize)]
+struct Persisted {
+    queued: Vec<JobRecord>,
+    failed: Vec<JobRecord>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScheduleReport {
+    kind: &'static str,
+    schedule: String,
+    next_run: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobsReport {
+    queue: &'static str,
+    running: Vec<JobRecord>,
+    queued: Vec<JobRecord>,
+    failed: Vec<JobRecord>,
+    schedules: Vec<ScheduleReport>,
+}
+
+// What happened to jobs sent through the shared queue
+#[derive(Default)]
+struct SharedCounters {
+    published: AtomicU64,
+    publish_failed: AtomicU64, // queued on this instance instead
+    acked: AtomicU64,
+    redelivered: AtomicU64, // handed back after a failed attempt
+}
+
+// Runs typed background jobs: queued now or for later, on a schedule, with
+// retries and a concurrency limit per kind. With a store path, queued and
+// failed jobs survive restarts. With a broker attached, jobs of shared kinds
+// go through a queue every instance reads from instead.
+pub struct JobQueue {
+    kinds: RwLock<HashMap<&'static str, Arc<Kind>>>,
+    jobs: Mutex<Jobs>,
+    next_id: AtomicU64,
+    wake: Notify,
+    dirty: AtomicBool,
+    store: Option<PathBuf>,
+    broker: OnceLock<Arc<dyn Broker>>,
+    shared: SharedCounters,
+}
+
+impl JobQueue {
+    pub fn new(store: Option<PathBuf>) -> Self {
+        Self {
+            kinds: RwLock::new(HashMap::new()),
+            jobs: Mutex::new(Jobs::default()),
+            next_id: AtomicU64::new(1),
+            wake: Notify::new(),
+            dirty: AtomicBool::new(false),
+            store,
+            broker: OnceLock::new(),
+            shared: SharedCounters::default(),
+        }
+    }
+
+    // Sends jobs of shared kinds through `broker` from now on. Done at
+    // startup, before the dispatcher starts; jobs queued earlier run here.
+    pub fn attach(&self, broker: Arc<dyn Broker>) {
+        let _ = self.broker.set(broker);
+    }
+
+    // Loads the jobs left in the store by the last run, returning how many
+    // were queued. Done at startup before the dispatcher starts; restored
+    // jobs get new IDs after any queued since. An unreadable file is skipped
+    // rather than stopping startup.
+    pub fn restore(&self) -> std::io::Result<usize> {
+        let Some(path) = &self.store else {
+            return Ok(0);
+        };
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        let persisted: Persisted = match serde_json::from_slice(&bytes) {
+            Ok(persisted) => persisted,
+            Err(e) => {
+                warn!("Ignoring unreadable jobs file: {}", e);
+                return Ok(0);
+            }
+        };
+        let restored = persisted.queued.len();
+        let mut jobs = self.jobs.lock().expect("jobs poisoned");
+        for mut record in persisted.queued {
+            record.id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            record.started_at = None;
+            jobs.push(record);
+        }
+        for mut record in persisted.failed {
+            record.id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            jobs.failed.push_back(record);
+        }
+        Ok(restored)
+    }
+
+    pub fn register<J, F, Fut>(&self, policy: JobPolicy, handler: F)
+    where
+        J: Job,
+        F: Fn(J) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let handler: Handler = Arc::new(move |payload| match serde_json::from_value::<J>(payload) {
+            Ok(job) => handler(job).boxed(),
+            Err(e) => futures::future::ready(Err(format!("Invalid payload: {}", e))).boxed(),
+        });
+        let kind = Kind {
+            handler,
+            policy,
+            permits: Arc::new(Semaphore::new(policy.concurrency.max(1))),
+            completed: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            durations: Mutex::new(LatencyHistogram::default()),
+        };
+        self.kinds
+            .write()
+            .expect("job kinds poisoned")
+            .insert(J::KIND, Arc::new(kind));
+        self.wake.notify_one();
+    }
+
+    pub fn enqueue<J: Job>(self: &Arc<Self>, job: &J) -> u64 {
+        self.enqueue_at(job, now_millis())
+    }
+
+    // Queues a job to run no earlier than `run_at` (Unix millis). Shared
+    // kinds due now are published instead; delayed jobs stay here.
+    pub fn enqueue_at<J: Job>(self: &Arc<Self>, job: &J, run_at: u64) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let record = JobRecord {
+            id,
+            kind: J::KIND.to_string(),
+            payload: serde_json::to_value(job).expect("job payload serializes"),
+            run_at,
+            attempts: 0,
+            started_at: None,
+            last_error: None,
+        };
+        if run_at <= now_millis() && let Some(broker) = self.shared_broker(J::KIND) {
+            self.clone().publish(broker, record);
+        } else {
+            self.admit(record);
+        }
+        id
+    }
+
+    // Queues a new job here, unless its kind's queue is full. Then it goes
+    // to failed, where it can be retried once the queue drains.
+    fn admit(&self, mut record: JobRecord) {
+        let kind = self.kinds.read().expect("job kinds poisoned").get(record.kind.as_str()).cloned();
+        let limit = kind.as_ref().and_then(|kind| kind.policy.max_queued);
+        {
+            let mut jobs = self.jobs.lock().expect("jobs poisoned");
+            match (limit, kind) {
+                (Some(limit), Some(kind)) if jobs.queued_count(&record.kind) >= limit => {
+                    warn!("Queue for {} jobs is full ({}); job {} goes to failed", record.kind, limit, record.id);
+                    kind.rejected.fetch_add(1, Ordering::Relaxed);
+                    record.last_error = Some("Queue full".to_string());
+                    jobs.fail(record);
+                }
+                _ => jobs.push(record),
+            }
+        }
+        self.changed();
+    }
+
+    fn queue_local(&self, record: JobRecord) {
+        self.jobs.lock().expect("jobs poisoned").push(record);
+        self.changed();
+    }
+
+    // Whether new jobs of this kind would be turned away. Kinds going through
+    // the shared queue leave the bound to the broker.
+    pub fn is_full<J: Job>(&self) -> bool {
+        if self.shared_broker(J::KIND).is_some() {
+            return false;
+        }
+        let limit = self
+            .kinds
+            .read()
+            .expect("job kinds poisoned")
+            .get(J::KIND)
+            .and_then(|kind| kind.policy.max_queued);
+        limit.is_some_and(|limit| self.jobs.lock().expect("jobs poisoned").queued_count(J::KIND) >= limit)
+    }
+
+    fn shared_broker(&self, kind: &str) -> Option<Arc<dyn Broker>> {
+        let broker = self.broker.get()?;
+        let kinds = self.kinds.read().expect("job kinds poisoned");
+        kinds.get(kind).filter(|kind| kind.policy.shared).map(|_| broker.clone())
+    }
+
+    // Publishes in the background, so queueing never waits on the network. A
+    // job the broker doesn't take runs here instead.
+    fn publish(self: Arc<Self>, broker: Arc<dyn Broker>, mut record: JobRecord) {
+        tokio::spawn(async move {
+            match broker.publish(&record.kind, &record.payload).await {
+                Ok(()) => {
+                    self.shared.published.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    warn!("Failed to publish job {} ({}) to {}: {}", record.id, record.kind, broker.name(), e);
+                    self.shared.publish_failed.fetch_add(1, Ordering::Relaxed);
+                    record.last_error = Some(format!("publish: {}", e));
+                    self.admit(record);
+                }
+            }
+        });
+    }
+
+    // Queues `job` each time the schedule comes due. A run is skipped while
+    // the previous one is still queued or running.
+    pub fn schedule<J: Job>(&self, schedule: Schedule, job: &J) {
+        let recurring = Recurring {
+            kind: J::KIND,
+            payload: serde_json::to_value(job).expect("job payload serializes"),
+            next_run: schedule.next_after(now_millis()),
+            schedule,
+        };
+        self.jobs.lock().expect("jobs poisoned").recurring.push(recurring);
+        self.wake.notify_one();
+    }
+
+    // Queues a failed job again with a fresh set of attempts
+    pub fn retry(self: &Arc<Self>, id: u64) -> bool {
+        let mut jobs = self.jobs.lock().expect("jobs poisoned");
+        let Some(index) = jobs.failed.iter().position(|record| record.id == id) else {
+            return false;
+        };
+        let mut record = jobs.failed.remove(index).expect("index is in range");
+        drop(jobs);
+        record.attempts = 0;
+        record.run_at = now_millis();
+        match self.shared_broker(&record.kind) {
+            Some(broker) => {
+                self.dirty.store(true, Ordering::Relaxed);
+                self.clone().publish(broker, record);
+            }
+            None => self.queue_local(record),
+        }
+        true
+    }
+
+    pub fn report(&self) -> JobsReport {
+        let jobs = self.jobs.lock().expect("jobs poisoned");
+        let mut running: Vec<JobRecord> = jobs.running.values().cloned().collect();
+        running.sort_by_key(|record| record.started_at);
+        JobsReport {
+            queue: self.broker.get().map(|broker| broker.name()).unwrap_or("local"),
+            running,
+            queued: jobs.queued.values().cloned().collect(),
+            failed: jobs.failed.iter().cloned().collect(),
+            schedules: jobs
+                .recurring
+                .iter()
+                .map(|recurring| ScheduleReport {
+                    kind: recurring.kind,
+                    schedule: recurring.schedule.describe(),
+                    next_run: recurring.next_run,
+                })
+                .collect(),
+        }
+    }
+
+    // Prometheus text exposition, per job kind
+    pub fn metrics(&self) -> String {
+        let kinds = self.kinds.read().expect("job kinds poisoned");
+        let jobs = self.jobs.lock().expect("jobs poisoned");
+        let mut names: Vec<&&str> = kinds.keys().collect();
+        names.sort();
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP news_feed_jobs_queued Background jobs waiting to run.");
+        let _ = writeln!(out, "# TYPE news_feed_jobs_queued gauge");
+        for name in &names {
+            let queued = jobs.queued_count(name);
+            let _ = writeln!(out, "news_feed_jobs_queued{{kind=\"{}\"}} {}", name, queued);
+        }
+        let _ = writeln!(out, "# HELP news_feed_jobs_running Background jobs running now.");
+        let _ = writeln!(out, "# TYPE news_feed_jobs_running gauge");
+        for name in &names {
+            let running = jobs.running.values().filter(|record| record.kind == **name).count();
+            let _ = writeln!(out, "news_feed_jobs_running{{kind=\"{}\"}} {}", name, running);
+        }
+        let _ = writeln!(out, "# HELP news_feed_jobs_completed_total Background jobs that succeeded.");
+        let _ = writeln!(out, "# TYPE news_feed_jobs_completed_total counter");
+        for name in &names {
+            let completed = kinds[**name].completed.load(Ordering::Relaxed);
+            let _ = writeln!(out, "news_feed_jobs_completed_total{{kind=\"{}\"}} {}", name, completed);
+        }
+        let _ = writeln!(out, "# HELP news_feed_jobs_failed_total Background jobs that ran out of attempts.");
+        let _ = writeln!(out, "# TYPE news_feed_jobs_failed_total counter");
+        for name in &names {
+            let failed = kinds[**name].failed.load(Ordering::Relaxed);
+            let _ = writeln!(out, "news_feed_jobs_failed_total{{kind=\"{}\"}} {}", name, failed);
+        }
+        let _ = writeln!(out, "# HELP news_feed_jobs_rejected_total Background jobs sent to failed because their queue was full.");
+        let _ = writeln!(out, "# TYPE news_feed_jobs_rejected_total counter");
+        for name in &names {
+            let rejected = kinds[**name].rejected.load(Ordering::Relaxed);
+            let _ = writeln!(out, "news_feed_jobs_rejected_total{{kind=\"{}\"}} {}", name, rejected);
+        }
+        let _ = writeln!(out, "# HELP news_feed_job_duration_seconds Time each background job attempt took.");
+        let _ = writeln!(out, "# TYPE news_feed_job_duration_seconds histogram");
+        for name in &names {
+            kinds[**name]
+                .durations
+                .lock()
+                .expect("job durations poisoned")
+                .render(&mut out, "news_feed_job_duration_seconds", &format!("kind=\"{}\"", name));
+        }
+        if let Some(broker) = self.broker.get() {
+            let _ = writeln!(out, "# HELP news_feed_jobs_shared_total Jobs sent through the shared queue, by outcome.");
+            let _ = writeln!(out, "# TYPE news_feed_jobs_shared_total counter");
+            for (outcome, counter) in [
+                ("published", &self.shared.published),
+                ("publish_failed", &self.shared.publish_failed),
+                ("acked", &self.shared.acked),
+                ("redelivered", &self.shared.redelivered),
+            ] {
+                let _ = writeln!(
+                    out,
+                    "news_feed_jobs_shared_total{{queue=\"{}\",outcome=\"{}\"}} {}",
+                    broker.name(),
+                    outcome,
+                    counter.load(Ordering::Relaxed)
+                );
+            }
+        }
+        out
+    }
+
+    // Starts the dispatcher. It wakes when a job is queued or finishes, and
+    // otherwise sleeps until the next job or schedule is due. With a broker
+    // attached, a consumer for the shared queue starts too.
+    pub fn spawn(self: &Arc<Self>) {
+        if let Some(broker) = self.broker.get() {
+            tokio::spawn(self.clone().consume(broker.clone()));
+        }
+        let queue = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let next_due = queue.dispatch_due();
+                queue.persist().await;
+                let idle = next_due
+                    .map(|at| Duration::from_millis(at.saturating_sub(now_millis())))
+                    .unwrap_or(Duration::from_secs(60));
+                tokio::select! {
+                    _ = queue.wake.notified() => {}
+                    _ = tokio::time::sleep(idle) => {}
+                }
+            }
+        });
+    }
+
+    // Starts every due job that has a free slot. Returns when the next job
+    // that isn't waiting for a slot comes due.
+    fn dispatch_due(self: &Arc<Self>) -> Option<u64> {
+        let now = now_millis();
+        let kinds = self.kinds.read().expect("job kinds poisoned");
+        let mut jobs = self.jobs.lock().expect("jobs poisoned");
+
+        let mut recurring_due = Vec::new();
+        for recurring in jobs.recurring.iter_mut().filter(|recurring| recurring.next_run <= now) {
+            recurring.next_run = recurring.schedule.next_after(now);
+            recurring_due.push((recurring.kind, recurring.payload.clone()));
+        }
+        for (kind, payload) in recurring_due {
+            let pending = jobs
+                .queued
+                .values()
+                .chain(jobs.running.values())
+                .any(|record| record.kind == kind);
+            if pending {
+                continue;
+            }
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            let record = JobRecord {
+                id,
+                kind: kind.to_string(),
+                payload,
+                run_at: now,
+                attempts: 0,
+                started_at: None,
+                last_error: None,
+            };
+            jobs.push(record);
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+
+        let due: Vec<(u64, u64)> = jobs.queued.range(..=(now, u64::MAX)).map(|(key, _)| *key).collect();
+        for key in due {
+            // Jobs of kinds nobody registered wait until someone does
+            let Some(kind) = kinds.get(jobs.queued[&key].kind.as_str()) else {
+                continue;
+            };
+            let Ok(permit) = kind.permits.clone().try_acquire_owned() else {
+                continue;
+            };
+            let mut record = jobs.take(&key).expect("key was just listed");
+            record.attempts += 1;
+            record.started_at = Some(now);
+            let future = (kind.handler)(record.payload.clone());
+            let id = record.id;
+            jobs.running.insert(id, record);
+            self.dirty.store(true, Ordering::Relaxed);
+            tokio::spawn(self.clone().run(id, kind.clone(), future, permit));
+        }
+
+        let next_queued = jobs.queued.keys().map(|(run_at, _)| *run_at).find(|run_at| *run_at > now);
+        let next_recurring = jobs.recurring.iter().map(|recurring| recurring.next_run).min();
+        next_queued.into_iter().chain(next_recurring).min()
+    }
+
+    async fn run(
+        self: Arc<Self>,
+        id: u64,
+        kind: Arc<Kind>,
+        future: BoxFuture<'static, Result<(), String>>,
+        permit: OwnedSemaphorePermit,
+    ) {
+        let outcome = kind.attempt(future).await;
+        drop(permit);
+
+        let mut jobs = self.jobs.lock().expect("jobs poisoned");
+        if let Some(mut record) = jobs.running.remove(&id) {
+            record.started_at = None;
+            match outcome {
+                Ok(()) => {
+                    kind.completed.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(error) => {
+                    warn!("Job {} ({}) failed on attempt {}: {}", id, record.kind, record.attempts, error);
+                    record.last_error = Some(error);
+                    if record.attempts < kind.policy.max_attempts {
+                        record.run_at = now_millis() + kind.policy.backoff_after(record.attempts).as_millis() as u64;
+                        jobs.push(record);
+                    } else {
+                        kind.failed.fetch_add(1, Ordering::Relaxed);
+                        jobs.fail(record);
+                    }
+                }
+            }
+        }
+        drop(jobs);
+        self.changed();
+    }
+
+    // Takes jobs from the shared queue as slots free up and runs them like
+    // local ones. Each is acknowledged once it's finished for good: it
+    // succeeded or ran out of attempts.
+    async fn consume(self: Arc<Self>, broker: Arc<dyn Broker>) {
+        let mut backoff = Duration::from_secs(1);
+        loop {
+            let free: usize = {
+                let kinds = self.kinds.read().expect("job kinds poisoned");
+                kinds
+                    .values()
+                    .filter(|kind| kind.policy.shared)
+                    .map(|kind| kind.permits.available_permits())
+                    .sum()
+            };
+            if free == 0 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+            let deliveries = match broker.fetch(free, FETCH_WAIT).await {
+                Ok(deliveries) => {
+                    backoff = Duration::from_secs(1);
+                    deliveries
+                }
+                Err(e) => {
+                    warn!("Failed to read the shared job queue ({}): {}", broker.name(), e);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(Duration::from_secs(30));
+                    continue;
+                }
+            };
+            for delivery in deliveries {
+                let kind = self.kinds.read().expect("job kinds poisoned").get(delivery.kind.as_str()).cloned();
+                // Another instance may run kinds this one doesn't
+                let Some(kind) = kind else {
+                    let _ = broker.nack(delivery, Duration::ZERO).await;
+                    continue;
+                };
+                let permit = kind.permits.clone().acquire_owned().await.expect("job permits are never closed");
+                tokio::spawn(self.clone().run_delivered(broker.clone(), kind, delivery, permit));
+            }
+        }
+    }
+
+    async fn run_delivered(
+        self: Arc<Self>,
+        broker: Arc<dyn Broker>,
+        kind: Arc<Kind>,
+        delivery: Delivery,
+        permit: OwnedSemaphorePermit,
+    ) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let now = now_millis();
+        let mut record = JobRecord {
+            id,
+            kind: delivery.kind.clone(),
+            payload: delivery.payload.clone(),
+            run_at: now,
+            attempts: delivery.attempts,
+            started_at: Some(now),
+            last_error: None,
+        };
+        self.jobs.lock().expect("jobs poisoned").running.insert(id, record.clone());
+        let outcome = kind.attempt((kind.handler)(delivery.payload.clone())).await;
+        drop(permit);
+        self.jobs.lock().expect("jobs poisoned").running.remove(&id);
+        record.started_at = None;
+
+        let settled = match outcome {
+            Ok(()) => {
+                kind.completed.fetch_add(1, Ordering::Relaxed);
+                self.shared.acked.fetch_add(1, Ordering::Relaxed);
+                broker.ack(delivery).await
+            }
+            Err(error) if delivery.attempts < kind.policy.max_attempts => {
+                warn!("Job {} ({}) failed on attempt {}: {}", id, record.kind, record.attempts, error);
+                self.shared.redelivered.fetch_add(1, Ordering::Relaxed);
+                let backoff = kind.policy.backoff_after(delivery.attempts);
+                broker.nack(delivery, backoff).await
+            }
+            Err(error) => {
+                warn!("Job {} ({}) failed on attempt {}: {}", id, record.kind, record.attempts, error);
+                kind.failed.fetch_add(1, Ordering::Relaxed);
+                record.last_error = Some(error);
+                {
+                    self.jobs.lock().expect("jobs poisoned").fail(record);
+                }
+                self.shared.acked.fetch_add(1, Ordering::Relaxed);
+                broker.ack(delivery).await
+            }
+        };
+        if let Err(e) = settled {
+            warn!("Failed to settle job {} with {}; it will be delivered again: {}", id, broker.name(), e);
+        }
+        self.changed();
+    }
+
+    fn changed(&self) {
+        self.dirty.store(true, Ordering::Relaxed);
+        self.wake.notify_one();
+    }
+
+    // Writes queued and failed jobs to the store, replacing the file whole so
+    // a crash mid-write leaves the previous version
+    async fn persist(&self) {
+        let Some(path) = self.store.clone() else {
+            return;
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        let persisted = {
+            let jobs = self.jobs.lock().expect("jobs poisoned");
+            Persisted {
+                queued: jobs.queued.values().chain(jobs.running.values()).cloned().collect(),
+                failed: jobs.failed.iter().cloned().collect(),
+            }
+        };
+        let bytes = serde_json::to_vec(&persisted).expect("jobs serialize");
+        let written = tokio::task::spawn_blocking(move || {
+            let temp = path.with_extension("tmp");
+            std::fs::write(&temp, bytes)?;
+            std::fs::rename(&temp, &path)
+        })
+        .await;
+        if !matches!(written, Ok(Ok(()))) {
+            error!("Failed to write the jobs file");
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+// Runs a job to completion, turning a panic into a failure
+async fn settle(future: BoxFuture<'static, Result<(), String>>) -> Result<(), String> {
+    AssertUnwindSafe(future)
+        .catch_unwind()
+        .await
+        .unwrap_or_else(|_| Err("Job panicked".to_string()))
+}
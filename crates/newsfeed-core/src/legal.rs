@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::ids::UserId;
+
+// What a legal hold is placed on; the path segment of the hold endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HoldKind {
+    Post,
+    User,
+}
+
+impl FromStr for HoldKind {
+    type Err = ();
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind {
+            "posts" => Ok(Self::Post),
+            "users" => Ok(Self::User),
+            _ => Err(()),
+        }
+    }
+}
+
+// Preserves content for legal proceedings: held posts (or all posts of a held
+// user) are exempt from retention and hidden from every read path
+#[derive(Debug, Clone, Serialize)]
+pub struct LegalHold {
+    pub case_ref: String,
+    pub reason: String,
+    pub placed_by: UserId,
+    pub placed_at: u64,
+}
+
+// Withholds a post from viewers in the listed jurisdictions, or from everyone
+// when the list is empty. Viewers it applies to see the message instead.
+#[derive(Debug, Clone, Serialize)]
+pub struct Takedown {
+    pub jurisdictions: Vec<String>, // ISO 3166-1 alpha-2, uppercase
+    pub message: String,
+    pub case_ref: String,
+    pub placed_by: UserId,
+    pub placed_at: u64,
+}
+
+impl Takedown {
+    // A viewer whose country is unknown is treated as being in every
+    // jurisdiction, so a scoped takedown never leaks to them
+    pub fn applies_to(&self, country: Option<&str>) -> bool {
+        match country {
+            Some(country) if !self.jurisdictions.is_empty() => {
+                self.jurisdictions.iter().any(|code| code.eq_ignore_ascii_case(country))
+            }
+            _ => true,
+        }
+    }
+}
+
+// Shown in place of a withheld post's content
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub jurisdictions: Vec<String>,
+}
+
+// One hold in the admin listing
+#[derive(Debug, Serialize)]
+pub struct HoldEntry {
+    pub kind: HoldKind,
+    pub id: String,
+    #[serde(flatten)]
+    pub hold: LegalHold,
+}
@@ -0,0 +1,446 @@
+// What the service is about, apart from how it's served: domain types, the
+// storage and cache traits backends implement, the services that post,
+// fan out, and assemble feeds, and the projections that build the caches
+// from the event log. newsfeed-store-memory keeps the caches,
+// newsfeed-http serves them, and newsfeed-bin wires it all together.
+pub mod account_state;
+pub mod accounts;
+pub mod activity;
+pub mod ads;
+pub mod ann;
+pub mod articles;
+pub mod audit;
+pub mod bloom;
+pub mod bootstrap;
+pub mod broker;
+pub mod cache;
+pub mod cluster;
+pub mod config;
+pub mod content;
+pub mod context;
+pub mod conversations;
+pub mod email;
+pub mod embeddings;
+pub mod emoji;
+pub mod engagement_log;
+pub mod events;
+pub mod fanout;
+pub mod feed_locks;
+pub mod feed_updates;
+pub mod fields;
+pub mod graph;
+pub mod hyperloglog;
+pub mod i18n;
+pub mod ids;
+pub mod images;
+pub mod interests;
+pub mod jobs;
+pub mod legal;
+pub mod logging;
+pub mod login_history;
+pub mod markers;
+pub mod media;
+pub mod mixer;
+pub mod moderation;
+pub mod news_feed;
+pub mod notifications;
+pub mod oauth;
+pub mod passwords;
+pub mod pipeline;
+pub mod posts;
+pub mod profile_views;
+pub mod projections;
+pub mod ranking;
+pub mod reach;
+pub mod receipts;
+pub mod region;
+pub mod related;
+pub mod residency;
+pub mod retention;
+pub mod rsvp;
+pub mod saved_searches;
+pub mod search;
+pub mod singleflight;
+pub mod storage;
+pub mod telemetry;
+pub mod timezones;
+pub mod two_factor;
+pub mod typeahead;
+pub mod users;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use articles::ArticleCard;
+use emoji::CustomEmoji;
+use graph::FollowEdge;
+use ids::{PostId, TagId, UserId};
+use jobs::Job;
+use legal::Tombstone;
+use media::HydratedVideo;
+use mixer::Injection;
+use notifications::QuietHours;
+use pipeline::RankingStrategy;
+use profile_views::VisitPrivacy;
+use rsvp::{EventDetails, RsvpStatus, RsvpSummary};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub id: UserId,
+    pub username: String,
+    pub profile_picture: String,
+    #[serde(default)]
+    pub profile_picture_variants: Vec<ImageVariant>,
+    #[serde(default)]
+    pub banner_url: Option<String>,
+    #[serde(default)]
+    pub banner_variants: Vec<ImageVariant>,
+    #[serde(default)]
+    pub location: Option<String>,
+    #[serde(default)]
+    pub website: Option<String>,
+    #[serde(default)]
+    pub joined_at: u64,
+    #[serde(default)]
+    pub verified: bool,
+    #[serde(default)]
+    pub username_changed_at: Option<u64>,
+    // IANA name, e.g. "Europe/Berlin"; UTC when unset
+    #[serde(default)]
+    pub timezone: Option<String>,
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Post {
+    pub id: PostId,
+    pub user_id: UserId,
+    pub content: String,
+    pub image_url: Option<String>,
+    pub video_url: Option<String>,
+    pub alt_text: Option<String>,
+    pub in_reply_to: Option<PostId>,
+    #[serde(default)]
+    pub mentions: Vec<UserId>,
+    // From the text, kept up to date by edits
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hashtags: Vec<TagId>,
+    #[serde(default)]
+    pub reply_policy: ReplyPolicy,
+    #[serde(default)]
+    pub emojis: Vec<CustomEmoji>,
+    pub timestamp: u64,
+    pub like_count: u32,
+    pub reply_count: u32,
+    // When the author last changed the text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edited_at: Option<u64>,
+    // Stored with its author, but shown to others without (see
+    // PostHydrator::shape)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub anonymous: bool,
+    // One of NEWS_FEED_LICENSES, e.g. "cc-by"; set at creation only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+    // Invited when the post was created; see CacheLayer::answer_co_author
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub co_authors: Vec<CoAuthor>,
+    // Makes the post something followers can RSVP to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<EventDetails>,
+    // Makes the post the card for a long-form article; its text is the summary
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub article: Option<ArticleCard>,
+}
+
+// A user the author listed on a post. Until they accept, only the two of
+// them see the invitation; a declined one is dropped from the post.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoAuthor {
+    pub user_id: UserId,
+    pub accepted: bool,
+}
+
+impl Post {
+    // Media attached but not described for screen readers
+    pub fn missing_alt_text(&self) -> bool {
+        (self.image_url.is_some() || self.video_url.is_some()) && self.alt_text.is_none()
+    }
+}
+
+// Who may reply to a post
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplyPolicy {
+    #[default]
+    Everyone,
+    Following, // accounts the author follows
+    Mentioned,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewsFeedItem {
+    pub post_id: PostId,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageVariant {
+    pub width: u32,
+    pub height: u32,
+    pub url: String,
+}
+
+// Who anonymous posts are shown as
+pub const ANONYMOUS_AUTHOR: &str = "anonymous";
+
+pub const MAX_CO_AUTHORS: usize = 5;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UsernameRedirect {
+    pub user_id: UserId,
+    pub expires_at: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct UsernameLookup {
+    pub user_id: UserId,
+    pub moved: bool,
+}
+
+#[derive(Debug)]
+pub enum UsernameError {
+    Invalid,
+    Taken,
+    Cooldown { retry_after_secs: u64 },
+    UnknownUser,
+}
+
+// Validated input for a new post
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostDraft {
+    pub content: String,
+    pub image_url: Option<String>,
+    pub video_url: Option<String>,
+    pub alt_text: Option<String>,
+    pub in_reply_to: Option<PostId>,
+    pub reply_policy: ReplyPolicy,
+    #[serde(default)]
+    pub anonymous: bool,
+    #[serde(default)]
+    pub license: Option<String>,
+    #[serde(default)]
+    pub co_authors: Vec<UserId>,
+    #[serde(default)]
+    pub event: Option<EventDetails>,
+    #[serde(default)]
+    pub article: Option<ArticleCard>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UserPreferences {
+    // Ranks posts with described media ahead of undescribed ones
+    #[serde(default)]
+    pub screen_reader: bool,
+    // Language of emails and pushes, and of responses without an
+    // Accept-Language; also used for sponsored post targeting, e.g. "en"
+    #[serde(default)]
+    pub language: Option<String>,
+    // Whether profiles this user views see them by name, anonymously, or not at all
+    #[serde(default)]
+    pub profile_visits: VisitPrivacy,
+    // Keeps this user's profile and posts out of the logged-out public API
+    #[serde(default)]
+    pub hide_from_logged_out: bool,
+    // How the home feed is ordered when a request doesn't say
+    #[serde(default)]
+    pub feed_ranking: RankingStrategy,
+    // Bell notifications aren't pushed during these hours, in the user's timezone
+    #[serde(default)]
+    pub quiet_hours: Option<QuietHours>,
+    // A daily email of the notifications since the last one
+    #[serde(default)]
+    pub email_digest: bool,
+}
+
+// Position in a feed: an item's delivery timestamp and post ID
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FeedCursor {
+    pub timestamp: u64,
+    pub post_id: PostId,
+}
+
+impl FeedCursor {
+    pub fn from_item(item: &NewsFeedItem) -> Self {
+        Self {
+            timestamp: item.timestamp,
+            post_id: item.post_id.clone(),
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        format!("{}:{}", self.timestamp, self.post_id)
+    }
+
+    pub fn decode(value: &str) -> Option<Self> {
+        let (timestamp, post_id) = value.split_once(':')?;
+        Some(Self {
+            timestamp: timestamp.parse().ok()?,
+            post_id: PostId::new(post_id),
+        })
+    }
+
+    // Index of the cursor's item, or of the first older item if it was evicted
+    pub fn locate(&self, items: &[NewsFeedItem]) -> usize {
+        items
+            .iter()
+            .position(|item| item.post_id == self.post_id)
+            .or_else(|| items.iter().position(|item| item.timestamp <= self.timestamp))
+            .unwrap_or(items.len())
+    }
+}
+
+// Last-read feed position, shared across a user's devices
+#[derive(Debug, Clone, Serialize)]
+pub struct FeedPosition {
+    pub cursor: FeedCursor,
+    pub updated_at: u64,
+}
+
+// Every change to posts and the social graph, in the order it happened. The
+// caches holding them are projections of this log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FeedEvent {
+    PostCreated(Box<Post>),
+    ThreadPublished { head_id: PostId, post_ids: Vec<PostId> },
+    PostLiked { user_id: UserId, post_id: PostId },
+    PostUnliked { user_id: UserId, post_id: PostId },
+    PostDeleted { post_id: PostId },
+    PostEdited(Box<Post>),
+    CoAuthorAnswered { post_id: PostId, user_id: UserId, accepted: bool },
+    RsvpSet { post_id: PostId, user_id: UserId, status: Option<RsvpStatus> },
+    Followed { follower_id: UserId, followed_id: UserId },
+    Unfollowed { follower_id: UserId, followed_id: UserId },
+    NotifySet { follower_id: UserId, followed_id: UserId, enabled: bool },
+    DailyLimitSet { follower_id: UserId, followed_id: UserId, limit: Option<u16> },
+    // Read back from storage at startup, as the last run left them
+    PostRestored(Box<Post>),
+    FollowRestored { follower_id: UserId, followed_id: UserId, edge: FollowEdge },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FanoutMessage {
+    pub post_id: PostId,
+    pub user_id: UserId,
+    pub at: u64, // when the post was published
+    pub friend_ids: Vec<UserId>,
+    pub notify_ids: Vec<UserId>, // followers with the bell on
+    pub daily_limits: HashMap<UserId, u16>, // followers capping this author per day
+    // The post itself, for an instance that takes the job from the shared
+    // queue without having it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post: Option<Post>,
+}
+
+impl Job for FanoutMessage {
+    const KIND: &'static str = "fanout";
+}
+
+// Copies the followed account's recent posts into a new follower's feed.
+// Every node applies the follow, so the job stays on the node that runs it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FollowBackfill {
+    pub follower_id: UserId,
+    pub followed_id: UserId,
+    pub posts: usize,
+}
+
+impl Job for FollowBackfill {
+    const KIND: &'static str = "follow_backfill";
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HydratedPost {
+    #[serde(flatten)]
+    pub post: Post,
+    pub author: Option<Author>,
+    // Left out for anonymous viewers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub liked: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub can_reply: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video: Option<HydratedVideo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread: Option<ThreadContinuation>,
+    // The newest few replies; the rest are at /v1/posts/{id}/replies
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub latest_replies: Vec<ReplyPreview>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub injected: Option<Injection>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub campaign_id: Option<String>,
+    // Set when a takedown withholds the post from this viewer; the content
+    // and media are blanked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub withheld: Option<Tombstone>,
+    // Counts for event posts, with the viewer's own answer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rsvp: Option<RsvpSummary>,
+    // Where an article's card links to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub article_url: Option<String>,
+}
+
+// "Show this thread": the rest of a thread, attached to its head post
+#[derive(Debug, Clone, Serialize)]
+pub struct ThreadContinuation {
+    pub post_count: usize,
+    pub posts: Vec<Post>,
+}
+
+// A reply as shown under its parent: enough to read it in place
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplyPreview {
+    pub id: PostId,
+    pub user_id: UserId,
+    pub author: Option<Author>,
+    pub content: String,
+    pub timestamp: u64,
+    pub like_count: u32,
+    pub reply_count: u32,
+}
+
+pub const REPLY_PREVIEW_LIMIT: usize = 2;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Author {
+    pub username: String,
+    pub profile_picture: String,
+    pub verified: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Counters {
+    pub likes: u32,
+    pub replies: u32,
+    pub updated_at: u64,
+}
+
+// Why a post can't be shown to a viewer. The HTTP layer decides how much of
+// it a response reveals, per NEWS_FEED_REVEAL_POST_RESTRICTIONS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostUnavailable {
+    Missing, // never existed, or under a legal hold
+    Deleted,
+    Blocked, // the author has blocked the viewer
+    Restricted, // the author's account isn't public and the viewer is anonymous
+}
@@ -0,0 +1,94 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashSet, VecDeque};
+
+// Logins kept per account for GET /v1/me/security/logins
+const MAX_LOGINS: usize = 50;
+
+// Where a login came from, as seen by the edge
+#[derive(Debug, Clone, Default)]
+pub struct LoginContext {
+    pub ip: Option<String>,
+    pub country: Option<String>,
+    pub user_agent: Option<String>,
+    pub device_id: Option<String>, // x-device-id, sent by the mobile apps
+}
+
+impl LoginContext {
+    // Apps identify their install with x-device-id; browsers only have their
+    // user agent. Hashed so the history doesn't keep raw device identifiers.
+    pub fn fingerprint(&self) -> String {
+        let source = match (&self.device_id, &self.user_agent) {
+            (Some(device_id), _) => format!("device:{}", device_id),
+            (None, Some(user_agent)) => format!("agent:{}", user_agent),
+            (None, None) => "unknown".to_string(),
+        };
+        hex::encode(&Sha256::digest(source.as_bytes())[..8])
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LoginRecord {
+    pub at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    pub fingerprint: String,
+    pub two_factor: bool,
+    pub new_device: bool,
+    pub new_location: bool,
+}
+
+impl LoginRecord {
+    pub fn is_anomalous(&self) -> bool {
+        self.new_device || self.new_location
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct LoginHistory {
+    recent: VecDeque<LoginRecord>, // newest first
+    known_devices: HashSet<String>,
+    known_countries: HashSet<String>,
+}
+
+impl LoginHistory {
+    // Nothing is new until there is something to compare with: an account's
+    // first login, and the first login with a known country, set the baseline
+    pub fn record(&mut self, context: LoginContext, two_factor: bool, now: u64) -> LoginRecord {
+        let fingerprint = context.fingerprint();
+        let country = context.country.map(|country| country.to_ascii_uppercase());
+
+        let new_device =
+            !self.known_devices.is_empty() && !self.known_devices.contains(&fingerprint);
+        self.known_devices.insert(fingerprint.clone());
+        let new_location = match &country {
+            Some(country) => {
+                let new = !self.known_countries.is_empty() && !self.known_countries.contains(country);
+                self.known_countries.insert(country.clone());
+                new
+            }
+            None => false,
+        };
+        let record = LoginRecord {
+            at: now,
+            ip: context.ip,
+            country,
+            user_agent: context.user_agent,
+            fingerprint,
+            two_factor,
+            new_device,
+            new_location,
+        };
+        self.recent.push_front(record.clone());
+        self.recent.truncate(MAX_LOGINS);
+        record
+    }
+
+    pub fn recent(&self) -> Vec<LoginRecord> {
+        self.recent.iter().cloned().collect()
+    }
+}
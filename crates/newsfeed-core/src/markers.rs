@@ -0,0 +1,52 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::ids::UserId;
+
+// The steps of a fanout that must happen once per follower
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Notified = 1,
+    Delivered = 2,
+}
+
+impl Step {
+    pub const ALL: [Step; 2] = [Self::Notified, Self::Delivered];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Notified => "notification",
+            Self::Delivered => "feed",
+        }
+    }
+}
+
+// How far one post's fanout got with each follower. A post is fanned out
+// once, so its ID names the message; a retried job, a redelivered queue
+// message, or a repeated node-to-node batch finds the steps already done.
+#[derive(Debug, Serialize)]
+pub struct DeliveryMarker {
+    pub created_at: u64,
+    steps: HashMap<UserId, u8>,
+}
+
+impl DeliveryMarker {
+    pub fn new(created_at: u64) -> Self {
+        Self {
+            created_at,
+            steps: HashMap::new(),
+        }
+    }
+
+    pub fn is_done(&self, user_id: &UserId, step: Step) -> bool {
+        self.steps.get(user_id).is_some_and(|done| done & step as u8 != 0)
+    }
+
+    // Returns false if the step was already done
+    pub fn mark(&mut self, user_id: &UserId, step: Step) -> bool {
+        let done = self.steps.entry(user_id.clone()).or_default();
+        let first = *done & step as u8 == 0;
+        *done |= step as u8;
+        first
+    }
+}
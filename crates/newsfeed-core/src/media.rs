@@ -0,0 +1,156 @@
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{Config, Settings};
+use crate::ids::{PostId, UserId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VideoStatus {
+    Processing,
+    Ready,
+    Failed,
+}
+
+// Video as returned in hydrated posts, with freshly signed URLs once ready
+#[derive(Debug, Clone, Serialize)]
+pub struct HydratedVideo {
+    pub status: VideoStatus,
+    pub playlist_url: Option<String>,
+    pub poster_url: Option<String>,
+}
+
+pub const MASTER_PLAYLIST: &str = "master.m3u8";
+pub const POSTER: &str = "poster.jpg";
+
+type HmacSha256 = Hmac<Sha256>;
+
+// What a media signature opens: the poster alone, or the playlists and
+// segments an HLS player walks from the master playlist
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MediaScope {
+    Poster,
+    Stream,
+}
+
+impl MediaScope {
+    fn of(file: &str) -> Option<Self> {
+        if file == POSTER {
+            Some(Self::Poster)
+        } else if file.ends_with(".m3u8") || file.ends_with(".ts") {
+            Some(Self::Stream)
+        } else {
+            None
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Poster => "poster",
+            Self::Stream => "stream",
+        }
+    }
+}
+
+// Issues and checks HMAC-signed, expiring /media URLs. A signature covers one
+// post's poster or its HLS files, for the viewer it was issued to (none for
+// logged-out visitors), so the handler can check that viewer may still see
+// the post.
+pub struct MediaSigner {
+    key: Vec<u8>,
+    settings: Arc<Settings>,
+}
+
+impl MediaSigner {
+    pub fn new(config: &Config, settings: Arc<Settings>) -> Self {
+        Self {
+            key: config.media_signing_key.as_bytes().to_vec(),
+            settings,
+        }
+    }
+
+    pub fn hydrate_video(&self, post_id: &PostId, viewer_id: Option<&UserId>, status: VideoStatus) -> HydratedVideo {
+        let ready = status == VideoStatus::Ready;
+        let viewer_id = viewer_id.map(UserId::as_str);
+        HydratedVideo {
+            status,
+            playlist_url: ready.then(|| self.sign_url(post_id.as_str(), MASTER_PLAYLIST, viewer_id)),
+            poster_url: ready.then(|| self.sign_url(post_id.as_str(), POSTER, viewer_id)),
+        }
+    }
+
+    fn sign_url(&self, media_id: &str, file: &str, viewer_id: Option<&str>) -> String {
+        let scope = MediaScope::of(file).expect("signed media files have a scope");
+        let expires = now_secs() + self.settings.current().media_url_ttl_secs;
+        format!("/media/{}/{}?{}", media_id, file, self.query(media_id, scope, viewer_id, expires))
+    }
+
+    fn query(&self, media_id: &str, scope: MediaScope, viewer_id: Option<&str>, expires: u64) -> String {
+        let signature = hex::encode(self.mac(media_id, scope, viewer_id, expires).finalize().into_bytes());
+        match viewer_id {
+            Some(viewer_id) => format!("expires={}&viewer={}&sig={}", expires, viewer_id, signature),
+            None => format!("expires={}&sig={}", expires, signature),
+        }
+    }
+
+    fn mac(&self, media_id: &str, scope: MediaScope, viewer_id: Option<&str>, expires: u64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        for part in [media_id, scope.as_str(), viewer_id.unwrap_or_default(), &expires.to_string()] {
+            mac.update(part.as_bytes());
+            mac.update(b"\n");
+        }
+        mac
+    }
+
+    // Whether the signature opens `file` for the viewer. The caller still
+    // checks that the viewer may see the post.
+    pub fn verify(&self, media_id: &str, file: &str, viewer_id: Option<&str>, expires: u64, sig: &str) -> bool {
+        if expires < now_secs() {
+            return false;
+        }
+        let Some(scope) = MediaScope::of(file) else {
+            return false;
+        };
+        match hex::decode(sig) {
+            Ok(signature) => self.mac(media_id, scope, viewer_id, expires).verify_slice(&signature).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    // Carries the caller's signature onto every URI in a playlist, since
+    // players resolve relative references without the original query string
+    pub fn sign_playlist(&self, media_id: &str, viewer_id: Option<&str>, expires: u64, playlist: &str) -> String {
+        let query = self.query(media_id, MediaScope::Stream, viewer_id, expires);
+        playlist
+            .lines()
+            .map(|line| {
+                if line.is_empty() || line.starts_with('#') {
+                    line.to_string()
+                } else {
+                    format!("{}?{}", line, query)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+            + "\n"
+    }
+}
+
+pub fn content_type(file: &str) -> &'static str {
+    match file.rsplit('.').next() {
+        Some("m3u8") => "application/vnd.apple.mpegurl",
+        Some("ts") => "video/mp2t",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
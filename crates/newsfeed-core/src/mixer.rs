@@ -0,0 +1,201 @@
+use futures::future::BoxFuture;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, RwLock};
+
+use crate::cache::Cache;
+use crate::config::Settings;
+use crate::content::extract_hashtags;
+use crate::ids::{PostId, UserId};
+use crate::pipeline::{Candidate, FeedRequest, Mixer};
+use crate::singleflight::SingleFlight;
+use crate::{Post, now_millis};
+
+pub const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+const TRENDING_REFRESH_MILLIS: u64 = 60 * 1000;
+const TRENDING_SIZE: usize = 50;
+
+// Why a post outside the viewer's network appears in their feed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Injection {
+    Trending,
+    Sponsored,
+}
+
+// Per-viewer impressions of injected posts for the current UTC day only;
+// older days are dropped on the first write of a new day. Also keyed by
+// author to count each followed account's posts delivered today.
+#[derive(Debug, Serialize)]
+pub struct ImpressionLog<K: Eq + Hash = PostId> {
+    day: u64,
+    counts: HashMap<K, u16>,
+}
+
+impl<K: Eq + Hash> Default for ImpressionLog<K> {
+    fn default() -> Self {
+        Self {
+            day: 0,
+            counts: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone> ImpressionLog<K> {
+    pub fn count(&self, key: &K, day: u64) -> u16 {
+        if self.day == day {
+            self.counts.get(key).copied().unwrap_or(0)
+        } else {
+            0
+        }
+    }
+
+    pub fn record(&mut self, key: &K, day: u64) {
+        if self.day != day {
+            self.day = day;
+            self.counts.clear();
+        }
+        *self.counts.entry(key.clone()).or_default() += 1;
+    }
+}
+
+struct TrendingSnapshot {
+    computed_at: u64,
+    post_ids: Vec<PostId>,
+}
+
+// Picks trending posts to mix into feed pages, subject to per-viewer daily caps
+pub struct FeedMixer {
+    cache: Arc<dyn Cache>,
+    settings: Arc<Settings>,
+    trending: RwLock<TrendingSnapshot>,
+    refresh: SingleFlight<Vec<PostId>>,
+}
+
+impl FeedMixer {
+    pub fn new(cache: Arc<dyn Cache>, settings: Arc<Settings>) -> Self {
+        Self {
+            cache,
+            settings,
+            trending: RwLock::new(TrendingSnapshot {
+                computed_at: 0,
+                post_ids: Vec::new(),
+            }),
+            refresh: SingleFlight::default(),
+        }
+    }
+
+    // Up to `slots` trending posts the viewer may still see today, recorded
+    // as shown. Posts on the viewer's topics go first, the rest by engagement.
+    pub async fn pick(&self, viewer_id: &UserId, slots: usize, page: &[PostId]) -> Vec<Post> {
+        let daily_cap = self.settings.current().injected_daily_cap;
+        if slots == 0 || daily_cap == 0 {
+            return Vec::new();
+        }
+
+        let now = now_millis();
+        let day = now / DAY_MILLIS;
+        let interests = self.cache.get_interests(viewer_id);
+        let mut eligible: Vec<(f64, Post)> = self
+            .trending_post_ids()
+            .await
+            .iter()
+            .filter(|post_id| !page.contains(post_id))
+            .filter(|post_id| self.cache.impression_count(viewer_id, post_id, day) < daily_cap)
+            .filter(|post_id| !self.cache.is_hidden(viewer_id, post_id))
+            .filter_map(|post_id| self.cache.get_post(post_id))
+            .filter(|post| {
+                &post.user_id != viewer_id && !self.cache.is_following(viewer_id, &post.user_id)
+            })
+            .map(|post| {
+                let topics = interests.topic_affinity(&extract_hashtags(&post.content), now);
+                (topics.max(interests.author_affinity(&post.user_id, now)), post)
+            })
+            .collect();
+        // Stable, so engagement order holds among equal matches
+        eligible.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let picked: Vec<Post> = eligible.into_iter().take(slots).map(|(_, post)| post).collect();
+
+        for post in &picked {
+            self.cache.record_impression(viewer_id, &post.id, day);
+        }
+        picked
+    }
+
+    // Trending lookups that reused a refresh another page build had started
+    pub fn refreshes_shared(&self) -> u64 {
+        self.refresh.shared()
+    }
+
+    async fn trending_post_ids(&self) -> Vec<PostId> {
+        let now = now_millis();
+        {
+            let snapshot = self.trending.read().expect("trending snapshot poisoned");
+            if now.saturating_sub(snapshot.computed_at) < TRENDING_REFRESH_MILLIS {
+                return snapshot.post_ids.clone();
+            }
+        }
+
+        // A stale snapshot is seen by every page build at once; one rescans
+        self.refresh
+            .run("trending", || async { self.refresh_trending(now) })
+            .await
+    }
+
+    fn refresh_trending(&self, now: u64) -> Vec<PostId> {
+        // Most engaged top-level posts from the last day
+        let mut scored: Vec<(u32, PostId)> = self
+            .cache
+            .recent_posts(now.saturating_sub(DAY_MILLIS))
+            .into_iter()
+            .filter(|post| post.in_reply_to.is_none())
+            .map(|post| {
+                let counters = self.cache.get_counters(&post.id);
+                (counters.likes + 2 * counters.replies, post.id)
+            })
+            .filter(|(score, _)| *score > 0)
+            .collect();
+        scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        let post_ids: Vec<PostId> = scored
+            .into_iter()
+            .take(TRENDING_SIZE)
+            .map(|(_, post_id)| post_id)
+            .collect();
+
+        *self.trending.write().expect("trending snapshot poisoned") = TrendingSnapshot {
+            computed_at: now,
+            post_ids: post_ids.clone(),
+        };
+        post_ids
+    }
+}
+
+// One trending post after every `interval` organic posts
+impl Mixer for FeedMixer {
+    fn mix<'a>(&'a self, request: &'a FeedRequest<'_>, organic: Vec<Candidate>) -> BoxFuture<'a, Vec<Candidate>> {
+        Box::pin(async move {
+            let interval = self.settings.current().injection_interval.max(1);
+            let page: Vec<PostId> = organic.iter().map(|candidate| candidate.post.id.clone()).collect();
+            let mut injected = self
+                .pick(&request.ctx.user_id, organic.len() / interval, &page)
+                .await
+                .into_iter()
+                .map(|post| Candidate {
+                    injected: Some(Injection::Trending),
+                    ..Candidate::organic(post)
+                });
+
+            let mut mixed = Vec::with_capacity(organic.len());
+            for (index, candidate) in organic.into_iter().enumerate() {
+                mixed.push(candidate);
+                if (index + 1) % interval == 0
+                    && let Some(extra) = injected.next()
+                {
+                    mixed.push(extra);
+                }
+            }
+            mixed
+        })
+    }
+}
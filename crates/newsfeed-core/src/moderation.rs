@@ -0,0 +1,245 @@
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::mem;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::graph::{GraphEvent, GraphEventKind};
+use crate::ids::UserId;
+use crate::{info, now_millis};
+
+// Accounts churning with each other in both directions before it's a ring
+const RING_MIN_MEMBERS: usize = 3;
+// Most accounts listed as evidence in one flag
+const MAX_EVIDENCE_ACCOUNTS: usize = 20;
+
+// What an account was flagged for, with the numbers behind it
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum Signal {
+    // Many follows in a short window
+    MassFollow {
+        follows: usize,
+        window_secs: u64,
+        recent_targets: Vec<UserId>,
+    },
+    // Many follows undone soon after
+    FollowChurn {
+        churned: usize,
+        window_secs: u64,
+        accounts: Vec<UserId>,
+    },
+    // Accounts that follow and unfollow each other
+    ChurnRing { members: Vec<UserId> },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Flag {
+    #[serde(flatten)]
+    pub signal: Signal,
+    pub flagged_at: u64,
+}
+
+// One flagged account; a newer flag for the same reason replaces the older one
+#[derive(Debug, Clone, Serialize)]
+pub struct ModerationCase {
+    pub user_id: UserId,
+    pub opened_at: u64,
+    pub flags: Vec<Flag>,
+}
+
+// Flagged accounts waiting for a moderator
+#[derive(Debug, Default)]
+pub struct ModerationQueue {
+    cases: DashMap<UserId, ModerationCase>,
+}
+
+impl ModerationQueue {
+    pub fn flag(&self, user_id: &UserId, signal: Signal) {
+        let now = now_millis();
+        let mut case = self.cases.entry(user_id.clone()).or_insert_with(|| {
+            info!("Moderation case opened for {}", user_id);
+            ModerationCase {
+                user_id: user_id.clone(),
+                opened_at: now,
+                flags: Vec::new(),
+            }
+        });
+        case.flags
+            .retain(|flag| mem::discriminant(&flag.signal) != mem::discriminant(&signal));
+        case.flags.push(Flag {
+            signal,
+            flagged_at: now,
+        });
+    }
+
+    // Oldest cases first
+    pub fn cases(&self) -> Vec<ModerationCase> {
+        let mut cases: Vec<_> = self.cases.iter().map(|entry| entry.clone()).collect();
+        cases.sort_by_key(|case| case.opened_at);
+        cases
+    }
+
+    pub fn resolve(&self, user_id: &UserId) -> bool {
+        self.cases.remove(user_id).is_some()
+    }
+
+    pub fn open_cases(&self) -> usize {
+        self.cases.len()
+    }
+}
+
+// Watches graph events for follow patterns that suggest automation or
+// follower trading. Runs on a single task, so its windows are plain maps.
+pub struct FollowAnalyzer {
+    queue: Arc<ModerationQueue>,
+    burst_limit: usize,
+    burst_millis: u64,
+    churn_limit: usize,
+    churn_millis: u64,
+    recent_follows: HashMap<UserId, VecDeque<(u64, UserId)>>, // follower -> follows in the burst window
+    open_follows: HashMap<(UserId, UserId), u64>,             // follows still in the churn window
+    churns: HashMap<UserId, VecDeque<(u64, UserId)>>,         // follower -> follows undone in the churn window
+}
+
+impl FollowAnalyzer {
+    pub fn new(queue: Arc<ModerationQueue>, config: &Config) -> Self {
+        Self {
+            queue,
+            burst_limit: config.follow_burst_limit.max(1),
+            burst_millis: config.follow_burst_secs.saturating_mul(1000),
+            churn_limit: config.follow_churn_limit.max(1),
+            churn_millis: config.follow_churn_secs.saturating_mul(1000),
+            recent_follows: HashMap::new(),
+            open_follows: HashMap::new(),
+            churns: HashMap::new(),
+        }
+    }
+
+    pub fn handle(&mut self, event: GraphEvent) {
+        match event.kind {
+            GraphEventKind::Follow => self.on_follow(event),
+            GraphEventKind::Unfollow => self.on_unfollow(event),
+        }
+    }
+
+    fn on_follow(&mut self, event: GraphEvent) {
+        let cutoff = event.at.saturating_sub(self.burst_millis);
+        let follows = self.recent_follows.entry(event.follower_id.clone()).or_default();
+        follows.push_back((event.at, event.followed_id.clone()));
+        prune(follows, cutoff);
+        if follows.len() >= self.burst_limit {
+            let signal = Signal::MassFollow {
+                follows: follows.len(),
+                window_secs: self.burst_millis / 1000,
+                recent_targets: follows
+                    .iter()
+                    .rev()
+                    .take(MAX_EVIDENCE_ACCOUNTS)
+                    .map(|(_, target)| target.clone())
+                    .collect(),
+            };
+            self.queue.flag(&event.follower_id, signal);
+        }
+        self.open_follows
+            .insert((event.follower_id, event.followed_id), event.at);
+    }
+
+    fn on_unfollow(&mut self, event: GraphEvent) {
+        let key = (event.follower_id, event.followed_id);
+        let Some(followed_at) = self.open_follows.remove(&key) else {
+            return;
+        };
+        if event.at.saturating_sub(followed_at) > self.churn_millis {
+            return;
+        }
+        let (follower_id, followed_id) = key;
+
+        let cutoff = event.at.saturating_sub(self.churn_millis);
+        let churns = self.churns.entry(follower_id.clone()).or_default();
+        churns.push_back((event.at, followed_id));
+        prune(churns, cutoff);
+        if churns.len() >= self.churn_limit {
+            let signal = Signal::FollowChurn {
+                churned: churns.len(),
+                window_secs: self.churn_millis / 1000,
+                accounts: distinct(churns.iter().rev().map(|(_, target)| target)),
+            };
+            self.queue.flag(&follower_id, signal);
+        }
+        self.check_ring(&follower_id);
+    }
+
+    // A ring is the group of accounts linked by reciprocal churn: each
+    // churned a follow of another member, who churned one of theirs back
+    fn check_ring(&self, user_id: &UserId) {
+        let mut members = vec![user_id.clone()];
+        let mut next = 0;
+        while next < members.len() && members.len() < MAX_EVIDENCE_ACCOUNTS {
+            let member = members[next].clone();
+            next += 1;
+            for partner in self.churn_partners(&member) {
+                if !members.contains(&partner) {
+                    members.push(partner);
+                }
+            }
+        }
+        if members.len() < RING_MIN_MEMBERS {
+            return;
+        }
+
+        members.truncate(MAX_EVIDENCE_ACCOUNTS);
+        members.sort();
+        for member in &members {
+            self.queue.flag(
+                member,
+                Signal::ChurnRing {
+                    members: members.clone(),
+                },
+            );
+        }
+    }
+
+    fn churn_partners(&self, user_id: &UserId) -> Vec<UserId> {
+        let Some(churns) = self.churns.get(user_id) else {
+            return Vec::new();
+        };
+        distinct(churns.iter().map(|(_, target)| target).filter(|target| {
+            self.churns
+                .get(*target)
+                .is_some_and(|back| back.iter().any(|(_, other)| other == user_id))
+        }))
+    }
+
+    // Drops everything that has left its window, including idle accounts
+    pub fn sweep(&mut self, now: u64) {
+        let burst_cutoff = now.saturating_sub(self.burst_millis);
+        let churn_cutoff = now.saturating_sub(self.churn_millis);
+        self.recent_follows.retain(|_, follows| {
+            prune(follows, burst_cutoff);
+            !follows.is_empty()
+        });
+        self.churns.retain(|_, churns| {
+            prune(churns, churn_cutoff);
+            !churns.is_empty()
+        });
+        self.open_follows
+            .retain(|_, followed_at| *followed_at >= churn_cutoff);
+    }
+}
+
+fn prune(window: &mut VecDeque<(u64, UserId)>, cutoff: u64) {
+    while window.front().is_some_and(|(at, _)| *at < cutoff) {
+        window.pop_front();
+    }
+}
+
+fn distinct<'a>(accounts: impl Iterator<Item = &'a UserId>) -> Vec<UserId> {
+    let mut seen = HashSet::new();
+    accounts
+        .filter(|account| seen.insert(*account))
+        .take(MAX_EVIDENCE_ACCOUNTS)
+        .cloned()
+        .collect()
+}
@@ -0,0 +1,477 @@
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::ads::AdService;
+use crate::cache::Cache;
+use crate::config::Settings;
+use crate::context::{Cancelled, RequestContext, ViewerContext};
+use crate::conversations::Timeline;
+use crate::engagement_log::{EngagementLog, Interaction};
+use crate::ids::{PostId, TagId, UserId};
+use crate::media::MediaSigner;
+use crate::mixer::FeedMixer;
+use crate::pipeline::{
+    AccessibilityOrder, Candidate, FeedMode, FeedPipeline, FeedRequest, FollowedFeed, HiddenPosts, Hydrator, Ranker,
+    RankingStrategy,
+};
+use crate::reach::{Channel, ReachTotals};
+use crate::singleflight::SingleFlight;
+use crate::{
+    ANONYMOUS_AUTHOR, Author, FeedCursor, HydratedPost, Post, PostUnavailable, REPLY_PREVIEW_LIMIT, ReplyPreview,
+    ThreadContinuation, now_millis,
+};
+
+// Attaches author, live counters, viewer state, and media to a post. The
+// feed pipeline's hydration stage, also used for timelines and conversations.
+// Every post a response carries is shaped for its viewer here, so handlers
+// don't each decide what a viewer may see.
+pub struct PostHydrator {
+    pub cache: Arc<dyn Cache>,
+    pub media_signer: Arc<MediaSigner>,
+}
+
+impl PostHydrator {
+    // An error if the viewer mustn't see the post at all (see
+    // Cache::restriction). Anonymous viewers get no liked or can_reply
+    // flags, and a reply count of only the replies they could see.
+    pub fn shape(&self, viewer: &ViewerContext, post: Post) -> Result<HydratedPost, PostUnavailable> {
+        if let Some(reason) = self.cache.post_restriction(viewer, &post) {
+            return Err(reason);
+        }
+        let country = viewer.country();
+        let masked = self.masks_author(viewer, &post);
+        let author = if masked { Some(anonymous_author()) } else { self.author(&post.user_id) };
+
+        let counters = self.cache.get_counters(&post.id);
+
+        let video = self
+            .cache
+            .get_video(&post.id)
+            .map(|status| self.media_signer.hydrate_video(&post.id, viewer.user_id(), status));
+
+        let thread = self.cache.get_thread(&post.id).map(|post_ids| {
+            let posts: Vec<Post> = post_ids
+                .iter()
+                .filter(|post_id| self.cache.tombstone_in(country, post_id).is_none())
+                .filter_map(|post_id| self.cache.get_post(post_id))
+                .collect();
+            ThreadContinuation {
+                post_count: posts.len() + 1,
+                posts,
+            }
+        });
+
+        // Withheld replies are left out rather than shown blanked
+        let latest_replies: Vec<ReplyPreview> = if counters.replies == 0 {
+            Vec::new()
+        } else {
+            self.cache
+                .latest_replies(&post.id, REPLY_PREVIEW_LIMIT)
+                .iter()
+                .filter(|reply_id| self.cache.tombstone_in(country, reply_id).is_none())
+                .filter_map(|reply_id| self.cache.get_post(reply_id))
+                .filter(|reply| !self.cache.hidden_from(viewer, &reply.user_id))
+                .map(|reply| self.reply_preview(viewer, reply))
+                .collect()
+        };
+
+        let withheld = self.cache.tombstone_in(country, &post.id);
+        let (liked, can_reply) = match viewer.user_id() {
+            Some(user_id) => (
+                Some(self.cache.has_liked(user_id, &post.id)),
+                Some(self.cache.can_reply(user_id, &post)),
+            ),
+            None => (None, None),
+        };
+        let post_id = post.id.clone();
+        let mut hydrated_post = post;
+        if withheld.is_some() {
+            hydrated_post.content = String::new();
+            hydrated_post.image_url = None;
+            hydrated_post.video_url = None;
+            hydrated_post.alt_text = None;
+            hydrated_post.mentions.clear();
+            hydrated_post.hashtags.clear();
+            hydrated_post.emojis.clear();
+            hydrated_post.event = None;
+            hydrated_post.article = None;
+        }
+        if masked {
+            hydrated_post.user_id = UserId::new(ANONYMOUS_AUTHOR);
+        }
+        // An open invitation is between the author and the invitee
+        let viewer_id = viewer.user_id();
+        let rsvp = hydrated_post
+            .event
+            .as_ref()
+            .map(|_| self.cache.rsvp_summary(&post_id, viewer_id));
+        let article_url = hydrated_post
+            .article
+            .as_ref()
+            .map(|_| format!("/v1/articles/{}", post_id));
+        if viewer_id != Some(&hydrated_post.user_id) {
+            hydrated_post
+                .co_authors
+                .retain(|co_author| co_author.accepted || viewer_id == Some(&co_author.user_id));
+        }
+        hydrated_post.like_count = counters.likes;
+        hydrated_post.reply_count = self.reply_count(viewer, &post_id, counters.replies);
+        // Pick up re-uploaded emoji and drop ones removed since posting
+        hydrated_post.emojis = hydrated_post
+            .emojis
+            .iter()
+            .filter_map(|emoji| self.cache.get_emoji(&emoji.shortcode))
+            .collect();
+
+        Ok(HydratedPost {
+            post: hydrated_post,
+            author,
+            liked,
+            can_reply,
+            video: video.filter(|_| withheld.is_none()),
+            thread: thread.filter(|_| withheld.is_none()),
+            latest_replies: if withheld.is_none() { latest_replies } else { Vec::new() },
+            injected: None,
+            campaign_id: None,
+            withheld,
+            rsvp,
+            article_url,
+        })
+    }
+
+    pub fn reply_preview(&self, viewer: &ViewerContext, reply: Post) -> ReplyPreview {
+        let counters = self.cache.get_counters(&reply.id);
+        let masked = self.masks_author(viewer, &reply);
+        ReplyPreview {
+            author: if masked { Some(anonymous_author()) } else { self.author(&reply.user_id) },
+            reply_count: self.reply_count(viewer, &reply.id, counters.replies),
+            id: reply.id,
+            user_id: if masked { UserId::new(ANONYMOUS_AUTHOR) } else { reply.user_id },
+            content: reply.content,
+            timestamp: reply.timestamp,
+            like_count: counters.likes,
+        }
+    }
+
+    // Users get the counter as it stands. An anonymous viewer's count leaves
+    // out replies from accounts that aren't public and replies withheld where
+    // they are, so it matches what they can open.
+    pub fn reply_count(&self, viewer: &ViewerContext, post_id: &PostId, replies: u32) -> u32 {
+        if replies == 0 || viewer.user_id().is_some() {
+            return replies;
+        }
+        self.cache
+            .get_replies(post_id)
+            .iter()
+            .filter(|reply_id| self.cache.tombstone_in(viewer.country(), reply_id).is_none())
+            .filter_map(|reply_id| self.cache.get_post(reply_id))
+            .filter(|reply| !self.cache.hidden_from(viewer, &reply.user_id))
+            .count() as u32
+    }
+
+    pub fn author(&self, user_id: &UserId) -> Option<Author> {
+        self.cache.get_user(user_id).map(|user| Author {
+            username: user.username,
+            profile_picture: user.profile_picture,
+            verified: user.verified,
+        })
+    }
+
+    // Only the author sees who wrote their anonymous posts
+    pub fn masks_author(&self, viewer: &ViewerContext, post: &Post) -> bool {
+        post.anonymous && viewer.user_id() != Some(&post.user_id)
+    }
+}
+
+pub fn anonymous_author() -> Author {
+    Author {
+        username: ANONYMOUS_AUTHOR.to_string(),
+        profile_picture: String::new(),
+        verified: false,
+    }
+}
+
+impl Hydrator for PostHydrator {
+    fn hydrate(&self, viewer_id: &UserId, candidate: Candidate) -> Option<HydratedPost> {
+        let viewer = self.cache.viewer(viewer_id);
+        Some(HydratedPost {
+            injected: candidate.injected,
+            campaign_id: candidate.campaign_id,
+            ..self.shape(&viewer, candidate.post).ok()?
+        })
+    }
+}
+
+pub struct NewsFeedService {
+    pub cache: Arc<dyn Cache>,
+    pub hydrator: Arc<PostHydrator>,
+    pub feed_mixer: Arc<FeedMixer>,
+    pub pipeline: FeedPipeline,
+    pub settings: Arc<Settings>, // for the page cache TTL
+    pub page_hits: AtomicU64,
+    pub page_misses: AtomicU64,
+    pub page_builds: SingleFlight<Result<Vec<HydratedPost>, Cancelled>>,
+    pub reach_totals: ReachTotals,
+    pub engagement_log: Arc<EngagementLog>, // impressions
+}
+
+impl NewsFeedService {
+    pub fn new(
+        cache: Arc<dyn Cache>,
+        media_signer: Arc<MediaSigner>,
+        rankers: Vec<(Arc<dyn Ranker>, &'static [RankingStrategy])>, // run in order on ranked pages
+        feed_mixer: Arc<FeedMixer>,
+        ad_service: Arc<AdService>,
+        engagement_log: Arc<EngagementLog>,
+        settings: Arc<Settings>,
+    ) -> Self {
+        let hydrator = Arc::new(PostHydrator {
+            cache: cache.clone(),
+            media_signer,
+        });
+        // Latest skips reordering and trending; sponsored slots stay in both
+        let mut pipeline = FeedPipeline::new(Arc::new(FollowedFeed { cache: cache.clone() }), hydrator.clone())
+            .filter(Arc::new(HiddenPosts { cache: cache.clone() }), FeedMode::ALL);
+        for (ranker, strategies) in rankers {
+            pipeline = pipeline.ranker(ranker, strategies);
+        }
+        let pipeline = pipeline
+            .mixer(feed_mixer.clone(), &[FeedMode::Ranked])
+            .mixer(ad_service, FeedMode::ALL)
+            .post_processor(Arc::new(AccessibilityOrder { cache: cache.clone() }), &[FeedMode::Ranked]);
+        Self {
+            cache,
+            hydrator,
+            feed_mixer,
+            pipeline,
+            settings,
+            page_hits: AtomicU64::new(0),
+            page_misses: AtomicU64::new(0),
+            page_builds: SingleFlight::default(),
+            reach_totals: ReachTotals::default(),
+            engagement_log,
+        }
+    }
+
+    // Authors seeing their own posts don't add to their reach
+    pub fn record_reach(&self, viewer_id: &UserId, post: &Post, channel: Channel) {
+        if &post.user_id != viewer_id {
+            self.cache.record_reach(&post.id, channel);
+            self.reach_totals.record(channel);
+            self.engagement_log.record(viewer_id, post, Interaction::Impression { channel });
+        }
+    }
+
+    pub fn reach_metrics(&self) -> String {
+        self.reach_totals.metrics()
+    }
+
+    // Every post on the page counts as an impression for the channel that
+    // put it there, cached pages included
+    pub async fn get_news_feed(
+        &self,
+        ctx: &RequestContext,
+        limit: usize,
+        start: Option<&FeedCursor>,
+        mode: FeedMode,
+        ranking: RankingStrategy,
+    ) -> Result<Vec<HydratedPost>, Cancelled> {
+        let posts = self.serve_page(ctx, limit, start, mode, ranking).await?;
+        for hydrated in &posts {
+            self.record_reach(&ctx.user_id, &hydrated.post, Channel::on_feed(hydrated.injected));
+        }
+        Ok(posts)
+    }
+
+    // Serves a recently assembled page when there is one, so repeated
+    // refreshes skip ranking and hydration
+    pub async fn serve_page(
+        &self,
+        ctx: &RequestContext,
+        limit: usize,
+        start: Option<&FeedCursor>,
+        mode: FeedMode,
+        ranking: RankingStrategy,
+    ) -> Result<Vec<HydratedPost>, Cancelled> {
+        let key = format!(
+            "{}:{}:{}:{}",
+            mode.as_str(),
+            ranking.as_str(),
+            limit,
+            start.map(FeedCursor::encode).unwrap_or_default()
+        );
+        let page_ttl_millis = self.settings.current().page_cache_ttl_ms;
+        let fresh_after = now_millis().saturating_sub(page_ttl_millis);
+        if page_ttl_millis > 0
+            && let Some(posts) = self.cache.get_feed_page(&ctx.user_id, &key, fresh_after)
+        {
+            self.page_hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(posts);
+        }
+
+        let request = FeedRequest {
+            ctx,
+            limit,
+            start,
+            mode,
+            ranking,
+        };
+        // Identical requests arriving together (a double-tapped refresh) share one build
+        let flight_key = format!("{}:{}", ctx.user_id, key);
+        match self
+            .page_builds
+            .run(&flight_key, || self.build_page(&request, &key, fresh_after))
+            .await
+        {
+            // The build was shared with a request that has since given up;
+            // this one still has time, so it builds the page itself
+            Err(Cancelled) if ctx.check().is_ok() => self.build_page(&request, &key, fresh_after).await,
+            result => result,
+        }
+    }
+
+    pub async fn build_page(
+        &self,
+        request: &FeedRequest<'_>,
+        key: &str,
+        fresh_after: u64,
+    ) -> Result<Vec<HydratedPost>, Cancelled> {
+        self.page_misses.fetch_add(1, Ordering::Relaxed);
+        let posts = self.pipeline.assemble(request).await?;
+        if self.settings.current().page_cache_ttl_ms > 0 {
+            self.cache
+                .put_feed_page(&request.ctx.user_id, key.to_string(), posts.clone(), fresh_after);
+        }
+        Ok(posts)
+    }
+
+    // Prometheus text exposition of page cache effectiveness
+    pub fn page_cache_metrics(&self) -> String {
+        let hits = self.page_hits.load(Ordering::Relaxed);
+        let misses = self.page_misses.load(Ordering::Relaxed);
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP news_feed_page_cache_hits_total Feed pages served from the page cache.");
+        let _ = writeln!(out, "# TYPE news_feed_page_cache_hits_total counter");
+        let _ = writeln!(out, "news_feed_page_cache_hits_total {}", hits);
+        let _ = writeln!(out, "# HELP news_feed_page_cache_misses_total Feed pages assembled because no cached page was fresh.");
+        let _ = writeln!(out, "# TYPE news_feed_page_cache_misses_total counter");
+        let _ = writeln!(out, "news_feed_page_cache_misses_total {}", misses);
+        let _ = writeln!(out, "# HELP news_feed_page_cache_hit_ratio Share of feed page requests served from the cache.");
+        let _ = writeln!(out, "# TYPE news_feed_page_cache_hit_ratio gauge");
+        let _ = writeln!(out, "news_feed_page_cache_hit_ratio {}", hits as f64 / (hits + misses).max(1) as f64);
+        let _ = writeln!(out, "# HELP news_feed_page_builds_shared_total Feed page requests that waited on an identical in-flight build.");
+        let _ = writeln!(out, "# TYPE news_feed_page_builds_shared_total counter");
+        let _ = writeln!(out, "news_feed_page_builds_shared_total {}", self.page_builds.shared());
+        let _ = writeln!(out, "# HELP news_feed_trending_refreshes_shared_total Trending lookups that waited on an in-flight refresh.");
+        let _ = writeln!(out, "# TYPE news_feed_trending_refreshes_shared_total counter");
+        let _ = writeln!(out, "news_feed_trending_refreshes_shared_total {}", self.feed_mixer.refreshes_shared());
+        out.push_str(&self.pipeline.metrics());
+        out
+    }
+
+    // Items delivered after `since`, oldest `limit` first so nothing is skipped,
+    // returned newest first along with the cursor for the next poll
+    pub fn new_items(&self, user_id: &UserId, since: &FeedCursor, limit: usize) -> (Vec<HydratedPost>, FeedCursor) {
+        let feed_items = self.cache.home_feed(user_id);
+        let newer = &feed_items[..since.locate(&feed_items)];
+        let batch = &newer[newer.len().saturating_sub(limit)..];
+        let cursor = batch.first().map(FeedCursor::from_item).unwrap_or_else(|| since.clone());
+        let viewer = self.cache.viewer(user_id);
+        let posts = batch
+            .iter()
+            .filter_map(|item| self.cache.get_post(&item.post_id))
+            .filter_map(|post| self.shape(&viewer, post).ok())
+            .collect();
+        (posts, cursor)
+    }
+
+    // Where the page after the one starting at `start` begins: the first
+    // item past it, or None at the end of the feed. Cursors are found by post
+    // ID, so items fanned in at the front meanwhile don't shift the pages.
+    pub fn next_cursor(&self, user_id: &UserId, start: Option<&FeedCursor>, limit: usize) -> Option<FeedCursor> {
+        let feed_items = self.cache.home_feed(user_id);
+        let start_index = start.map(|cursor| cursor.locate(&feed_items)).unwrap_or(0);
+        feed_items.get(start_index + limit).map(FeedCursor::from_item)
+    }
+
+    pub fn latest_cursor(&self, user_id: &UserId) -> FeedCursor {
+        self.cache
+            .home_feed(user_id)
+            .first()
+            .map(FeedCursor::from_item)
+            .unwrap_or(FeedCursor {
+                timestamp: 0,
+                post_id: PostId::default(),
+            })
+    }
+
+    // An author's own posts, newest first, including any kept out of feeds by
+    // a follower's daily limit. Replies live in their conversations instead,
+    // and anonymous posts are only listed for the author.
+    pub fn profile_timeline(&self, viewer_id: &UserId, author_id: &UserId, offset: usize, limit: usize) -> Timeline {
+        let posts: Vec<Post> = self
+            .cache
+            .get_user_post_ids(author_id)
+            .iter()
+            .rev()
+            .filter_map(|post_id| self.cache.get_post(post_id))
+            .filter(|post| post.in_reply_to.is_none() && (!post.anonymous || viewer_id == author_id))
+            .collect();
+        let next_cursor = (posts.len() > offset + limit).then(|| (offset + limit).to_string());
+        let viewer = self.cache.viewer(viewer_id);
+        Timeline {
+            posts: posts
+                .into_iter()
+                .skip(offset)
+                .take(limit)
+                .inspect(|post| self.record_reach(viewer_id, post, Channel::Profile))
+                .filter_map(|post| self.shape(&viewer, post).ok())
+                .collect(),
+            next_cursor,
+        }
+    }
+
+    // Posts using a hashtag, newest first, replies included. Posts the viewer
+    // can't see, or that are withheld from them, are left out before paging.
+    pub fn hashtag_timeline(&self, viewer_id: &UserId, tag: &TagId, offset: usize, limit: usize) -> Timeline {
+        let viewer = self.cache.viewer(viewer_id);
+        let posts: Vec<Post> = self
+            .cache
+            .hashtag_post_ids(tag)
+            .iter()
+            .filter_map(|post_id| self.cache.get_post(post_id))
+            .filter(|post| self.cache.post_restriction(&viewer, post).is_none())
+            .filter(|post| self.cache.tombstone_for(viewer_id, &post.id).is_none())
+            .collect();
+        let next_cursor = (posts.len() > offset + limit).then(|| (offset + limit).to_string());
+        Timeline {
+            posts: posts
+                .into_iter()
+                .skip(offset)
+                .take(limit)
+                .filter_map(|post| self.shape(&viewer, post).ok())
+                .collect(),
+            next_cursor,
+        }
+    }
+
+    // Posts the viewer was invited to co-author and hasn't answered, newest first
+    pub fn co_author_requests(&self, viewer_id: &UserId) -> Timeline {
+        let viewer = self.cache.viewer(viewer_id);
+        Timeline {
+            posts: self
+                .cache
+                .pending_co_authorships(viewer_id)
+                .iter()
+                .filter_map(|post_id| self.cache.get_post(post_id))
+                .filter_map(|post| self.shape(&viewer, post).ok())
+                .collect(),
+            next_cursor: None,
+        }
+    }
+
+    pub fn shape(&self, viewer: &ViewerContext, post: Post) -> Result<HydratedPost, PostUnavailable> {
+        self.hydrator.shape(viewer, post)
+    }
+}
@@ -0,0 +1,215 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::cache::Cache;
+use crate::i18n::Catalogs;
+use crate::ids::{PostId, UserId};
+use crate::login_history::LoginRecord;
+use crate::now_millis;
+use crate::saved_searches::SavedSearchMatch;
+use crate::timezones::ClockTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    NewPost,  // an account with the bell on posted
+    NewLogin, // a login from a device or country the account hasn't used
+    SavedSearch, // new posts match one of the user's saved searches
+    Like,        // someone liked the user's post
+    Reply,       // someone replied to the user's post
+    Follow,      // someone followed the user
+    CoAuthorRequest, // someone invited the user to co-author a post
+    EventReminder,   // an event the user RSVPed to starts soon
+}
+
+impl NotificationKind {
+    // A digest email's line for this kind, with a {count} to fill in
+    pub fn digest_line(self) -> &'static str {
+        match self {
+            Self::NewPost => "New posts from accounts you turned the bell on for: {count}",
+            Self::NewLogin => "Sign-ins from a new device or location: {count}",
+            Self::SavedSearch => "Saved searches with new posts: {count}",
+            Self::Like => "Likes on your posts: {count}",
+            Self::Reply => "Replies to your posts: {count}",
+            Self::Follow => "New followers: {count}",
+            Self::CoAuthorRequest => "Invitations to co-author a post: {count}",
+            Self::EventReminder => "Reminders of events you RSVPed to: {count}",
+        }
+    }
+}
+
+// Local times between which bell notifications aren't pushed. They still
+// land in the inbox. A window can cross midnight, like 22:00 to 07:00.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuietHours {
+    pub start: ClockTime,
+    pub end: ClockTime,
+}
+
+impl QuietHours {
+    pub fn contains(&self, time: ClockTime) -> bool {
+        match self.start <= self.end {
+            true => self.start <= time && time < self.end,
+            false => time >= self.start || time < self.end,
+        }
+    }
+}
+
+// High-priority notifications are also pushed to the user's devices. Bell,
+// security, and event reminder notifications are high priority; saved search
+// matches, likes, replies, and follows only wait in the inbox.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    High,
+    Normal,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    pub kind: NotificationKind,
+    pub priority: Priority,
+    pub actor_id: UserId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_id: Option<PostId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub login: Option<LoginRecord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub saved_search: Option<SavedSearchMatch>,
+    pub created_at: u64,
+}
+
+impl Notification {
+    pub fn new_post(author_id: &UserId, post_id: &PostId) -> Self {
+        Self {
+            kind: NotificationKind::NewPost,
+            priority: Priority::High,
+            actor_id: author_id.clone(),
+            post_id: Some(post_id.clone()),
+            login: None,
+            saved_search: None,
+            created_at: now_millis(),
+        }
+    }
+
+    // The account is its own actor here
+    pub fn new_login(user_id: &UserId, login: LoginRecord) -> Self {
+        Self {
+            kind: NotificationKind::NewLogin,
+            priority: Priority::High,
+            actor_id: user_id.clone(),
+            post_id: None,
+            login: Some(login),
+            saved_search: None,
+            created_at: now_millis(),
+        }
+    }
+
+    // Points at the newest matching post; the account is its own actor
+    pub fn saved_search(user_id: &UserId, newest_post_id: &PostId, found: SavedSearchMatch) -> Self {
+        Self {
+            kind: NotificationKind::SavedSearch,
+            priority: Priority::Normal,
+            actor_id: user_id.clone(),
+            post_id: Some(newest_post_id.clone()),
+            login: None,
+            saved_search: Some(found),
+            created_at: now_millis(),
+        }
+    }
+
+    // Points at the event post; the author is the actor
+    pub fn event_reminder(author_id: &UserId, post_id: &PostId) -> Self {
+        Self {
+            kind: NotificationKind::EventReminder,
+            priority: Priority::High,
+            actor_id: author_id.clone(),
+            post_id: Some(post_id.clone()),
+            login: None,
+            saved_search: None,
+            created_at: now_millis(),
+        }
+    }
+
+    // Engagement with the user's account: a like or reply points at the post
+    // liked or the reply itself, a follow at nothing
+    pub fn engagement(kind: NotificationKind, actor_id: &UserId, post_id: Option<&PostId>) -> Self {
+        Self {
+            kind,
+            priority: Priority::Normal,
+            actor_id: actor_id.clone(),
+            post_id: post_id.cloned(),
+            login: None,
+            saved_search: None,
+            created_at: now_millis(),
+        }
+    }
+
+    // The same kind, from the same account, about the same post
+    pub fn repeats(&self, other: &Notification) -> bool {
+        self.kind == other.kind && self.actor_id == other.actor_id && self.post_id == other.post_id
+    }
+}
+
+// Sends push notifications to devices. No push provider is wired up yet, so
+// each push is logged instead.
+#[derive(Debug, Default)]
+pub struct PushGateway {
+    sent: AtomicU64,
+    held: AtomicU64, // not sent during quiet hours
+}
+
+impl PushGateway {
+    pub fn send(&self, user_id: &UserId, notification: &Notification, text: &str) {
+        println!(
+            "Push to {}: {:?} from {}: {}",
+            user_id, notification.kind, notification.actor_id, text
+        );
+        self.sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    pub fn hold(&self) {
+        self.held.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn held(&self) -> u64 {
+        self.held.load(Ordering::Relaxed)
+    }
+}
+
+// A notification as one line of text, as pushed and as listed
+pub fn notification_text(cache: &dyn Cache, catalogs: &Catalogs, locale: &str, notification: &Notification) -> String {
+    let from_actor = |message: &str| {
+        let username = cache
+            .get_user(&notification.actor_id)
+            .map(|user| user.username)
+            .unwrap_or_else(|| notification.actor_id.to_string());
+        catalogs.format(locale, message, &[("username", &username)])
+    };
+    match notification.kind {
+        NotificationKind::NewPost => from_actor("New post from @{username}"),
+        NotificationKind::NewLogin => catalogs.text(locale, "New sign-in to your account").to_string(),
+        NotificationKind::SavedSearch => {
+            let query = notification.saved_search.as_ref().map_or("", |found| found.query.as_str());
+            catalogs.format(locale, "New posts match your saved search: {query}", &[("query", query)])
+        }
+        NotificationKind::Like => from_actor("@{username} liked your post"),
+        NotificationKind::Reply => from_actor("@{username} replied to your post"),
+        NotificationKind::Follow => from_actor("@{username} followed you"),
+        NotificationKind::CoAuthorRequest => from_actor("@{username} invited you to co-author a post"),
+        NotificationKind::EventReminder => {
+            let title = notification
+                .post_id
+                .as_ref()
+                .and_then(|post_id| cache.get_post(post_id))
+                .and_then(|post| post.event)
+                .map(|event| event.title)
+                .unwrap_or_default();
+            catalogs.format(locale, "Starting soon: {title}", &[("title", &title)])
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use dashmap::DashMap;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+
+use crate::accounts::{Delegation, Scope};
+use crate::ids::UserId;
+
+// Authorization codes are exchanged right after the redirect
+const CODE_TTL_MILLIS: u64 = 10 * 60 * 1000;
+const MAX_REDIRECT_URIS: usize = 10;
+
+// The account sandbox apps act as with client credentials tokens
+const TEST_USER_ID: &str = "sandbox";
+
+pub fn test_user() -> UserId {
+    UserId::new(TEST_USER_ID)
+}
+
+// What a third-party app can ask for. Account management is never delegated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OAuthScope {
+    ReadFeed,
+    WritePosts,
+    WriteEngagement,
+}
+
+impl OAuthScope {
+    pub const ALL: [OAuthScope; 3] = [Self::ReadFeed, Self::WritePosts, Self::WriteEngagement];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ReadFeed => "read:feed",
+            Self::WritePosts => "write:posts",
+            Self::WriteEngagement => "write:engagement",
+        }
+    }
+
+    // Shown on the consent page
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::ReadFeed => "Read your feed, profile, and notifications",
+            Self::WritePosts => "Publish posts and replies as you",
+            Self::WriteEngagement => "Like posts and follow accounts as you",
+        }
+    }
+
+    pub fn account_scope(self) -> Scope {
+        match self {
+            Self::ReadFeed => Scope::Read,
+            Self::WritePosts => Scope::Post,
+            Self::WriteEngagement => Scope::Engage,
+        }
+    }
+
+    fn from_account_scope(scope: Scope) -> Option<Self> {
+        Self::ALL.into_iter().find(|oauth| oauth.account_scope() == scope)
+    }
+
+    // Parses a space-separated scope parameter; None if any scope is unknown
+    pub fn parse_list(scopes: &str) -> Option<Vec<OAuthScope>> {
+        let mut parsed = Vec::new();
+        for scope in scopes.split_whitespace() {
+            let scope = scope.parse().ok()?;
+            if !parsed.contains(&scope) {
+                parsed.push(scope);
+            }
+        }
+        (!parsed.is_empty()).then_some(parsed)
+    }
+
+    pub fn join(scopes: &[Scope]) -> String {
+        scopes
+            .iter()
+            .filter_map(|&scope| Self::from_account_scope(scope))
+            .map(OAuthScope::as_str)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl FromStr for OAuthScope {
+    type Err = ();
+
+    fn from_str(scope: &str) -> Result<Self, Self::Err> {
+        Self::ALL.into_iter().find(|oauth| oauth.as_str() == scope).ok_or(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OAuthClient {
+    pub client_id: String,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub owner_id: UserId,
+    pub created_at: u64,
+    // Sandbox apps only ever see the sandbox tenant, never production data
+    pub sandbox: bool,
+    #[serde(skip)]
+    secret_hash: String,
+}
+
+// What a user approved for an app; revoking it invalidates the app's tokens
+#[derive(Debug, Clone, Serialize)]
+pub struct OAuthGrant {
+    pub client_id: String,
+    pub client_name: String,
+    pub scopes: String,
+    pub granted_at: u64,
+}
+
+#[derive(Debug)]
+struct AuthorizationCode {
+    client_id: String,
+    redirect_uri: String,
+    user_id: UserId,
+    scopes: Vec<Scope>,
+    two_factor: bool,
+    expires_at: u64,
+}
+
+// Error codes from RFC 6749 section 5.2
+#[derive(Debug, PartialEq, Eq)]
+pub enum OAuthError {
+    InvalidClient,
+    InvalidGrant,
+    UnauthorizedClient,
+}
+
+impl OAuthError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidClient => "invalid_client",
+            Self::InvalidGrant => "invalid_grant",
+            Self::UnauthorizedClient => "unauthorized_client",
+        }
+    }
+}
+
+// Token claims for a successful code exchange
+pub struct Exchange {
+    pub user_id: UserId,
+    pub two_factor: bool,
+    pub delegation: Delegation,
+}
+
+// The authorization server: registered apps, pending authorization codes,
+// and the grants users have approved
+#[derive(Debug, Default)]
+pub struct OAuthProvider {
+    clients: DashMap<String, OAuthClient>,
+    codes: DashMap<String, AuthorizationCode>,
+    grants: DashMap<(UserId, String), OAuthGrant>,
+}
+
+impl OAuthProvider {
+    // Redirect URIs must be https, except for local development
+    pub fn valid_redirect_uri(uri: &str) -> bool {
+        uri.len() <= 512
+            && !uri.contains('#')
+            && (uri.starts_with("https://")
+                || uri.starts_with("http://localhost")
+                || uri.starts_with("http://127.0.0.1"))
+    }
+
+    // Returns the client and its secret, which is only ever shown here
+    pub fn register(
+        &self,
+        owner_id: &UserId,
+        name: &str,
+        redirect_uris: Vec<String>,
+        sandbox: bool,
+        now: u64,
+    ) -> Option<(OAuthClient, String)> {
+        if redirect_uris.is_empty()
+            || redirect_uris.len() > MAX_REDIRECT_URIS
+            || !redirect_uris.iter().all(|uri| Self::valid_redirect_uri(uri))
+        {
+            return None;
+        }
+        let secret = format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+        let client = OAuthClient {
+            client_id: format!("app_{}", uuid::Uuid::new_v4().simple()),
+            name: name.to_string(),
+            redirect_uris,
+            owner_id: owner_id.clone(),
+            created_at: now,
+            sandbox,
+            secret_hash: hash_secret(&secret),
+        };
+        self.clients.insert(client.client_id.clone(), client.clone());
+        Some((client, secret))
+    }
+
+    pub fn client(&self, client_id: &str) -> Option<OAuthClient> {
+        self.clients.get(client_id).map(|client| client.clone())
+    }
+
+    pub fn clients_of(&self, owner_id: &UserId) -> Vec<OAuthClient> {
+        let mut clients: Vec<OAuthClient> = self
+            .clients
+            .iter()
+            .filter(|client| client.owner_id == *owner_id)
+            .map(|client| client.clone())
+            .collect();
+        clients.sort_by_key(|client| client.created_at);
+        clients
+    }
+
+    // Records the user's consent and issues a single-use code for the app
+    pub fn approve(
+        &self,
+        client: &OAuthClient,
+        redirect_uri: &str,
+        user_id: &UserId,
+        scopes: &[OAuthScope],
+        two_factor: bool,
+        now: u64,
+    ) -> String {
+        let scopes: Vec<Scope> = scopes.iter().map(|scope| scope.account_scope()).collect();
+        self.codes.retain(|_, code| code.expires_at > now);
+        // Approving again updates the scopes but keeps earlier tokens valid
+        self.grants
+            .entry((user_id.clone(), client.client_id.clone()))
+            .and_modify(|grant| grant.scopes = OAuthScope::join(&scopes))
+            .or_insert_with(|| OAuthGrant {
+                client_id: client.client_id.clone(),
+                client_name: client.name.clone(),
+                scopes: OAuthScope::join(&scopes),
+                granted_at: now,
+            });
+        let code = uuid::Uuid::new_v4().simple().to_string();
+        self.codes.insert(
+            code.clone(),
+            AuthorizationCode {
+                client_id: client.client_id.clone(),
+                redirect_uri: redirect_uri.to_string(),
+                user_id: user_id.clone(),
+                scopes,
+                two_factor,
+                expires_at: now + CODE_TTL_MILLIS,
+            },
+        );
+        code
+    }
+
+    fn authenticate(&self, client_id: &str, client_secret: &str) -> Result<OAuthClient, OAuthError> {
+        self.client(client_id)
+            .filter(|client| client.secret_hash == hash_secret(client_secret))
+            .ok_or(OAuthError::InvalidClient)
+    }
+
+    pub fn exchange(
+        &self,
+        client_id: &str,
+        client_secret: &str,
+        code: &str,
+        redirect_uri: &str,
+        token_ttl_millis: u64,
+        now: u64,
+    ) -> Result<Exchange, OAuthError> {
+        let client = self.authenticate(client_id, client_secret)?;
+        // Removed first so a code can't be tried twice
+        let (_, code) = self.codes.remove(code).ok_or(OAuthError::InvalidGrant)?;
+        if code.client_id != client.client_id || code.redirect_uri != redirect_uri || code.expires_at <= now {
+            return Err(OAuthError::InvalidGrant);
+        }
+        Ok(Exchange {
+            user_id: code.user_id,
+            two_factor: code.two_factor,
+            delegation: Delegation {
+                client_id: client.client_id,
+                scopes: code.scopes,
+                issued_at: now,
+                expires_at: now + token_ttl_millis,
+            },
+        })
+    }
+
+    // Client credentials grant, for sandbox apps only: the token acts as the
+    // sandbox's test account with every scope, and needs no user's consent
+    pub fn sandbox_credentials(
+        &self,
+        client_id: &str,
+        client_secret: &str,
+        token_ttl_millis: u64,
+        now: u64,
+    ) -> Result<Exchange, OAuthError> {
+        let client = self.authenticate(client_id, client_secret)?;
+        if !client.sandbox {
+            return Err(OAuthError::UnauthorizedClient);
+        }
+        Ok(Exchange {
+            user_id: test_user(),
+            two_factor: false,
+            delegation: Delegation {
+                client_id: client.client_id,
+                scopes: OAuthScope::ALL.iter().map(|scope| scope.account_scope()).collect(),
+                issued_at: now,
+                expires_at: now + token_ttl_millis,
+            },
+        })
+    }
+
+    // A delegated token is good until it expires or the user revokes the
+    // grant; approving the app again later doesn't revive it. Sandbox tokens
+    // for the test account have no grant behind them.
+    pub fn is_active(&self, user_id: &UserId, delegation: &Delegation, now: u64) -> bool {
+        if now >= delegation.expires_at {
+            return false;
+        }
+        if *user_id == test_user() && self.is_sandbox(&delegation.client_id) {
+            return true;
+        }
+        self.grants
+            .get(&(user_id.clone(), delegation.client_id.clone()))
+            .is_some_and(|grant| grant.granted_at <= delegation.issued_at)
+    }
+
+    pub fn is_sandbox(&self, client_id: &str) -> bool {
+        self.clients.get(client_id).is_some_and(|client| client.sandbox)
+    }
+
+    pub fn grants_of(&self, user_id: &UserId) -> Vec<OAuthGrant> {
+        let mut grants: Vec<OAuthGrant> = self
+            .grants
+            .iter()
+            .filter(|grant| grant.key().0 == *user_id)
+            .map(|grant| grant.value().clone())
+            .collect();
+        grants.sort_by_key(|grant| grant.granted_at);
+        grants
+    }
+
+    pub fn revoke(&self, user_id: &UserId, client_id: &str) -> bool {
+        self.grants.remove(&(user_id.clone(), client_id.to_string())).is_some()
+    }
+}
+
+// The page GET /oauth/authorize shows. Its form posts back to the same URL,
+// query string included, so the request is authenticated the same way.
+pub fn consent_page(client: &OAuthClient, username: &str, scopes: &[OAuthScope]) -> String {
+    let items: String = scopes
+        .iter()
+        .map(|scope| format!("<li>{}</li>", escape_html(scope.description())))
+        .collect();
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Authorize {name}</title></head>\n\
+         <body><h1>{name} wants to access your account</h1>\n\
+         <p>Signed in as @{username}. {name} will be able to:</p>\n<ul>{items}</ul>\n\
+         <form method=\"post\">\n\
+         <button type=\"submit\" name=\"decision\" value=\"approve\">Allow</button>\n\
+         <button type=\"submit\" name=\"decision\" value=\"deny\">Cancel</button>\n\
+         </form></body></html>\n",
+        name = escape_html(&client.name),
+        username = escape_html(username),
+        items = items,
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
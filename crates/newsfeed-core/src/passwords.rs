@@ -0,0 +1,121 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use dashmap::DashMap;
+use rand::RngCore;
+use std::sync::Arc;
+
+use crate::ids::UserId;
+use crate::storage::Storage;
+use crate::warn;
+
+const MIN_LENGTH: usize = 8;
+// Argon2 takes any length; this keeps a request from making it hash megabytes
+const MAX_LENGTH: usize = 128;
+const MAX_FAILURES: u32 = 5;
+const LOCKOUT_SECS: u64 = 300;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PasswordError {
+    TooShort,
+    TooLong,
+    Invalid, // wrong, or the account has no password
+    LockedOut,
+}
+
+#[derive(Debug, Default)]
+struct Attempts {
+    failures: u32,
+    locked_until: u64, // seconds
+}
+
+// Argon2id password hashes with the default parameters. Hashing is slow on
+// purpose, so callers run it off the async threads. Five wrong passwords in
+// a row lock the account's logins for five minutes, as with two-factor
+// codes.
+pub struct Passwords {
+    hashes: DashMap<UserId, String>,
+    attempts: DashMap<UserId, Attempts>,
+    storage: Option<Arc<dyn Storage>>, // hashes are written through and read back
+}
+
+impl Passwords {
+    pub fn new(storage: Option<Arc<dyn Storage>>) -> Self {
+        Self {
+            hashes: DashMap::new(),
+            attempts: DashMap::new(),
+            storage,
+        }
+    }
+
+    pub fn check_strength(password: &str) -> Result<(), PasswordError> {
+        let length = password.chars().count();
+        if length < MIN_LENGTH {
+            return Err(PasswordError::TooShort);
+        }
+        if length > MAX_LENGTH {
+            return Err(PasswordError::TooLong);
+        }
+        Ok(())
+    }
+
+    // Hashed before the account exists, so a failed signup stores nothing
+    pub fn hash(password: &str) -> Result<String, PasswordError> {
+        Self::check_strength(password)?;
+        let mut salt = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let salt = SaltString::encode_b64(&salt).expect("16 bytes is a valid salt");
+        Ok(Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .expect("default Argon2 parameters accept any password")
+            .to_string())
+    }
+
+    pub fn set_hash(&self, user_id: &UserId, hash: String) {
+        if let Some(storage) = &self.storage
+            && let Err(e) = storage.set_password_hash(user_id, &hash)
+        {
+            warn!("storage: failed to write password for {}: {}", user_id, e);
+        }
+        self.hashes.insert(user_id.clone(), hash);
+        self.attempts.remove(user_id);
+    }
+
+    pub fn has_password(&self, user_id: &UserId) -> bool {
+        self.stored_hash(user_id).is_some()
+    }
+
+    fn stored_hash(&self, user_id: &UserId) -> Option<String> {
+        if let Some(hash) = self.hashes.get(user_id) {
+            return Some(hash.clone());
+        }
+        let hash = self.storage.as_ref()?.get_password_hash(user_id).ok()??;
+        self.hashes.insert(user_id.clone(), hash.clone());
+        Some(hash)
+    }
+
+    pub fn verify(&self, user_id: &UserId, password: &str, now_secs: u64) -> Result<(), PasswordError> {
+        if self
+            .attempts
+            .get(user_id)
+            .is_some_and(|attempts| now_secs < attempts.locked_until)
+        {
+            return Err(PasswordError::LockedOut);
+        }
+        let accepted = self.stored_hash(user_id).is_some_and(|hash| {
+            PasswordHash::new(&hash).is_ok_and(|parsed| {
+                Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok()
+            })
+        });
+        if accepted {
+            self.attempts.remove(user_id);
+            return Ok(());
+        }
+        let mut attempts = self.attempts.entry(user_id.clone()).or_default();
+        attempts.failures += 1;
+        if attempts.failures >= MAX_FAILURES {
+            attempts.failures = 0;
+            attempts.locked_until = now_secs + LOCKOUT_SECS;
+        }
+        Err(PasswordError::Invalid)
+    }
+}
@@ -0,0 +1,362 @@
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use crate::cache::Cache;
+use crate::context::{Cancelled, RequestContext};
+use crate::ids::UserId;
+use crate::mixer::Injection;
+use crate::{FeedCursor, HydratedPost, Post};
+
+// How a page is assembled. Ranked is the default home feed; latest keeps
+// strict delivery order and skips ranking and trending posts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedMode {
+    #[default]
+    Ranked,
+    Latest,
+}
+
+impl FeedMode {
+    pub const ALL: &'static [FeedMode] = &[Self::Ranked, Self::Latest];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Ranked => "ranked",
+            Self::Latest => "latest",
+        }
+    }
+}
+
+// How the organic posts on a ranked page are ordered. Personalized uses the
+// viewer's interests, feedback, and vector; chronological keeps delivery
+// order; engagement favours liked and replied-to posts, fading with age.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RankingStrategy {
+    #[default]
+    Personalized,
+    Chronological,
+    Engagement,
+}
+
+impl RankingStrategy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Personalized => "personalized",
+            Self::Chronological => "chronological",
+            Self::Engagement => "engagement",
+        }
+    }
+}
+
+// One page request, as every stage sees it
+pub struct FeedRequest<'a> {
+    pub ctx: &'a RequestContext,
+    pub limit: usize,
+    pub start: Option<&'a FeedCursor>,
+    pub mode: FeedMode,
+    pub ranking: RankingStrategy,
+}
+
+// A post on its way through the pipeline, and why it is there
+pub struct Candidate {
+    pub post: Post,
+    pub injected: Option<Injection>,
+    pub campaign_id: Option<String>,
+}
+
+impl Candidate {
+    pub fn organic(post: Post) -> Self {
+        Self {
+            post,
+            injected: None,
+            campaign_id: None,
+        }
+    }
+}
+
+// Where a page's posts come from
+pub trait CandidateSource: Send + Sync {
+    fn candidates(&self, request: &FeedRequest<'_>) -> Vec<Candidate>;
+}
+
+// Drops candidates the viewer shouldn't see
+pub trait CandidateFilter: Send + Sync {
+    fn keep(&self, request: &FeedRequest<'_>, candidate: &Candidate) -> bool;
+}
+
+// Reorders the organic page
+pub trait Ranker: Send + Sync {
+    fn rank(&self, request: &FeedRequest<'_>, page: Vec<Candidate>) -> Vec<Candidate>;
+}
+
+// Adds posts from outside the viewer's network
+pub trait Mixer: Send + Sync {
+    fn mix<'a>(&'a self, request: &'a FeedRequest<'_>, page: Vec<Candidate>) -> BoxFuture<'a, Vec<Candidate>>;
+}
+
+// Attaches author, counters, viewer state, and media; None drops a post the
+// viewer mustn't see
+pub trait Hydrator: Send + Sync {
+    fn hydrate(&self, viewer_id: &UserId, candidate: Candidate) -> Option<HydratedPost>;
+}
+
+// Last changes to the finished page
+pub trait PostProcessor: Send + Sync {
+    fn process(&self, request: &FeedRequest<'_>, page: Vec<HydratedPost>) -> Vec<HydratedPost>;
+}
+
+// A stage and the modes it runs in
+struct Staged<T: ?Sized> {
+    stage: Arc<T>,
+    modes: &'static [FeedMode],
+}
+
+impl<T: ?Sized> Staged<T> {
+    fn runs_in(&self, mode: FeedMode) -> bool {
+        self.modes.contains(&mode)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum StageKind {
+    Source,
+    Filter,
+    Rank,
+    Mix,
+    Hydrate,
+    PostProcess,
+}
+
+impl StageKind {
+    const ALL: [StageKind; 6] = [
+        Self::Source,
+        Self::Filter,
+        Self::Rank,
+        Self::Mix,
+        Self::Hydrate,
+        Self::PostProcess,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Source => "source",
+            Self::Filter => "filter",
+            Self::Rank => "rank",
+            Self::Mix => "mix",
+            Self::Hydrate => "hydrate",
+            Self::PostProcess => "post_process",
+        }
+    }
+}
+
+// Cumulative time per stage, for the metrics endpoint
+#[derive(Default)]
+struct StageTimings {
+    runs: [AtomicU64; 6],
+    micros: [AtomicU64; 6],
+}
+
+impl StageTimings {
+    fn record(&self, kind: StageKind, started: Instant) {
+        let index = kind as usize;
+        self.runs[index].fetch_add(1, Ordering::Relaxed);
+        self.micros[index].fetch_add(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+// A ranker and the strategies it is part of. Rankers only run in ranked mode.
+struct StrategyRanker {
+    stage: Arc<dyn Ranker>,
+    strategies: &'static [RankingStrategy],
+}
+
+// Assembles a feed page: candidate sourcing, filtering, ranking, mixing,
+// hydration, then post-processing. Every stage but the source and the
+// hydrator is optional and registered for the modes it applies to, or for
+// rankers, the strategies.
+pub struct FeedPipeline {
+    source: Arc<dyn CandidateSource>,
+    filters: Vec<Staged<dyn CandidateFilter>>,
+    rankers: Vec<StrategyRanker>,
+    mixers: Vec<Staged<dyn Mixer>>,
+    hydrator: Arc<dyn Hydrator>,
+    post_processors: Vec<Staged<dyn PostProcessor>>,
+    timings: StageTimings,
+}
+
+impl FeedPipeline {
+    pub fn new(source: Arc<dyn CandidateSource>, hydrator: Arc<dyn Hydrator>) -> Self {
+        Self {
+            source,
+            filters: Vec::new(),
+            rankers: Vec::new(),
+            mixers: Vec::new(),
+            hydrator,
+            post_processors: Vec::new(),
+            timings: StageTimings::default(),
+        }
+    }
+
+    pub fn filter(mut self, stage: Arc<dyn CandidateFilter>, modes: &'static [FeedMode]) -> Self {
+        self.filters.push(Staged { stage, modes });
+        self
+    }
+
+    pub fn ranker(mut self, stage: Arc<dyn Ranker>, strategies: &'static [RankingStrategy]) -> Self {
+        self.rankers.push(StrategyRanker { stage, strategies });
+        self
+    }
+
+    pub fn mixer(mut self, stage: Arc<dyn Mixer>, modes: &'static [FeedMode]) -> Self {
+        self.mixers.push(Staged { stage, modes });
+        self
+    }
+
+    pub fn post_processor(mut self, stage: Arc<dyn PostProcessor>, modes: &'static [FeedMode]) -> Self {
+        self.post_processors.push(Staged { stage, modes });
+        self
+    }
+
+    // Stops between stages, between hydrated posts, and at the mixers' await
+    // points once the request is cancelled or out of time
+    pub async fn assemble(&self, request: &FeedRequest<'_>) -> Result<Vec<HydratedPost>, Cancelled> {
+        let ctx = request.ctx;
+        let started = Instant::now();
+        let mut page = self.source.candidates(request);
+        self.timings.record(StageKind::Source, started);
+
+        ctx.check()?;
+        let started = Instant::now();
+        let filters: Vec<_> = self.filters.iter().filter(|filter| filter.runs_in(request.mode)).collect();
+        page.retain(|candidate| filters.iter().all(|filter| filter.stage.keep(request, candidate)));
+        self.timings.record(StageKind::Filter, started);
+
+        ctx.check()?;
+        let started = Instant::now();
+        if request.mode == FeedMode::Ranked {
+            for ranker in self.rankers.iter().filter(|ranker| ranker.strategies.contains(&request.ranking)) {
+                page = ranker.stage.rank(request, page);
+            }
+        }
+        self.timings.record(StageKind::Rank, started);
+
+        let started = Instant::now();
+        for mixer in self.mixers.iter().filter(|mixer| mixer.runs_in(request.mode)) {
+            page = ctx.run(mixer.stage.mix(request, page)).await?;
+        }
+        self.timings.record(StageKind::Mix, started);
+
+        let started = Instant::now();
+        let mut hydrated = self.hydrate(ctx, page)?;
+        self.timings.record(StageKind::Hydrate, started);
+
+        ctx.check()?;
+        let started = Instant::now();
+        for processor in self.post_processors.iter().filter(|processor| processor.runs_in(request.mode)) {
+            hydrated = processor.stage.process(request, hydrated);
+        }
+        self.timings.record(StageKind::PostProcess, started);
+        Ok(hydrated)
+    }
+
+    // Hydrates the page one candidate at a time, in page order. Hydrators
+    // read the in-process cache and don't wait on anything, so running them
+    // side by side would gain nothing; a hydrator that fetched from a remote
+    // store would need to become async first.
+    fn hydrate(&self, ctx: &RequestContext, page: Vec<Candidate>) -> Result<Vec<HydratedPost>, Cancelled> {
+        let mut hydrated = Vec::with_capacity(page.len());
+        for candidate in page {
+            ctx.check()?;
+            hydrated.extend(self.hydrator.hydrate(&ctx.user_id, candidate));
+        }
+        Ok(hydrated)
+    }
+
+    // Prometheus text exposition of time spent per stage
+    pub fn metrics(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP news_feed_pipeline_stage_runs_total Feed pages that went through each assembly stage.");
+        let _ = writeln!(out, "# TYPE news_feed_pipeline_stage_runs_total counter");
+        for kind in StageKind::ALL {
+            let runs = self.timings.runs[kind as usize].load(Ordering::Relaxed);
+            let _ = writeln!(out, "news_feed_pipeline_stage_runs_total{{stage=\"{}\"}} {}", kind.as_str(), runs);
+        }
+        let _ = writeln!(out, "# HELP news_feed_pipeline_stage_seconds_total Time spent in each feed assembly stage.");
+        let _ = writeln!(out, "# TYPE news_feed_pipeline_stage_seconds_total counter");
+        for kind in StageKind::ALL {
+            let micros = self.timings.micros[kind as usize].load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "news_feed_pipeline_stage_seconds_total{{stage=\"{}\"}} {}",
+                kind.as_str(),
+                micros as f64 / 1_000_000.0
+            );
+        }
+        out
+    }
+}
+
+// The viewer's home feed, fanned out or pulled, one page from the cursor on
+pub struct FollowedFeed {
+    pub cache: Arc<dyn Cache>,
+}
+
+impl CandidateSource for FollowedFeed {
+    fn candidates(&self, request: &FeedRequest<'_>) -> Vec<Candidate> {
+        let feed_items = self.cache.home_feed(&request.ctx.user_id);
+        let start_index = request.start.map(|cursor| cursor.locate(&feed_items)).unwrap_or(0);
+        feed_items
+            .into_iter()
+            .skip(start_index)
+            .take(request.limit)
+            .filter_map(|item| self.cache.get_post(&item.post_id))
+            .map(Candidate::organic)
+            .collect()
+    }
+}
+
+// Posts hidden after they were delivered
+pub struct HiddenPosts {
+    pub cache: Arc<dyn Cache>,
+}
+
+impl CandidateFilter for HiddenPosts {
+    fn keep(&self, request: &FeedRequest<'_>, candidate: &Candidate) -> bool {
+        !self.cache.is_hidden(&request.ctx.user_id, &candidate.post.id)
+    }
+}
+
+// Screen reader users get posts with described media first. Only organic
+// posts move; injected posts keep their slots. The sort is stable, so
+// recency order holds within each group.
+pub struct AccessibilityOrder {
+    pub cache: Arc<dyn Cache>,
+}
+
+impl PostProcessor for AccessibilityOrder {
+    fn process(&self, request: &FeedRequest<'_>, mut page: Vec<HydratedPost>) -> Vec<HydratedPost> {
+        if !self.cache.get_preferences(&request.ctx.user_id).screen_reader {
+            return page;
+        }
+        let slots: Vec<usize> = (0..page.len()).filter(|&index| page[index].injected.is_none()).collect();
+        let mut organic: Vec<HydratedPost> = slots
+            .iter()
+            .rev()
+            .map(|&index| page.remove(index))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .collect();
+        organic.sort_by_key(|hydrated| hydrated.post.missing_alt_text());
+        for (index, hydrated) in slots.into_iter().zip(organic) {
+            page.insert(index, hydrated);
+        }
+        page
+    }
+}
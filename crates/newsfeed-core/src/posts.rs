@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::cache::Cache;
+use crate::emoji::CustomEmoji;
+use crate::events::EventStore;
+use crate::ids::{PostId, UserId};
+use crate::jobs::{Job, JobQueue};
+use crate::{CoAuthor, FeedEvent, Post, PostDraft, content, debug, now_millis};
+
+pub struct PostService {
+    pub cache: Arc<dyn Cache>,
+    pub events: Arc<EventStore<FeedEvent>>,
+}
+
+impl PostService {
+    pub fn new(cache: Arc<dyn Cache>, events: Arc<EventStore<FeedEvent>>) -> Self {
+        Self { cache, events }
+    }
+
+    // Users @mentioned and custom emoji used in the text
+    pub fn tags(&self, text: &str) -> (Vec<UserId>, Vec<CustomEmoji>) {
+        let mentions = content::extract_mentions(text)
+            .iter()
+            .filter_map(|username| self.cache.find_user_id_by_username(username))
+            .collect();
+        let emojis = content::extract_shortcodes(text)
+            .iter()
+            .filter_map(|shortcode| self.cache.get_emoji(shortcode))
+            .collect();
+        (mentions, emojis)
+    }
+
+    pub async fn create_post(&self, user_id: &UserId, draft: PostDraft) -> Post {
+        let post = self.new_post(user_id, draft);
+        self.events.publish(FeedEvent::PostCreated(Box::new(post.clone())));
+        debug!("Post created: {}", post.id);
+        post
+    }
+
+    // The body is saved before the post is published, so no feed shows a
+    // card for an article that can't be opened yet
+    pub async fn create_article(&self, user_id: &UserId, draft: PostDraft, markdown: String) -> Post {
+        let post = self.new_post(user_id, draft);
+        self.cache.set_article(&post.id, markdown);
+        self.events.publish(FeedEvent::PostCreated(Box::new(post.clone())));
+        debug!("Article created: {}", post.id);
+        post
+    }
+
+    pub fn new_post(&self, user_id: &UserId, draft: PostDraft) -> Post {
+        let (mentions, emojis) = self.tags(&draft.content);
+        let hashtags = content::extract_hashtags(&draft.content);
+
+        Post {
+            id: PostId::new(format!("post_{}", Uuid::new_v4())),
+            user_id: user_id.clone(),
+            content: draft.content,
+            image_url: draft.image_url,
+            video_url: draft.video_url,
+            alt_text: draft.alt_text,
+            in_reply_to: draft.in_reply_to,
+            mentions,
+            hashtags,
+            reply_policy: draft.reply_policy,
+            emojis,
+            timestamp: now_millis(),
+            like_count: 0,
+            reply_count: 0,
+            edited_at: None,
+            anonymous: draft.anonymous,
+            license: draft.license,
+            co_authors: draft
+                .co_authors
+                .into_iter()
+                .map(|user_id| CoAuthor { user_id, accepted: false })
+                .collect(),
+            event: draft.event,
+            article: draft.article,
+        }
+    }
+
+    // New text for an existing post. Media, the reply policy and the
+    // post's place in feeds stay as they were.
+    pub async fn edit_post(&self, mut post: Post, content: String, alt_text: Option<String>) -> Post {
+        let (mentions, emojis) = self.tags(&content);
+        post.hashtags = content::extract_hashtags(&content);
+        post.content = content;
+        post.alt_text = alt_text;
+        post.mentions = mentions;
+        post.emojis = emojis;
+        post.edited_at = Some(now_millis());
+
+        self.events.publish(FeedEvent::PostEdited(Box::new(post.clone())));
+        debug!("Post edited: {}", post.id);
+        post
+    }
+
+    pub async fn delete_post(&self, post_id: &PostId) {
+        self.events.publish(FeedEvent::PostDeleted { post_id: post_id.clone() });
+        debug!("Post deleted: {}", post_id);
+    }
+
+    // Publishes drafts as a chain where each post replies to the previous
+    // one. Callers validate every draft first so the thread lands whole.
+    pub async fn create_thread(&self, user_id: &UserId, drafts: Vec<PostDraft>) -> Vec<Post> {
+        let mut posts: Vec<Post> = Vec::with_capacity(drafts.len());
+        for mut draft in drafts {
+            draft.in_reply_to = posts.last().map(|previous| previous.id.clone());
+            posts.push(self.create_post(user_id, draft).await);
+        }
+
+        if let Some((head, rest)) = posts.split_first() {
+            self.events.publish(FeedEvent::ThreadPublished {
+                head_id: head.id.clone(),
+                post_ids: rest.iter().map(|post| post.id.clone()).collect(),
+            });
+        }
+        posts
+    }
+}
+
+// A post to publish when the job comes due
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScheduledPost {
+    pub user_id: UserId,
+    pub draft: PostDraft,
+}
+
+impl Job for ScheduledPost {
+    const KIND: &'static str = "scheduled_post";
+}
+
+// Reminds an event post's attendees that it starts soon
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EventReminder {
+    pub post_id: PostId,
+}
+
+impl Job for EventReminder {
+    const KIND: &'static str = "event_reminder";
+}
+
+// Queues the reminder for an event post, unless reminders are off or the
+// time for it has already passed
+pub fn schedule_event_reminder(jobs: &Arc<JobQueue>, post: &Post, reminder_secs: u64) {
+    let Some(event) = &post.event else {
+        return;
+    };
+    let remind_at = event.starts_at.saturating_sub(reminder_secs * 1000);
+    if reminder_secs == 0 || remind_at <= now_millis() {
+        return;
+    }
+    jobs.enqueue_at(&EventReminder { post_id: post.id.clone() }, remind_at);
+}
@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::activity::civil_date;
+use crate::hyperloglog::HyperLogLog;
+use crate::ids::UserId;
+
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+pub const RETAINED_DAYS: u64 = 90;
+// Visits kept for the recent visitors list and for spotting repeats
+const RECENT_VISITS: usize = 100;
+// Another visit by the same viewer within this long isn't counted again
+const REPEAT_WINDOW_MILLIS: u64 = 30 * 60 * 1000;
+
+// How a user's visits show up to the profiles they view
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VisitPrivacy {
+    // Counted, and listed by name among recent visitors
+    Named,
+    // Counted, but never listed
+    #[default]
+    Anonymous,
+    // Not recorded at all
+    Off,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Visit {
+    viewer_id: UserId,
+    at: u64,
+    named: bool,
+}
+
+// Who viewed one user's profile: views per UTC day for about three months,
+// the all-time total, unique visitors as a sketch, and the latest visits
+#[derive(Debug, Default, Serialize)]
+pub struct ProfileViews {
+    days: BTreeMap<u64, u32>,
+    total: u64,
+    visitors: HyperLogLog,
+    recent: VecDeque<Visit>, // newest first
+}
+
+#[derive(Debug, Serialize)]
+pub struct DayViews {
+    pub date: String,
+    pub views: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProfileViewStats {
+    pub total_views: u64,
+    pub unique_visitors: u64, // approximate
+    pub views_in_period: u64,
+    pub days: Vec<DayViews>, // days with views, oldest first
+    // Viewers who chose to be named, newest first, with when they last came
+    pub recent_visitors: Vec<(UserId, u64)>,
+}
+
+impl ProfileViews {
+    // False for a repeat visit, which isn't counted again
+    pub fn record(&mut self, viewer_id: &UserId, privacy: VisitPrivacy, now: u64) -> bool {
+        if privacy == VisitPrivacy::Off {
+            return false;
+        }
+        let named = privacy == VisitPrivacy::Named;
+        if let Some(index) = self.recent.iter().position(|visit| &visit.viewer_id == viewer_id)
+            && now.saturating_sub(self.recent[index].at) < REPEAT_WINDOW_MILLIS
+        {
+            // Still move them to the front, in case they changed privacy
+            let mut visit = self.recent.remove(index).expect("index is in range");
+            visit.named = named;
+            self.recent.push_front(visit);
+            return false;
+        }
+
+        let today = now / DAY_MILLIS;
+        *self.days.entry(today).or_default() += 1;
+        if let Some((&oldest, _)) = self.days.first_key_value()
+            && oldest + RETAINED_DAYS <= today
+        {
+            self.days = self.days.split_off(&(today + 1 - RETAINED_DAYS));
+        }
+        self.total += 1;
+        self.visitors.insert(viewer_id);
+        self.recent.push_front(Visit {
+            viewer_id: viewer_id.clone(),
+            at: now,
+            named,
+        });
+        self.recent.truncate(RECENT_VISITS);
+        true
+    }
+
+    // The last `days` days, today included
+    pub fn stats(&self, now: u64, days: u64) -> ProfileViewStats {
+        let today = now / DAY_MILLIS;
+        let first_day = today + 1 - days.clamp(1, RETAINED_DAYS);
+        let days: Vec<DayViews> = self
+            .days
+            .range(first_day..)
+            .map(|(&day, &views)| DayViews {
+                date: civil_date(day),
+                views,
+            })
+            .collect();
+        let mut recent_visitors: Vec<(UserId, u64)> = Vec::new();
+        for visit in self.recent.iter().filter(|visit| visit.named) {
+            if !recent_visitors.iter().any(|(viewer_id, _)| viewer_id == &visit.viewer_id) {
+                recent_visitors.push((visit.viewer_id.clone(), visit.at));
+            }
+        }
+        ProfileViewStats {
+            total_views: self.total,
+            unique_visitors: self.visitors.estimate(),
+            views_in_period: days.iter().map(|day| day.views as u64).sum(),
+            days,
+            recent_visitors,
+        }
+    }
+}
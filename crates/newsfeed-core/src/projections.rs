@@ -0,0 +1,356 @@
+use std::sync::Arc;
+
+use crate::activity::Activity;
+use crate::cache::Cache;
+use crate::embeddings::Vectors;
+use crate::engagement_log::{EngagementLog, Interaction};
+use crate::events::{Projection, Recorded};
+use crate::fanout::FanoutService;
+use crate::ids::UserId;
+use crate::interests::Engagement;
+use crate::notifications::{Notification, NotificationKind};
+use crate::search::{Change, Document, SearchIndex};
+use crate::{ANONYMOUS_AUTHOR, FeedEvent, content};
+
+// Posts, the indexes by author and parent, threads, and like counters
+pub struct PostProjection {
+    pub cache: Arc<dyn Cache>,
+    pub engagement_log: Arc<EngagementLog>,
+}
+
+impl Projection<FeedEvent> for PostProjection {
+    fn name(&self) -> &'static str {
+        "posts"
+    }
+
+    fn apply(&self, recorded: &Recorded<FeedEvent>, replay: bool) {
+        match &recorded.event {
+            FeedEvent::PostCreated(post) => {
+                self.cache.set_post(post.as_ref().clone());
+                self.cache.add_user_post(&post.user_id, &post.id);
+                self.cache.request_co_authors(post);
+                self.cache.index_hashtags(post, &[]);
+                self.cache.typeahead().add_hashtags(&content::extract_hashtags(&post.content));
+                if let Some(parent_id) = &post.in_reply_to {
+                    self.cache.add_reply(parent_id, &post.id);
+                    if !replay {
+                        self.cache.persist_counts(parent_id, 0, 1);
+                    }
+                    if let Some(parent) = self.cache.get_post(parent_id) {
+                        self.cache.record_interest(&post.user_id, &parent, Engagement::Reply, recorded.at);
+                    }
+                }
+                // Activity days are kept by wall clock, so history isn't re-counted
+                if !replay {
+                    self.cache.record_activity(&post.user_id, Activity::Post);
+                }
+            }
+            FeedEvent::PostRestored(post) => {
+                self.cache.restore_post(post.as_ref().clone());
+                self.cache.typeahead().add_hashtags(&content::extract_hashtags(&post.content));
+                if let Some(parent_id) = &post.in_reply_to
+                    && let Some(parent) = self.cache.get_post(parent_id)
+                {
+                    self.cache.record_interest(&post.user_id, &parent, Engagement::Reply, post.timestamp);
+                }
+            }
+            FeedEvent::ThreadPublished { head_id, post_ids } => {
+                self.cache.set_thread(head_id, post_ids.clone());
+            }
+            FeedEvent::CoAuthorAnswered { post_id, user_id, accepted } => {
+                self.cache.answer_co_author(post_id, user_id, *accepted);
+            }
+            FeedEvent::RsvpSet { post_id, user_id, status } => {
+                self.cache.set_rsvp(post_id, user_id, *status);
+            }
+            FeedEvent::PostLiked { user_id, post_id } => {
+                // Repeated likes change nothing
+                let newly_liked = if replay {
+                    self.cache.like_post(user_id, post_id)
+                } else {
+                    self.cache.apply_like(user_id, post_id, true)
+                };
+                if !newly_liked {
+                    return;
+                }
+                let post = self.cache.get_post(post_id);
+                if let Some(post) = &post {
+                    self.cache.record_interest(user_id, post, Engagement::Like, recorded.at);
+                }
+                if !replay {
+                    self.cache.record_activity(user_id, Activity::Like);
+                    if let Some(post) = &post {
+                        self.engagement_log.record(user_id, post, Interaction::Like);
+                    }
+                }
+            }
+            // The like's topic interest and activity stay; they're history
+            FeedEvent::PostUnliked { user_id, post_id } => {
+                if replay {
+                    self.cache.unlike_post(user_id, post_id);
+                } else {
+                    self.cache.apply_like(user_id, post_id, false);
+                }
+            }
+            FeedEvent::PostDeleted { post_id } => {
+                if let Some(post) = self.cache.delete_post(post_id) {
+                    self.cache.typeahead().remove_hashtags(&content::extract_hashtags(&post.content));
+                    if let Some(parent_id) = &post.in_reply_to
+                        && !replay
+                    {
+                        self.cache.persist_counts(parent_id, 0, -1);
+                    }
+                }
+            }
+            FeedEvent::PostEdited(post) => {
+                if let Some(previous) = self.cache.replace_post(post.as_ref().clone()) {
+                    self.cache.typeahead().remove_hashtags(&content::extract_hashtags(&previous.content));
+                    self.cache.typeahead().add_hashtags(&content::extract_hashtags(&post.content));
+                    self.cache.reindex_hashtags(&previous, post);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn reset(&self) {
+        self.cache.clear_posts();
+    }
+}
+
+// Follow edges and their bell and daily-limit settings, saved to storage
+// as they change
+pub struct GraphProjection {
+    pub cache: Arc<dyn Cache>,
+}
+
+impl Projection<FeedEvent> for GraphProjection {
+    fn name(&self) -> &'static str {
+        "graph"
+    }
+
+    fn apply(&self, recorded: &Recorded<FeedEvent>, replay: bool) {
+        let graph = self.cache.graph();
+        let (follower_id, followed_id) = match &recorded.event {
+            FeedEvent::Followed { follower_id, followed_id } if replay => {
+                graph.restore_follow(follower_id, followed_id, recorded.at);
+                (follower_id, followed_id)
+            }
+            FeedEvent::Followed { follower_id, followed_id } => {
+                graph.follow(follower_id, followed_id, recorded.at);
+                (follower_id, followed_id)
+            }
+            FeedEvent::Unfollowed { follower_id, followed_id } if replay => {
+                graph.restore_unfollow(follower_id, followed_id);
+                (follower_id, followed_id)
+            }
+            FeedEvent::Unfollowed { follower_id, followed_id } => {
+                graph.unfollow(follower_id, followed_id, recorded.at);
+                (follower_id, followed_id)
+            }
+            FeedEvent::NotifySet { follower_id, followed_id, enabled } => {
+                graph.set_notify(follower_id, followed_id, *enabled);
+                (follower_id, followed_id)
+            }
+            FeedEvent::DailyLimitSet { follower_id, followed_id, limit } => {
+                graph.set_daily_limit(follower_id, followed_id, *limit);
+                (follower_id, followed_id)
+            }
+            // Already in storage as it is
+            FeedEvent::FollowRestored { follower_id, followed_id, edge } => {
+                graph.restore_edge(follower_id, followed_id, edge.clone());
+                return;
+            }
+            _ => return,
+        };
+        self.cache.store_follow(follower_id, followed_id);
+    }
+
+    fn reset(&self) {
+        self.cache.graph().clear();
+    }
+}
+
+// Likes, replies, follows, and co-author invitations, in the inbox of the
+// account they're about. Only live events notify.
+pub struct NotificationProjection {
+    pub cache: Arc<dyn Cache>,
+}
+
+impl Projection<FeedEvent> for NotificationProjection {
+    fn name(&self) -> &'static str {
+        "notifications"
+    }
+
+    fn apply(&self, recorded: &Recorded<FeedEvent>, replay: bool) {
+        if replay {
+            return;
+        }
+        // Who's notified, who did it, and the notification, which for an
+        // anonymous reply doesn't name them
+        let notices = match &recorded.event {
+            FeedEvent::PostLiked { user_id, post_id } => {
+                let Some(post) = self.cache.get_post(post_id) else {
+                    return;
+                };
+                let notification = Notification::engagement(NotificationKind::Like, user_id, Some(post_id));
+                vec![(post.user_id, user_id.clone(), notification)]
+            }
+            FeedEvent::PostCreated(post) => {
+                let mut notices: Vec<_> = post
+                    .co_authors
+                    .iter()
+                    .map(|co_author| {
+                        let notification =
+                            Notification::engagement(NotificationKind::CoAuthorRequest, &post.user_id, Some(&post.id));
+                        (co_author.user_id.clone(), post.user_id.clone(), notification)
+                    })
+                    .collect();
+                if let Some(parent) = post.in_reply_to.as_ref().and_then(|parent_id| self.cache.get_post(parent_id)) {
+                    let shown_as = match post.anonymous {
+                        true => UserId::new(ANONYMOUS_AUTHOR),
+                        false => post.user_id.clone(),
+                    };
+                    let notification = Notification::engagement(NotificationKind::Reply, &shown_as, Some(&post.id));
+                    notices.push((parent.user_id, post.user_id.clone(), notification));
+                }
+                notices
+            }
+            FeedEvent::Followed { follower_id, followed_id } => {
+                let notification = Notification::engagement(NotificationKind::Follow, follower_id, None);
+                vec![(followed_id.clone(), follower_id.clone(), notification)]
+            }
+            _ => return,
+        };
+        for (user_id, actor_id, notification) in notices {
+            if user_id == actor_id || self.cache.has_blocked(&user_id, &actor_id) {
+                continue;
+            }
+            self.cache.add_notification_once(&user_id, notification);
+        }
+    }
+
+    // Notifications can't be rebuilt from history, so a rebuild keeps the
+    // inbox as it is
+    fn reset(&self) {}
+}
+
+// Home feeds: top-level posts fanned out to the author's followers. Fanout
+// reads the graph as it is when the event is applied, so a rebuilt feed
+// reflects today's follows rather than those at posting time. Restored
+// posts aren't fanned out again; the feeds they reached are in storage.
+// Authors with more followers than fanout writes to are pulled in by
+// Cache::home_feed instead.
+pub struct FeedProjection {
+    pub cache: Arc<dyn Cache>,
+    pub fanout_service: Arc<FanoutService>,
+}
+
+impl Projection<FeedEvent> for FeedProjection {
+    fn name(&self) -> &'static str {
+        "feeds"
+    }
+
+    fn apply(&self, recorded: &Recorded<FeedEvent>, replay: bool) {
+        match &recorded.event {
+            // Followers would know who wrote an anonymous post
+            FeedEvent::PostCreated(post) if post.in_reply_to.is_none() && !post.anonymous => {
+                self.fanout_service
+                    .fanout_post(&post.id, &post.user_id, post.timestamp, !replay);
+            }
+            FeedEvent::PostRestored(post) if post.in_reply_to.is_none() && !post.anonymous => {
+                self.fanout_service.restore_pull(&post.user_id);
+                for co_author in post.co_authors.iter().filter(|co_author| co_author.accepted) {
+                    self.fanout_service.restore_pull(&co_author.user_id);
+                }
+            }
+            // An accepted co-author's followers get the post too. Delivery
+            // markers keep it to once per feed for those who follow both.
+            FeedEvent::CoAuthorAnswered { post_id, user_id, accepted: true } => {
+                if let Some(post) = self.cache.get_post(post_id)
+                    && post.in_reply_to.is_none()
+                {
+                    self.fanout_service.fanout_post(post_id, user_id, post.timestamp, !replay);
+                }
+            }
+            // A rebuilt feed already has the posts of everyone followed
+            FeedEvent::Followed { follower_id, followed_id } if !replay => {
+                self.fanout_service.backfill(follower_id, followed_id);
+            }
+            _ => {}
+        }
+    }
+
+    fn reset(&self) {
+        self.cache.clear_feeds();
+    }
+}
+
+// The post search index. Every event goes to the indexer in sequence order,
+// and it applies them in the background, so search trails writes briefly but
+// never skips one. A rebuild swaps in the new index once it has caught up.
+pub struct SearchProjection {
+    pub index: Arc<SearchIndex>,
+}
+
+impl Projection<FeedEvent> for SearchProjection {
+    fn name(&self) -> &'static str {
+        "search"
+    }
+
+    fn apply(&self, recorded: &Recorded<FeedEvent>, _replay: bool) {
+        let change = match &recorded.event {
+            FeedEvent::PostCreated(post) | FeedEvent::PostRestored(post) | FeedEvent::PostEdited(post) => {
+                Some(Change::Upsert(Document {
+                post_id: post.id.clone(),
+                // So `from:` doesn't find anonymous posts
+                author_id: if post.anonymous { UserId::new(ANONYMOUS_AUTHOR) } else { post.user_id.clone() },
+                text: match &post.alt_text {
+                    Some(alt_text) => format!("{} {}", post.content, alt_text),
+                    None => post.content.clone(),
+                },
+                timestamp: post.timestamp,
+                has_image: post.image_url.is_some(),
+                has_video: post.video_url.is_some(),
+                license: post.license.clone(),
+            }))
+            }
+            FeedEvent::PostDeleted { post_id } => Some(Change::Remove(post_id.clone())),
+            _ => None,
+        };
+        self.index.submit(recorded.seq, change);
+    }
+
+    fn reset(&self) {
+        self.index.reset();
+    }
+}
+
+// Only registered when embeddings are on
+pub struct VectorProjection {
+    pub vectors: Arc<Vectors>,
+}
+
+impl Projection<FeedEvent> for VectorProjection {
+    fn name(&self) -> &'static str {
+        "vectors"
+    }
+
+    fn apply(&self, recorded: &Recorded<FeedEvent>, _replay: bool) {
+        match &recorded.event {
+            FeedEvent::PostCreated(post) | FeedEvent::PostRestored(post) | FeedEvent::PostEdited(post) => self.vectors.submit(
+                post.id.clone(),
+                match &post.alt_text {
+                    Some(alt_text) => format!("{} {}", post.content, alt_text),
+                    None => post.content.clone(),
+                },
+            ),
+            FeedEvent::PostDeleted { post_id } => self.vectors.remove(post_id.clone()),
+            _ => {}
+        }
+    }
+
+    fn reset(&self) {
+        self.vectors.clear();
+    }
+}
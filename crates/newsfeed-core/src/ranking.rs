@@ -0,0 +1,240 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::cache::Cache;
+use crate::config::Settings;
+use crate::embeddings::Vectors;
+use crate::ids::{PostId, TagId, UserId};
+use crate::interests::Interests;
+use crate::pipeline::{Candidate, FeedRequest, Ranker};
+use crate::{Post, now_millis};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignalKind {
+    Hide,
+    NotInterested,
+    Mute,
+    FastScroll, // scrolled past without stopping
+}
+
+impl SignalKind {
+    // How strongly one fresh signal pushes matching posts down
+    fn weight(self) -> f64 {
+        match self {
+            SignalKind::FastScroll => 0.25,
+            SignalKind::Hide => 1.0,
+            SignalKind::NotInterested => 2.0,
+            SignalKind::Mute => 5.0,
+        }
+    }
+}
+
+// Viewer feedback against an author and/or topics. Post-level feedback
+// carries the post's author and hashtags.
+#[derive(Debug, Clone, Serialize)]
+pub struct NegativeSignal {
+    pub kind: SignalKind,
+    pub post_id: Option<PostId>,
+    pub author_id: Option<UserId>,
+    pub topics: Vec<TagId>,
+    pub created_at: u64,
+}
+
+impl NegativeSignal {
+    pub fn for_post(kind: SignalKind, post: &Post) -> Self {
+        Self {
+            kind,
+            post_id: Some(post.id.clone()),
+            author_id: Some(post.user_id.clone()),
+            topics: crate::content::extract_hashtags(&post.content),
+            created_at: now_millis(),
+        }
+    }
+
+    fn matches(&self, post_author: &UserId, post_topics: &[TagId]) -> bool {
+        self.author_id.as_ref() == Some(post_author)
+            || self.topics.iter().any(|topic| post_topics.contains(topic))
+    }
+}
+
+// How much more a reply counts than a like, as in search's engagement boost
+const REPLY_WEIGHT: f64 = 2.0;
+
+// How much of a post's engagement score is there with no engagement at all,
+// so unliked posts still fall back to recency order
+const ENGAGEMENT_BASE: f64 = 1.0;
+
+// How far a post matching the viewer's strongest topic rises; the same as a
+// fresh hide sinks one
+const INTEREST_WEIGHT: f64 = 1.0;
+
+// How far a post by the viewer's strongest author rises. Less than for
+// topics, since following the author already put the post in the feed.
+const AUTHOR_WEIGHT: f64 = 0.5;
+
+// How much interest affinity counts against feedback. The default is what
+// the live feed uses; the eval harness (crates/newsfeed-bin/src/eval.rs)
+// tries others offline.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct RankingWeights {
+    pub topics: f64,
+    pub authors: f64,
+}
+
+impl Default for RankingWeights {
+    fn default() -> Self {
+        Self {
+            topics: INTEREST_WEIGHT,
+            authors: AUTHOR_WEIGHT,
+        }
+    }
+}
+
+// Lower ranks higher: matching signals, decayed, push a post down, and
+// affinity with the viewer's interests pulls it up
+pub fn penalty(
+    weights: &RankingWeights,
+    signals: &[NegativeSignal],
+    interests: &Interests,
+    half_life_millis: f64,
+    post: &Post,
+    now: u64,
+) -> f64 {
+    let topics = crate::content::extract_hashtags(&post.content);
+    let feedback: f64 = signals
+        .iter()
+        .filter(|signal| signal.matches(&post.user_id, &topics))
+        .map(|signal| signal.kind.weight() * decay(half_life_millis, now, signal.created_at))
+        .sum();
+    feedback
+        - weights.topics * interests.topic_affinity(&topics, now)
+        - weights.authors * interests.author_affinity(&post.user_id, now)
+}
+
+// Reorders feed pages using the viewer's feedback and topic interests
+pub struct RankingService {
+    cache: Arc<dyn Cache>,
+    settings: Arc<Settings>,
+}
+
+impl RankingService {
+    pub fn new(cache: Arc<dyn Cache>, settings: Arc<Settings>) -> Self {
+        Self { cache, settings }
+    }
+}
+
+// Halves a signal's weight every half-life
+fn decay(half_life_millis: f64, now: u64, created_at: u64) -> f64 {
+    let age = now.saturating_sub(created_at) as f64;
+    0.5f64.powf(age / half_life_millis)
+}
+
+impl Ranker for RankingService {
+    // Posts sink by the decayed weight of matching signals and rise by how
+    // well their hashtags and author match the viewer's interests; ties keep
+    // recency order
+    fn rank(&self, request: &FeedRequest<'_>, mut page: Vec<Candidate>) -> Vec<Candidate> {
+        let signals = self.cache.get_negative_signals(&request.ctx.user_id);
+        let interests = self.cache.get_interests(&request.ctx.user_id);
+        let now = now_millis();
+        if signals.is_empty() && interests.is_empty() {
+            return page;
+        }
+
+        let half_life_millis = (self.settings.current().signal_half_life_secs.max(1) * 1000) as f64;
+        let weights = RankingWeights::default();
+        let mut scored: Vec<(f64, Candidate)> = page
+            .drain(..)
+            .map(|candidate| {
+                let score = penalty(&weights, &signals, &interests, half_life_millis, &candidate.post, now);
+                (score, candidate)
+            })
+            .collect();
+        scored.sort_by(|a, b| a.0.total_cmp(&b.0));
+        scored.into_iter().map(|(_, candidate)| candidate).collect()
+    }
+}
+
+// Orders a page by likes and replies, the log of likes plus twice the
+// replies, with the score halving every NEWS_FEED_RANKING_HALF_LIFE_SECS of
+// the post's age. Only the viewer's page is reordered, so paging still
+// follows delivery order.
+pub struct EngagementRanker {
+    cache: Arc<dyn Cache>,
+    settings: Arc<Settings>,
+}
+
+impl EngagementRanker {
+    pub fn new(cache: Arc<dyn Cache>, settings: Arc<Settings>) -> Self {
+        Self { cache, settings }
+    }
+}
+
+impl Ranker for EngagementRanker {
+    fn rank(&self, _request: &FeedRequest<'_>, page: Vec<Candidate>) -> Vec<Candidate> {
+        let half_life_millis = (self.settings.current().ranking_half_life_secs.max(1) * 1000) as f64;
+        let now = now_millis();
+        let mut scored: Vec<(f64, Candidate)> = page
+            .into_iter()
+            .map(|candidate| {
+                let counters = self.cache.get_counters(&candidate.post.id);
+                let engagement = (1.0 + counters.likes as f64 + REPLY_WEIGHT * counters.replies as f64).ln();
+                let score = (ENGAGEMENT_BASE + engagement) * decay(half_life_millis, now, candidate.post.timestamp);
+                (score, candidate)
+            })
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.into_iter().map(|(_, candidate)| candidate).collect()
+    }
+}
+
+// How much of a post's place on a page comes from recency; the rest is how
+// close it is to the viewer's vector
+const VECTOR_RECENCY_SHARE: f64 = 0.5;
+
+// Moves posts close to what the viewer writes and likes up the page. Runs
+// only when embeddings are on, and leaves the page alone for viewers with
+// no vector yet.
+pub struct VectorRanker {
+    cache: Arc<dyn Cache>,
+    vectors: Arc<Vectors>,
+}
+
+impl VectorRanker {
+    pub fn new(cache: Arc<dyn Cache>, vectors: Arc<Vectors>) -> Self {
+        Self { cache, vectors }
+    }
+}
+
+impl Ranker for VectorRanker {
+    fn rank(&self, request: &FeedRequest<'_>, page: Vec<Candidate>) -> Vec<Candidate> {
+        let Some(viewer) = self.vectors.user_vector(self.cache.as_ref(), &request.ctx.user_id) else {
+            return page;
+        };
+        let similarities: Vec<Option<f64>> = page
+            .iter()
+            .map(|candidate| self.vectors.similarity(&viewer, &candidate.post.id).map(f64::from))
+            .collect();
+        // Posts not embedded yet count as average for the page
+        let known: Vec<f64> = similarities.iter().flatten().copied().collect();
+        if known.is_empty() {
+            return page;
+        }
+        let average = known.iter().sum::<f64>() / known.len() as f64;
+
+        let count = page.len() as f64;
+        let mut scored: Vec<(f64, Candidate)> = page
+            .into_iter()
+            .zip(similarities)
+            .enumerate()
+            .map(|(position, (candidate, similarity))| {
+                let recency = 1.0 - position as f64 / count;
+                let score = VECTOR_RECENCY_SHARE * recency + (1.0 - VECTOR_RECENCY_SHARE) * similarity.unwrap_or(average);
+                (score, candidate)
+            })
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.into_iter().map(|(_, candidate)| candidate).collect()
+    }
+}
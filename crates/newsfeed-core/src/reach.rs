@@ -0,0 +1,85 @@
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::mixer::Injection;
+
+// How a post got in front of a viewer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    Follow, // delivered to a follower's feed by fanout
+    Trending,
+    Sponsored,
+    Profile, // listed on the author's profile
+}
+
+impl Channel {
+    pub const ALL: &'static [Channel] = &[Self::Follow, Self::Trending, Self::Sponsored, Self::Profile];
+
+    // Where a post on a home feed page came from
+    pub fn on_feed(injected: Option<Injection>) -> Self {
+        match injected {
+            None => Self::Follow,
+            Some(Injection::Trending) => Self::Trending,
+            Some(Injection::Sponsored) => Self::Sponsored,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Follow => "follow",
+            Self::Trending => "trending",
+            Self::Sponsored => "sponsored",
+            Self::Profile => "profile",
+        }
+    }
+}
+
+// One post's impressions by channel. An impression is the post being served
+// on a page; whether it was scrolled into view is what the view beacon says.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Reach {
+    pub follow: u64,
+    pub trending: u64,
+    pub sponsored: u64,
+    pub profile: u64,
+}
+
+impl Reach {
+    pub fn record(&mut self, channel: Channel) {
+        match channel {
+            Channel::Follow => self.follow += 1,
+            Channel::Trending => self.trending += 1,
+            Channel::Sponsored => self.sponsored += 1,
+            Channel::Profile => self.profile += 1,
+        }
+    }
+}
+
+// Impressions of every post by channel, for /metrics
+#[derive(Debug, Default)]
+pub struct ReachTotals {
+    counts: [AtomicU64; 4], // in Channel::ALL order
+}
+
+impl ReachTotals {
+    pub fn record(&self, channel: Channel) {
+        self.counts[channel as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn metrics(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP news_feed_impressions_total Posts served on pages, by distribution channel.");
+        let _ = writeln!(out, "# TYPE news_feed_impressions_total counter");
+        for &channel in Channel::ALL {
+            let _ = writeln!(
+                out,
+                "news_feed_impressions_total{{channel=\"{}\"}} {}",
+                channel.as_str(),
+                self.counts[channel as usize].load(Ordering::Relaxed)
+            );
+        }
+        out
+    }
+}
@@ -0,0 +1,188 @@
+use serde::Serialize;
+use std::fmt::Write as _;
+
+use crate::ids::{PostId, UserId};
+
+// Upper bounds of the latency buckets, in milliseconds; a last bucket holds
+// anything slower
+const BUCKETS_MS: [u64; 18] = [
+    1, 2, 5, 10, 20, 50, 100, 200, 500, 1_000, 2_000, 5_000, 10_000, 20_000, 60_000, 120_000, 300_000, 600_000,
+];
+
+// Publish-to-feed latencies in fixed buckets, so a post fanned out to a
+// million followers takes the same space as one fanned out to ten.
+// Percentiles come out as the bound of the bucket they fall in.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LatencyHistogram {
+    counts: [u64; BUCKETS_MS.len() + 1],
+    sum_ms: u64,
+    max_ms: u64,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency_ms: u64, count: u64) {
+        let bucket = BUCKETS_MS
+            .iter()
+            .position(|&bound| latency_ms <= bound)
+            .unwrap_or(BUCKETS_MS.len());
+        self.counts[bucket] += count;
+        self.sum_ms += latency_ms * count;
+        self.max_ms = self.max_ms.max(latency_ms);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    // The smallest bucket bound that at least `quantile` of the samples are
+    // under; the slowest sample for the overflow bucket
+    pub fn percentile(&self, quantile: f64) -> Option<u64> {
+        let total = self.count();
+        if total == 0 {
+            return None;
+        }
+        let rank = ((total as f64 * quantile).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(BUCKETS_MS.get(bucket).copied().unwrap_or(self.max_ms).min(self.max_ms));
+            }
+        }
+        Some(self.max_ms)
+    }
+
+    pub fn summary(&self) -> Option<LatencySummary> {
+        Some(LatencySummary {
+            p50: self.percentile(0.5)?,
+            p90: self.percentile(0.9)?,
+            p99: self.percentile(0.99)?,
+            max: self.max_ms,
+        })
+    }
+
+    // Prometheus histogram lines for `name`, in seconds. `labels` go before
+    // `le`, e.g. `kind="fanout"`, or are empty.
+    pub fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let (prefix, series) = match labels {
+            "" => (String::new(), String::new()),
+            labels => (format!("{},", labels), format!("{{{}}}", labels)),
+        };
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS_MS.iter().zip(&self.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{{}le=\"{}\"}} {}", name, prefix, *bound as f64 / 1000.0, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{{}le=\"+Inf\"}} {}", name, prefix, self.count());
+        let _ = writeln!(out, "{}_sum{} {}", name, series, self.sum_ms as f64 / 1000.0);
+        let _ = writeln!(out, "{}_count{} {}", name, series, self.count());
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencySummary {
+    p50: u64,
+    p90: u64,
+    p99: u64,
+    max: u64,
+}
+
+// How a follower's copy of a post turned out
+#[derive(Debug, Clone, Copy)]
+pub enum Outcome {
+    Delivered,
+    Skipped,   // already had it, or hid it
+    Throttled, // over their daily limit for the author
+}
+
+// Fanout progress for one post: how many followers it was meant for, and
+// how many have been handled so far. Kept on the node that ran the fanout;
+// counts for feeds on other nodes come from their acknowledgements.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeliveryReceipt {
+    author_id: UserId,
+    published_at: u64,
+    followers: u32,
+    delivered: u32,
+    skipped: u32,
+    throttled: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    completed_at: Option<u64>,
+    latency_ms: LatencyHistogram,
+}
+
+impl DeliveryReceipt {
+    pub fn new(author_id: UserId, published_at: u64, followers: u32) -> Self {
+        Self {
+            author_id,
+            published_at,
+            followers,
+            delivered: 0,
+            skipped: 0,
+            throttled: 0,
+            completed_at: (followers == 0).then_some(published_at),
+            latency_ms: LatencyHistogram::default(),
+        }
+    }
+
+    // Records `count` followers handled at `at` (Unix millis)
+    pub fn record(&mut self, outcome: Outcome, count: u32, at: u64) {
+        match outcome {
+            Outcome::Delivered => {
+                self.delivered += count;
+                self.latency_ms.record(at.saturating_sub(self.published_at), count as u64);
+            }
+            Outcome::Skipped => self.skipped += count,
+            Outcome::Throttled => self.throttled += count,
+        }
+        if self.completed_at.is_none() && self.remaining() == 0 {
+            self.completed_at = Some(at);
+        }
+    }
+
+    pub fn delivered(&self) -> u32 {
+        self.delivered
+    }
+
+    pub fn remaining(&self) -> u32 {
+        self.followers
+            .saturating_sub(self.delivered + self.skipped + self.throttled)
+    }
+
+    pub fn report(&self, post_id: &PostId) -> ReceiptReport {
+        ReceiptReport {
+            post_id: post_id.clone(),
+            author_id: self.author_id.clone(),
+            published_at: self.published_at,
+            followers: self.followers,
+            delivered: self.delivered,
+            skipped: self.skipped,
+            throttled: self.throttled,
+            remaining: self.remaining(),
+            completed_at: self.completed_at,
+            latency_ms: self.latency_ms.summary(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReceiptReport {
+    post_id: PostId,
+    author_id: UserId,
+    published_at: u64,
+    followers: u32,
+    delivered: u32,
+    skipped: u32,
+    throttled: u32,
+    remaining: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    completed_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<LatencySummary>, // publish to feed insert
+}
+
+impl ReceiptReport {
+    pub fn author_id(&self) -> &UserId {
+        &self.author_id
+    }
+}
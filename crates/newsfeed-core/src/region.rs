@@ -0,0 +1,29 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Where an account's data has to be stored, e.g. "eu" or "us"
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String")]
+pub struct Region(String);
+
+impl Region {
+    pub fn new(code: &str) -> Self {
+        Self(code.trim().to_ascii_lowercase())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Region {
+    fn from(code: String) -> Self {
+        Self::new(&code)
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
@@ -0,0 +1,112 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use crate::cache::Cache;
+use crate::content::extract_hashtags;
+use crate::embeddings::Vectors;
+use crate::ids::{PostId, TagId};
+use crate::search::SearchIndex;
+use crate::Post;
+
+// How much each signal, itself 0-1, counts towards a related post's score
+const HASHTAG_WEIGHT: f64 = 0.4;
+const ENGAGER_WEIGHT: f64 = 0.35;
+const TEXT_WEIGHT: f64 = 0.25;
+
+// Related posts kept per post; pages ask for fewer
+pub const MAX_RELATED: usize = 50;
+
+// Bounds on the work for one post: candidates from the search index, and the
+// likers whose other likes are looked at
+const TEXT_CANDIDATES: usize = 200;
+const MAX_ENGAGERS: usize = 200;
+const MAX_LIKES_PER_ENGAGER: usize = 200;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RelatedPost {
+    pub post_id: PostId,
+    pub score: f64,
+    pub shared_hashtags: Vec<TagId>,
+    pub shared_engagers: u32, // users who liked both posts
+    pub text_similarity: f64,
+}
+
+// Related posts as computed for every viewer; who may see each one is
+// checked when a page is served
+#[derive(Debug, Clone, Serialize)]
+pub struct CachedRelated {
+    pub computed_at: u64,
+    pub posts: Vec<RelatedPost>,
+}
+
+#[derive(Default)]
+struct Signals {
+    text_similarity: f64,
+    shared_engagers: u32,
+}
+
+// Posts like `post`, best first. Candidates are posts sharing its words
+// (hashtags included) and posts its likers also liked; each is scored by
+// the overlap of hashtags, the overlap of likers, and text similarity.
+// With embeddings on, the nearest posts by vector are candidates too, and
+// text similarity is the better of the word and vector measures.
+pub fn compute(cache: &dyn Cache, search: &SearchIndex, vectors: Option<&Vectors>, post: &Post) -> Vec<RelatedPost> {
+    let mut candidates: HashMap<PostId, Signals> = HashMap::new();
+    for (post_id, similarity) in search.similar(&post.id, TEXT_CANDIDATES) {
+        candidates.entry(post_id).or_default().text_similarity = similarity;
+    }
+    let own_vector = vectors.and_then(|vectors| vectors.post_vector(&post.id));
+    if let (Some(vectors), Some(_)) = (vectors, &own_vector) {
+        for (post_id, _) in vectors.similar_posts(&post.id, TEXT_CANDIDATES) {
+            candidates.entry(post_id).or_default();
+        }
+    }
+    let likers = cache.post_likers(&post.id, MAX_ENGAGERS);
+    for liker in &likers {
+        for liked in cache.liked_posts(liker, MAX_LIKES_PER_ENGAGER) {
+            if liked != post.id {
+                candidates.entry(liked).or_default().shared_engagers += 1;
+            }
+        }
+    }
+
+    let hashtags: HashSet<TagId> = extract_hashtags(&post.content).into_iter().collect();
+    let mut related: Vec<RelatedPost> = candidates
+        .into_iter()
+        .filter_map(|(post_id, mut signals)| {
+            let candidate = cache.get_post(&post_id)?;
+            if let (Some(vectors), Some(own_vector)) = (vectors, &own_vector)
+                && let Some(similarity) = vectors.similarity(own_vector, &post_id)
+            {
+                signals.text_similarity = signals.text_similarity.max(similarity.max(0.0) as f64);
+            }
+            let theirs: HashSet<TagId> = extract_hashtags(&candidate.content).into_iter().collect();
+            let mut shared_hashtags: Vec<TagId> = hashtags.intersection(&theirs).cloned().collect();
+            shared_hashtags.sort();
+            let hashtag_overlap = match hashtags.union(&theirs).count() {
+                0 => 0.0,
+                union => shared_hashtags.len() as f64 / union as f64,
+            };
+            // Cosine of the two sets of likers
+            let their_likes = cache.get_counters(&post_id).likes.max(signals.shared_engagers);
+            let engager_overlap = if signals.shared_engagers == 0 {
+                0.0
+            } else {
+                (signals.shared_engagers as f64 / (likers.len() as f64 * their_likes as f64).sqrt()).min(1.0)
+            };
+            let score = HASHTAG_WEIGHT * hashtag_overlap
+                + ENGAGER_WEIGHT * engager_overlap
+                + TEXT_WEIGHT * signals.text_similarity;
+            (score > 0.0).then_some(RelatedPost {
+                post_id,
+                score,
+                shared_hashtags,
+                shared_engagers: signals.shared_engagers,
+                text_similarity: signals.text_similarity,
+            })
+        })
+        .collect();
+    related.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| b.post_id.cmp(&a.post_id)));
+    related.truncate(MAX_RELATED);
+    related
+}
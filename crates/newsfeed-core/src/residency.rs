@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+pub use crate::region::Region;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ResidencyError {
+    UnknownRegion,
+    CrossRegionRead,
+}
+
+// Sends each account's stored files (profile images, transcoded video) to
+// the backend of the account's region. This node serves `local`; with cross-
+// region reads denied, it refuses to read from any other region's backend.
+pub struct StorageRouter {
+    local: Region,
+    backends: HashMap<Region, PathBuf>,
+    deny_cross_region_reads: bool,
+}
+
+impl StorageRouter {
+    pub fn new(config: &Config) -> Self {
+        let local = Region::new(&config.region);
+        let mut backends: HashMap<Region, PathBuf> = config
+            .region_backends
+            .iter()
+            .map(|(region, root)| (Region::new(region), root.clone()))
+            .collect();
+        // The node's own region falls back to the media directory
+        backends
+            .entry(local.clone())
+            .or_insert_with(|| config.media_dir.clone());
+        Self {
+            local,
+            backends,
+            deny_cross_region_reads: !config.cross_region_reads,
+        }
+    }
+
+    // Accounts without a region live in the node's own
+    pub fn resolve(&self, region: Option<&Region>) -> Region {
+        region.cloned().unwrap_or_else(|| self.local.clone())
+    }
+
+    pub fn is_known(&self, region: &Region) -> bool {
+        self.backends.contains_key(region)
+    }
+
+    pub fn regions(&self) -> Vec<Region> {
+        let mut regions: Vec<Region> = self.backends.keys().cloned().collect();
+        regions.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        regions
+    }
+
+    // Writes always go to the data's own region, wherever the node is
+    pub fn write_root(&self, region: &Region) -> Result<&Path, ResidencyError> {
+        self.backends
+            .get(region)
+            .map(PathBuf::as_path)
+            .ok_or(ResidencyError::UnknownRegion)
+    }
+
+    pub fn read_root(&self, region: &Region) -> Result<&Path, ResidencyError> {
+        if self.deny_cross_region_reads && *region != self.local {
+            return Err(ResidencyError::CrossRegionRead);
+        }
+        self.write_root(region)
+    }
+}
+
+// Checks that every storage root exists (creating it if needed) and takes
+// writes, by writing and removing a probe file
+pub fn check_roots(config: &Config) -> std::io::Result<()> {
+    let roots = std::iter::once(&config.media_dir).chain(config.region_backends.iter().map(|(_, root)| root));
+    for root in roots {
+        std::fs::create_dir_all(root)?;
+        let probe = root.join(format!(".startup-probe-{}", std::process::id()));
+        std::fs::write(&probe, b"ok")?;
+        std::fs::remove_file(&probe)?;
+    }
+    Ok(())
+}
+
+// Moves a directory tree between backends. A rename only works within one
+// filesystem, so anything else is copied and then removed.
+pub async fn move_tree(from: &Path, to: &Path) -> std::io::Result<()> {
+    if !tokio::fs::try_exists(from).await? {
+        return Ok(());
+    }
+    if let Some(parent) = to.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    if tokio::fs::rename(from, to).await.is_ok() {
+        return Ok(());
+    }
+    copy_tree(from.to_path_buf(), to.to_path_buf()).await?;
+    tokio::fs::remove_dir_all(from).await
+}
+
+async fn copy_tree(from: PathBuf, to: PathBuf) -> std::io::Result<()> {
+    let mut pending = vec![(from, to)];
+    while let Some((from, to)) = pending.pop() {
+        tokio::fs::create_dir_all(&to).await?;
+        let mut entries = tokio::fs::read_dir(&from).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let target = to.join(entry.file_name());
+            if entry.file_type().await?.is_dir() {
+                pending.push((entry.path(), target));
+            } else {
+                tokio::fs::copy(entry.path(), target).await?;
+            }
+        }
+    }
+    Ok(())
+}
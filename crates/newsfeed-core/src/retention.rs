@@ -0,0 +1,36 @@
+use std::collections::HashMap;
+
+use crate::config::Config;
+use crate::ids::UserId;
+
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+// How long posts are kept. Days of 0 mean forever, both for the deployment
+// default and for an account's override.
+#[derive(Debug)]
+pub struct RetentionPolicy {
+    post_days: u64,
+    overrides: HashMap<UserId, u64>,
+    pub dry_run: bool,
+}
+
+impl RetentionPolicy {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            post_days: config.retention_post_days,
+            overrides: config.retention_overrides.iter().cloned().collect(),
+            dry_run: config.retention_dry_run,
+        }
+    }
+
+    // Nothing to reap when no one has a limit
+    pub fn is_enabled(&self) -> bool {
+        self.post_days > 0 || self.overrides.values().any(|&days| days > 0)
+    }
+
+    // Posts by the author created before this are expired
+    pub fn cutoff(&self, author_id: &UserId, now: u64) -> Option<u64> {
+        let days = self.overrides.get(author_id).copied().unwrap_or(self.post_days);
+        (days > 0).then(|| now.saturating_sub(days.saturating_mul(DAY_MILLIS)))
+    }
+}
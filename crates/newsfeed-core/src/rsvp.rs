@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::ids::UserId;
+
+pub const MAX_EVENT_TITLE_CHARS: usize = 100;
+pub const MAX_EVENT_LOCATION_CHARS: usize = 200;
+
+// What makes a post an event; set at creation only
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventDetails {
+    pub title: String,
+    pub starts_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ends_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+}
+
+impl EventDetails {
+    // Events without an end are over once they've started
+    pub fn is_over(&self, now: u64) -> bool {
+        self.ends_at.unwrap_or(self.starts_at) <= now
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RsvpStatus {
+    Going,
+    Interested,
+}
+
+// Who answered one event post, and how
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(transparent)]
+pub struct Rsvps {
+    answers: HashMap<UserId, RsvpStatus>,
+}
+
+impl Rsvps {
+    // None withdraws the user's answer
+    pub fn set(&mut self, user_id: &UserId, status: Option<RsvpStatus>) {
+        match status {
+            Some(status) => self.answers.insert(user_id.clone(), status),
+            None => self.answers.remove(user_id),
+        };
+    }
+
+    pub fn status(&self, user_id: &UserId) -> Option<RsvpStatus> {
+        self.answers.get(user_id).copied()
+    }
+
+    // Everyone going or interested, who get the reminder
+    pub fn attendees(&self) -> Vec<UserId> {
+        self.answers.keys().cloned().collect()
+    }
+
+    pub fn summary(&self, viewer_id: Option<&UserId>) -> RsvpSummary {
+        let going = self.answers.values().filter(|&&status| status == RsvpStatus::Going).count();
+        RsvpSummary {
+            going,
+            interested: self.answers.len() - going,
+            viewer: viewer_id.and_then(|viewer_id| self.status(viewer_id)),
+        }
+    }
+}
+
+// RSVP counts shown on an event post
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RsvpSummary {
+    pub going: usize,
+    pub interested: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub viewer: Option<RsvpStatus>, // the viewer's own answer
+}
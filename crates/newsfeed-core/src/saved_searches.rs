@@ -0,0 +1,37 @@
+use serde::Serialize;
+
+// Searches one account can keep
+pub const MAX_SAVED_SEARCHES: usize = 25;
+
+// A search query kept by a user, checked in the background for new posts
+#[derive(Debug, Clone, Serialize)]
+pub struct SavedSearch {
+    pub id: String,
+    pub query: String,
+    pub created_at: u64,
+    // Posts published up to here have been looked at, so only later ones
+    // can be new matches
+    pub checked_through: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_match_at: Option<u64>, // when new matches were last found
+}
+
+impl SavedSearch {
+    pub fn new(id: String, query: String, now: u64) -> Self {
+        Self {
+            id,
+            query,
+            created_at: now,
+            checked_through: now,
+            last_match_at: None,
+        }
+    }
+}
+
+// What a saved search notification points at
+#[derive(Debug, Clone, Serialize)]
+pub struct SavedSearchMatch {
+    pub search_id: String,
+    pub query: String,
+    pub new_matches: usize,
+}
@@ -0,0 +1,556 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc;
+
+use crate::ids::{PostId, UserId};
+
+// Bumped whenever tokenizing or what gets indexed changes. An index built
+// under an older version is brought up to date by rebuilding the `search`
+// projection from the event log.
+pub const SCHEMA_VERSION: u32 = 3;
+
+const MAX_TERM_CHARS: usize = 64;
+
+// BM25 parameters: how quickly repeats of a term stop adding relevance, and
+// how much a long post's relevance is scaled down
+const BM25_K1: f64 = 1.2;
+const BM25_B: f64 = 0.75;
+
+// The share of its score a post keeps however old it is
+const RECENCY_FLOOR: f64 = 0.25;
+
+// Terms in more posts than this say little about what a post is about, and
+// walking their postings would be slow, so `similar` skips them
+const MAX_SIMILAR_POSTINGS: usize = 5_000;
+
+// Lowercased words in order, repeats included. Punctuation splits words, so
+// `#rust` and `@alice` are found by `rust` and `alice`.
+fn tokens(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.chars().take(MAX_TERM_CHARS).collect::<String>().to_lowercase())
+}
+
+// Lowercased words, each once
+pub fn terms(text: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+    tokens(text).filter(|term| seen.insert(term.clone())).collect()
+}
+
+// A post as the index sees it
+pub struct Document {
+    pub post_id: PostId,
+    pub author_id: UserId,
+    pub text: String,
+    pub timestamp: u64,
+    pub has_image: bool,
+    pub has_video: bool,
+    pub license: Option<String>,
+}
+
+// What an event changes in the index
+pub enum Change {
+    Upsert(Document),
+    Remove(PostId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Has {
+    Image,
+    Video,
+    Media, // either
+}
+
+// A parsed search query: words that must all appear, plus filters written
+// as `from:alice`, `has:image`, `has:video`, `has:media`, `license:cc`,
+// `since:2024-05-01`, and `until:2024-06-01`. Dates are UTC days; `until` is
+// exclusive. `license:` matches licenses starting with its value.
+#[derive(Debug, Default)]
+pub struct Query {
+    pub terms: Vec<String>,
+    pub from: Option<String>, // username, without the @
+    pub has: Vec<Has>,
+    pub license: Option<String>, // lowercased prefix
+    pub since: Option<u64>, // Unix millis
+    pub until: Option<u64>,
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut query = Query::default();
+        let mut text = Vec::new();
+        for word in input.split_whitespace() {
+            let Some((operator, value)) = word.split_once(':') else {
+                text.push(word);
+                continue;
+            };
+            match operator.to_lowercase().as_str() {
+                "from" => {
+                    let username = value.trim_start_matches('@');
+                    if username.is_empty() {
+                        return Err("from: needs a username".to_string());
+                    }
+                    query.from = Some(username.to_string());
+                }
+                "has" => query.has.push(match value.to_lowercase().as_str() {
+                    "image" => Has::Image,
+                    "video" => Has::Video,
+                    "media" => Has::Media,
+                    _ => return Err(format!("has:{} isn't one of image, video, or media", value)),
+                }),
+                "license" => {
+                    if value.is_empty() {
+                        return Err("license: needs a license, e.g. license:cc".to_string());
+                    }
+                    query.license = Some(value.to_lowercase());
+                }
+                "since" => query.since = Some(parse_day(value).ok_or_else(|| format!("since:{} isn't a YYYY-MM-DD date", value))?),
+                "until" => query.until = Some(parse_day(value).ok_or_else(|| format!("until:{} isn't a YYYY-MM-DD date", value))?),
+                // Not an operator, e.g. a URL
+                _ => text.push(word),
+            }
+        }
+        query.terms = terms(&text.join(" "));
+        Ok(query)
+    }
+}
+
+// Midnight UTC of a YYYY-MM-DD date, in Unix millis
+fn parse_day(value: &str) -> Option<u64> {
+    let mut parts = value.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if !(1970..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    // Days from the civil calendar, counting years from March so the leap
+    // day comes last
+    let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    u64::try_from(days * 86_400_000).ok()
+}
+
+// A post matching the query, with its BM25 text relevance
+pub struct Hit {
+    pub post_id: PostId,
+    pub timestamp: u64,
+    pub relevance: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sort {
+    #[default]
+    Top,
+    Latest,
+}
+
+// How the `top` order mixes text relevance with recency and engagement
+pub struct Ranking {
+    pub now: u64,
+    pub half_life_millis: f64,
+    pub engagement_weight: f64,
+}
+
+// Orders hits for a results page. `top` multiplies text relevance by an
+// engagement boost, the log of likes plus twice the replies, and by a
+// recency factor that halves every half-life down to a floor. `latest` is
+// newest first.
+pub fn rank(mut hits: Vec<Hit>, sort: Sort, ranking: &Ranking, engagement: impl Fn(&PostId) -> (u32, u32)) -> Vec<PostId> {
+    match sort {
+        Sort::Latest => hits.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then_with(|| b.post_id.cmp(&a.post_id))),
+        Sort::Top => {
+            let mut scored: Vec<(f64, Hit)> = hits
+                .into_iter()
+                .map(|hit| {
+                    let (likes, replies) = engagement(&hit.post_id);
+                    let boost = 1.0 + ranking.engagement_weight * (1.0 + likes as f64 + 2.0 * replies as f64).ln();
+                    let age = ranking.now.saturating_sub(hit.timestamp) as f64;
+                    let recency = RECENCY_FLOOR + (1.0 - RECENCY_FLOOR) * 0.5f64.powf(age / ranking.half_life_millis);
+                    (hit.relevance * boost * recency, hit)
+                })
+                .collect();
+            scored.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| b.1.timestamp.cmp(&a.1.timestamp)));
+            hits = scored.into_iter().map(|(_, hit)| hit).collect();
+        }
+    }
+    hits.into_iter().map(|hit| hit.post_id).collect()
+}
+
+enum Op {
+    // Every event is sent, changing the index or not, so the indexer always
+    // knows how far through the log it is
+    Apply { seq: u64, change: Option<Change> },
+    // Start again from the first event. The current index keeps answering
+    // until the new one has caught up to `through`.
+    Reset { through: u64 },
+}
+
+struct Doc {
+    author_id: UserId,
+    timestamp: u64,
+    has_image: bool,
+    has_video: bool,
+    license: Option<String>,
+    length: u32,                      // words, repeats included
+    frequencies: HashMap<String, u32>, // times each term appears
+}
+
+impl Doc {
+    fn passes(&self, query: &Query) -> bool {
+        query.has.iter().all(|has| match has {
+            Has::Image => self.has_image,
+            Has::Video => self.has_video,
+            Has::Media => self.has_image || self.has_video,
+        }) && query
+            .license
+            .as_ref()
+            .is_none_or(|prefix| self.license.as_ref().is_some_and(|license| license.starts_with(prefix.as_str())))
+            && query.since.is_none_or(|since| self.timestamp >= since)
+            && query.until.is_none_or(|until| self.timestamp < until)
+    }
+}
+
+#[derive(Default)]
+struct Index {
+    docs: HashMap<PostId, Doc>,
+    postings: HashMap<String, HashSet<PostId>>,
+    authors: HashMap<UserId, HashSet<PostId>>,
+    total_length: u64,
+}
+
+impl Index {
+    // Replaces whatever was indexed for the post, so applying a change twice
+    // leaves the same index as applying it once
+    fn apply(&mut self, change: Change) {
+        match change {
+            Change::Upsert(document) => {
+                self.remove(&document.post_id);
+                let mut frequencies: HashMap<String, u32> = HashMap::new();
+                for token in tokens(&document.text) {
+                    *frequencies.entry(token).or_default() += 1;
+                }
+                for term in frequencies.keys() {
+                    self.postings
+                        .entry(term.clone())
+                        .or_default()
+                        .insert(document.post_id.clone());
+                }
+                self.authors
+                    .entry(document.author_id.clone())
+                    .or_default()
+                    .insert(document.post_id.clone());
+                let length = frequencies.values().sum();
+                self.total_length += length as u64;
+                self.docs.insert(
+                    document.post_id,
+                    Doc {
+                        author_id: document.author_id,
+                        timestamp: document.timestamp,
+                        has_image: document.has_image,
+                        has_video: document.has_video,
+                        license: document.license,
+                        length,
+                        frequencies,
+                    },
+                );
+            }
+            Change::Remove(post_id) => self.remove(&post_id),
+        }
+    }
+
+    fn remove(&mut self, post_id: &PostId) {
+        let Some(doc) = self.docs.remove(post_id) else {
+            return;
+        };
+        self.total_length -= doc.length as u64;
+        for term in doc.frequencies.keys() {
+            if let Some(posts) = self.postings.get_mut(term) {
+                posts.remove(post_id);
+                if posts.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        }
+        if let Some(posts) = self.authors.get_mut(&doc.author_id) {
+            posts.remove(post_id);
+            if posts.is_empty() {
+                self.authors.remove(&doc.author_id);
+            }
+        }
+    }
+
+    // Posts containing every term, by `author` if given, that pass the
+    // query's filters. Without terms every hit is equally relevant.
+    fn matching(&self, query: &Query, author: Option<&UserId>) -> Vec<Hit> {
+        let mut sets = Vec::with_capacity(query.terms.len() + 1);
+        for term in &query.terms {
+            match self.postings.get(term) {
+                Some(posts) => sets.push(posts),
+                None => return Vec::new(),
+            }
+        }
+        if let Some(author) = author {
+            match self.authors.get(author) {
+                Some(posts) => sets.push(posts),
+                None => return Vec::new(),
+            }
+        }
+        sets.sort_by_key(|posts| posts.len());
+        let Some((smallest, rest)) = sets.split_first() else {
+            return Vec::new();
+        };
+
+        let count = self.docs.len() as f64;
+        let average_length = (self.total_length as f64 / count).max(1.0);
+        let idf: Vec<f64> = query
+            .terms
+            .iter()
+            .map(|term| {
+                let containing = self.postings.get(term).map_or(0, HashSet::len) as f64;
+                (1.0 + (count - containing + 0.5) / (containing + 0.5)).ln()
+            })
+            .collect();
+        smallest
+            .iter()
+            .filter(|post_id| rest.iter().all(|posts| posts.contains(*post_id)))
+            .filter_map(|post_id| Some((post_id, self.docs.get(post_id)?)))
+            .filter(|(_, doc)| doc.passes(query))
+            .map(|(post_id, doc)| {
+                let relevance = if query.terms.is_empty() {
+                    1.0
+                } else {
+                    let norm = BM25_K1 * (1.0 - BM25_B + BM25_B * doc.length as f64 / average_length);
+                    query
+                        .terms
+                        .iter()
+                        .zip(&idf)
+                        .map(|(term, idf)| {
+                            let frequency = doc.frequencies.get(term).copied().unwrap_or(0) as f64;
+                            idf * frequency * (BM25_K1 + 1.0) / (frequency + norm)
+                        })
+                        .sum()
+                };
+                Hit {
+                    post_id: post_id.clone(),
+                    timestamp: doc.timestamp,
+                    relevance,
+                }
+            })
+            .collect()
+    }
+
+    // Posts sharing terms with `post_id`, most similar first, scored by the
+    // cosine of their tf-idf vectors
+    fn similar(&self, post_id: &PostId, max: usize) -> Vec<(PostId, f64)> {
+        let Some(doc) = self.docs.get(post_id) else {
+            return Vec::new();
+        };
+        let count = self.docs.len() as f64;
+        let weight = |term: &str, frequency: u32| {
+            let containing = self.postings.get(term).map_or(0, HashSet::len).max(1) as f64;
+            frequency as f64 * (1.0 + count / containing).ln()
+        };
+        let norm = |doc: &Doc| {
+            doc.frequencies
+                .iter()
+                .map(|(term, frequency)| weight(term, *frequency).powi(2))
+                .sum::<f64>()
+                .sqrt()
+        };
+
+        let mut dots: HashMap<&PostId, f64> = HashMap::new();
+        for (term, frequency) in &doc.frequencies {
+            let Some(posts) = self.postings.get(term).filter(|posts| posts.len() <= MAX_SIMILAR_POSTINGS) else {
+                continue;
+            };
+            let own = weight(term, *frequency);
+            for other_id in posts.iter().filter(|other_id| *other_id != post_id) {
+                let Some(other) = self.docs.get(other_id).and_then(|other| other.frequencies.get(term)) else {
+                    continue;
+                };
+                *dots.entry(other_id).or_default() += own * weight(term, *other);
+            }
+        }
+
+        let own_norm = norm(doc);
+        let mut similar: Vec<(PostId, f64)> = dots
+            .into_iter()
+            .filter_map(|(other_id, dot)| {
+                let other_norm = norm(self.docs.get(other_id)?);
+                (own_norm > 0.0 && other_norm > 0.0).then(|| (other_id.clone(), dot / (own_norm * other_norm)))
+            })
+            .collect();
+        similar.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| b.0.cmp(&a.0)));
+        similar.truncate(max);
+        similar
+    }
+}
+
+pub struct SearchHits {
+    pub hits: Vec<Hit>,
+    pub indexed_seq: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchStatus {
+    schema_version: u32,
+    documents: usize,
+    terms: usize,
+    queued_seq: u64,  // the newest event sent to the indexer
+    indexed_seq: u64, // the newest event applied
+    pub lag: u64,
+    pub reindexing: bool,
+}
+
+// Full-text index of posts, kept up to date from the event log. Changes are
+// queued in sequence order and applied by one background task, so writes
+// never wait on indexing and searches may trail them by a few events.
+pub struct SearchIndex {
+    live: RwLock<Index>,
+    ops: mpsc::UnboundedSender<Op>,
+    queued_seq: AtomicU64,
+    indexed_seq: AtomicU64,
+    reindexing: AtomicBool,
+    reindexes: AtomicU64,
+}
+
+impl SearchIndex {
+    // Starts the indexer task
+    pub fn spawn() -> Arc<Self> {
+        let (ops, receiver) = mpsc::unbounded_channel();
+        let index = Arc::new(Self {
+            live: RwLock::new(Index::default()),
+            ops,
+            queued_seq: AtomicU64::new(0),
+            indexed_seq: AtomicU64::new(0),
+            reindexing: AtomicBool::new(false),
+            reindexes: AtomicU64::new(0),
+        });
+        tokio::spawn(index.clone().run(receiver));
+        index
+    }
+
+    // Called for every event, in sequence order
+    pub fn submit(&self, seq: u64, change: Option<Change>) {
+        self.queued_seq.store(seq, Ordering::Relaxed);
+        let _ = self.ops.send(Op::Apply { seq, change });
+    }
+
+    // Rebuilds from the events that follow, which must start again at the
+    // first one. Searches keep using the current index meanwhile.
+    pub fn reset(&self) {
+        let through = self.queued_seq.load(Ordering::Relaxed);
+        if through > 0 {
+            self.reindexing.store(true, Ordering::Relaxed);
+        }
+        let _ = self.ops.send(Op::Reset { through });
+    }
+
+    // Empties the index along with the event log it was built from
+    pub fn clear(&self) {
+        self.queued_seq.store(0, Ordering::Relaxed);
+        let _ = self.ops.send(Op::Reset { through: 0 });
+    }
+
+    async fn run(self: Arc<Self>, mut ops: mpsc::UnboundedReceiver<Op>) {
+        let mut building: Option<(Index, u64)> = None;
+        while let Some(op) = ops.recv().await {
+            match op {
+                Op::Reset { through } => {
+                    self.indexed_seq.store(0, Ordering::Relaxed);
+                    if through == 0 {
+                        building = None;
+                        *self.live.write().expect("search index poisoned") = Index::default();
+                    } else {
+                        building = Some((Index::default(), through));
+                    }
+                }
+                Op::Apply { seq, change } => {
+                    // Already applied; the log only moves forward
+                    if seq <= self.indexed_seq.load(Ordering::Relaxed) {
+                        continue;
+                    }
+                    match building.as_mut() {
+                        Some((shadow, through)) => {
+                            if let Some(change) = change {
+                                shadow.apply(change);
+                            }
+                            if seq >= *through {
+                                let (shadow, _) = building.take().expect("shadow index being built");
+                                *self.live.write().expect("search index poisoned") = shadow;
+                                self.reindexing.store(false, Ordering::Relaxed);
+                                self.reindexes.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                        None => {
+                            if let Some(change) = change {
+                                self.live.write().expect("search index poisoned").apply(change);
+                            }
+                        }
+                    }
+                    self.indexed_seq.store(seq, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    // Every post matching the query, unordered; see rank
+    pub fn search(&self, query: &Query, author: Option<&UserId>) -> SearchHits {
+        let index = self.live.read().expect("search index poisoned");
+        SearchHits {
+            hits: index.matching(query, author),
+            indexed_seq: self.indexed_seq(),
+        }
+    }
+
+    // Up to `max` posts whose text is most like the post's, with their
+    // similarity from 0 to 1; nothing if the post isn't indexed yet
+    pub fn similar(&self, post_id: &PostId, max: usize) -> Vec<(PostId, f64)> {
+        self.live.read().expect("search index poisoned").similar(post_id, max)
+    }
+
+    // The newest event the index reflects
+    pub fn indexed_seq(&self) -> u64 {
+        self.indexed_seq.load(Ordering::Relaxed)
+    }
+
+    pub fn status(&self) -> SearchStatus {
+        let index = self.live.read().expect("search index poisoned");
+        let queued_seq = self.queued_seq.load(Ordering::Relaxed);
+        let indexed_seq = self.indexed_seq.load(Ordering::Relaxed);
+        SearchStatus {
+            schema_version: SCHEMA_VERSION,
+            documents: index.docs.len(),
+            terms: index.postings.len(),
+            queued_seq,
+            indexed_seq,
+            lag: queued_seq.saturating_sub(indexed_seq),
+            reindexing: self.reindexing.load(Ordering::Relaxed),
+        }
+    }
+
+    // Prometheus text exposition of the index size and how far behind it is
+    pub fn metrics(&self) -> String {
+        let status = self.status();
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP news_feed_search_documents Posts in the search index.");
+        let _ = writeln!(out, "# TYPE news_feed_search_documents gauge");
+        let _ = writeln!(out, "news_feed_search_documents {}", status.documents);
+        let _ = writeln!(out, "# HELP news_feed_search_lag_events Events logged but not yet applied to the search index.");
+        let _ = writeln!(out, "# TYPE news_feed_search_lag_events gauge");
+        let _ = writeln!(out, "news_feed_search_lag_events {}", status.lag);
+        let _ = writeln!(out, "# HELP news_feed_search_reindexes_total Search index rebuilds swapped in.");
+        let _ = writeln!(out, "# TYPE news_feed_search_reindexes_total counter");
+        let _ = writeln!(out, "news_feed_search_reindexes_total {}", self.reindexes.load(Ordering::Relaxed));
+        out
+    }
+}
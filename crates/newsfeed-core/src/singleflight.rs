@@ -0,0 +1,55 @@
+use dashmap::DashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::OnceCell;
+
+// Concurrent calls with the same key share one run of the work: the first
+// caller computes, the rest wait for its result. Once the work finishes the key
+// is forgotten, so the next call computes afresh. If the computing caller is
+// cancelled, one of the waiters takes over.
+#[derive(Debug)]
+pub struct SingleFlight<V> {
+    inflight: DashMap<String, Arc<OnceCell<V>>>,
+    shared: AtomicU64,
+}
+
+impl<V> Default for SingleFlight<V> {
+    fn default() -> Self {
+        Self {
+            inflight: DashMap::new(),
+            shared: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<V: Clone> SingleFlight<V> {
+    pub async fn run<F, Fut>(&self, key: &str, work: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        let cell = self.inflight.entry(key.to_string()).or_default().clone();
+        let mut computed = false;
+        let value = cell
+            .get_or_init(|| {
+                computed = true;
+                work()
+            })
+            .await
+            .clone();
+
+        if computed {
+            self.inflight
+                .remove_if(key, |_, current| Arc::ptr_eq(current, &cell));
+        } else {
+            self.shared.fetch_add(1, Ordering::Relaxed);
+        }
+        value
+    }
+
+    // Calls answered with another caller's result
+    pub fn shared(&self) -> u64 {
+        self.shared.load(Ordering::Relaxed)
+    }
+}
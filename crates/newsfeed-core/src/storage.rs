@@ -0,0 +1,127 @@
+use std::fmt;
+
+use crate::account_state::AccountRecord;
+use crate::graph::FollowEdge;
+use crate::ids::{PostId, UserId};
+use crate::two_factor::TwoFactorRecord;
+use crate::{NewsFeedItem, Post, User};
+
+// An entry another instance changed in a shared backend, so copies cached
+// here are stale
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Post(PostId),
+    User(UserId),
+    Feed(UserId),
+}
+
+// Durable copies of what the cache layer holds that can't be rebuilt from
+// anything else: posts, users, home feeds, and follow edges, plus password
+// hashes, account states, two-factor enrollments, and article bodies. The
+// cache writes each change through and reads a missing entry back;
+// everything else it keeps (likers, threads, signals) is still lost on
+// restart. A shared backend is written by several instances at once, so the
+// cache reads feeds, followers, account states, and enrollments from it
+// instead of trusting its own, and lets it decide whether a like is new.
+pub trait Storage: fmt::Debug + Send + Sync + 'static {
+    fn name(&self) -> &'static str;
+
+    fn get_post(&self, post_id: &PostId) -> Result<Option<Post>, String>;
+
+    // Like and reply counts are taken from a new post only. Those of a stored
+    // one only move through add_counts, so an edit or a replay can't roll
+    // them back.
+    fn set_post(&self, post: &Post) -> Result<(), String>;
+
+    // Adds to a stored post's counts; a missing post is left missing
+    fn add_counts(&self, post_id: &PostId, likes: i64, replies: i64) -> Result<(), String>;
+
+    // Records a live like. A backend that keeps who liked what says whether
+    // the like is new and counts it itself, so instances sharing it count
+    // each like once. None leaves both to the cache, which calls add_counts.
+    fn add_like(&self, _post_id: &PostId, _user_id: &UserId) -> Result<Option<bool>, String> {
+        Ok(None)
+    }
+
+    // The same for an unlike: whether the user had liked the post
+    fn remove_like(&self, _post_id: &PostId, _user_id: &UserId) -> Result<Option<bool>, String> {
+        Ok(None)
+    }
+
+    // The post's article body goes with it
+    fn remove_post(&self, post_id: &PostId) -> Result<(), String>;
+
+    // Every stored post, in no particular order
+    fn posts(&self) -> Result<Vec<Post>, String>;
+
+    // An article's markdown, stored under its post's ID apart from the post,
+    // so reading posts back doesn't read every body
+    fn get_article(&self, post_id: &PostId) -> Result<Option<String>, String>;
+
+    fn set_article(&self, post_id: &PostId, markdown: &str) -> Result<(), String>;
+
+    fn get_user(&self, user_id: &UserId) -> Result<Option<User>, String>;
+
+    fn set_user(&self, user: &User) -> Result<(), String>;
+
+    fn users(&self) -> Result<Vec<User>, String>;
+
+    // An encoded Argon2 hash, salt and parameters included
+    fn get_password_hash(&self, user_id: &UserId) -> Result<Option<String>, String>;
+
+    fn set_password_hash(&self, user_id: &UserId, hash: &str) -> Result<(), String>;
+
+    // State and email; accounts without one predate signup and are active
+    fn get_account(&self, user_id: &UserId) -> Result<Option<AccountRecord>, String>;
+
+    fn set_account(&self, user_id: &UserId, record: &AccountRecord) -> Result<(), String>;
+
+    // A TOTP enrollment, pending or enabled, with its recovery code hashes
+    fn get_two_factor(&self, user_id: &UserId) -> Result<Option<TwoFactorRecord>, String>;
+
+    fn set_two_factor(&self, user_id: &UserId, record: &TwoFactorRecord) -> Result<(), String>;
+
+    fn remove_two_factor(&self, user_id: &UserId) -> Result<(), String>;
+
+    // Newest first, as the cache keeps it
+    fn get_feed(&self, user_id: &UserId) -> Result<Option<Vec<NewsFeedItem>>, String>;
+
+    fn set_feed(&self, user_id: &UserId, items: &[NewsFeedItem]) -> Result<(), String>;
+
+    // Items were added to the feed, which is now `feed`. Backends that can
+    // add items on their own override this, so instances sharing them don't
+    // overwrite each other's deliveries.
+    fn add_to_feed(&self, user_id: &UserId, _added: &[NewsFeedItem], feed: &[NewsFeedItem]) -> Result<(), String> {
+        self.set_feed(user_id, feed)
+    }
+
+    // Posts were removed from the feed, which is now `feed`
+    fn remove_from_feed(&self, user_id: &UserId, _removed: &[PostId], feed: &[NewsFeedItem]) -> Result<(), String> {
+        self.set_feed(user_id, feed)
+    }
+
+    fn set_follow(&self, follower_id: &UserId, followed_id: &UserId, edge: &FollowEdge) -> Result<(), String>;
+
+    fn remove_follow(&self, follower_id: &UserId, followed_id: &UserId) -> Result<(), String>;
+
+    // Every edge, as (follower, followed, edge)
+    fn follows(&self) -> Result<Vec<(UserId, UserId, FollowEdge)>, String>;
+
+    // Whether other instances write to it too
+    fn shared(&self) -> bool {
+        false
+    }
+
+    // An account's followers, from a backend that can list them; None means
+    // the cache's graph answers
+    fn followers(&self, _user_id: &UserId) -> Result<Option<Vec<(UserId, FollowEdge)>>, String> {
+        Ok(None)
+    }
+
+    // Calls `apply` from a background thread with each change other
+    // instances make, for as long as the process runs. False if the backend
+    // has no change stream.
+    fn subscribe(&self, _apply: Box<dyn Fn(Change) + Send + Sync>) -> Result<bool, String> {
+        Ok(false)
+    }
+}
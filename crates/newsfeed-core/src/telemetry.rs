@@ -0,0 +1,70 @@
+use serde::Deserialize;
+use std::collections::HashSet;
+
+use crate::ids::PostId;
+use crate::interests::Engagement;
+
+pub const MAX_ITEMS: usize = 100;
+// A post left on screen longer than this was most likely left alone
+pub const MAX_DWELL_MILLIS: u64 = 10 * 60 * 1000;
+// Under this without a click, the viewer scrolled past
+const FAST_SCROLL_MILLIS: u64 = 500;
+// At least this long, the viewer stopped to read
+const READ_MILLIS: u64 = 5_000;
+
+// One feed item as the client saw it
+#[derive(Debug, Clone, Deserialize)]
+pub struct TelemetryItem {
+    pub post_id: PostId,
+    pub dwell_ms: u64, // time on screen
+    #[serde(default)]
+    pub clicked: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TelemetryBatch {
+    pub items: Vec<TelemetryItem>,
+}
+
+impl TelemetryBatch {
+    // A batch is all or nothing: one bad item rejects it
+    pub fn validate(&self) -> Result<(), String> {
+        if self.items.is_empty() {
+            return Err("items is empty".to_string());
+        }
+        if self.items.len() > MAX_ITEMS {
+            return Err(format!("At most {} items per batch", MAX_ITEMS));
+        }
+        let mut seen = HashSet::new();
+        if let Some(item) = self.items.iter().find(|item| !seen.insert(&item.post_id)) {
+            return Err(format!("{} is in the batch twice", item.post_id));
+        }
+        Ok(())
+    }
+}
+
+// What an item says about the viewer's taste
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reading {
+    ScrolledPast,            // a fast-scroll signal against the post
+    Engaged(Engagement),     // a positive interest in the post
+    Neutral,
+}
+
+impl TelemetryItem {
+    pub fn dwell_millis(&self) -> u64 {
+        self.dwell_ms.min(MAX_DWELL_MILLIS)
+    }
+
+    pub fn reading(&self) -> Reading {
+        if self.clicked {
+            Reading::Engaged(Engagement::Click)
+        } else if self.dwell_millis() >= READ_MILLIS {
+            Reading::Engaged(Engagement::Dwell)
+        } else if self.dwell_millis() < FAST_SCROLL_MILLIS {
+            Reading::ScrolledPast
+        } else {
+            Reading::Neutral
+        }
+    }
+}
@@ -0,0 +1,427 @@
+// IANA time zones, read from the system's zoneinfo files (TZif, RFC 8536) in
+// $TZDIR or /usr/share/zoneinfo. A zone is read on first use and kept.
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const DAY_SECS: i64 = 86_400;
+
+// Offsets from UTC over time, in seconds east
+#[derive(Debug)]
+pub struct Zone {
+    transitions: Vec<i64>, // UTC seconds, ascending
+    offsets: Vec<i32>,     // in effect from each transition on
+    initial: i32,          // before the first transition
+    rule: Option<Rule>,    // after the last one, from the file's TZ string
+}
+
+impl Zone {
+    pub fn utc() -> Self {
+        Self {
+            transitions: Vec::new(),
+            offsets: Vec::new(),
+            initial: 0,
+            rule: None,
+        }
+    }
+
+    pub fn offset_at(&self, utc_secs: i64) -> i32 {
+        let after_last = self.transitions.last().is_none_or(|&last| utc_secs >= last);
+        if after_last && let Some(rule) = &self.rule {
+            return rule.offset_at(utc_secs);
+        }
+        match self.transitions.partition_point(|&at| at <= utc_secs) {
+            0 => self.initial,
+            index => self.offsets[index - 1],
+        }
+    }
+
+    // Wall clock seconds, counted as if the wall clock were UTC
+    pub fn local(&self, utc_secs: i64) -> i64 {
+        utc_secs + self.offset_at(utc_secs) as i64
+    }
+
+    // The instant a wall clock time names. A time repeated when clocks go
+    // back is the first of the two; a time skipped when they go forward is
+    // moved forward by the gap, so 02:30 in a 02:00-03:00 gap is 03:30.
+    pub fn to_utc(&self, local_secs: i64) -> i64 {
+        let before = self.offset_at(local_secs - DAY_SECS) as i64;
+        let after = self.offset_at(local_secs + DAY_SECS) as i64;
+        [local_secs - before, local_secs - after]
+            .into_iter()
+            .filter(|&utc| self.local(utc) == local_secs)
+            .min()
+            .unwrap_or(local_secs - before)
+    }
+}
+
+// The zones read so far
+#[derive(Debug)]
+pub struct Timezones {
+    dir: PathBuf,
+    zones: DashMap<String, Arc<Zone>>,
+}
+
+impl Default for Timezones {
+    fn default() -> Self {
+        Self {
+            dir: std::env::var_os("TZDIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("/usr/share/zoneinfo")),
+            zones: DashMap::new(),
+        }
+    }
+}
+
+impl Timezones {
+    // None for names that aren't zones. Only zones that exist are kept, so
+    // made-up names can't grow the map.
+    pub fn get(&self, name: &str) -> Option<Arc<Zone>> {
+        if let Some(zone) = self.zones.get(name) {
+            return Some(zone.clone());
+        }
+        if !valid_name(name) {
+            return None;
+        }
+        let data = std::fs::read(self.dir.join(name)).ok()?;
+        let zone = Arc::new(parse(&data)?);
+        self.zones.insert(name.to_string(), zone.clone());
+        Some(zone)
+    }
+}
+
+// Like "Europe/Berlin" or "America/Argentina/Buenos_Aires"; never a path
+// out of the zoneinfo directory
+fn valid_name(name: &str) -> bool {
+    name.len() <= 64
+        && name.split('/').all(|part| {
+            !part.is_empty()
+                && !part.starts_with('.')
+                && part.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+'))
+        })
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn int(&mut self, size: usize) -> Option<i64> {
+        let bytes = self.bytes(size)?;
+        Some(match size {
+            4 => i32::from_be_bytes(bytes.try_into().ok()?) as i64,
+            _ => i64::from_be_bytes(bytes.try_into().ok()?),
+        })
+    }
+
+    fn count(&mut self) -> Option<usize> {
+        usize::try_from(self.int(4)?).ok()
+    }
+}
+
+struct Header {
+    version: u8,
+    isutcnt: usize,
+    isstdcnt: usize,
+    leapcnt: usize,
+    timecnt: usize,
+    typecnt: usize,
+    charcnt: usize,
+}
+
+impl Header {
+    fn read(reader: &mut Reader) -> Option<Self> {
+        if reader.bytes(4)? != b"TZif" {
+            return None;
+        }
+        let version = reader.bytes(1)?[0];
+        reader.bytes(15)?;
+        Some(Self {
+            version,
+            isutcnt: reader.count()?,
+            isstdcnt: reader.count()?,
+            leapcnt: reader.count()?,
+            timecnt: reader.count()?,
+            typecnt: reader.count()?,
+            charcnt: reader.count()?,
+        })
+    }
+
+    // The data block's length for `time_size`-byte times
+    fn block_len(&self, time_size: usize) -> usize {
+        self.timecnt * (time_size + 1)
+            + self.typecnt * 6
+            + self.charcnt
+            + self.leapcnt * (time_size + 4)
+            + self.isstdcnt
+            + self.isutcnt
+    }
+}
+
+// Version 2 and later files repeat the data with 64-bit times, followed by a
+// TZ string for times after the last transition. Version 1 has only 32-bit
+// times and no TZ string.
+fn parse(data: &[u8]) -> Option<Zone> {
+    let mut reader = Reader { data, pos: 0 };
+    let mut header = Header::read(&mut reader)?;
+    let mut time_size = 4;
+    if header.version >= b'2' {
+        reader.bytes(header.block_len(4))?;
+        header = Header::read(&mut reader)?;
+        time_size = 8;
+    }
+    let transitions = (0..header.timecnt)
+        .map(|_| reader.int(time_size))
+        .collect::<Option<Vec<i64>>>()?;
+    let indices = reader.bytes(header.timecnt)?;
+    let types = (0..header.typecnt)
+        .map(|_| {
+            let offset = reader.int(4)? as i32;
+            reader.bytes(2)?;
+            Some(offset)
+        })
+        .collect::<Option<Vec<i32>>>()?;
+    let offsets = indices
+        .iter()
+        .map(|&index| types.get(index as usize).copied())
+        .collect::<Option<Vec<i32>>>()?;
+    // Abbreviations, leap seconds, and standard/UT indicators aren't needed
+    reader.bytes(header.charcnt + header.leapcnt * (time_size + 4) + header.isstdcnt + header.isutcnt)?;
+    let rule = match time_size {
+        8 => std::str::from_utf8(&data[reader.pos..])
+            .ok()
+            .and_then(|footer| Rule::parse(footer.trim_matches('\n'))),
+        _ => None,
+    };
+    Some(Zone {
+        transitions,
+        offsets,
+        initial: *types.first()?,
+        rule,
+    })
+}
+
+// A POSIX TZ string such as "CET-1CEST,M3.5.0,M10.5.0/3": a standard offset
+// and, for zones with daylight saving time, its offset and when it starts
+// and ends each year. Only the month-week-day form of the dates is read;
+// zones using the others keep their last transition's offset.
+#[derive(Debug)]
+struct Rule {
+    standard: i32,
+    daylight: Option<(i32, DateRule, DateRule)>,
+}
+
+// Day `weekday` (0 is Sunday) of week `week` (5 is the last) of `month`, at
+// `time` seconds past local midnight
+#[derive(Debug)]
+struct DateRule {
+    month: u32,
+    week: u32,
+    weekday: u32,
+    time: i64,
+}
+
+impl Rule {
+    fn parse(value: &str) -> Option<Self> {
+        let rest = skip_name(value)?;
+        let (standard, rest) = parse_offset(rest)?;
+        // POSIX offsets count hours west of UTC
+        let standard = -standard as i32;
+        if rest.is_empty() {
+            return Some(Self { standard, daylight: None });
+        }
+        let rest = skip_name(rest)?;
+        let (daylight, rest) = match rest.starts_with(',') {
+            true => (standard + 3600, rest),
+            false => {
+                let (offset, rest) = parse_offset(rest)?;
+                (-offset as i32, rest)
+            }
+        };
+        let (start, end) = rest.strip_prefix(',')?.split_once(',')?;
+        Some(Self {
+            standard,
+            daylight: Some((daylight, DateRule::parse(start)?, DateRule::parse(end)?)),
+        })
+    }
+
+    fn offset_at(&self, utc_secs: i64) -> i32 {
+        let Some((daylight, start, end)) = &self.daylight else {
+            return self.standard;
+        };
+        let (year, _, _) = civil_from_days((utc_secs + self.standard as i64).div_euclid(DAY_SECS));
+        // Each change happens at a wall clock time in the offset before it
+        let starts = start.local_secs(year) - self.standard as i64;
+        let ends = end.local_secs(year) - *daylight as i64;
+        let in_daylight = match starts < ends {
+            true => (starts..ends).contains(&utc_secs),
+            // Southern hemisphere: daylight time spans the new year
+            false => !(ends..starts).contains(&utc_secs),
+        };
+        if in_daylight { *daylight } else { self.standard }
+    }
+}
+
+impl DateRule {
+    fn parse(value: &str) -> Option<Self> {
+        let (date, time) = match value.split_once('/') {
+            Some((date, time)) => (date, parse_clock(time)?),
+            None => (value, 2 * 3600),
+        };
+        let mut parts = date.strip_prefix('M')?.split('.');
+        let rule = Self {
+            month: parts.next()?.parse().ok()?,
+            week: parts.next()?.parse().ok()?,
+            weekday: parts.next()?.parse().ok()?,
+            time,
+        };
+        ((1..=12).contains(&rule.month) && (1..=5).contains(&rule.week) && rule.weekday <= 6).then_some(rule)
+    }
+
+    fn local_secs(&self, year: i64) -> i64 {
+        let first = days_from_civil(year, self.month, 1);
+        let next_month = match self.month {
+            12 => days_from_civil(year + 1, 1, 1),
+            month => days_from_civil(year, month + 1, 1),
+        };
+        let first_weekday = weekday(first);
+        let mut day = first + (self.weekday as i64 - first_weekday as i64).rem_euclid(7) + (self.week as i64 - 1) * 7;
+        while day >= next_month {
+            day -= 7;
+        }
+        day * DAY_SECS + self.time
+    }
+}
+
+// Names are letters, or anything in angle brackets ("<+0330>")
+fn skip_name(value: &str) -> Option<&str> {
+    match value.strip_prefix('<') {
+        Some(rest) => Some(&rest[rest.find('>')? + 1..]),
+        None => {
+            let end = value.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(value.len());
+            (end >= 3).then_some(&value[end..])
+        }
+    }
+}
+
+// "[+-]hh[:mm[:ss]]" at the start of `value`, and what follows it
+fn parse_offset(value: &str) -> Option<(i64, &str)> {
+    let end = value
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, ':' | '+' | '-')))
+        .unwrap_or(value.len());
+    Some((parse_clock(&value[..end])?, &value[end..]))
+}
+
+fn parse_clock(value: &str) -> Option<i64> {
+    let (sign, value) = match value.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let mut secs = 0;
+    for (index, part) in value.split(':').enumerate() {
+        if index > 2 || part.is_empty() {
+            return None;
+        }
+        secs += part.parse::<i64>().ok()? * [3600, 60, 1][index];
+    }
+    Some(sign * secs)
+}
+
+// 0 is Sunday; 1970-01-01 was a Thursday
+pub fn weekday(days: i64) -> u32 {
+    (days + 4).rem_euclid(7) as u32
+}
+
+// Days since 1970-01-01 to (year, month, day)
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// (year, month, day) to days since 1970-01-01
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+// "2026-10-18T09:00", with optional seconds and an optional "Z" or
+// "+02:00": the wall clock seconds, and the offset if one was given
+pub fn parse_date_time(value: &str) -> Option<(i64, Option<i32>)> {
+    let (date, time) = value.split_once(['T', ' '])?;
+    let mut date_parts = date.splitn(3, '-');
+    let year: i64 = date_parts.next()?.parse().ok()?;
+    let month: u32 = date_parts.next()?.parse().ok()?;
+    let day: u32 = date_parts.next()?.parse().ok()?;
+    let (clock, offset) = match time.find(['Z', 'z', '+', '-']) {
+        Some(at) if matches!(&time[at..], "Z" | "z") => (&time[..at], Some(0)),
+        Some(at) => (&time[..at], Some(parse_clock(&time[at..])? as i32)),
+        None => (time, None),
+    };
+    let secs = parse_clock(clock.split('.').next()?)?;
+    let valid_date = (1..=12).contains(&month)
+        && day >= 1
+        && civil_from_days(days_from_civil(year, month, day)) == (year, month, day);
+    if !valid_date || !(0..DAY_SECS).contains(&secs) {
+        return None;
+    }
+    Some((days_from_civil(year, month, day) * DAY_SECS + secs, offset))
+}
+
+// A time of day, "HH:MM", kept as minutes past midnight
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ClockTime(u16);
+
+impl ClockTime {
+    pub fn of(local_secs: i64) -> Self {
+        Self((local_secs.rem_euclid(DAY_SECS) / 60) as u16)
+    }
+
+    pub fn hour(self) -> u16 {
+        self.0 / 60
+    }
+}
+
+impl TryFrom<String> for ClockTime {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, String> {
+        let invalid = || format!("expected a time like \"22:00\", got {:?}", value);
+        let (hours, minutes) = value.split_once(':').ok_or_else(invalid)?;
+        if minutes.len() != 2 {
+            return Err(invalid());
+        }
+        let time = match (hours.parse::<u16>(), minutes.parse::<u16>()) {
+            (Ok(hours), Ok(minutes)) if hours <= 23 && minutes <= 59 => hours * 60 + minutes,
+            _ => return Err(invalid()),
+        };
+        Ok(Self(time))
+    }
+}
+
+impl From<ClockTime> for String {
+    fn from(time: ClockTime) -> Self {
+        format!("{:02}:{:02}", time.0 / 60, time.0 % 60)
+    }
+}
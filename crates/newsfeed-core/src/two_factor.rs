@@ -0,0 +1,427 @@
+use dashmap::DashMap;
+use hmac::{Hmac, Mac};
+use qrcode::QrCode;
+use qrcode::render::svg;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::ids::UserId;
+use crate::storage::Storage;
+use crate::warn;
+
+// RFC 6238 defaults, which every authenticator app supports
+const STEP_SECS: u64 = 30;
+const DIGITS: u32 = 6;
+const SECRET_BYTES: usize = 20;
+// Codes from one step either side are accepted to allow for clock drift
+const DRIFT_STEPS: u64 = 1;
+const RECOVERY_CODES: usize = 10;
+const MAX_FAILURES: u32 = 5;
+const LOCKOUT_SECS: u64 = 300;
+const ISSUER: &str = "NewsFeed";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TwoFactorError {
+    AlreadyEnabled,
+    NotEnrolled,
+    InvalidCode,
+    LockedOut,
+    Unavailable, // storage couldn't say whether the account has it on
+}
+
+// Returned by setup; the secret stays pending until a code confirms it
+#[derive(Debug, Serialize)]
+pub struct Enrollment {
+    pub secret: String, // base32, for manual entry
+    pub otpauth_uri: String,
+    pub qr_svg: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TwoFactorStatus {
+    pub enabled: bool,
+    pub recovery_codes_left: usize,
+    pub required: bool,
+}
+
+// Opaque outside this module; storage only serializes it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TwoFactorRecord {
+    secret: Vec<u8>,
+    enabled: bool,
+    // Last step a code was accepted for, so a code can't be replayed
+    last_step: u64,
+    recovery_hashes: Vec<String>,
+    failures: u32,
+    locked_until: u64, // seconds
+}
+
+impl TwoFactorRecord {
+    // Accepts a TOTP code or an unused recovery code (which is then used up)
+    fn check(&mut self, code: &str, now_secs: u64) -> Result<(), TwoFactorError> {
+        if now_secs < self.locked_until {
+            return Err(TwoFactorError::LockedOut);
+        }
+        let code = code.trim();
+        let accepted = if code.contains('-') {
+            let hash = hash_recovery_code(code);
+            let before = self.recovery_hashes.len();
+            self.recovery_hashes.retain(|stored| *stored != hash);
+            self.recovery_hashes.len() < before
+        } else {
+            match matching_step(&self.secret, code, now_secs) {
+                Some(step) if step > self.last_step => {
+                    self.last_step = step;
+                    true
+                }
+                _ => false,
+            }
+        };
+        if accepted {
+            self.failures = 0;
+            return Ok(());
+        }
+        self.failures += 1;
+        if self.failures >= MAX_FAILURES {
+            self.failures = 0;
+            self.locked_until = now_secs + LOCKOUT_SECS;
+        }
+        Err(TwoFactorError::InvalidCode)
+    }
+}
+
+// TOTP enrollment and verification per account, plus the policy requiring it
+// for admins. Records are written through to storage and read back on a
+// miss, or on every check when other instances share the storage, since any
+// of them may have turned two-factor on.
+#[derive(Debug, Default)]
+pub struct TwoFactor {
+    records: DashMap<UserId, TwoFactorRecord>,
+    require_for_admins: AtomicBool,
+    storage: Option<Arc<dyn Storage>>,
+}
+
+impl TwoFactor {
+    pub fn new(require_for_admins: bool, storage: Option<Arc<dyn Storage>>) -> Self {
+        Self {
+            records: DashMap::new(),
+            require_for_admins: AtomicBool::new(require_for_admins),
+            storage,
+        }
+    }
+
+    // A record storage can't be read for is an error, not "not enrolled", so
+    // logins fail closed
+    fn load(&self, user_id: &UserId) -> Result<(), TwoFactorError> {
+        let Some(storage) = &self.storage else {
+            return Ok(());
+        };
+        if !storage.shared() && self.records.contains_key(user_id) {
+            return Ok(());
+        }
+        match storage.get_two_factor(user_id) {
+            Ok(Some(record)) => {
+                self.records.insert(user_id.clone(), record);
+            }
+            Ok(None) => {
+                self.records.remove(user_id);
+            }
+            Err(e) => {
+                warn!("storage: failed to read two-factor for {}: {}", user_id, e);
+                return Err(TwoFactorError::Unavailable);
+            }
+        }
+        Ok(())
+    }
+
+    // Called with no guard held, since a shared backend is a network call
+    fn save(&self, user_id: &UserId, record: &TwoFactorRecord) {
+        if let Some(storage) = &self.storage
+            && let Err(e) = storage.set_two_factor(user_id, record)
+        {
+            warn!("storage: failed to write two-factor for {}: {}", user_id, e);
+        }
+    }
+
+    // Starts (or restarts) enrollment with a fresh secret
+    pub fn setup(&self, user_id: &UserId, account_name: &str) -> Result<Enrollment, TwoFactorError> {
+        self.load(user_id)?;
+        let mut record = self.records.entry(user_id.clone()).or_default();
+        if record.enabled {
+            return Err(TwoFactorError::AlreadyEnabled);
+        }
+        let mut secret = vec![0u8; SECRET_BYTES];
+        rand::thread_rng().fill(&mut secret[..]);
+        let encoded = base32::encode(base32::Alphabet::Rfc4648 { padding: false }, &secret);
+        record.secret = secret;
+        let saved = record.clone();
+        drop(record);
+        self.save(user_id, &saved);
+
+        let otpauth_uri = format!(
+            "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&digits={DIGITS}&period={STEP_SECS}",
+            issuer = ISSUER,
+            account = account_name,
+            secret = encoded,
+        );
+        let qr_svg = QrCode::new(otpauth_uri.as_bytes())
+            .map(|code| code.render::<svg::Color>().min_dimensions(200, 200).build())
+            .unwrap_or_default();
+        Ok(Enrollment {
+            secret: encoded,
+            otpauth_uri,
+            qr_svg,
+        })
+    }
+
+    // Confirms enrollment with a code from the app; returns the recovery
+    // codes, which are only ever shown here
+    pub fn enable(&self, user_id: &UserId, code: &str, now_secs: u64) -> Result<Vec<String>, TwoFactorError> {
+        self.load(user_id)?;
+        let mut record = self
+            .records
+            .get_mut(user_id)
+            .filter(|record| !record.secret.is_empty())
+            .ok_or(TwoFactorError::NotEnrolled)?;
+        if record.enabled {
+            return Err(TwoFactorError::AlreadyEnabled);
+        }
+        if code.contains('-') {
+            return Err(TwoFactorError::InvalidCode);
+        }
+        // Failures are saved too, so the lockout holds across instances
+        let enabled = record.check(code, now_secs).map(|()| {
+            let codes: Vec<String> = (0..RECOVERY_CODES).map(|_| recovery_code()).collect();
+            record.recovery_hashes = codes.iter().map(|code| hash_recovery_code(code)).collect();
+            record.enabled = true;
+            codes
+        });
+        let saved = record.clone();
+        drop(record);
+        self.save(user_id, &saved);
+        enabled
+    }
+
+    pub fn verify(&self, user_id: &UserId, code: &str, now_secs: u64) -> Result<(), TwoFactorError> {
+        self.load(user_id)?;
+        let mut record = self
+            .records
+            .get_mut(user_id)
+            .filter(|record| record.enabled)
+            .ok_or(TwoFactorError::NotEnrolled)?;
+        // The used step or recovery code is saved, so it can't be replayed
+        // through another instance
+        let checked = record.check(code, now_secs);
+        let saved = record.clone();
+        drop(record);
+        self.save(user_id, &saved);
+        checked
+    }
+
+    // Turning 2FA off takes a valid code, so a stolen session can't do it.
+    // It stays on if storage can't forget it.
+    pub fn disable(&self, user_id: &UserId, code: &str, now_secs: u64) -> Result<(), TwoFactorError> {
+        self.verify(user_id, code, now_secs)?;
+        if let Some(storage) = &self.storage
+            && let Err(e) = storage.remove_two_factor(user_id)
+        {
+            warn!("storage: failed to remove two-factor for {}: {}", user_id, e);
+            return Err(TwoFactorError::Unavailable);
+        }
+        self.records.remove(user_id);
+        Ok(())
+    }
+
+    pub fn is_enabled(&self, user_id: &UserId) -> Result<bool, TwoFactorError> {
+        self.load(user_id)?;
+        Ok(self.records.get(user_id).is_some_and(|record| record.enabled))
+    }
+
+    pub fn status(&self, user_id: &UserId, is_admin: bool) -> Result<TwoFactorStatus, TwoFactorError> {
+        self.load(user_id)?;
+        let record = self.records.get(user_id);
+        Ok(TwoFactorStatus {
+            enabled: record.as_ref().is_some_and(|record| record.enabled),
+            recovery_codes_left: record.map_or(0, |record| record.recovery_hashes.len()),
+            required: is_admin && self.required_for_admins(),
+        })
+    }
+
+    pub fn required_for_admins(&self) -> bool {
+        self.require_for_admins.load(Ordering::Relaxed)
+    }
+
+    pub fn set_required_for_admins(&self, required: bool) {
+        self.require_for_admins.store(required, Ordering::Relaxed);
+    }
+}
+
+// Returns the time step the code is valid for, if any
+fn matching_step(secret: &[u8], code: &str, now_secs: u64) -> Option<u64> {
+    if code.len() != DIGITS as usize {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let current = now_secs / STEP_SECS;
+    (current.saturating_sub(DRIFT_STEPS)..=current + DRIFT_STEPS).find(|&step| hotp(secret, step) == code)
+}
+
+// RFC 4226
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    value % 10u32.pow(DIGITS)
+}
+
+// Formatted like "3f9a-c01e" so it can't be mistaken for a TOTP code
+fn recovery_code() -> String {
+    let bytes: [u8; 4] = rand::random();
+    let hex = hex::encode(bytes);
+    format!("{}-{}", &hex[..4], &hex[4..])
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    hex::encode(Sha256::digest(code.trim().to_ascii_lowercase().as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account_state::AccountRecord;
+    use crate::graph::FollowEdge;
+    use crate::ids::PostId;
+    use crate::{NewsFeedItem, Post, User};
+
+    // A backend that can't be reached
+    #[derive(Debug)]
+    struct Down;
+
+    impl Storage for Down {
+        fn name(&self) -> &'static str {
+            "down"
+        }
+        fn get_post(&self, _: &PostId) -> Result<Option<Post>, String> {
+            Err("down".into())
+        }
+        fn set_post(&self, _: &Post) -> Result<(), String> {
+            Err("down".into())
+        }
+        fn add_counts(&self, _: &PostId, _: i64, _: i64) -> Result<(), String> {
+            Err("down".into())
+        }
+        fn remove_post(&self, _: &PostId) -> Result<(), String> {
+            Err("down".into())
+        }
+        fn posts(&self) -> Result<Vec<Post>, String> {
+            Err("down".into())
+        }
+        fn get_article(&self, _: &PostId) -> Result<Option<String>, String> {
+            Err("down".into())
+        }
+        fn set_article(&self, _: &PostId, _: &str) -> Result<(), String> {
+            Err("down".into())
+        }
+        fn get_user(&self, _: &UserId) -> Result<Option<User>, String> {
+            Err("down".into())
+        }
+        fn set_user(&self, _: &User) -> Result<(), String> {
+            Err("down".into())
+        }
+        fn users(&self) -> Result<Vec<User>, String> {
+            Err("down".into())
+        }
+        fn get_password_hash(&self, _: &UserId) -> Result<Option<String>, String> {
+            Err("down".into())
+        }
+        fn set_password_hash(&self, _: &UserId, _: &str) -> Result<(), String> {
+            Err("down".into())
+        }
+        fn get_account(&self, _: &UserId) -> Result<Option<AccountRecord>, String> {
+            Err("down".into())
+        }
+        fn set_account(&self, _: &UserId, _: &AccountRecord) -> Result<(), String> {
+            Err("down".into())
+        }
+        fn get_two_factor(&self, _: &UserId) -> Result<Option<TwoFactorRecord>, String> {
+            Err("down".into())
+        }
+        fn set_two_factor(&self, _: &UserId, _: &TwoFactorRecord) -> Result<(), String> {
+            Err("down".into())
+        }
+        fn remove_two_factor(&self, _: &UserId) -> Result<(), String> {
+            Err("down".into())
+        }
+        fn get_feed(&self, _: &UserId) -> Result<Option<Vec<NewsFeedItem>>, String> {
+            Err("down".into())
+        }
+        fn set_feed(&self, _: &UserId, _: &[NewsFeedItem]) -> Result<(), String> {
+            Err("down".into())
+        }
+        fn set_follow(&self, _: &UserId, _: &UserId, _: &FollowEdge) -> Result<(), String> {
+            Err("down".into())
+        }
+        fn remove_follow(&self, _: &UserId, _: &UserId) -> Result<(), String> {
+            Err("down".into())
+        }
+        fn follows(&self) -> Result<Vec<(UserId, UserId, FollowEdge)>, String> {
+            Err("down".into())
+        }
+    }
+
+    fn current_code(two_factor: &TwoFactor, user_id: &UserId, now_secs: u64) -> String {
+        let secret = two_factor.records.get(user_id).expect("enrolled").secret.clone();
+        format!("{:06}", hotp(&secret, now_secs / STEP_SECS))
+    }
+
+    // RFC 4226 appendix D
+    #[test]
+    fn hotp_matches_the_rfc_vectors() {
+        let secret = b"12345678901234567890";
+        assert_eq!(hotp(secret, 0), 755224);
+        assert_eq!(hotp(secret, 1), 287082);
+        assert_eq!(hotp(secret, 9), 520489);
+    }
+
+    #[test]
+    fn codes_and_recovery_codes_work_once() {
+        let two_factor = TwoFactor::new(false, None);
+        let user_id = UserId::new("ana");
+        let now = 1_700_000_000;
+        two_factor.setup(&user_id, "ana").expect("setup");
+        let recovery = two_factor
+            .enable(&user_id, &current_code(&two_factor, &user_id, now), now)
+            .expect("enable");
+        assert_eq!(recovery.len(), RECOVERY_CODES);
+
+        let later = now + STEP_SECS;
+        let code = current_code(&two_factor, &user_id, later);
+        assert_eq!(two_factor.verify(&user_id, &code, later), Ok(()));
+        assert_eq!(two_factor.verify(&user_id, &code, later), Err(TwoFactorError::InvalidCode));
+
+        assert_eq!(two_factor.verify(&user_id, &recovery[0], later), Ok(()));
+        assert_eq!(two_factor.verify(&user_id, &recovery[0], later), Err(TwoFactorError::InvalidCode));
+        assert_eq!(two_factor.status(&user_id, false).expect("status").recovery_codes_left, RECOVERY_CODES - 1);
+    }
+
+    // An enrollment that can't be read is not "not enrolled"
+    #[test]
+    fn unreadable_storage_fails_closed() {
+        let two_factor = TwoFactor::new(false, Some(Arc::new(Down)));
+        let user_id = UserId::new("ana");
+        assert_eq!(two_factor.is_enabled(&user_id), Err(TwoFactorError::Unavailable));
+        assert_eq!(two_factor.verify(&user_id, "123456", 0), Err(TwoFactorError::Unavailable));
+        assert!(matches!(two_factor.setup(&user_id, "ana"), Err(TwoFactorError::Unavailable)));
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::RwLock;
+
+use crate::ids::{TagId, UserId};
+
+#[derive(Debug)]
+struct Node<V> {
+    children: BTreeMap<char, Node<V>>,
+    values: Vec<V>, // keys ending here
+}
+
+impl<V> Default for Node<V> {
+    fn default() -> Self {
+        Self {
+            children: BTreeMap::new(),
+            values: Vec::new(),
+        }
+    }
+}
+
+impl<V> Node<V> {
+    fn is_empty(&self) -> bool {
+        self.values.is_empty() && self.children.is_empty()
+    }
+}
+
+// A character trie, changed in place as keys come and go; emptied branches
+// are pruned on removal
+#[derive(Debug)]
+pub struct PrefixTrie<V> {
+    root: Node<V>,
+}
+
+impl<V> Default for PrefixTrie<V> {
+    fn default() -> Self {
+        Self {
+            root: Node::default(),
+        }
+    }
+}
+
+impl<V: Clone + PartialEq> PrefixTrie<V> {
+    pub fn insert(&mut self, key: &str, value: V) {
+        let mut node = &mut self.root;
+        for c in key.chars() {
+            node = node.children.entry(c).or_default();
+        }
+        if !node.values.contains(&value) {
+            node.values.push(value);
+        }
+    }
+
+    pub fn remove(&mut self, key: &str, value: &V) {
+        let chars: Vec<char> = key.chars().collect();
+        Self::remove_at(&mut self.root, &chars, value);
+    }
+
+    fn remove_at(node: &mut Node<V>, chars: &[char], value: &V) {
+        let Some((c, rest)) = chars.split_first() else {
+            node.values.retain(|existing| existing != value);
+            return;
+        };
+        let Some(child) = node.children.get_mut(c) else {
+            return;
+        };
+        Self::remove_at(child, rest, value);
+        if child.is_empty() {
+            node.children.remove(c);
+        }
+    }
+
+    // Up to `max` keys starting with `prefix`, shortest first, so an exact
+    // match comes before its completions
+    pub fn complete(&self, prefix: &str, max: usize) -> Vec<(String, V)> {
+        let mut node = &self.root;
+        for c in prefix.chars() {
+            match node.children.get(&c) {
+                Some(child) => node = child,
+                None => return Vec::new(),
+            }
+        }
+        let mut found = Vec::new();
+        let mut queue = VecDeque::from([(prefix.to_string(), node)]);
+        while let Some((key, node)) = queue.pop_front() {
+            for value in &node.values {
+                if found.len() == max {
+                    return found;
+                }
+                found.push((key.clone(), value.clone()));
+            }
+            for (c, child) in &node.children {
+                let mut key = key.clone();
+                key.push(*c);
+                queue.push_back((key, child));
+            }
+        }
+        found
+    }
+}
+
+#[derive(Debug, Default)]
+struct Hashtags {
+    trie: PrefixTrie<TagId>,
+    posts: HashMap<TagId, u32>, // posts using each hashtag
+}
+
+// Prefix indexes for the typeahead: usernames, kept in step with signups and
+// renames, and hashtags, counted as posts are created and deleted
+#[derive(Debug, Default)]
+pub struct Typeahead {
+    usernames: RwLock<PrefixTrie<UserId>>, // lowercased
+    hashtags: RwLock<Hashtags>,
+}
+
+impl Typeahead {
+    pub fn add_username(&self, username: &str, user_id: &UserId) {
+        self.usernames
+            .write()
+            .expect("typeahead poisoned")
+            .insert(&username.to_lowercase(), user_id.clone());
+    }
+
+    pub fn remove_username(&self, username: &str, user_id: &UserId) {
+        self.usernames
+            .write()
+            .expect("typeahead poisoned")
+            .remove(&username.to_lowercase(), user_id);
+    }
+
+    pub fn add_hashtags(&self, hashtags: &[TagId]) {
+        let mut index = self.hashtags.write().expect("typeahead poisoned");
+        for hashtag in hashtags {
+            let posts = index.posts.entry(hashtag.clone()).or_default();
+            *posts += 1;
+            if *posts == 1 {
+                index.trie.insert(hashtag.as_str(), hashtag.clone());
+            }
+        }
+    }
+
+    pub fn remove_hashtags(&self, hashtags: &[TagId]) {
+        let mut index = self.hashtags.write().expect("typeahead poisoned");
+        for hashtag in hashtags {
+            let Some(posts) = index.posts.get_mut(hashtag) else {
+                continue;
+            };
+            *posts -= 1;
+            if *posts == 0 {
+                index.posts.remove(hashtag);
+                index.trie.remove(hashtag.as_str(), hashtag);
+            }
+        }
+    }
+
+    // Lowercased usernames starting with `prefix`, shortest first
+    pub fn usernames(&self, prefix: &str, max: usize) -> Vec<(String, UserId)> {
+        self.usernames.read().expect("typeahead poisoned").complete(prefix, max)
+    }
+
+    // Hashtags starting with `prefix`, shortest first, with their post counts
+    pub fn hashtags(&self, prefix: &str, max: usize) -> Vec<(TagId, u32)> {
+        let index = self.hashtags.read().expect("typeahead poisoned");
+        index
+            .trie
+            .complete(prefix, max)
+            .into_iter()
+            .map(|(_, hashtag)| {
+                let posts = index.posts.get(&hashtag).copied().unwrap_or(0);
+                (hashtag, posts)
+            })
+            .collect()
+    }
+
+    // Hashtags come from posts, and go when the posts are rebuilt
+    pub fn clear_hashtags(&self) {
+        *self.hashtags.write().expect("typeahead poisoned") = Hashtags::default();
+    }
+
+    pub fn clear(&self) {
+        *self.usernames.write().expect("typeahead poisoned") = PrefixTrie::default();
+        self.clear_hashtags();
+    }
+}
@@ -0,0 +1,216 @@
+use std::sync::Arc;
+
+use crate::account_state::{AccountRecord, AccountState, EmailVerifications};
+use crate::cache::Cache;
+use crate::config::Config;
+use crate::email::EmailService;
+use crate::i18n::Catalogs;
+use crate::ids::UserId;
+use crate::login_history::{LoginContext, LoginRecord};
+use crate::notifications::{Notification, PushGateway, notification_text};
+use crate::residency::Region;
+use crate::{User, UsernameError, info, now_millis};
+
+pub fn valid_username(username: &str) -> bool {
+    (3..=15).contains(&username.len())
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Only catches obvious typos; the verification email is the real check
+pub fn valid_email(email: &str) -> bool {
+    email.len() <= 254
+        && email
+            .split_once('@')
+            .is_some_and(|(local, domain)| {
+                !local.is_empty() && domain.contains('.') && !domain.contains('@')
+            })
+}
+
+pub struct UserService {
+    pub cache: Arc<dyn Cache>,
+    pub config: Arc<Config>,
+    pub email: Arc<EmailService>,
+    pub push: Arc<PushGateway>,
+    pub catalogs: Arc<Catalogs>,
+    pub verifications: EmailVerifications,
+}
+
+impl UserService {
+    pub fn new(
+        cache: Arc<dyn Cache>,
+        config: Arc<Config>,
+        email: Arc<EmailService>,
+        push: Arc<PushGateway>,
+        catalogs: Arc<Catalogs>,
+    ) -> Self {
+        Self {
+            cache,
+            config,
+            email,
+            push,
+            catalogs,
+            verifications: EmailVerifications::default(),
+        }
+    }
+
+    // Logins from a new device or country alert the account in-app, by push,
+    // and by email
+    pub fn record_login(&self, user_id: &UserId, context: LoginContext, two_factor: bool) -> LoginRecord {
+        let login = self.cache.record_login(user_id, context, two_factor);
+        if !login.is_anomalous() {
+            return login;
+        }
+        info!(
+            "User {} logged in from a new {} ({})",
+            user_id,
+            if login.new_device { "device" } else { "location" },
+            login.fingerprint
+        );
+        let notification = Notification::new_login(user_id, login.clone());
+        self.cache.add_notification(user_id, notification.clone());
+        let locale = self.cache.user_locale(user_id, &self.catalogs);
+        let text = notification_text(self.cache.as_ref(), &self.catalogs, &locale, &notification);
+        self.push.send(user_id, &notification, &text);
+        if let Some(email) = self.cache.account_record(user_id).email {
+            let catalogs = &self.catalogs;
+            let device = login
+                .user_agent
+                .as_deref()
+                .unwrap_or_else(|| catalogs.text(&locale, "an unknown device"));
+            let place = login
+                .country
+                .as_deref()
+                .unwrap_or_else(|| catalogs.text(&locale, "an unknown location"));
+            self.email.send(
+                &email,
+                catalogs.text(&locale, "New sign-in to your account"),
+                &catalogs.format(
+                    &locale,
+                    "Your account was signed in to from {device} in {place}. If this wasn't you, secure your account now.",
+                    &[("device", device), ("place", place)],
+                ),
+            );
+        }
+        login
+    }
+
+    // New accounts start out pending until their email is verified
+    pub fn register(&self, username: &str, email: &str, region: Option<Region>) -> Result<User, UsernameError> {
+        if !valid_username(username) {
+            return Err(UsernameError::Invalid);
+        }
+        let user = User {
+            id: UserId::new(format!("user_{}", uuid::Uuid::new_v4().simple())),
+            username: username.to_string(),
+            profile_picture: String::new(),
+            profile_picture_variants: Vec::new(),
+            banner_url: None,
+            banner_variants: Vec::new(),
+            location: None,
+            website: None,
+            joined_at: now_millis(),
+            verified: false,
+            username_changed_at: None,
+            timezone: None,
+        };
+        self.cache.create_user(user.clone())?;
+        self.cache.set_account_record(
+            &user.id,
+            AccountRecord {
+                state: AccountState::PendingVerification,
+                email: Some(email.to_string()),
+                email_verified: false,
+                state_changed_at: now_millis(),
+                reason: None,
+                region,
+            },
+        );
+        self.send_verification(&user.id, email);
+        info!("User {} signed up as {}", user.id, user.username);
+        Ok(user)
+    }
+
+    pub fn send_verification(&self, user_id: &UserId, email: &str) {
+        let now = now_millis();
+        let expires_at = now + self.config.email_verification_ttl_secs.saturating_mul(1000);
+        let token = self.verifications.issue(user_id, email, expires_at, now);
+        let locale = self.cache.user_locale(user_id, &self.catalogs);
+        self.email.send(
+            email,
+            self.catalogs.text(&locale, "Verify your email address"),
+            &self
+                .catalogs
+                .format(&locale, "Confirm this address with the code: {code}", &[("code", &token)]),
+        );
+    }
+
+    // Sets a new, unverified address and sends it a verification token
+    pub fn change_email(&self, user_id: &UserId, email: &str) -> AccountRecord {
+        let mut record = self.cache.account_record(user_id);
+        record.email = Some(email.to_string());
+        record.email_verified = false;
+        self.cache.set_account_record(user_id, record.clone());
+        self.send_verification(user_id, email);
+        record
+    }
+
+    // Tokens for an address the account has since replaced are refused
+    pub fn verify_email(&self, token: &str) -> Option<AccountRecord> {
+        let pending = self.verifications.redeem(token, now_millis())?;
+        let mut record = self.cache.account_record(&pending.user_id);
+        if record.email.as_deref() != Some(pending.email.as_str()) {
+            return None;
+        }
+        record.email_verified = true;
+        if record.state == AccountState::PendingVerification {
+            record.state = AccountState::Active;
+            record.state_changed_at = now_millis();
+        }
+        self.cache.set_account_record(&pending.user_id, record.clone());
+        info!("User {} verified {}", pending.user_id, pending.email);
+        Some(record)
+    }
+
+    // Err carries the current state when the move isn't allowed
+    pub fn transition(
+        &self,
+        user_id: &UserId,
+        next: AccountState,
+        reason: Option<String>,
+    ) -> Result<AccountRecord, AccountState> {
+        let mut record = self.cache.account_record(user_id);
+        if !record.state.can_become(next) {
+            return Err(record.state);
+        }
+        info!("User {} went from {:?} to {:?}", user_id, record.state, next);
+        record.state = next;
+        record.state_changed_at = now_millis();
+        record.reason = reason;
+        self.cache.set_account_record(user_id, record.clone());
+        Ok(record)
+    }
+
+    pub fn change_username(&self, user_id: &UserId, new_username: &str) -> Result<User, UsernameError> {
+        if !valid_username(new_username) {
+            return Err(UsernameError::Invalid);
+        }
+
+        let user = self.cache.get_user(user_id).ok_or(UsernameError::UnknownUser)?;
+        if let Some(changed_at) = user.username_changed_at {
+            let available_at = changed_at + self.config.username_change_cooldown_secs * 1000;
+            let now = now_millis();
+            if now < available_at {
+                return Err(UsernameError::Cooldown {
+                    retry_after_secs: (available_at - now).div_ceil(1000),
+                });
+            }
+        }
+
+        let redirect_until = now_millis() + self.config.username_redirect_grace_secs * 1000;
+        let renamed = self.cache.rename_user(user_id, new_username, redirect_until)?;
+        info!("User {} renamed from {} to {}", user_id, user.username, renamed.username);
+        Ok(renamed)
+    }
+}
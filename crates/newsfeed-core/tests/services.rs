@@ -0,0 +1,388 @@
+// The services against the DashMap cache, wired the way newsfeed-bin wires
+// them but with no server, storage, or other nodes
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use newsfeed_core::ads::AdService;
+use newsfeed_core::cache::Cache;
+use newsfeed_core::cluster::{
+    DeliverFeedItemsRequest, DeliverFeedItemsResponse, FeedPartitions, FeedTarget, RemoteDelivery,
+};
+use newsfeed_core::config::{Config, Settings, Source, Tunables};
+use newsfeed_core::context::{Cancellation, FeatureFlags, RequestContext, Tenant};
+use newsfeed_core::conversations::ConversationService;
+use newsfeed_core::email::EmailService;
+use newsfeed_core::engagement_log::EngagementLog;
+use newsfeed_core::events::EventStore;
+use newsfeed_core::fanout::{FanoutService, FanoutWorker};
+use newsfeed_core::i18n::Catalogs;
+use newsfeed_core::ids::{PostId, UserId};
+use newsfeed_core::jobs::JobQueue;
+use newsfeed_core::media::MediaSigner;
+use newsfeed_core::mixer::FeedMixer;
+use newsfeed_core::news_feed::NewsFeedService;
+use newsfeed_core::notifications::PushGateway;
+use newsfeed_core::pipeline::{FeedMode, RankingStrategy};
+use newsfeed_core::posts::PostService;
+use newsfeed_core::projections::{FeedProjection, GraphProjection, NotificationProjection, PostProjection};
+use newsfeed_core::users::UserService;
+use newsfeed_core::{
+    FanoutMessage, FeedEvent, NewsFeedItem, PostDraft, PostUnavailable, ReplyPolicy, User, UsernameError, now_millis,
+};
+
+use newsfeed_store_memory::cache::CacheLayer;
+use newsfeed_store_memory::eviction::CacheEviction;
+
+// A single-node cluster never sends batches anywhere
+struct NoPeers;
+
+impl RemoteDelivery for NoPeers {
+    fn deliver<'a>(&'a self, node: &'a str, _request: DeliverFeedItemsRequest) -> BoxFuture<'a, Result<DeliverFeedItemsResponse, String>> {
+        Box::pin(async move { Err(format!("no route to {}", node)) })
+    }
+}
+
+struct Services {
+    cache: Arc<CacheLayer>,
+    events: Arc<EventStore<FeedEvent>>,
+    fanout_worker: Arc<FanoutWorker>,
+    posts: PostService,
+    news_feed: Arc<NewsFeedService>,
+    conversations: ConversationService,
+    users: UserService,
+    email: Arc<EmailService>,
+}
+
+// `pull_followers` of 1 merges every post into followers' feeds at read
+// time, so feeds are complete without running the fanout queue
+fn services(pull_followers: usize) -> Services {
+    let source = Source::load().expect("settings load");
+    let config = Arc::new(Config::from_source(&source));
+    let settings = Arc::new(Settings::new(&config, Tunables::from_source(&source)).expect("settings are valid"));
+    let cache = Arc::new(CacheLayer::new(None, CacheEviction::new(&config, settings.clone())));
+    let catalogs = Arc::new(Catalogs::load(&config).expect("locales load"));
+    let push = Arc::new(PushGateway::default());
+    let fanout_worker = Arc::new(FanoutWorker::new(
+        cache.clone(),
+        push.clone(),
+        catalogs.clone(),
+        Arc::new(FeedPartitions::new(&config)),
+        Arc::new(NoPeers),
+    ));
+    let fanout_service = Arc::new(FanoutService::new(
+        cache.clone(),
+        Arc::new(JobQueue::new(None)),
+        pull_followers,
+        0,
+    ));
+    let engagement_log = Arc::new(EngagementLog::new(&config, settings.clone()));
+    let events = Arc::new(EventStore::default());
+    events.register(Arc::new(PostProjection {
+        cache: cache.clone(),
+        engagement_log: engagement_log.clone(),
+    }));
+    events.register(Arc::new(GraphProjection { cache: cache.clone() }));
+    events.register(Arc::new(NotificationProjection { cache: cache.clone() }));
+    events.register(Arc::new(FeedProjection {
+        cache: cache.clone(),
+        fanout_service,
+    }));
+    let news_feed = Arc::new(NewsFeedService::new(
+        cache.clone(),
+        Arc::new(MediaSigner::new(&config, settings.clone())),
+        Vec::new(),
+        Arc::new(FeedMixer::new(cache.clone(), settings.clone())),
+        Arc::new(AdService::new(cache.clone(), &config, settings.clone())),
+        engagement_log,
+        settings,
+    ));
+    let email = Arc::new(EmailService::default());
+    Services {
+        posts: PostService::new(cache.clone(), events.clone()),
+        conversations: ConversationService::new(cache.clone(), news_feed.clone()),
+        users: UserService::new(cache.clone(), config, email.clone(), push, catalogs),
+        cache,
+        events,
+        fanout_worker,
+        news_feed,
+        email,
+    }
+}
+
+fn add_user(cache: &CacheLayer, id: &str) -> UserId {
+    let user_id = UserId::new(id);
+    cache.set_user(User {
+        id: user_id.clone(),
+        username: id.to_string(),
+        profile_picture: String::new(),
+        profile_picture_variants: Vec::new(),
+        banner_url: None,
+        banner_variants: Vec::new(),
+        location: None,
+        website: None,
+        joined_at: now_millis(),
+        verified: false,
+        username_changed_at: None,
+        timezone: None,
+    });
+    user_id
+}
+
+fn draft(content: &str) -> PostDraft {
+    PostDraft {
+        content: content.to_string(),
+        image_url: None,
+        video_url: None,
+        alt_text: None,
+        in_reply_to: None,
+        reply_policy: ReplyPolicy::Everyone,
+        anonymous: false,
+        license: None,
+        co_authors: Vec::new(),
+        event: None,
+        article: None,
+    }
+}
+
+fn request(user_id: &UserId) -> RequestContext {
+    RequestContext {
+        user_id: user_id.clone(),
+        roles: Vec::new(),
+        tenant: Tenant::Production,
+        request_id: "test".to_string(),
+        deadline: Instant::now() + Duration::from_secs(5),
+        cancel: Cancellation::default().0,
+        flags: FeatureFlags::default(),
+    }
+}
+
+// A fanout job retried after a crash, or a batch redelivered by the
+// queue, runs deliver_local again for the same post and follower
+#[tokio::test]
+async fn redelivered_fanout_adds_one_feed_item_and_one_notification() {
+    let services = services(0);
+    let author_id = UserId::new("user_author");
+    let follower_id = UserId::new("user_follower");
+    let targets = [FeedTarget {
+        user_id: follower_id.to_string(),
+        deliver: true,
+        notify: true,
+        daily_limit: None,
+    }];
+    let item = NewsFeedItem {
+        post_id: PostId::new("post_redelivered"),
+        timestamp: now_millis(),
+    };
+
+    let first = services.fanout_worker.deliver_local(&author_id, &item, &targets).await;
+    let second = services.fanout_worker.deliver_local(&author_id, &item, &targets).await;
+
+    assert_eq!(first.delivered, 1);
+    assert_eq!((second.delivered, second.repeated), (0, 1));
+    let feed = services.cache.get_news_feed(&follower_id);
+    assert_eq!(feed.len(), 1);
+    assert_eq!(feed[0].post_id, item.post_id);
+    assert_eq!(services.cache.get_notifications(&follower_id).len(), 1);
+}
+
+#[tokio::test]
+async fn fanout_job_pushes_the_post_to_followers_only() {
+    let services = services(0);
+    let author_id = add_user(&services.cache, "user_author");
+    let follower_id = add_user(&services.cache, "user_follower");
+    let stranger_id = add_user(&services.cache, "user_stranger");
+    let post = services.posts.new_post(&author_id, draft("hello"));
+    services.cache.set_post(post.clone());
+
+    services
+        .fanout_worker
+        .process(FanoutMessage {
+            post_id: post.id.clone(),
+            user_id: author_id,
+            at: post.timestamp,
+            friend_ids: vec![follower_id.clone()],
+            notify_ids: Vec::new(),
+            daily_limits: HashMap::new(),
+            post: None,
+        })
+        .await
+        .expect("fanout runs");
+
+    let feed = services.cache.get_news_feed(&follower_id);
+    assert_eq!(feed.iter().map(|item| &item.post_id).collect::<Vec<_>>(), [&post.id]);
+    assert!(services.cache.get_news_feed(&stranger_id).is_empty());
+    assert!(services.cache.get_notifications(&follower_id).is_empty());
+}
+
+// Past the follower threshold nothing is written to feeds; the post is
+// merged in when a follower reads theirs
+#[tokio::test]
+async fn pull_author_posts_are_merged_into_feeds_when_read() {
+    let services = services(1);
+    let author_id = add_user(&services.cache, "user_author");
+    let follower_id = add_user(&services.cache, "user_follower");
+    services.events.publish(FeedEvent::Followed {
+        follower_id: follower_id.clone(),
+        followed_id: author_id.clone(),
+    });
+
+    let post = services.posts.create_post(&author_id, draft("pulled")).await;
+
+    assert!(services.cache.get_news_feed(&follower_id).is_empty());
+    let feed = services.cache.home_feed(&follower_id);
+    assert_eq!(feed.iter().map(|item| &item.post_id).collect::<Vec<_>>(), [&post.id]);
+}
+
+#[tokio::test]
+async fn thread_posts_reply_to_the_one_before() {
+    let services = services(0);
+    let author_id = add_user(&services.cache, "user_author");
+
+    let thread = services
+        .posts
+        .create_thread(&author_id, vec![draft("one"), draft("two"), draft("three")])
+        .await;
+
+    assert_eq!(thread[0].in_reply_to, None);
+    assert_eq!(thread[1].in_reply_to.as_ref(), Some(&thread[0].id));
+    assert_eq!(thread[2].in_reply_to.as_ref(), Some(&thread[1].id));
+    assert_eq!(
+        services.cache.get_thread(&thread[0].id),
+        Some(vec![thread[1].id.clone(), thread[2].id.clone()])
+    );
+    assert_eq!(services.cache.get_replies(&thread[0].id), [thread[1].id.clone()]);
+}
+
+#[tokio::test]
+async fn deleted_post_is_unavailable() {
+    let services = services(0);
+    let author_id = add_user(&services.cache, "user_author");
+    let viewer_id = add_user(&services.cache, "user_viewer");
+    let post = services.posts.create_post(&author_id, draft("soon gone")).await;
+    let viewer = services.cache.viewer(&viewer_id);
+    assert!(services.cache.post_for(&viewer, &post.id).is_ok());
+
+    services.posts.delete_post(&post.id).await;
+
+    assert_eq!(services.cache.post_for(&viewer, &post.id).err(), Some(PostUnavailable::Deleted));
+}
+
+#[tokio::test]
+async fn latest_feed_is_newest_first_and_pages_by_cursor() {
+    let services = services(1);
+    let author_id = add_user(&services.cache, "user_author");
+    let follower_id = add_user(&services.cache, "user_follower");
+    services.events.publish(FeedEvent::Followed {
+        follower_id: follower_id.clone(),
+        followed_id: author_id.clone(),
+    });
+    let mut posts = Vec::new();
+    for content in ["first", "second", "third"] {
+        posts.push(services.posts.create_post(&author_id, draft(content)).await);
+        tokio::time::sleep(Duration::from_millis(2)).await;
+    }
+    let ctx = request(&follower_id);
+
+    let page = services
+        .news_feed
+        .get_news_feed(&ctx, 2, None, FeedMode::Latest, RankingStrategy::Chronological)
+        .await
+        .expect("not cancelled");
+    assert_eq!(
+        page.iter().map(|hydrated| hydrated.post.content.as_str()).collect::<Vec<_>>(),
+        ["third", "second"]
+    );
+
+    let cursor = services
+        .news_feed
+        .next_cursor(&follower_id, None, 2)
+        .expect("a third post is left");
+    let rest = services
+        .news_feed
+        .get_news_feed(&ctx, 2, Some(&cursor), FeedMode::Latest, RankingStrategy::Chronological)
+        .await
+        .expect("not cancelled");
+    assert_eq!(rest.iter().map(|hydrated| hydrated.post.id.clone()).collect::<Vec<_>>(), [posts[0].id.clone()]);
+}
+
+#[tokio::test]
+async fn conversation_has_ancestors_in_order_and_direct_replies() {
+    let services = services(0);
+    let author_id = add_user(&services.cache, "user_author");
+    let replier_id = add_user(&services.cache, "user_replier");
+    let root = services.posts.create_post(&author_id, draft("root")).await;
+    let middle = services
+        .posts
+        .create_post(&replier_id, PostDraft {
+            in_reply_to: Some(root.id.clone()),
+            ..draft("middle")
+        })
+        .await;
+    let reply = services
+        .posts
+        .create_post(&author_id, PostDraft {
+            in_reply_to: Some(middle.id.clone()),
+            ..draft("reply")
+        })
+        .await;
+
+    let conversation = services
+        .conversations
+        .get_conversation(&replier_id, &middle.id, 0, 10)
+        .await
+        .expect("the post is visible");
+
+    assert_eq!(conversation.post.post.id, middle.id);
+    assert_eq!(
+        conversation.ancestors.iter().map(|hydrated| &hydrated.post.id).collect::<Vec<_>>(),
+        [&root.id]
+    );
+    assert_eq!(
+        conversation.replies.iter().map(|node| node.post.post.id.clone()).collect::<Vec<_>>(),
+        [reply.id]
+    );
+}
+
+#[tokio::test]
+async fn conversation_for_a_missing_post_is_unavailable() {
+    let services = services(0);
+    let viewer_id = add_user(&services.cache, "user_viewer");
+
+    let result = services
+        .conversations
+        .get_conversation(&viewer_id, &PostId::new("post_missing"), 0, 10)
+        .await;
+
+    assert_eq!(result.err(), Some(PostUnavailable::Missing));
+}
+
+#[test]
+fn signup_sends_a_verification_and_reserves_the_username() {
+    let services = services(0);
+
+    let user = services.users.register("carol", "carol@example.com", None).expect("username is free");
+
+    assert_eq!(services.email.sent(), 1);
+    assert_eq!(services.cache.find_user_id_by_username("carol"), Some(user.id));
+    assert!(matches!(
+        services.users.register("carol", "other@example.com", None),
+        Err(UsernameError::Taken)
+    ));
+    assert!(matches!(services.users.register("no spaces", "x@example.com", None), Err(UsernameError::Invalid)));
+}
+
+#[test]
+fn username_changes_wait_out_the_cooldown() {
+    let services = services(0);
+    let user = services.users.register("dave", "dave@example.com", None).expect("username is free");
+
+    let renamed = services.users.change_username(&user.id, "david").expect("first change is allowed");
+
+    assert_eq!(renamed.username, "david");
+    assert!(matches!(
+        services.users.change_username(&user.id, "davey"),
+        Err(UsernameError::Cooldown { .. })
+    ));
+}
@@ -0,0 +1,401 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Sender};
+use std::time::Instant;
+use warp::http::header::{HeaderMap, HeaderValue};
+use warp::http::Method;
+use warp::hyper::body::HttpBody;
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+use newsfeed_core::accounts::{AccountTokens, Scope};
+use newsfeed_core::config::{Config, Settings};
+use newsfeed_core::engagement_log::sample_point;
+use newsfeed_core::ids::UserId;
+use newsfeed_core::receipts::LatencyHistogram;
+use newsfeed_core::{now_millis, warn};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+const ROTATED_FILES: usize = 5;
+
+// Route templates for grouping and sampling. Literal routes come before
+// parameterized ones sharing a prefix; keep in sync with the routes in main.
+const ROUTE_TEMPLATES: &[&str] = &[
+    "/v1/me/feed",
+    "/v2/me/feed",
+    "/v1/me/feed/hide",
+    "/v1/me/feed/poll",
+    "/v1/me/feed/stream",
+    "/v1/me/feed/feedback",
+    "/v1/me/feed/telemetry",
+    "/v1/me/feed/position",
+    "/v1/me/threads",
+    "/v1/articles",
+    "/v1/articles/{id}",
+    "/v1/me/accounts",
+    "/v1/me/accounts/{id}",
+    "/v1/me/username",
+    "/v1/me/account",
+    "/v1/me/email",
+    "/v1/me/email/verification",
+    "/v1/me/deactivate",
+    "/v1/me/2fa",
+    "/v1/me/2fa/setup",
+    "/v1/me/2fa/enable",
+    "/v1/login",
+    "/v1/me/security/logins",
+    "/v1/me/privacy/access_log",
+    "/v1/oauth/clients",
+    "/oauth/authorize",
+    "/oauth/token",
+    "/v1/me/oauth/grants",
+    "/v1/me/oauth/grants/{id}",
+    "/v1/federation/key",
+    "/v1/federation/inbox",
+    "/v1/me/profile",
+    "/v1/me/avatar",
+    "/v1/me/banner",
+    "/v1/me/preferences",
+    "/v1/me/analytics",
+    "/v1/me/notifications",
+    "/v1/me/notifications/read",
+    "/v1/me/co_author_requests",
+    "/v1/me/stats",
+    "/v1/posts/like",
+    "/v1/posts/unlike",
+    "/v1/posts/views",
+    "/v1/posts/{id}",
+    "/v1/posts/{id}/replies",
+    "/v1/posts/{id}/conversation",
+    "/v1/posts/{id}/co_author",
+    "/v1/posts/{id}/rsvp",
+    "/v1/accounts",
+    "/v1/accounts/verify-email",
+    "/v1/users/follow",
+    "/v1/users/unfollow",
+    "/v1/users/by-username/{username}",
+    "/v1/users/{id}/block",
+    "/v1/users/{id}/notify",
+    "/v1/users/{id}/daily-limit",
+    "/v1/users/{id}/posts",
+    "/v1/users/{id}",
+    "/v1/public/users/by-username/{username}",
+    "/v1/public/users/{id}/posts",
+    "/v1/public/users/{id}",
+    "/v1/public/posts/{id}",
+    "/v1/tags/{tag}/posts",
+    "/v1/tags/trending",
+    "/v1/public/tags/{tag}",
+    "/v1/sponsored/{campaign_id}/click",
+    "/v1/batch",
+    "/v1/emojis",
+    "/v1/admin/campaigns",
+    "/v1/admin/debug/runtime",
+    "/v1/admin/debug/caches",
+    "/v1/admin/debug/profile",
+    "/v1/admin/debug/memory",
+    "/metrics",
+    "/v1/admin/users/{id}/verified",
+    "/v1/admin/users/{id}/state",
+    "/v1/admin/2fa-policy",
+    "/v1/admin/moderation",
+    "/v1/admin/moderation/{id}/resolve",
+    "/v1/admin/legal-holds",
+    "/v1/admin/legal-holds/{kind}/{id}",
+    "/v1/admin/takedowns/{post_id}",
+    "/v1/admin/audit",
+    "/v1/admin/emojis/{shortcode}",
+    "/profiles/*",
+    "/emoji/*",
+    "/media/{id}/*",
+];
+
+pub fn route_template(path: &str) -> &'static str {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    ROUTE_TEMPLATES
+        .iter()
+        .find(|template| {
+            let parts: Vec<&str> = template.trim_matches('/').split('/').collect();
+            match parts.split_last() {
+                Some((&"*", prefix)) => {
+                    segments.len() > prefix.len() && matches_parts(prefix, &segments[..prefix.len()])
+                }
+                _ => parts.len() == segments.len() && matches_parts(&parts, &segments),
+            }
+        })
+        .copied()
+        .unwrap_or("unmatched")
+}
+
+fn matches_parts(parts: &[&str], segments: &[&str]) -> bool {
+    parts
+        .iter()
+        .zip(segments)
+        .all(|(part, segment)| part.starts_with('{') || part == segment)
+}
+
+#[derive(Debug, Serialize)]
+struct AccessLogEntry {
+    timestamp: u64,
+    request_id: String,
+    method: String,
+    route: &'static str,
+    status: u16,
+    latency_ms: f64,
+    user: Option<UserId>,
+    bytes: Option<u64>,
+}
+
+// Where log lines go. The file sink rotates by size and is written from its
+// own thread so requests never wait on disk.
+enum Sink {
+    Off,
+    Stdout,
+    File(Sender<String>),
+}
+
+pub struct AccessLogger {
+    sink: Sink,
+    settings: Arc<Settings>, // for the per-route sampling rates
+    account_tokens: Arc<AccountTokens>,
+    metrics: Arc<RequestMetrics>,
+}
+
+// Latency and status codes per route, for /metrics. Every response counts,
+// whether or not it's logged.
+#[derive(Default)]
+pub struct RequestMetrics {
+    latency: Mutex<HashMap<(Method, &'static str), LatencyHistogram>>,
+    statuses: Mutex<HashMap<(Method, &'static str, u16), u64>>,
+}
+
+impl RequestMetrics {
+    fn record(&self, method: &Method, route: &'static str, status: u16, latency_ms: u64) {
+        self.latency
+            .lock()
+            .expect("request latency poisoned")
+            .entry((method.clone(), route))
+            .or_default()
+            .record(latency_ms, 1);
+        *self
+            .statuses
+            .lock()
+            .expect("request statuses poisoned")
+            .entry((method.clone(), route, status))
+            .or_default() += 1;
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP news_feed_request_duration_seconds Time to answer a request, by method and route.");
+        let _ = writeln!(out, "# TYPE news_feed_request_duration_seconds histogram");
+        let latency = self.latency.lock().expect("request latency poisoned");
+        let mut routes: Vec<_> = latency.iter().collect();
+        routes.sort_by(|a, b| (a.0.1, a.0.0.as_str()).cmp(&(b.0.1, b.0.0.as_str())));
+        for ((method, route), histogram) in routes {
+            let labels = format!("method=\"{}\",route=\"{}\"", method, route);
+            histogram.render(&mut out, "news_feed_request_duration_seconds", &labels);
+        }
+        drop(latency);
+        let _ = writeln!(out, "# HELP news_feed_requests_total Requests answered, by method, route, and status code.");
+        let _ = writeln!(out, "# TYPE news_feed_requests_total counter");
+        let statuses = self.statuses.lock().expect("request statuses poisoned");
+        let mut counts: Vec<_> = statuses.iter().collect();
+        counts.sort_by(|a, b| (a.0.1, a.0.0.as_str(), a.0.2).cmp(&(b.0.1, b.0.0.as_str(), b.0.2)));
+        for ((method, route, status), count) in counts {
+            let _ = writeln!(
+                out,
+                "news_feed_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                method, route, status, count
+            );
+        }
+        out
+    }
+}
+
+impl AccessLogger {
+    pub fn new(
+        config: &Config,
+        settings: Arc<Settings>,
+        account_tokens: Arc<AccountTokens>,
+        metrics: Arc<RequestMetrics>,
+    ) -> Self {
+        let sink = match config.access_log.as_str() {
+            "off" => Sink::Off,
+            "stdout" => Sink::Stdout,
+            path => Sink::File(spawn_file_writer(PathBuf::from(path), config.access_log_max_bytes)),
+        };
+        Self {
+            sink,
+            settings,
+            account_tokens,
+            metrics,
+        }
+    }
+
+    pub fn complete(
+        &self,
+        start: Instant,
+        method: &Method,
+        path: &str,
+        headers: &HeaderMap,
+        mut response: Response,
+    ) -> Response {
+        let request_id = resolve_request_id(headers);
+        if let Ok(value) = HeaderValue::from_str(&request_id) {
+            response.headers_mut().insert(REQUEST_ID_HEADER, value);
+        }
+
+        let route = route_template(path);
+        let status = response.status();
+        let latency = start.elapsed();
+        self.metrics
+            .record(method, route, status.as_u16(), latency.as_millis() as u64);
+        if matches!(self.sink, Sink::Off) {
+            return response;
+        }
+
+        // Errors are always logged; successes are sampled per route
+        if !status.is_client_error() && !status.is_server_error() {
+            let rate = self.sample_rate(method, route);
+            if rate < 1.0 && sample_point(&request_id) >= rate {
+                return response;
+            }
+        }
+
+        let entry = AccessLogEntry {
+            timestamp: now_millis(),
+            request_id,
+            method: method.to_string(),
+            route,
+            status: status.as_u16(),
+            latency_ms: latency.as_secs_f64() * 1000.0,
+            user: self.user(headers),
+            bytes: response.body().size_hint().exact(),
+        };
+        let line = serde_json::to_string(&entry).expect("access log entry serializes");
+        match &self.sink {
+            Sink::Off => {}
+            Sink::Stdout => println!("{}", line),
+            Sink::File(sender) => {
+                let _ = sender.send(line);
+            }
+        }
+        response
+    }
+
+    fn sample_rate(&self, method: &Method, route: &str) -> f64 {
+        let key = format!("{} {}", method, route);
+        self.settings
+            .current()
+            .access_log_sampling
+            .iter()
+            .find(|(pattern, _)| *pattern == key)
+            .map(|(_, rate)| *rate)
+            .unwrap_or(1.0)
+    }
+
+    // The acting account, without logging the token itself
+    fn user(&self, headers: &HeaderMap) -> Option<UserId> {
+        let token = headers.get("authorization")?.to_str().ok()?;
+        let accounts = self.account_tokens.decode(token).ok()?;
+        let active = headers
+            .get("x-active-account")
+            .and_then(|value| value.to_str().ok());
+        Some(
+            accounts
+                .select(active, Scope::Read)
+                .unwrap_or_else(|_| accounts.primary.clone()),
+        )
+    }
+}
+
+// The caller's request ID if it is usable, otherwise a fresh one
+pub fn resolve_request_id(headers: &HeaderMap) -> String {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty() && value.len() <= 64)
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string())
+}
+
+// Wraps the full route tree so every response, including rejections, is logged
+pub fn with_access_log<F, R>(
+    filter: F,
+    logger: Arc<AccessLogger>,
+) -> impl Filter<Extract = (Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    warp::any()
+        .map(Instant::now)
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(warp::header::headers_cloned())
+        .and(filter)
+        .map(
+            move |start: Instant, method: Method, path: FullPath, headers: HeaderMap, reply: R| {
+                logger.complete(start, &method, path.as_str(), &headers, reply.into_response())
+            },
+        )
+}
+
+fn spawn_file_writer(path: PathBuf, max_bytes: u64) -> Sender<String> {
+    let (sender, receiver) = mpsc::channel::<String>();
+    std::thread::spawn(move || {
+        let mut file = open_log(&path);
+        let mut written = file
+            .as_ref()
+            .and_then(|file| file.metadata().ok())
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+
+        for line in receiver {
+            if written > 0 && written + line.len() as u64 + 1 > max_bytes {
+                drop(file.take());
+                rotate(&path);
+                file = open_log(&path);
+                written = 0;
+            }
+            if let Some(file) = file.as_mut() {
+                match writeln!(file, "{}", line) {
+                    Ok(()) => written += line.len() as u64 + 1,
+                    Err(e) => warn!("Failed to write access log {}: {}", path.display(), e),
+                }
+            }
+        }
+    });
+    sender
+}
+
+fn open_log(path: &Path) -> Option<File> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(file) => Some(file),
+        Err(e) => {
+            warn!("Failed to open access log {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+// access.log -> access.log.1 -> ... -> access.log.N, dropping the oldest
+fn rotate(path: &Path) {
+    let numbered = |index: usize| PathBuf::from(format!("{}.{}", path.display(), index));
+    let _ = std::fs::remove_file(numbered(ROTATED_FILES));
+    for index in (1..ROTATED_FILES).rev() {
+        let _ = std::fs::rename(numbered(index), numbered(index + 1));
+    }
+    let _ = std::fs::rename(path, numbered(1));
+}
@@ -0,0 +1,124 @@
+
+
+use newsfeed_core::account_state::AccountState;
+use newsfeed_core::accounts::{AccountError, AccountSet, AccountTokens, Scope};
+use newsfeed_core::cache::Cache;
+use newsfeed_core::ids::UserId;
+use newsfeed_core::oauth::OAuthProvider;
+use newsfeed_core::two_factor::{TwoFactor, TwoFactorError};
+use newsfeed_core::{now_millis, warn};
+
+use newsfeed_store_memory::cache::CacheLayer;
+
+use crate::rejections::{AuthError, Conflict, Forbidden, SessionExpired, StorageUnavailable};
+
+// Authentication middleware
+pub fn decode_credentials(
+    tokens: &AccountTokens,
+    auth_token: Option<String>,
+) -> Result<AccountSet, warp::Rejection> {
+    auth_token
+        .and_then(|token| tokens.decode(&token).ok())
+        .ok_or_else(|| warp::reject::custom(AuthError))
+}
+
+// Resolves the account a request acts as, honoring x-active-account
+pub fn select_account(
+    accounts: AccountSet,
+    active: Option<String>,
+    required: Scope,
+) -> Result<UserId, warp::Rejection> {
+    accounts
+        .select(active.as_deref(), required)
+        .map_err(account_rejection)
+}
+
+// Every authenticated route passes through here, so the account's state is
+// checked before any handler can mutate anything. A validly signed token for
+// an account that no longer exists is refused like an invalid one.
+pub fn check_account_state(cache: &CacheLayer, user_id: UserId, scope: Scope) -> Result<UserId, warp::Rejection> {
+    if cache.get_user(&user_id).is_none() {
+        return Err(warp::reject::custom(AuthError));
+    }
+    let state = cache.checked_account_state(&user_id).map_err(|e| {
+        warn!("storage: failed to read account {}: {}", user_id, e);
+        warp::reject::custom(StorageUnavailable)
+    })?;
+    if state.allows(scope) {
+        return Ok(user_id);
+    }
+    let (code, message) = match state {
+        AccountState::PendingVerification => ("email_unverified", "Verify your email address first"),
+        AccountState::Suspended => ("account_suspended", "This account is suspended"),
+        AccountState::Active | AccountState::Deactivated => {
+            ("account_deactivated", "This account is deactivated")
+        }
+    };
+    Err(warp::reject::custom(Forbidden { code, message }))
+}
+
+// Tokens issued to apps stop working once they expire or the user revokes
+// the app's access. Sandbox apps' tokens only work in the sandbox tenant.
+pub fn check_delegation(oauth: &OAuthProvider, accounts: &AccountSet, sandbox: bool) -> Result<(), warp::Rejection> {
+    match &accounts.delegation {
+        Some(delegation)
+            if !oauth.is_active(&accounts.primary, delegation, now_millis())
+                || oauth.is_sandbox(&delegation.client_id) != sandbox =>
+        {
+            Err(warp::reject::custom(AuthError))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Accounts with 2FA on only accept tokens from a login that passed it
+pub fn check_two_factor(
+    two_factor: &TwoFactor,
+    accounts: &AccountSet,
+    user_id: UserId,
+) -> Result<UserId, warp::Rejection> {
+    if two_factor.is_enabled(&user_id).map_err(two_factor_rejection)? && !accounts.passed_two_factor(&user_id) {
+        return Err(warp::reject::custom(Forbidden {
+            code: "two_factor_required",
+            message: "Log in with your authenticator code first",
+        }));
+    }
+    Ok(user_id)
+}
+
+pub fn two_factor_rejection(error: TwoFactorError) -> warp::Rejection {
+    match error {
+        TwoFactorError::AlreadyEnabled => warp::reject::custom(Conflict {
+            code: "two_factor_enabled",
+            message: "Two-factor authentication is already on",
+        }),
+        TwoFactorError::NotEnrolled => warp::reject::custom(Conflict {
+            code: "two_factor_not_enrolled",
+            message: "Two-factor authentication is not set up",
+        }),
+        TwoFactorError::InvalidCode => warp::reject::custom(Forbidden {
+            code: "invalid_two_factor_code",
+            message: "That code is not valid",
+        }),
+        TwoFactorError::LockedOut => warp::reject::custom(Forbidden {
+            code: "two_factor_locked",
+            message: "Too many invalid codes; try again in a few minutes",
+        }),
+        TwoFactorError::Unavailable => warp::reject::custom(StorageUnavailable),
+    }
+}
+
+pub fn account_rejection(error: AccountError) -> warp::Rejection {
+    match error {
+        AccountError::InvalidToken => warp::reject::custom(AuthError),
+        AccountError::SessionExpired => warp::reject::custom(SessionExpired),
+        AccountError::NotLinked => warp::reject::custom(Forbidden {
+            code: "account_not_linked",
+            message: "The active account is not linked to this token",
+        }),
+        AccountError::ScopeDenied => warp::reject::custom(Forbidden {
+            code: "scope_denied",
+            message: "The active account is not permitted to do this",
+        }),
+    }
+}
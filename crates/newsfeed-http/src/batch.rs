@@ -0,0 +1,196 @@
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+use warp::filters::BoxedFilter;
+use warp::http::header::{ACCEPT_LANGUAGE, CONTENT_TYPE, HeaderMap};
+use warp::http::{Method, StatusCode};
+use warp::hyper::service::Service;
+use warp::hyper::{Body, Request};
+use warp::reply::Response;
+
+use newsfeed_core::context::{Cancellation, Deadline, PeerAddr};
+use newsfeed_core::i18n::Catalogs;
+
+use crate::i18n;
+use crate::limits::Exposure;
+
+// Headers a sub-request inherits from the batch request
+const FORWARDED_HEADERS: &[&str] = &["authorization", "x-active-account", "accept-language"];
+
+#[derive(Debug, Deserialize)]
+pub struct BatchRequest {
+    pub requests: Vec<SubRequest>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubRequest {
+    pub method: String,
+    pub path: String, // may include a query string
+    #[serde(default)]
+    pub body: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubResponse {
+    pub status: u16,
+    pub latency_ms: f64,
+    pub body: serde_json::Value, // JSON when the route returns JSON, otherwise a string
+}
+
+impl SubResponse {
+    fn error(status: StatusCode, message: &str) -> Self {
+        Self {
+            status: status.as_u16(),
+            latency_ms: 0.0,
+            body: serde_json::json!({ "error": message }),
+        }
+    }
+}
+
+// Runs sub-requests through the same route tree as top-level requests. The
+// routes are only known once they are all built, so they're installed after.
+pub struct BatchDispatcher {
+    routes: OnceLock<BoxedFilter<(Response,)>>,
+    catalogs: Arc<Catalogs>, // sub-request errors are translated like top-level ones
+}
+
+impl BatchDispatcher {
+    pub fn new(catalogs: Arc<Catalogs>) -> Self {
+        Self {
+            routes: OnceLock::new(),
+            catalogs,
+        }
+    }
+
+    pub fn install(&self, routes: BoxedFilter<(Response,)>) {
+        let _ = self.routes.set(routes);
+    }
+
+    // Sub-requests run concurrently, all against the batch's deadline and
+    // cancelled along with it; responses keep the request order. Only paths
+    // the batch's listener serves are reachable, as for top-level requests.
+    pub async fn execute(
+        &self,
+        headers: &HeaderMap,
+        exposure: Exposure,
+        peer: PeerAddr,
+        deadline: Deadline,
+        cancellation: &Cancellation,
+        requests: Vec<SubRequest>,
+    ) -> Vec<SubResponse> {
+        let Some(routes) = self.routes.get() else {
+            return Vec::new();
+        };
+        let accept_language = headers
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        let handles: Vec<_> = requests
+            .into_iter()
+            .map(|sub| {
+                let cancel = cancellation.0.child_token();
+                let request = build_request(headers, exposure, peer, deadline, Cancellation(cancel.clone()), sub);
+                let mut service = warp::service(routes.clone());
+                let catalogs = self.catalogs.clone();
+                let accept_language = accept_language.clone();
+                tokio::spawn(async move {
+                    let request = match request {
+                        Ok(request) => request,
+                        Err(message) => return SubResponse::error(StatusCode::BAD_REQUEST, message),
+                    };
+                    if !exposure.serves(request.uri().path()) {
+                        return SubResponse::error(StatusCode::NOT_FOUND, "Not found");
+                    }
+                    let start = Instant::now();
+                    // The spawned task outlives a dropped batch unless it stops itself
+                    let response = tokio::select! {
+                        response = service.call(request) => match response {
+                            Ok(response) => response,
+                            Err(never) => match never {},
+                        },
+                        _ = cancel.cancelled() => {
+                            return SubResponse::error(StatusCode::GATEWAY_TIMEOUT, "Request cancelled");
+                        }
+                        _ = tokio::time::sleep_until(deadline.0.into()) => {
+                            return SubResponse::error(StatusCode::GATEWAY_TIMEOUT, "Request timed out");
+                        }
+                    };
+                    let response = i18n::localize(&catalogs, accept_language.as_deref(), response).await;
+                    let status = response.status().as_u16();
+                    let body = read_body(response).await;
+                    SubResponse {
+                        status,
+                        latency_ms: start.elapsed().as_secs_f64() * 1000.0,
+                        body,
+                    }
+                })
+            })
+            .collect();
+
+        let mut responses = Vec::with_capacity(handles.len());
+        for handle in handles {
+            responses.push(handle.await.unwrap_or_else(|_| {
+                SubResponse::error(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+            }));
+        }
+        responses
+    }
+}
+
+fn build_request(
+    headers: &HeaderMap,
+    exposure: Exposure,
+    peer: PeerAddr,
+    deadline: Deadline,
+    cancellation: Cancellation,
+    sub: SubRequest,
+) -> Result<Request<Body>, &'static str> {
+    let method = Method::from_bytes(sub.method.to_ascii_uppercase().as_bytes()).map_err(|_| "Invalid method")?;
+    if !sub.path.starts_with('/') {
+        return Err("Path must start with /");
+    }
+    if sub.path.split('?').next().is_some_and(|path| path.trim_end_matches('/').ends_with("/batch")) {
+        return Err("Batches cannot be nested");
+    }
+
+    let mut builder = Request::builder()
+        .method(method)
+        .uri(&sub.path)
+        .extension(deadline)
+        .extension(cancellation)
+        .extension(exposure)
+        .extension(peer);
+    for name in FORWARDED_HEADERS {
+        if let Some(value) = headers.get(*name) {
+            builder = builder.header(*name, value);
+        }
+    }
+    let body = match sub.body {
+        Some(body) => {
+            let bytes = serde_json::to_vec(&body).map_err(|_| "Invalid body")?;
+            builder = builder
+                .header(CONTENT_TYPE, "application/json")
+                .header("content-length", bytes.len());
+            Body::from(bytes)
+        }
+        None => Body::empty(),
+    };
+    builder.body(body).map_err(|_| "Invalid path")
+}
+
+async fn read_body(response: Response) -> serde_json::Value {
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    let bytes = match warp::hyper::body::to_bytes(response.into_body()).await {
+        Ok(bytes) => bytes,
+        Err(_) => return serde_json::Value::Null,
+    };
+    if is_json && let Ok(value) = serde_json::from_slice(&bytes) {
+        return value;
+    }
+    serde_json::Value::String(String::from_utf8_lossy(&bytes).into_owned())
+}
@@ -0,0 +1,270 @@
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::codegen::{Service, http};
+use tonic::server::{NamedService, UnaryService};
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Code, Request, Response, Status};
+
+use newsfeed_core::cluster::{DeliverFeedItemsRequest, DeliverFeedItemsResponse, LocalDelivery, RemoteDelivery};
+use newsfeed_core::config::Config;
+use newsfeed_core::error;
+
+const SERVICE: &str = "newsfeed.internal.FeedDelivery";
+const DELIVER_FEED_ITEMS: &str = "/newsfeed.internal.FeedDelivery/DeliverFeedItems";
+
+// Attempts per batch before the fanout job fails (and is retried by the job
+// queue), and the wait before the first retry; the wait doubles each time
+const MAX_ATTEMPTS: u32 = 3;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+
+// Every peer needs an address the client can connect to
+pub fn validate(config: &Config) -> Result<(), String> {
+    if let Some((node, url)) = config
+        .feed_nodes
+        .iter()
+        .find(|(_, url)| url.parse::<tonic::transport::Uri>().is_err())
+    {
+        return Err(format!("NEWS_FEED_FEED_NODES: {} has an invalid URL, {}", node, url));
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct RpcCounters {
+    sent: AtomicU64,
+    retried: AtomicU64,
+    failed: AtomicU64,
+    received: AtomicU64,
+}
+
+// Sends fanout batches to the nodes that own the feeds. Each peer gets a few
+// lazily connected HTTP/2 channels that every batch to it reuses, taken in
+// turn; each channel multiplexes many calls.
+pub struct FeedDeliveryClient {
+    channels: HashMap<String, Vec<Channel>>,
+    next: AtomicUsize,
+    secret: Option<String>,
+    counters: Arc<RpcCounters>,
+}
+
+impl FeedDeliveryClient {
+    pub fn new(config: &Config) -> Self {
+        let channels = config
+            .feed_nodes
+            .iter()
+            .filter(|(node, _)| *node != config.node_id)
+            .filter_map(|(node, url)| {
+                let endpoint = Endpoint::from_shared(url.clone())
+                    .ok()?
+                    .connect_timeout(Duration::from_secs(2))
+                    .timeout(Duration::from_secs(5));
+                let pool = (0..config.rpc_connections.max(1)).map(|_| endpoint.connect_lazy()).collect();
+                Some((node.clone(), pool))
+            })
+            .collect();
+        Self {
+            channels,
+            next: AtomicUsize::new(0),
+            secret: config.rpc_secret.clone(),
+            counters: Arc::default(),
+        }
+    }
+
+    // Delivers one batch, retrying while the failure looks temporary
+    async fn send(
+        &self,
+        node: &str,
+        request: DeliverFeedItemsRequest,
+    ) -> Result<DeliverFeedItemsResponse, String> {
+        let pool = self
+            .channels
+            .get(node)
+            .ok_or_else(|| format!("no address for feed node {}", node))?;
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            let channel = pool[self.next.fetch_add(1, Ordering::Relaxed) % pool.len()].clone();
+            match self.call(channel, request.clone()).await {
+                Ok(response) => {
+                    self.counters.sent.fetch_add(1, Ordering::Relaxed);
+                    return Ok(response);
+                }
+                Err(status) if attempt < MAX_ATTEMPTS && retryable(status.code()) => {
+                    self.counters.retried.fetch_add(1, Ordering::Relaxed);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(status) => {
+                    self.counters.failed.fetch_add(1, Ordering::Relaxed);
+                    return Err(format!(
+                        "batch {} to {}: {:?}, {}",
+                        request.batch_id,
+                        node,
+                        status.code(),
+                        status.message()
+                    ));
+                }
+            }
+        }
+    }
+
+    async fn call(
+        &self,
+        channel: Channel,
+        request: DeliverFeedItemsRequest,
+    ) -> Result<DeliverFeedItemsResponse, Status> {
+        let mut client = tonic::client::Grpc::new(channel);
+        client
+            .ready()
+            .await
+            .map_err(|e| Status::unavailable(format!("connecting: {}", e)))?;
+        let mut request = Request::new(request);
+        if let Some(secret) = &self.secret {
+            let value = format!("Bearer {}", secret)
+                .parse()
+                .map_err(|_| Status::internal("RPC secret isn't a valid header value"))?;
+            request.metadata_mut().insert("authorization", value);
+        }
+        let path = http::uri::PathAndQuery::from_static(DELIVER_FEED_ITEMS);
+        let response = client.unary(request, path, ProstCodec::default()).await?;
+        Ok(response.into_inner())
+    }
+
+    // Prometheus text exposition of node-to-node fanout batches
+    pub fn metrics(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP news_feed_rpc_batches_total Fanout batches by outcome: sent, retried, and failed from this node, received from others.");
+        let _ = writeln!(out, "# TYPE news_feed_rpc_batches_total counter");
+        for (outcome, counter) in [
+            ("sent", &self.counters.sent),
+            ("retried", &self.counters.retried),
+            ("failed", &self.counters.failed),
+            ("received", &self.counters.received),
+        ] {
+            let _ = writeln!(
+                out,
+                "news_feed_rpc_batches_total{{outcome=\"{}\"}} {}",
+                outcome,
+                counter.load(Ordering::Relaxed)
+            );
+        }
+        out
+    }
+}
+
+impl RemoteDelivery for FeedDeliveryClient {
+    fn deliver<'a>(
+        &'a self,
+        node: &'a str,
+        request: DeliverFeedItemsRequest,
+    ) -> BoxFuture<'a, Result<DeliverFeedItemsResponse, String>> {
+        Box::pin(self.send(node, request))
+    }
+}
+
+fn retryable(code: Code) -> bool {
+    matches!(
+        code,
+        Code::Unavailable | Code::DeadlineExceeded | Code::ResourceExhausted | Code::Aborted | Code::Unknown
+    )
+}
+
+// The DeliverFeedItems service, for `tonic::transport::Server`
+#[derive(Clone)]
+pub struct FeedDeliveryServer {
+    delivery: Arc<dyn LocalDelivery>,
+    secret: Option<Arc<str>>,
+    counters: Arc<RpcCounters>,
+}
+
+impl FeedDeliveryServer {
+    pub fn new(delivery: Arc<dyn LocalDelivery>, client: &FeedDeliveryClient) -> Self {
+        Self {
+            delivery,
+            secret: client.secret.as_deref().map(Arc::from),
+            counters: client.counters.clone(),
+        }
+    }
+
+    // Serves on an already bound listener until the process exits
+    pub async fn serve(self, incoming: TcpIncoming) {
+        if let Err(e) = tonic::transport::Server::builder()
+            .add_service(self)
+            .serve_with_incoming(incoming)
+            .await
+        {
+            error!("Feed delivery RPC server error: {}", e);
+        }
+    }
+}
+
+// Binds the RPC listener; done at startup alongside the HTTP listeners
+pub fn bind(addr: SocketAddr) -> Result<TcpIncoming, String> {
+    TcpIncoming::new(addr, true, None).map_err(|e| e.to_string())
+}
+
+impl NamedService for FeedDeliveryServer {
+    const NAME: &'static str = SERVICE;
+}
+
+impl Service<http::Request<BoxBody>> for FeedDeliveryServer {
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let server = self.clone();
+        Box::pin(async move {
+            if request.uri().path() != DELIVER_FEED_ITEMS {
+                return Ok(Status::unimplemented(request.uri().path().to_string()).into_http());
+            }
+            let mut grpc = tonic::server::Grpc::new(ProstCodec::default());
+            Ok(grpc.unary(DeliverFeedItems(server), request).await)
+        })
+    }
+}
+
+struct DeliverFeedItems(FeedDeliveryServer);
+
+impl UnaryService<DeliverFeedItemsRequest> for DeliverFeedItems {
+    type Response = DeliverFeedItemsResponse;
+    type Future = BoxFuture<'static, Result<Response<DeliverFeedItemsResponse>, Status>>;
+
+    fn call(&mut self, request: Request<DeliverFeedItemsRequest>) -> Self::Future {
+        let server = self.0.clone();
+        Box::pin(async move {
+            if let Some(secret) = &server.secret {
+                let presented = request
+                    .metadata()
+                    .get("authorization")
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.strip_prefix("Bearer "));
+                if presented != Some(secret) {
+                    return Err(Status::unauthenticated("missing or wrong RPC secret"));
+                }
+            }
+            server.counters.received.fetch_add(1, Ordering::Relaxed);
+            server
+                .delivery
+                .deliver_batch(request.into_inner())
+                .await
+                .map(Response::new)
+                .map_err(Status::invalid_argument)
+        })
+    }
+}
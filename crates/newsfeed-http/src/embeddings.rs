@@ -0,0 +1,103 @@
+// The embeddings backend behind NEWS_FEED_EMBEDDINGS: the local model from
+// newsfeed-core, or an external service over HTTP
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use warp::hyper::client::HttpConnector;
+use warp::hyper::{Body, Client, Request};
+
+use newsfeed_core::config::Config;
+use newsfeed_core::embeddings::{Embeddings, LocalEmbeddings};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Which backend a NEWS_FEED_EMBEDDINGS value names, if this build has
+// embeddings at all
+pub fn backend(spec: &str) -> Result<&'static str, String> {
+    let name = match spec.split_once("://").map(|(scheme, _)| scheme) {
+        None if spec == "local" => "local",
+        Some("http") => "http",
+        Some("https") => return Err("https isn't supported; put the service behind a local http:// proxy".to_string()),
+        _ => return Err(format!("expected local or an http:// URL, got {}", spec)),
+    };
+    if !cfg!(feature = "embeddings") {
+        return Err("this build has no embeddings; build with --features embeddings".to_string());
+    }
+    Ok(name)
+}
+
+// The configured backend; None when embeddings are off
+pub fn connect(config: &Config) -> Result<Option<Arc<dyn Embeddings>>, String> {
+    let Some(spec) = &config.embeddings else {
+        return Ok(None);
+    };
+    let dimensions = config.embedding_dimensions;
+    match backend(spec)? {
+        "local" => Ok(Some(Arc::new(LocalEmbeddings::new(dimensions)))),
+        _ => Ok(Some(Arc::new(HttpEmbeddings {
+            url: spec.clone(),
+            dimensions,
+            client: Client::new(),
+        }))),
+    }
+}
+
+#[derive(Serialize)]
+struct EmbedRequest<'a> {
+    input: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct EmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+// An external service: POST {"input": [texts]} answered with
+// {"embeddings": [[...], ...]}, one vector per text
+pub struct HttpEmbeddings {
+    url: String,
+    dimensions: usize,
+    client: Client<HttpConnector>,
+}
+
+impl Embeddings for HttpEmbeddings {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    fn embed_posts<'a>(&'a self, texts: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>, String>> {
+        Box::pin(async move {
+            let body = serde_json::to_vec(&EmbedRequest { input: texts }).map_err(|e| e.to_string())?;
+            let request = Request::post(&self.url)
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .map_err(|e| e.to_string())?;
+            let response = tokio::time::timeout(REQUEST_TIMEOUT, self.client.request(request))
+                .await
+                .map_err(|_| format!("{} timed out", self.url))?
+                .map_err(|e| format!("{}: {}", self.url, e))?;
+            if !response.status().is_success() {
+                return Err(format!("{} answered {}", self.url, response.status()));
+            }
+            let bytes = warp::hyper::body::to_bytes(response.into_body())
+                .await
+                .map_err(|e| format!("{}: {}", self.url, e))?;
+            let parsed: EmbedResponse = serde_json::from_slice(&bytes).map_err(|e| format!("{}: {}", self.url, e))?;
+            if parsed.embeddings.len() != texts.len() {
+                return Err(format!(
+                    "{} returned {} vectors for {} texts",
+                    self.url,
+                    parsed.embeddings.len(),
+                    texts.len()
+                ));
+            }
+            Ok(parsed.embeddings)
+        })
+    }
+}
+
@@ -0,0 +1,174 @@
+use dashmap::DashMap;
+use futures::stream::SplitSink;
+use futures::{SinkExt, StreamExt};
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use warp::ws::{Message, WebSocket};
+
+use newsfeed_core::account_state::AccountState;
+use newsfeed_core::ids::UserId;
+use newsfeed_core::news_feed::NewsFeedService;
+use newsfeed_core::{FeedCursor, HydratedPost};
+use newsfeed_store_memory::cache::CacheLayer;
+
+// Pings keep idle connections open through proxies and find dead peers. Each
+// ping also picks up items that didn't wake the stream (see Hybrid Fanout).
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+// A client that can't take a message this long is dropped
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+const STREAM_BATCH_SIZE: usize = 50;
+
+type Sink = SplitSink<WebSocket, Message>;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamMessage<'a> {
+    // Sent once on connect: where the stream starts
+    Ready { cursor: String },
+    // Newly delivered items, newest first, as from the long poll
+    Feed { feed: &'a [HydratedPost], cursor: String },
+}
+
+// Pushes new feed items to connected WebSocket clients. Each connection waits
+// on its user's feed updates, like a long poll that never returns, and sends
+// what was delivered since its cursor.
+pub struct FeedStreamService {
+    cache: Arc<CacheLayer>,
+    news_feed_service: Arc<NewsFeedService>,
+    max_per_user: usize,
+    connections: DashMap<UserId, usize>, // open connections per user
+    opened: AtomicU64,
+    sent: AtomicU64, // feed messages
+}
+
+// One open stream; frees its user's slot when dropped
+pub struct StreamSlot {
+    service: Arc<FeedStreamService>,
+    user_id: UserId,
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        self.service
+            .connections
+            .remove_if_mut(&self.user_id, |_, count| {
+                *count -= 1;
+                *count == 0
+            });
+    }
+}
+
+impl FeedStreamService {
+    pub fn new(cache: Arc<CacheLayer>, news_feed_service: Arc<NewsFeedService>, max_per_user: usize) -> Self {
+        Self {
+            cache,
+            news_feed_service,
+            max_per_user,
+            connections: DashMap::new(),
+            opened: AtomicU64::new(0),
+            sent: AtomicU64::new(0),
+        }
+    }
+
+    // A slot for a new stream, or None if the user has too many open
+    pub fn connect(self: &Arc<Self>, user_id: &UserId) -> Option<StreamSlot> {
+        let mut count = self.connections.entry(user_id.clone()).or_default();
+        if *count >= self.max_per_user {
+            return None;
+        }
+        *count += 1;
+        self.opened.fetch_add(1, Ordering::Relaxed);
+        Some(StreamSlot {
+            service: self.clone(),
+            user_id: user_id.clone(),
+        })
+    }
+
+    // Runs until the client goes away, stops reading, or the account is no
+    // longer active
+    pub async fn run(self: Arc<Self>, slot: StreamSlot, socket: WebSocket, mut cursor: FeedCursor) {
+        let user_id = slot.user_id.clone();
+        let (mut sink, mut incoming) = socket.split();
+        // Subscribe before catching up so an item landing in between still wakes us
+        let mut receiver = self.cache.feed_updates.subscribe(&user_id);
+        let mut ping = tokio::time::interval(PING_INTERVAL);
+        ping.tick().await;
+
+        let ready = StreamMessage::Ready { cursor: cursor.encode() };
+        let mut open = send(&mut sink, &ready).await && self.send_new(&mut sink, &user_id, &mut cursor).await;
+        while open {
+            open = tokio::select! {
+                update = receiver.recv() => match update {
+                    Ok(_) | Err(RecvError::Lagged(_)) => self.send_new(&mut sink, &user_id, &mut cursor).await,
+                    Err(RecvError::Closed) => false,
+                },
+                _ = ping.tick() => {
+                    self.cache.account_state(&user_id) == AccountState::Active
+                        && timeout_send(&mut sink, Message::ping(Vec::new())).await
+                        && self.send_new(&mut sink, &user_id, &mut cursor).await
+                },
+                // Pongs and anything else the client sends are ignored
+                message = incoming.next() => matches!(message, Some(Ok(message)) if !message.is_close()),
+            };
+        }
+
+        self.cache.feed_updates.release(&user_id, receiver);
+        let _ = sink.close().await;
+        drop(slot);
+    }
+
+    // Sends everything delivered after `cursor`, moving it along; false once
+    // the client can't be written to
+    async fn send_new(
+        &self,
+        sink: &mut Sink,
+        user_id: &UserId,
+        cursor: &mut FeedCursor,
+    ) -> bool {
+        loop {
+            let (feed, next) = self.news_feed_service.new_items(user_id, cursor, STREAM_BATCH_SIZE);
+            if feed.is_empty() {
+                return true;
+            }
+            *cursor = next;
+            let message = StreamMessage::Feed {
+                feed: &feed,
+                cursor: cursor.encode(),
+            };
+            if !send(sink, &message).await {
+                return false;
+            }
+            self.sent.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn metrics(&self) -> String {
+        let open: usize = self.connections.iter().map(|entry| *entry.value()).sum();
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP news_feed_stream_connections Open feed stream WebSockets.");
+        let _ = writeln!(out, "# TYPE news_feed_stream_connections gauge");
+        let _ = writeln!(out, "news_feed_stream_connections {}", open);
+        let _ = writeln!(out, "# HELP news_feed_stream_opened_total Feed stream WebSockets opened.");
+        let _ = writeln!(out, "# TYPE news_feed_stream_opened_total counter");
+        let _ = writeln!(out, "news_feed_stream_opened_total {}", self.opened.load(Ordering::Relaxed));
+        let _ = writeln!(out, "# HELP news_feed_stream_messages_total Feed messages sent over streams.");
+        let _ = writeln!(out, "# TYPE news_feed_stream_messages_total counter");
+        let _ = writeln!(out, "news_feed_stream_messages_total {}", self.sent.load(Ordering::Relaxed));
+        out
+    }
+}
+
+async fn send(sink: &mut Sink, message: &StreamMessage<'_>) -> bool {
+    match serde_json::to_string(message) {
+        Ok(text) => timeout_send(sink, Message::text(text)).await,
+        Err(_) => false,
+    }
+}
+
+async fn timeout_send(sink: &mut Sink, message: Message) -> bool {
+    matches!(tokio::time::timeout(SEND_TIMEOUT, sink.send(message)).await, Ok(Ok(())))
+}
@@ -0,0 +1,3440 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::time::Duration;
+use uuid::Uuid;
+use warp::{Filter, Reply};
+
+use newsfeed_core::account_state::AccountState;
+use newsfeed_core::accounts::{AccountSet, LinkedAccount, Scope};
+use newsfeed_core::activity::Activity;
+use newsfeed_core::ads::Campaign;
+use newsfeed_core::articles::{ArticleCard, MAX_ARTICLE_CHARS, MAX_ARTICLE_TITLE_CHARS};
+use newsfeed_core::audit::AuditAction;
+use newsfeed_core::cache::Cache;
+use newsfeed_core::config::{Config, SettingChange};
+use newsfeed_core::context::{Cancellation, Cancelled, Deadline, PeerAddr, RequestContext, ViewerContext};
+use newsfeed_core::emoji::CustomEmoji;
+use newsfeed_core::engagement_log::Interaction;
+use newsfeed_core::fields::{FieldSelection, project};
+use newsfeed_core::ids::{PostId, TagId, UserId};
+use newsfeed_core::images::{ImageError, ProfileImageKind};
+use newsfeed_core::legal::{HoldKind, LegalHold, Takedown};
+use newsfeed_core::login_history::LoginContext;
+use newsfeed_core::mixer::DAY_MILLIS;
+use newsfeed_core::conversations::Timeline;
+use newsfeed_core::notifications::notification_text;
+use newsfeed_core::oauth::{OAuthClient, OAuthError, OAuthScope, consent_page};
+use newsfeed_core::passwords::{PasswordError, Passwords};
+use newsfeed_core::posts::{ScheduledPost, schedule_event_reminder};
+use newsfeed_core::ranking::{NegativeSignal, SignalKind};
+use newsfeed_core::related::MAX_RELATED;
+use newsfeed_core::residency::{Region, ResidencyError, move_tree};
+use newsfeed_core::rsvp::{EventDetails, MAX_EVENT_LOCATION_CHARS, MAX_EVENT_TITLE_CHARS, RsvpStatus};
+use newsfeed_core::saved_searches::SavedSearch;
+use newsfeed_core::search::Ranking;
+use newsfeed_core::telemetry::{MAX_DWELL_MILLIS, Reading, TelemetryBatch};
+use newsfeed_core::users::valid_email;
+use newsfeed_core::{
+    FanoutMessage, FeedCursor, FeedEvent, FeedPosition, HydratedPost, MAX_CO_AUTHORS, Post, PostDraft, UserPreferences,
+    UsernameError, articles, content, debug, emoji, info, media, now_millis, profile_views, related, search, timezones,
+    warn,
+};
+
+use newsfeed_store_memory::memory;
+
+use crate::AppState;
+use crate::auth::{account_rejection, check_account_state, select_account, two_factor_rejection};
+use crate::batch::BatchRequest;
+use crate::http_signature::{self, SignatureError};
+use crate::limits::Exposure;
+use crate::profiling::MAX_PROFILE_SECS;
+use crate::rejections::{
+    Conflict, ContentTooLong, Forbidden, InvalidCredentials, MediaAccessDenied, NotFound, Overloaded, ProfileFailed,
+    RateLimited, RequestCancelled, SignatureRejected, StorageError, ValidationError, post_unavailable,
+};
+use crate::requests::{
+    AccountTokenResponse, AnalyticsResponse, AnswerCoAuthorRequest, ArticleResponse, AuditQuery, AuditResponse,
+    AuthorizeQuery, BatchResponse, ChangeEmailRequest, ChangeUsernameRequest, ClientsResponse, ConsentForm,
+    ContextResponse, ConversationQuery, CreateArticleRequest, CreateCampaignRequest, CreatePostRequest,
+    CreatePostResponse, CreateThreadRequest, CreateThreadResponse, EditPostRequest, FederationKeyResponse, FeedMeta,
+    FeedPollQuery, FeedPollResponse, FeedPositionResponse, FeedStreamQuery, FeedbackRequest, FieldsQuery,
+    FollowUserRequest, GetFeedQuery, GetFeedResponse, GrantsResponse, HidePostsRequest, HidePostsResponse,
+    HoldsResponse, InboxResponse, InterestsResponse, LikePostRequest, LinkAccountRequest, LocalizedNotification,
+    LoginHistoryResponse, LoginRequest, MarkNotificationsReadRequest, MarkNotificationsReadResponse, MediaQuery,
+    ModerationCasesResponse, NotificationsResponse, OAuthErrorResponse, PasswordLoginRequest, PlaceHoldRequest,
+    PostAnalytics, ProfileQuery, ProfileResponse, ProfileViewsQuery, ProfileViewsResponse, ProfileVisitor,
+    ProjectionsResponse, RebuildResponse, RegionsResponse, RegisterClientRequest, RegisterClientResponse,
+    RelatedPostView, RelatedPostsResponse, RelatedQuery, RevealAuthorRequest, RevealAuthorResponse, RsvpRequest,
+    SaveSearchRequest, SavedSearchesResponse, ScheduledPostResponse, SearchQuery, SearchResults, SetAccountStateRequest,
+    SetDailyLimitRequest, SetFeedPositionRequest, SetNotifyRequest, SetPasswordRequest, SetRegionRequest,
+    SetVerifiedRequest, SignupRequest, SignupResponse, StatsQuery, SuccessResponse, TakedownRequest, TokenRequest,
+    TokenResponse, TrendingHashtag, TrendingHashtagsQuery, TrendingHashtagsResponse, TwoFactorCodeRequest,
+    TwoFactorEnabledResponse, TwoFactorPolicy, TypeaheadHashtag, TypeaheadQuery, TypeaheadResponse, TypeaheadUser,
+    UpdateProfileRequest, VerifyEmailRequest, ViewBeaconRequest,
+};
+use crate::versioning::Envelope;
+use crate::video::{self, VideoPipeline};
+
+// JSON request bodies, capped at the configured size
+pub fn json_body<T: serde::de::DeserializeOwned + Send>(
+    max_bytes: u64,
+) -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone {
+    warp::body::content_length_limit(max_bytes).and(warp::body::json())
+}
+
+pub const MAX_THREAD_POSTS: usize = 25;
+
+pub fn check_length(text: &str, config: &Config) -> Result<(), warp::Rejection> {
+    let length = content::weighted_length(text, config.url_weight);
+    if length > config.max_post_length {
+        return Err(warp::reject::custom(ContentTooLong {
+            length,
+            max_length: config.max_post_length,
+        }));
+    }
+    Ok(())
+}
+
+pub fn clean_alt_text(alt_text: Option<String>) -> Option<String> {
+    alt_text
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty())
+}
+
+pub fn validate_post(request: CreatePostRequest, config: &Config) -> Result<PostDraft, warp::Rejection> {
+    // create_post_handler takes publish_at out first
+    if request.publish_at.is_some() {
+        return Err(warp::reject::custom(ValidationError(
+            "Only single top-level posts can be scheduled".to_string(),
+        )));
+    }
+    check_length(&request.content, config)?;
+
+    let alt_text = clean_alt_text(request.alt_text);
+    if config.require_image_alt_text && request.image_url.is_some() && alt_text.is_none() {
+        return Err(warp::reject::custom(ValidationError(
+            "alt_text is required for image posts".to_string(),
+        )));
+    }
+    // ffmpeg fetches the video server-side, so only public https sources
+    if let Some(video_url) = &request.video_url
+        && !video::valid_source_url(video_url)
+    {
+        return Err(warp::reject::custom(ValidationError(
+            "video_url must be a public https:// URL".to_string(),
+        )));
+    }
+    let license = request
+        .license
+        .map(|license| check_license(&license, config))
+        .transpose()?;
+    let mut co_authors = request.co_authors;
+    let mut seen = HashSet::new();
+    co_authors.retain(|user_id| seen.insert(user_id.clone()));
+    if co_authors.len() > MAX_CO_AUTHORS {
+        return Err(warp::reject::custom(ValidationError(format!(
+            "A post can have at most {} co-authors",
+            MAX_CO_AUTHORS
+        ))));
+    }
+    if request.anonymous && !co_authors.is_empty() {
+        return Err(warp::reject::custom(ValidationError(
+            "Anonymous posts can't have co-authors".to_string(),
+        )));
+    }
+    let event = request.event.map(check_event).transpose()?;
+    if request.anonymous && event.is_some() {
+        return Err(warp::reject::custom(ValidationError(
+            "Anonymous posts can't be events".to_string(),
+        )));
+    }
+
+    Ok(PostDraft {
+        content: request.content,
+        image_url: request.image_url,
+        video_url: request.video_url,
+        alt_text,
+        in_reply_to: None,
+        reply_policy: request.reply_policy,
+        anonymous: request.anonymous,
+        license,
+        co_authors,
+        event,
+        article: None,
+    })
+}
+
+// Times are epoch milliseconds; an event must not have started yet
+pub fn check_event(mut event: EventDetails) -> Result<EventDetails, warp::Rejection> {
+    event.title = event.title.trim().to_string();
+    event.location = event
+        .location
+        .map(|location| location.trim().to_string())
+        .filter(|location| !location.is_empty());
+    let problem = if event.title.is_empty() {
+        Some("An event needs a title".to_string())
+    } else if event.title.chars().count() > MAX_EVENT_TITLE_CHARS {
+        Some(format!("An event title can be at most {} characters", MAX_EVENT_TITLE_CHARS))
+    } else if event.location.as_ref().is_some_and(|location| location.chars().count() > MAX_EVENT_LOCATION_CHARS) {
+        Some(format!("An event location can be at most {} characters", MAX_EVENT_LOCATION_CHARS))
+    } else if event.starts_at <= now_millis() {
+        Some("An event must start in the future".to_string())
+    } else if event.ends_at.is_some_and(|ends_at| ends_at <= event.starts_at) {
+        Some("An event must end after it starts".to_string())
+    } else {
+        None
+    };
+    match problem {
+        Some(message) => Err(warp::reject::custom(ValidationError(message))),
+        None => Ok(event),
+    }
+}
+
+// Co-authors must be other existing accounts, and neither side may have
+// blocked the other
+pub fn check_co_authors(state: &AppState, author_id: &UserId, draft: &PostDraft) -> Result<(), warp::Rejection> {
+    for user_id in &draft.co_authors {
+        if user_id == author_id
+            || state.cache.get_user(user_id).is_none()
+            || state.cache.has_blocked(user_id, author_id)
+            || state.cache.has_blocked(author_id, user_id)
+        {
+            return Err(warp::reject::custom(ValidationError(format!(
+                "{} can't be invited as a co-author",
+                user_id
+            ))));
+        }
+    }
+    Ok(())
+}
+
+// The license as configured, lowercased
+pub fn check_license(license: &str, config: &Config) -> Result<String, warp::Rejection> {
+    let license = license.trim().to_lowercase();
+    if config.licenses.contains(&license) {
+        return Ok(license);
+    }
+    let message = if config.licenses.is_empty() {
+        "Posts can't carry a license on this server".to_string()
+    } else {
+        format!("license must be one of {}", config.licenses.join(", "))
+    };
+    Err(warp::reject::custom(ValidationError(message)))
+}
+
+// Anonymous posts are allowed per tenant
+pub fn check_anonymous(ctx: &RequestContext, draft: &PostDraft, config: &Config) -> Result<(), warp::Rejection> {
+    if draft.anonymous && !config.anonymous_posting.iter().any(|tenant| tenant == ctx.tenant.name()) {
+        return Err(warp::reject::custom(Forbidden {
+            code: "anonymous_posting_off",
+            message: "Anonymous posting is turned off",
+        }));
+    }
+    Ok(())
+}
+
+// Posts can be scheduled up to a year ahead
+pub const MAX_SCHEDULE_MILLIS: u64 = 365 * DAY_MILLIS;
+
+// When a scheduled post goes out. A time without an offset is a wall clock
+// time in the author's timezone.
+pub fn resolve_publish_at(state: &AppState, user_id: &UserId, value: &str) -> Result<u64, warp::Rejection> {
+    let invalid = |message: &str| warp::reject::custom(ValidationError(message.to_string()));
+    let (local, offset) = timezones::parse_date_time(value)
+        .ok_or_else(|| invalid("publish_at must be a date and time like 2026-10-18T09:00, with an optional offset"))?;
+    let utc = match offset {
+        Some(offset) => local - offset as i64,
+        None => state.cache.user_zone(user_id).to_utc(local),
+    };
+    let publish_at = u64::try_from(utc).unwrap_or(0).saturating_mul(1000);
+    let now = now_millis();
+    if publish_at <= now {
+        return Err(invalid("publish_at must be in the future"));
+    }
+    if publish_at > now + MAX_SCHEDULE_MILLIS {
+        return Err(invalid("publish_at must be within a year"));
+    }
+    Ok(publish_at)
+}
+
+pub fn start_media_processing(state: &AppState, post: &Post) {
+    submit_video(&state.video_pipeline, &account_region(state, &post.user_id), post);
+}
+
+pub fn submit_video(video_pipeline: &VideoPipeline, region: &Region, post: &Post) {
+    if let Some(video_url) = &post.video_url
+        && let Err(e) = video_pipeline.submit(&post.id, video_url, region)
+    {
+        warn!("Video processing failed: {}", e);
+    }
+}
+
+// Route handlers
+pub async fn create_post_handler(
+    ctx: RequestContext,
+    mut request: CreatePostRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let publish_at = request
+        .publish_at
+        .take()
+        .map(|value| resolve_publish_at(&state, &ctx.user_id, &value))
+        .transpose()?;
+    let draft = validate_post(request, &state.config)?;
+    check_anonymous(&ctx, &draft, &state.config)?;
+    check_co_authors(&state, &ctx.user_id, &draft)?;
+    if let Some(publish_at) = publish_at {
+        if draft.event.as_ref().is_some_and(|event| event.starts_at <= publish_at) {
+            return Err(warp::reject::custom(ValidationError(
+                "An event must start after it's published".to_string(),
+            )));
+        }
+        let scheduled = ScheduledPost {
+            user_id: ctx.user_id.clone(),
+            draft,
+        };
+        let scheduled_id = state.jobs.enqueue_at(&scheduled, publish_at);
+        debug!("Post by {} scheduled for {}", ctx.user_id, publish_at);
+        return Ok(warp::reply::json(&ScheduledPostResponse {
+            success: true,
+            scheduled_id,
+            publish_at,
+        }));
+    }
+    check_fanout_backlog(&state)?;
+    let post = state.post_service.create_post(&ctx.user_id, draft).await;
+
+    start_media_processing(&state, &post);
+    schedule_event_reminder(&state.jobs, &post, state.config.event_reminder_secs);
+
+    Ok(warp::reply::json(&CreatePostResponse {
+        success: true,
+        post_id: post.id,
+    }))
+}
+
+// An article is published as a top-level post carrying its card, which
+// fans out like any other; the body is only served by get_article_handler
+pub async fn create_article_handler(
+    ctx: RequestContext,
+    request: CreateArticleRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let title = request.title.trim().to_string();
+    let problem = if title.is_empty() {
+        Some("An article needs a title".to_string())
+    } else if title.chars().count() > MAX_ARTICLE_TITLE_CHARS {
+        Some(format!("An article title can be at most {} characters", MAX_ARTICLE_TITLE_CHARS))
+    } else if request.body.trim().is_empty() {
+        Some("An article needs a body".to_string())
+    } else if request.body.chars().count() > MAX_ARTICLE_CHARS {
+        Some(format!("An article body can be at most {} characters", MAX_ARTICLE_CHARS))
+    } else {
+        None
+    };
+    if let Some(message) = problem {
+        return Err(warp::reject::custom(ValidationError(message)));
+    }
+    let summary = match request.summary.map(|summary| summary.trim().to_string()).filter(|summary| !summary.is_empty()) {
+        Some(summary) => {
+            check_length(&summary, &state.config)?;
+            summary
+        }
+        None => Some(articles::teaser(&request.body, state.config.max_post_length))
+            .filter(|teaser| !teaser.is_empty())
+            .unwrap_or_else(|| title.clone()),
+    };
+    let license = request
+        .license
+        .map(|license| check_license(&license, &state.config))
+        .transpose()?;
+    let card = ArticleCard {
+        title,
+        cover_image_url: request
+            .cover_image_url
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty()),
+        reading_minutes: articles::reading_minutes(&request.body),
+    };
+    let draft = PostDraft {
+        content: summary,
+        image_url: None,
+        video_url: None,
+        alt_text: None,
+        in_reply_to: None,
+        reply_policy: request.reply_policy,
+        anonymous: false,
+        license,
+        co_authors: Vec::new(),
+        event: None,
+        article: Some(card),
+    };
+    check_fanout_backlog(&state)?;
+    let post = state
+        .post_service
+        .create_article(&ctx.user_id, draft, request.body)
+        .await;
+
+    Ok(warp::reply::json(&CreatePostResponse {
+        success: true,
+        post_id: post.id,
+    }))
+}
+
+// The article page: its post as anywhere else, plus the body as written and
+// as HTML. Posts that aren't articles are not found, and a withheld article
+// is served blank with its tombstone.
+pub async fn get_article_handler(post_id: PostId, ctx: RequestContext, state: AppState) -> Result<impl Reply, warp::Rejection> {
+    let viewer = state.cache.viewer(&ctx.user_id);
+    let post = state
+        .cache
+        .post_for(&viewer, &post_id)
+        .map_err(|reason| post_unavailable(&state.config, reason))?;
+    if post.article.is_none() {
+        return Err(warp::reject::custom(NotFound));
+    }
+    let post = state
+        .news_feed_service
+        .shape(&viewer, post)
+        .map_err(|reason| post_unavailable(&state.config, reason))?;
+    let body = match post.withheld {
+        Some(_) => String::new(),
+        None => state.cache.article_body(&post_id).unwrap_or_default(),
+    };
+    Ok(warp::reply::json(&ArticleResponse {
+        html: articles::render(&body),
+        body,
+        post,
+    }))
+}
+
+// Top-level posts are fanned out, so they're turned away while the fanout
+// queue is full rather than sending their fanout straight to failed
+pub fn check_fanout_backlog(state: &AppState) -> Result<(), warp::Rejection> {
+    if state.jobs.is_full::<FanoutMessage>() {
+        return Err(warp::reject::custom(Overloaded { retry_after_secs: 5 }));
+    }
+    Ok(())
+}
+
+pub async fn create_reply_handler(
+    post_id: PostId,
+    ctx: RequestContext,
+    request: CreatePostRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let parent = state
+        .cache
+        .post_for(&state.cache.viewer(&ctx.user_id), &post_id)
+        .map_err(|reason| post_unavailable(&state.config, reason))?;
+
+    if !state.cache.can_reply(&ctx.user_id, &parent) {
+        return Err(warp::reject::custom(Forbidden {
+            code: "reply_restricted",
+            message: "The author has limited who can reply to this post",
+        }));
+    }
+
+    let mut draft = validate_post(request, &state.config)?;
+    check_anonymous(&ctx, &draft, &state.config)?;
+    if !draft.co_authors.is_empty() {
+        return Err(warp::reject::custom(ValidationError(
+            "Replies can't have co-authors".to_string(),
+        )));
+    }
+    if draft.event.is_some() {
+        return Err(warp::reject::custom(ValidationError(
+            "Replies can't be events".to_string(),
+        )));
+    }
+    draft.in_reply_to = Some(parent.id);
+    let reply = state.post_service.create_post(&ctx.user_id, draft).await;
+
+    start_media_processing(&state, &reply);
+
+    Ok(warp::reply::json(&CreatePostResponse {
+        success: true,
+        post_id: reply.id,
+    }))
+}
+
+pub async fn create_thread_handler(
+    ctx: RequestContext,
+    request: CreateThreadRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if request.posts.is_empty() || request.posts.len() > MAX_THREAD_POSTS {
+        return Err(warp::reject::custom(ValidationError(format!(
+            "A thread must contain between 1 and {} posts",
+            MAX_THREAD_POSTS
+        ))));
+    }
+
+    let drafts = request
+        .posts
+        .into_iter()
+        .map(|post| validate_post(post, &state.config))
+        .collect::<Result<Vec<_>, _>>()?;
+    if drafts.iter().any(|draft| draft.anonymous) {
+        return Err(warp::reject::custom(ValidationError(
+            "Threads can't be posted anonymously".to_string(),
+        )));
+    }
+    if drafts.iter().any(|draft| !draft.co_authors.is_empty()) {
+        return Err(warp::reject::custom(ValidationError(
+            "Threads can't have co-authors".to_string(),
+        )));
+    }
+    if drafts.iter().any(|draft| draft.event.is_some()) {
+        return Err(warp::reject::custom(ValidationError(
+            "Threads can't be events".to_string(),
+        )));
+    }
+    check_fanout_backlog(&state)?;
+    let posts = state.post_service.create_thread(&ctx.user_id, drafts).await;
+
+    for post in &posts {
+        start_media_processing(&state, post);
+    }
+
+    Ok(warp::reply::json(&CreateThreadResponse {
+        success: true,
+        post_ids: posts.into_iter().map(|post| post.id).collect(),
+    }))
+}
+
+// One page of the feed, the cursor for the page after it, and the saved
+// position it resumed from, if any
+pub async fn load_feed(
+    ctx: &RequestContext,
+    query: &GetFeedQuery,
+    state: &AppState,
+) -> Result<(Vec<HydratedPost>, Option<String>, Option<FeedPositionResponse>), warp::Rejection> {
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(|cursor| {
+            FeedCursor::decode(cursor).ok_or_else(|| warp::reject::custom(ValidationError("Invalid cursor".to_string())))
+        })
+        .transpose()?;
+    let position = if query.resume && cursor.is_none() {
+        state.cache.get_feed_position(&ctx.user_id)
+    } else {
+        None
+    };
+    let start = cursor.as_ref().or(position.as_ref().map(|position| &position.cursor));
+    state.cache.record_activity(&ctx.user_id, Activity::FeedRead);
+    let ranking = query
+        .ranking
+        .unwrap_or_else(|| state.cache.get_preferences(&ctx.user_id).feed_ranking);
+    let feed = state
+        .news_feed_service
+        .get_news_feed(ctx, limit, start, query.mode, ranking)
+        .await
+        .map_err(|Cancelled| warp::reject::custom(RequestCancelled))?;
+    let next_cursor = state
+        .news_feed_service
+        .next_cursor(&ctx.user_id, start, limit)
+        .map(|cursor| cursor.encode());
+    Ok((feed, next_cursor, position.map(FeedPositionResponse::from)))
+}
+
+pub fn parse_fields(fields: Option<&str>, root: &str) -> Result<Option<FieldSelection>, warp::Rejection> {
+    fields
+        .map(|fields| FieldSelection::parse(fields, root))
+        .transpose()
+        .map_err(|message| warp::reject::custom(ValidationError(message)))
+}
+
+pub async fn get_feed_handler(
+    ctx: RequestContext,
+    query: GetFeedQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let selection = parse_fields(query.fields.as_deref(), "post")?;
+    let (feed, next_cursor, resumed_from) = load_feed(&ctx, &query, &state).await?;
+    Ok(warp::reply::json(&project(
+        &GetFeedResponse {
+            feed,
+            next_cursor,
+            resumed_from,
+        },
+        Some("feed"),
+        selection.as_ref(),
+    )))
+}
+
+// v2: posts under `data`, everything about the page under `meta`
+pub async fn get_feed_v2_handler(
+    ctx: RequestContext,
+    query: GetFeedQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let selection = parse_fields(query.fields.as_deref(), "post")?;
+    let (feed, next_cursor, resumed_from) = load_feed(&ctx, &query, &state).await?;
+    Ok(warp::reply::json(&project(
+        &Envelope {
+            data: feed,
+            meta: FeedMeta {
+                next_cursor,
+                resumed_from,
+            },
+        },
+        Some("data"),
+        selection.as_ref(),
+    )))
+}
+
+pub const MAX_HIDE_BATCH: usize = 100;
+
+pub async fn hide_posts_handler(
+    ctx: RequestContext,
+    request: HidePostsRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if request.post_ids.is_empty() || request.post_ids.len() > MAX_HIDE_BATCH {
+        return Err(warp::reject::custom(ValidationError(format!(
+            "post_ids must contain between 1 and {} IDs",
+            MAX_HIDE_BATCH
+        ))));
+    }
+
+    let removed = {
+        let _feed_lock = state.cache.feed_locks().lock(&ctx.user_id).await;
+        state.cache.hide_posts(&ctx.user_id, &request.post_ids)
+    };
+    Ok(warp::reply::json(&HidePostsResponse {
+        success: true,
+        removed,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct TelemetryResponse {
+    pub accepted: usize,
+    pub skipped: usize, // deleted, under a legal hold, or the viewer's own
+}
+
+// Batched client telemetry: how long each feed item was on screen and whether
+// it was opened. Quick scrolls past become fast-scroll signals; long looks and
+// clicks become interests.
+pub async fn feed_telemetry_handler(
+    ctx: RequestContext,
+    batch: TelemetryBatch,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let now = now_millis();
+    state
+        .telemetry_limiter
+        .check(&ctx.user_id, now)
+        .map_err(|retry_after_secs| warp::reject::custom(RateLimited { retry_after_secs }))?;
+    batch.validate().map_err(|e| warp::reject::custom(ValidationError(e)))?;
+
+    let (mut accepted, mut skipped) = (0, 0);
+    for item in &batch.items {
+        let Some(post) = state
+            .cache
+            .get_post(&item.post_id)
+            .filter(|post| post.user_id != ctx.user_id)
+        else {
+            skipped += 1;
+            continue;
+        };
+        accepted += 1;
+        state.cache.record_view(&post.id, &ctx.user_id);
+        state
+            .engagement_log
+            .record(&ctx.user_id, &post, Interaction::Dwell { millis: item.dwell_millis() });
+        if item.clicked {
+            state.engagement_log.record(&ctx.user_id, &post, Interaction::Click);
+        }
+        match item.reading() {
+            Reading::ScrolledPast if state.cache.first_reading(&ctx.user_id, &post.id, false) => {
+                state
+                    .cache
+                    .add_negative_signal(&ctx.user_id, NegativeSignal::for_post(SignalKind::FastScroll, &post));
+            }
+            Reading::Engaged(engagement) if state.cache.first_reading(&ctx.user_id, &post.id, true) => {
+                state.cache.record_interest(&ctx.user_id, &post, engagement, now);
+            }
+            _ => {}
+        }
+    }
+    Ok(warp::reply::json(&TelemetryResponse { accepted, skipped }))
+}
+
+pub async fn feedback_handler(
+    ctx: RequestContext,
+    request: FeedbackRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if let Some(post_id) = request.post_id {
+        let post = state
+            .cache
+            .get_post(&post_id)
+            .ok_or_else(|| warp::reject::custom(NotFound))?;
+        if request.kind == SignalKind::Hide {
+            let _feed_lock = state.cache.feed_locks().lock(&ctx.user_id).await;
+            state.cache.hide_posts(&ctx.user_id, &[post_id]);
+        } else {
+            state
+                .cache
+                .add_negative_signal(&ctx.user_id, NegativeSignal::for_post(request.kind, &post));
+        }
+        return Ok(warp::reply::json(&SuccessResponse { success: true }));
+    }
+
+    if request.kind != SignalKind::Mute {
+        return Err(warp::reject::custom(ValidationError(
+            "post_id is required for this feedback kind".to_string(),
+        )));
+    }
+
+    if let Some(author_id) = &request.author_id
+        && state.cache.get_user(author_id).is_none()
+    {
+        return Err(warp::reject::custom(NotFound));
+    }
+    let topic = request
+        .topic
+        .map(|topic| TagId::from_hashtag(&topic))
+        .filter(|topic| !topic.as_str().is_empty());
+    if request.author_id.is_none() && topic.is_none() {
+        return Err(warp::reject::custom(ValidationError(
+            "mute requires post_id, author_id, or topic".to_string(),
+        )));
+    }
+
+    state.cache.add_negative_signal(
+        &ctx.user_id,
+        NegativeSignal {
+            kind: SignalKind::Mute,
+            post_id: None,
+            author_id: request.author_id,
+            topics: topic.into_iter().collect(),
+            created_at: now_millis(),
+        },
+    );
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+pub const MAX_POLL_WAIT_SECS: u64 = 30;
+pub const POLL_BATCH_SIZE: usize = 20;
+
+// Long-poll fallback for clients without SSE or WebSockets: answers as soon
+// as anything newer than `since` reaches the feed, or 304 after `wait` seconds
+pub async fn poll_feed_handler(
+    ctx: RequestContext,
+    query: FeedPollQuery,
+    state: AppState,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let Some(since) = query.since else {
+        // No cursor yet: hand out the current one to poll from
+        return Ok(warp::reply::json(&FeedPollResponse {
+            feed: Vec::new(),
+            cursor: state.news_feed_service.latest_cursor(&ctx.user_id).encode(),
+        })
+        .into_response());
+    };
+    let since = FeedCursor::decode(&since)
+        .ok_or_else(|| warp::reject::custom(ValidationError("Invalid cursor".to_string())))?;
+    state.cache.record_activity(&ctx.user_id, Activity::FeedRead);
+    let wait = Duration::from_secs(query.wait.unwrap_or(25).min(MAX_POLL_WAIT_SECS));
+
+    // Subscribe before checking so an item landing in between still wakes us
+    let mut receiver = state.cache.feed_updates.subscribe(&ctx.user_id);
+    let deadline = tokio::time::Instant::now() + wait;
+    let (mut feed, mut cursor) = state.news_feed_service.new_items(&ctx.user_id, &since, POLL_BATCH_SIZE);
+    while feed.is_empty() {
+        match tokio::time::timeout_at(deadline, receiver.recv()).await {
+            Ok(Ok(_)) | Ok(Err(tokio::sync::broadcast::error::RecvError::Lagged(_))) => {
+                (feed, cursor) = state.news_feed_service.new_items(&ctx.user_id, &since, POLL_BATCH_SIZE);
+            }
+            Ok(Err(tokio::sync::broadcast::error::RecvError::Closed)) | Err(_) => break,
+        }
+    }
+    state.cache.feed_updates.release(&ctx.user_id, receiver);
+
+    if feed.is_empty() {
+        return Ok(warp::reply::with_status(warp::reply(), warp::http::StatusCode::NOT_MODIFIED).into_response());
+    }
+    Ok(warp::reply::json(&FeedPollResponse {
+        feed,
+        cursor: cursor.encode(),
+    })
+    .into_response())
+}
+
+// Upgrades to a WebSocket that pushes feed items as they're delivered (see
+// FeedStreamService). The request's time limit covers only the upgrade.
+pub async fn stream_feed_handler(
+    ctx: RequestContext,
+    ws: warp::ws::Ws,
+    query: FeedStreamQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let cursor = match query.cursor {
+        Some(cursor) => FeedCursor::decode(&cursor)
+            .ok_or_else(|| warp::reject::custom(ValidationError("Invalid cursor".to_string())))?,
+        None => state.news_feed_service.latest_cursor(&ctx.user_id),
+    };
+    let slot = state.feed_stream.connect(&ctx.user_id).ok_or_else(|| {
+        warp::reject::custom(Conflict {
+            code: "too_many_streams",
+            message: "Too many feed streams are open for this account",
+        })
+    })?;
+    state.cache.record_activity(&ctx.user_id, Activity::FeedRead);
+    let service = state.feed_stream.clone();
+    Ok(ws.on_upgrade(move |socket| service.run(slot, socket, cursor)))
+}
+
+pub async fn get_feed_position_handler(
+    ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let position = state
+        .cache
+        .get_feed_position(&ctx.user_id)
+        .ok_or_else(|| warp::reject::custom(NotFound))?;
+    Ok(warp::reply::json(&FeedPositionResponse::from(position)))
+}
+
+pub async fn set_feed_position_handler(
+    ctx: RequestContext,
+    request: SetFeedPositionRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let item = state
+        .cache
+        .find_feed_item(&ctx.user_id, &request.post_id)
+        .ok_or_else(|| {
+            warp::reject::custom(ValidationError("That post is not in your feed".to_string()))
+        })?;
+
+    let position = FeedPosition {
+        cursor: FeedCursor::from_item(&item),
+        updated_at: now_millis(),
+    };
+    state.cache.set_feed_position(&ctx.user_id, position.clone());
+    Ok(warp::reply::json(&FeedPositionResponse::from(position)))
+}
+
+// One post. A post the viewer can't have is answered with why, as far as
+// NEWS_FEED_REVEAL_POST_RESTRICTIONS allows.
+pub async fn get_post_handler(
+    post_id: PostId,
+    ctx: RequestContext,
+    query: FieldsQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let selection = parse_fields(query.fields.as_deref(), "post")?;
+    let viewer = state.cache.viewer(&ctx.user_id);
+    let post = state
+        .cache
+        .post_for(&viewer, &post_id)
+        .and_then(|post| state.news_feed_service.shape(&viewer, post))
+        .map_err(|reason| post_unavailable(&state.config, reason))?;
+    Ok(warp::reply::json(&project(&post, None, selection.as_ref())))
+}
+
+// Only the author may change or remove a post; anyone else who can see it
+// is told so
+pub fn authored_post(
+    state: &AppState,
+    ctx: &RequestContext,
+    post_id: &PostId,
+    action: &'static str,
+) -> Result<Post, warp::Rejection> {
+    let post = state
+        .cache
+        .post_for(&state.cache.viewer(&ctx.user_id), post_id)
+        .map_err(|reason| post_unavailable(&state.config, reason))?;
+    if post.user_id != ctx.user_id {
+        return Err(warp::reject::custom(Forbidden {
+            code: "not_author",
+            message: action,
+        }));
+    }
+    Ok(post)
+}
+
+pub async fn edit_post_handler(
+    post_id: PostId,
+    ctx: RequestContext,
+    request: EditPostRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let post = authored_post(&state, &ctx, &post_id, "Only the author can edit this post")?;
+    check_length(&request.content, &state.config)?;
+    let alt_text = clean_alt_text(request.alt_text);
+    if state.config.require_image_alt_text && post.image_url.is_some() && alt_text.is_none() {
+        return Err(warp::reject::custom(ValidationError(
+            "alt_text is required for image posts".to_string(),
+        )));
+    }
+
+    let post = state.post_service.edit_post(post, request.content, alt_text).await;
+    let viewer = state.cache.viewer(&ctx.user_id);
+    let post = state
+        .news_feed_service
+        .shape(&viewer, post)
+        .map_err(|reason| post_unavailable(&state.config, reason))?;
+    Ok(warp::reply::json(&post))
+}
+
+pub async fn delete_post_handler(
+    post_id: PostId,
+    ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    authored_post(&state, &ctx, &post_id, "Only the author can delete this post")?;
+    state.post_service.delete_post(&post_id).await;
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+pub async fn get_conversation_handler(
+    post_id: PostId,
+    ctx: RequestContext,
+    query: ConversationQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let offset = match query.cursor {
+        Some(cursor) => cursor
+            .parse()
+            .map_err(|_| warp::reject::custom(ValidationError("Invalid cursor".to_string())))?,
+        None => 0,
+    };
+
+    let conversation = state
+        .conversation_service
+        .get_conversation(&ctx.user_id, &post_id, offset, limit)
+        .await
+        .map_err(|reason| post_unavailable(&state.config, reason))?;
+    // Opening a post is fetching its conversation; later pages aren't
+    if offset == 0 {
+        state.engagement_log.record(&ctx.user_id, &conversation.post.post, Interaction::Open);
+    }
+    Ok(warp::reply::json(&conversation))
+}
+
+pub async fn get_replies_handler(
+    post_id: PostId,
+    ctx: RequestContext,
+    query: ConversationQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let offset = match query.cursor {
+        Some(cursor) => cursor
+            .parse()
+            .map_err(|_| warp::reject::custom(ValidationError("Invalid cursor".to_string())))?,
+        None => 0,
+    };
+
+    let replies = state
+        .conversation_service
+        .replies(&ctx.user_id, &post_id, offset, limit)
+        .map_err(|reason| post_unavailable(&state.config, reason))?;
+    Ok(warp::reply::json(&replies))
+}
+
+pub async fn follow_user_handler(
+    ctx: RequestContext,
+    request: FollowUserRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if state.cache.has_blocked(&request.target_user_id, &ctx.user_id) {
+        return Err(warp::reject::custom(NotFound));
+    }
+    state.events.publish(FeedEvent::Followed {
+        follower_id: ctx.user_id,
+        followed_id: request.target_user_id,
+    });
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+pub async fn unfollow_user_handler(
+    ctx: RequestContext,
+    request: FollowUserRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    state.events.publish(FeedEvent::Unfollowed {
+        follower_id: ctx.user_id,
+        followed_id: request.target_user_id,
+    });
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+// Blocking also removes any follow between the two accounts. From then on
+// the blocked account gets 404s for the blocker's profile and posts, and
+// doesn't see them in feeds, search, or conversations.
+pub async fn block_user_handler(
+    target_user_id: UserId,
+    ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if target_user_id == ctx.user_id {
+        return Err(warp::reject::custom(ValidationError("You can't block yourself".to_string())));
+    }
+    if state.cache.get_user(&target_user_id).is_none() {
+        return Err(warp::reject::custom(NotFound));
+    }
+    state.cache.block(&ctx.user_id, &target_user_id);
+    for (follower_id, followed_id) in [(&ctx.user_id, &target_user_id), (&target_user_id, &ctx.user_id)] {
+        if state.cache.is_following(follower_id, followed_id) {
+            state.events.publish(FeedEvent::Unfollowed {
+                follower_id: follower_id.clone(),
+                followed_id: followed_id.clone(),
+            });
+        }
+    }
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+pub async fn unblock_user_handler(
+    target_user_id: UserId,
+    ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    state.cache.unblock(&ctx.user_id, &target_user_id);
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+// The bell is a property of an existing follow
+pub async fn set_notify_handler(
+    target_user_id: UserId,
+    ctx: RequestContext,
+    request: SetNotifyRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if !state.cache.is_following(&ctx.user_id, &target_user_id) {
+        return Err(warp::reject::custom(Conflict {
+            code: "not_following",
+            message: "Follow the account before turning on notifications",
+        }));
+    }
+    state.events.publish(FeedEvent::NotifySet {
+        follower_id: ctx.user_id,
+        followed_id: target_user_id,
+        enabled: request.enabled,
+    });
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+pub const MAX_DAILY_LIMIT: u16 = 50;
+
+// Soft-mute: the account stays followed but only its first posts of each UTC
+// day reach the feed; the rest are on its profile timeline
+pub async fn set_daily_limit_handler(
+    target_user_id: UserId,
+    ctx: RequestContext,
+    request: SetDailyLimitRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if let Some(limit) = request.posts_per_day
+        && !(1..=MAX_DAILY_LIMIT).contains(&limit)
+    {
+        return Err(warp::reject::custom(ValidationError(format!(
+            "posts_per_day must be between 1 and {}",
+            MAX_DAILY_LIMIT
+        ))));
+    }
+    if !state.cache.is_following(&ctx.user_id, &target_user_id) {
+        return Err(warp::reject::custom(Conflict {
+            code: "not_following",
+            message: "Follow the account before limiting its posts",
+        }));
+    }
+    state.events.publish(FeedEvent::DailyLimitSet {
+        follower_id: ctx.user_id,
+        followed_id: target_user_id,
+        limit: request.posts_per_day,
+    });
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+pub async fn get_user_posts_handler(
+    author_id: UserId,
+    ctx: RequestContext,
+    query: ConversationQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if state.cache.get_user(&author_id).is_none()
+        || state.cache.is_user_held(&author_id)
+        || state.cache.hidden_from(&state.cache.viewer(&ctx.user_id), &author_id)
+    {
+        return Err(warp::reject::custom(NotFound));
+    }
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let offset = match query.cursor {
+        Some(cursor) => cursor
+            .parse()
+            .map_err(|_| warp::reject::custom(ValidationError("Invalid cursor".to_string())))?,
+        None => 0,
+    };
+
+    let timeline = state
+        .news_feed_service
+        .profile_timeline(&ctx.user_id, &author_id, offset, limit);
+    Ok(warp::reply::json(&timeline))
+}
+
+// Posts matching every word of the query and its filters, ranked or newest
+// first. The index can trail a delete briefly, so hits are checked against
+// the posts cache.
+pub async fn search_posts_handler(
+    ctx: RequestContext,
+    query: SearchQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let parsed = search::Query::parse(&query.q).map_err(|e| warp::reject::custom(ValidationError(e)))?;
+    if parsed.terms.is_empty() && parsed.from.is_none() {
+        return Err(warp::reject::custom(ValidationError(
+            "Query needs words to search for or a from: filter".to_string(),
+        )));
+    }
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let offset = match query.cursor {
+        Some(cursor) => cursor
+            .parse()
+            .map_err(|_| warp::reject::custom(ValidationError("Invalid cursor".to_string())))?,
+        None => 0,
+    };
+
+    // An unknown author matches nothing
+    let author = match &parsed.from {
+        Some(username) => match state.cache.find_user_id_by_username(username) {
+            Some(user_id) => Some(user_id),
+            None => {
+                return Ok(warp::reply::json(&SearchResults {
+                    posts: Vec::new(),
+                    next_cursor: None,
+                    indexed_seq: state.search.indexed_seq(),
+                }));
+            }
+        },
+        None => None,
+    };
+    let results = state.search.search(&parsed, author.as_ref());
+    let settings = state.settings.current();
+    let ranking = Ranking {
+        now: now_millis(),
+        half_life_millis: (settings.search_half_life_secs.max(1) * 1000) as f64,
+        engagement_weight: settings.search_engagement_weight,
+    };
+    let ranked = search::rank(results.hits, query.sort, &ranking, |post_id| {
+        let counters = state.cache.get_counters(post_id);
+        (counters.likes, counters.replies)
+    });
+    let viewer = state.cache.viewer(&ctx.user_id);
+    let posts = ranked
+        .iter()
+        .skip(offset)
+        .take(limit)
+        .filter_map(|post_id| state.cache.get_post(post_id))
+        .filter(|post| !state.cache.is_user_held(&post.user_id))
+        .filter(|post| state.cache.tombstone_for(&ctx.user_id, &post.id).is_none())
+        .filter_map(|post| state.news_feed_service.shape(&viewer, post).ok())
+        .collect();
+    Ok(warp::reply::json(&SearchResults {
+        posts,
+        next_cursor: (ranked.len() > offset + limit).then(|| (offset + limit).to_string()),
+        indexed_seq: results.indexed_seq,
+    }))
+}
+
+// "More like this" for a post's detail page. The list is computed for all
+// viewers and cached per post; each viewer then loses what they can't see.
+pub async fn related_posts_handler(
+    post_id: PostId,
+    ctx: RequestContext,
+    query: RelatedQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let viewer = state.cache.viewer(&ctx.user_id);
+    let post = state
+        .cache
+        .post_for(&viewer, &post_id)
+        .map_err(|reason| post_unavailable(&state.config, reason))?;
+    if state.cache.tombstone_for(&ctx.user_id, &post.id).is_some() {
+        return Err(warp::reject::custom(NotFound));
+    }
+    let limit = query.limit.unwrap_or(10).clamp(1, MAX_RELATED);
+
+    let ttl_millis = state.settings.current().related_cache_ttl_secs.saturating_mul(1000);
+    let related = match state
+        .cache
+        .get_related(&post_id, now_millis().saturating_sub(ttl_millis))
+        .filter(|_| ttl_millis > 0)
+    {
+        Some(related) => related,
+        None => {
+            let related = related::compute(state.cache.as_ref(), &state.search, state.vectors.as_deref(), &post);
+            if ttl_millis > 0 {
+                state.cache.put_related(&post_id, related.clone());
+            }
+            related
+        }
+    };
+
+    let posts = related
+        .into_iter()
+        .filter_map(|related| Some((state.cache.get_post(&related.post_id)?, related)))
+        .filter(|(post, _)| !state.cache.is_user_held(&post.user_id))
+        .filter(|(post, _)| state.cache.tombstone_for(&ctx.user_id, &post.id).is_none())
+        .filter(|(post, _)| !state.cache.is_hidden(&ctx.user_id, &post.id))
+        .filter_map(|(post, related)| Some((state.news_feed_service.shape(&viewer, post).ok()?, related)))
+        .take(limit)
+        .map(|(post, related)| RelatedPostView {
+            post,
+            score: related.score,
+            shared_hashtags: related.shared_hashtags,
+            shared_engagers: related.shared_engagers,
+            text_similarity: related.text_similarity,
+        })
+        .collect();
+    Ok(warp::reply::json(&RelatedPostsResponse { post_id, posts }))
+}
+
+pub async fn list_saved_searches_handler(ctx: RequestContext, state: AppState) -> Result<impl Reply, warp::Rejection> {
+    Ok(warp::reply::json(&SavedSearchesResponse {
+        saved_searches: state.cache.get_saved_searches(&ctx.user_id),
+    }))
+}
+
+// Saves a query for the background check. Only posts published from now on
+// count as new matches.
+pub async fn save_search_handler(
+    ctx: RequestContext,
+    request: SaveSearchRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let query = request.query.split_whitespace().collect::<Vec<_>>().join(" ");
+    let parsed = search::Query::parse(&query).map_err(|e| warp::reject::custom(ValidationError(e)))?;
+    if parsed.terms.is_empty() && parsed.from.is_none() {
+        return Err(warp::reject::custom(ValidationError(
+            "Query needs words to search for or a from: filter".to_string(),
+        )));
+    }
+    let saved = SavedSearch::new(format!("search_{}", Uuid::new_v4()), query, now_millis());
+    state
+        .cache
+        .add_saved_search(&ctx.user_id, saved.clone())
+        .map_err(|code| {
+            warp::reject::custom(Conflict {
+                code,
+                message: match code {
+                    "already_saved" => "This query is already saved",
+                    _ => "Delete a saved search before saving another",
+                },
+            })
+        })?;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&saved),
+        warp::http::StatusCode::CREATED,
+    ))
+}
+
+pub async fn delete_saved_search_handler(
+    search_id: String,
+    ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if !state.cache.remove_saved_search(&ctx.user_id, &search_id) {
+        return Err(warp::reject::custom(NotFound));
+    }
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+// Completions taken from each prefix index before ranking
+pub const TYPEAHEAD_CANDIDATES: usize = 200;
+pub const MAX_TYPEAHEAD_CHARS: usize = 64;
+
+pub async fn hashtag_timeline_handler(
+    tag: String,
+    ctx: RequestContext,
+    query: ConversationQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let tag = parse_hashtag(&tag)?;
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let offset = match query.cursor {
+        Some(cursor) => cursor
+            .parse()
+            .map_err(|_| warp::reject::custom(ValidationError("Invalid cursor".to_string())))?,
+        None => 0,
+    };
+
+    let timeline = state
+        .news_feed_service
+        .hashtag_timeline(&ctx.user_id, &tag, offset, limit);
+    Ok(warp::reply::json(&timeline))
+}
+
+// Hashtags used by the most posts in the last day
+pub async fn trending_hashtags_handler(
+    _ctx: RequestContext,
+    query: TrendingHashtagsQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let limit = query.limit.unwrap_or(10).clamp(1, 50);
+    let hashtags = state
+        .cache
+        .trending_hashtags(now_millis().saturating_sub(DAY_MILLIS), limit)
+        .into_iter()
+        .map(|(hashtag, posts)| TrendingHashtag { hashtag, posts })
+        .collect();
+    Ok(warp::reply::json(&TrendingHashtagsResponse { hashtags }))
+}
+
+// Usernames and hashtags starting with the query. `@` or `#` in front asks for
+// just one kind. Accounts the viewer follows come first, then exact matches,
+// then shorter names; hashtags rank by exact match, then how many posts use
+// them.
+pub async fn typeahead_handler(
+    ctx: RequestContext,
+    query: TypeaheadQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let q = query.q.trim();
+    if q.chars().count() > MAX_TYPEAHEAD_CHARS {
+        return Err(warp::reject::custom(ValidationError(format!(
+            "Query is longer than {} characters",
+            MAX_TYPEAHEAD_CHARS
+        ))));
+    }
+    let (want_users, want_hashtags, prefix) = match q.chars().next() {
+        Some('@') => (true, false, &q[1..]),
+        Some('#') => (false, true, &q[1..]),
+        _ => (true, true, q),
+    };
+    let prefix = prefix.to_lowercase();
+    if prefix.is_empty() {
+        return Err(warp::reject::custom(ValidationError("Query is empty".to_string())));
+    }
+    let limit = query.limit.unwrap_or(5).clamp(1, 20);
+    let cache = &state.cache;
+
+    let mut users = Vec::new();
+    if want_users {
+        // Followed accounts are looked up directly, so they aren't lost when
+        // more than the candidate limit share the prefix
+        let following: HashSet<UserId> = cache
+            .graph()
+            .following(&ctx.user_id)
+            .into_iter()
+            .map(|(user_id, _)| user_id)
+            .collect();
+        let mut candidates: HashSet<UserId> = cache
+            .typeahead()
+            .usernames(&prefix, TYPEAHEAD_CANDIDATES)
+            .into_iter()
+            .map(|(_, user_id)| user_id)
+            .collect();
+        candidates.extend(following.iter().cloned());
+        users = candidates
+            .into_iter()
+            .filter(|user_id| cache.account_state(user_id) == AccountState::Active && !cache.is_user_held(user_id))
+            .filter_map(|user_id| cache.get_user(&user_id))
+            .filter(|user| user.username.to_lowercase().starts_with(&prefix))
+            .map(|user| TypeaheadUser {
+                following: following.contains(&user.id),
+                user_id: user.id,
+                username: user.username,
+                profile_picture: user.profile_picture,
+                verified: user.verified,
+            })
+            .collect();
+        users.sort_by_cached_key(|user| {
+            let username = user.username.to_lowercase();
+            (!user.following, username != prefix, username.len(), username)
+        });
+        users.truncate(limit);
+    }
+
+    let mut hashtags = Vec::new();
+    if want_hashtags {
+        hashtags = cache
+            .typeahead()
+            .hashtags(&prefix, TYPEAHEAD_CANDIDATES)
+            .into_iter()
+            .map(|(hashtag, posts)| TypeaheadHashtag { hashtag, posts })
+            .collect();
+        hashtags.sort_by(|a, b| {
+            (a.hashtag.as_str() != prefix)
+                .cmp(&(b.hashtag.as_str() != prefix))
+                .then(b.posts.cmp(&a.posts))
+                .then(a.hashtag.as_str().len().cmp(&b.hashtag.as_str().len()))
+                .then(a.hashtag.cmp(&b.hashtag))
+        });
+        hashtags.truncate(limit);
+    }
+
+    Ok(warp::reply::json(&TypeaheadResponse { users, hashtags }))
+}
+
+pub const MAX_STATS_WEEKS: u64 = 53;
+
+pub async fn get_stats_handler(
+    ctx: RequestContext,
+    query: StatsQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let weeks = query.weeks.unwrap_or(12).clamp(1, MAX_STATS_WEEKS);
+    Ok(warp::reply::json(&state.cache.activity_stats(&ctx.user_id, weeks)))
+}
+
+pub async fn get_profile_views_handler(
+    ctx: RequestContext,
+    query: ProfileViewsQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let days = query.days.unwrap_or(30).clamp(1, profile_views::RETAINED_DAYS);
+    let stats = state.cache.profile_view_stats(&ctx.user_id, days);
+    // Visitors who have since been deleted drop off the list
+    let recent_visitors = stats
+        .recent_visitors
+        .into_iter()
+        .filter_map(|(user_id, visited_at)| {
+            let user = state.cache.get_user(&user_id)?;
+            Some(ProfileVisitor {
+                user_id,
+                username: user.username,
+                visited_at,
+            })
+        })
+        .collect();
+    Ok(warp::reply::json(&ProfileViewsResponse {
+        total_views: stats.total_views,
+        unique_visitors: stats.unique_visitors,
+        views_in_period: stats.views_in_period,
+        days: stats.days,
+        recent_visitors,
+    }))
+}
+
+pub async fn get_notifications_handler(
+    ctx: RequestContext,
+    accept_language: Option<String>,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let preferred = state.cache.get_preferences(&ctx.user_id).language;
+    let locale = state.catalogs.pick(accept_language.as_deref(), preferred.as_deref());
+    let read_at = state.cache.notifications_read_at(&ctx.user_id);
+    let notifications: Vec<LocalizedNotification> = state
+        .cache
+        .get_notifications(&ctx.user_id)
+        .into_iter()
+        .map(|notification| LocalizedNotification {
+            text: notification_text(state.cache.as_ref(), &state.catalogs, &locale, &notification),
+            unread: notification.created_at > read_at,
+            notification,
+        })
+        .collect();
+    let unread_count = notifications.iter().filter(|notification| notification.unread).count();
+    Ok(warp::reply::json(&NotificationsResponse {
+        notifications,
+        unread_count,
+    }))
+}
+
+// Marks notifications read up to `through`, by default all of them so far
+pub async fn mark_notifications_read_handler(
+    ctx: RequestContext,
+    request: MarkNotificationsReadRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let through = request.through.unwrap_or_else(now_millis);
+    state.cache.mark_notifications_read(&ctx.user_id, through);
+    Ok(warp::reply::json(&MarkNotificationsReadResponse {
+        unread_count: state.cache.unread_notifications(&ctx.user_id),
+    }))
+}
+
+pub async fn co_author_requests_handler(ctx: RequestContext, state: AppState) -> Result<impl Reply, warp::Rejection> {
+    Ok(warp::reply::json(&state.news_feed_service.co_author_requests(&ctx.user_id)))
+}
+
+// Accepting credits the viewer on the post and fans it out to their
+// followers; declining drops them from it
+pub async fn answer_co_author_handler(
+    post_id: PostId,
+    ctx: RequestContext,
+    request: AnswerCoAuthorRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if !state.cache.has_co_author_request(&ctx.user_id, &post_id) {
+        return Err(warp::reject::custom(NotFound));
+    }
+    state.events.publish(FeedEvent::CoAuthorAnswered {
+        post_id,
+        user_id: ctx.user_id,
+        accepted: request.accept,
+    });
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+pub async fn rsvp_handler(
+    post_id: PostId,
+    ctx: RequestContext,
+    request: RsvpRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    answer_event(&state, post_id, ctx.user_id, Some(request.status))
+}
+
+pub async fn withdraw_rsvp_handler(
+    post_id: PostId,
+    ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    answer_event(&state, post_id, ctx.user_id, None)
+}
+
+// Posts that aren't events are not found; events that are over can't be
+// answered or withdrawn from
+pub fn answer_event(
+    state: &AppState,
+    post_id: PostId,
+    user_id: UserId,
+    status: Option<RsvpStatus>,
+) -> Result<warp::reply::Json, warp::Rejection> {
+    let post = state
+        .cache
+        .post_for(&state.cache.viewer(&user_id), &post_id)
+        .map_err(|reason| post_unavailable(&state.config, reason))?;
+    let Some(event) = &post.event else {
+        return Err(warp::reject::custom(NotFound));
+    };
+    if event.is_over(now_millis()) {
+        return Err(warp::reject::custom(ValidationError("The event is over".to_string())));
+    }
+    let current = state.cache.rsvp_summary(&post_id, Some(&user_id)).viewer;
+    if current != status {
+        state.events.publish(FeedEvent::RsvpSet { post_id, user_id, status });
+    }
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+// Likes and unlikes only reach posts the caller could open, answered like
+// GET /v1/posts/{id} otherwise, so made-up or hidden IDs record nothing
+pub fn likeable_post(state: &AppState, ctx: &RequestContext, post_id: &PostId) -> Result<(), warp::Rejection> {
+    state
+        .cache
+        .post_for(&state.cache.viewer(&ctx.user_id), post_id)
+        .map(|_| ())
+        .map_err(|reason| post_unavailable(&state.config, reason))
+}
+
+pub async fn like_post_handler(
+    ctx: RequestContext,
+    request: LikePostRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    likeable_post(&state, &ctx, &request.post_id)?;
+    // Liking twice is a no-op; the log only gets the first
+    if !state.cache.decides_likes() || !state.cache.has_liked(&ctx.user_id, &request.post_id) {
+        state.events.publish(FeedEvent::PostLiked {
+            user_id: ctx.user_id,
+            post_id: request.post_id,
+        });
+    }
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+pub async fn unlike_post_handler(
+    ctx: RequestContext,
+    request: LikePostRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    likeable_post(&state, &ctx, &request.post_id)?;
+    if !state.cache.decides_likes() || state.cache.has_liked(&ctx.user_id, &request.post_id) {
+        state.events.publish(FeedEvent::PostUnliked {
+            user_id: ctx.user_id,
+            post_id: request.post_id,
+        });
+    }
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+pub const MAX_BEACON_POSTS: usize = 100;
+pub const MAX_ANALYTICS_POSTS: usize = 50;
+
+// Clients report the posts that were on screen; authors' own views don't count
+pub async fn view_beacon_handler(
+    ctx: RequestContext,
+    request: ViewBeaconRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if request.post_ids.len() > MAX_BEACON_POSTS {
+        return Err(warp::reject::custom(ValidationError(format!(
+            "At most {} posts per beacon",
+            MAX_BEACON_POSTS
+        ))));
+    }
+    if let Some(post_id) = request.dwell_ms.keys().find(|post_id| !request.post_ids.contains(post_id)) {
+        return Err(warp::reject::custom(ValidationError(format!(
+            "dwell_ms has {}, which isn't in post_ids",
+            post_id
+        ))));
+    }
+    for post_id in &request.post_ids {
+        if let Some(post) = state.cache.get_post(post_id)
+            && post.user_id != ctx.user_id
+        {
+            state.cache.record_view(post_id, &ctx.user_id);
+            if let Some(millis) = request.dwell_ms.get(post_id) {
+                let millis = (*millis).min(MAX_DWELL_MILLIS);
+                state.engagement_log.record(&ctx.user_id, &post, Interaction::Dwell { millis });
+            }
+        }
+    }
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+// Engagement for the author's most recent posts
+pub async fn analytics_handler(ctx: RequestContext, state: AppState) -> Result<impl Reply, warp::Rejection> {
+    let posts = state
+        .cache
+        .get_user_post_ids(&ctx.user_id)
+        .iter()
+        .rev()
+        .filter_map(|post_id| state.cache.get_post(post_id))
+        // Not posts they only co-authored
+        .filter(|post| post.user_id == ctx.user_id)
+        .take(MAX_ANALYTICS_POSTS)
+        .map(|post| {
+            let counters = state.cache.get_counters(&post.id);
+            PostAnalytics {
+                unique_viewers: state.cache.unique_viewers(&post.id),
+                reached_followers: state.cache.reached_followers(&post.id),
+                impressions: state.cache.get_reach(&post.id),
+                post_id: post.id,
+                timestamp: post.timestamp,
+                likes: counters.likes,
+                replies: counters.replies,
+            }
+        })
+        .collect();
+    Ok(warp::reply::json(&AnalyticsResponse { posts }))
+}
+
+pub async fn media_handler(
+    media_id: String,
+    tail: warp::path::Tail,
+    query: MediaQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let file = tail.as_str();
+    if file.is_empty() || file.split('/').any(|segment| segment == ".." || segment.is_empty()) {
+        return Err(warp::reject::not_found());
+    }
+
+    let viewer_id = query.viewer.as_deref();
+    let expires = match (query.expires, &query.sig) {
+        (Some(expires), Some(sig)) if state.media_signer.verify(&media_id, file, viewer_id, expires, sig) => expires,
+        _ => return Err(warp::reject::custom(MediaAccessDenied)),
+    };
+
+    // A signed URL only lasts as long as its viewer may see the post, so a
+    // block, a deletion, or a held account closes it before it expires
+    let viewer = match viewer_id {
+        Some(viewer_id) => {
+            let viewer_id = UserId::new(viewer_id);
+            if state.cache.get_user(&viewer_id).is_none() || state.cache.is_user_held(&viewer_id) {
+                return Err(warp::reject::custom(MediaAccessDenied));
+            }
+            state.cache.viewer(&viewer_id)
+        }
+        None => ViewerContext::Anonymous { country: None },
+    };
+    let post = state
+        .cache
+        .post_for(&viewer, &PostId::new(media_id.as_str()))
+        .map_err(|_| warp::reject::not_found())?;
+
+    // Transcodes are stored in the post author's region
+    let root = state
+        .storage
+        .read_root(&account_region(&state, &post.user_id))
+        .map_err(residency_rejection)?;
+    let path = root.join(&media_id).join(file);
+    let body = tokio::fs::read(&path)
+        .await
+        .map_err(|_| warp::reject::not_found())?;
+
+    let body = if file.ends_with(".m3u8") {
+        let playlist = String::from_utf8_lossy(&body);
+        state
+            .media_signer
+            .sign_playlist(&media_id, viewer_id, expires, &playlist)
+            .into_bytes()
+    } else {
+        body
+    };
+
+    Ok(warp::reply::with_header(
+        body,
+        "content-type",
+        media::content_type(file),
+    ))
+}
+
+pub const MAX_LOCATION_LENGTH: usize = 30;
+pub const MAX_WEBSITE_LENGTH: usize = 100;
+
+pub fn build_profile(
+    state: &AppState,
+    viewer: &ViewerContext,
+    profile_id: &UserId,
+    moved_from: Option<String>,
+) -> Result<ProfileResponse, warp::Rejection> {
+    let user = state
+        .cache
+        .get_user(profile_id)
+        .filter(|_| !state.cache.is_user_held(profile_id))
+        .filter(|_| !state.cache.hidden_from(viewer, profile_id))
+        .ok_or_else(|| warp::reject::custom(NotFound))?;
+
+    Ok(ProfileResponse {
+        follower_count: state.cache.graph().follower_count(profile_id),
+        following_count: state.cache.graph().following_count(profile_id),
+        post_count: state.cache.get_post_count(profile_id),
+        user,
+        moved_from,
+    })
+}
+
+pub async fn get_accounts_handler(accounts: AccountSet) -> Result<impl Reply, warp::Rejection> {
+    Ok(warp::reply::json(&accounts))
+}
+
+// Linking rewrites the token, so only the primary account may do it
+pub fn require_primary(accounts: &AccountSet, active: Option<String>) -> Result<(), warp::Rejection> {
+    match active {
+        Some(user_id) if accounts.primary.as_str() != user_id => Err(warp::reject::custom(Forbidden {
+            code: "primary_account_required",
+            message: "Switch back to the primary account to manage linked accounts",
+        })),
+        _ => Ok(()),
+    }
+}
+
+pub async fn link_account_handler(
+    mut accounts: AccountSet,
+    active: Option<String>,
+    request: LinkAccountRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    require_primary(&accounts, active)?;
+
+    // Possessing the other account's token proves control of it
+    let linked = state
+        .account_tokens
+        .decode(&request.token)
+        .map_err(|_| {
+            warp::reject::custom(ValidationError("Invalid token for the linked account".to_string()))
+        })?;
+    let linked_id = linked.primary;
+    if linked_id == accounts.primary {
+        return Err(warp::reject::custom(ValidationError(
+            "An account cannot be linked to itself".to_string(),
+        )));
+    }
+    if state.cache.get_user(&linked_id).is_none() {
+        return Err(warp::reject::custom(NotFound));
+    }
+    if request.scopes.is_empty() {
+        return Err(warp::reject::custom(ValidationError(
+            "At least one scope is required".to_string(),
+        )));
+    }
+
+    accounts.linked.retain(|account| account.user_id != linked_id);
+    accounts.linked.push(LinkedAccount {
+        user_id: linked_id,
+        scopes: request.scopes,
+        two_factor: linked.two_factor,
+    });
+    // The session is only as fresh as the older of the two logins
+    accounts.auth_time = accounts.auth_time.min(linked.auth_time);
+
+    Ok(warp::reply::json(&AccountTokenResponse {
+        token: state.account_tokens.issue(&accounts).map_err(account_rejection)?,
+        accounts,
+    }))
+}
+
+pub async fn unlink_account_handler(
+    linked_id: UserId,
+    mut accounts: AccountSet,
+    active: Option<String>,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    require_primary(&accounts, active)?;
+
+    let before = accounts.linked.len();
+    accounts.linked.retain(|account| account.user_id != linked_id);
+    if accounts.linked.len() == before {
+        return Err(warp::reject::custom(NotFound));
+    }
+
+    Ok(warp::reply::json(&AccountTokenResponse {
+        token: state.account_tokens.issue(&accounts).map_err(account_rejection)?,
+        accounts,
+    }))
+}
+
+pub async fn get_profile_handler(
+    profile_id: UserId,
+    ctx: RequestContext,
+    query: FieldsQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let selection = parse_fields(query.fields.as_deref(), "user")?;
+    let profile = build_profile(&state, &state.cache.viewer(&ctx.user_id), &profile_id, None)?;
+    state.cache.record_profile_visit(&ctx.user_id, &profile_id);
+    Ok(warp::reply::json(&project(&profile, None, selection.as_ref())))
+}
+
+pub async fn get_profile_by_username_handler(
+    username: String,
+    ctx: RequestContext,
+    query: FieldsQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let selection = parse_fields(query.fields.as_deref(), "user")?;
+    let lookup = state
+        .cache
+        .resolve_username(&username)
+        .ok_or_else(|| warp::reject::custom(NotFound))?;
+    let moved_from = lookup.moved.then_some(username);
+    let profile = build_profile(&state, &state.cache.viewer(&ctx.user_id), &lookup.user_id, moved_from)?;
+    state.cache.record_profile_visit(&ctx.user_id, &lookup.user_id);
+    Ok(warp::reply::json(&project(&profile, None, selection.as_ref())))
+}
+
+// Logged-out browsing: read-only, rate limited per client IP, and the same
+// for every visitor. Accounts that aren't public (see is_public_account) are
+// 404s, withheld posts are left out instead of blanked, and nothing is
+// recorded about the visit.
+pub async fn public_profile_handler(
+    profile_id: UserId,
+    viewer: ViewerContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let profile = build_profile(&state, &viewer, &profile_id, None)?;
+    Ok(public_reply(&profile))
+}
+
+pub async fn public_profile_by_username_handler(
+    username: String,
+    viewer: ViewerContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let lookup = state
+        .cache
+        .resolve_username(&username)
+        .ok_or_else(|| warp::reject::custom(NotFound))?;
+    let moved_from = lookup.moved.then_some(username);
+    let profile = build_profile(&state, &viewer, &lookup.user_id, moved_from)?;
+    Ok(public_reply(&profile))
+}
+
+pub async fn public_user_posts_handler(
+    author_id: UserId,
+    viewer: ViewerContext,
+    query: ConversationQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if state.cache.hidden_from(&viewer, &author_id) {
+        return Err(warp::reject::custom(NotFound));
+    }
+    let (offset, limit) = public_page(&query)?;
+    let post_ids = state.cache.get_user_post_ids(&author_id);
+    let posts = post_ids
+        .iter()
+        .rev()
+        .filter_map(|post_id| state.cache.get_post(post_id))
+        .filter(|post| post.in_reply_to.is_none() && !post.anonymous);
+    Ok(public_reply(&public_timeline(&state, &viewer, posts, offset, limit)))
+}
+
+pub async fn public_post_handler(
+    post_id: PostId,
+    viewer: ViewerContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let post = state
+        .cache
+        .post_for(&viewer, &post_id)
+        .and_then(|post| state.news_feed_service.shape(&viewer, post))
+        .map_err(|reason| post_unavailable(&state.config, reason))?;
+    // Withheld posts are left out rather than blanked
+    if post.withheld.is_some() {
+        return Err(warp::reject::custom(NotFound));
+    }
+    Ok(public_reply(&post))
+}
+
+// Posts using a hashtag, newest first
+pub async fn public_tag_handler(
+    tag: String,
+    viewer: ViewerContext,
+    query: ConversationQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let tag = parse_hashtag(&tag)?;
+    let (offset, limit) = public_page(&query)?;
+    let posts = state
+        .cache
+        .hashtag_post_ids(&tag)
+        .into_iter()
+        .filter_map(|post_id| state.cache.get_post(&post_id));
+    Ok(public_reply(&public_timeline(&state, &viewer, posts, offset, limit)))
+}
+
+// A hashtag from a path, in the form posts are indexed under
+pub fn parse_hashtag(tag: &str) -> Result<TagId, warp::Rejection> {
+    let tag = TagId::from_hashtag(tag);
+    if !tag.as_str().chars().all(|c| c.is_alphanumeric() || c == '_')
+        || !tag.as_str().chars().any(char::is_alphanumeric)
+    {
+        return Err(warp::reject::custom(ValidationError("Invalid hashtag".to_string())));
+    }
+    Ok(tag)
+}
+
+// Logged-out pages stop this far back, so deep paging can't be used to
+// scan everything
+pub const PUBLIC_MAX_DEPTH: usize = 1000;
+pub const PUBLIC_MAX_LIMIT: usize = 50;
+// Shared caches in front of the server may serve public responses briefly
+pub const PUBLIC_CACHE_CONTROL: &str = "public, max-age=30";
+
+pub fn public_page(query: &ConversationQuery) -> Result<(usize, usize), warp::Rejection> {
+    let limit = query.limit.unwrap_or(20).clamp(1, PUBLIC_MAX_LIMIT);
+    let offset = match &query.cursor {
+        Some(cursor) => cursor
+            .parse()
+            .ok()
+            .filter(|&offset| offset < PUBLIC_MAX_DEPTH)
+            .ok_or_else(|| warp::reject::custom(ValidationError("Invalid cursor".to_string())))?,
+        None => 0,
+    };
+    Ok((offset, limit.min(PUBLIC_MAX_DEPTH - offset)))
+}
+
+// One page of the public posts among `posts`, which are newest first
+pub fn public_timeline(
+    state: &AppState,
+    viewer: &ViewerContext,
+    posts: impl Iterator<Item = Post>,
+    offset: usize,
+    limit: usize,
+) -> Timeline {
+    let mut page: Vec<Post> = posts
+        .filter(|post| is_public_post(state, viewer, post))
+        .skip(offset)
+        .take(limit + 1)
+        .collect();
+    let more = page.len() > limit && offset + limit < PUBLIC_MAX_DEPTH;
+    page.truncate(limit);
+    Timeline {
+        posts: page
+            .into_iter()
+            .filter_map(|post| state.news_feed_service.shape(viewer, post).ok())
+            .collect(),
+        next_cursor: more.then(|| (offset + limit).to_string()),
+    }
+}
+
+pub fn is_public_post(state: &AppState, viewer: &ViewerContext, post: &Post) -> bool {
+    !state.cache.hidden_from(viewer, &post.user_id)
+        && !state.cache.is_held(post)
+        && state.cache.tombstone_in(viewer.country(), &post.id).is_none()
+}
+
+pub fn public_reply<T: Serialize>(body: &T) -> warp::reply::WithHeader<warp::reply::Json> {
+    warp::reply::with_header(warp::reply::json(body), "cache-control", PUBLIC_CACHE_CONTROL)
+}
+
+pub fn username_rejection(error: UsernameError) -> warp::Rejection {
+    match error {
+        UsernameError::Invalid => warp::reject::custom(ValidationError(
+            "Usernames must be 3-15 letters, digits, or underscores".to_string(),
+        )),
+        UsernameError::Taken => warp::reject::custom(Conflict {
+            code: "username_taken",
+            message: "That username is not available",
+        }),
+        UsernameError::Cooldown { retry_after_secs } => warp::reject::custom(ValidationError(format!(
+            "Username was changed recently; try again in {} seconds",
+            retry_after_secs
+        ))),
+        UsernameError::UnknownUser => warp::reject::custom(NotFound),
+    }
+}
+
+pub fn invalid_transition() -> warp::Rejection {
+    warp::reject::custom(Conflict {
+        code: "invalid_transition",
+        message: "The account cannot move to that state from its current one",
+    })
+}
+
+pub fn email_rejection() -> warp::Rejection {
+    warp::reject::custom(ValidationError("Invalid email address".to_string()))
+}
+
+pub fn residency_rejection(error: ResidencyError) -> warp::Rejection {
+    match error {
+        ResidencyError::UnknownRegion => {
+            warp::reject::custom(ValidationError("Unknown region".to_string()))
+        }
+        ResidencyError::CrossRegionRead => warp::reject::custom(Forbidden {
+            code: "region_restricted",
+            message: "This data is stored in another region and can't be read from here",
+        }),
+    }
+}
+
+// The region an account's data lives in
+pub fn account_region(state: &AppState, user_id: &UserId) -> Region {
+    state.storage.resolve(state.cache.account_region(user_id).as_ref())
+}
+
+pub async fn signup_handler(
+    context: LoginContext,
+    request: SignupRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let email = request.email.trim();
+    if !valid_email(email) {
+        return Err(email_rejection());
+    }
+    if let Some(region) = &request.region
+        && !state.storage.is_known(region)
+    {
+        return Err(residency_rejection(ResidencyError::UnknownRegion));
+    }
+    let password = request
+        .password
+        .ok_or_else(|| warp::reject::custom(ValidationError("A password is required".to_string())))?;
+    let password_hash = hash_password(password).await?;
+    let user = state
+        .user_service
+        .register(&request.username, email, request.region)
+        .map_err(username_rejection)?;
+    state.passwords.set_hash(&user.id, password_hash);
+    let accounts = AccountSet {
+        primary: user.id.clone(),
+        linked: Vec::new(),
+        two_factor: false,
+        delegation: None,
+        auth_time: now_millis() / 1000,
+    };
+    // Signing up is the account's first login
+    state.user_service.record_login(&user.id, context, false);
+    Ok(warp::reply::json(&SignupResponse {
+        token: state.account_tokens.issue(&accounts).map_err(account_rejection)?,
+        user_id: user.id,
+        state: AccountState::PendingVerification,
+    }))
+}
+
+// Argon2 is slow on purpose, so it runs off the async threads
+pub async fn hash_password(password: String) -> Result<String, warp::Rejection> {
+    tokio::task::spawn_blocking(move || Passwords::hash(&password))
+        .await
+        .map_err(|_| warp::reject::custom(ValidationError("Password hashing failed".to_string())))?
+        .map_err(password_rejection)
+}
+
+pub fn password_rejection(error: PasswordError) -> warp::Rejection {
+    match error {
+        PasswordError::TooShort => warp::reject::custom(ValidationError(
+            "Passwords need at least 8 characters".to_string(),
+        )),
+        PasswordError::TooLong => warp::reject::custom(ValidationError(
+            "Passwords can't be longer than 128 characters".to_string(),
+        )),
+        PasswordError::Invalid => warp::reject::custom(InvalidCredentials),
+        PasswordError::LockedOut => warp::reject::custom(Forbidden {
+            code: "login_locked",
+            message: "Too many wrong passwords; try again in a few minutes",
+        }),
+    }
+}
+
+pub async fn verify_password(state: &AppState, user_id: &UserId, password: String) -> Result<(), warp::Rejection> {
+    let passwords = state.passwords.clone();
+    let user_id = user_id.clone();
+    tokio::task::spawn_blocking(move || passwords.verify(&user_id, &password, now_millis() / 1000))
+        .await
+        .map_err(|_| warp::reject::custom(InvalidCredentials))?
+        .map_err(password_rejection)
+}
+
+// Unauthenticated: the username and password are the proof. Only the
+// current username works, not one kept for a redirect after a rename.
+pub async fn password_login_handler(
+    context: LoginContext,
+    request: PasswordLoginRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let user_id = state
+        .cache
+        .resolve_username(&request.username)
+        .filter(|lookup| !lookup.moved)
+        .map(|lookup| lookup.user_id)
+        .ok_or_else(|| warp::reject::custom(InvalidCredentials))?;
+    verify_password(&state, &user_id, request.password).await?;
+    let user_id = check_account_state(&state.cache, user_id, Scope::Read)?;
+    let mut accounts = AccountSet {
+        primary: user_id.clone(),
+        linked: Vec::new(),
+        two_factor: false,
+        delegation: None,
+        auth_time: now_millis() / 1000,
+    };
+    second_factor(&state, &mut accounts, &user_id, request.code, "Log in with your authenticator code too")?;
+    state.user_service.record_login(&user_id, context, accounts.passed_two_factor(&user_id));
+    Ok(warp::reply::json(&AccountTokenResponse {
+        token: state.account_tokens.issue(&accounts).map_err(account_rejection)?,
+        accounts,
+    }))
+}
+
+pub async fn set_password_handler(
+    ctx: RequestContext,
+    request: SetPasswordRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if state.passwords.has_password(&ctx.user_id) {
+        let current = request.current_password.ok_or_else(|| {
+            warp::reject::custom(ValidationError("current_password is required".to_string()))
+        })?;
+        verify_password(&state, &ctx.user_id, current).await?;
+    }
+    let hash = hash_password(request.password).await?;
+    state.passwords.set_hash(&ctx.user_id, hash);
+    info!("User {} changed their password", ctx.user_id);
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+// Unauthenticated: the token from the email is the proof
+pub async fn verify_email_handler(
+    request: VerifyEmailRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let record = state.user_service.verify_email(&request.token).ok_or_else(|| {
+        warp::reject::custom(ValidationError(
+            "Invalid or expired verification token".to_string(),
+        ))
+    })?;
+    Ok(warp::reply::json(&record))
+}
+
+// What the server knows about the caller for this request
+pub async fn get_context_handler(ctx: RequestContext) -> Result<impl Reply, warp::Rejection> {
+    Ok(warp::reply::json(&ContextResponse {
+        remaining_ms: ctx.remaining().as_millis() as u64,
+        features: ctx.flags.names(),
+        user_id: ctx.user_id,
+        roles: ctx.roles,
+        tenant: ctx.tenant,
+        request_id: ctx.request_id,
+    }))
+}
+
+pub async fn get_account_handler(ctx: RequestContext, state: AppState) -> Result<impl Reply, warp::Rejection> {
+    Ok(warp::reply::json(&state.cache.account_record(&ctx.user_id)))
+}
+
+pub async fn change_email_handler(
+    ctx: RequestContext,
+    request: ChangeEmailRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let email = request.email.trim();
+    if !valid_email(email) {
+        return Err(email_rejection());
+    }
+    Ok(warp::reply::json(&state.user_service.change_email(&ctx.user_id, email)))
+}
+
+pub async fn resend_verification_handler(
+    ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let record = state.cache.account_record(&ctx.user_id);
+    let email = match record.email {
+        Some(email) if !record.email_verified => email,
+        _ => {
+            return Err(warp::reject::custom(Conflict {
+                code: "nothing_to_verify",
+                message: "There is no unverified email address on this account",
+            }));
+        }
+    };
+    state.user_service.send_verification(&ctx.user_id, &email);
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+pub async fn deactivate_handler(ctx: RequestContext, state: AppState) -> Result<impl Reply, warp::Rejection> {
+    let record = state
+        .user_service
+        .transition(&ctx.user_id, AccountState::Deactivated, None)
+        .map_err(|_| invalid_transition())?;
+    Ok(warp::reply::json(&record))
+}
+
+pub async fn set_account_state_handler(
+    user_id: UserId,
+    ctx: RequestContext,
+    request: SetAccountStateRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if state.cache.get_user(&user_id).is_none() {
+        return Err(warp::reject::custom(NotFound));
+    }
+    let record = state
+        .user_service
+        .transition(&user_id, request.state, request.reason)
+        .map_err(|_| invalid_transition())?;
+    info!("Admin {} set {} to {:?}", ctx.user_id, user_id, record.state);
+    Ok(warp::reply::json(&record))
+}
+
+pub async fn two_factor_status_handler(ctx: RequestContext, state: AppState) -> Result<impl Reply, warp::Rejection> {
+    let is_admin = state.config.admin_user_ids.contains(&ctx.user_id);
+    let status = state.two_factor.status(&ctx.user_id, is_admin).map_err(two_factor_rejection)?;
+    Ok(warp::reply::json(&status))
+}
+
+pub async fn two_factor_setup_handler(ctx: RequestContext, state: AppState) -> Result<impl Reply, warp::Rejection> {
+    let user = state.cache.get_user(&ctx.user_id).ok_or_else(|| warp::reject::custom(NotFound))?;
+    let enrollment = state
+        .two_factor
+        .setup(&ctx.user_id, &user.username)
+        .map_err(two_factor_rejection)?;
+    Ok(warp::reply::json(&enrollment))
+}
+
+// Takes the raw token rather than going through auth so it can hand back one
+// that has passed two-factor for the enrolling account
+pub async fn two_factor_enable_handler(
+    mut accounts: AccountSet,
+    active: Option<String>,
+    request: TwoFactorCodeRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let user_id = select_account(accounts.clone(), active, Scope::Manage)?;
+    let user_id = check_account_state(&state.cache, user_id, Scope::Manage)?;
+    let recovery_codes = state
+        .two_factor
+        .enable(&user_id, &request.code, now_millis() / 1000)
+        .map_err(two_factor_rejection)?;
+    accounts.mark_two_factor(&user_id);
+    info!("User {} turned on two-factor authentication", user_id);
+    Ok(warp::reply::json(&TwoFactorEnabledResponse {
+        recovery_codes,
+        token: state.account_tokens.issue(&accounts).map_err(account_rejection)?,
+    }))
+}
+
+pub async fn two_factor_disable_handler(
+    ctx: RequestContext,
+    request: TwoFactorCodeRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    state
+        .two_factor
+        .disable(&ctx.user_id, &request.code, now_millis() / 1000)
+        .map_err(two_factor_rejection)?;
+    info!("User {} turned off two-factor authentication", ctx.user_id);
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+// The second step of both logins: checks the code, if one is given, and
+// marks the token as having passed two-factor. Accounts with two-factor on
+// can't log in without one.
+pub fn second_factor(
+    state: &AppState,
+    accounts: &mut AccountSet,
+    user_id: &UserId,
+    code: Option<String>,
+    message: &'static str,
+) -> Result<(), warp::Rejection> {
+    match code {
+        Some(code) => {
+            state
+                .two_factor
+                .verify(user_id, &code, now_millis() / 1000)
+                .map_err(two_factor_rejection)?;
+            accounts.mark_two_factor(user_id);
+        }
+        None if state.two_factor.is_enabled(user_id).map_err(two_factor_rejection)? => {
+            return Err(warp::reject::custom(Forbidden {
+                code: "two_factor_required",
+                message,
+            }));
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+// Renews a session for the selected account: exchanges a token, plus an
+// authenticator or recovery code when two-factor is on, for a fresh token.
+// The fresh token keeps the original login's auth_time, so renewing stops
+// NEWS_FEED_SESSION_SECS after the password was last given.
+pub async fn login_handler(
+    mut accounts: AccountSet,
+    active: Option<String>,
+    context: LoginContext,
+    request: LoginRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let user_id = select_account(accounts.clone(), active, Scope::Read)?;
+    let user_id = check_account_state(&state.cache, user_id, Scope::Read)?;
+    second_factor(&state, &mut accounts, &user_id, request.code, "Log in with your authenticator code first")?;
+    state
+        .user_service
+        .record_login(&user_id, context, accounts.passed_two_factor(&user_id));
+    Ok(warp::reply::json(&AccountTokenResponse {
+        token: state.account_tokens.issue(&accounts).map_err(account_rejection)?,
+        accounts,
+    }))
+}
+
+pub async fn federation_key_handler(state: AppState) -> Result<impl Reply, warp::Rejection> {
+    Ok(warp::reply::json(&FederationKeyResponse {
+        key_id: state.signer.key_id().to_string(),
+        public_key: state.signer.public_key(),
+    }))
+}
+
+// Components every inbound signature has to cover, so it can't be replayed
+// against another endpoint or host, or with another body
+pub const INBOX_SIGNED_COMPONENTS: [&str; 4] = ["@method", "@authority", "@path", "content-digest"];
+
+// Accepts deliveries from peers in NEWS_FEED_FEDERATION_PEERS. Nothing
+// consumes them yet, so they're verified, logged, and acknowledged; the
+// acknowledgement is signed so the peer can tell it came from us.
+pub async fn federation_inbox_handler(
+    method: warp::http::Method,
+    path: warp::path::FullPath,
+    headers: warp::http::HeaderMap,
+    body: warp::hyper::body::Bytes,
+    state: AppState,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let key_id = state
+        .peers
+        .verify(
+            header("signature-input").as_deref(),
+            header("signature").as_deref(),
+            |component| match component {
+                "@method" => Some(method.as_str().to_string()),
+                "@authority" => header("host").map(|host| host.to_ascii_lowercase()),
+                "@path" => Some(path.as_str().to_string()),
+                name => header(name),
+            },
+            &INBOX_SIGNED_COMPONENTS,
+            now_millis() / 1000,
+        )
+        .map_err(|error| warp::reject::custom(SignatureRejected(error)))?;
+    // The signature covers the digest header; this ties it to the body
+    if !header("content-digest").is_some_and(|digest| http_signature::digest_matches(&digest, &body)) {
+        return Err(warp::reject::custom(SignatureRejected(SignatureError::BadDigest)));
+    }
+    let activity: serde_json::Value = serde_json::from_slice(&body)
+        .map_err(|_| warp::reject::custom(ValidationError("Deliveries must be JSON".to_string())))?;
+    debug!(
+        "Federated delivery from {}: {}",
+        key_id,
+        activity.get("type").and_then(|kind| kind.as_str()).unwrap_or("unknown")
+    );
+
+    let reply = serde_json::to_vec(&InboxResponse { accepted: true }).expect("response serializes");
+    let digest = http_signature::content_digest(&reply);
+    let (signature_input, signature) = state.signer.sign(
+        &[("@status", "202".to_string()), ("content-digest", digest.clone())],
+        now_millis() / 1000,
+    );
+    warp::http::Response::builder()
+        .status(warp::http::StatusCode::ACCEPTED)
+        .header("content-type", "application/json")
+        .header("content-digest", digest)
+        .header("signature-input", signature_input)
+        .header("signature", signature)
+        .body(reply.into())
+        .map_err(|_| warp::reject::custom(StorageError))
+}
+
+pub const MAX_CLIENT_NAME_CHARS: usize = 50;
+
+pub async fn register_client_handler(
+    ctx: RequestContext,
+    request: RegisterClientRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let name = request.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_CLIENT_NAME_CHARS {
+        return Err(warp::reject::custom(ValidationError(format!(
+            "App names must be 1-{} characters",
+            MAX_CLIENT_NAME_CHARS
+        ))));
+    }
+    let (client, client_secret) = state
+        .oauth
+        .register(&ctx.user_id, name, request.redirect_uris, request.sandbox, now_millis())
+        .ok_or_else(|| {
+            warp::reject::custom(ValidationError(
+                "redirect_uris must list 1-10 https URLs (http only for localhost)".to_string(),
+            ))
+        })?;
+    info!("User {} registered OAuth client {} ({})", ctx.user_id, client.client_id, client.name);
+    Ok(warp::reply::json(&RegisterClientResponse { client, client_secret }))
+}
+
+pub async fn list_clients_handler(ctx: RequestContext, state: AppState) -> Result<impl Reply, warp::Rejection> {
+    Ok(warp::reply::json(&ClientsResponse {
+        clients: state.oauth.clients_of(&ctx.user_id),
+    }))
+}
+
+// Errors about the client or redirect URI are shown to the user; anything
+// else is reported to the app through the redirect
+pub fn authorize_client(state: &AppState, query: &AuthorizeQuery) -> Result<OAuthClient, warp::Rejection> {
+    state
+        .oauth
+        .client(&query.client_id)
+        .filter(|client| client.redirect_uris.contains(&query.redirect_uri))
+        .ok_or_else(|| {
+            warp::reject::custom(ValidationError(
+                "Unknown client_id, or a redirect_uri the app didn't register".to_string(),
+            ))
+        })
+}
+
+pub fn requested_scopes(query: &AuthorizeQuery) -> Result<Vec<OAuthScope>, &'static str> {
+    if query.response_type != "code" {
+        return Err("unsupported_response_type");
+    }
+    OAuthScope::parse_list(&query.scope).ok_or("invalid_scope")
+}
+
+pub fn authorize_redirect(
+    query: &AuthorizeQuery,
+    params: &[(&str, &str)],
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let mut params = params.to_vec();
+    if let Some(state) = &query.state {
+        params.push(("state", state));
+    }
+    let separator = if query.redirect_uri.contains('?') { '&' } else { '?' };
+    let location = format!(
+        "{}{}{}",
+        query.redirect_uri,
+        separator,
+        serde_urlencoded::to_string(&params).unwrap_or_default()
+    );
+    let uri: warp::http::Uri = location
+        .parse()
+        .map_err(|_| warp::reject::custom(ValidationError("Invalid redirect_uri".to_string())))?;
+    Ok(warp::redirect::see_other(uri).into_response())
+}
+
+pub async fn authorize_page_handler(
+    ctx: RequestContext,
+    query: AuthorizeQuery,
+    state: AppState,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let client = authorize_client(&state, &query)?;
+    let scopes = match requested_scopes(&query) {
+        Ok(scopes) => scopes,
+        Err(error) => return authorize_redirect(&query, &[("error", error)]),
+    };
+    let user = state.cache.get_user(&ctx.user_id).ok_or_else(|| warp::reject::custom(NotFound))?;
+    Ok(warp::reply::html(consent_page(&client, &user.username, &scopes)).into_response())
+}
+
+pub async fn authorize_decision_handler(
+    ctx: RequestContext,
+    query: AuthorizeQuery,
+    form: ConsentForm,
+    state: AppState,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let client = authorize_client(&state, &query)?;
+    let scopes = match requested_scopes(&query) {
+        Ok(scopes) => scopes,
+        Err(error) => return authorize_redirect(&query, &[("error", error)]),
+    };
+    if form.decision != "approve" {
+        return authorize_redirect(&query, &[("error", "access_denied")]);
+    }
+    // auth already required a two-factor login if the account has it on
+    let two_factor = state.two_factor.is_enabled(&ctx.user_id).map_err(two_factor_rejection)?;
+    let code = state
+        .oauth
+        .approve(&client, &query.redirect_uri, &ctx.user_id, &scopes, two_factor, now_millis());
+    info!("User {} authorized {} for {}", ctx.user_id, client.client_id, query.scope);
+    authorize_redirect(&query, &[("code", &code)])
+}
+
+pub fn oauth_error(error: &'static str, status: warp::http::StatusCode) -> warp::reply::Response {
+    warp::reply::with_status(warp::reply::json(&OAuthErrorResponse { error }), status).into_response()
+}
+
+// Authorization code grant with the client secret in the form body; sandbox
+// apps can also use client credentials to act as the sandbox test account
+pub async fn token_handler(request: TokenRequest, state: AppState) -> Result<warp::reply::Response, warp::Rejection> {
+    let ttl_millis = state.config.oauth_token_ttl_secs.saturating_mul(1000);
+    let exchange = match request.grant_type.as_str() {
+        "authorization_code" => state.oauth.exchange(
+            &request.client_id,
+            &request.client_secret,
+            &request.code,
+            &request.redirect_uri,
+            ttl_millis,
+            now_millis(),
+        ),
+        "client_credentials" => {
+            state
+                .oauth
+                .sandbox_credentials(&request.client_id, &request.client_secret, ttl_millis, now_millis())
+        }
+        _ => return Ok(oauth_error("unsupported_grant_type", warp::http::StatusCode::BAD_REQUEST)),
+    };
+    let exchange = match exchange {
+        Ok(exchange) => exchange,
+        Err(error) => {
+            let status = match error {
+                OAuthError::InvalidClient => warp::http::StatusCode::UNAUTHORIZED,
+                OAuthError::InvalidGrant | OAuthError::UnauthorizedClient => warp::http::StatusCode::BAD_REQUEST,
+            };
+            return Ok(oauth_error(error.code(), status));
+        }
+    };
+    let scope = OAuthScope::join(&exchange.delegation.scopes);
+    let accounts = AccountSet {
+        primary: exchange.user_id,
+        linked: Vec::new(),
+        two_factor: exchange.two_factor,
+        delegation: Some(exchange.delegation),
+        auth_time: now_millis() / 1000,
+    };
+    let reply = warp::reply::json(&TokenResponse {
+        access_token: state.account_tokens.issue(&accounts).map_err(account_rejection)?,
+        token_type: "Bearer",
+        expires_in: state.config.oauth_token_ttl_secs,
+        scope,
+    });
+    Ok(warp::reply::with_header(reply, "cache-control", "no-store").into_response())
+}
+
+pub async fn list_grants_handler(ctx: RequestContext, state: AppState) -> Result<impl Reply, warp::Rejection> {
+    Ok(warp::reply::json(&GrantsResponse {
+        grants: state.oauth.grants_of(&ctx.user_id),
+    }))
+}
+
+pub async fn revoke_grant_handler(
+    client_id: String,
+    ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if !state.oauth.revoke(&ctx.user_id, &client_id) {
+        return Err(warp::reject::custom(NotFound));
+    }
+    info!("User {} revoked access for {}", ctx.user_id, client_id);
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+pub async fn login_history_handler(ctx: RequestContext, state: AppState) -> Result<impl Reply, warp::Rejection> {
+    Ok(warp::reply::json(&LoginHistoryResponse {
+        logins: state.cache.recent_logins(&ctx.user_id),
+    }))
+}
+
+pub async fn get_two_factor_policy_handler(_ctx: RequestContext, state: AppState) -> Result<impl Reply, warp::Rejection> {
+    Ok(warp::reply::json(&TwoFactorPolicy {
+        required_for_admins: state.two_factor.required_for_admins(),
+    }))
+}
+
+pub async fn set_two_factor_policy_handler(
+    ctx: RequestContext,
+    policy: TwoFactorPolicy,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    state.two_factor.set_required_for_admins(policy.required_for_admins);
+    info!(
+        "Admin {} set two-factor required for admins to {}",
+        ctx.user_id, policy.required_for_admins
+    );
+    Ok(warp::reply::json(&policy))
+}
+
+pub async fn change_username_handler(
+    ctx: RequestContext,
+    request: ChangeUsernameRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let user = state
+        .user_service
+        .change_username(&ctx.user_id, &request.username)
+        .map_err(username_rejection)?;
+    Ok(warp::reply::json(&user))
+}
+
+pub async fn update_profile_handler(
+    ctx: RequestContext,
+    request: UpdateProfileRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let mut user = state
+        .cache
+        .get_user(&ctx.user_id)
+        .ok_or_else(|| warp::reject::custom(NotFound))?;
+
+    if let Some(location) = request.location {
+        let location = location.trim().to_string();
+        if content::grapheme_length(&location) > MAX_LOCATION_LENGTH {
+            return Err(warp::reject::custom(ValidationError(format!(
+                "location must be at most {} characters",
+                MAX_LOCATION_LENGTH
+            ))));
+        }
+        user.location = Some(location).filter(|location| !location.is_empty());
+    }
+
+    if let Some(website) = request.website {
+        let website = website.trim().to_string();
+        if !website.is_empty()
+            && (website.len() > MAX_WEBSITE_LENGTH
+                || !(website.starts_with("https://") || website.starts_with("http://")))
+        {
+            return Err(warp::reject::custom(ValidationError(format!(
+                "website must be an http(s) URL of at most {} characters",
+                MAX_WEBSITE_LENGTH
+            ))));
+        }
+        user.website = Some(website).filter(|website| !website.is_empty());
+    }
+
+    if let Some(timezone) = request.timezone {
+        let timezone = timezone.trim().to_string();
+        if !timezone.is_empty() && state.cache.timezones.get(&timezone).is_none() {
+            return Err(warp::reject::custom(ValidationError(format!(
+                "Unknown timezone {:?}; expected an IANA name like \"Europe/Berlin\"",
+                timezone
+            ))));
+        }
+        user.timezone = Some(timezone).filter(|timezone| !timezone.is_empty());
+    }
+
+    state.cache.set_user(user.clone());
+    Ok(warp::reply::json(&user))
+}
+
+pub async fn upload_profile_image_handler(
+    kind: ProfileImageKind,
+    ctx: RequestContext,
+    body: warp::hyper::body::Bytes,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let mut user = state
+        .cache
+        .get_user(&ctx.user_id)
+        .ok_or_else(|| warp::reject::custom(NotFound))?;
+
+    let region = account_region(&state, &ctx.user_id);
+    let variants = match state
+        .image_pipeline
+        .process_profile_image(&ctx.user_id, &region, kind, body.to_vec())
+        .await
+    {
+        Ok(variants) => variants,
+        Err(ImageError::Unsupported) => {
+            return Err(warp::reject::custom(ValidationError(
+                "Upload must be a PNG, JPEG, WebP, or GIF image".to_string(),
+            )));
+        }
+        Err(ImageError::Storage(e)) => {
+            warn!("Failed to store {:?} for user {}: {}", kind, ctx.user_id, e);
+            return Err(warp::reject::custom(StorageError));
+        }
+    };
+
+    let previous = match kind {
+        ProfileImageKind::Avatar => {
+            user.profile_picture = variants[0].url.clone();
+            std::mem::replace(&mut user.profile_picture_variants, variants)
+        }
+        ProfileImageKind::Banner => {
+            user.banner_url = Some(variants[0].url.clone());
+            std::mem::replace(&mut user.banner_variants, variants)
+        }
+    };
+    state.cache.set_user(user.clone());
+    state.image_pipeline.remove_variants(&region, &previous).await;
+
+    Ok(warp::reply::json(&user))
+}
+
+// Profile images are public, but only readable where the account's region
+// allows it
+pub async fn profile_image_handler(
+    user_id: UserId,
+    file: String,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if user_id.as_str().starts_with('.') || file.starts_with('.') {
+        return Err(warp::reject::not_found());
+    }
+    let root = state
+        .storage
+        .read_root(&account_region(&state, &user_id))
+        .map_err(residency_rejection)?;
+    let path = root.join("profiles").join(user_id.as_str()).join(&file);
+    let body = tokio::fs::read(&path)
+        .await
+        .map_err(|_| warp::reject::not_found())?;
+    Ok(warp::reply::with_header(body, "content-type", media::content_type(&file)))
+}
+
+pub async fn search_status_handler(_ctx: RequestContext, state: AppState) -> Result<impl Reply, warp::Rejection> {
+    Ok(warp::reply::json(&state.search.status()))
+}
+
+pub async fn list_projections_handler(_ctx: RequestContext, state: AppState) -> Result<impl Reply, warp::Rejection> {
+    Ok(warp::reply::json(&ProjectionsResponse {
+        events: state.events.event_count(),
+        projections: state.events.projection_names(),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventExportQuery {
+    #[serde(default)]
+    pub after: u64, // sequence number
+}
+
+// The event log as JSON lines, oldest first, for the eval harness
+// (crates/newsfeed-bin/src/eval.rs)
+pub async fn export_events_handler(
+    _ctx: RequestContext,
+    query: EventExportQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let events = state.events.clone();
+    let body = tokio::task::spawn_blocking(move || {
+        let mut body = Vec::new();
+        for recorded in events.since(query.after) {
+            serde_json::to_writer(&mut body, &recorded).expect("events serialize");
+            body.push(b'\n');
+        }
+        body
+    })
+    .await
+    .map_err(|_| warp::reject::custom(StorageError))?;
+    Ok(warp::reply::with_header(body, "content-type", "application/x-ndjson"))
+}
+
+// Replays the whole event log into one projection. Writes wait until it's
+// done, so this blocks a worker thread rather than the runtime.
+pub async fn rebuild_projection_handler(
+    name: String,
+    ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let events = state.events.clone();
+    let projection = name.clone();
+    let replayed = tokio::task::spawn_blocking(move || events.rebuild(&projection))
+        .await
+        .map_err(|_| warp::reject::custom(StorageError))?
+        .ok_or_else(|| warp::reject::custom(NotFound))?;
+    info!("Admin {} rebuilt the {} projection from {} events", ctx.user_id, name, replayed);
+    Ok(warp::reply::json(&RebuildResponse {
+        projection: name,
+        events_replayed: replayed,
+    }))
+}
+
+pub async fn list_jobs_handler(_ctx: RequestContext, state: AppState) -> Result<impl Reply, warp::Rejection> {
+    Ok(warp::reply::json(&state.jobs.report()))
+}
+
+pub async fn retry_job_handler(id: u64, ctx: RequestContext, state: AppState) -> Result<impl Reply, warp::Rejection> {
+    if !state.jobs.retry(id) {
+        return Err(warp::reject::custom(NotFound));
+    }
+    info!("Admin {} queued failed job {} again", ctx.user_id, id);
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+pub async fn get_settings_handler(_ctx: RequestContext, state: AppState) -> Result<impl Reply, warp::Rejection> {
+    Ok(warp::reply::json(&*state.settings.current()))
+}
+
+#[derive(Serialize)]
+pub struct ReloadResponse {
+    pub changes: Vec<SettingChange>,
+}
+
+pub async fn reload_settings_handler(ctx: RequestContext, state: AppState) -> Result<impl Reply, warp::Rejection> {
+    let changes = reload_settings(&state, &ctx.user_id).map_err(|e| warp::reject::custom(ValidationError(e)))?;
+    Ok(warp::reply::json(&ReloadResponse { changes }))
+}
+
+// Reloads the tunables, recording what changed in the audit log
+pub fn reload_settings(state: &AppState, admin_id: &UserId) -> Result<Vec<SettingChange>, String> {
+    let changes = state.settings.reload()?;
+    if !changes.is_empty() {
+        state.audit_log.record(
+            admin_id,
+            AuditAction::ReloadConfig,
+            "config".to_string(),
+            None,
+            serde_json::json!({ "changes": changes }),
+        );
+    }
+    Ok(changes)
+}
+
+// Fanout progress for a post whose fanout ran on this node
+pub async fn delivery_receipt_handler(
+    post_id: PostId,
+    ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let receipt = state
+        .cache
+        .get_receipt(&post_id)
+        .ok_or_else(|| warp::reject::custom(NotFound))?;
+    state.audit_log.record_access(
+        &ctx.user_id,
+        AuditAction::ViewDelivery,
+        receipt.author_id(),
+        hold_target(HoldKind::Post, post_id.as_str()),
+        serde_json::Value::Null,
+    );
+    Ok(warp::reply::json(&receipt))
+}
+
+// Who wrote an anonymous post. Every reveal is audited with its reason.
+pub async fn reveal_author_handler(
+    post_id: PostId,
+    ctx: RequestContext,
+    request: RevealAuthorRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if request.reason.trim().is_empty() {
+        return Err(warp::reject::custom(ValidationError("reason is required".to_string())));
+    }
+    let post = state
+        .cache
+        .get_post(&post_id)
+        .ok_or_else(|| warp::reject::custom(NotFound))?;
+    if !post.anonymous {
+        return Err(warp::reject::custom(ValidationError("The post isn't anonymous".to_string())));
+    }
+    state.audit_log.record(
+        &ctx.user_id,
+        AuditAction::RevealAuthor,
+        hold_target(HoldKind::Post, post_id.as_str()),
+        request.case_ref,
+        serde_json::json!({ "reason": request.reason, "author_id": post.user_id }),
+    );
+    Ok(warp::reply::json(&RevealAuthorResponse {
+        username: state.cache.get_user(&post.user_id).map(|user| user.username),
+        user_id: post.user_id,
+        post_id,
+    }))
+}
+
+pub async fn startup_report_handler(_ctx: RequestContext, state: AppState) -> Result<impl Reply, warp::Rejection> {
+    Ok(warp::reply::json(&state.startup.report()))
+}
+
+pub async fn list_regions_handler(_ctx: RequestContext, state: AppState) -> Result<impl Reply, warp::Rejection> {
+    Ok(warp::reply::json(&RegionsResponse {
+        local: state.storage.resolve(None),
+        regions: state.storage.regions(),
+        cross_region_reads: state.config.cross_region_reads,
+    }))
+}
+
+// Moves the account's stored files to the new region's backend before
+// recording the region, so nothing is left behind where it may not be read
+pub async fn set_region_handler(
+    user_id: UserId,
+    ctx: RequestContext,
+    request: SetRegionRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if state.cache.get_user(&user_id).is_none() {
+        return Err(warp::reject::custom(NotFound));
+    }
+    let from = account_region(&state, &user_id);
+    let to = request.region;
+    let to_root = state.storage.write_root(&to).map_err(residency_rejection)?;
+
+    if from != to {
+        let from_root = state.storage.write_root(&from).map_err(residency_rejection)?;
+        let mut dirs = vec![std::path::PathBuf::from("profiles").join(user_id.as_str())];
+        dirs.extend(
+            state
+                .cache
+                .get_user_post_ids(&user_id)
+                .iter()
+                .filter(|post_id| state.cache.get_post(post_id).is_some_and(|post| post.user_id == user_id))
+                .filter(|post_id| state.cache.get_video(post_id).is_some())
+                .map(|post_id| std::path::PathBuf::from(post_id.as_str())),
+        );
+        for dir in dirs {
+            if let Err(e) = move_tree(&from_root.join(&dir), &to_root.join(&dir)).await {
+                warn!("Failed to move {} from {} to {}: {}", dir.display(), from, to, e);
+                return Err(warp::reject::custom(StorageError));
+            }
+        }
+    }
+
+    let mut record = state.cache.account_record(&user_id);
+    record.region = Some(to.clone());
+    state.cache.set_account_record(&user_id, record.clone());
+    state.audit_log.record_access(
+        &ctx.user_id,
+        AuditAction::SetRegion,
+        &user_id,
+        hold_target(HoldKind::User, user_id.as_str()),
+        serde_json::json!({ "from": from, "to": to }),
+    );
+    Ok(warp::reply::json(&record))
+}
+
+pub async fn list_moderation_cases_handler(
+    _ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    Ok(warp::reply::json(&ModerationCasesResponse {
+        cases: state.moderation.cases(),
+    }))
+}
+
+pub async fn resolve_moderation_case_handler(
+    user_id: UserId,
+    ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if !state.moderation.resolve(&user_id) {
+        return Err(warp::reject::custom(NotFound));
+    }
+    info!("Admin {} resolved the moderation case for {}", ctx.user_id, user_id);
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+pub fn hold_target(kind: HoldKind, id: &str) -> String {
+    match kind {
+        HoldKind::Post => format!("post:{}", id),
+        HoldKind::User => format!("user:{}", id),
+    }
+}
+
+// Checks the raw content, since a held post is invisible to get_post. Holding
+// something already held replaces the earlier hold.
+pub async fn place_hold_handler(
+    kind: HoldKind,
+    id: String,
+    ctx: RequestContext,
+    request: PlaceHoldRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if request.case_ref.trim().is_empty() {
+        return Err(warp::reject::custom(ValidationError("case_ref is required".to_string())));
+    }
+    let exists = match kind {
+        HoldKind::Post => state.cache.post_exists(&PostId::new(id.as_str())),
+        HoldKind::User => state.cache.get_user(&UserId::new(id.as_str())).is_some(),
+    };
+    if !exists {
+        return Err(warp::reject::custom(NotFound));
+    }
+
+    let hold = LegalHold {
+        case_ref: request.case_ref,
+        reason: request.reason,
+        placed_by: ctx.user_id.clone(),
+        placed_at: now_millis(),
+    };
+    state.cache.place_hold(kind, &id, hold.clone());
+    state.audit_log.record(
+        &ctx.user_id,
+        AuditAction::PlaceHold,
+        hold_target(kind, &id),
+        Some(hold.case_ref.clone()),
+        serde_json::json!({ "reason": hold.reason }),
+    );
+    Ok(warp::reply::json(&hold))
+}
+
+pub async fn release_hold_handler(
+    kind: HoldKind,
+    id: String,
+    ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let hold = state
+        .cache
+        .release_hold(kind, &id)
+        .ok_or_else(|| warp::reject::custom(NotFound))?;
+    state.audit_log.record(
+        &ctx.user_id,
+        AuditAction::ReleaseHold,
+        hold_target(kind, &id),
+        Some(hold.case_ref),
+        serde_json::json!({ "placed_by": hold.placed_by, "placed_at": hold.placed_at }),
+    );
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+pub async fn list_holds_handler(_ctx: RequestContext, state: AppState) -> Result<impl Reply, warp::Rejection> {
+    Ok(warp::reply::json(&HoldsResponse {
+        holds: state.cache.holds(),
+    }))
+}
+
+pub async fn takedown_handler(
+    post_id: PostId,
+    ctx: RequestContext,
+    request: TakedownRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if request.case_ref.trim().is_empty() || request.message.trim().is_empty() {
+        return Err(warp::reject::custom(ValidationError(
+            "case_ref and message are required".to_string(),
+        )));
+    }
+    if let Some(code) = request
+        .jurisdictions
+        .iter()
+        .find(|code| code.len() != 2 || !code.chars().all(|c| c.is_ascii_alphabetic()))
+    {
+        return Err(warp::reject::custom(ValidationError(format!(
+            "Invalid jurisdiction {:?}: use ISO 3166-1 alpha-2 codes",
+            code
+        ))));
+    }
+    if !state.cache.post_exists(&post_id) {
+        return Err(warp::reject::custom(NotFound));
+    }
+
+    let takedown = Takedown {
+        jurisdictions: request
+            .jurisdictions
+            .iter()
+            .map(|code| code.to_ascii_uppercase())
+            .collect(),
+        message: request.message,
+        case_ref: request.case_ref,
+        placed_by: ctx.user_id.clone(),
+        placed_at: now_millis(),
+    };
+    state.cache.set_takedown(&post_id, takedown.clone());
+    state.audit_log.record(
+        &ctx.user_id,
+        AuditAction::Takedown,
+        hold_target(HoldKind::Post, post_id.as_str()),
+        Some(takedown.case_ref.clone()),
+        serde_json::json!({ "jurisdictions": takedown.jurisdictions, "message": takedown.message }),
+    );
+    Ok(warp::reply::json(&takedown))
+}
+
+pub async fn lift_takedown_handler(
+    post_id: PostId,
+    ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let takedown = state
+        .cache
+        .lift_takedown(&post_id)
+        .ok_or_else(|| warp::reject::custom(NotFound))?;
+    state.audit_log.record(
+        &ctx.user_id,
+        AuditAction::LiftTakedown,
+        hold_target(HoldKind::Post, post_id.as_str()),
+        Some(takedown.case_ref),
+        serde_json::json!({ "jurisdictions": takedown.jurisdictions }),
+    );
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+// Admin accesses to the caller's data, from the audit log
+pub async fn data_access_log_handler(
+    ctx: RequestContext,
+    query: AuditQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let limit = query.limit.unwrap_or(100).clamp(1, 500);
+    Ok(warp::reply::json(&AuditResponse {
+        entries: state.audit_log.accesses(&ctx.user_id, limit),
+    }))
+}
+
+pub async fn audit_log_handler(
+    _ctx: RequestContext,
+    query: AuditQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    Ok(warp::reply::json(&AuditResponse {
+        entries: state.audit_log.entries(limit),
+    }))
+}
+
+pub async fn set_verified_handler(
+    profile_id: UserId,
+    ctx: RequestContext,
+    request: SetVerifiedRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let mut user = state
+        .cache
+        .get_user(&profile_id)
+        .ok_or_else(|| warp::reject::custom(NotFound))?;
+
+    user.verified = request.verified;
+    state.cache.set_user(user.clone());
+    info!(
+        "Admin {} set verified={} for user {}",
+        ctx.user_id, request.verified, profile_id
+    );
+    Ok(warp::reply::json(&user))
+}
+
+pub async fn get_preferences_handler(
+    ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    Ok(warp::reply::json(&state.cache.get_preferences(&ctx.user_id)))
+}
+
+pub async fn update_preferences_handler(
+    ctx: RequestContext,
+    preferences: UserPreferences,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    state.cache.set_preferences(&ctx.user_id, preferences);
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+pub async fn list_emojis_handler(state: AppState) -> Result<impl Reply, warp::Rejection> {
+    Ok(warp::reply::json(&state.cache.list_emojis()))
+}
+
+pub async fn upload_emoji_handler(
+    shortcode: String,
+    _ctx: RequestContext,
+    content_type: Option<String>,
+    body: warp::hyper::body::Bytes,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if !emoji::is_valid_shortcode(&shortcode) {
+        return Err(warp::reject::custom(ValidationError(
+            "Shortcodes must be 2-32 lowercase letters, digits, or underscores".to_string(),
+        )));
+    }
+    let extension = content_type
+        .as_deref()
+        .and_then(emoji::image_extension)
+        .ok_or_else(|| {
+            warp::reject::custom(ValidationError(
+                "Emoji must be a PNG, GIF, or WebP image".to_string(),
+            ))
+        })?;
+
+    let emoji_dir = state.config.media_dir.join("emoji");
+    let file_name = format!("{}.{}", shortcode, extension);
+    let written = async {
+        tokio::fs::create_dir_all(&emoji_dir).await?;
+        tokio::fs::write(emoji_dir.join(&file_name), &body).await
+    };
+    if let Err(e) = written.await {
+        warn!("Failed to store emoji {}: {}", shortcode, e);
+        return Err(warp::reject::custom(StorageError));
+    }
+
+    let emoji = CustomEmoji {
+        shortcode: shortcode.clone(),
+        url: format!("/emoji/{}", file_name),
+    };
+    if let Some(previous) = state.cache.set_emoji(emoji.clone())
+        && previous.url != emoji.url
+    {
+        remove_emoji_file(&state, &previous).await;
+    }
+
+    info!("Emoji uploaded: :{}:", shortcode);
+    Ok(warp::reply::json(&emoji))
+}
+
+pub async fn delete_emoji_handler(
+    shortcode: String,
+    _ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let emoji = state
+        .cache
+        .remove_emoji(&shortcode)
+        .ok_or_else(|| warp::reject::custom(NotFound))?;
+    remove_emoji_file(&state, &emoji).await;
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+pub async fn remove_emoji_file(state: &AppState, emoji: &CustomEmoji) {
+    if let Some(file_name) = emoji.url.strip_prefix("/emoji/") {
+        let path = state.config.media_dir.join("emoji").join(file_name);
+        if let Err(e) = tokio::fs::remove_file(&path).await {
+            warn!("Failed to remove {}: {}", path.display(), e);
+        }
+    }
+}
+
+pub async fn create_campaign_handler(
+    _ctx: RequestContext,
+    request: CreateCampaignRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if request.budget == 0 || request.duration_secs == 0 {
+        return Err(warp::reject::custom(ValidationError(
+            "budget and duration_secs must be positive".to_string(),
+        )));
+    }
+    if state.cache.get_post(&request.post_id).is_none() {
+        return Err(warp::reject::custom(NotFound));
+    }
+    if let Some(user_id) = &request.targeting.follower_of
+        && state.cache.get_user(user_id).is_none()
+    {
+        return Err(warp::reject::custom(ValidationError(format!(
+            "Unknown follower_of user: {}",
+            user_id
+        ))));
+    }
+
+    let mut targeting = request.targeting;
+    targeting.topic = targeting
+        .topic
+        .map(|topic| TagId::from_hashtag(topic.as_str()));
+
+    let now = now_millis();
+    let campaign = Campaign {
+        id: format!("campaign_{}", uuid::Uuid::new_v4()),
+        post_id: request.post_id,
+        targeting,
+        budget: request.budget,
+        starts_at: now,
+        ends_at: now + request.duration_secs * 1000,
+        impressions: 0,
+        clicks: 0,
+    };
+    state.cache.add_campaign(campaign.clone());
+
+    info!("Campaign created: {} for post {}", campaign.id, campaign.post_id);
+    Ok(warp::reply::json(&campaign))
+}
+
+pub async fn list_campaigns_handler(
+    _ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let mut campaigns = state.cache.list_campaigns();
+    campaigns.sort_by_key(|campaign| std::cmp::Reverse(campaign.starts_at));
+    Ok(warp::reply::json(&campaigns))
+}
+
+pub async fn sponsored_click_handler(
+    campaign_id: String,
+    _ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if !state.cache.record_campaign_click(&campaign_id) {
+        return Err(warp::reject::custom(NotFound));
+    }
+    Ok(warp::reply::json(&SuccessResponse { success: true }))
+}
+
+pub async fn batch_handler(
+    ctx: RequestContext,
+    headers: warp::http::HeaderMap,
+    exposure: Option<Exposure>,
+    peer: Option<PeerAddr>,
+    request: BatchRequest,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let max_requests = state.settings.current().batch_max_requests;
+    if request.requests.is_empty() || request.requests.len() > max_requests {
+        return Err(warp::reject::custom(ValidationError(format!(
+            "A batch needs between 1 and {} requests",
+            max_requests
+        ))));
+    }
+    // Every listener sets the exposure; assume the public one if it's missing
+    let exposure = exposure.unwrap_or(Exposure::Public);
+    let responses = state
+        .batch
+        .execute(
+            &headers,
+            exposure,
+            peer.unwrap_or(PeerAddr(None)),
+            Deadline(ctx.deadline),
+            &Cancellation(ctx.cancel.clone()),
+            request.requests,
+        )
+        .await;
+    Ok(warp::reply::json(&BatchResponse { responses }))
+}
+
+pub async fn runtime_stats_handler(
+    _ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    Ok(warp::reply::json(&state.profiler.runtime_report()))
+}
+
+pub async fn cache_stats_handler(
+    _ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let cache = state.cache.clone();
+    let stats = tokio::task::spawn_blocking(move || cache.shard_stats(100))
+        .await
+        .map_err(|_| warp::reject::custom(ProfileFailed))?;
+    Ok(warp::reply::json(&stats))
+}
+
+pub async fn cpu_profile_handler(
+    _ctx: RequestContext,
+    query: ProfileQuery,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let seconds = query.seconds.unwrap_or(10);
+    if seconds == 0 || seconds > MAX_PROFILE_SECS {
+        return Err(warp::reject::custom(ValidationError(format!(
+            "seconds must be between 1 and {}",
+            MAX_PROFILE_SECS
+        ))));
+    }
+    let frequency = query.frequency.unwrap_or(99).clamp(1, 1000);
+
+    info!("Capturing CPU profile for {}s at {} Hz", seconds, frequency);
+    let folded = match state
+        .profiler
+        .capture_cpu_profile(Duration::from_secs(seconds), frequency)
+        .await
+    {
+        None => {
+            return Err(warp::reject::custom(Conflict {
+                code: "profile_in_progress",
+                message: "A CPU profile is already being captured",
+            }));
+        }
+        Some(Err(e)) => {
+            warn!("CPU profile failed: {}", e);
+            return Err(warp::reject::custom(ProfileFailed));
+        }
+        Some(Ok(folded)) => folded,
+    };
+    Ok(warp::reply::with_header(
+        folded,
+        "content-type",
+        "text/plain; charset=utf-8",
+    ))
+}
+
+pub async fn memory_stats_handler(
+    _ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    let monitor = state.memory_monitor.clone();
+    let report = tokio::task::spawn_blocking(move || monitor.report())
+        .await
+        .map_err(|_| warp::reject::custom(ProfileFailed))?;
+    Ok(warp::reply::json(&report))
+}
+
+pub async fn interests_handler(
+    user_id: UserId,
+    ctx: RequestContext,
+    state: AppState,
+) -> Result<impl Reply, warp::Rejection> {
+    if state.cache.get_user(&user_id).is_none() {
+        return Err(warp::reject::custom(NotFound));
+    }
+    state.audit_log.record_access(
+        &ctx.user_id,
+        AuditAction::ViewInterests,
+        &user_id,
+        hold_target(HoldKind::User, user_id.as_str()),
+        serde_json::Value::Null,
+    );
+    let interests = state.cache.get_interests(&user_id);
+    let now = now_millis();
+    Ok(warp::reply::json(&InterestsResponse {
+        topics: interests.topic_weights(now),
+        authors: interests.author_weights(now),
+        user_id,
+    }))
+}
+
+pub async fn metrics_handler(state: AppState) -> Result<impl Reply, warp::Rejection> {
+    let monitor = state.memory_monitor.clone();
+    let report = tokio::task::spawn_blocking(move || monitor.report())
+        .await
+        .map_err(|_| warp::reject::custom(ProfileFailed))?;
+    let mut metrics = memory::render_metrics(&report);
+    metrics.push_str(&state.news_feed_service.page_cache_metrics());
+    metrics.push_str(&state.cache.eviction_metrics());
+    metrics.push_str(&state.cache.read_metrics());
+    metrics.push_str(&state.requests.render());
+    metrics.push_str(&state.news_feed_service.reach_metrics());
+    metrics.push_str(&state.feed_stream.metrics());
+    metrics.push_str(&state.jobs.metrics());
+    metrics.push_str(&state.feed_nodes.metrics());
+    metrics.push_str(&state.fanout_worker.metrics());
+    metrics.push_str(&state.search.metrics());
+    if let Some(vectors) = &state.vectors {
+        metrics.push_str(&vectors.metrics());
+    }
+    let _ = writeln!(metrics, "# HELP news_feed_push_notifications_total Push notifications sent.");
+    let _ = writeln!(metrics, "# TYPE news_feed_push_notifications_total counter");
+    let _ = writeln!(metrics, "news_feed_push_notifications_total {}", state.push_gateway.sent());
+    let _ = writeln!(metrics, "# HELP news_feed_push_notifications_held_total Bell notifications not pushed during quiet hours.");
+    let _ = writeln!(metrics, "# TYPE news_feed_push_notifications_held_total counter");
+    let _ = writeln!(metrics, "news_feed_push_notifications_held_total {}", state.push_gateway.held());
+    let _ = writeln!(metrics, "# HELP news_feed_emails_sent_total Emails sent.");
+    let _ = writeln!(metrics, "# TYPE news_feed_emails_sent_total counter");
+    let _ = writeln!(metrics, "news_feed_emails_sent_total {}", state.email_service.sent());
+    let _ = writeln!(metrics, "# HELP news_feed_moderation_open_cases Flagged accounts waiting in the moderation queue.");
+    let _ = writeln!(metrics, "# TYPE news_feed_moderation_open_cases gauge");
+    let _ = writeln!(metrics, "news_feed_moderation_open_cases {}", state.moderation.open_cases());
+    Ok(warp::reply::with_header(
+        metrics,
+        "content-type",
+        "text/plain; version=0.0.4",
+    ))
+}
@@ -0,0 +1,295 @@
+// HTTP message signatures (RFC 9421) with Content-Digest (RFC 9530), for
+// server-to-server calls. A signature covers a list of components: derived
+// ones such as "@method", "@authority", "@path", and "@status", and header
+// fields by lowercase name. Outgoing webhook or ActivityPub deliveries sign
+// "@method" "@authority" "@path" "content-digest" with the same Signer that
+// signs federation responses here.
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use ed25519_dalek::{Signer as _, Verifier as _};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+use newsfeed_core::warn;
+
+const LABEL: &str = "sig1";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SignatureError {
+    Missing,
+    Malformed,
+    UnknownKey,
+    MissingComponent(String), // required but not covered, or covered but absent
+    Expired,
+    BadDigest,
+    BadSignature,
+}
+
+impl SignatureError {
+    pub fn message(&self) -> String {
+        match self {
+            Self::Missing => "Signature and Signature-Input headers are required".to_string(),
+            Self::Malformed => "Malformed Signature or Signature-Input header".to_string(),
+            Self::UnknownKey => "Unknown keyid".to_string(),
+            Self::MissingComponent(name) => format!("The signature must cover {}", name),
+            Self::Expired => "The signature is too old or created in the future".to_string(),
+            Self::BadDigest => "Content-Digest doesn't match the body".to_string(),
+            Self::BadSignature => "Invalid signature".to_string(),
+        }
+    }
+}
+
+// Peers are trusted either by their ed25519 public key (federation) or by a
+// shared secret (webhooks)
+#[derive(Debug)]
+enum VerifyingKey {
+    Ed25519(ed25519_dalek::VerifyingKey),
+    Hmac(Vec<u8>),
+}
+
+impl VerifyingKey {
+    // "ed25519:<hex public key>" or "hmac-sha256:<secret>"
+    fn parse(spec: &str) -> Option<Self> {
+        let (alg, material) = spec.split_once(':')?;
+        match alg {
+            "ed25519" => {
+                let bytes: [u8; 32] = hex::decode(material).ok()?.try_into().ok()?;
+                ed25519_dalek::VerifyingKey::from_bytes(&bytes).ok().map(Self::Ed25519)
+            }
+            "hmac-sha256" if !material.is_empty() => Some(Self::Hmac(material.as_bytes().to_vec())),
+            _ => None,
+        }
+    }
+
+    fn alg(&self) -> &'static str {
+        match self {
+            Self::Ed25519(_) => "ed25519",
+            Self::Hmac(_) => "hmac-sha256",
+        }
+    }
+
+    fn verify(&self, base: &[u8], signature: &[u8]) -> bool {
+        match self {
+            Self::Ed25519(key) => ed25519_dalek::Signature::from_slice(signature)
+                .is_ok_and(|signature| key.verify(base, &signature).is_ok()),
+            Self::Hmac(secret) => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+                mac.update(base);
+                mac.verify_slice(signature).is_ok()
+            }
+        }
+    }
+}
+
+// Signs with this server's ed25519 key
+pub struct Signer {
+    key_id: String,
+    key: ed25519_dalek::SigningKey,
+}
+
+impl Signer {
+    // The seed is 32 bytes of hex; anything else derives one from it, so a
+    // passphrase works too
+    pub fn ed25519(key_id: &str, seed: &str) -> Self {
+        let bytes: [u8; 32] = hex::decode(seed)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .unwrap_or_else(|| Sha256::digest(seed.as_bytes()).into());
+        Self {
+            key_id: key_id.to_string(),
+            key: ed25519_dalek::SigningKey::from_bytes(&bytes),
+        }
+    }
+
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    // "ed25519:<hex>", the form peers configure it in
+    pub fn public_key(&self) -> String {
+        format!("ed25519:{}", hex::encode(self.key.verifying_key().as_bytes()))
+    }
+
+    // Returns the Signature-Input and Signature header values
+    pub fn sign(&self, components: &[(&str, String)], created: u64) -> (String, String) {
+        let params = signature_params(components.iter().map(|(name, _)| *name), created, &self.key_id, "ed25519");
+        let base = signature_base(components, &params);
+        let signature = STANDARD.encode(self.key.sign(base.as_bytes()).to_bytes());
+        (format!("{}={}", LABEL, params), format!("{}=:{}:", LABEL, signature))
+    }
+}
+
+// Trusted peers by keyid
+#[derive(Debug, Default)]
+pub struct KeyRing {
+    keys: HashMap<String, VerifyingKey>,
+    max_age_secs: u64,
+}
+
+impl KeyRing {
+    // Entries that don't parse are skipped with a warning
+    pub fn new(peers: &[(String, String)], max_age_secs: u64) -> Self {
+        let mut keys = HashMap::new();
+        for (key_id, spec) in peers {
+            match VerifyingKey::parse(spec) {
+                Some(key) => {
+                    keys.insert(key_id.clone(), key);
+                }
+                None => warn!("Ignoring federation peer {}: unrecognized key", key_id),
+            }
+        }
+        Self { keys, max_age_secs }
+    }
+
+    // Checks the signature labelled sig1 (or the only one present). `value`
+    // resolves a covered component to its value in the message; `required`
+    // components must be covered. Returns the signer's keyid.
+    pub fn verify(
+        &self,
+        signature_input: Option<&str>,
+        signature: Option<&str>,
+        value: impl Fn(&str) -> Option<String>,
+        required: &[&str],
+        now_secs: u64,
+    ) -> Result<String, SignatureError> {
+        let (Some(signature_input), Some(signature)) = (signature_input, signature) else {
+            return Err(SignatureError::Missing);
+        };
+        let (label, params) = pick_member(signature_input, None)?;
+        let (_, encoded) = pick_member(signature, Some(&label))?;
+        let parsed = parse_params(&params)?;
+
+        let key = self.keys.get(&parsed.key_id).ok_or(SignatureError::UnknownKey)?;
+        if parsed.alg.as_deref().is_some_and(|alg| alg != key.alg()) {
+            return Err(SignatureError::BadSignature);
+        }
+        if let Some(missing) = required.iter().find(|name| !parsed.components.iter().any(|c| c == *name)) {
+            return Err(SignatureError::MissingComponent(missing.to_string()));
+        }
+        let created = parsed.created.ok_or(SignatureError::Malformed)?;
+        if created > now_secs + 60 || now_secs.saturating_sub(created) > self.max_age_secs {
+            return Err(SignatureError::Expired);
+        }
+
+        let mut components = Vec::new();
+        for name in &parsed.components {
+            let component = value(name).ok_or_else(|| SignatureError::MissingComponent(name.clone()))?;
+            components.push((name.as_str(), component));
+        }
+        let base = signature_base(&components, &params);
+        let signature = encoded
+            .strip_prefix(':')
+            .and_then(|rest| rest.strip_suffix(':'))
+            .and_then(|encoded| STANDARD.decode(encoded).ok())
+            .ok_or(SignatureError::Malformed)?;
+        if key.verify(base.as_bytes(), &signature) {
+            Ok(parsed.key_id)
+        } else {
+            Err(SignatureError::BadSignature)
+        }
+    }
+}
+
+// RFC 9530 Content-Digest header value
+pub fn content_digest(body: &[u8]) -> String {
+    format!("sha-256=:{}:", STANDARD.encode(Sha256::digest(body)))
+}
+
+pub fn digest_matches(header: &str, body: &[u8]) -> bool {
+    header
+        .split(',')
+        .map(str::trim)
+        .filter(|member| member.starts_with("sha-256="))
+        .any(|member| member == content_digest(body))
+}
+
+fn signature_params<'a>(names: impl Iterator<Item = &'a str>, created: u64, key_id: &str, alg: &str) -> String {
+    let names: Vec<String> = names.map(|name| format!("\"{}\"", name)).collect();
+    format!("({});created={};keyid=\"{}\";alg=\"{}\"", names.join(" "), created, key_id, alg)
+}
+
+// RFC 9421 section 2.5
+fn signature_base(components: &[(&str, String)], params: &str) -> String {
+    let mut base = String::new();
+    for (name, value) in components {
+        base.push_str(&format!("\"{}\": {}\n", name, value.trim()));
+    }
+    base.push_str(&format!("\"@signature-params\": {}", params));
+    base
+}
+
+// Splits a structured-field dictionary into its `label=value` members,
+// leaving inner lists, strings, and byte sequences intact
+fn dictionary(header: &str) -> Vec<(&str, &str)> {
+    let mut members = Vec::new();
+    let (mut start, mut depth, mut quoted, mut bytes) = (0, 0, false, false);
+    for (index, c) in header.char_indices() {
+        match c {
+            '"' if !bytes => quoted = !quoted,
+            ':' if !quoted => bytes = !bytes,
+            '(' if !quoted && !bytes => depth += 1,
+            ')' if !quoted && !bytes => depth -= 1,
+            ',' if !quoted && !bytes && depth == 0 => {
+                members.push(&header[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    members.push(&header[start..]);
+    members
+        .into_iter()
+        .filter_map(|member| member.trim().split_once('='))
+        .collect()
+}
+
+// The member labelled sig1, or the only member; a receiver can't guess which
+// of several unknown signatures is meant for it
+fn pick_member(header: &str, label: Option<&str>) -> Result<(String, String), SignatureError> {
+    let members = dictionary(header);
+    let wanted = label.unwrap_or(LABEL);
+    members
+        .iter()
+        .find(|(name, _)| *name == wanted)
+        .or_else(|| (label.is_none() && members.len() == 1).then(|| &members[0]))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .ok_or(SignatureError::Malformed)
+}
+
+struct Params {
+    components: Vec<String>,
+    created: Option<u64>,
+    key_id: String,
+    alg: Option<String>,
+}
+
+// `("@method" "@path");created=1;keyid="k";alg="ed25519"`
+fn parse_params(params: &str) -> Result<Params, SignatureError> {
+    let inner = params.strip_prefix('(').ok_or(SignatureError::Malformed)?;
+    let (list, rest) = inner.split_once(')').ok_or(SignatureError::Malformed)?;
+    let components = list
+        .split_whitespace()
+        .map(|name| name.trim_matches('"').to_string())
+        .collect();
+    let mut parsed = Params {
+        components,
+        created: None,
+        key_id: String::new(),
+        alg: None,
+    };
+    for param in rest.split(';').filter(|param| !param.is_empty()) {
+        let (name, value) = param.split_once('=').ok_or(SignatureError::Malformed)?;
+        let value = value.trim_matches('"');
+        match name {
+            "created" => parsed.created = value.parse().ok(),
+            "keyid" => parsed.key_id = value.to_string(),
+            "alg" => parsed.alg = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    if parsed.key_id.is_empty() {
+        return Err(SignatureError::Malformed);
+    }
+    Ok(parsed)
+}
@@ -0,0 +1,47 @@
+// Server-generated text in responses: error messages translated into the
+// request's language with the catalogs in newsfeed-core
+use warp::http::header::{CONTENT_LENGTH, CONTENT_TYPE, HeaderValue, VARY};
+use warp::hyper::Body;
+use warp::reply::Response;
+
+use newsfeed_core::i18n::Catalogs;
+
+// Translates the `error` of a JSON error response, rejections included,
+// into the request's language. Messages built with values in them
+// (lengths, limits, names) have no catalog entry and stay in English.
+pub async fn localize(catalogs: &Catalogs, accept_language: Option<&str>, response: Response) -> Response {
+    let locale = catalogs.pick(accept_language, None);
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    let is_error = response.status().is_client_error() || response.status().is_server_error();
+    if !catalogs.has_catalog(&locale) || !is_json || !is_error {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let bytes = match warp::hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(_) => return Response::from_parts(parts, Body::empty()),
+    };
+    let mut value: serde_json::Value = match serde_json::from_slice(&bytes) {
+        Ok(value) => value,
+        Err(_) => return Response::from_parts(parts, Body::from(bytes)),
+    };
+    let translated = value
+        .get("error")
+        .and_then(|error| error.as_str())
+        .and_then(|error| catalogs.translation(&locale, error))
+        .map(str::to_string);
+    let Some(translated) = translated else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    value["error"] = serde_json::Value::String(translated);
+    parts.headers.remove(CONTENT_LENGTH);
+    if let Ok(locale) = HeaderValue::from_str(&locale) {
+        parts.headers.insert("content-language", locale);
+    }
+    parts.headers.insert(VARY, HeaderValue::from_static("accept-language"));
+    Response::from_parts(parts, Body::from(value.to_string()))
+}
@@ -0,0 +1,118 @@
+// The HTTP and gRPC side: warp filters and handlers over the core services,
+// plus what only a server needs (rate limits, access logs, profiling, the
+// embeddings and transcoding clients).
+#![recursion_limit = "256"]
+
+pub mod access_log;
+pub mod auth;
+pub mod batch;
+pub mod cluster;
+pub mod embeddings;
+pub mod feed_stream;
+pub mod handlers;
+pub mod http_signature;
+pub mod i18n;
+pub mod limits;
+pub mod profiling;
+pub mod rate_limit;
+pub mod rejections;
+pub mod requests;
+pub mod routes;
+pub mod sandbox;
+pub mod versioning;
+pub mod video;
+
+use std::sync::Arc;
+
+use newsfeed_core::accounts::AccountTokens;
+use newsfeed_core::audit::AuditLog;
+use newsfeed_core::bootstrap::Bootstrap;
+use newsfeed_core::config::{Config, Settings};
+use newsfeed_core::conversations::ConversationService;
+use newsfeed_core::email::EmailService;
+use newsfeed_core::embeddings::Vectors;
+use newsfeed_core::engagement_log::EngagementLog;
+use newsfeed_core::events::EventStore;
+use newsfeed_core::fanout::FanoutWorker;
+use newsfeed_core::i18n::Catalogs;
+use newsfeed_core::ids::UserId;
+use newsfeed_core::images::ImagePipeline;
+use newsfeed_core::jobs::JobQueue;
+use newsfeed_core::media::MediaSigner;
+use newsfeed_core::moderation::ModerationQueue;
+use newsfeed_core::news_feed::NewsFeedService;
+use newsfeed_core::notifications::PushGateway;
+use newsfeed_core::oauth::OAuthProvider;
+use newsfeed_core::passwords::Passwords;
+use newsfeed_core::posts::PostService;
+use newsfeed_core::residency::StorageRouter;
+use newsfeed_core::search::SearchIndex;
+use newsfeed_core::two_factor::TwoFactor;
+use newsfeed_core::users::UserService;
+use newsfeed_core::FeedEvent;
+
+use newsfeed_store_memory::cache::CacheLayer;
+use newsfeed_store_memory::memory::MemoryMonitor;
+
+use crate::access_log::RequestMetrics;
+use crate::batch::BatchDispatcher;
+use crate::cluster::FeedDeliveryClient;
+use crate::feed_stream::FeedStreamService;
+use crate::http_signature::{KeyRing, Signer};
+use crate::profiling::Profiler;
+use crate::rate_limit::RateLimiter;
+use crate::video::VideoPipeline;
+
+pub use routes::build_routes;
+
+// Application State, one per tenant
+#[derive(Clone)]
+pub struct AppState {
+    pub cache: Arc<CacheLayer>,
+    pub post_service: Arc<PostService>,
+    pub news_feed_service: Arc<NewsFeedService>,
+    pub feed_stream: Arc<FeedStreamService>, // WebSocket feed updates
+    pub conversation_service: Arc<ConversationService>,
+    pub user_service: Arc<UserService>,
+    pub image_pipeline: Arc<ImagePipeline>,
+    pub account_tokens: Arc<AccountTokens>,
+    pub video_pipeline: Arc<VideoPipeline>,
+    pub media_signer: Arc<MediaSigner>,
+    pub profiler: Arc<Profiler>,
+    pub memory_monitor: Arc<MemoryMonitor>,
+    pub push_gateway: Arc<PushGateway>,
+    pub moderation: Arc<ModerationQueue>,
+    pub audit_log: Arc<AuditLog>,
+    pub email_service: Arc<EmailService>,
+    pub catalogs: Arc<Catalogs>, // translations of server-generated text
+    pub two_factor: Arc<TwoFactor>,
+    pub passwords: Arc<Passwords>,
+    pub oauth: Arc<OAuthProvider>,
+    pub signer: Arc<Signer>,
+    pub peers: Arc<KeyRing>,
+    pub storage: Arc<StorageRouter>,
+    pub batch: Arc<BatchDispatcher>,
+    pub events: Arc<EventStore<FeedEvent>>,
+    pub engagement_log: Arc<EngagementLog>,
+    pub telemetry_limiter: Arc<RateLimiter<UserId>>, // batches per user
+    pub public_limiter: Arc<RateLimiter<String>>, // logged-out requests per client IP
+    pub search: Arc<SearchIndex>,
+    pub vectors: Option<Arc<Vectors>>, // set when embeddings are on
+    pub jobs: Arc<JobQueue>,
+    pub fanout_worker: Arc<FanoutWorker>, // also serves other nodes' fanout batches
+    pub feed_nodes: Arc<FeedDeliveryClient>,
+    pub startup: Arc<Bootstrap>,
+    pub requests: Arc<RequestMetrics>, // shared by both tenants
+    pub config: Arc<Config>,
+    pub settings: Arc<Settings>, // shared by both tenants
+    pub sandbox: bool, // serves the sandbox tenant rather than production
+}
+
+// The settings only the server checks; startup stops on these
+pub fn validate(config: &Config) -> Result<(), String> {
+    limits::listeners(config)?;
+    if let Some(spec) = &config.embeddings {
+        embeddings::backend(spec).map_err(|e| format!("NEWS_FEED_EMBEDDINGS: {}", e))?;
+    }
+    cluster::validate(config)
+}
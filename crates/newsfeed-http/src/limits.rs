@@ -0,0 +1,322 @@
+use serde::Serialize;
+use std::convert::Infallible;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::fs::FileTypeExt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{UnixListener, UnixStream};
+use warp::http::header::{ACCEPT_LANGUAGE, HeaderValue};
+use warp::http::{Method, StatusCode};
+use warp::hyper::server::accept::{self, Accept};
+use warp::hyper::server::conn::{AddrIncoming, AddrStream};
+use warp::hyper::service::{Service, make_service_fn, service_fn};
+use warp::hyper::{Body, Request, Server};
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+use newsfeed_core::config::{Config, Settings};
+use newsfeed_core::context::{Cancellation, Deadline, PeerAddr};
+use newsfeed_core::error;
+use newsfeed_core::i18n::Catalogs;
+
+use crate::access_log::{AccessLogger, REQUEST_ID_HEADER, resolve_request_id, route_template};
+use crate::i18n;
+
+#[derive(Debug, Serialize)]
+struct NotFoundResponse {
+    error: String,
+}
+
+#[derive(Debug, Serialize)]
+struct TimeoutResponse {
+    error: String,
+    code: &'static str,
+    request_id: String,
+}
+
+// How long each route may take to produce a response, read from the current
+// settings so a reload applies to the next request
+pub struct RequestLimits {
+    settings: Arc<Settings>,
+}
+
+impl RequestLimits {
+    pub fn new(settings: Arc<Settings>) -> Self {
+        Self { settings }
+    }
+
+    fn timeout_for(&self, method: &Method, path: &str) -> Duration {
+        let tunables = self.settings.current();
+        let key = format!("{} {}", method, route_template(path)); // "METHOD /route/template"
+        let millis = tunables
+            .route_timeouts_ms
+            .iter()
+            .find(|(pattern, _)| *pattern == key)
+            .map(|(_, timeout)| *timeout)
+            .unwrap_or(tunables.request_timeout_ms);
+        Duration::from_millis(millis.max(1))
+    }
+}
+
+// Where the server accepts connections: "host:port", or "unix:/path" for a
+// Unix domain socket, e.g. for a sidecar proxy on the same host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.strip_prefix("unix:") {
+            Some("") => Err("unix: needs a socket path".to_string()),
+            Some(path) => Ok(Self::Unix(PathBuf::from(path))),
+            None => value
+                .parse()
+                .map(Self::Tcp)
+                .map_err(|_| format!("{} is not host:port or unix:/path", value)),
+        }
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+// Which routes a listener serves. With an admin listener configured, the
+// admin API and metrics are served only there. Requests carry it so batch
+// sub-requests are held to the same rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exposure {
+    All,
+    Public,
+    Internal,
+}
+
+impl Exposure {
+    pub fn serves(self, path: &str) -> bool {
+        let internal = path == "/metrics" || path.starts_with("/v1/admin/");
+        match self {
+            Self::All => true,
+            Self::Public => !internal,
+            Self::Internal => internal,
+        }
+    }
+}
+
+// Every address to listen on, and which routes each serves
+pub fn listeners(config: &Config) -> Result<Vec<(ListenAddr, Exposure)>, String> {
+    let exposure = if config.admin_listen.is_some() { Exposure::Public } else { Exposure::All };
+    let mut listeners = config
+        .listen
+        .iter()
+        .map(|addr| Ok((addr.parse().map_err(|e| format!("NEWS_FEED_LISTEN: {}", e))?, exposure)))
+        .collect::<Result<Vec<_>, String>>()?;
+    if listeners.is_empty() {
+        return Err("NEWS_FEED_LISTEN has no addresses".to_string());
+    }
+    if let Some(addr) = &config.admin_listen {
+        let addr = addr.parse().map_err(|e| format!("NEWS_FEED_ADMIN_LISTEN: {}", e))?;
+        listeners.push((addr, Exposure::Internal));
+    }
+    Ok(listeners)
+}
+
+enum Socket {
+    Tcp(AddrIncoming),
+    Unix(UnixListener),
+}
+
+// A bound listening socket, not yet accepting connections
+pub struct Listener {
+    pub addr: ListenAddr,
+    pub exposure: Exposure,
+    socket: Socket,
+}
+
+// Binds a listener; done before serving so startup can retry it. A socket
+// file left behind by an earlier run is replaced, but no other kind of file.
+pub fn bind(addr: &ListenAddr, exposure: Exposure) -> std::io::Result<Listener> {
+    let socket = match addr {
+        ListenAddr::Tcp(tcp) => Socket::Tcp(AddrIncoming::bind(tcp).map_err(std::io::Error::other)?),
+        ListenAddr::Unix(path) => {
+            if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+                std::fs::remove_file(path)?;
+            }
+            Socket::Unix(UnixListener::bind(path)?)
+        }
+    };
+    Ok(Listener {
+        addr: addr.clone(),
+        exposure,
+        socket,
+    })
+}
+
+// Serves the route tree on one listener with per-route timeouts. A request
+// that runs out of time is dropped and answered with 504, carrying the
+// request ID so it can be matched with the access log. Error responses are
+// translated on the way out.
+pub async fn serve<F, R>(
+    listener: Listener,
+    routes: F,
+    limits: Arc<RequestLimits>,
+    logger: Arc<AccessLogger>,
+    catalogs: Arc<Catalogs>,
+)
+where
+    F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    let result = match listener.socket {
+        Socket::Tcp(incoming) => accept_on(incoming, listener.exposure, routes, limits, logger, catalogs).await,
+        Socket::Unix(unix) => {
+            let connections = futures::stream::unfold(unix, |unix| async move {
+                let connection = unix.accept().await.map(|(stream, _)| stream);
+                Some((connection, unix))
+            });
+            accept_on(accept::from_stream(connections), listener.exposure, routes, limits, logger, catalogs).await
+        }
+    };
+    if let Err(e) = result {
+        error!("Server error on {}: {}", listener.addr, e);
+    }
+}
+
+// The remote address of an accepted connection, if it has one
+pub trait Peer {
+    fn peer(&self) -> Option<IpAddr>;
+}
+
+impl Peer for AddrStream {
+    fn peer(&self) -> Option<IpAddr> {
+        Some(self.remote_addr().ip())
+    }
+}
+
+impl Peer for UnixStream {
+    fn peer(&self) -> Option<IpAddr> {
+        None
+    }
+}
+
+async fn accept_on<I, F, R>(
+    incoming: I,
+    exposure: Exposure,
+    routes: F,
+    limits: Arc<RequestLimits>,
+    logger: Arc<AccessLogger>,
+    catalogs: Arc<Catalogs>,
+) -> Result<(), warp::hyper::Error>
+where
+    I: Accept,
+    I::Conn: Peer + AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    let service = warp::service(routes);
+    let make_service = make_service_fn(move |connection: &I::Conn| {
+        let peer = PeerAddr(connection.peer());
+        let service = service.clone();
+        let limits = limits.clone();
+        let logger = logger.clone();
+        let catalogs = catalogs.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle(service.clone(), exposure, peer, limits.clone(), logger.clone(), catalogs.clone(), request)
+            }))
+        }
+    });
+    Server::builder(incoming).serve(make_service).await
+}
+
+async fn handle<S>(
+    service: S,
+    exposure: Exposure,
+    peer: PeerAddr,
+    limits: Arc<RequestLimits>,
+    logger: Arc<AccessLogger>,
+    catalogs: Arc<Catalogs>,
+    request: Request<Body>,
+) -> Result<Response, Infallible>
+where
+    S: Service<Request<Body>, Response = Response, Error = Infallible>,
+{
+    let accept_language = request
+        .headers()
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let Ok(response) = respond(service, exposure, peer, limits, logger, request).await;
+    Ok(i18n::localize(&catalogs, accept_language.as_deref(), response).await)
+}
+
+async fn respond<S>(
+    mut service: S,
+    exposure: Exposure,
+    peer: PeerAddr,
+    limits: Arc<RequestLimits>,
+    logger: Arc<AccessLogger>,
+    mut request: Request<Body>,
+) -> Result<Response, Infallible>
+where
+    S: Service<Request<Body>, Response = Response, Error = Infallible>,
+{
+    let start = Instant::now();
+    // Pin the request ID here so the access log and a 504 report the same one
+    let request_id = resolve_request_id(request.headers());
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        request.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let headers = request.headers().clone();
+    if !exposure.serves(&path) {
+        let response = warp::reply::with_status(
+            warp::reply::json(&NotFoundResponse {
+                error: "Not found".to_string(),
+            }),
+            StatusCode::NOT_FOUND,
+        )
+        .into_response();
+        return Ok(logger.complete(start, &method, &path, &headers, response));
+    }
+    let timeout = limits.timeout_for(&method, &path);
+    // Handlers read the deadline and cancellation token from the request
+    // context. The guard cancels the token however this future ends,
+    // including being dropped when the client disconnects.
+    let cancellation = Cancellation::default();
+    let _cancel_on_exit = cancellation.0.clone().drop_guard();
+    request.extensions_mut().insert(Deadline(start + timeout));
+    request.extensions_mut().insert(cancellation);
+    request.extensions_mut().insert(exposure);
+    request.extensions_mut().insert(peer);
+
+    match tokio::time::timeout(timeout, service.call(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            let response = warp::reply::with_status(
+                warp::reply::json(&TimeoutResponse {
+                    error: "Request timed out".to_string(),
+                    code: "timeout",
+                    request_id,
+                }),
+                StatusCode::GATEWAY_TIMEOUT,
+            )
+            .into_response();
+            Ok(logger.complete(start, &method, &path, &headers, response))
+        }
+    }
+}
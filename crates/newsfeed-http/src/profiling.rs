@@ -0,0 +1,159 @@
+use serde::Serialize;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio_metrics::{RuntimeIntervals, RuntimeMonitor, TaskMetrics, TaskMonitor};
+
+pub const MAX_PROFILE_SECS: u64 = 60;
+
+// Monitors for the long-lived background task families
+#[derive(Clone, Default)]
+pub struct TaskMonitors {
+    pub fanout: TaskMonitor,
+    pub transcode: TaskMonitor,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RuntimeSnapshot {
+    interval_ms: u128, // since the previous snapshot
+    workers: usize,
+    alive_tasks: usize,
+    global_queue_depth: usize,
+    busy_ratio: f64,
+    total_park_count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaskSnapshot {
+    name: &'static str,
+    instrumented: u64,
+    dropped: u64,
+    polls: u64,
+    mean_poll_us: u128,
+    mean_slow_poll_us: u128,
+    slow_poll_ratio: f64,
+    mean_first_poll_delay_us: u128,
+    mean_scheduled_us: u128,
+}
+
+impl TaskSnapshot {
+    fn new(name: &'static str, metrics: TaskMetrics) -> Self {
+        Self {
+            name,
+            instrumented: metrics.instrumented_count,
+            dropped: metrics.dropped_count,
+            polls: metrics.total_poll_count,
+            mean_poll_us: metrics.mean_poll_duration().as_micros(),
+            mean_slow_poll_us: metrics.mean_slow_poll_duration().as_micros(),
+            slow_poll_ratio: metrics.slow_poll_ratio(),
+            mean_first_poll_delay_us: metrics.mean_first_poll_delay().as_micros(),
+            mean_scheduled_us: metrics.mean_scheduled_duration().as_micros(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RuntimeReport {
+    runtime: RuntimeSnapshot,
+    tasks: Vec<TaskSnapshot>,
+}
+
+// Runtime and task metrics plus on-demand CPU profiles for admins
+pub struct Profiler {
+    runtime: Mutex<RuntimeIntervals>,
+    tasks: TaskMonitors,
+    capturing: AtomicBool,
+}
+
+impl Profiler {
+    pub fn new(tasks: TaskMonitors) -> Self {
+        let monitor = RuntimeMonitor::new(&tokio::runtime::Handle::current());
+        Self {
+            runtime: Mutex::new(monitor.intervals()),
+            tasks,
+            capturing: AtomicBool::new(false),
+        }
+    }
+
+    pub fn runtime_report(&self) -> RuntimeReport {
+        let metrics = self
+            .runtime
+            .lock()
+            .expect("runtime intervals poisoned")
+            .next()
+            .expect("runtime intervals never end");
+        let runtime = RuntimeSnapshot {
+            interval_ms: metrics.elapsed.as_millis(),
+            workers: metrics.workers_count,
+            alive_tasks: tokio::runtime::Handle::current().metrics().num_alive_tasks(),
+            global_queue_depth: metrics.global_queue_depth,
+            busy_ratio: metrics.busy_ratio(),
+            total_park_count: metrics.total_park_count,
+        };
+        RuntimeReport {
+            runtime,
+            tasks: vec![
+                TaskSnapshot::new("fanout", self.tasks.fanout.cumulative()),
+                TaskSnapshot::new("transcode", self.tasks.transcode.cumulative()),
+            ],
+        }
+    }
+
+    // Samples every thread for `duration` and returns folded stacks
+    // ("thread;outer;...;inner count" per line), the input format of most
+    // flamegraph tools. Returns None if a capture is already running.
+    pub async fn capture_cpu_profile(&self, duration: Duration, frequency: i32) -> Option<Result<String, String>> {
+        if self.capturing.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        // Clears the flag even if the request times out and this future is dropped
+        let _capturing = CaptureGuard(&self.capturing);
+
+        // The profiler guard isn't Send, so keep it on a blocking thread
+        let result = tokio::task::spawn_blocking(move || {
+            let guard = pprof::ProfilerGuardBuilder::default()
+                .frequency(frequency)
+                .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+                .build()
+                .map_err(|e| e.to_string())?;
+            std::thread::sleep(duration);
+            let report = guard.report().build().map_err(|e| e.to_string())?;
+            Ok(fold_stacks(&report))
+        })
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+        Some(result)
+    }
+}
+
+struct CaptureGuard<'a>(&'a AtomicBool);
+
+impl Drop for CaptureGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+fn fold_stacks(report: &pprof::Report) -> String {
+    let mut lines: Vec<(isize, String)> = report
+        .data
+        .iter()
+        .map(|(frames, count)| {
+            let mut line = frames.thread_name_or_id();
+            for frame in frames.frames.iter().rev() {
+                for symbol in frame.iter().rev() {
+                    let _ = write!(line, ";{}", symbol);
+                }
+            }
+            (*count, line)
+        })
+        .collect();
+    lines.sort_by_key(|(count, _)| std::cmp::Reverse(*count));
+
+    let mut folded = String::new();
+    for (count, line) in lines {
+        let _ = writeln!(folded, "{} {}", line, count);
+    }
+    folded
+}
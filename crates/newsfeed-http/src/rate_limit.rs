@@ -0,0 +1,68 @@
+use dashmap::DashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use newsfeed_core::config::{Settings, Tunables};
+
+// Buckets are swept for idle keys once every this many checks
+const SWEEP_EVERY: u64 = 1024;
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated_at: u64, // millis
+}
+
+// A token bucket per key: `burst` requests at once, refilled at `per_minute`.
+// Both are read from the tunables on every check, so a reload applies to
+// buckets already in use. Keys whose bucket has refilled completely are
+// dropped now and then, since a new bucket starts full anyway.
+pub struct RateLimiter<K: Eq + Hash> {
+    settings: Arc<Settings>,
+    limits: fn(&Tunables) -> (u32, u32), // per minute, burst
+    buckets: DashMap<K, Bucket>,
+    checks: AtomicU64,
+}
+
+impl<K: Eq + Hash + Clone> RateLimiter<K> {
+    pub fn new(settings: Arc<Settings>, limits: fn(&Tunables) -> (u32, u32)) -> Self {
+        Self {
+            settings,
+            limits,
+            buckets: DashMap::new(),
+            checks: AtomicU64::new(0),
+        }
+    }
+
+    // Takes a token, or says how many seconds until one is free
+    pub fn check(&self, key: &K, now: u64) -> Result<(), u64> {
+        let (per_minute, burst) = (self.limits)(&self.settings.current());
+        let burst = burst.max(1) as f64;
+        let per_millis = per_minute as f64 / 60_000.0;
+        if self.checks.fetch_add(1, Ordering::Relaxed) % SWEEP_EVERY == SWEEP_EVERY - 1 {
+            self.sweep(now, burst, per_millis);
+        }
+        let mut bucket = self.buckets.entry(key.clone()).or_insert(Bucket {
+            tokens: burst,
+            updated_at: now,
+        });
+        bucket.tokens = refilled(&bucket, now, burst, per_millis);
+        bucket.updated_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        let wait_millis = (1.0 - bucket.tokens) / per_millis;
+        Err((wait_millis / 1000.0).ceil().max(1.0) as u64)
+    }
+
+    fn sweep(&self, now: u64, burst: f64, per_millis: f64) {
+        self.buckets.retain(|_, bucket| refilled(bucket, now, burst, per_millis) < burst);
+    }
+}
+
+fn refilled(bucket: &Bucket, now: u64, burst: f64, per_millis: f64) -> f64 {
+    let elapsed = now.saturating_sub(bucket.updated_at) as f64;
+    (bucket.tokens + elapsed * per_millis).min(burst)
+}
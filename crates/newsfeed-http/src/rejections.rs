@@ -0,0 +1,274 @@
+use warp::Reply;
+
+use newsfeed_core::config::Config;
+use newsfeed_core::PostUnavailable;
+
+use crate::http_signature::SignatureError;
+use crate::requests::{CodedErrorResponse, ContentTooLongResponse, ErrorResponse, RateLimitedResponse};
+
+#[derive(Debug)]
+pub struct AuthError;
+impl warp::reject::Reject for AuthError {}
+
+#[derive(Debug)]
+pub struct InvalidCredentials;
+impl warp::reject::Reject for InvalidCredentials {}
+
+#[derive(Debug)]
+pub struct SessionExpired;
+impl warp::reject::Reject for SessionExpired {}
+
+#[derive(Debug)]
+pub struct SignatureRejected(pub SignatureError);
+impl warp::reject::Reject for SignatureRejected {}
+
+#[derive(Debug)]
+pub struct StorageError;
+impl warp::reject::Reject for StorageError {}
+
+// Account state or two-factor enrollment couldn't be read back, so the
+// request is refused rather than treated as active without two-factor
+#[derive(Debug)]
+pub struct StorageUnavailable;
+impl warp::reject::Reject for StorageUnavailable {}
+
+#[derive(Debug)]
+pub struct ProfileFailed;
+impl warp::reject::Reject for ProfileFailed {}
+
+#[derive(Debug)]
+pub struct NotFound;
+impl warp::reject::Reject for NotFound {}
+
+// Why a post can't be shown to a viewer. Handlers reject with it through
+// post_unavailable, which applies NEWS_FEED_REVEAL_POST_RESTRICTIONS.
+#[derive(Debug)]
+pub struct Unavailable(PostUnavailable);
+impl warp::reject::Reject for Unavailable {}
+
+#[derive(Debug)]
+pub struct ValidationError(pub String);
+impl warp::reject::Reject for ValidationError {}
+
+#[derive(Debug)]
+pub struct Forbidden {
+    pub code: &'static str,
+    pub message: &'static str,
+}
+impl warp::reject::Reject for Forbidden {}
+
+#[derive(Debug)]
+pub struct Conflict {
+    pub code: &'static str,
+    pub message: &'static str,
+}
+impl warp::reject::Reject for Conflict {}
+
+#[derive(Debug)]
+pub struct RateLimited {
+    pub retry_after_secs: u64,
+}
+impl warp::reject::Reject for RateLimited {}
+
+// Background work is backed up; the client should try again later
+#[derive(Debug)]
+pub struct Overloaded {
+    pub retry_after_secs: u64,
+}
+impl warp::reject::Reject for Overloaded {}
+
+#[derive(Debug)]
+pub struct ContentTooLong {
+    pub length: usize,
+    pub max_length: usize,
+}
+impl warp::reject::Reject for ContentTooLong {}
+
+#[derive(Debug)]
+pub struct MediaAccessDenied;
+impl warp::reject::Reject for MediaAccessDenied {}
+
+// A request given up on partway through, see context::Cancelled
+#[derive(Debug)]
+pub struct RequestCancelled;
+impl warp::reject::Reject for RequestCancelled {}
+
+pub async fn handle_rejection(err: warp::Rejection) -> Result<impl Reply, std::convert::Infallible> {
+    if err.find::<AuthError>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&ErrorResponse {
+                error: "Authentication required".to_string(),
+            }),
+            warp::http::StatusCode::UNAUTHORIZED,
+        ))
+    } else if err.find::<InvalidCredentials>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&CodedErrorResponse {
+                error: "Wrong username or password".to_string(),
+                code: "invalid_credentials",
+            }),
+            warp::http::StatusCode::UNAUTHORIZED,
+        ))
+    } else if err.find::<SessionExpired>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&CodedErrorResponse {
+                error: "Your session has expired; log in with your password again".to_string(),
+                code: "session_expired",
+            }),
+            warp::http::StatusCode::UNAUTHORIZED,
+        ))
+    } else if let Some(SignatureRejected(error)) = err.find::<SignatureRejected>() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&CodedErrorResponse {
+                error: error.message(),
+                code: "invalid_signature",
+            }),
+            warp::http::StatusCode::UNAUTHORIZED,
+        ))
+    } else if let Some(ValidationError(message)) = err.find::<ValidationError>() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&ErrorResponse {
+                error: message.clone(),
+            }),
+            warp::http::StatusCode::BAD_REQUEST,
+        ))
+    } else if err.find::<StorageError>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&ErrorResponse {
+                error: "Failed to store upload".to_string(),
+            }),
+            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        ))
+    } else if let Some(forbidden) = err.find::<Forbidden>() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&CodedErrorResponse {
+                error: forbidden.message.to_string(),
+                code: forbidden.code,
+            }),
+            warp::http::StatusCode::FORBIDDEN,
+        ))
+    } else if let Some(conflict) = err.find::<Conflict>() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&CodedErrorResponse {
+                error: conflict.message.to_string(),
+                code: conflict.code,
+            }),
+            warp::http::StatusCode::CONFLICT,
+        ))
+    } else if let Some(limited) = err.find::<RateLimited>() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&RateLimitedResponse {
+                error: "Too many requests".to_string(),
+                code: "rate_limited",
+                retry_after_secs: limited.retry_after_secs,
+            }),
+            warp::http::StatusCode::TOO_MANY_REQUESTS,
+        ))
+    } else if err.find::<StorageUnavailable>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&CodedErrorResponse {
+                error: "Account data can't be loaded right now; try again shortly".to_string(),
+                code: "storage_unavailable",
+            }),
+            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+        ))
+    } else if let Some(overloaded) = err.find::<Overloaded>() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&RateLimitedResponse {
+                error: "The server is busy; try again shortly".to_string(),
+                code: "overloaded",
+                retry_after_secs: overloaded.retry_after_secs,
+            }),
+            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+        ))
+    } else if let Some(too_long) = err.find::<ContentTooLong>() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&ContentTooLongResponse {
+                error: "Content exceeds the maximum length".to_string(),
+                length: too_long.length,
+                max_length: too_long.max_length,
+            }),
+            warp::http::StatusCode::BAD_REQUEST,
+        ))
+    } else if err.find::<MediaAccessDenied>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&ErrorResponse {
+                error: "Invalid or expired media URL".to_string(),
+            }),
+            warp::http::StatusCode::FORBIDDEN,
+        ))
+    } else if err.find::<RequestCancelled>().is_some() {
+        // Usually nobody is left to read this: the client went away or the
+        // server already answered 504. Batch sub-requests do see it.
+        Ok(warp::reply::with_status(
+            warp::reply::json(&CodedErrorResponse {
+                error: "Request timed out".to_string(),
+                code: "timeout",
+            }),
+            warp::http::StatusCode::GATEWAY_TIMEOUT,
+        ))
+    } else if err.find::<warp::reject::PayloadTooLarge>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&CodedErrorResponse {
+                error: "Request body is too large".to_string(),
+                code: "payload_too_large",
+            }),
+            warp::http::StatusCode::PAYLOAD_TOO_LARGE,
+        ))
+    } else if err.find::<warp::reject::LengthRequired>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&CodedErrorResponse {
+                error: "Request body needs a Content-Length".to_string(),
+                code: "length_required",
+            }),
+            warp::http::StatusCode::LENGTH_REQUIRED,
+        ))
+    } else if let Some(Unavailable(unavailable)) = err.find::<Unavailable>() {
+        let (error, code, status) = match unavailable {
+            PostUnavailable::Missing => ("Post not found", "post_not_found", warp::http::StatusCode::NOT_FOUND),
+            PostUnavailable::Deleted => ("Post was deleted", "post_deleted", warp::http::StatusCode::GONE),
+            PostUnavailable::Blocked => (
+                "The author has blocked you",
+                "blocked_by_author",
+                warp::http::StatusCode::FORBIDDEN,
+            ),
+            PostUnavailable::Restricted => (
+                "Log in to see this post",
+                "visibility_restricted",
+                warp::http::StatusCode::FORBIDDEN,
+            ),
+        };
+        Ok(warp::reply::with_status(
+            warp::reply::json(&CodedErrorResponse {
+                error: error.to_string(),
+                code,
+            }),
+            status,
+        ))
+    } else if err.find::<NotFound>().is_some() || err.is_not_found() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&ErrorResponse {
+                error: "Not found".to_string(),
+            }),
+            warp::http::StatusCode::NOT_FOUND,
+        ))
+    } else {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&ErrorResponse {
+                error: "Internal server error".to_string(),
+            }),
+            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        ))
+    }
+}
+
+// Blocks and restrictions look like missing posts unless the config says to
+// reveal them
+pub fn post_unavailable(config: &Config, reason: PostUnavailable) -> warp::Rejection {
+    match reason {
+        PostUnavailable::Blocked | PostUnavailable::Restricted if !config.reveal_post_restrictions => {
+            warp::reject::custom(Unavailable(PostUnavailable::Missing))
+        }
+        reason => warp::reject::custom(Unavailable(reason)),
+    }
+}
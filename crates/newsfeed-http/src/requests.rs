@@ -0,0 +1,759 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use newsfeed_core::account_state::AccountState;
+use newsfeed_core::accounts::{AccountSet, Scope};
+use newsfeed_core::ads::Targeting;
+use newsfeed_core::audit::AuditEntry;
+use newsfeed_core::context::{Role, Tenant};
+use newsfeed_core::ids::{PostId, TagId, UserId};
+use newsfeed_core::interests::{AuthorWeight, TopicWeight};
+use newsfeed_core::legal::HoldEntry;
+use newsfeed_core::login_history::LoginRecord;
+use newsfeed_core::moderation::ModerationCase;
+use newsfeed_core::notifications::Notification;
+use newsfeed_core::oauth::{OAuthClient, OAuthGrant};
+use newsfeed_core::pipeline::{FeedMode, RankingStrategy};
+use newsfeed_core::ranking::SignalKind;
+use newsfeed_core::reach::Reach;
+use newsfeed_core::residency::Region;
+use newsfeed_core::rsvp::{EventDetails, RsvpStatus};
+use newsfeed_core::saved_searches::SavedSearch;
+use newsfeed_core::search::Sort;
+use newsfeed_core::{FeedPosition, HydratedPost, ReplyPolicy, User, profile_views};
+
+use crate::batch::SubResponse;
+
+// HTTP Request/Response structs
+#[derive(Debug, Deserialize)]
+pub struct CreatePostRequest {
+    pub content: String,
+    pub image_url: Option<String>,
+    pub video_url: Option<String>,
+    pub alt_text: Option<String>,
+    #[serde(default)]
+    pub reply_policy: ReplyPolicy,
+    // Publish later instead; a local time is read in the author's timezone
+    #[serde(default)]
+    pub publish_at: Option<String>,
+    // Where the tenant allows it (NEWS_FEED_ANONYMOUS_POSTING)
+    #[serde(default)]
+    pub anonymous: bool,
+    // One of NEWS_FEED_LICENSES
+    #[serde(default)]
+    pub license: Option<String>,
+    // Users invited to co-author a top-level post
+    #[serde(default)]
+    pub co_authors: Vec<UserId>,
+    // Makes a top-level post an event others can RSVP to
+    #[serde(default)]
+    pub event: Option<EventDetails>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateArticleRequest {
+    pub title: String,
+    pub body: String, // markdown
+    #[serde(default)]
+    pub cover_image_url: Option<String>,
+    // The text of the article's post; its first paragraph when left out
+    #[serde(default)]
+    pub summary: Option<String>,
+    #[serde(default)]
+    pub reply_policy: ReplyPolicy,
+    #[serde(default)]
+    pub license: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArticleResponse {
+    #[serde(flatten)]
+    pub post: HydratedPost,
+    pub body: String, // markdown as written
+    pub html: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatePostResponse {
+    pub success: bool,
+    pub post_id: PostId,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScheduledPostResponse {
+    pub success: bool,
+    pub scheduled_id: u64, // the job that will publish it
+    pub publish_at: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EditPostRequest {
+    pub content: String,
+    pub alt_text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateThreadRequest {
+    pub posts: Vec<CreatePostRequest>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateThreadResponse {
+    pub success: bool,
+    pub post_ids: Vec<PostId>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetFeedResponse {
+    pub feed: Vec<HydratedPost>,
+    // Pass as ?cursor= for the next page; absent on the last one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resumed_from: Option<FeedPositionResponse>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchResponse {
+    pub responses: Vec<SubResponse>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContextResponse {
+    pub user_id: UserId,
+    pub roles: Vec<Role>,
+    pub tenant: Tenant,
+    pub request_id: String,
+    pub remaining_ms: u64,
+    pub features: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeedMeta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resumed_from: Option<FeedPositionResponse>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetFeedQuery {
+    #[serde(default)]
+    pub limit: Option<usize>,
+    // A page's next_cursor; takes precedence over resume
+    #[serde(default)]
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub resume: bool,
+    #[serde(default)]
+    pub fields: Option<String>,
+    #[serde(default)]
+    pub mode: FeedMode,
+    // Overrides the viewer's feed_ranking preference
+    #[serde(default)]
+    pub ranking: Option<RankingStrategy>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatsQuery {
+    pub weeks: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProfileViewsQuery {
+    pub days: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProfileVisitor {
+    pub user_id: UserId,
+    pub username: String,
+    pub visited_at: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProfileViewsResponse {
+    pub total_views: u64,
+    pub unique_visitors: u64, // approximate, all time
+    pub views_in_period: u64,
+    pub days: Vec<profile_views::DayViews>,
+    pub recent_visitors: Vec<ProfileVisitor>, // named visitors only
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeedStreamQuery {
+    // Resume after this cursor; without one the stream starts at the newest item
+    #[serde(default)]
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeedPollQuery {
+    #[serde(default)]
+    pub since: Option<String>,
+    #[serde(default)]
+    pub wait: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeedPollResponse {
+    pub feed: Vec<HydratedPost>,
+    pub cursor: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FieldsQuery {
+    #[serde(default)]
+    pub fields: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeedPositionResponse {
+    pub post_id: PostId,
+    pub cursor: String,
+    pub updated_at: u64,
+}
+
+impl From<FeedPosition> for FeedPositionResponse {
+    fn from(position: FeedPosition) -> Self {
+        Self {
+            cursor: position.cursor.encode(),
+            post_id: position.cursor.post_id,
+            updated_at: position.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HidePostsRequest {
+    pub post_ids: Vec<PostId>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HidePostsResponse {
+    pub success: bool,
+    pub removed: usize,
+}
+
+// Post-level feedback names a post; mutes may instead name an author or topic
+#[derive(Debug, Deserialize)]
+pub struct FeedbackRequest {
+    pub kind: SignalKind,
+    pub post_id: Option<PostId>,
+    pub author_id: Option<UserId>,
+    pub topic: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateCampaignRequest {
+    pub post_id: PostId,
+    #[serde(default)]
+    pub targeting: Targeting,
+    pub budget: u32,
+    pub duration_secs: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProfileQuery {
+    pub seconds: Option<u64>,
+    pub frequency: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetFeedPositionRequest {
+    pub post_id: PostId,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConversationQuery {
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    #[serde(default)]
+    pub sort: Sort,
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResults {
+    pub posts: Vec<HydratedPost>,
+    pub next_cursor: Option<String>,
+    pub indexed_seq: u64, // the newest event the results reflect
+}
+
+#[derive(Debug, Serialize)]
+pub struct InterestsResponse {
+    pub user_id: UserId,
+    pub topics: Vec<TopicWeight>, // strongest first, decayed to now
+    pub authors: Vec<AuthorWeight>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RelatedQuery {
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RelatedPostsResponse {
+    pub post_id: PostId,
+    pub posts: Vec<RelatedPostView>,
+}
+
+// A related post with why it was picked
+#[derive(Debug, Serialize)]
+pub struct RelatedPostView {
+    #[serde(flatten)]
+    pub post: HydratedPost,
+    pub score: f64,
+    pub shared_hashtags: Vec<TagId>,
+    pub shared_engagers: u32,
+    pub text_similarity: f64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SaveSearchRequest {
+    pub query: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SavedSearchesResponse {
+    pub saved_searches: Vec<SavedSearch>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TypeaheadQuery {
+    pub q: String,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TrendingHashtagsQuery {
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrendingHashtag {
+    pub hashtag: TagId,
+    pub posts: usize, // in the last day
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrendingHashtagsResponse {
+    pub hashtags: Vec<TrendingHashtag>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TypeaheadUser {
+    pub user_id: UserId,
+    pub username: String,
+    pub profile_picture: String,
+    pub verified: bool,
+    pub following: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TypeaheadHashtag {
+    pub hashtag: TagId,
+    pub posts: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TypeaheadResponse {
+    pub users: Vec<TypeaheadUser>,
+    pub hashtags: Vec<TypeaheadHashtag>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProfileResponse {
+    #[serde(flatten)]
+    pub user: User,
+    pub follower_count: usize,
+    pub following_count: usize,
+    pub post_count: usize,
+    // Set when the profile was found through a previous username
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moved_from: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LinkAccountRequest {
+    pub token: String,
+    pub scopes: Vec<Scope>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccountTokenResponse {
+    pub token: String,
+    pub accounts: AccountSet,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FederationKeyResponse {
+    pub key_id: String,
+    pub public_key: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InboxResponse {
+    pub accepted: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterClientRequest {
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    #[serde(default)]
+    pub sandbox: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RegisterClientResponse {
+    #[serde(flatten)]
+    pub client: OAuthClient,
+    pub client_secret: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientsResponse {
+    pub clients: Vec<OAuthClient>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GrantsResponse {
+    pub grants: Vec<OAuthGrant>,
+}
+
+// GET and POST /oauth/authorize; missing parameters fail validation below
+#[derive(Debug, Deserialize)]
+pub struct AuthorizeQuery {
+    #[serde(default)]
+    pub response_type: String,
+    #[serde(default)]
+    pub client_id: String,
+    #[serde(default)]
+    pub redirect_uri: String,
+    #[serde(default)]
+    pub scope: String,
+    pub state: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConsentForm {
+    pub decision: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    #[serde(default)]
+    pub code: String,
+    #[serde(default)]
+    pub redirect_uri: String,
+    #[serde(default)]
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: u64,
+    pub scope: String,
+}
+
+// RFC 6749 error body, which OAuth client libraries expect
+#[derive(Debug, Serialize)]
+pub struct OAuthErrorResponse {
+    pub error: &'static str,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginRequest {
+    // Required once the account has two-factor on
+    pub code: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoginHistoryResponse {
+    pub logins: Vec<LoginRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorCodeRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TwoFactorEnabledResponse {
+    pub recovery_codes: Vec<String>,
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TwoFactorPolicy {
+    pub required_for_admins: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SignupRequest {
+    pub username: String,
+    pub email: String,
+    // Where the account's data is stored; defaults to the node's own region
+    #[serde(default)]
+    pub region: Option<Region>,
+    // Required; optional here only so leaving it out is a 400, not a parse error
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordLoginRequest {
+    pub username: String,
+    pub password: String,
+    // Required once the account has two-factor on
+    pub code: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetPasswordRequest {
+    pub password: String,
+    // Required when the account already has a password
+    pub current_password: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SignupResponse {
+    pub user_id: UserId,
+    pub token: String,
+    pub state: AccountState,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangeEmailRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetAccountStateRequest {
+    pub state: AccountState,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetRegionRequest {
+    pub region: Region,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProjectionsResponse {
+    pub events: usize,
+    pub projections: Vec<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RebuildResponse {
+    pub projection: String,
+    pub events_replayed: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RegionsResponse {
+    pub local: Region,
+    pub regions: Vec<Region>,
+    pub cross_region_reads: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangeUsernameRequest {
+    pub username: String,
+}
+
+// Omitted fields are left unchanged; empty strings clear a field
+#[derive(Debug, Deserialize)]
+pub struct UpdateProfileRequest {
+    pub location: Option<String>,
+    pub website: Option<String>,
+    pub timezone: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevealAuthorRequest {
+    pub reason: String,
+    pub case_ref: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RevealAuthorResponse {
+    pub post_id: PostId,
+    pub user_id: UserId,
+    pub username: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlaceHoldRequest {
+    pub case_ref: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HoldsResponse {
+    pub holds: Vec<HoldEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TakedownRequest {
+    #[serde(default)]
+    pub jurisdictions: Vec<String>, // empty withholds the post everywhere
+    pub message: String,
+    pub case_ref: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditResponse {
+    pub entries: Vec<AuditEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModerationCasesResponse {
+    pub cases: Vec<ModerationCase>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetVerifiedRequest {
+    pub verified: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FollowUserRequest {
+    pub target_user_id: UserId,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetNotifyRequest {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetDailyLimitRequest {
+    pub posts_per_day: Option<u16>, // null removes the limit
+}
+
+#[derive(Debug, Serialize)]
+pub struct NotificationsResponse {
+    pub notifications: Vec<LocalizedNotification>,
+    pub unread_count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LocalizedNotification {
+    #[serde(flatten)]
+    pub notification: Notification,
+    pub text: String, // in the caller's language
+    pub unread: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnswerCoAuthorRequest {
+    pub accept: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RsvpRequest {
+    pub status: RsvpStatus,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MarkNotificationsReadRequest {
+    // A notification's created_at; notifications up to it are marked read
+    #[serde(default)]
+    pub through: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MarkNotificationsReadResponse {
+    pub unread_count: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ViewBeaconRequest {
+    pub post_ids: Vec<PostId>,
+    // Milliseconds each post was on screen, for the engagement log; optional
+    #[serde(default)]
+    pub dwell_ms: HashMap<PostId, u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PostAnalytics {
+    pub post_id: PostId,
+    pub timestamp: u64,
+    pub likes: u32,
+    pub replies: u32,
+    pub unique_viewers: u64, // approximate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reached_followers: Option<u32>, // while this node has the post's delivery receipt
+    pub impressions: Reach, // by the channel that served the post
+}
+
+#[derive(Debug, Serialize)]
+pub struct AnalyticsResponse {
+    pub posts: Vec<PostAnalytics>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LikePostRequest {
+    pub post_id: PostId,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MediaQuery {
+    pub expires: Option<u64>,
+    pub viewer: Option<String>,
+    pub sig: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SuccessResponse {
+    pub success: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CodedErrorResponse {
+    pub error: String,
+    pub code: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RateLimitedResponse {
+    pub error: String,
+    pub code: &'static str,
+    pub retry_after_secs: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContentTooLongResponse {
+    pub error: String,
+    pub length: usize,
+    pub max_length: usize,
+}
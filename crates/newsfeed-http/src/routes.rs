@@ -0,0 +1,1617 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use warp::{Filter, Reply};
+
+use newsfeed_core::accounts::{AccountSet, Scope};
+use newsfeed_core::articles::MAX_ARTICLE_JSON_BYTES;
+use newsfeed_core::context::{
+    Cancellation, Deadline, FeatureFlags, PeerAddr, RequestContext, Role, Tenant, ViewerContext, client_ip,
+};
+use newsfeed_core::ids::{PostId, UserId};
+use newsfeed_core::images::ProfileImageKind;
+use newsfeed_core::legal::HoldKind;
+use newsfeed_core::login_history::LoginContext;
+use newsfeed_core::{emoji, images, now_millis};
+
+
+use crate::AppState;
+use crate::access_log::REQUEST_ID_HEADER;
+use crate::auth::{
+    check_account_state, check_delegation, check_two_factor, decode_credentials, select_account, two_factor_rejection,
+};
+use crate::handlers::{
+    EventExportQuery, analytics_handler, answer_co_author_handler, audit_log_handler, authorize_decision_handler,
+    authorize_page_handler, batch_handler, block_user_handler, cache_stats_handler, change_email_handler,
+    change_username_handler, co_author_requests_handler, cpu_profile_handler, create_article_handler,
+    create_campaign_handler, create_post_handler, create_reply_handler, create_thread_handler, data_access_log_handler,
+    deactivate_handler, delete_emoji_handler, delete_post_handler, delete_saved_search_handler,
+    delivery_receipt_handler, edit_post_handler, export_events_handler, federation_inbox_handler,
+    federation_key_handler, feed_telemetry_handler, feedback_handler, follow_user_handler, get_account_handler,
+    get_accounts_handler, get_article_handler, get_context_handler, get_conversation_handler, get_feed_handler,
+    get_feed_position_handler, get_feed_v2_handler, get_notifications_handler, get_post_handler,
+    get_preferences_handler, get_profile_by_username_handler, get_profile_handler, get_profile_views_handler,
+    get_replies_handler, get_settings_handler, get_stats_handler, get_two_factor_policy_handler, get_user_posts_handler,
+    hashtag_timeline_handler, hide_posts_handler, interests_handler, json_body, lift_takedown_handler,
+    like_post_handler, link_account_handler, list_campaigns_handler, list_clients_handler, list_emojis_handler,
+    list_grants_handler, list_holds_handler, list_jobs_handler, list_moderation_cases_handler, list_projections_handler,
+    list_regions_handler, list_saved_searches_handler, login_handler, login_history_handler,
+    mark_notifications_read_handler, media_handler, memory_stats_handler, metrics_handler, password_login_handler,
+    place_hold_handler, poll_feed_handler, profile_image_handler, public_post_handler,
+    public_profile_by_username_handler, public_profile_handler, public_tag_handler, public_user_posts_handler,
+    rebuild_projection_handler, register_client_handler, related_posts_handler, release_hold_handler,
+    reload_settings_handler, resend_verification_handler, resolve_moderation_case_handler, retry_job_handler,
+    reveal_author_handler, revoke_grant_handler, rsvp_handler, runtime_stats_handler, save_search_handler,
+    search_posts_handler, search_status_handler, set_account_state_handler, set_daily_limit_handler,
+    set_feed_position_handler, set_notify_handler, set_password_handler, set_region_handler,
+    set_two_factor_policy_handler, set_verified_handler, signup_handler, sponsored_click_handler,
+    startup_report_handler, stream_feed_handler, takedown_handler, token_handler, trending_hashtags_handler,
+    two_factor_disable_handler, two_factor_enable_handler, two_factor_setup_handler, two_factor_status_handler,
+    typeahead_handler, unblock_user_handler, unfollow_user_handler, unlike_post_handler, unlink_account_handler,
+    update_preferences_handler, update_profile_handler, upload_emoji_handler, upload_profile_image_handler,
+    verify_email_handler, view_beacon_handler, withdraw_rsvp_handler,
+};
+use crate::limits::Exposure;
+use crate::rejections::{Forbidden, RateLimited, handle_rejection};
+use crate::requests::{
+    AuditQuery, AuthorizeQuery, ConversationQuery, FeedPollQuery, FeedStreamQuery, FieldsQuery, GetFeedQuery,
+    MediaQuery, ProfileQuery, ProfileViewsQuery, RelatedQuery, SearchQuery, StatsQuery, TrendingHashtagsQuery,
+    TypeaheadQuery,
+};
+use crate::versioning::{ApiVersioning, with_versioning};
+
+// The full route tree for one tenant, with its batch endpoint wired to it
+pub fn build_routes(
+    state: AppState,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = std::convert::Infallible> + Clone + Send + Sync + 'static {
+    let cache = state.cache.clone();
+    let config = state.config.clone();
+    let account_tokens = state.account_tokens.clone();
+
+    // Authentication filters: credentials decodes the token's account set,
+    // auth(scope) resolves the active account and checks its scope
+    // The header wins; ?auth_token= is for browsers, e.g. on the OAuth consent page
+    let credentials = warp::header::optional::<String>("authorization")
+        .and(
+            warp::query::<HashMap<String, String>>()
+                .map(|params: HashMap<String, String>| params.get("auth_token").cloned())
+                .or(warp::any().map(|| None))
+                .unify(),
+        )
+        .map(|header: Option<String>, query: Option<String>| header.or(query))
+        .and_then({
+            let account_tokens = account_tokens.clone();
+            move |auth: Option<String>| {
+                let result = decode_credentials(&account_tokens, auth);
+                async move { result }
+            }
+        });
+
+    // The edge sets x-viewer-country from the client's IP; it scopes takedowns
+    let auth = |scope: Scope| {
+        credentials
+            .clone()
+            .and(warp::header::optional::<String>("x-active-account"))
+            .and_then({
+                let cache = cache.clone();
+                let two_factor = state.two_factor.clone();
+                let oauth = state.oauth.clone();
+                let sandbox = state.sandbox;
+                move |accounts: AccountSet, active: Option<String>| {
+                    let cache = cache.clone();
+                    let two_factor = two_factor.clone();
+                    let oauth = oauth.clone();
+                    async move {
+                        check_delegation(&oauth, &accounts, sandbox)?;
+                        let user_id = select_account(accounts.clone(), active, scope)?;
+                        let user_id = check_two_factor(&two_factor, &accounts, user_id)?;
+                        check_account_state(&cache, user_id, scope)
+                    }
+                }
+            })
+            .and(warp::header::optional::<String>("x-viewer-country"))
+            .and(warp::header::optional::<String>(REQUEST_ID_HEADER))
+            .and(warp::ext::optional::<Deadline>())
+            .and(warp::ext::optional::<Cancellation>())
+            .map({
+                let cache = cache.clone();
+                let config = config.clone();
+                let settings = state.settings.clone();
+                let tenant = if state.sandbox { Tenant::Sandbox } else { Tenant::Production };
+                let flags = FeatureFlags::new(&config.features);
+                move |user_id: UserId,
+                      country: Option<String>,
+                      request_id: Option<String>,
+                      deadline: Option<Deadline>,
+                      cancellation: Option<Cancellation>| {
+                    if let Some(country) = country {
+                        cache.set_viewer_country(&user_id, &country);
+                    }
+                    let roles = if config.admin_user_ids.contains(&user_id) {
+                        vec![Role::Admin]
+                    } else {
+                        Vec::new()
+                    };
+                    // Batch sub-requests have no request ID of their own
+                    let request_id = request_id.unwrap_or_else(|| Uuid::new_v4().simple().to_string());
+                    let deadline = deadline.map(|deadline| deadline.0).unwrap_or_else(|| {
+                        Instant::now() + Duration::from_millis(settings.current().request_timeout_ms)
+                    });
+                    RequestContext {
+                        user_id,
+                        roles,
+                        tenant,
+                        request_id,
+                        deadline,
+                        cancel: cancellation.unwrap_or_default().0,
+                        flags: flags.clone(),
+                    }
+                }
+            })
+            // Every route embeds this; boxing keeps their futures small
+            .boxed()
+    };
+
+    // Where a login or signup came from. The edge sets x-viewer-country;
+    // apps send x-device-id.
+    let trusted_proxies = config.trusted_proxies;
+    let login_context = warp::header::optional::<String>("x-forwarded-for")
+        .and(warp::ext::optional::<PeerAddr>())
+        .and(warp::header::optional::<String>("x-viewer-country"))
+        .and(warp::header::optional::<String>("user-agent"))
+        .and(warp::header::optional::<String>("x-device-id"))
+        .map(
+            move |forwarded: Option<String>,
+                  peer: Option<PeerAddr>,
+                  country: Option<String>,
+                  user_agent: Option<String>,
+                  device_id: Option<String>| LoginContext {
+                ip: client_ip(forwarded.as_deref(), peer.unwrap_or(PeerAddr(None)), trusted_proxies)
+                    .map(|ip| ip.to_string()),
+                country,
+                user_agent,
+                device_id,
+            },
+        );
+
+    // Logged-out callers, as anonymous viewers, limited per client IP.
+    // Callers with no known IP share one bucket.
+    let public = warp::header::optional::<String>("x-forwarded-for")
+        .and(warp::ext::optional::<PeerAddr>())
+        .and(warp::header::optional::<String>("x-viewer-country"))
+        .and_then({
+            let limiter = state.public_limiter.clone();
+            move |forwarded: Option<String>, peer: Option<PeerAddr>, country: Option<String>| {
+                let client = client_ip(forwarded.as_deref(), peer.unwrap_or(PeerAddr(None)), trusted_proxies)
+                    .map(|ip| ip.to_string())
+                    .unwrap_or_default();
+                let result = limiter
+                    .check(&client, now_millis())
+                    .map(|_| ViewerContext::Anonymous {
+                        country: country.map(|country| country.trim().to_ascii_uppercase()),
+                    })
+                    .map_err(|retry_after_secs| warp::reject::custom(RateLimited { retry_after_secs }));
+                async move { result }
+            }
+        });
+
+    // Admin filter: an authenticated user listed in the admin config
+    let admin = auth(Scope::Manage)
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(|ctx: RequestContext, state: AppState| async move {
+            if !ctx.has_role(Role::Admin) {
+                return Err(warp::reject::custom(Forbidden {
+                    code: "admin_required",
+                    message: "Admin access required",
+                }));
+            }
+            // Admins who have enrolled already had their token checked by auth
+            if state.two_factor.required_for_admins()
+                && !state.two_factor.is_enabled(&ctx.user_id).map_err(two_factor_rejection)?
+            {
+                return Err(warp::reject::custom(Forbidden {
+                    code: "two_factor_enrollment_required",
+                    message: "Admins must turn on two-factor authentication",
+                }));
+            }
+            Ok(ctx)
+        });
+
+    // Routes
+    let get_accounts = warp::get()
+        .and(warp::path!("v1" / "me" / "accounts"))
+        .and(credentials.clone())
+        .and_then(get_accounts_handler);
+
+    let link_account = warp::post()
+        .and(warp::path!("v1" / "me" / "accounts"))
+        .and(credentials.clone())
+        .and(warp::header::optional::<String>("x-active-account"))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(link_account_handler);
+
+    let unlink_account = warp::delete()
+        .and(warp::path!("v1" / "me" / "accounts" / UserId))
+        .and(credentials.clone())
+        .and(warp::header::optional::<String>("x-active-account"))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(unlink_account_handler);
+
+    let create_post = warp::post()
+        .and(warp::path!("v1" / "me" / "feed"))
+        .and(auth(Scope::Post))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(create_post_handler);
+
+    let create_article = warp::post()
+        .and(warp::path!("v1" / "articles"))
+        .and(auth(Scope::Post))
+        .and(json_body(config.max_json_body_bytes.max(MAX_ARTICLE_JSON_BYTES)))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(create_article_handler);
+
+    let get_article = warp::get()
+        .and(warp::path!("v1" / "articles" / PostId))
+        .and(auth(Scope::Read))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(get_article_handler);
+
+    let create_thread = warp::post()
+        .and(warp::path!("v1" / "me" / "threads"))
+        .and(auth(Scope::Post))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(create_thread_handler);
+
+    let get_feed = warp::get()
+        .and(warp::path!("v1" / "me" / "feed"))
+        .and(auth(Scope::Read))
+        .and(warp::query::<GetFeedQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(get_feed_handler);
+
+    let get_feed_v2 = warp::get()
+        .and(warp::path!("v2" / "me" / "feed"))
+        .and(auth(Scope::Read))
+        .and(warp::query::<GetFeedQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(get_feed_v2_handler);
+
+    let poll_feed = warp::get()
+        .and(warp::path!("v1" / "me" / "feed" / "poll"))
+        .and(auth(Scope::Read))
+        .and(warp::query::<FeedPollQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(poll_feed_handler);
+
+    let stream_feed = warp::get()
+        .and(warp::path!("v1" / "me" / "feed" / "stream"))
+        .and(auth(Scope::Read))
+        .and(warp::ws())
+        .and(warp::query::<FeedStreamQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(stream_feed_handler);
+
+    let hide_posts = warp::post()
+        .and(warp::path!("v1" / "me" / "feed" / "hide"))
+        .and(auth(Scope::Engage))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(hide_posts_handler);
+
+    let feed_telemetry = warp::post()
+        .and(warp::path!("v1" / "me" / "feed" / "telemetry"))
+        .and(auth(Scope::Read))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(feed_telemetry_handler);
+
+    let feedback = warp::post()
+        .and(warp::path!("v1" / "me" / "feed" / "feedback"))
+        .and(auth(Scope::Engage))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(feedback_handler);
+
+    let create_campaign = warp::post()
+        .and(warp::path!("v1" / "admin" / "campaigns"))
+        .and(admin.clone())
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(create_campaign_handler);
+
+    let list_campaigns = warp::get()
+        .and(warp::path!("v1" / "admin" / "campaigns"))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(list_campaigns_handler);
+
+    let sponsored_click = warp::post()
+        .and(warp::path!("v1" / "sponsored" / String / "click"))
+        .and(auth(Scope::Read))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(sponsored_click_handler);
+
+    // Sub-requests authenticate on their own with the forwarded credentials
+    let batch = warp::post()
+        .and(warp::path!("v1" / "batch"))
+        .and(auth(Scope::Read))
+        .and(warp::header::headers_cloned())
+        .and(warp::ext::optional::<Exposure>())
+        .and(warp::ext::optional::<PeerAddr>())
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(batch_handler);
+
+    let runtime_stats = warp::get()
+        .and(warp::path!("v1" / "admin" / "debug" / "runtime"))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(runtime_stats_handler);
+
+    let interests = warp::get()
+        .and(warp::path!("v1" / "admin" / "debug" / "interests" / UserId))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(interests_handler);
+
+    let cache_stats = warp::get()
+        .and(warp::path!("v1" / "admin" / "debug" / "caches"))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(cache_stats_handler);
+
+    let cpu_profile = warp::get()
+        .and(warp::path!("v1" / "admin" / "debug" / "profile"))
+        .and(admin.clone())
+        .and(warp::query::<ProfileQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(cpu_profile_handler);
+
+    let memory_stats = warp::get()
+        .and(warp::path!("v1" / "admin" / "debug" / "memory"))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(memory_stats_handler);
+
+    let metrics = warp::get()
+        .and(warp::path!("metrics"))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(metrics_handler);
+
+    let get_feed_position = warp::get()
+        .and(warp::path!("v1" / "me" / "feed" / "position"))
+        .and(auth(Scope::Read))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(get_feed_position_handler);
+
+    let set_feed_position = warp::put()
+        .and(warp::path!("v1" / "me" / "feed" / "position"))
+        .and(auth(Scope::Read))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(set_feed_position_handler);
+
+    let create_reply = warp::post()
+        .and(warp::path!("v1" / "posts" / PostId / "replies"))
+        .and(auth(Scope::Post))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(create_reply_handler);
+
+    let get_replies = warp::get()
+        .and(warp::path!("v1" / "posts" / PostId / "replies"))
+        .and(auth(Scope::Read))
+        .and(warp::query::<ConversationQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(get_replies_handler);
+
+    let get_post = warp::get()
+        .and(warp::path!("v1" / "posts" / PostId))
+        .and(auth(Scope::Read))
+        .and(warp::query::<FieldsQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(get_post_handler);
+
+    let edit_post = warp::patch()
+        .and(warp::path!("v1" / "posts" / PostId))
+        .and(auth(Scope::Post))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(edit_post_handler);
+
+    let delete_post = warp::delete()
+        .and(warp::path!("v1" / "posts" / PostId))
+        .and(auth(Scope::Post))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(delete_post_handler);
+
+    let get_conversation = warp::get()
+        .and(warp::path!("v1" / "posts" / PostId / "conversation"))
+        .and(auth(Scope::Read))
+        .and(warp::query::<ConversationQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(get_conversation_handler);
+
+    let related_posts = warp::get()
+        .and(warp::path!("v1" / "posts" / PostId / "related"))
+        .and(auth(Scope::Read))
+        .and(warp::query::<RelatedQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(related_posts_handler);
+
+    let follow_user = warp::post()
+        .and(warp::path!("v1" / "users" / "follow"))
+        .and(auth(Scope::Engage))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(follow_user_handler);
+
+    let unfollow_user = warp::post()
+        .and(warp::path!("v1" / "users" / "unfollow"))
+        .and(auth(Scope::Engage))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(unfollow_user_handler);
+
+    let block_user = warp::put()
+        .and(warp::path!("v1" / "users" / UserId / "block"))
+        .and(auth(Scope::Engage))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(block_user_handler);
+
+    let unblock_user = warp::delete()
+        .and(warp::path!("v1" / "users" / UserId / "block"))
+        .and(auth(Scope::Engage))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(unblock_user_handler);
+
+    let set_notify = warp::put()
+        .and(warp::path!("v1" / "users" / UserId / "notify"))
+        .and(auth(Scope::Engage))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(set_notify_handler);
+
+    let set_daily_limit = warp::put()
+        .and(warp::path!("v1" / "users" / UserId / "daily-limit"))
+        .and(auth(Scope::Engage))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(set_daily_limit_handler);
+
+    let get_user_posts = warp::get()
+        .and(warp::path!("v1" / "users" / UserId / "posts"))
+        .and(auth(Scope::Read))
+        .and(warp::query::<ConversationQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(get_user_posts_handler);
+
+    let search_posts = warp::get()
+        .and(warp::path!("v1" / "search" / "posts"))
+        .and(auth(Scope::Read))
+        .and(warp::query::<SearchQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(search_posts_handler);
+
+    let list_saved_searches = warp::get()
+        .and(warp::path!("v1" / "me" / "saved_searches"))
+        .and(auth(Scope::Read))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(list_saved_searches_handler);
+
+    let save_search = warp::post()
+        .and(warp::path!("v1" / "me" / "saved_searches"))
+        .and(auth(Scope::Manage))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(save_search_handler);
+
+    let delete_saved_search = warp::delete()
+        .and(warp::path!("v1" / "me" / "saved_searches" / String))
+        .and(auth(Scope::Manage))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(delete_saved_search_handler);
+
+    let hashtag_timeline = warp::get()
+        .and(warp::path!("v1" / "tags" / String / "posts"))
+        .and(auth(Scope::Read))
+        .and(warp::query::<ConversationQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(hashtag_timeline_handler);
+
+    let trending_hashtags = warp::get()
+        .and(warp::path!("v1" / "tags" / "trending"))
+        .and(auth(Scope::Read))
+        .and(warp::query::<TrendingHashtagsQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(trending_hashtags_handler);
+
+    let typeahead = warp::get()
+        .and(warp::path!("v1" / "typeahead"))
+        .and(auth(Scope::Read))
+        .and(warp::query::<TypeaheadQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(typeahead_handler);
+
+    let get_stats = warp::get()
+        .and(warp::path!("v1" / "me" / "stats"))
+        .and(auth(Scope::Read))
+        .and(warp::query::<StatsQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(get_stats_handler);
+
+    let get_profile_views = warp::get()
+        .and(warp::path!("v1" / "me" / "profile" / "views"))
+        .and(auth(Scope::Read))
+        .and(warp::query::<ProfileViewsQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(get_profile_views_handler);
+
+    let get_notifications = warp::get()
+        .and(warp::path!("v1" / "me" / "notifications"))
+        .and(auth(Scope::Read))
+        .and(warp::header::optional::<String>("accept-language"))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(get_notifications_handler);
+
+    // Only changes what the reader has seen, so reading is enough
+    let mark_notifications_read = warp::post()
+        .and(warp::path!("v1" / "me" / "notifications" / "read"))
+        .and(auth(Scope::Read))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(mark_notifications_read_handler);
+
+    let co_author_requests = warp::get()
+        .and(warp::path!("v1" / "me" / "co_author_requests"))
+        .and(auth(Scope::Read))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(co_author_requests_handler);
+
+    let answer_co_author = warp::post()
+        .and(warp::path!("v1" / "posts" / PostId / "co_author"))
+        .and(auth(Scope::Post))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(answer_co_author_handler);
+
+    let rsvp = warp::put()
+        .and(warp::path!("v1" / "posts" / PostId / "rsvp"))
+        .and(auth(Scope::Engage))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(rsvp_handler);
+
+    let withdraw_rsvp = warp::delete()
+        .and(warp::path!("v1" / "posts" / PostId / "rsvp"))
+        .and(auth(Scope::Engage))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(withdraw_rsvp_handler);
+
+    let like_post = warp::post()
+        .and(warp::path!("v1" / "posts" / "like"))
+        .and(auth(Scope::Engage))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(like_post_handler);
+
+    let unlike_post = warp::post()
+        .and(warp::path!("v1" / "posts" / "unlike"))
+        .and(auth(Scope::Engage))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(unlike_post_handler);
+
+    let view_beacon = warp::post()
+        .and(warp::path!("v1" / "posts" / "views"))
+        .and(auth(Scope::Read))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(view_beacon_handler);
+
+    let analytics = warp::get()
+        .and(warp::path!("v1" / "me" / "analytics"))
+        .and(auth(Scope::Read))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(analytics_handler);
+
+    let get_profile = warp::get()
+        .and(warp::path!("v1" / "users" / UserId))
+        .and(auth(Scope::Read))
+        .and(warp::query::<FieldsQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(get_profile_handler);
+
+    let get_profile_by_username = warp::get()
+        .and(warp::path!("v1" / "users" / "by-username" / String))
+        .and(auth(Scope::Read))
+        .and(warp::query::<FieldsQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(get_profile_by_username_handler);
+
+    let public_profile = warp::get()
+        .and(warp::path!("v1" / "public" / "users" / UserId))
+        .and(public.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(public_profile_handler);
+
+    let public_profile_by_username = warp::get()
+        .and(warp::path!("v1" / "public" / "users" / "by-username" / String))
+        .and(public.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(public_profile_by_username_handler);
+
+    let public_user_posts = warp::get()
+        .and(warp::path!("v1" / "public" / "users" / UserId / "posts"))
+        .and(public.clone())
+        .and(warp::query::<ConversationQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(public_user_posts_handler);
+
+    let public_post = warp::get()
+        .and(warp::path!("v1" / "public" / "posts" / PostId))
+        .and(public.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(public_post_handler);
+
+    let public_tag = warp::get()
+        .and(warp::path!("v1" / "public" / "tags" / String))
+        .and(public.clone())
+        .and(warp::query::<ConversationQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(public_tag_handler);
+
+    let signup = warp::post()
+        .and(warp::path!("v1" / "accounts"))
+        .and(login_context)
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(signup_handler);
+
+    // The same signup, kept next to /v1/auth/login
+    let register = warp::post()
+        .and(warp::path!("v1" / "auth" / "register"))
+        .and(login_context)
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(signup_handler);
+
+    let password_login = warp::post()
+        .and(warp::path!("v1" / "auth" / "login"))
+        .and(login_context)
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(password_login_handler);
+
+    let set_password = warp::put()
+        .and(warp::path!("v1" / "me" / "password"))
+        .and(auth(Scope::Manage))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(set_password_handler);
+
+    let verify_email = warp::post()
+        .and(warp::path!("v1" / "accounts" / "verify-email"))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(verify_email_handler);
+
+    let get_account = warp::get()
+        .and(warp::path!("v1" / "me" / "account"))
+        .and(auth(Scope::Read))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(get_account_handler);
+
+    let get_context = warp::get()
+        .and(warp::path!("v1" / "me" / "context"))
+        .and(auth(Scope::Read))
+        .and_then(get_context_handler);
+
+    let change_email = warp::put()
+        .and(warp::path!("v1" / "me" / "email"))
+        .and(auth(Scope::Manage))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(change_email_handler);
+
+    let resend_verification = warp::post()
+        .and(warp::path!("v1" / "me" / "email" / "verification"))
+        .and(auth(Scope::Manage))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(resend_verification_handler);
+
+    let deactivate = warp::post()
+        .and(warp::path!("v1" / "me" / "deactivate"))
+        .and(auth(Scope::Manage))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(deactivate_handler);
+
+    let set_account_state = warp::put()
+        .and(warp::path!("v1" / "admin" / "users" / UserId / "state"))
+        .and(admin.clone())
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(set_account_state_handler);
+
+    let two_factor_status = warp::get()
+        .and(warp::path!("v1" / "me" / "2fa"))
+        .and(auth(Scope::Manage))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(two_factor_status_handler);
+
+    let two_factor_setup = warp::post()
+        .and(warp::path!("v1" / "me" / "2fa" / "setup"))
+        .and(auth(Scope::Manage))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(two_factor_setup_handler);
+
+    let two_factor_enable = warp::post()
+        .and(warp::path!("v1" / "me" / "2fa" / "enable"))
+        .and(credentials.clone())
+        .and(warp::header::optional::<String>("x-active-account"))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(two_factor_enable_handler);
+
+    let two_factor_disable = warp::delete()
+        .and(warp::path!("v1" / "me" / "2fa"))
+        .and(auth(Scope::Manage))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(two_factor_disable_handler);
+
+    let login = warp::post()
+        .and(warp::path!("v1" / "login"))
+        .and(credentials.clone())
+        .and(warp::header::optional::<String>("x-active-account"))
+        .and(login_context)
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(login_handler);
+
+    let federation_key = warp::get()
+        .and(warp::path!("v1" / "federation" / "key"))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(federation_key_handler);
+
+    let federation_inbox = warp::post()
+        .and(warp::path!("v1" / "federation" / "inbox"))
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::content_length_limit(config.max_json_body_bytes))
+        .and(warp::body::bytes())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(federation_inbox_handler);
+
+    let register_client = warp::post()
+        .and(warp::path!("v1" / "oauth" / "clients"))
+        .and(auth(Scope::Manage))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(register_client_handler);
+
+    let list_clients = warp::get()
+        .and(warp::path!("v1" / "oauth" / "clients"))
+        .and(auth(Scope::Manage))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(list_clients_handler);
+
+    // Browsers reach the consent page with ?auth_token=; the form posts back
+    // to the same URL
+    let authorize_page = warp::get()
+        .and(warp::path!("oauth" / "authorize"))
+        .and(auth(Scope::Manage))
+        .and(warp::query::<AuthorizeQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(authorize_page_handler);
+
+    let authorize_decision = warp::post()
+        .and(warp::path!("oauth" / "authorize"))
+        .and(auth(Scope::Manage))
+        .and(warp::query::<AuthorizeQuery>())
+        .and(warp::body::content_length_limit(config.max_json_body_bytes))
+        .and(warp::body::form())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(authorize_decision_handler);
+
+    let oauth_token = warp::post()
+        .and(warp::path!("oauth" / "token"))
+        .and(warp::body::content_length_limit(config.max_json_body_bytes))
+        .and(warp::body::form())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(token_handler);
+
+    let list_grants = warp::get()
+        .and(warp::path!("v1" / "me" / "oauth" / "grants"))
+        .and(auth(Scope::Manage))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(list_grants_handler);
+
+    let revoke_grant = warp::delete()
+        .and(warp::path!("v1" / "me" / "oauth" / "grants" / String))
+        .and(auth(Scope::Manage))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(revoke_grant_handler);
+
+    let login_history = warp::get()
+        .and(warp::path!("v1" / "me" / "security" / "logins"))
+        .and(auth(Scope::Manage))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(login_history_handler);
+
+    let data_access_log = warp::get()
+        .and(warp::path!("v1" / "me" / "privacy" / "access_log"))
+        .and(auth(Scope::Manage))
+        .and(warp::query::<AuditQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(data_access_log_handler);
+
+    let get_two_factor_policy = warp::get()
+        .and(warp::path!("v1" / "admin" / "2fa-policy"))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(get_two_factor_policy_handler);
+
+    let set_two_factor_policy = warp::put()
+        .and(warp::path!("v1" / "admin" / "2fa-policy"))
+        .and(admin.clone())
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(set_two_factor_policy_handler);
+
+    let change_username = warp::put()
+        .and(warp::path!("v1" / "me" / "username"))
+        .and(auth(Scope::Manage))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(change_username_handler);
+
+    let update_profile = warp::patch()
+        .and(warp::path!("v1" / "me" / "profile"))
+        .and(auth(Scope::Manage))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(update_profile_handler);
+
+    let upload_avatar = warp::put()
+        .and(warp::path!("v1" / "me" / "avatar"))
+        .map(|| ProfileImageKind::Avatar)
+        .and(auth(Scope::Manage))
+        .and(warp::body::content_length_limit(images::MAX_UPLOAD_BYTES))
+        .and(warp::body::bytes())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(upload_profile_image_handler);
+
+    let upload_banner = warp::put()
+        .and(warp::path!("v1" / "me" / "banner"))
+        .map(|| ProfileImageKind::Banner)
+        .and(auth(Scope::Manage))
+        .and(warp::body::content_length_limit(images::MAX_UPLOAD_BYTES))
+        .and(warp::body::bytes())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(upload_profile_image_handler);
+
+    // Processed avatar and banner variants, read from the account's region
+    let profile_images = warp::get()
+        .and(warp::path!("profiles" / UserId / String))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(profile_image_handler);
+
+    let set_verified = warp::put()
+        .and(warp::path!("v1" / "admin" / "users" / UserId / "verified"))
+        .and(admin.clone())
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(set_verified_handler);
+
+    let search_status = warp::get()
+        .and(warp::path!("v1" / "admin" / "search"))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(search_status_handler);
+
+    let list_projections = warp::get()
+        .and(warp::path!("v1" / "admin" / "projections"))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(list_projections_handler);
+
+    let export_events = warp::get()
+        .and(warp::path!("v1" / "admin" / "events"))
+        .and(admin.clone())
+        .and(warp::query::<EventExportQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(export_events_handler);
+
+    let rebuild_projection = warp::post()
+        .and(warp::path!("v1" / "admin" / "projections" / String / "rebuild"))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(rebuild_projection_handler);
+
+    let list_jobs = warp::get()
+        .and(warp::path!("v1" / "admin" / "jobs"))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(list_jobs_handler);
+
+    let retry_job = warp::post()
+        .and(warp::path!("v1" / "admin" / "jobs" / u64 / "retry"))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(retry_job_handler);
+
+    let get_settings = warp::get()
+        .and(warp::path!("v1" / "admin" / "config"))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(get_settings_handler);
+
+    let reload_settings = warp::post()
+        .and(warp::path!("v1" / "admin" / "config" / "reload"))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(reload_settings_handler);
+
+    let delivery_receipt = warp::get()
+        .and(warp::path!("v1" / "admin" / "posts" / PostId / "delivery"))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(delivery_receipt_handler);
+
+    let reveal_author = warp::post()
+        .and(warp::path!("v1" / "admin" / "posts" / PostId / "reveal"))
+        .and(admin.clone())
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(reveal_author_handler);
+
+    let startup_report = warp::get()
+        .and(warp::path!("v1" / "admin" / "startup"))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(startup_report_handler);
+
+    let list_regions = warp::get()
+        .and(warp::path!("v1" / "admin" / "regions"))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(list_regions_handler);
+
+    let set_region = warp::put()
+        .and(warp::path!("v1" / "admin" / "users" / UserId / "region"))
+        .and(admin.clone())
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(set_region_handler);
+
+    let list_moderation_cases = warp::get()
+        .and(warp::path!("v1" / "admin" / "moderation"))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(list_moderation_cases_handler);
+
+    let resolve_moderation_case = warp::post()
+        .and(warp::path!("v1" / "admin" / "moderation" / UserId / "resolve"))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(resolve_moderation_case_handler);
+
+    let place_hold = warp::put()
+        .and(warp::path!("v1" / "admin" / "legal-holds" / HoldKind / String))
+        .and(admin.clone())
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(place_hold_handler);
+
+    let release_hold = warp::delete()
+        .and(warp::path!("v1" / "admin" / "legal-holds" / HoldKind / String))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(release_hold_handler);
+
+    let list_holds = warp::get()
+        .and(warp::path!("v1" / "admin" / "legal-holds"))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(list_holds_handler);
+
+    let takedown = warp::put()
+        .and(warp::path!("v1" / "admin" / "takedowns" / PostId))
+        .and(admin.clone())
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(takedown_handler);
+
+    let lift_takedown = warp::delete()
+        .and(warp::path!("v1" / "admin" / "takedowns" / PostId))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(lift_takedown_handler);
+
+    let audit_log = warp::get()
+        .and(warp::path!("v1" / "admin" / "audit"))
+        .and(admin.clone())
+        .and(warp::query::<AuditQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(audit_log_handler);
+
+    let get_preferences = warp::get()
+        .and(warp::path!("v1" / "me" / "preferences"))
+        .and(auth(Scope::Read))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(get_preferences_handler);
+
+    let update_preferences = warp::put()
+        .and(warp::path!("v1" / "me" / "preferences"))
+        .and(auth(Scope::Manage))
+        .and(json_body(config.max_json_body_bytes))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(update_preferences_handler);
+
+    let list_emojis = warp::get()
+        .and(warp::path!("v1" / "emojis"))
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(list_emojis_handler);
+
+    let upload_emoji = warp::put()
+        .and(warp::path!("v1" / "admin" / "emojis" / String))
+        .and(admin.clone())
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::body::content_length_limit(emoji::MAX_EMOJI_BYTES))
+        .and(warp::body::bytes())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(upload_emoji_handler);
+
+    let delete_emoji = warp::delete()
+        .and(warp::path!("v1" / "admin" / "emojis" / String))
+        .and(admin.clone())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(delete_emoji_handler);
+
+    // Custom emoji images are public
+    let emoji_files = warp::get()
+        .and(warp::path("emoji"))
+        .and(warp::fs::dir(config.media_dir.join("emoji")));
+
+    // Transcoded video output (HLS playlists, segments, posters), signed URLs only
+    let media = warp::get()
+        .and(warp::path("media"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::tail())
+        .and(warp::query::<MediaQuery>())
+        .and(warp::any().map({
+            let state = state.clone();
+            move || state.clone()
+        }))
+        .and_then(media_handler);
+
+    let routes = create_post
+        .or(create_thread)
+        .or(create_article)
+        .or(get_article)
+        .or(get_feed)
+        .or(get_feed_v2)
+        .or(poll_feed)
+        .or(stream_feed)
+        .or(hide_posts)
+        .or(feedback)
+        .or(feed_telemetry)
+        .or(create_campaign)
+        .or(list_campaigns)
+        .or(sponsored_click)
+        .or(batch)
+        .or(runtime_stats)
+        .or(cache_stats)
+        .or(cpu_profile)
+        .or(memory_stats)
+        .or(interests)
+        .or(metrics)
+        .or(get_feed_position)
+        .or(set_feed_position)
+        .or(create_reply)
+        .or(get_replies)
+        .or(get_post)
+        .or(edit_post)
+        .or(delete_post)
+        .or(get_conversation)
+        .or(related_posts)
+        // Boxing every so often too keeps the nested route future small
+        // enough for a worker thread's stack in debug builds. Collapsing to
+        // a plain response keeps the nested reply type under the recursion
+        // limit.
+        .map(Reply::into_response)
+        .boxed()
+        .or(follow_user)
+        .or(unfollow_user)
+        .or(block_user)
+        .or(unblock_user)
+        .or(set_notify)
+        .or(set_daily_limit)
+        .or(get_user_posts)
+        .or(search_posts)
+        .or(list_saved_searches)
+        .or(save_search)
+        .or(delete_saved_search)
+        .or(typeahead)
+        .or(hashtag_timeline)
+        .or(trending_hashtags)
+        .or(get_notifications)
+        .or(mark_notifications_read)
+        .or(co_author_requests)
+        .or(answer_co_author)
+        .or(rsvp)
+        .or(withdraw_rsvp)
+        .or(get_stats)
+        .or(get_profile_views)
+        .or(like_post)
+        .or(unlike_post)
+        .or(view_beacon)
+        .or(analytics)
+        .or(get_accounts)
+        .or(link_account)
+        .or(unlink_account)
+        .or(get_profile)
+        .or(get_profile_by_username)
+        .or(public_profile)
+        .or(public_profile_by_username)
+        .or(public_user_posts)
+        .or(public_post)
+        .or(public_tag)
+        .boxed()
+        .or(signup)
+        .or(register)
+        .or(password_login)
+        .or(set_password)
+        .or(verify_email)
+        .or(get_account)
+        .or(get_context)
+        .or(change_email)
+        .or(resend_verification)
+        .or(deactivate)
+        .or(set_account_state)
+        .or(two_factor_status)
+        .or(two_factor_setup)
+        .or(two_factor_enable)
+        .or(two_factor_disable)
+        .or(login)
+        .or(login_history)
+        .or(data_access_log)
+        .boxed()
+        .or(register_client)
+        .or(list_clients)
+        .or(authorize_page)
+        .or(authorize_decision)
+        .or(oauth_token)
+        .or(list_grants)
+        .or(revoke_grant)
+        .or(federation_key)
+        .or(federation_inbox)
+        .or(get_two_factor_policy)
+        .or(set_two_factor_policy)
+        .or(change_username)
+        .or(update_profile)
+        .or(upload_avatar)
+        .or(upload_banner)
+        .or(profile_images)
+        .or(set_verified)
+        .boxed()
+        .or(list_moderation_cases)
+        .or(resolve_moderation_case)
+        .or(place_hold)
+        .or(release_hold)
+        .or(list_holds)
+        .or(takedown)
+        .or(lift_takedown)
+        .or(audit_log)
+        .or(list_regions)
+        .or(set_region)
+        .or(search_status)
+        .or(list_projections)
+        .or(rebuild_projection)
+        .or(export_events)
+        .boxed()
+        .or(list_jobs)
+        .or(retry_job)
+        .or(delivery_receipt)
+        .or(reveal_author)
+        .or(startup_report)
+        .or(get_settings)
+        .or(reload_settings)
+        .or(get_preferences)
+        .or(update_preferences)
+        .or(list_emojis)
+        .or(upload_emoji)
+        .or(delete_emoji)
+        .or(emoji_files)
+        .or(media)
+        // Boxing keeps the composed route type (and compile times) manageable
+        .boxed()
+        .recover(handle_rejection);
+    let routes = with_versioning(routes, Arc::new(ApiVersioning::new(&config)));
+    state.batch.install(routes.clone().boxed());
+    routes
+}
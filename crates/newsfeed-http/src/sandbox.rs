@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use warp::reply::Response;
+use warp::Filter;
+
+use newsfeed_core::accounts::AccountTokens;
+use newsfeed_core::oauth::OAuthProvider;
+
+// Whether a request carries a token issued to a sandbox app. Read the same
+// way the auth filters read it: the header wins over ?auth_token=.
+fn sandbox_token(
+    tokens: Arc<AccountTokens>,
+    oauth: Arc<OAuthProvider>,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(
+            warp::query::<HashMap<String, String>>()
+                .map(|params: HashMap<String, String>| params.get("auth_token").cloned())
+                .or(warp::any().map(|| None))
+                .unify(),
+        )
+        .and_then(move |header: Option<String>, query: Option<String>| {
+            let sandbox = header
+                .or(query)
+                .and_then(|token| tokens.decode(&token).ok())
+                .and_then(|accounts| accounts.delegation)
+                .is_some_and(|delegation| oauth.is_sandbox(&delegation.client_id));
+            async move {
+                if sandbox {
+                    Ok(())
+                } else {
+                    Err(warp::reject())
+                }
+            }
+        })
+        .untuple_one()
+}
+
+// Sends requests made with a sandbox app's token to the sandbox tenant's
+// routes; everything else, including unauthenticated requests, reaches
+// production
+pub fn with_sandbox<P, S>(
+    production: P,
+    sandbox: S,
+    tokens: Arc<AccountTokens>,
+    oauth: Arc<OAuthProvider>,
+) -> impl Filter<Extract = (Response,), Error = Infallible> + Clone
+where
+    P: Filter<Extract = (Response,), Error = Infallible> + Clone + Send + Sync + 'static,
+    S: Filter<Extract = (Response,), Error = Infallible> + Clone + Send + Sync + 'static,
+{
+    sandbox_token(tokens, oauth).and(sandbox).or(production).unify()
+}
@@ -0,0 +1,153 @@
+use serde::Serialize;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use warp::http::header::{HeaderMap, HeaderValue};
+use warp::http::{Method, StatusCode};
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+use newsfeed_core::config::Config;
+
+use crate::access_log::route_template;
+
+const VERSION_HEADER: &str = "api-version";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiVersion {
+    V1,
+    V2,
+}
+
+impl ApiVersion {
+    pub const SUPPORTED: [ApiVersion; 2] = [ApiVersion::V1, ApiVersion::V2];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "1",
+            ApiVersion::V2 => "2",
+        }
+    }
+
+    fn parse(segment: &str) -> Option<Self> {
+        Self::SUPPORTED
+            .into_iter()
+            .find(|version| segment.strip_prefix('v') == Some(version.as_str()))
+    }
+}
+
+// What the first path segment says about the API version
+enum Requested {
+    Unversioned, // media, emoji files, /metrics
+    Supported(ApiVersion),
+    Unsupported,
+}
+
+fn requested_version(path: &str) -> Requested {
+    let segment = path.trim_start_matches('/').split('/').next().unwrap_or("");
+    let is_version = segment.len() > 1
+        && segment.starts_with('v')
+        && segment[1..].bytes().all(|byte| byte.is_ascii_digit());
+    if !is_version {
+        return Requested::Unversioned;
+    }
+    ApiVersion::parse(segment).map_or(Requested::Unsupported, Requested::Supported)
+}
+
+// Routes superseded by a newer version: "METHOD /route/template", successor
+// path, and when the successor shipped (unix seconds)
+const DEPRECATED_ROUTES: &[(&str, &str, u64)] = &[("GET /v1/me/feed", "/v2/me/feed", 1_792_108_800)];
+
+#[derive(Debug, Serialize)]
+struct UnsupportedVersionResponse {
+    error: String,
+    code: &'static str,
+    supported: Vec<&'static str>,
+}
+
+// Envelope used by v2 responses
+#[derive(Debug, Serialize)]
+pub struct Envelope<T: Serialize, M: Serialize> {
+    pub data: T,
+    pub meta: M,
+}
+
+// Version headers on every versioned response, deprecation headers on old routes
+pub struct ApiVersioning {
+    sunset: Option<u64>,
+}
+
+impl ApiVersioning {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            sunset: config.api_v1_sunset,
+        }
+    }
+
+    fn annotate(&self, method: &Method, path: &str, mut response: Response) -> Response {
+        let Requested::Supported(version) = requested_version(path) else {
+            return response;
+        };
+        let headers = response.headers_mut();
+        headers.insert(VERSION_HEADER, HeaderValue::from_static(version.as_str()));
+
+        let key = format!("{} {}", method, route_template(path));
+        if let Some((_, successor, deprecated_at)) = DEPRECATED_ROUTES.iter().find(|(route, _, _)| *route == key) {
+            insert(headers, "deprecation", format!("@{}", deprecated_at));
+            insert(headers, "link", format!("<{}>; rel=\"successor-version\"", successor));
+            if let Some(sunset) = self.sunset {
+                let date = httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(sunset));
+                insert(headers, "sunset", date);
+            }
+        }
+        response
+    }
+}
+
+fn insert(headers: &mut HeaderMap, name: &'static str, value: String) {
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        headers.insert(name, value);
+    }
+}
+
+// Answers requests for unknown versions before routing, and annotates the rest
+pub fn with_versioning<F, R>(
+    filter: F,
+    versioning: Arc<ApiVersioning>,
+) -> impl Filter<Extract = (Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    let unsupported = warp::path::full()
+        .and_then(|path: FullPath| async move {
+            match requested_version(path.as_str()) {
+                Requested::Unsupported => Ok(()),
+                _ => Err(warp::reject::not_found()),
+            }
+        })
+        .map(|()| {
+            warp::reply::with_status(
+                warp::reply::json(&UnsupportedVersionResponse {
+                    error: "Unsupported API version".to_string(),
+                    code: "unsupported_version",
+                    supported: ApiVersion::SUPPORTED
+                        .iter()
+                        .map(|version| version.as_str())
+                        .collect(),
+                }),
+                StatusCode::NOT_FOUND,
+            )
+            .into_response()
+        });
+
+    let routed = warp::method()
+        .and(warp::path::full())
+        .and(filter)
+        .map(move |method: Method, path: FullPath, reply: R| {
+            versioning.annotate(&method, path.as_str(), reply.into_response())
+        });
+
+    unsupported.or(routed).unify()
+}
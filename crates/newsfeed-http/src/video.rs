@@ -0,0 +1,406 @@
+// Video transcoding: sources are downloaded from public hosts only, then
+// packaged by ffmpeg into HLS renditions in the author's regional backend
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::{mpsc, Semaphore};
+use tokio_metrics::TaskMonitor;
+
+use newsfeed_core::cache::Cache;
+use newsfeed_core::config::Config;
+use newsfeed_core::ids::PostId;
+use newsfeed_core::media::{MASTER_PLAYLIST, POSTER, VideoStatus};
+use newsfeed_core::residency::{Region, StorageRouter};
+use newsfeed_core::{info, warn};
+use newsfeed_store_memory::cache::CacheLayer;
+
+struct Rendition {
+    name: &'static str,
+    height: u32,
+    video_bitrate: &'static str,
+    bandwidth: u32,
+}
+
+// HLS ladder produced for every uploaded video
+const RENDITIONS: [Rendition; 3] = [
+    Rendition { name: "360p", height: 360, video_bitrate: "800k", bandwidth: 928_000 },
+    Rendition { name: "720p", height: 720, video_bitrate: "2800k", bandwidth: 2_928_000 },
+    Rendition { name: "1080p", height: 1080, video_bitrate: "5000k", bandwidth: 5_192_000 },
+];
+
+// Video URLs a post may carry: public https addresses, not internal hosts.
+// Hostnames are resolved again before each fetch (see public_source).
+pub fn valid_source_url(url: &str) -> bool {
+    if url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return false;
+    }
+    let Some((host, _)) = source_host(url) else {
+        return false;
+    };
+    if host == "localhost" || host.ends_with(".localhost") {
+        return false;
+    }
+    match host.parse::<IpAddr>() {
+        Ok(ip) => is_public_ip(ip),
+        Err(_) => valid_hostname(&host),
+    }
+}
+
+// The host, lowercased, and port of an https URL without userinfo
+fn source_host(url: &str) -> Option<(String, u16)> {
+    let rest = url.strip_prefix("https://")?;
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    if authority.contains('@') {
+        return None;
+    }
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(bracketed) => {
+            let (host, after) = bracketed.split_once(']')?;
+            // Only IPv6 addresses go in brackets
+            host.parse::<std::net::Ipv6Addr>().ok()?;
+            (host, after.strip_prefix(':'))
+        }
+        None => match authority.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    let port = match port {
+        Some(port) => port.parse().ok()?,
+        None => 443,
+    };
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    (!host.is_empty()).then_some((host, port))
+}
+
+// Letters, digits, and hyphens in dot-separated labels, with a top-level
+// label that isn't a number. The resolver reads hosts like "127.1",
+// "2130706433", or "0x7f.0.0.1" as addresses, so only the canonical dotted
+// quad, checked as an IP, may be numeric.
+fn valid_hostname(host: &str) -> bool {
+    let labels: Vec<&str> = host.split('.').collect();
+    let well_formed = labels.iter().all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    });
+    let numeric = |label: &str| {
+        label.chars().all(|c| c.is_ascii_digit())
+            || label
+                .strip_prefix("0x")
+                .is_some_and(|hex| hex.chars().all(|c| c.is_ascii_hexdigit()))
+    };
+    well_formed && host.len() <= 253 && !labels.last().is_some_and(|tld| numeric(tld))
+}
+
+// Not loopback, private, link-local, carrier-grade NAT, benchmarking,
+// reserved, or otherwise special-purpose
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0 // "this network"
+                || (a == 100 && (b & 0xc0) == 64) // 100.64.0.0/10, shared
+                || (a == 192 && b == 0 && c == 0) // 192.0.0.0/24, protocol assignments
+                || (a == 198 && (b & 0xfe) == 18) // 198.18.0.0/15, benchmarking
+                || a >= 240) // reserved
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            let internal = ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || (segments[0] & 0xfe00) == 0xfc00 // unique local
+                || (segments[0] & 0xffc0) == 0xfe80 // link local
+                || (segments[0] == 0x2001 && segments[1] == 0x0db8) // documentation
+                || (segments[0] == 0x64 && segments[1] == 0xff9b) // NAT64, an IPv4 address inside
+                || segments[..6] == [0; 6]; // IPv4-compatible
+            !internal && ip.to_ipv4_mapped().is_none()
+        }
+    }
+}
+
+// Resolves the source's host and checks every address it resolves to, so a
+// public name pointing at an internal address is refused. Returns the host
+// and the address to connect to, which the download is pinned to.
+async fn public_source(source_url: &str) -> Result<(String, SocketAddr), String> {
+    let (host, port) = source_host(source_url).ok_or("invalid source URL")?;
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return if is_public_ip(ip) {
+            Ok((host, SocketAddr::new(ip, port)))
+        } else {
+            Err(format!("{} is not a public address", ip))
+        };
+    }
+    let addresses: Vec<_> = tokio::net::lookup_host((host.as_str(), port))
+        .await
+        .map_err(|e| format!("failed to resolve {}: {}", host, e))?
+        .collect();
+    if let Some(address) = addresses.iter().find(|address| !is_public_ip(address.ip())) {
+        return Err(format!("{} resolves to {}, which is not public", host, address.ip()));
+    }
+    match addresses.first() {
+        Some(address) => Ok((host, *address)),
+        None => Err(format!("{} has no addresses", host)),
+    }
+}
+
+// Redirects followed before a source download gives up
+const MAX_SOURCE_REDIRECTS: usize = 5;
+
+// Downloads a video source to `dest`. Every hop connects only to the address
+// public_source checked, so DNS can't change between the check and the
+// connect, and a redirect is checked like the original URL before it's
+// followed.
+async fn download_source(source_url: &str, dest: &Path, max_bytes: u64) -> Result<(), String> {
+    let mut url = source_url.to_string();
+    for _ in 0..=MAX_SOURCE_REDIRECTS {
+        if !valid_source_url(&url) {
+            return Err(format!("{} is not a public https URL", url));
+        }
+        let (host, address) = public_source(&url).await?;
+        let client = reqwest::Client::builder()
+            .resolve(&host, address)
+            .redirect(reqwest::redirect::Policy::none())
+            .https_only(true)
+            .no_proxy()
+            .connect_timeout(Duration::from_secs(10))
+            .timeout(Duration::from_secs(600))
+            .build()
+            .map_err(|e| format!("failed to build HTTP client: {}", e))?;
+        let mut response = client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| format!("failed to fetch {}: {}", url, e))?;
+
+        if response.status().is_redirection() {
+            let location = response
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .ok_or_else(|| format!("{} redirected without a location", url))?;
+            url = response
+                .url()
+                .join(location)
+                .map_err(|e| format!("{} redirected to a bad location: {}", url, e))?
+                .to_string();
+            continue;
+        }
+        if !response.status().is_success() {
+            return Err(format!("{} answered {}", url, response.status()));
+        }
+        if response.content_length().is_some_and(|length| length > max_bytes) {
+            return Err(format!("{} is larger than {} bytes", url, max_bytes));
+        }
+
+        let mut file = tokio::fs::File::create(dest)
+            .await
+            .map_err(|e| format!("failed to create {}: {}", dest.display(), e))?;
+        let mut written = 0u64;
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| format!("failed to read {}: {}", url, e))?
+        {
+            written += chunk.len() as u64;
+            if written > max_bytes {
+                return Err(format!("{} is larger than {} bytes", url, max_bytes));
+            }
+            file.write_all(&chunk)
+                .await
+                .map_err(|e| format!("failed to write {}: {}", dest.display(), e))?;
+        }
+        return file
+            .flush()
+            .await
+            .map_err(|e| format!("failed to write {}: {}", dest.display(), e));
+    }
+    Err(format!("{} redirected more than {} times", source_url, MAX_SOURCE_REDIRECTS))
+}
+
+#[derive(Debug)]
+struct TranscodeJob {
+    post_id: PostId,
+    source_url: String,
+    media_dir: PathBuf, // the author's regional backend
+}
+
+// Downloads a video and runs ffmpeg on the local copy to produce a poster
+// frame and HLS renditions
+struct Transcoder {
+    ffmpeg_path: String,
+    source_max_bytes: u64,
+}
+
+impl Transcoder {
+    async fn transcode(&self, job: &TranscodeJob) -> Result<(), String> {
+        let out_dir = job.media_dir.join(job.post_id.as_str());
+        tokio::fs::create_dir_all(&out_dir)
+            .await
+            .map_err(|e| format!("failed to create {}: {}", out_dir.display(), e))?;
+
+        // ffmpeg never sees the URL, so it can't resolve the host again or
+        // follow a redirect somewhere internal. The download stays out of
+        // the served media directory.
+        let source = std::env::temp_dir().join(format!("news-feed-source-{}", job.post_id));
+        let result = async {
+            download_source(&job.source_url, &source, self.source_max_bytes).await?;
+            self.package(&out_dir, &source).await
+        }
+        .await;
+        let _ = tokio::fs::remove_file(&source).await;
+        result
+    }
+
+    async fn package(&self, out_dir: &Path, source: &Path) -> Result<(), String> {
+        let poster = out_dir.join(POSTER);
+        let source = path_arg(source);
+        let input = input_args(&source);
+        self.run(&[&["-y", "-ss", "1"], &input[..], &["-frames:v", "1", &path_arg(&poster)]].concat())
+            .await?;
+
+        let mut master = String::from("#EXTM3U\n#EXT-X-VERSION:3\n");
+        for rendition in &RENDITIONS {
+            let rendition_dir = out_dir.join(rendition.name);
+            tokio::fs::create_dir_all(&rendition_dir)
+                .await
+                .map_err(|e| format!("failed to create {}: {}", rendition_dir.display(), e))?;
+
+            self.run(&[&["-y"], &input[..], &[
+                "-vf", &format!("scale=-2:{}", rendition.height),
+                "-c:v", "libx264", "-b:v", rendition.video_bitrate,
+                "-c:a", "aac", "-b:a", "128k",
+                "-hls_time", "6", "-hls_playlist_type", "vod",
+                "-hls_segment_filename", &path_arg(&rendition_dir.join("segment_%03d.ts")),
+                &path_arg(&rendition_dir.join("index.m3u8")),
+            ]].concat())
+            .await?;
+
+            master.push_str(&format!(
+                "#EXT-X-STREAM-INF:BANDWIDTH={},RESOLUTION=x{}\n{}/index.m3u8\n",
+                rendition.bandwidth, rendition.height, rendition.name
+            ));
+        }
+
+        let master_path = out_dir.join(MASTER_PLAYLIST);
+        tokio::fs::write(&master_path, master)
+            .await
+            .map_err(|e| format!("failed to write {}: {}", master_path.display(), e))
+    }
+
+    async fn run(&self, args: &[&str]) -> Result<(), String> {
+        let output = Command::new(&self.ffmpeg_path)
+            .args(args)
+            .output()
+            .await
+            .map_err(|e| format!("failed to run {}: {}", self.ffmpeg_path, e))?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Err(format!(
+                "ffmpeg exited with {}: {}",
+                output.status,
+                stderr.lines().last().unwrap_or_default()
+            ))
+        }
+    }
+}
+
+// ffmpeg only reads the downloaded file and only demuxes plain container
+// formats, so a source can't be a playlist (HLS, concat) that points it at
+// other files or the network
+fn input_args(source: &str) -> [&str; 6] {
+    [
+        "-protocol_whitelist", "file",
+        "-format_whitelist", "mov,mp4,m4a,3gp,3g2,mj2,matroska,webm,avi,flv,mpegts",
+        "-i", source,
+    ]
+}
+
+fn path_arg(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+// Background transcode stage for video posts
+pub struct VideoPipeline {
+    cache: Arc<CacheLayer>,
+    storage: Arc<StorageRouter>,
+    sender: mpsc::UnboundedSender<TranscodeJob>,
+}
+
+impl VideoPipeline {
+    pub fn new(
+        cache: Arc<CacheLayer>,
+        storage: Arc<StorageRouter>,
+        config: &Config,
+        monitor: TaskMonitor,
+    ) -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<TranscodeJob>();
+        let transcoder = Arc::new(Transcoder {
+            ffmpeg_path: config.ffmpeg_path.clone(),
+            source_max_bytes: config.video_source_max_bytes,
+        });
+        // ffmpeg is CPU heavy, so cap how many transcodes run at once
+        let permits = Arc::new(Semaphore::new(config.transcode_workers.max(1)));
+
+        tokio::spawn({
+            let cache = cache.clone();
+            async move {
+                while let Some(job) = receiver.recv().await {
+                    let permit = match permits.clone().acquire_owned().await {
+                        Ok(permit) => permit,
+                        Err(_) => break,
+                    };
+                    let cache = cache.clone();
+                    let transcoder = transcoder.clone();
+                    tokio::spawn(monitor.instrument(async move {
+                        info!("Transcoding video for post {}", job.post_id);
+                        let status = match transcoder.transcode(&job).await {
+                            Ok(()) => VideoStatus::Ready,
+                            Err(e) => {
+                                warn!("Transcode failed for post {}: {}", job.post_id, e);
+                                VideoStatus::Failed
+                            }
+                        };
+                        cache.set_video(&job.post_id, status);
+                        drop(permit);
+                    }));
+                }
+            }
+        });
+
+        Self { cache, storage, sender }
+    }
+
+    pub fn submit(&self, post_id: &PostId, source_url: &str, region: &Region) -> Result<(), &'static str> {
+        let media_dir = self
+            .storage
+            .write_root(region)
+            .map_err(|_| "No storage backend for the author's region")?
+            .to_path_buf();
+        self.cache.set_video(post_id, VideoStatus::Processing);
+        self.sender
+            .send(TranscodeJob {
+                post_id: post_id.clone(),
+                source_url: source_url.to_string(),
+                media_dir,
+            })
+            .map_err(|_| "Failed to enqueue transcode job")
+    }
+}
+
@@ -0,0 +1,371 @@
+// With neither backend compiled in, nothing here ever makes a delivery
+#![cfg_attr(not(any(feature = "redis-queue", feature = "nats-queue")), allow(dead_code))]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use newsfeed_core::broker::Broker;
+use newsfeed_core::config::Config;
+
+// Which backend a NEWS_FEED_QUEUE_URL names, if this build includes it
+pub fn backend(url: &str) -> Result<&'static str, String> {
+    let (name, feature, included) = match url.split_once("://").map(|(scheme, _)| scheme) {
+        Some("redis" | "rediss") => ("redis", "redis-queue", cfg!(feature = "redis-queue")),
+        Some("nats" | "tls") => ("nats", "nats-queue", cfg!(feature = "nats-queue")),
+        _ => return Err(format!("expected a redis:// or nats:// URL, got {}", url)),
+    };
+    if !included {
+        return Err(format!("this build has no {} queue; build with --features {}", name, feature));
+    }
+    Ok(name)
+}
+
+// Connects to the shared queue, creating the stream and consumer group if
+// they don't exist yet. None when jobs stay on this instance.
+pub async fn connect(config: &Config) -> Result<Option<Arc<dyn Broker>>, String> {
+    let Some(url) = &config.queue_url else {
+        return Ok(None);
+    };
+    // Unique per process, so a restarted instance doesn't inherit the jobs it
+    // left pending; those are claimed again after the timeout
+    let consumer = format!("{}-{}", config.node_id, std::process::id());
+    let redeliver = Duration::from_secs(config.queue_redeliver_secs.max(1));
+    match backend(url)? {
+        #[cfg(feature = "redis-queue")]
+        "redis" => {
+            let broker = redis_queue::RedisBroker::connect(url, config, consumer, redeliver).await?;
+            Ok(Some(Arc::new(broker)))
+        }
+        #[cfg(feature = "nats-queue")]
+        "nats" => {
+            let broker = nats_queue::NatsBroker::connect(url, config, redeliver).await?;
+            Ok(Some(Arc::new(broker)))
+        }
+        _ => {
+            let _ = (consumer, redeliver);
+            unreachable!("backend() only names compiled-in queues")
+        }
+    }
+}
+
+// Redis Streams: one stream, read through a consumer group. Pending entries
+// idle past the timeout are claimed with XAUTOCLAIM, so this needs Redis 6.2
+// or later.
+#[cfg(feature = "redis-queue")]
+mod redis_queue {
+    use futures::future::BoxFuture;
+    use redis::AsyncCommands;
+    use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+    use redis::streams::{
+        StreamAutoClaimOptions, StreamAutoClaimReply, StreamId, StreamMaxlen, StreamPendingCountReply,
+        StreamReadOptions, StreamReadReply,
+    };
+    use std::time::Duration;
+
+    use newsfeed_core::broker::{Broker, Delivery};
+    use newsfeed_core::config::Config;
+    use newsfeed_core::warn;
+
+    // Acknowledged entries are trimmed past roughly this many
+    const MAX_LEN: usize = 100_000;
+
+    pub struct RedisBroker {
+        stream: String,
+        group: String,
+        consumer: String,
+        redeliver: Duration,
+        commands: ConnectionManager,
+        // Blocking reads get their own connection so they don't hold up
+        // publishes and acknowledgements
+        reads: ConnectionManager,
+    }
+
+    impl RedisBroker {
+        pub async fn connect(url: &str, config: &Config, consumer: String, redeliver: Duration) -> Result<Self, String> {
+            let client = redis::Client::open(url).map_err(|e| e.to_string())?;
+            // Startup retries the whole step, so each connection tries just once
+            // here; later the managers reconnect on the next command
+            let managed = || {
+                ConnectionManagerConfig::new()
+                    .set_number_of_retries(1)
+                    .set_connection_timeout(Duration::from_secs(2))
+            };
+            let mut commands = ConnectionManager::new_with_config(client.clone(), managed())
+                .await
+                .map_err(|e| e.to_string())?;
+            let reads = ConnectionManager::new_with_config(client, managed())
+                .await
+                .map_err(|e| e.to_string())?;
+            let created: redis::RedisResult<()> = commands
+                .xgroup_create_mkstream(&config.queue_stream, &config.queue_group, "$")
+                .await;
+            match created {
+                Err(e) if e.code() != Some("BUSYGROUP") => return Err(e.to_string()),
+                _ => {}
+            }
+            Ok(Self {
+                stream: config.queue_stream.clone(),
+                group: config.queue_group.clone(),
+                consumer,
+                redeliver,
+                commands,
+                reads,
+            })
+        }
+
+        fn delivery(entry: StreamId, attempts: u32) -> Result<Delivery, String> {
+            let kind: String = entry.get("kind").ok_or_else(|| format!("entry {} has no kind", entry.id))?;
+            let payload: String = entry.get("payload").ok_or_else(|| format!("entry {} has no payload", entry.id))?;
+            Ok(Delivery {
+                payload: serde_json::from_str(&payload).map_err(|e| format!("entry {}: {}", entry.id, e))?,
+                id: entry.id,
+                kind,
+                attempts,
+            })
+        }
+
+        // Stale entries from any consumer, this one included
+        async fn claim(&self, max: usize) -> Result<Vec<Delivery>, String> {
+            let mut commands = self.commands.clone();
+            let reply: StreamAutoClaimReply = commands
+                .xautoclaim_options(
+                    &self.stream,
+                    &self.group,
+                    &self.consumer,
+                    self.redeliver.as_millis() as u64,
+                    "0-0",
+                    StreamAutoClaimOptions::default().count(max),
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+            let mut deliveries = Vec::new();
+            for entry in reply.claimed {
+                let pending: StreamPendingCountReply = commands
+                    .xpending_count(&self.stream, &self.group, &entry.id, &entry.id, 1)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                let attempts = pending.ids.first().map(|pending| pending.times_delivered as u32).unwrap_or(1);
+                deliveries.extend(self.settle_unreadable(entry, attempts).await?);
+            }
+            Ok(deliveries)
+        }
+
+        // An entry that can't be parsed would be claimed forever; it's
+        // acknowledged and dropped instead
+        async fn settle_unreadable(&self, entry: StreamId, attempts: u32) -> Result<Option<Delivery>, String> {
+            let id = entry.id.clone();
+            match Self::delivery(entry, attempts) {
+                Ok(delivery) => Ok(Some(delivery)),
+                Err(e) => {
+                    warn!("Dropping unreadable shared job: {}", e);
+                    let mut commands = self.commands.clone();
+                    let _: () = commands.xack(&self.stream, &self.group, &[&id]).await.map_err(|e| e.to_string())?;
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    impl Broker for RedisBroker {
+        fn name(&self) -> &'static str {
+            "redis"
+        }
+
+        fn publish<'a>(&'a self, kind: &'a str, payload: &'a serde_json::Value) -> BoxFuture<'a, Result<(), String>> {
+            Box::pin(async move {
+                let mut commands = self.commands.clone();
+                let _: String = commands
+                    .xadd_maxlen(
+                        &self.stream,
+                        StreamMaxlen::Approx(MAX_LEN),
+                        "*",
+                        &[("kind", kind.to_string()), ("payload", payload.to_string())],
+                    )
+                    .await
+                    .map_err(|e| e.to_string())?;
+                Ok(())
+            })
+        }
+
+        fn fetch(&self, max: usize, wait: Duration) -> BoxFuture<'_, Result<Vec<Delivery>, String>> {
+            Box::pin(async move {
+                let claimed = self.claim(max).await?;
+                if !claimed.is_empty() {
+                    return Ok(claimed);
+                }
+                let options = StreamReadOptions::default()
+                    .group(&self.group, &self.consumer)
+                    .count(max)
+                    .block(wait.as_millis() as usize);
+                let mut reads = self.reads.clone();
+                let reply: Option<StreamReadReply> = reads
+                    .xread_options(&[&self.stream], &[">"], &options)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                let mut deliveries = Vec::new();
+                for entry in reply.into_iter().flat_map(|reply| reply.keys).flat_map(|key| key.ids) {
+                    deliveries.extend(self.settle_unreadable(entry, 1).await?);
+                }
+                Ok(deliveries)
+            })
+        }
+
+        fn ack(&self, delivery: Delivery) -> BoxFuture<'_, Result<(), String>> {
+            Box::pin(async move {
+                let mut commands = self.commands.clone();
+                let _: () = commands
+                    .xack(&self.stream, &self.group, &[&delivery.id])
+                    .await
+                    .map_err(|e| e.to_string())?;
+                Ok(())
+            })
+        }
+
+        fn nack(&self, _delivery: Delivery, _delay: Duration) -> BoxFuture<'_, Result<(), String>> {
+            // Left pending; the next claim after the timeout picks it up
+            Box::pin(async { Ok(()) })
+        }
+    }
+}
+
+// NATS JetStream: a work-queue stream with one subject per job kind, read
+// through a durable pull consumer that every instance shares.
+#[cfg(feature = "nats-queue")]
+mod nats_queue {
+    use async_nats::jetstream::{self, AckKind, Message, consumer, stream};
+    use futures::StreamExt;
+    use futures::future::BoxFuture;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use newsfeed_core::broker::{Broker, Delivery};
+    use newsfeed_core::config::Config;
+    use newsfeed_core::warn;
+
+    pub struct NatsBroker {
+        stream: String,
+        context: jetstream::Context,
+        consumer: consumer::Consumer<consumer::pull::Config>,
+        // Fetched messages awaiting their ack, by stream sequence
+        in_flight: Mutex<HashMap<String, Message>>,
+    }
+
+    impl NatsBroker {
+        pub async fn connect(url: &str, config: &Config, redeliver: Duration) -> Result<Self, String> {
+            let client = async_nats::connect(url).await.map_err(|e| e.to_string())?;
+            let context = jetstream::new(client);
+            let stream = context
+                .get_or_create_stream(stream::Config {
+                    name: config.queue_stream.clone(),
+                    subjects: vec![format!("{}.*", config.queue_stream)],
+                    retention: stream::RetentionPolicy::WorkQueue,
+                    ..Default::default()
+                })
+                .await
+                .map_err(|e| e.to_string())?;
+            let consumer = stream
+                .get_or_create_consumer(
+                    &config.queue_group,
+                    consumer::pull::Config {
+                        durable_name: Some(config.queue_group.clone()),
+                        ack_wait: redeliver,
+                        ..Default::default()
+                    },
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(Self {
+                stream: config.queue_stream.clone(),
+                context,
+                consumer,
+                in_flight: Mutex::new(HashMap::new()),
+            })
+        }
+
+        fn take(&self, delivery: &Delivery) -> Result<Message, String> {
+            self.in_flight
+                .lock()
+                .expect("in-flight messages poisoned")
+                .remove(&delivery.id)
+                .ok_or_else(|| format!("message {} isn't in flight", delivery.id))
+        }
+    }
+
+    impl Broker for NatsBroker {
+        fn name(&self) -> &'static str {
+            "nats"
+        }
+
+        fn publish<'a>(&'a self, kind: &'a str, payload: &'a serde_json::Value) -> BoxFuture<'a, Result<(), String>> {
+            Box::pin(async move {
+                let subject = format!("{}.{}", self.stream, kind);
+                let body = serde_json::to_vec(payload).map_err(|e| e.to_string())?;
+                // The second await is the stream's acknowledgement of the publish
+                self.context
+                    .publish(subject, body.into())
+                    .await
+                    .map_err(|e| e.to_string())?
+                    .await
+                    .map_err(|e| e.to_string())?;
+                Ok(())
+            })
+        }
+
+        fn fetch(&self, max: usize, wait: Duration) -> BoxFuture<'_, Result<Vec<Delivery>, String>> {
+            Box::pin(async move {
+                let mut messages = self
+                    .consumer
+                    .fetch()
+                    .max_messages(max)
+                    .expires(wait)
+                    .messages()
+                    .await
+                    .map_err(|e| e.to_string())?;
+                let mut deliveries = Vec::new();
+                while let Some(message) = messages.next().await {
+                    let message = message.map_err(|e| e.to_string())?;
+                    let (sequence, attempts) = {
+                        let info = message.info().map_err(|e| e.to_string())?;
+                        (info.stream_sequence, info.delivered.max(1) as u32)
+                    };
+                    let kind = message.subject.as_str().rsplit('.').next().unwrap_or_default().to_string();
+                    let payload = match serde_json::from_slice(&message.payload) {
+                        Ok(payload) => payload,
+                        Err(e) => {
+                            // Would be redelivered forever otherwise
+                            warn!("Dropping unreadable shared job {}: {}", sequence, e);
+                            message.ack_with(AckKind::Term).await.map_err(|e| e.to_string())?;
+                            continue;
+                        }
+                    };
+                    let id = sequence.to_string();
+                    self.in_flight
+                        .lock()
+                        .expect("in-flight messages poisoned")
+                        .insert(id.clone(), message);
+                    deliveries.push(Delivery {
+                        id,
+                        kind,
+                        payload,
+                        attempts,
+                    });
+                }
+                Ok(deliveries)
+            })
+        }
+
+        fn ack(&self, delivery: Delivery) -> BoxFuture<'_, Result<(), String>> {
+            Box::pin(async move { self.take(&delivery)?.ack().await.map_err(|e| e.to_string()) })
+        }
+
+        fn nack(&self, delivery: Delivery, delay: Duration) -> BoxFuture<'_, Result<(), String>> {
+            Box::pin(async move {
+                self.take(&delivery)?
+                    .ack_with(AckKind::Nak(Some(delay)))
+                    .await
+                    .map_err(|e| e.to_string())
+            })
+        }
+    }
+}
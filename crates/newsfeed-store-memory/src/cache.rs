@@ -0,0 +1,2166 @@
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+use newsfeed_core::account_state::{AccountRecord, AccountState};
+use newsfeed_core::activity::{Activity, ActivityLog, ActivityStats};
+use newsfeed_core::ads::Campaign;
+use newsfeed_core::bloom::DeliveredFilter;
+use newsfeed_core::cache::Cache;
+use newsfeed_core::context::ViewerContext;
+use newsfeed_core::emoji::CustomEmoji;
+use newsfeed_core::feed_locks::FeedLocks;
+use newsfeed_core::feed_updates::FeedUpdates;
+use newsfeed_core::graph::{FollowEdge, SocialGraph};
+use newsfeed_core::hyperloglog::HyperLogLog;
+use newsfeed_core::i18n::Catalogs;
+use newsfeed_core::ids::{PostId, TagId, UserId};
+use newsfeed_core::interests::{Engagement, Interests};
+use newsfeed_core::legal::{HoldEntry, HoldKind, LegalHold, Takedown, Tombstone};
+use newsfeed_core::login_history::{LoginContext, LoginHistory, LoginRecord};
+use newsfeed_core::markers::{DeliveryMarker, Step};
+use newsfeed_core::media::VideoStatus;
+use newsfeed_core::mixer::{DAY_MILLIS, ImpressionLog};
+use newsfeed_core::notifications::Notification;
+use newsfeed_core::profile_views::{ProfileViewStats, ProfileViews};
+use newsfeed_core::ranking::{NegativeSignal, SignalKind};
+use newsfeed_core::reach::{Channel, Reach};
+use newsfeed_core::receipts::{DeliveryReceipt, Outcome, ReceiptReport};
+use newsfeed_core::related::{CachedRelated, RelatedPost};
+use newsfeed_core::residency::Region;
+use newsfeed_core::retention::RetentionPolicy;
+use newsfeed_core::rsvp::{RsvpStatus, RsvpSummary, Rsvps};
+use newsfeed_core::saved_searches::{MAX_SAVED_SEARCHES, SavedSearch};
+use newsfeed_core::storage::Storage;
+use newsfeed_core::timezones::{ClockTime, Timezones, Zone};
+use newsfeed_core::typeahead::Typeahead;
+use newsfeed_core::{
+    Counters, FeedPosition, HydratedPost, NewsFeedItem, Post, PostUnavailable, ReplyPolicy, User, UserPreferences,
+    UsernameError, UsernameLookup, UsernameRedirect, content, now_millis, warn,
+};
+
+use crate::cache_stats::{self, PostRead, PostReads, ShardStats, shard_stats};
+use crate::eviction::CacheEviction;
+use crate::memory::{CacheMemory, estimate};
+use crate::storage;
+
+pub const MAX_CACHED_PAGES: usize = 8;
+pub const MAX_FEED_ITEMS: usize = 1000;
+// How long a feed read from shared storage is served before it's read again
+// for other instances' deliveries
+pub const SHARED_FEED_RELOAD_MILLIS: u64 = 5_000;
+// Per hashtag, newest first
+pub const MAX_HASHTAG_ITEMS: usize = 1000;
+// Per followed pull author, newest first
+pub const MAX_PULLED_PER_AUTHOR: usize = 100;
+
+// Hydrated feed pages recently served to a user. Any change to the user's
+// feed drops them all.
+#[derive(Debug, Default, Serialize)]
+pub struct CachedPages {
+    pages: Vec<CachedPage>, // newest last
+}
+
+#[derive(Debug, Serialize)]
+pub struct CachedPage {
+    key: String, // page size and starting cursor
+    cached_at: u64,
+    posts: Vec<HydratedPost>,
+}
+
+// Cache Layer using DashMap for thread-safe concurrent access
+#[derive(Debug)]
+pub struct CacheLayer {
+    news_feeds: DashMap<UserId, VecDeque<NewsFeedItem>>,
+    feeds_loaded: DashMap<UserId, u64>, // when each feed was last read from shared storage
+    posts: DashMap<PostId, Post>,
+    users: DashMap<UserId, User>,
+    hot_cache: DashMap<PostId, Post>,
+    deleted_posts: DashMap<PostId, UserId>, // deleted posts and their authors
+    graph: SocialGraph,
+    actions: DashMap<UserId, HashMap<PostId, bool>>, // liked posts
+    likers: DashMap<PostId, HashSet<UserId>>, // who liked each post
+    counters: DashMap<PostId, Counters>,
+    videos: DashMap<PostId, VideoStatus>, // transcode state
+    pub preferences: DashMap<UserId, UserPreferences>,
+    threads: DashMap<PostId, Vec<PostId>>, // head post -> remaining posts in order
+    replies: DashMap<PostId, Vec<PostId>>, // direct replies
+    usernames: DashMap<String, UserId>, // lowercased username
+    username_redirects: DashMap<String, UsernameRedirect>, // lowercased old username
+    emojis: DashMap<String, CustomEmoji>, // shortcode -> emoji
+    user_posts: DashMap<UserId, Vec<PostId>>, // authored and co-authored posts, oldest first
+    co_author_requests: DashMap<UserId, Vec<PostId>>, // posts awaiting each invitee's answer, oldest first
+    rsvps: DashMap<PostId, Rsvps>, // answers to event posts
+    hashtags: DashMap<TagId, VecDeque<NewsFeedItem>>, // posts using each hashtag, newest first
+    articles: DashMap<PostId, String>, // article markdown, read back from storage on a miss
+    feed_positions: DashMap<UserId, FeedPosition>,
+    hidden_posts: DashMap<UserId, HashSet<PostId>>, // kept out of the feed
+    blocks: DashMap<UserId, HashSet<UserId>>, // accounts each user has blocked
+    negative_signals: DashMap<UserId, VecDeque<NegativeSignal>>, // newest first
+    telemetry_readings: DashMap<UserId, VecDeque<(PostId, bool)>>, // posts telemetry already scored, newest first
+    interests: DashMap<UserId, Interests>, // from likes, replies, and profile visits
+    impressions: DashMap<UserId, ImpressionLog>, // injected post impressions today
+    author_deliveries: DashMap<UserId, ImpressionLog<UserId>>, // posts fanned in per author today
+    pull_authors: DashMap<UserId, u64>, // authors merged into feeds at read time, and since when
+    campaigns: DashMap<String, Campaign>,
+    feed_pages: DashMap<UserId, CachedPages>, // recently served pages
+    related_posts: DashMap<PostId, CachedRelated>, // "more like this", per post
+    view_sketches: DashMap<PostId, HyperLogLog>, // distinct viewers
+    reach: DashMap<PostId, Reach>, // impressions by channel
+    delivered: DashMap<UserId, DeliveredFilter>, // recently delivered posts
+    delivery_markers: DashMap<PostId, DeliveryMarker>, // followers each fanout has reached
+    receipts: DashMap<PostId, DeliveryReceipt>, // fanout progress per post
+    notifications: DashMap<UserId, VecDeque<Notification>>, // newest first
+    notifications_read: DashMap<UserId, u64>, // notifications created up to this time are read
+    pub digests: DashMap<UserId, u64>, // when each user's last email digest went out
+    saved_searches: DashMap<UserId, Vec<SavedSearch>>, // oldest first
+    activity: DashMap<UserId, ActivityLog>, // posts, likes, and reading time per day
+    profile_views: DashMap<UserId, ProfileViews>, // visits to each user's profile
+    login_history: DashMap<UserId, LoginHistory>,
+    held_posts: DashMap<PostId, LegalHold>,
+    held_users: DashMap<UserId, LegalHold>,
+    takedowns: DashMap<PostId, Takedown>,
+    viewer_countries: DashMap<UserId, String>, // last country the edge reported
+    account_records: DashMap<UserId, AccountRecord>, // state and email; absent means active
+    pub feed_updates: FeedUpdates, // wakes long-polling clients on new feed items
+    feed_locks: FeedLocks, // serializes writers to one user's feed
+    typeahead: Typeahead, // username and hashtag prefixes
+    pub timezones: Timezones, // zoneinfo files read so far
+    pub storage: Option<Arc<dyn Storage>>, // durable posts, users, feeds, and follows
+    eviction: CacheEviction, // when posts, users, likes, and hot posts were last used
+    post_reads: PostReads, // get_post hits and misses
+}
+
+impl CacheLayer {
+    pub fn new(storage: Option<Arc<dyn Storage>>, eviction: CacheEviction) -> Self {
+        Self {
+            news_feeds: DashMap::new(),
+            feeds_loaded: DashMap::new(),
+            posts: DashMap::new(),
+            users: DashMap::new(),
+            hot_cache: DashMap::new(),
+            deleted_posts: DashMap::new(),
+            graph: SocialGraph::default(),
+            actions: DashMap::new(),
+            likers: DashMap::new(),
+            counters: DashMap::new(),
+            videos: DashMap::new(),
+            preferences: DashMap::new(),
+            threads: DashMap::new(),
+            replies: DashMap::new(),
+            usernames: DashMap::new(),
+            username_redirects: DashMap::new(),
+            emojis: DashMap::new(),
+            user_posts: DashMap::new(),
+            co_author_requests: DashMap::new(),
+            rsvps: DashMap::new(),
+            hashtags: DashMap::new(),
+            articles: DashMap::new(),
+            feed_positions: DashMap::new(),
+            hidden_posts: DashMap::new(),
+            blocks: DashMap::new(),
+            negative_signals: DashMap::new(),
+            telemetry_readings: DashMap::new(),
+            interests: DashMap::new(),
+            impressions: DashMap::new(),
+            author_deliveries: DashMap::new(),
+            pull_authors: DashMap::new(),
+            campaigns: DashMap::new(),
+            feed_pages: DashMap::new(),
+            related_posts: DashMap::new(),
+            view_sketches: DashMap::new(),
+            reach: DashMap::new(),
+            delivered: DashMap::new(),
+            delivery_markers: DashMap::new(),
+            receipts: DashMap::new(),
+            notifications: DashMap::new(),
+            notifications_read: DashMap::new(),
+            digests: DashMap::new(),
+            saved_searches: DashMap::new(),
+            activity: DashMap::new(),
+            profile_views: DashMap::new(),
+            login_history: DashMap::new(),
+            held_posts: DashMap::new(),
+            held_users: DashMap::new(),
+            takedowns: DashMap::new(),
+            viewer_countries: DashMap::new(),
+            account_records: DashMap::new(),
+            feed_updates: FeedUpdates::default(),
+            feed_locks: FeedLocks::default(),
+            typeahead: Typeahead::default(),
+            timezones: Timezones::default(),
+            storage,
+            eviction,
+            post_reads: PostReads::default(),
+        }
+    }
+
+    // Writes a change through to storage. The cache already has it, so a
+    // failed write is logged rather than failing the request.
+    pub fn persist(&self, what: &str, write: impl FnOnce(&dyn Storage) -> Result<(), String>) {
+        if let Some(storage) = &self.storage
+            && let Err(e) = write(storage.as_ref())
+        {
+            warn!("storage: failed to save {}: {}", what, e);
+        }
+    }
+
+    // Brings a user's stored feed into the cache if it isn't there yet. With
+    // `reload`, a feed that other instances also deliver to is read again
+    // once the copy here is SHARED_FEED_RELOAD_MILLIS old; deliveries skip
+    // that, since a shared backend adds their items on its own. The items
+    // count as delivered, so a replayed fanout doesn't add them twice.
+    pub fn load_feed(&self, user_id: &UserId, reload: bool) {
+        let Some(storage) = &self.storage else {
+            return;
+        };
+        let now = now_millis();
+        if self.news_feeds.contains_key(user_id)
+            && (!reload
+                || !storage.shared()
+                || self
+                    .feeds_loaded
+                    .get(user_id)
+                    .is_some_and(|loaded_at| now.saturating_sub(*loaded_at) < SHARED_FEED_RELOAD_MILLIS))
+        {
+            return;
+        }
+        match storage.get_feed(user_id) {
+            Ok(Some(items)) => {
+                let mut delivered = self.delivered.entry(user_id.clone()).or_default();
+                for item in &items {
+                    delivered.insert(&item.post_id);
+                }
+                drop(delivered);
+                self.news_feeds.insert(user_id.clone(), items.into());
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!("storage: failed to load the feed of {}: {}", user_id, e);
+                return;
+            }
+        }
+        if storage.shared() {
+            self.feeds_loaded.insert(user_id.clone(), now);
+        }
+    }
+
+    // The whole feed, for a backend that stores it that way, copied so no
+    // guard is held while it's written. Shared backends change their copy
+    // item by item and get an empty one.
+    pub fn feed_for_storage(&self, feed: &VecDeque<NewsFeedItem>) -> Option<Vec<NewsFeedItem>> {
+        let storage = self.storage.as_ref()?;
+        Some(if storage.shared() { Vec::new() } else { feed.iter().cloned().collect() })
+    }
+
+    // News Feed Cache
+    pub fn get_news_feed(&self, user_id: &UserId) -> Vec<NewsFeedItem> {
+        self.load_feed(user_id, true);
+        self.prune_deleted(user_id);
+        self.news_feeds
+            .get(user_id)
+            .map(|feed| feed.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // Drops items whose posts were deleted after fanout. Deletion doesn't
+    // visit every follower's feed; each feed is cleaned the next time it's
+    // read, before anything is paged or hydrated.
+    pub fn prune_deleted(&self, user_id: &UserId) {
+        if self.deleted_posts.is_empty() {
+            return;
+        }
+        let dangling = self
+            .news_feeds
+            .get(user_id)
+            .is_some_and(|feed| feed.iter().any(|item| self.deleted_posts.contains_key(&item.post_id)));
+        if !dangling {
+            return;
+        }
+        let Some(mut feed) = self.news_feeds.get_mut(user_id) else {
+            return;
+        };
+        let mut removed = Vec::new();
+        feed.retain(|item| {
+            let deleted = self.deleted_posts.contains_key(&item.post_id);
+            if deleted {
+                removed.push(item.post_id.clone());
+            }
+            !deleted
+        });
+        let stored = self.feed_for_storage(&feed);
+        drop(feed);
+        if let Some(stored) = stored {
+            self.persist("feed", |storage| storage.remove_from_feed(user_id, &removed, &stored));
+        }
+    }
+
+    // Recent top-level posts by followed pull authors, since the follow and
+    // within the follower's daily limit, that aren't in the pushed feed
+    pub fn pulled_items(&self, user_id: &UserId, pushed: &[NewsFeedItem]) -> Vec<NewsFeedItem> {
+        let pushed: HashSet<&PostId> = pushed.iter().map(|item| &item.post_id).collect();
+        // A co-authored post is on each author's timeline; it's pulled once
+        let mut pulled = HashSet::new();
+        let mut items = Vec::new();
+        for (author_id, edge) in self.graph.following(user_id) {
+            if !self.pull_authors.contains_key(&author_id) {
+                continue;
+            }
+            let mut posts: Vec<Post> = self
+                .get_user_post_ids(&author_id)
+                .iter()
+                .rev()
+                .filter_map(|post_id| self.get_post(post_id))
+                .filter(|post| post.in_reply_to.is_none() && !post.anonymous)
+                .take_while(|post| post.timestamp >= edge.followed_at)
+                .take(MAX_PULLED_PER_AUTHOR)
+                .collect();
+            // The first posts of each UTC day get in, as fanout would let them
+            if let Some(limit) = edge.daily_limit {
+                posts.reverse();
+                let mut per_day: HashMap<u64, u16> = HashMap::new();
+                posts.retain(|post| {
+                    let count = per_day.entry(post.timestamp / DAY_MILLIS).or_default();
+                    *count += 1;
+                    *count <= limit
+                });
+            }
+            items.extend(
+                posts
+                    .into_iter()
+                    .filter(|post| {
+                        !pushed.contains(&post.id)
+                            && !self.is_hidden(user_id, &post.id)
+                            && pulled.insert(post.id.clone())
+                    })
+                    .map(|post| NewsFeedItem {
+                        post_id: post.id,
+                        timestamp: post.timestamp,
+                    }),
+            );
+        }
+        items
+    }
+
+    pub fn get_receipt(&self, post_id: &PostId) -> Option<ReceiptReport> {
+        self.receipts.get(post_id).map(|receipt| receipt.report(post_id))
+    }
+
+    pub fn reached_followers(&self, post_id: &PostId) -> Option<u32> {
+        self.receipts.get(post_id).map(|receipt| receipt.delivered())
+    }
+
+    // Drops markers for fanouts that started before `before`, returning how many
+    pub fn expire_delivery_markers(&self, before: u64) -> usize {
+        let count = self.delivery_markers.len();
+        self.delivery_markers.retain(|_, marker| marker.created_at >= before);
+        count - self.delivery_markers.len()
+    }
+
+    pub fn invalidate_feed_pages(&self, user_id: &UserId) {
+        self.feed_pages.remove(user_id);
+    }
+
+    // For changes that can affect any viewer's pages
+    pub fn invalidate_all_feed_pages(&self) {
+        self.feed_pages.clear();
+    }
+
+    // Removes posts from the viewer's feed for good and records why
+    pub fn hide_posts(&self, user_id: &UserId, post_ids: &[PostId]) -> usize {
+        let mut removed = 0;
+        self.load_feed(user_id, false);
+        for post_id in post_ids {
+            self.hidden_posts
+                .entry(user_id.clone())
+                .or_default()
+                .insert(post_id.clone());
+
+            let stored = self.news_feeds.get_mut(user_id).and_then(|mut feed| {
+                let before = feed.len();
+                feed.retain(|item| &item.post_id != post_id);
+                removed += before - feed.len();
+                (feed.len() < before).then(|| self.feed_for_storage(&feed)).flatten()
+            });
+            if let Some(stored) = stored {
+                self.persist("feed", |storage| {
+                    storage.remove_from_feed(user_id, std::slice::from_ref(post_id), &stored)
+                });
+            }
+
+            if let Some(post) = self.get_post(post_id) {
+                self.add_negative_signal(user_id, NegativeSignal::for_post(SignalKind::Hide, &post));
+            }
+        }
+        self.invalidate_feed_pages(user_id);
+        removed
+    }
+
+    // True if this is a new block
+    pub fn block(&self, blocker_id: &UserId, blocked_id: &UserId) -> bool {
+        let added = self
+            .blocks
+            .entry(blocker_id.clone())
+            .or_default()
+            .insert(blocked_id.clone());
+        // Their cached pages may hold the blocker's posts
+        self.invalidate_feed_pages(blocked_id);
+        added
+    }
+
+    pub fn unblock(&self, blocker_id: &UserId, blocked_id: &UserId) -> bool {
+        let mut removed = false;
+        self.blocks.remove_if_mut(blocker_id, |_, blocked| {
+            removed = blocked.remove(blocked_id);
+            blocked.is_empty()
+        });
+        self.invalidate_feed_pages(blocked_id);
+        removed
+    }
+
+    // Why nothing of an author's may be shown to the viewer, if so: to an
+    // anonymous viewer, any account that isn't public is restricted; to a
+    // user, an account that has blocked them
+    pub fn restriction(&self, viewer: &ViewerContext, author_id: &UserId) -> Option<PostUnavailable> {
+        match viewer.user_id() {
+            None => (!self.is_public_account(author_id)).then_some(PostUnavailable::Restricted),
+            Some(user_id) => self.has_blocked(author_id, user_id).then_some(PostUnavailable::Blocked),
+        }
+    }
+
+    pub fn add_negative_signal(&self, user_id: &UserId, signal: NegativeSignal) {
+        {
+            let mut signals = self.negative_signals.entry(user_id.clone()).or_default();
+            signals.push_front(signal);
+            // Keep only the latest 500 signals
+            signals.truncate(500);
+        }
+        // Signals (hides included) change ranking
+        self.invalidate_feed_pages(user_id);
+    }
+
+    // Telemetry scores a post once each way per user: one fast scroll and
+    // one positive reading at most, however often the post is reported
+    pub fn first_reading(&self, user_id: &UserId, post_id: &PostId, positive: bool) -> bool {
+        let mut readings = self.telemetry_readings.entry(user_id.clone()).or_default();
+        if readings.iter().any(|(read_id, read_positive)| read_id == post_id && *read_positive == positive) {
+            return false;
+        }
+        readings.push_front((post_id.clone(), positive));
+        readings.truncate(500);
+        true
+    }
+
+    pub fn add_campaign(&self, campaign: Campaign) {
+        self.campaigns.insert(campaign.id.clone(), campaign);
+    }
+
+    pub fn record_campaign_click(&self, campaign_id: &str) -> bool {
+        match self.campaigns.get_mut(campaign_id) {
+            Some(mut campaign) => {
+                campaign.clicks += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Sampled per map; a blocking call meant for admin diagnostics
+    pub fn shard_stats(&self, rounds: usize) -> Vec<ShardStats> {
+        let mut stats = vec![
+            shard_stats("news_feeds", &self.news_feeds, rounds),
+            shard_stats("posts", &self.posts, rounds),
+            shard_stats("users", &self.users, rounds),
+            shard_stats("hot_cache", &self.hot_cache, rounds),
+            shard_stats("deleted_posts", &self.deleted_posts, rounds),
+            shard_stats("actions", &self.actions, rounds),
+            shard_stats("likers", &self.likers, rounds),
+            shard_stats("counters", &self.counters, rounds),
+            shard_stats("videos", &self.videos, rounds),
+            shard_stats("preferences", &self.preferences, rounds),
+            shard_stats("threads", &self.threads, rounds),
+            shard_stats("replies", &self.replies, rounds),
+            shard_stats("usernames", &self.usernames, rounds),
+            shard_stats("username_redirects", &self.username_redirects, rounds),
+            shard_stats("emojis", &self.emojis, rounds),
+            shard_stats("user_posts", &self.user_posts, rounds),
+            shard_stats("co_author_requests", &self.co_author_requests, rounds),
+            shard_stats("rsvps", &self.rsvps, rounds),
+            shard_stats("hashtags", &self.hashtags, rounds),
+            shard_stats("articles", &self.articles, rounds),
+            shard_stats("feed_positions", &self.feed_positions, rounds),
+            shard_stats("hidden_posts", &self.hidden_posts, rounds),
+            shard_stats("blocks", &self.blocks, rounds),
+            shard_stats("negative_signals", &self.negative_signals, rounds),
+            shard_stats("telemetry_readings", &self.telemetry_readings, rounds),
+            shard_stats("interests", &self.interests, rounds),
+            shard_stats("impressions", &self.impressions, rounds),
+            shard_stats("author_deliveries", &self.author_deliveries, rounds),
+            shard_stats("pull_authors", &self.pull_authors, rounds),
+            shard_stats("campaigns", &self.campaigns, rounds),
+            shard_stats("feed_pages", &self.feed_pages, rounds),
+            shard_stats("related_posts", &self.related_posts, rounds),
+            shard_stats("view_sketches", &self.view_sketches, rounds),
+            shard_stats("reach", &self.reach, rounds),
+            shard_stats("delivered", &self.delivered, rounds),
+            shard_stats("delivery_markers", &self.delivery_markers, rounds),
+            shard_stats("receipts", &self.receipts, rounds),
+            shard_stats("notifications", &self.notifications, rounds),
+            shard_stats("notifications_read", &self.notifications_read, rounds),
+            shard_stats("digests", &self.digests, rounds),
+            shard_stats("saved_searches", &self.saved_searches, rounds),
+            shard_stats("activity", &self.activity, rounds),
+            shard_stats("profile_views", &self.profile_views, rounds),
+            shard_stats("login_history", &self.login_history, rounds),
+            shard_stats("held_posts", &self.held_posts, rounds),
+            shard_stats("held_users", &self.held_users, rounds),
+            shard_stats("takedowns", &self.takedowns, rounds),
+            shard_stats("viewer_countries", &self.viewer_countries, rounds),
+            shard_stats("account_records", &self.account_records, rounds),
+        ];
+        stats.extend(self.graph.maps().map(|(name, map)| shard_stats(name, map, rounds)));
+        stats
+    }
+
+    pub fn memory_usage(&self) -> Vec<CacheMemory> {
+        let mut usage = vec![
+            estimate("news_feeds", &self.news_feeds),
+            estimate("posts", &self.posts),
+            estimate("users", &self.users),
+            estimate("hot_cache", &self.hot_cache),
+            estimate("deleted_posts", &self.deleted_posts),
+            estimate("actions", &self.actions),
+            estimate("likers", &self.likers),
+            estimate("counters", &self.counters),
+            estimate("videos", &self.videos),
+            estimate("preferences", &self.preferences),
+            estimate("threads", &self.threads),
+            estimate("replies", &self.replies),
+            estimate("usernames", &self.usernames),
+            estimate("username_redirects", &self.username_redirects),
+            estimate("emojis", &self.emojis),
+            estimate("user_posts", &self.user_posts),
+            estimate("co_author_requests", &self.co_author_requests),
+            estimate("rsvps", &self.rsvps),
+            estimate("hashtags", &self.hashtags),
+            estimate("articles", &self.articles),
+            estimate("feed_positions", &self.feed_positions),
+            estimate("hidden_posts", &self.hidden_posts),
+            estimate("blocks", &self.blocks),
+            estimate("negative_signals", &self.negative_signals),
+            estimate("telemetry_readings", &self.telemetry_readings),
+            estimate("interests", &self.interests),
+            estimate("impressions", &self.impressions),
+            estimate("author_deliveries", &self.author_deliveries),
+            estimate("pull_authors", &self.pull_authors),
+            estimate("campaigns", &self.campaigns),
+            estimate("feed_pages", &self.feed_pages),
+            estimate("related_posts", &self.related_posts),
+            estimate("view_sketches", &self.view_sketches),
+            estimate("reach", &self.reach),
+            estimate("delivered", &self.delivered),
+            estimate("delivery_markers", &self.delivery_markers),
+            estimate("receipts", &self.receipts),
+            estimate("notifications", &self.notifications),
+            estimate("notifications_read", &self.notifications_read),
+            estimate("digests", &self.digests),
+            estimate("saved_searches", &self.saved_searches),
+            estimate("activity", &self.activity),
+            estimate("profile_views", &self.profile_views),
+            estimate("login_history", &self.login_history),
+            estimate("held_posts", &self.held_posts),
+            estimate("held_users", &self.held_users),
+            estimate("takedowns", &self.takedowns),
+            estimate("viewer_countries", &self.viewer_countries),
+            estimate("account_records", &self.account_records),
+        ];
+        usage.extend(self.graph.maps().map(|(name, map)| estimate(name, map)));
+        usage
+    }
+
+    // Empties every map; used to wipe the sandbox tenant, which has no
+    // storage. Long-polling clients keep waiting and simply see the
+    // reseeded data.
+    pub fn clear(&self) {
+        self.eviction.clear();
+        self.news_feeds.clear();
+        self.feeds_loaded.clear();
+        self.posts.clear();
+        self.users.clear();
+        self.hot_cache.clear();
+        self.deleted_posts.clear();
+        self.actions.clear();
+        self.likers.clear();
+        self.counters.clear();
+        self.videos.clear();
+        self.preferences.clear();
+        self.threads.clear();
+        self.replies.clear();
+        self.usernames.clear();
+        self.username_redirects.clear();
+        self.emojis.clear();
+        self.user_posts.clear();
+        self.co_author_requests.clear();
+        self.rsvps.clear();
+        self.hashtags.clear();
+        self.articles.clear();
+        self.feed_positions.clear();
+        self.hidden_posts.clear();
+        self.blocks.clear();
+        self.negative_signals.clear();
+        self.telemetry_readings.clear();
+        self.interests.clear();
+        self.impressions.clear();
+        self.author_deliveries.clear();
+        self.pull_authors.clear();
+        self.campaigns.clear();
+        self.feed_pages.clear();
+        self.related_posts.clear();
+        self.view_sketches.clear();
+        self.reach.clear();
+        self.delivered.clear();
+        self.delivery_markers.clear();
+        self.receipts.clear();
+        self.typeahead.clear();
+        self.notifications.clear();
+        self.notifications_read.clear();
+        self.digests.clear();
+        self.saved_searches.clear();
+        self.activity.clear();
+        self.profile_views.clear();
+        self.login_history.clear();
+        self.held_posts.clear();
+        self.held_users.clear();
+        self.takedowns.clear();
+        self.viewer_countries.clear();
+        self.account_records.clear();
+        self.graph.clear();
+    }
+
+    pub fn find_feed_item(&self, user_id: &UserId, post_id: &PostId) -> Option<NewsFeedItem> {
+        self.load_feed(user_id, true);
+        let pushed = self.news_feeds
+            .get(user_id)
+            .and_then(|feed| feed.iter().find(|item| &item.post_id == post_id).cloned());
+        pushed.or_else(|| {
+            if self.pull_authors.is_empty() {
+                return None;
+            }
+            self.home_feed(user_id).into_iter().find(|item| &item.post_id == post_id)
+        })
+    }
+
+    pub fn get_feed_position(&self, user_id: &UserId) -> Option<FeedPosition> {
+        self.feed_positions.get(user_id).map(|entry| entry.clone())
+    }
+
+    pub fn set_feed_position(&self, user_id: &UserId, position: FeedPosition) {
+        self.feed_positions.insert(user_id.clone(), position);
+    }
+
+    // Reads an evicted post back into the cache before it's changed in place
+    pub fn ensure_post_cached(&self, post_id: &PostId) {
+        if !self.posts.contains_key(post_id) {
+            self.load_post(post_id);
+        }
+    }
+
+    // Whether the post exists at all, held or not
+    pub fn post_exists(&self, post_id: &PostId) -> bool {
+        self.posts.contains_key(post_id) || self.load_post(post_id).is_some()
+    }
+
+    // Reads a post the cache doesn't have from storage, and keeps it
+    pub fn load_post(&self, post_id: &PostId) -> Option<Post> {
+        let post = match self.storage.as_ref()?.get_post(post_id) {
+            Ok(post) => post?,
+            Err(e) => {
+                warn!("storage: failed to load post {}: {}", post_id, e);
+                return None;
+            }
+        };
+        self.cache_post(post.clone());
+        Some(post)
+    }
+
+    pub fn cache_post(&self, post: Post) {
+        let now = now_millis();
+        // Popular posts go to hot cache, which drops its least recently used
+        // posts once it's full
+        if post.like_count > 100 {
+            self.hot_cache.insert(post.id.clone(), post.clone());
+            self.eviction.hot_cache.touch(&post.id, now);
+            self.eviction
+                .hot_cache
+                .trim(self.hot_cache.len(), |post_id| self.hot_cache.remove(post_id).is_some());
+        }
+        self.eviction.posts.touch(&post.id, now);
+        self.posts.insert(post.id.clone(), post);
+    }
+
+    pub fn list_emojis(&self) -> Vec<CustomEmoji> {
+        let mut emojis: Vec<CustomEmoji> =
+            self.emojis.iter().map(|entry| entry.value().clone()).collect();
+        emojis.sort_by(|a, b| a.shortcode.cmp(&b.shortcode));
+        emojis
+    }
+
+    pub fn set_emoji(&self, emoji: CustomEmoji) -> Option<CustomEmoji> {
+        self.emojis.insert(emoji.shortcode.clone(), emoji)
+    }
+
+    pub fn remove_emoji(&self, shortcode: &str) -> Option<CustomEmoji> {
+        self.emojis.remove(shortcode).map(|(_, emoji)| emoji)
+    }
+
+    pub fn has_co_author_request(&self, user_id: &UserId, post_id: &PostId) -> bool {
+        self.co_author_requests
+            .get(user_id)
+            .is_some_and(|posts| posts.contains(post_id))
+    }
+
+    pub fn unindex_hashtags(&self, post_id: &PostId, tags: &[TagId]) {
+        for tag in tags {
+            if let Some(mut items) = self.hashtags.get_mut(tag) {
+                items.retain(|item| &item.post_id != post_id);
+            }
+        }
+        self.hashtags.retain(|_, items| !items.is_empty());
+    }
+
+    // Hashtags by how many posts used them since `since`, most first
+    pub fn trending_hashtags(&self, since: u64, limit: usize) -> Vec<(TagId, usize)> {
+        let mut counts: Vec<(TagId, usize)> = self
+            .hashtags
+            .iter()
+            .map(|entry| {
+                let recent = entry.value().iter().take_while(|item| item.timestamp >= since).count();
+                (entry.key().clone(), recent)
+            })
+            .filter(|(_, recent)| *recent > 0)
+            .collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        counts.truncate(limit);
+        counts
+    }
+
+    pub fn article_body(&self, post_id: &PostId) -> Option<String> {
+        if let Some(markdown) = self.articles.get(post_id) {
+            return Some(markdown.clone());
+        }
+        let markdown = match self.storage.as_ref()?.get_article(post_id) {
+            Ok(markdown) => markdown?,
+            Err(e) => {
+                warn!("storage: failed to load article {}: {}", post_id, e);
+                return None;
+            }
+        };
+        self.articles.insert(post_id.clone(), markdown.clone());
+        Some(markdown)
+    }
+
+    pub fn event_attendees(&self, post_id: &PostId) -> Vec<UserId> {
+        self.rsvps
+            .get(post_id)
+            .map(|rsvps| rsvps.attendees())
+            .unwrap_or_default()
+    }
+
+    pub fn get_post_count(&self, user_id: &UserId) -> usize {
+        self.user_posts.get(user_id).map(|posts| posts.len()).unwrap_or(0)
+    }
+
+    // Reads a user the cache doesn't have from storage, and keeps it
+    pub fn load_user(&self, user_id: &UserId) -> Option<User> {
+        let user = match self.storage.as_ref()?.get_user(user_id) {
+            Ok(user) => user?,
+            Err(e) => {
+                warn!("storage: failed to load user {}: {}", user_id, e);
+                return None;
+            }
+        };
+        self.cache_user(user.clone());
+        Some(user)
+    }
+
+    pub fn set_user(&self, user: User) {
+        self.persist("user", |storage| storage.set_user(&user));
+        self.cache_user(user);
+    }
+
+    // Stores the user and indexes their username
+    pub fn cache_user(&self, user: User) {
+        self.usernames.insert(user.username.to_lowercase(), user.id.clone());
+        self.typeahead.add_username(&user.username, &user.id);
+        self.eviction.users.touch(&user.id, now_millis());
+        if let Some(previous) = self.users.insert(user.id.clone(), user.clone())
+            && previous.username.to_lowercase() != user.username.to_lowercase()
+        {
+            self.typeahead.remove_username(&previous.username, &user.id);
+        }
+    }
+
+    pub fn account_state(&self, user_id: &UserId) -> AccountState {
+        self.account_record(user_id).state
+    }
+
+    // For the checks on each request and login: a shared backend is read
+    // every time, since another instance may have suspended the account, and
+    // a record that can't be read is an error rather than active
+    pub fn checked_account_state(&self, user_id: &UserId) -> Result<AccountState, String> {
+        let shared = self.storage.as_ref().is_some_and(|storage| storage.shared());
+        Ok(self.load_account_record(user_id, shared)?.unwrap_or_default().state)
+    }
+
+    pub fn load_account_record(&self, user_id: &UserId, fresh: bool) -> Result<Option<AccountRecord>, String> {
+        if let Some(storage) = &self.storage
+            && (fresh || !self.account_records.contains_key(user_id))
+            && let Some(record) = storage.get_account(user_id)?
+        {
+            self.account_records.insert(user_id.clone(), record.clone());
+            return Ok(Some(record));
+        }
+        Ok(self.account_records.get(user_id).map(|record| record.clone()))
+    }
+
+    pub fn account_region(&self, user_id: &UserId) -> Option<Region> {
+        self.account_records
+            .get(user_id)
+            .and_then(|record| record.region.clone())
+    }
+
+    // Resolves current usernames, then old ones still within their grace period
+    pub fn resolve_username(&self, username: &str) -> Option<UsernameLookup> {
+        let key = username.to_lowercase();
+        if let Some(user_id) = self.usernames.get(&key) {
+            return Some(UsernameLookup {
+                user_id: user_id.clone(),
+                moved: false,
+            });
+        }
+        self.active_redirect(&key).map(|redirect| UsernameLookup {
+            user_id: redirect.user_id,
+            moved: true,
+        })
+    }
+
+    pub fn active_redirect(&self, key: &str) -> Option<UsernameRedirect> {
+        let redirect = self.username_redirects.get(key)?.clone();
+        if redirect.expires_at > now_millis() {
+            Some(redirect)
+        } else {
+            self.username_redirects.remove(key);
+            None
+        }
+    }
+
+    // The user's timezone, or UTC when they haven't set one
+    pub fn user_zone(&self, user_id: &UserId) -> Arc<Zone> {
+        self.get_user(user_id)
+            .and_then(|user| user.timezone.as_deref().and_then(|name| self.timezones.get(name)))
+            .unwrap_or_else(|| Arc::new(Zone::utc()))
+    }
+
+    pub fn set_preferences(&self, user_id: &UserId, preferences: UserPreferences) {
+        self.preferences.insert(user_id.clone(), preferences);
+        self.invalidate_feed_pages(user_id);
+    }
+
+    pub fn recent_logins(&self, user_id: &UserId) -> Vec<LoginRecord> {
+        self.login_history
+            .get(user_id)
+            .map(|history| history.recent())
+            .unwrap_or_default()
+    }
+
+    pub fn notifications_read_at(&self, user_id: &UserId) -> u64 {
+        self.notifications_read.get(user_id).map_or(0, |read_at| *read_at)
+    }
+
+    // Marks the notifications created up to `through` read. The mark only
+    // moves forward, so a stale client can't make read ones unread.
+    pub fn mark_notifications_read(&self, user_id: &UserId, through: u64) {
+        let mut read_at = self.notifications_read.entry(user_id.clone()).or_default();
+        *read_at = (*read_at).max(through);
+    }
+
+    pub fn unread_notifications(&self, user_id: &UserId) -> usize {
+        let read_at = self.notifications_read_at(user_id);
+        self.notifications
+            .get(user_id)
+            .map_or(0, |inbox| inbox.iter().filter(|notification| notification.created_at > read_at).count())
+    }
+
+    // Saved searches
+    pub fn get_saved_searches(&self, user_id: &UserId) -> Vec<SavedSearch> {
+        self.saved_searches
+            .get(user_id)
+            .map(|searches| searches.clone())
+            .unwrap_or_default()
+    }
+
+    // Fails with a conflict if the same query is saved already, or the
+    // account has as many as it may keep
+    pub fn add_saved_search(&self, user_id: &UserId, search: SavedSearch) -> Result<(), &'static str> {
+        let mut searches = self.saved_searches.entry(user_id.clone()).or_default();
+        if searches.iter().any(|existing| existing.query == search.query) {
+            return Err("already_saved");
+        }
+        if searches.len() >= MAX_SAVED_SEARCHES {
+            return Err("too_many_saved_searches");
+        }
+        searches.push(search);
+        Ok(())
+    }
+
+    pub fn remove_saved_search(&self, user_id: &UserId, search_id: &str) -> bool {
+        let Some(mut searches) = self.saved_searches.get_mut(user_id) else {
+            return false;
+        };
+        let before = searches.len();
+        searches.retain(|search| search.id != search_id);
+        searches.len() < before
+    }
+
+    pub fn saved_search_owners(&self) -> Vec<UserId> {
+        self.saved_searches
+            .iter()
+            .filter(|entry| !entry.value().is_empty())
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    // Moves the search's watermark; a search deleted meanwhile stays deleted
+    pub fn mark_saved_search_checked(&self, user_id: &UserId, search_id: &str, through: u64, matched: bool) {
+        if let Some(mut searches) = self.saved_searches.get_mut(user_id)
+            && let Some(search) = searches.iter_mut().find(|search| search.id == search_id)
+        {
+            search.checked_through = search.checked_through.max(through);
+            if matched {
+                search.last_match_at = Some(now_millis());
+            }
+        }
+    }
+
+    pub fn get_notifications(&self, user_id: &UserId) -> Vec<Notification> {
+        self.notifications
+            .get(user_id)
+            .map(|inbox| inbox.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // Legal holds and takedowns
+    pub fn is_held(&self, post: &Post) -> bool {
+        self.held_posts.contains_key(&post.id) || self.held_users.contains_key(&post.user_id)
+    }
+
+    pub fn is_user_held(&self, user_id: &UserId) -> bool {
+        self.held_users.contains_key(user_id)
+    }
+
+    // Whether logged-out visitors may see the account and its posts: it
+    // exists, is active and not held, and hasn't opted out
+    pub fn is_public_account(&self, user_id: &UserId) -> bool {
+        self.get_user(user_id).is_some()
+            && !self.is_user_held(user_id)
+            && self.account_state(user_id) == AccountState::Active
+            && !self.get_preferences(user_id).hide_from_logged_out
+    }
+
+    pub fn place_hold(&self, kind: HoldKind, id: &str, hold: LegalHold) {
+        match kind {
+            HoldKind::Post => {
+                self.held_posts.insert(PostId::new(id), hold);
+            }
+            HoldKind::User => {
+                self.held_users.insert(UserId::new(id), hold);
+            }
+        }
+        self.invalidate_all_feed_pages();
+    }
+
+    pub fn release_hold(&self, kind: HoldKind, id: &str) -> Option<LegalHold> {
+        let released = match kind {
+            HoldKind::Post => self.held_posts.remove(id).map(|(_, hold)| hold),
+            HoldKind::User => self.held_users.remove(id).map(|(_, hold)| hold),
+        };
+        self.invalidate_all_feed_pages();
+        released
+    }
+
+    pub fn holds(&self) -> Vec<HoldEntry> {
+        let posts = self.held_posts.iter().map(|entry| HoldEntry {
+            kind: HoldKind::Post,
+            id: entry.key().to_string(),
+            hold: entry.value().clone(),
+        });
+        let users = self.held_users.iter().map(|entry| HoldEntry {
+            kind: HoldKind::User,
+            id: entry.key().to_string(),
+            hold: entry.value().clone(),
+        });
+        let mut holds: Vec<HoldEntry> = posts.chain(users).collect();
+        holds.sort_by_key(|entry| entry.hold.placed_at);
+        holds
+    }
+
+    pub fn set_takedown(&self, post_id: &PostId, takedown: Takedown) {
+        self.takedowns.insert(post_id.clone(), takedown);
+        self.invalidate_all_feed_pages();
+    }
+
+    pub fn lift_takedown(&self, post_id: &PostId) -> Option<Takedown> {
+        let lifted = self.takedowns.remove(post_id).map(|(_, takedown)| takedown);
+        self.invalidate_all_feed_pages();
+        lifted
+    }
+
+    pub fn viewer_country(&self, user_id: &UserId) -> Option<String> {
+        self.viewer_countries.get(user_id).map(|country| country.clone())
+    }
+
+    pub fn set_viewer_country(&self, user_id: &UserId, country: &str) {
+        let country = country.trim().to_ascii_uppercase();
+        if self.viewer_countries.get(user_id).is_none_or(|known| *known != country) {
+            self.viewer_countries.insert(user_id.clone(), country);
+        }
+    }
+
+    pub fn activity_stats(&self, user_id: &UserId, weeks: u64) -> ActivityStats {
+        self.activity
+            .get(user_id)
+            .map(|log| log.stats(now_millis(), weeks))
+            .unwrap_or_else(|| ActivityLog::default().stats(now_millis(), weeks))
+    }
+
+    // Profile Views
+    // Visits to one's own profile aren't counted. A counted visit is also a
+    // weak sign of interest in the owner's posts.
+    pub fn record_profile_visit(&self, viewer_id: &UserId, owner_id: &UserId) {
+        if viewer_id == owner_id {
+            return;
+        }
+        let now = now_millis();
+        let privacy = self.get_preferences(viewer_id).profile_visits;
+        let counted = self
+            .profile_views
+            .entry(owner_id.clone())
+            .or_default()
+            .record(viewer_id, privacy, now);
+        if counted {
+            self.interests
+                .entry(viewer_id.clone())
+                .or_default()
+                .record_author(owner_id, Engagement::ProfileVisit, now);
+        }
+    }
+
+    pub fn profile_view_stats(&self, user_id: &UserId, days: u64) -> ProfileViewStats {
+        self.profile_views
+            .get(user_id)
+            .map(|views| views.stats(now_millis(), days))
+            .unwrap_or_else(|| ProfileViews::default().stats(now_millis(), days))
+    }
+
+    // Marks the post liked for the user, without counting it. False if it
+    // already was.
+    pub fn record_like(&self, user_id: &UserId, post_id: &PostId) -> bool {
+        // Likers decide, since a user's actions may have been evicted
+        let newly_liked = self
+            .likers
+            .entry(post_id.clone())
+            .or_default()
+            .insert(user_id.clone());
+        // Record user action
+        self.actions
+            .entry(user_id.clone())
+            .or_default()
+            .insert(post_id.clone(), true);
+        self.eviction.actions.touch(user_id, now_millis());
+        if newly_liked {
+            // The liker's cached pages would still show the post as not liked
+            self.invalidate_feed_pages(user_id);
+        }
+        newly_liked
+    }
+
+    pub fn forget_like(&self, user_id: &UserId, post_id: &PostId) -> bool {
+        if let Some(mut actions) = self.actions.get_mut(user_id) {
+            actions.remove(post_id);
+            self.eviction.actions.touch(user_id, now_millis());
+        }
+        let liked = self
+            .likers
+            .get_mut(post_id)
+            .is_some_and(|mut likers| likers.remove(user_id));
+        if liked {
+            self.invalidate_feed_pages(user_id);
+        }
+        liked
+    }
+
+    // Moves the like counter, then the post cache. Counts from before a
+    // restart may not include a like being taken back, as who liked what
+    // isn't stored, so it stops at zero.
+    pub fn count_like(&self, post_id: &PostId, by: i32) {
+        let mut counters = self.counters_entry(post_id);
+        counters.likes = counters.likes.saturating_add_signed(by);
+        counters.updated_at = now_millis();
+        self.store_counts(post_id, &counters);
+    }
+
+    pub fn record_view(&self, post_id: &PostId, viewer_id: &UserId) {
+        self.view_sketches
+            .entry(post_id.clone())
+            .or_default()
+            .insert(viewer_id);
+    }
+
+    pub fn get_reach(&self, post_id: &PostId) -> Reach {
+        self.reach.get(post_id).map(|reach| *reach).unwrap_or_default()
+    }
+
+    pub fn unique_viewers(&self, post_id: &PostId) -> u64 {
+        self.view_sketches
+            .get(post_id)
+            .map(|sketch| sketch.estimate())
+            .unwrap_or(0)
+    }
+
+    // Live counters for a post, starting from the counts stored on the post
+    // when the counters were compacted away
+    pub fn counters_entry(&self, post_id: &PostId) -> dashmap::mapref::one::RefMut<'_, PostId, Counters> {
+        if !self.counters.contains_key(post_id) {
+            self.ensure_post_cached(post_id);
+        }
+        self.counters.entry(post_id.clone()).or_insert_with(|| {
+            self.posts
+                .get(post_id)
+                .map(|post| Counters {
+                    likes: post.like_count,
+                    replies: post.reply_count,
+                    updated_at: 0,
+                })
+                .unwrap_or_default()
+        })
+    }
+
+    // Copies the counts onto the cached post. Storage gets each change as it
+    // happens instead, through persist_counts.
+    pub fn store_counts(&self, post_id: &PostId, counters: &Counters) {
+        if let Some(mut post_entry) = self.posts.get_mut(post_id) {
+            post_entry.like_count = counters.likes;
+            post_entry.reply_count = counters.replies;
+        }
+        if let Some(mut hot_post_entry) = self.hot_cache.get_mut(post_id) {
+            hot_post_entry.like_count = counters.likes;
+            hot_post_entry.reply_count = counters.replies;
+        }
+    }
+
+    // With a backend that keeps likers, a like the cache hasn't seen may
+    // still be stored, so handlers leave the decision to apply_like
+    pub fn decides_likes(&self) -> bool {
+        self.storage.as_ref().is_none_or(|storage| !storage.shared())
+    }
+
+    // Folds live counters into the cached posts and drops the counters of
+    // posts with no activity since `cold_before`. Returns (folded, pruned).
+    pub fn compact_counters(&self, cold_before: u64) -> (usize, usize) {
+        let mut folded = 0;
+        let mut cold = Vec::new();
+        for entry in self.counters.iter() {
+            self.store_counts(entry.key(), entry.value());
+            folded += 1;
+            if entry.updated_at < cold_before {
+                cold.push(entry.key().clone());
+            }
+        }
+
+        // Recheck under the lock: a like may have landed since the scan
+        let pruned = cold
+            .iter()
+            .filter(|post_id| {
+                self.counters
+                    .remove_if(*post_id, |_, counters| counters.updated_at < cold_before)
+                    .is_some()
+            })
+            .count();
+        (folded, pruned)
+    }
+
+    // Drops what another instance changed in shared storage, so the next
+    // read goes back to storage. Feed pages built from it go too.
+    pub fn apply_change(&self, change: storage::Change) {
+        match change {
+            storage::Change::Post(post_id) => {
+                self.posts.remove(&post_id);
+                self.hot_cache.remove(&post_id);
+                self.counters.remove(&post_id);
+                self.eviction.posts.forget(&post_id);
+                self.eviction.hot_cache.forget(&post_id);
+                self.invalidate_all_feed_pages();
+            }
+            storage::Change::User(user_id) => {
+                self.users.remove(&user_id);
+                self.eviction.users.forget(&user_id);
+            }
+            storage::Change::Feed(user_id) => {
+                self.news_feeds.remove(&user_id);
+                self.feeds_loaded.remove(&user_id);
+                self.invalidate_feed_pages(&user_id);
+            }
+        }
+    }
+
+    // Drops entries idle past their map's TTL, returning how many. Posts and
+    // users are only dropped when storage can give them back.
+    pub fn evict_idle(&self, now: u64) -> usize {
+        self.eviction.refresh();
+        let mut evicted = 0;
+        if self.storage.is_some() {
+            evicted += self.eviction.posts.expire(now, |post_id| self.posts.remove(post_id).is_some());
+            evicted += self.eviction.users.expire(now, |user_id| self.users.remove(user_id).is_some());
+        }
+        evicted += self.eviction.actions.expire(now, |user_id| self.actions.remove(user_id).is_some());
+        evicted += self.eviction.hot_cache.expire(now, |post_id| self.hot_cache.remove(post_id).is_some());
+        evicted
+    }
+
+    pub fn eviction_metrics(&self) -> String {
+        self.eviction.render_metrics()
+    }
+
+    // Post read sources and feed lengths
+    pub fn read_metrics(&self) -> String {
+        let mut out = String::new();
+        self.post_reads.render(&mut out);
+        cache_stats::render_feed_lengths(&mut out, self.news_feeds.iter().map(|feed| feed.len()));
+        out
+    }
+
+    // Posts past their author's retention period and not under legal hold.
+    // Evicted posts are only in storage, so then storage is scanned instead.
+    pub fn expired_posts(&self, policy: &RetentionPolicy, now: u64) -> Vec<PostId> {
+        let expired = |post: &Post| {
+            policy
+                .cutoff(&post.user_id, now)
+                .is_some_and(|cutoff| post.timestamp < cutoff)
+                && !self.is_held(post)
+        };
+        if self.eviction.posts.expires()
+            && let Some(storage) = &self.storage
+        {
+            match storage.posts() {
+                Ok(posts) => return posts.into_iter().filter(|post| expired(post)).map(|post| post.id).collect(),
+                Err(e) => warn!("storage: failed to list posts for retention: {}", e),
+            }
+        }
+        self.posts
+            .iter()
+            .filter(|post| expired(post))
+            .map(|post| post.key().clone())
+            .collect()
+    }
+
+    // Related posts computed at or after `fresh_after`
+    pub fn get_related(&self, post_id: &PostId, fresh_after: u64) -> Option<Vec<RelatedPost>> {
+        self.related_posts
+            .get(post_id)
+            .filter(|cached| cached.computed_at >= fresh_after)
+            .map(|cached| cached.posts.clone())
+    }
+
+    pub fn put_related(&self, post_id: &PostId, posts: Vec<RelatedPost>) {
+        self.related_posts.insert(
+            post_id.clone(),
+            CachedRelated {
+                computed_at: now_millis(),
+                posts,
+            },
+        );
+    }
+}
+
+impl Cache for CacheLayer {
+    fn graph(&self) -> &SocialGraph {
+        &self.graph
+    }
+
+    fn typeahead(&self) -> &Typeahead {
+        &self.typeahead
+    }
+
+    fn feed_locks(&self) -> &FeedLocks {
+        &self.feed_locks
+    }
+
+    fn home_feed(&self, user_id: &UserId) -> Vec<NewsFeedItem> {
+        let pushed = self.get_news_feed(user_id);
+        if self.pull_authors.is_empty() {
+            return pushed;
+        }
+        let mut pulled = self.pulled_items(user_id, &pushed);
+        if pulled.is_empty() {
+            return pushed;
+        }
+        pulled.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then_with(|| b.post_id.cmp(&a.post_id)));
+
+        let mut feed = Vec::with_capacity(pushed.len() + pulled.len());
+        let mut pulled = pulled.into_iter().peekable();
+        for item in pushed {
+            while let Some(newer) = pulled.next_if(|pulled_item| pulled_item.timestamp > item.timestamp) {
+                feed.push(newer);
+            }
+            feed.push(item);
+        }
+        feed.extend(pulled);
+        feed.truncate(MAX_FEED_ITEMS);
+        feed
+    }
+
+    fn mark_pull_author(&self, user_id: &UserId) {
+        self.pull_authors.entry(user_id.clone()).or_insert_with(now_millis);
+    }
+
+    fn add_to_news_feed(&self, user_id: &UserId, item: NewsFeedItem) -> bool {
+        if self.is_hidden(user_id, &item.post_id) {
+            return false;
+        }
+
+        let post_id = item.post_id.clone();
+        self.load_feed(user_id, false);
+        let stored = {
+            let mut delivered = self.delivered.entry(user_id.clone()).or_default();
+            let mut feed = self.news_feeds.entry(user_id.clone()).or_default();
+            // The marker is exact until it expires, even once the item has
+            // left the feed. Past that, only a filter hit (or a false
+            // positive) needs the feed scan.
+            if self.step_done(&post_id, user_id, Step::Delivered)
+                || (delivered.might_contain(&post_id) && feed.iter().any(|existing| existing.post_id == post_id))
+            {
+                return false;
+            }
+            delivered.insert(&post_id);
+            feed.push_front(item.clone());
+            self.mark_step(&post_id, user_id, Step::Delivered);
+
+            // Keep only latest 1000 items
+            if feed.len() > MAX_FEED_ITEMS {
+                feed.truncate(MAX_FEED_ITEMS);
+            }
+            self.feed_for_storage(&feed)
+        };
+        if let Some(stored) = stored {
+            self.persist("feed", |storage| storage.add_to_feed(user_id, &[item], &stored));
+        }
+        self.invalidate_feed_pages(user_id);
+        self.feed_updates.publish(user_id, &post_id);
+        true
+    }
+
+    fn backfill_news_feed(&self, user_id: &UserId, items: Vec<NewsFeedItem>) -> usize {
+        self.load_feed(user_id, false);
+        let mut added = Vec::new();
+        let stored = {
+            let mut delivered = self.delivered.entry(user_id.clone()).or_default();
+            let mut feed = self.news_feeds.entry(user_id.clone()).or_default();
+            for item in items {
+                if self.is_hidden(user_id, &item.post_id)
+                    || (delivered.might_contain(&item.post_id)
+                        && feed.iter().any(|existing| existing.post_id == item.post_id))
+                {
+                    continue;
+                }
+                delivered.insert(&item.post_id);
+                // Ahead of the first older item; the feed is newest first
+                let at = feed
+                    .iter()
+                    .position(|existing| existing.timestamp < item.timestamp)
+                    .unwrap_or(feed.len());
+                feed.insert(at, item.clone());
+                added.push(item);
+            }
+            if added.is_empty() {
+                return 0;
+            }
+            if feed.len() > MAX_FEED_ITEMS {
+                feed.truncate(MAX_FEED_ITEMS);
+            }
+            self.feed_for_storage(&feed)
+        };
+        if let Some(stored) = stored {
+            self.persist("feed", |storage| storage.add_to_feed(user_id, &added, &stored));
+        }
+        self.invalidate_feed_pages(user_id);
+        added.len()
+    }
+
+    fn step_done(&self, post_id: &PostId, user_id: &UserId, step: Step) -> bool {
+        self.delivery_markers
+            .get(post_id)
+            .is_some_and(|marker| marker.is_done(user_id, step))
+    }
+
+    fn mark_step(&self, post_id: &PostId, user_id: &UserId, step: Step) -> bool {
+        self.delivery_markers
+            .entry(post_id.clone())
+            .or_insert_with(|| DeliveryMarker::new(now_millis()))
+            .mark(user_id, step)
+    }
+
+    fn start_receipt(&self, post_id: &PostId, author_id: &UserId, published_at: u64, followers: u32) {
+        self.receipts
+            .entry(post_id.clone())
+            .or_insert_with(|| DeliveryReceipt::new(author_id.clone(), published_at, followers));
+    }
+
+    fn record_receipt(&self, post_id: &PostId, outcome: Outcome, count: u32, at: u64) -> bool {
+        match self.receipts.get_mut(post_id) {
+            Some(mut receipt) => {
+                receipt.record(outcome, count, at);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn get_feed_page(&self, user_id: &UserId, key: &str, fresh_after: u64) -> Option<Vec<HydratedPost>> {
+        self.feed_pages.get(user_id).and_then(|cached| {
+            cached
+                .pages
+                .iter()
+                .find(|page| page.key == key && page.cached_at >= fresh_after)
+                .map(|page| page.posts.clone())
+        })
+    }
+
+    fn put_feed_page(&self, user_id: &UserId, key: String, posts: Vec<HydratedPost>, fresh_after: u64) {
+        let mut cached = self.feed_pages.entry(user_id.clone()).or_default();
+        cached
+            .pages
+            .retain(|page| page.key != key && page.cached_at >= fresh_after);
+        cached.pages.push(CachedPage {
+            key,
+            cached_at: now_millis(),
+            posts,
+        });
+        if cached.pages.len() > MAX_CACHED_PAGES {
+            cached.pages.remove(0);
+        }
+    }
+
+    fn is_hidden(&self, user_id: &UserId, post_id: &PostId) -> bool {
+        self.hidden_posts
+            .get(user_id)
+            .is_some_and(|hidden| hidden.contains(post_id))
+    }
+
+    fn has_blocked(&self, blocker_id: &UserId, user_id: &UserId) -> bool {
+        self.blocks
+            .get(blocker_id)
+            .is_some_and(|blocked| blocked.contains(user_id))
+    }
+
+    fn post_restriction(&self, viewer: &ViewerContext, post: &Post) -> Option<PostUnavailable> {
+        self.restriction(viewer, &post.user_id)
+            .map(|reason| if post.anonymous { PostUnavailable::Missing } else { reason })
+    }
+
+    fn hidden_from(&self, viewer: &ViewerContext, author_id: &UserId) -> bool {
+        self.restriction(viewer, author_id).is_some()
+    }
+
+    fn post_for(&self, viewer: &ViewerContext, post_id: &PostId) -> Result<Post, PostUnavailable> {
+        match self.get_post(post_id) {
+            Some(post) => match self.post_restriction(viewer, &post) {
+                Some(reason) => Err(reason),
+                None => Ok(post),
+            },
+            None => match self.deleted_posts.get(post_id) {
+                Some(author_id) if !self.hidden_from(viewer, &author_id) => Err(PostUnavailable::Deleted),
+                _ => Err(PostUnavailable::Missing),
+            },
+        }
+    }
+
+    fn record_interest(&self, user_id: &UserId, post: &Post, engagement: Engagement, at: u64) {
+        if &post.user_id != user_id {
+            self.interests
+                .entry(user_id.clone())
+                .or_default()
+                .record_post(user_id, post, engagement, at);
+        }
+    }
+
+    fn get_interests(&self, user_id: &UserId) -> Interests {
+        self.interests
+            .get(user_id)
+            .map(|interests| interests.clone())
+            .unwrap_or_default()
+    }
+
+    fn get_negative_signals(&self, user_id: &UserId) -> Vec<NegativeSignal> {
+        self.negative_signals
+            .get(user_id)
+            .map(|signals| signals.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn impression_count(&self, user_id: &UserId, post_id: &PostId, day: u64) -> u16 {
+        self.impressions
+            .get(user_id)
+            .map(|log| log.count(post_id, day))
+            .unwrap_or(0)
+    }
+
+    fn record_impression(&self, user_id: &UserId, post_id: &PostId, day: u64) {
+        self.impressions
+            .entry(user_id.clone())
+            .or_default()
+            .record(post_id, day);
+    }
+
+    fn delivered_from(&self, user_id: &UserId, author_id: &UserId, day: u64) -> u16 {
+        self.author_deliveries
+            .get(user_id)
+            .map(|log| log.count(author_id, day))
+            .unwrap_or(0)
+    }
+
+    fn record_delivery(&self, user_id: &UserId, author_id: &UserId, day: u64) {
+        self.author_deliveries
+            .entry(user_id.clone())
+            .or_default()
+            .record(author_id, day);
+    }
+
+    fn list_campaigns(&self) -> Vec<Campaign> {
+        self.campaigns.iter().map(|entry| entry.clone()).collect()
+    }
+
+    fn record_campaign_impression(&self, campaign_id: &str, now: u64) -> Option<Campaign> {
+        let mut campaign = self.campaigns.get_mut(campaign_id)?;
+        if !campaign.is_deliverable(now) {
+            return None;
+        }
+        campaign.impressions += 1;
+        Some(campaign.clone())
+    }
+
+    fn clear_posts(&self) {
+        self.posts.clear();
+        self.hot_cache.clear();
+        self.deleted_posts.clear();
+        self.user_posts.clear();
+        self.co_author_requests.clear();
+        self.rsvps.clear();
+        self.hashtags.clear();
+        self.replies.clear();
+        self.threads.clear();
+        self.counters.clear();
+        self.actions.clear();
+        self.likers.clear();
+        self.related_posts.clear();
+        self.interests.clear();
+        self.typeahead.clear_hashtags();
+        self.eviction.posts.clear();
+        self.eviction.actions.clear();
+        self.eviction.hot_cache.clear();
+    }
+
+    fn clear_feeds(&self) {
+        self.news_feeds.clear();
+        self.feeds_loaded.clear();
+        self.delivered.clear();
+        self.delivery_markers.clear();
+        self.receipts.clear();
+        self.author_deliveries.clear();
+        self.pull_authors.clear();
+        self.feed_pages.clear();
+    }
+
+    fn recent_posts(&self, since: u64) -> Vec<Post> {
+        self.posts
+            .iter()
+            .filter(|entry| entry.timestamp >= since && !self.is_held(entry))
+            .map(|entry| entry.clone())
+            .collect()
+    }
+
+    fn get_post(&self, post_id: &PostId) -> Option<Post> {
+        let now = now_millis();
+        let post = match self.hot_cache.get(post_id) {
+            Some(hot) => {
+                self.eviction.hot_cache.touch(post_id, now);
+                self.post_reads.record(PostRead::Hot);
+                Some(hot.clone())
+            }
+            None => self.posts.get(post_id).map(|entry| {
+                self.post_reads.record(PostRead::Cached);
+                entry.clone()
+            }),
+        };
+        let post = match post {
+            Some(post) => {
+                self.eviction.posts.touch(post_id, now);
+                post
+            }
+            None => {
+                let loaded = self.load_post(post_id);
+                self.post_reads.record(match loaded {
+                    Some(_) => PostRead::Storage,
+                    None => PostRead::Missing,
+                });
+                loaded?
+            }
+        };
+        (!self.is_held(&post)).then_some(post)
+    }
+
+    fn set_post(&self, post: Post) {
+        self.persist("post", |storage| storage.set_post(&post));
+        self.cache_post(post);
+    }
+
+    fn restore_post(&self, mut post: Post) {
+        // Stored before posts kept their hashtags
+        if post.hashtags.is_empty() {
+            post.hashtags = content::extract_hashtags(&post.content);
+        }
+        self.index_hashtags(&post, &[]);
+        if let Some(parent_id) = &post.in_reply_to {
+            self.replies
+                .entry(parent_id.clone())
+                .or_default()
+                .push(post.id.clone());
+        }
+        self.add_user_post(&post.user_id, &post.id);
+        for co_author in &post.co_authors {
+            if co_author.accepted {
+                self.add_user_post(&co_author.user_id, &post.id);
+            }
+        }
+        self.request_co_authors(&post);
+        self.cache_post(post);
+    }
+
+    fn get_emoji(&self, shortcode: &str) -> Option<CustomEmoji> {
+        self.emojis.get(shortcode).map(|entry| entry.clone())
+    }
+
+    fn get_thread(&self, head_post_id: &PostId) -> Option<Vec<PostId>> {
+        self.threads.get(head_post_id).map(|entry| entry.clone())
+    }
+
+    fn set_thread(&self, head_post_id: &PostId, post_ids: Vec<PostId>) {
+        self.threads.insert(head_post_id.clone(), post_ids);
+    }
+
+    fn add_user_post(&self, user_id: &UserId, post_id: &PostId) {
+        self.user_posts
+            .entry(user_id.clone())
+            .or_default()
+            .push(post_id.clone());
+    }
+
+    fn request_co_authors(&self, post: &Post) {
+        for co_author in post.co_authors.iter().filter(|co_author| !co_author.accepted) {
+            self.co_author_requests
+                .entry(co_author.user_id.clone())
+                .or_default()
+                .push(post.id.clone());
+        }
+    }
+
+    fn pending_co_authorships(&self, user_id: &UserId) -> Vec<PostId> {
+        let mut posts = self.co_author_requests
+            .get(user_id)
+            .map(|posts| posts.clone())
+            .unwrap_or_default();
+        posts.reverse();
+        posts
+    }
+
+    fn answer_co_author(&self, post_id: &PostId, user_id: &UserId, accepted: bool) -> Option<Post> {
+        if let Some(mut posts) = self.co_author_requests.get_mut(user_id) {
+            posts.retain(|id| id != post_id);
+        }
+        let mut post = self.get_post(post_id)?;
+        if accepted {
+            post.co_authors
+                .iter_mut()
+                .filter(|co_author| &co_author.user_id == user_id)
+                .for_each(|co_author| co_author.accepted = true);
+        } else {
+            post.co_authors.retain(|co_author| &co_author.user_id != user_id);
+        }
+        self.replace_post(post.clone())?;
+        if accepted {
+            let mut posts = self.user_posts.entry(user_id.clone()).or_default();
+            if !posts.contains(post_id) {
+                let index = posts.partition_point(|id| {
+                    self.posts.get(id).is_some_and(|other| other.timestamp <= post.timestamp)
+                });
+                posts.insert(index, post_id.clone());
+            }
+        }
+        Some(post)
+    }
+
+    fn index_hashtags(&self, post: &Post, indexed: &[TagId]) {
+        for tag in post.hashtags.iter().filter(|tag| !indexed.contains(tag)) {
+            let mut items = self.hashtags.entry(tag.clone()).or_default();
+            let index = items.partition_point(|item| item.timestamp > post.timestamp);
+            items.insert(index, NewsFeedItem {
+                post_id: post.id.clone(),
+                timestamp: post.timestamp,
+            });
+            items.truncate(MAX_HASHTAG_ITEMS);
+        }
+    }
+
+    fn reindex_hashtags(&self, previous: &Post, post: &Post) {
+        let dropped: Vec<TagId> = previous
+            .hashtags
+            .iter()
+            .filter(|tag| !post.hashtags.contains(tag))
+            .cloned()
+            .collect();
+        self.unindex_hashtags(&post.id, &dropped);
+        self.index_hashtags(post, &previous.hashtags);
+    }
+
+    fn hashtag_post_ids(&self, tag: &TagId) -> Vec<PostId> {
+        self.hashtags
+            .get(tag)
+            .map(|items| items.iter().map(|item| item.post_id.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    fn set_article(&self, post_id: &PostId, markdown: String) {
+        self.persist("article", |storage| storage.set_article(post_id, &markdown));
+        self.articles.insert(post_id.clone(), markdown);
+    }
+
+    fn set_rsvp(&self, post_id: &PostId, user_id: &UserId, status: Option<RsvpStatus>) {
+        self.rsvps.entry(post_id.clone()).or_default().set(user_id, status);
+    }
+
+    fn rsvp_summary(&self, post_id: &PostId, viewer_id: Option<&UserId>) -> RsvpSummary {
+        self.rsvps
+            .get(post_id)
+            .map(|rsvps| rsvps.summary(viewer_id))
+            .unwrap_or_default()
+    }
+
+    fn get_user_post_ids(&self, user_id: &UserId) -> Vec<PostId> {
+        self.user_posts
+            .get(user_id)
+            .map(|posts| posts.clone())
+            .unwrap_or_default()
+    }
+
+    fn get_user(&self, user_id: &UserId) -> Option<User> {
+        match self.users.get(user_id).map(|entry| entry.clone()) {
+            Some(user) => {
+                self.eviction.users.touch(user_id, now_millis());
+                Some(user)
+            }
+            None => self.load_user(user_id),
+        }
+    }
+
+    fn create_user(&self, user: User) -> Result<(), UsernameError> {
+        let key = user.username.to_lowercase();
+        if self.active_redirect(&key).is_some() {
+            return Err(UsernameError::Taken);
+        }
+        match self.usernames.entry(key) {
+            dashmap::mapref::entry::Entry::Occupied(_) => return Err(UsernameError::Taken),
+            dashmap::mapref::entry::Entry::Vacant(entry) => {
+                entry.insert(user.id.clone());
+            }
+        }
+        self.typeahead.add_username(&user.username, &user.id);
+        self.persist("user", |storage| storage.set_user(&user));
+        self.eviction.users.touch(&user.id, now_millis());
+        self.users.insert(user.id.clone(), user);
+        Ok(())
+    }
+
+    fn account_record(&self, user_id: &UserId) -> AccountRecord {
+        self.load_account_record(user_id, false)
+            .unwrap_or_else(|e| {
+                warn!("storage: failed to read account {}: {}", user_id, e);
+                self.account_records.get(user_id).map(|record| record.clone())
+            })
+            .unwrap_or_default()
+    }
+
+    fn set_account_record(&self, user_id: &UserId, record: AccountRecord) {
+        self.persist("account", |storage| storage.set_account(user_id, &record));
+        self.account_records.insert(user_id.clone(), record);
+    }
+
+    fn find_user_id_by_username(&self, username: &str) -> Option<UserId> {
+        self.resolve_username(username).map(|lookup| lookup.user_id)
+    }
+
+    fn rename_user(
+        &self,
+        user_id: &UserId,
+        new_username: &str,
+        redirect_until: u64,
+    ) -> Result<User, UsernameError> {
+        let mut user = self.get_user(user_id).ok_or(UsernameError::UnknownUser)?;
+        let old_key = user.username.to_lowercase();
+        let new_key = new_username.to_lowercase();
+
+        if new_key != old_key {
+            // A name still redirecting elsewhere is reserved for its old owner
+            if self
+                .active_redirect(&new_key)
+                .is_some_and(|redirect| &redirect.user_id != user_id)
+            {
+                return Err(UsernameError::Taken);
+            }
+            match self.usernames.entry(new_key.clone()) {
+                dashmap::mapref::entry::Entry::Occupied(_) => return Err(UsernameError::Taken),
+                dashmap::mapref::entry::Entry::Vacant(entry) => {
+                    entry.insert(user_id.clone());
+                }
+            }
+            self.usernames.remove(&old_key);
+            self.username_redirects.remove(&new_key);
+            self.username_redirects.insert(
+                old_key,
+                UsernameRedirect {
+                    user_id: user_id.clone(),
+                    expires_at: redirect_until,
+                },
+            );
+        }
+
+        self.typeahead.remove_username(&user.username, user_id);
+        self.typeahead.add_username(new_username, user_id);
+        user.username = new_username.to_string();
+        user.username_changed_at = Some(now_millis());
+        self.persist("user", |storage| storage.set_user(&user));
+        self.eviction.users.touch(&user.id, now_millis());
+        self.users.insert(user.id.clone(), user.clone());
+        Ok(user)
+    }
+
+    fn get_preferences(&self, user_id: &UserId) -> UserPreferences {
+        self.preferences
+            .get(user_id)
+            .map(|entry| entry.clone())
+            .unwrap_or_default()
+    }
+
+    fn in_quiet_hours(&self, user_id: &UserId, now: u64) -> bool {
+        let Some(quiet_hours) = self.get_preferences(user_id).quiet_hours else {
+            return false;
+        };
+        let local = self.user_zone(user_id).local((now / 1000) as i64);
+        quiet_hours.contains(ClockTime::of(local))
+    }
+
+    fn user_locale(&self, user_id: &UserId, catalogs: &Catalogs) -> String {
+        catalogs.pick(None, self.get_preferences(user_id).language.as_deref())
+    }
+
+    fn is_following(&self, follower_id: &UserId, user_id: &UserId) -> bool {
+        self.graph.is_following(follower_id, user_id)
+    }
+
+    fn followers(&self, user_id: &UserId) -> Vec<(UserId, FollowEdge)> {
+        if let Some(storage) = &self.storage
+            && storage.shared()
+        {
+            match storage.followers(user_id) {
+                Ok(Some(followers)) => return followers,
+                Ok(None) => {}
+                Err(e) => warn!("storage: failed to load the followers of {}: {}", user_id, e),
+            }
+        }
+        self.graph.followers(user_id)
+    }
+
+    fn store_follow(&self, follower_id: &UserId, followed_id: &UserId) {
+        match self.graph.edge(follower_id, followed_id) {
+            Some(edge) => self.persist("follow", |storage| storage.set_follow(follower_id, followed_id, &edge)),
+            None => self.persist("follow", |storage| storage.remove_follow(follower_id, followed_id)),
+        }
+    }
+
+    fn record_login(&self, user_id: &UserId, context: LoginContext, two_factor: bool) -> LoginRecord {
+        self.login_history
+            .entry(user_id.clone())
+            .or_default()
+            .record(context, two_factor, now_millis())
+    }
+
+    fn add_notification(&self, user_id: &UserId, notification: Notification) {
+        let mut inbox = self.notifications.entry(user_id.clone()).or_default();
+        inbox.push_front(notification);
+        // Keep only the latest 200 notifications
+        inbox.truncate(200);
+    }
+
+    fn add_notification_once(&self, user_id: &UserId, notification: Notification) {
+        let repeated = self
+            .notifications
+            .get(user_id)
+            .is_some_and(|inbox| inbox.iter().any(|existing| existing.repeats(&notification)));
+        if !repeated {
+            self.add_notification(user_id, notification);
+        }
+    }
+
+    fn tombstone_for(&self, viewer_id: &UserId, post_id: &PostId) -> Option<Tombstone> {
+        if !self.takedowns.contains_key(post_id) {
+            return None;
+        }
+        self.tombstone_in(self.viewer_country(viewer_id).as_deref(), post_id)
+    }
+
+    fn tombstone_in(&self, country: Option<&str>, post_id: &PostId) -> Option<Tombstone> {
+        let takedown = self.takedowns.get(post_id)?;
+        takedown
+            .applies_to(country)
+            .then(|| Tombstone {
+                message: takedown.message.clone(),
+                jurisdictions: takedown.jurisdictions.clone(),
+            })
+    }
+
+    fn viewer(&self, user_id: &UserId) -> ViewerContext {
+        ViewerContext::User {
+            user_id: user_id.clone(),
+            country: self.viewer_country(user_id),
+        }
+    }
+
+    fn record_activity(&self, user_id: &UserId, activity: Activity) {
+        self.activity
+            .entry(user_id.clone())
+            .or_default()
+            .record(activity, now_millis());
+    }
+
+    fn can_reply(&self, user_id: &UserId, post: &Post) -> bool {
+        if *user_id == post.user_id {
+            return true;
+        }
+        match post.reply_policy {
+            ReplyPolicy::Everyone => true,
+            ReplyPolicy::Following => self.is_following(&post.user_id, user_id),
+            ReplyPolicy::Mentioned => post.mentions.iter().any(|id| id == user_id),
+        }
+    }
+
+    fn like_post(&self, user_id: &UserId, post_id: &PostId) -> bool {
+        let newly_liked = self.record_like(user_id, post_id);
+        if newly_liked {
+            self.count_like(post_id, 1);
+        }
+        newly_liked
+    }
+
+    fn unlike_post(&self, user_id: &UserId, post_id: &PostId) -> bool {
+        let unliked = self.forget_like(user_id, post_id);
+        if unliked {
+            self.count_like(post_id, -1);
+        }
+        unliked
+    }
+
+    fn add_reply(&self, post_id: &PostId, reply_id: &PostId) {
+        self.replies
+            .entry(post_id.clone())
+            .or_default()
+            .push(reply_id.clone());
+
+        let mut counters = self.counters_entry(post_id);
+        counters.replies += 1;
+        counters.updated_at = now_millis();
+        self.store_counts(post_id, &counters);
+    }
+
+    fn record_reach(&self, post_id: &PostId, channel: Channel) {
+        self.reach.entry(post_id.clone()).or_default().record(channel);
+    }
+
+    fn persist_counts(&self, post_id: &PostId, likes: i64, replies: i64) {
+        self.persist("counts", |storage| storage.add_counts(post_id, likes, replies));
+    }
+
+    fn apply_like(&self, user_id: &UserId, post_id: &PostId, liked: bool) -> bool {
+        let stored = self.storage.as_ref().and_then(|storage| {
+            let stored = if liked {
+                storage.add_like(post_id, user_id)
+            } else {
+                storage.remove_like(post_id, user_id)
+            };
+            stored.unwrap_or_else(|e| {
+                warn!("storage: failed to save like: {}", e);
+                None
+            })
+        });
+        let Some(changed) = stored else {
+            let changed = if liked {
+                self.like_post(user_id, post_id)
+            } else {
+                self.unlike_post(user_id, post_id)
+            };
+            if changed {
+                self.persist_counts(post_id, if liked { 1 } else { -1 }, 0);
+            }
+            return changed;
+        };
+        // The local likers follow storage either way, so the post shows as
+        // the user left it
+        if liked {
+            self.record_like(user_id, post_id);
+        } else {
+            self.forget_like(user_id, post_id);
+        }
+        if changed {
+            self.count_like(post_id, if liked { 1 } else { -1 });
+        }
+        changed
+    }
+
+    fn delete_post(&self, post_id: &PostId) -> Option<Post> {
+        self.ensure_post_cached(post_id);
+        let (_, post) = self.posts.remove(post_id)?;
+        self.eviction.posts.forget(post_id);
+        self.eviction.hot_cache.forget(post_id);
+        self.deleted_posts.insert(post_id.clone(), post.user_id.clone());
+        // Cached pages may hold it, in any feed
+        self.invalidate_all_feed_pages();
+        self.persist("post deletion", |storage| storage.remove_post(post_id));
+        self.hot_cache.remove(post_id);
+        self.counters.remove(post_id);
+        self.videos.remove(post_id);
+        self.view_sketches.remove(post_id);
+        self.reach.remove(post_id);
+        self.receipts.remove(post_id);
+        self.likers.remove(post_id);
+        self.rsvps.remove(post_id);
+        self.unindex_hashtags(post_id, &post.hashtags);
+        self.articles.remove(post_id);
+        self.related_posts.remove(post_id);
+        self.threads.remove(post_id);
+        self.replies.remove(post_id);
+        if let Some(parent_id) = &post.in_reply_to
+            && let Some(mut replies) = self.replies.get_mut(parent_id)
+        {
+            let before = replies.len();
+            replies.retain(|reply_id| reply_id != post_id);
+            if replies.len() < before {
+                drop(replies);
+                let mut counters = self.counters_entry(parent_id);
+                counters.replies = counters.replies.saturating_sub(1);
+                counters.updated_at = now_millis();
+                self.store_counts(parent_id, &counters);
+            }
+        }
+        if let Some(mut posts) = self.user_posts.get_mut(&post.user_id) {
+            posts.retain(|id| id != post_id);
+        }
+        for co_author in &post.co_authors {
+            let index = if co_author.accepted { &self.user_posts } else { &self.co_author_requests };
+            if let Some(mut posts) = index.get_mut(&co_author.user_id) {
+                posts.retain(|id| id != post_id);
+            }
+        }
+        Some(post)
+    }
+
+    fn replace_post(&self, post: Post) -> Option<Post> {
+        self.ensure_post_cached(&post.id);
+        let previous = self.posts.get(&post.id).map(|entry| entry.clone())?;
+        self.persist("post", |storage| storage.set_post(&post));
+        if let Some(mut hot) = self.hot_cache.get_mut(&post.id) {
+            *hot = post.clone();
+        }
+        self.related_posts.remove(&post.id);
+        self.eviction.posts.touch(&post.id, now_millis());
+        self.posts.insert(post.id.clone(), post);
+        self.invalidate_all_feed_pages();
+        Some(previous)
+    }
+
+    fn get_replies(&self, post_id: &PostId) -> Vec<PostId> {
+        self.replies
+            .get(post_id)
+            .map(|replies| replies.clone())
+            .unwrap_or_default()
+    }
+
+    fn latest_replies(&self, post_id: &PostId, count: usize) -> Vec<PostId> {
+        self.replies
+            .get(post_id)
+            .map(|replies| replies.iter().rev().take(count).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn has_liked(&self, user_id: &UserId, post_id: &PostId) -> bool {
+        self.likers
+            .get(post_id)
+            .is_some_and(|likers| likers.contains(user_id))
+    }
+
+    fn post_likers(&self, post_id: &PostId, max: usize) -> Vec<UserId> {
+        self.likers
+            .get(post_id)
+            .map(|likers| likers.iter().take(max).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn liked_posts(&self, user_id: &UserId, max: usize) -> Vec<PostId> {
+        self.actions
+            .get(user_id)
+            .map(|actions| {
+                self.eviction.actions.touch(user_id, now_millis());
+                actions
+                    .iter()
+                    .filter(|(_, liked)| **liked)
+                    .map(|(post_id, _)| post_id.clone())
+                    .take(max)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn get_counters(&self, post_id: &PostId) -> Counters {
+        if let Some(c) = self.counters.get(post_id) {
+            return Counters {
+                likes: c.likes,
+                replies: c.replies,
+                updated_at: c.updated_at,
+            };
+        }
+        // Compacted: the post carries the final counts
+        self.posts
+            .get(post_id)
+            .map(|post| Counters {
+                likes: post.like_count,
+                replies: post.reply_count,
+                updated_at: 0,
+            })
+            .unwrap_or_default()
+    }
+
+    fn get_video(&self, post_id: &PostId) -> Option<VideoStatus> {
+        self.videos.get(post_id).map(|entry| *entry)
+    }
+
+    fn set_video(&self, post_id: &PostId, status: VideoStatus) {
+        self.videos.insert(post_id.clone(), status);
+    }
+}
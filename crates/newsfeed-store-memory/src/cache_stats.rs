@@ -0,0 +1,111 @@
+use dashmap::DashMap;
+use serde::Serialize;
+use std::fmt::Write;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Upper bounds of the feed length buckets, in items; feeds are capped at
+// 1,000 so the last one holds them all
+const FEED_LENGTH_BUCKETS: [usize; 7] = [0, 10, 50, 100, 250, 500, 1_000];
+
+// Where a post read was answered from
+#[derive(Debug, Clone, Copy)]
+pub enum PostRead {
+    Hot,     // the hot cache
+    Cached,  // the posts map
+    Storage, // not cached; loaded back from storage
+    Missing, // nowhere
+}
+
+impl PostRead {
+    const ALL: [PostRead; 4] = [PostRead::Hot, PostRead::Cached, PostRead::Storage, PostRead::Missing];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            PostRead::Hot => "hot",
+            PostRead::Cached => "cached",
+            PostRead::Storage => "storage",
+            PostRead::Missing => "missing",
+        }
+    }
+}
+
+// Post reads by where they were answered from, for /metrics
+#[derive(Debug, Default)]
+pub struct PostReads {
+    counts: [AtomicU64; 4],
+}
+
+impl PostReads {
+    pub fn record(&self, read: PostRead) {
+        self.counts[read as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP news_feed_post_reads_total Post lookups, by where they were answered from.");
+        let _ = writeln!(out, "# TYPE news_feed_post_reads_total counter");
+        for read in PostRead::ALL {
+            let _ = writeln!(
+                out,
+                "news_feed_post_reads_total{{source=\"{}\"}} {}",
+                read.as_str(),
+                self.counts[read as usize].load(Ordering::Relaxed)
+            );
+        }
+    }
+}
+
+// Feeds by how many items they hold, as gauges read at scrape time
+pub fn render_feed_lengths(out: &mut String, lengths: impl Iterator<Item = usize>) {
+    let mut counts = [0u64; FEED_LENGTH_BUCKETS.len()];
+    let mut feeds = 0u64;
+    let mut items = 0u64;
+    for length in lengths {
+        if let Some(bucket) = FEED_LENGTH_BUCKETS.iter().position(|&bound| length <= bound) {
+            counts[bucket] += 1;
+        }
+        feeds += 1;
+        items += length as u64;
+    }
+    let _ = writeln!(out, "# HELP news_feed_feeds Feeds held in memory, by the most items they hold.");
+    let _ = writeln!(out, "# TYPE news_feed_feeds gauge");
+    let mut cumulative = 0;
+    for (bound, count) in FEED_LENGTH_BUCKETS.iter().zip(counts) {
+        cumulative += count;
+        let _ = writeln!(out, "news_feed_feeds{{le=\"{}\"}} {}", bound, cumulative);
+    }
+    let _ = writeln!(out, "news_feed_feeds{{le=\"+Inf\"}} {}", feeds);
+    let _ = writeln!(out, "# HELP news_feed_feed_items Items across all feeds held in memory.");
+    let _ = writeln!(out, "# TYPE news_feed_feed_items gauge");
+    let _ = writeln!(out, "news_feed_feed_items {}", items);
+}
+
+// Shard balance and write-lock contention for one DashMap
+#[derive(Debug, Serialize)]
+pub struct ShardStats {
+    name: &'static str,
+    shards: usize,
+    entries: usize,
+    max_shard_entries: usize,
+    // Share of samples that found a shard write-locked
+    contention: f64,
+}
+
+pub fn shard_stats<K: Eq + Hash, V>(name: &'static str, map: &DashMap<K, V>, rounds: usize) -> ShardStats {
+    let shards = map.shards();
+    let sizes: Vec<usize> = shards.iter().map(|shard| shard.read().len()).collect();
+
+    let mut locked = 0;
+    for _ in 0..rounds {
+        locked += shards.iter().filter(|shard| shard.is_locked_exclusive()).count();
+        std::thread::yield_now();
+    }
+
+    ShardStats {
+        name,
+        shards: shards.len(),
+        entries: sizes.iter().sum(),
+        max_shard_entries: sizes.iter().copied().max().unwrap_or(0),
+        contention: locked as f64 / (rounds * shards.len()).max(1) as f64,
+    }
+}
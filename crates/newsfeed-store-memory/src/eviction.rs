@@ -0,0 +1,177 @@
+use dashmap::DashMap;
+use std::fmt::Write;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use newsfeed_core::config::{Config, Settings};
+use newsfeed_core::ids::{PostId, UserId};
+
+// When each key of one cache map was last read or written. Keys idle past
+// the TTL are expired by the reaper; past `max_entries`, the least recently
+// used keys are dropped as new ones come in. Zero turns either off, and a
+// tracker with both off records nothing. The TTL follows reloads; entries
+// cached while it was off are tracked from their next use.
+#[derive(Debug)]
+pub struct Recency<K: Eq + Hash> {
+    ttl_millis: AtomicU64,
+    max_entries: usize,
+    last_used: DashMap<K, u64>,
+    expired: AtomicU64,
+    displaced: AtomicU64, // dropped to stay under max_entries
+}
+
+impl<K: Eq + Hash + Clone> Recency<K> {
+    pub fn new(ttl_secs: u64, max_entries: usize) -> Self {
+        Self {
+            ttl_millis: AtomicU64::new(ttl_secs.saturating_mul(1000)),
+            max_entries,
+            last_used: DashMap::new(),
+            expired: AtomicU64::new(0),
+            displaced: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set_ttl(&self, ttl_secs: u64) {
+        self.ttl_millis.store(ttl_secs.saturating_mul(1000), Ordering::Relaxed);
+    }
+
+    fn ttl_millis(&self) -> u64 {
+        self.ttl_millis.load(Ordering::Relaxed)
+    }
+
+    pub fn expires(&self) -> bool {
+        self.ttl_millis() > 0
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.expires() || self.max_entries > 0
+    }
+
+    pub fn touch(&self, key: &K, now: u64) {
+        if self.enabled() {
+            self.last_used.insert(key.clone(), now);
+        }
+    }
+
+    pub fn forget(&self, key: &K) {
+        self.last_used.remove(key);
+    }
+
+    pub fn clear(&self) {
+        self.last_used.clear();
+    }
+
+    // Calls `remove` for each key idle past the TTL, returning how many it
+    // removed. A key used again since the scan is kept.
+    pub fn expire(&self, now: u64, remove: impl Fn(&K) -> bool) -> usize {
+        let ttl_millis = self.ttl_millis();
+        if ttl_millis == 0 {
+            return 0;
+        }
+        let cutoff = now.saturating_sub(ttl_millis);
+        let idle: Vec<K> = self
+            .last_used
+            .iter()
+            .filter(|entry| *entry.value() < cutoff)
+            .map(|entry| entry.key().clone())
+            .collect();
+        let expired = idle
+            .iter()
+            .filter(|key| self.last_used.remove_if(*key, |_, used| *used < cutoff).is_some() && remove(key))
+            .count();
+        self.expired.fetch_add(expired as u64, Ordering::Relaxed);
+        expired
+    }
+
+    // Once the map holds more than `max_entries`, calls `remove` for its least
+    // recently used keys until it's back to 95% of the limit, so the sort
+    // isn't paid on every insert
+    pub fn trim(&self, len: usize, remove: impl Fn(&K) -> bool) -> usize {
+        if self.max_entries == 0 || len <= self.max_entries {
+            return 0;
+        }
+        let target = self.max_entries - self.max_entries / 20;
+        let mut by_use: Vec<(u64, K)> = self
+            .last_used
+            .iter()
+            .map(|entry| (*entry.value(), entry.key().clone()))
+            .collect();
+        by_use.sort_unstable_by_key(|(used, _)| *used);
+        let displaced = by_use
+            .into_iter()
+            .take(len - target)
+            .filter(|(_, key)| {
+                self.last_used.remove(key);
+                remove(key)
+            })
+            .count();
+        self.displaced.fetch_add(displaced as u64, Ordering::Relaxed);
+        displaced
+    }
+
+    fn render(&self, out: &mut String, map: &str) {
+        let _ = writeln!(
+            out,
+            "news_feed_cache_evictions_total{{map=\"{}\",reason=\"ttl\"}} {}",
+            map,
+            self.expired.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "news_feed_cache_evictions_total{{map=\"{}\",reason=\"size\"}} {}",
+            map,
+            self.displaced.load(Ordering::Relaxed)
+        );
+    }
+}
+
+// The trackers for the cache maps that would otherwise grow without bound
+#[derive(Debug)]
+pub struct CacheEviction {
+    pub posts: Recency<PostId>,
+    pub users: Recency<UserId>,
+    pub actions: Recency<UserId>, // liked posts, per user
+    pub hot_cache: Recency<PostId>,
+    settings: Arc<Settings>, // for the TTLs
+}
+
+impl CacheEviction {
+    pub fn new(config: &Config, settings: Arc<Settings>) -> Self {
+        let tunables = settings.current();
+        Self {
+            posts: Recency::new(tunables.post_ttl_secs, 0),
+            users: Recency::new(tunables.user_ttl_secs, 0),
+            actions: Recency::new(tunables.actions_ttl_secs, 0),
+            hot_cache: Recency::new(tunables.hot_cache_ttl_secs, config.hot_cache_max),
+            settings,
+        }
+    }
+
+    // Picks up TTLs changed by a reload
+    pub fn refresh(&self) {
+        let tunables = self.settings.current();
+        self.posts.set_ttl(tunables.post_ttl_secs);
+        self.users.set_ttl(tunables.user_ttl_secs);
+        self.actions.set_ttl(tunables.actions_ttl_secs);
+        self.hot_cache.set_ttl(tunables.hot_cache_ttl_secs);
+    }
+
+    pub fn clear(&self) {
+        self.posts.clear();
+        self.users.clear();
+        self.actions.clear();
+        self.hot_cache.clear();
+    }
+
+    pub fn render_metrics(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP news_feed_cache_evictions_total Cache entries evicted, by map and reason.");
+        let _ = writeln!(out, "# TYPE news_feed_cache_evictions_total counter");
+        self.posts.render(&mut out, "posts");
+        self.users.render(&mut out, "users");
+        self.actions.render(&mut out, "actions");
+        self.hot_cache.render(&mut out, "hot_cache");
+        out
+    }
+}
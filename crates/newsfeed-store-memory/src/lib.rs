@@ -0,0 +1,21 @@
+// The in-process backends: the DashMap cache in front of storage, the
+// sled and Redis Storage impls, and the Redis and NATS job queues
+pub mod broker;
+pub mod cache;
+pub mod cache_stats;
+pub mod eviction;
+pub mod memory;
+pub mod storage;
+
+use newsfeed_core::config::Config;
+
+// The backend settings; startup stops on these
+pub fn validate(config: &Config) -> Result<(), String> {
+    if let Some(spec) = &config.storage {
+        storage::backend(spec).map_err(|e| format!("NEWS_FEED_STORAGE: {}", e))?;
+    }
+    if let Some(url) = &config.queue_url {
+        broker::backend(url).map_err(|e| format!("NEWS_FEED_QUEUE_URL: {}", e))?;
+    }
+    Ok(())
+}
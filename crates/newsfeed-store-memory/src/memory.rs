@@ -0,0 +1,159 @@
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::hash::Hash;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use newsfeed_core::config::Config;
+use newsfeed_core::{info, warn};
+
+use crate::cache::CacheLayer;
+
+const SAMPLE_SIZE: usize = 32;
+
+// Counts serialized bytes without keeping them
+struct ByteCounter(usize);
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn approx_size<T: Serialize>(value: &T) -> usize {
+    let mut counter = ByteCounter(0);
+    let _ = serde_json::to_writer(&mut counter, value);
+    std::mem::size_of::<T>() + counter.0
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheMemory {
+    pub name: &'static str,
+    pub entries: usize,
+    pub approx_bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub budget_bytes: Option<u64>,
+}
+
+// Entry count times the mean size of a few sampled entries. Serialized size
+// stands in for heap usage, which is close enough to spot a runaway cache.
+pub fn estimate<K, V>(name: &'static str, map: &DashMap<K, V>) -> CacheMemory
+where
+    K: Eq + Hash + Serialize,
+    V: Serialize,
+{
+    let entries = map.len();
+    let (sampled, sampled_bytes) = map
+        .iter()
+        .take(SAMPLE_SIZE)
+        .fold((0usize, 0usize), |(count, bytes), entry| {
+            (count + 1, bytes + approx_size(entry.key()) + approx_size(entry.value()))
+        });
+    let approx_bytes = if sampled == 0 {
+        0
+    } else {
+        (entries as u64) * (sampled_bytes as u64) / (sampled as u64)
+    };
+
+    CacheMemory {
+        name,
+        entries,
+        approx_bytes,
+        budget_bytes: None,
+    }
+}
+
+// Per-cache memory estimates with optional budgets that log an alert when exceeded
+pub struct MemoryMonitor {
+    cache: Arc<CacheLayer>,
+    budgets: Vec<(String, u64)>,
+    over_budget: Mutex<HashSet<&'static str>>,
+}
+
+impl MemoryMonitor {
+    pub fn new(cache: Arc<CacheLayer>, config: &Config) -> Self {
+        Self {
+            cache,
+            budgets: config.cache_budgets.clone(),
+            over_budget: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn report(&self) -> Vec<CacheMemory> {
+        let mut report = self.cache.memory_usage();
+        for usage in report.iter_mut() {
+            usage.budget_bytes = self
+                .budgets
+                .iter()
+                .find(|(name, _)| name == usage.name)
+                .map(|(_, budget)| *budget);
+        }
+        report
+    }
+
+    // Logs once when a cache goes over budget and once when it recovers
+    pub fn check_budgets(&self) {
+        let mut over_budget = self.over_budget.lock().expect("budget alerts poisoned");
+        for usage in self.report() {
+            let Some(budget) = usage.budget_bytes else {
+                continue;
+            };
+            if usage.approx_bytes > budget {
+                if over_budget.insert(usage.name) {
+                    warn!(
+                        "ALERT: cache {} is ~{} bytes ({} entries), over its {} byte budget",
+                        usage.name, usage.approx_bytes, usage.entries, budget
+                    );
+                }
+            } else if over_budget.remove(usage.name) {
+                info!("Cache {} is back under its {} byte budget", usage.name, budget);
+            }
+        }
+    }
+
+    pub fn spawn_budget_checks(self: Arc<Self>, interval: Duration) {
+        if self.budgets.is_empty() {
+            return;
+        }
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let monitor = self.clone();
+                // Sampling takes shard read locks, so keep it off the async workers
+                let _ = tokio::task::spawn_blocking(move || monitor.check_budgets()).await;
+            }
+        });
+    }
+}
+
+// Prometheus text exposition of the cache estimates
+pub fn render_metrics(report: &[CacheMemory]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# HELP news_feed_cache_entries Entries in each cache map.");
+    let _ = writeln!(out, "# TYPE news_feed_cache_entries gauge");
+    for usage in report {
+        let _ = writeln!(out, "news_feed_cache_entries{{cache=\"{}\"}} {}", usage.name, usage.entries);
+    }
+    let _ = writeln!(out, "# HELP news_feed_cache_bytes Approximate memory used by each cache map.");
+    let _ = writeln!(out, "# TYPE news_feed_cache_bytes gauge");
+    for usage in report {
+        let _ = writeln!(out, "news_feed_cache_bytes{{cache=\"{}\"}} {}", usage.name, usage.approx_bytes);
+    }
+    let _ = writeln!(out, "# HELP news_feed_cache_budget_bytes Configured memory budget for a cache map.");
+    let _ = writeln!(out, "# TYPE news_feed_cache_budget_bytes gauge");
+    for usage in report {
+        if let Some(budget) = usage.budget_bytes {
+            let _ = writeln!(out, "news_feed_cache_budget_bytes{{cache=\"{}\"}} {}", usage.name, budget);
+        }
+    }
+    out
+}
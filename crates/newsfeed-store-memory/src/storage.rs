@@ -0,0 +1,734 @@
+// With no backend compiled in, nothing here is ever opened
+#![cfg_attr(not(any(feature = "sled-storage", feature = "redis-storage")), allow(dead_code))]
+
+use std::sync::Arc;
+
+use newsfeed_core::config::Config;
+pub use newsfeed_core::storage::{Change, Storage};
+
+// Which backend a NEWS_FEED_STORAGE value names, if this build includes it
+pub fn backend(spec: &str) -> Result<&'static str, String> {
+    let (name, feature, included) = match spec.split_once(':').map(|(scheme, _)| scheme) {
+        Some("sled") => ("sled", "sled-storage", cfg!(feature = "sled-storage")),
+        Some("redis" | "rediss") => ("redis", "redis-storage", cfg!(feature = "redis-storage")),
+        _ => return Err(format!("expected sled:<directory> or a redis:// URL, got {}", spec)),
+    };
+    if !included {
+        return Err(format!("this build has no {} storage; build with --features {}", name, feature));
+    }
+    Ok(name)
+}
+
+// Opens the configured backend; None keeps everything in memory
+pub fn open(config: &Config) -> Result<Option<Arc<dyn Storage>>, String> {
+    let Some(spec) = &config.storage else {
+        return Ok(None);
+    };
+    match backend(spec)? {
+        #[cfg(feature = "sled-storage")]
+        "sled" => {
+            let path = spec.split_once(':').map(|(_, path)| path).unwrap_or_default();
+            Ok(Some(Arc::new(sled_storage::SledStorage::open(path)?)))
+        }
+        #[cfg(feature = "redis-storage")]
+        "redis" => Ok(Some(Arc::new(redis_storage::RedisStorage::open(spec)?))),
+        _ => unreachable!("backend() only names compiled-in storage"),
+    }
+}
+
+// A stored count moved by `by`, kept from going below zero
+fn adjust(count: u32, by: i64) -> u32 {
+    (count as i64 + by).clamp(0, u32::MAX as i64) as u32
+}
+
+// An embedded sled database: one tree per kind, values as JSON. Sled
+// flushes to disk every half second, so the process dying can lose the
+// writes of the last half second.
+#[cfg(feature = "sled-storage")]
+mod sled_storage {
+    use serde::Serialize;
+    use serde::de::DeserializeOwned;
+
+    use super::{Storage, adjust};
+    use newsfeed_core::account_state::AccountRecord;
+    use newsfeed_core::graph::FollowEdge;
+    use newsfeed_core::ids::{PostId, UserId};
+    use newsfeed_core::two_factor::TwoFactorRecord;
+    use newsfeed_core::{NewsFeedItem, Post, User};
+
+    #[derive(Debug)]
+    pub struct SledStorage {
+        posts: sled::Tree,
+        users: sled::Tree,
+        passwords: sled::Tree,
+        accounts: sled::Tree,
+        two_factor: sled::Tree,
+        feeds: sled::Tree,
+        follows: sled::Tree, // "follower\0followed" -> edge
+        articles: sled::Tree, // post ID -> markdown
+    }
+
+    impl SledStorage {
+        pub fn open(path: &str) -> Result<Self, String> {
+            let db = sled::open(path).map_err(|e| format!("{}: {}", path, e))?;
+            let tree = |name: &str| db.open_tree(name).map_err(|e| format!("{}: {}", path, e));
+            Ok(Self {
+                posts: tree("posts")?,
+                users: tree("users")?,
+                passwords: tree("passwords")?,
+                accounts: tree("accounts")?,
+                two_factor: tree("two_factor")?,
+                feeds: tree("feeds")?,
+                follows: tree("follows")?,
+                articles: tree("articles")?,
+            })
+        }
+    }
+
+    fn follow_key(follower_id: &UserId, followed_id: &UserId) -> Vec<u8> {
+        format!("{}\0{}", follower_id, followed_id).into_bytes()
+    }
+
+    fn get<T: DeserializeOwned>(tree: &sled::Tree, key: impl AsRef<[u8]>) -> Result<Option<T>, String> {
+        match tree.get(key).map_err(|e| e.to_string())? {
+            Some(bytes) => serde_json::from_slice(&bytes).map(Some).map_err(|e| e.to_string()),
+            None => Ok(None),
+        }
+    }
+
+    fn set<T: Serialize + ?Sized>(tree: &sled::Tree, key: impl AsRef<[u8]>, value: &T) -> Result<(), String> {
+        let bytes = serde_json::to_vec(value).map_err(|e| e.to_string())?;
+        tree.insert(key.as_ref(), bytes).map(|_| ()).map_err(|e| e.to_string())
+    }
+
+    fn all<T: DeserializeOwned>(tree: &sled::Tree) -> Result<Vec<T>, String> {
+        tree.iter()
+            .values()
+            .map(|bytes| serde_json::from_slice(&bytes.map_err(|e| e.to_string())?).map_err(|e| e.to_string()))
+            .collect()
+    }
+
+    impl Storage for SledStorage {
+        fn name(&self) -> &'static str {
+            "sled"
+        }
+
+        fn get_post(&self, post_id: &PostId) -> Result<Option<Post>, String> {
+            get(&self.posts, post_id.as_str())
+        }
+
+        fn set_post(&self, post: &Post) -> Result<(), String> {
+            match get::<Post>(&self.posts, post.id.as_str())? {
+                Some(stored) => {
+                    let mut post = post.clone();
+                    post.like_count = stored.like_count;
+                    post.reply_count = stored.reply_count;
+                    set(&self.posts, post.id.as_str(), &post)
+                }
+                None => set(&self.posts, post.id.as_str(), post),
+            }
+        }
+
+        // Events are applied one at a time, so nothing writes the post in between
+        fn add_counts(&self, post_id: &PostId, likes: i64, replies: i64) -> Result<(), String> {
+            let Some(mut post) = get::<Post>(&self.posts, post_id.as_str())? else {
+                return Ok(());
+            };
+            post.like_count = adjust(post.like_count, likes);
+            post.reply_count = adjust(post.reply_count, replies);
+            set(&self.posts, post_id.as_str(), &post)
+        }
+
+        fn remove_post(&self, post_id: &PostId) -> Result<(), String> {
+            self.articles.remove(post_id.as_str()).map_err(|e| e.to_string())?;
+            self.posts.remove(post_id.as_str()).map(|_| ()).map_err(|e| e.to_string())
+        }
+
+        fn posts(&self) -> Result<Vec<Post>, String> {
+            all(&self.posts)
+        }
+
+        fn get_article(&self, post_id: &PostId) -> Result<Option<String>, String> {
+            get(&self.articles, post_id.as_str())
+        }
+
+        fn set_article(&self, post_id: &PostId, markdown: &str) -> Result<(), String> {
+            set(&self.articles, post_id.as_str(), markdown)
+        }
+
+        fn get_user(&self, user_id: &UserId) -> Result<Option<User>, String> {
+            get(&self.users, user_id.as_str())
+        }
+
+        fn set_user(&self, user: &User) -> Result<(), String> {
+            set(&self.users, user.id.as_str(), user)
+        }
+
+        fn users(&self) -> Result<Vec<User>, String> {
+            all(&self.users)
+        }
+
+        fn get_password_hash(&self, user_id: &UserId) -> Result<Option<String>, String> {
+            get(&self.passwords, user_id.as_str())
+        }
+
+        fn set_password_hash(&self, user_id: &UserId, hash: &str) -> Result<(), String> {
+            set(&self.passwords, user_id.as_str(), hash)
+        }
+
+        fn get_account(&self, user_id: &UserId) -> Result<Option<AccountRecord>, String> {
+            get(&self.accounts, user_id.as_str())
+        }
+
+        fn set_account(&self, user_id: &UserId, record: &AccountRecord) -> Result<(), String> {
+            set(&self.accounts, user_id.as_str(), record)
+        }
+
+        fn get_two_factor(&self, user_id: &UserId) -> Result<Option<TwoFactorRecord>, String> {
+            get(&self.two_factor, user_id.as_str())
+        }
+
+        fn set_two_factor(&self, user_id: &UserId, record: &TwoFactorRecord) -> Result<(), String> {
+            set(&self.two_factor, user_id.as_str(), record)
+        }
+
+        fn remove_two_factor(&self, user_id: &UserId) -> Result<(), String> {
+            self.two_factor.remove(user_id.as_str()).map(|_| ()).map_err(|e| e.to_string())
+        }
+
+        fn get_feed(&self, user_id: &UserId) -> Result<Option<Vec<NewsFeedItem>>, String> {
+            get(&self.feeds, user_id.as_str())
+        }
+
+        fn set_feed(&self, user_id: &UserId, items: &[NewsFeedItem]) -> Result<(), String> {
+            set(&self.feeds, user_id.as_str(), items)
+        }
+
+        fn set_follow(&self, follower_id: &UserId, followed_id: &UserId, edge: &FollowEdge) -> Result<(), String> {
+            set(&self.follows, follow_key(follower_id, followed_id), &(follower_id, followed_id, edge))
+        }
+
+        fn remove_follow(&self, follower_id: &UserId, followed_id: &UserId) -> Result<(), String> {
+            self.follows
+                .remove(follow_key(follower_id, followed_id))
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        }
+
+        fn follows(&self) -> Result<Vec<(UserId, UserId, FollowEdge)>, String> {
+            all(&self.follows)
+        }
+    }
+}
+
+// A Redis server, which several instances can share. Posts and users are
+// JSON strings in sets of IDs, and like and reply counts are their own keys,
+// moved with INCRBY. Each post's likers are a set, which decides whether a
+// like is new. A home feed is a sorted set of post IDs scored by delivery
+// time, and each account has sets of followers and followed accounts, with
+// one key per edge for its settings. Calls block on the server for up to two
+// seconds, off the async worker threads' other tasks.
+#[cfg(feature = "redis-storage")]
+mod redis_storage {
+    use redis::{Commands, Connection, RedisResult};
+    use serde::Serialize;
+    use serde::de::DeserializeOwned;
+    use std::fmt;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tokio::runtime::{Handle, RuntimeFlavor};
+
+    use super::{Change, Storage, adjust};
+    use newsfeed_core::account_state::AccountRecord;
+    use newsfeed_core::graph::FollowEdge;
+    use newsfeed_core::ids::{PostId, UserId};
+    use newsfeed_core::two_factor::TwoFactorRecord;
+    use crate::cache::MAX_FEED_ITEMS;
+    use newsfeed_core::{NewsFeedItem, Post, User, warn};
+
+    // Connections kept open; each call takes the next one round robin
+    const CONNECTIONS: usize = 8;
+    const TIMEOUT: Duration = Duration::from_secs(2);
+    // Posts and users read back per round trip
+    const BATCH: usize = 500;
+
+    // One value per key of a batch, None where the key is missing
+    type Column<T> = Vec<Option<T>>;
+
+    // KEYS: the posts set, the post's likers, its like count. ARGV: the post
+    // and user IDs. Returns 1 for a new like, 0 for a repeat, and -1 if the
+    // post isn't stored.
+    const LIKE: &str = r#"
+        if redis.call('SISMEMBER', KEYS[1], ARGV[1]) == 0 then return -1 end
+        local added = redis.call('SADD', KEYS[2], ARGV[2])
+        if added == 1 then redis.call('INCRBY', KEYS[3], 1) end
+        return added
+    "#;
+
+    // The same keys; returns 1 if the user had liked the post
+    const UNLIKE: &str = r#"
+        if redis.call('SISMEMBER', KEYS[1], ARGV[1]) == 0 then return -1 end
+        local removed = redis.call('SREM', KEYS[2], ARGV[2])
+        if removed == 1 then redis.call('DECRBY', KEYS[3], 1) end
+        return removed
+    "#;
+
+    // Every write is announced here as "<origin> <kind> <id>", so other
+    // instances can drop what they cached (see subscribe)
+    const CHANGES: &str = "news_feed:changes";
+
+    const POSTS: &str = "news_feed:posts";
+    const USERS: &str = "news_feed:users";
+
+    fn key(kind: &str, id: &str) -> String {
+        format!("news_feed:{}:{}", kind, id)
+    }
+
+    fn edge_key(follower_id: &str, followed_id: &str) -> String {
+        format!("news_feed:follow:{}:{}", follower_id, followed_id)
+    }
+
+    fn to_json<T: Serialize + ?Sized>(value: &T) -> Result<String, String> {
+        serde_json::to_string(value).map_err(|e| e.to_string())
+    }
+
+    fn from_json<T: DeserializeOwned>(json: &str) -> Result<T, String> {
+        serde_json::from_str(json).map_err(|e| e.to_string())
+    }
+
+    // A post as stored, with the counts kept beside it
+    fn with_counts(json: &str, likes: Option<i64>, replies: Option<i64>) -> Result<Post, String> {
+        let mut post: Post = from_json(json)?;
+        post.like_count = adjust(0, likes.unwrap_or_default());
+        post.reply_count = adjust(0, replies.unwrap_or_default());
+        Ok(post)
+    }
+
+    pub struct RedisStorage {
+        client: redis::Client,
+        connections: Vec<Mutex<Option<Connection>>>,
+        next: AtomicUsize,
+        origin: String, // this instance, in its own announcements
+    }
+
+    impl fmt::Debug for RedisStorage {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("RedisStorage").finish_non_exhaustive()
+        }
+    }
+
+    impl RedisStorage {
+        // Connects once, so an unreachable server fails the storage step,
+        // which startup retries
+        pub fn open(url: &str) -> Result<Self, String> {
+            let client = redis::Client::open(url).map_err(|e| e.to_string())?;
+            let storage = Self {
+                client,
+                connections: (0..CONNECTIONS).map(|_| Mutex::new(None)).collect(),
+                next: AtomicUsize::new(0),
+                origin: uuid::Uuid::new_v4().simple().to_string(),
+            };
+            storage.run(|connection| redis::cmd("PING").query::<()>(connection))?;
+            Ok(storage)
+        }
+
+        fn connect(&self) -> RedisResult<Connection> {
+            let connection = self.client.get_connection_with_timeout(TIMEOUT)?;
+            connection.set_read_timeout(Some(TIMEOUT))?;
+            connection.set_write_timeout(Some(TIMEOUT))?;
+            Ok(connection)
+        }
+
+        // Calls come from async handlers, so on a runtime worker the thread's
+        // other tasks are handed to another worker while this one waits on
+        // the server. A connection that failed is dropped and opened again on
+        // next use.
+        fn run<T>(&self, command: impl FnOnce(&mut Connection) -> RedisResult<T>) -> Result<T, String> {
+            let call = || {
+                let slot = &self.connections[self.next.fetch_add(1, Ordering::Relaxed) % CONNECTIONS];
+                let mut slot = slot.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                if slot.is_none() {
+                    *slot = Some(self.connect().map_err(|e| e.to_string())?);
+                }
+                let result = command(slot.as_mut().expect("connected above"));
+                if let Err(e) = &result
+                    && (e.is_io_error() || e.is_timeout() || e.is_connection_dropped())
+                {
+                    *slot = None;
+                }
+                result.map_err(|e| e.to_string())
+            };
+            match Handle::try_current() {
+                Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                    tokio::task::block_in_place(call)
+                }
+                _ => call(),
+            }
+        }
+
+        fn run_like(&self, script: &str, post_id: &PostId, user_id: &UserId) -> Result<Option<bool>, String> {
+            let id = post_id.as_str();
+            let result: i64 = self.run(|connection| {
+                redis::cmd("EVAL")
+                    .arg(script)
+                    .arg(3)
+                    .arg(POSTS)
+                    .arg(key("likers", id))
+                    .arg(key("likes", id))
+                    .arg(id)
+                    .arg(user_id.as_str())
+                    .query(connection)
+            })?;
+            Ok((result >= 0).then_some(result == 1))
+        }
+
+        // Adds the announcement of a change to a write's pipeline, so it's
+        // only sent if the write is
+        fn announce(&self, pipe: &mut redis::Pipeline, kind: &str, id: &str) {
+            pipe.publish(CHANGES, format!("{} {} {}", self.origin, kind, id)).ignore();
+        }
+
+        // Values of the given keys, in order; None for missing ones
+        fn get_many(&self, keys: &[String]) -> Result<Column<String>, String> {
+            if keys.is_empty() {
+                return Ok(Vec::new());
+            }
+            self.run(|connection| redis::cmd("MGET").arg(keys).query(connection))
+        }
+    }
+
+    impl Storage for RedisStorage {
+        fn name(&self) -> &'static str {
+            "redis"
+        }
+
+        fn get_post(&self, post_id: &PostId) -> Result<Option<Post>, String> {
+            let id = post_id.as_str();
+            let (json, likes, replies): (Option<String>, Option<i64>, Option<i64>) = self.run(|connection| {
+                redis::pipe()
+                    .get(key("post", id))
+                    .get(key("likes", id))
+                    .get(key("replies", id))
+                    .query(connection)
+            })?;
+            json.map(|json| with_counts(&json, likes, replies)).transpose()
+        }
+
+        fn set_post(&self, post: &Post) -> Result<(), String> {
+            let id = post.id.as_str();
+            let json = to_json(post)?;
+            let mut pipe = redis::pipe();
+            pipe.atomic()
+                .set(key("post", id), json)
+                .ignore()
+                .set_nx(key("likes", id), post.like_count)
+                .ignore()
+                .set_nx(key("replies", id), post.reply_count)
+                .ignore()
+                .sadd(POSTS, id)
+                .ignore();
+            self.announce(&mut pipe, "post", id);
+            self.run(|connection| pipe.query(connection))
+        }
+
+        fn add_counts(&self, post_id: &PostId, likes: i64, replies: i64) -> Result<(), String> {
+            let id = post_id.as_str();
+            if !self.run(|connection| connection.sismember::<_, _, bool>(POSTS, id))? {
+                return Ok(());
+            }
+            let mut pipe = redis::pipe();
+            pipe.incr(key("likes", id), likes)
+                .ignore()
+                .incr(key("replies", id), replies)
+                .ignore();
+            self.announce(&mut pipe, "post", id);
+            self.run(|connection| pipe.query(connection))
+        }
+
+        fn add_like(&self, post_id: &PostId, user_id: &UserId) -> Result<Option<bool>, String> {
+            self.run_like(LIKE, post_id, user_id)
+        }
+
+        fn remove_like(&self, post_id: &PostId, user_id: &UserId) -> Result<Option<bool>, String> {
+            self.run_like(UNLIKE, post_id, user_id)
+        }
+
+        fn remove_post(&self, post_id: &PostId) -> Result<(), String> {
+            let id = post_id.as_str();
+            let mut pipe = redis::pipe();
+            pipe.atomic()
+                .del(&[
+                    key("post", id),
+                    key("likes", id),
+                    key("replies", id),
+                    key("likers", id),
+                    key("article", id),
+                ])
+                .ignore()
+                .srem(POSTS, id)
+                .ignore();
+            self.announce(&mut pipe, "post", id);
+            self.run(|connection| pipe.query(connection))
+        }
+
+        fn posts(&self) -> Result<Vec<Post>, String> {
+            let ids: Vec<String> = self.run(|connection| connection.smembers(POSTS))?;
+            let mut posts = Vec::with_capacity(ids.len());
+            for batch in ids.chunks(BATCH) {
+                let keys = |kind: &str| -> Vec<String> { batch.iter().map(|id| key(kind, id)).collect() };
+                let (jsons, likes, replies): (Column<String>, Column<i64>, Column<i64>) =
+                    self.run(|connection| {
+                        redis::pipe()
+                            .cmd("MGET")
+                            .arg(keys("post"))
+                            .cmd("MGET")
+                            .arg(keys("likes"))
+                            .cmd("MGET")
+                            .arg(keys("replies"))
+                            .query(connection)
+                    })?;
+                for ((json, likes), replies) in jsons.into_iter().zip(likes).zip(replies) {
+                    // Deleted between the two reads
+                    if let Some(json) = json {
+                        posts.push(with_counts(&json, likes, replies)?);
+                    }
+                }
+            }
+            Ok(posts)
+        }
+
+        fn get_article(&self, post_id: &PostId) -> Result<Option<String>, String> {
+            self.run(|connection| connection.get(key("article", post_id.as_str())))
+        }
+
+        fn set_article(&self, post_id: &PostId, markdown: &str) -> Result<(), String> {
+            self.run(|connection| connection.set(key("article", post_id.as_str()), markdown))
+        }
+
+        fn get_user(&self, user_id: &UserId) -> Result<Option<User>, String> {
+            let json: Option<String> = self.run(|connection| connection.get(key("user", user_id.as_str())))?;
+            json.map(|json| from_json(&json)).transpose()
+        }
+
+        fn set_user(&self, user: &User) -> Result<(), String> {
+            let id = user.id.as_str();
+            let json = to_json(user)?;
+            let mut pipe = redis::pipe();
+            pipe.atomic()
+                .set(key("user", id), json)
+                .ignore()
+                .sadd(USERS, id)
+                .ignore();
+            self.announce(&mut pipe, "user", id);
+            self.run(|connection| pipe.query(connection))
+        }
+
+        fn users(&self) -> Result<Vec<User>, String> {
+            let ids: Vec<String> = self.run(|connection| connection.smembers(USERS))?;
+            let mut users = Vec::with_capacity(ids.len());
+            for batch in ids.chunks(BATCH) {
+                let keys: Vec<String> = batch.iter().map(|id| key("user", id)).collect();
+                for json in self.get_many(&keys)?.into_iter().flatten() {
+                    users.push(from_json(&json)?);
+                }
+            }
+            Ok(users)
+        }
+
+        fn get_password_hash(&self, user_id: &UserId) -> Result<Option<String>, String> {
+            self.run(|connection| connection.get(key("password", user_id.as_str())))
+        }
+
+        fn set_password_hash(&self, user_id: &UserId, hash: &str) -> Result<(), String> {
+            self.run(|connection| connection.set(key("password", user_id.as_str()), hash))
+        }
+
+        fn get_account(&self, user_id: &UserId) -> Result<Option<AccountRecord>, String> {
+            let json: Option<String> = self.run(|connection| connection.get(key("account", user_id.as_str())))?;
+            json.map(|json| from_json(&json)).transpose()
+        }
+
+        fn set_account(&self, user_id: &UserId, record: &AccountRecord) -> Result<(), String> {
+            let json = to_json(record)?;
+            self.run(|connection| connection.set(key("account", user_id.as_str()), json))
+        }
+
+        fn get_two_factor(&self, user_id: &UserId) -> Result<Option<TwoFactorRecord>, String> {
+            let json: Option<String> = self.run(|connection| connection.get(key("two_factor", user_id.as_str())))?;
+            json.map(|json| from_json(&json)).transpose()
+        }
+
+        fn set_two_factor(&self, user_id: &UserId, record: &TwoFactorRecord) -> Result<(), String> {
+            let json = to_json(record)?;
+            self.run(|connection| connection.set(key("two_factor", user_id.as_str()), json))
+        }
+
+        fn remove_two_factor(&self, user_id: &UserId) -> Result<(), String> {
+            self.run(|connection| connection.del(key("two_factor", user_id.as_str())))
+        }
+
+        // Newest first; items delivered at the same time come in reverse ID order
+        fn get_feed(&self, user_id: &UserId) -> Result<Option<Vec<NewsFeedItem>>, String> {
+            let entries: Vec<(String, f64)> =
+                self.run(|connection| connection.zrevrange_withscores(key("feed", user_id.as_str()), 0, -1))?;
+            if entries.is_empty() {
+                return Ok(None);
+            }
+            Ok(Some(
+                entries
+                    .into_iter()
+                    .map(|(post_id, timestamp)| NewsFeedItem {
+                        post_id: PostId::new(post_id),
+                        timestamp: timestamp as u64,
+                    })
+                    .collect(),
+            ))
+        }
+
+        fn set_feed(&self, user_id: &UserId, items: &[NewsFeedItem]) -> Result<(), String> {
+            let feed = key("feed", user_id.as_str());
+            let members: Vec<(u64, &str)> = items.iter().map(|item| (item.timestamp, item.post_id.as_str())).collect();
+            let mut pipe = redis::pipe();
+            pipe.atomic().del(&feed).ignore();
+            if !members.is_empty() {
+                pipe.zadd_multiple(&feed, &members).ignore();
+            }
+            self.announce(&mut pipe, "feed", user_id.as_str());
+            self.run(|connection| pipe.query(connection))
+        }
+
+        // Adding an item twice leaves one; the oldest past the cap are dropped
+        fn add_to_feed(&self, user_id: &UserId, added: &[NewsFeedItem], _feed: &[NewsFeedItem]) -> Result<(), String> {
+            if added.is_empty() {
+                return Ok(());
+            }
+            let feed = key("feed", user_id.as_str());
+            let members: Vec<(u64, &str)> = added.iter().map(|item| (item.timestamp, item.post_id.as_str())).collect();
+            let mut pipe = redis::pipe();
+            pipe.atomic()
+                .zadd_multiple(&feed, &members)
+                .ignore()
+                .zremrangebyrank(&feed, 0, -(MAX_FEED_ITEMS as isize) - 1)
+                .ignore();
+            self.announce(&mut pipe, "feed", user_id.as_str());
+            self.run(|connection| pipe.query(connection))
+        }
+
+        fn remove_from_feed(&self, user_id: &UserId, removed: &[PostId], _feed: &[NewsFeedItem]) -> Result<(), String> {
+            if removed.is_empty() {
+                return Ok(());
+            }
+            let members: Vec<&str> = removed.iter().map(|post_id| post_id.as_str()).collect();
+            let mut pipe = redis::pipe();
+            pipe.zrem(key("feed", user_id.as_str()), members).ignore();
+            self.announce(&mut pipe, "feed", user_id.as_str());
+            self.run(|connection| pipe.query(connection))
+        }
+
+        fn set_follow(&self, follower_id: &UserId, followed_id: &UserId, edge: &FollowEdge) -> Result<(), String> {
+            let (follower, followed) = (follower_id.as_str(), followed_id.as_str());
+            let json = to_json(edge)?;
+            self.run(|connection| {
+                redis::pipe()
+                    .atomic()
+                    .set(edge_key(follower, followed), json)
+                    .ignore()
+                    .sadd(key("following", follower), followed)
+                    .ignore()
+                    .sadd(key("followers", followed), follower)
+                    .ignore()
+                    .query(connection)
+            })
+        }
+
+        fn remove_follow(&self, follower_id: &UserId, followed_id: &UserId) -> Result<(), String> {
+            let (follower, followed) = (follower_id.as_str(), followed_id.as_str());
+            self.run(|connection| {
+                redis::pipe()
+                    .atomic()
+                    .del(edge_key(follower, followed))
+                    .ignore()
+                    .srem(key("following", follower), followed)
+                    .ignore()
+                    .srem(key("followers", followed), follower)
+                    .ignore()
+                    .query(connection)
+            })
+        }
+
+        // Read per stored user, so edges of accounts without a user record
+        // aren't restored
+        fn follows(&self) -> Result<Vec<(UserId, UserId, FollowEdge)>, String> {
+            let users: Vec<String> = self.run(|connection| connection.smembers(USERS))?;
+            let mut follows = Vec::new();
+            for follower in users {
+                let followed: Vec<String> = self.run(|connection| connection.smembers(key("following", &follower)))?;
+                let keys: Vec<String> = followed.iter().map(|followed| edge_key(&follower, followed)).collect();
+                for (followed, json) in followed.iter().zip(self.get_many(&keys)?) {
+                    if let Some(json) = json {
+                        follows.push((UserId::new(follower.as_str()), UserId::new(followed.as_str()), from_json(&json)?));
+                    }
+                }
+            }
+            Ok(follows)
+        }
+
+        fn shared(&self) -> bool {
+            true
+        }
+
+        fn followers(&self, user_id: &UserId) -> Result<Option<Vec<(UserId, FollowEdge)>>, String> {
+            let followers: Vec<String> = self.run(|connection| connection.smembers(key("followers", user_id.as_str())))?;
+            let keys: Vec<String> = followers.iter().map(|follower| edge_key(follower, user_id.as_str())).collect();
+            let mut edges = Vec::with_capacity(followers.len());
+            for (follower, json) in followers.into_iter().zip(self.get_many(&keys)?) {
+                if let Some(json) = json {
+                    edges.push((UserId::new(follower), from_json(&json)?));
+                }
+            }
+            Ok(Some(edges))
+        }
+
+        // Announcements from this instance are skipped, since its cache
+        // already has its own writes. A dropped subscription is opened again
+        // a second later; changes announced in between aren't seen, and what
+        // they touched stays cached until it's idle long enough to be evicted.
+        fn subscribe(&self, apply: Box<dyn Fn(Change) + Send + Sync>) -> Result<bool, String> {
+            let client = self.client.clone();
+            let origin = self.origin.clone();
+            std::thread::Builder::new()
+                .name("redis-changes".to_string())
+                .spawn(move || loop {
+                    if let Err(e) = listen(&client, &origin, &*apply) {
+                        warn!("storage: change stream dropped, resubscribing: {}", e);
+                    }
+                    std::thread::sleep(Duration::from_secs(1));
+                })
+                .map_err(|e| e.to_string())?;
+            Ok(true)
+        }
+    }
+
+    fn listen(client: &redis::Client, origin: &str, apply: &dyn Fn(Change)) -> RedisResult<()> {
+        let mut connection = client.get_connection_with_timeout(TIMEOUT)?;
+        let mut pubsub = connection.as_pubsub();
+        pubsub.subscribe(CHANGES)?;
+        loop {
+            let payload: String = pubsub.get_message()?.get_payload()?;
+            let mut parts = payload.splitn(3, ' ');
+            let (Some(from), Some(kind), Some(id)) = (parts.next(), parts.next(), parts.next()) else {
+                continue;
+            };
+            if from == origin {
+                continue;
+            }
+            match kind {
+                "post" => apply(Change::Post(PostId::new(id))),
+                "user" => apply(Change::User(UserId::new(id))),
+                "feed" => apply(Change::Feed(UserId::new(id))),
+                _ => {}
+            }
+        }
+    }
+}